@@ -0,0 +1,105 @@
+//! A wrapper around tarpaulin which adds a CSV report format. Run it from a
+//! project directory with `--out csv` to write `tarpaulin-report.csv` with the
+//! covered and coverable lines of each file.
+use cargo_tarpaulin::config::{Config, ConfigWrapper};
+use cargo_tarpaulin::errors::RunError;
+use cargo_tarpaulin::report::{register_report, Report, RunMetadata};
+use cargo_tarpaulin::run;
+use cargo_tarpaulin::traces::TraceMap;
+use clap::App;
+use std::fs::File;
+use std::io::Write;
+use std::process;
+
+struct CsvReport;
+
+impl Report for CsvReport {
+    fn render(
+        &self,
+        config: &Config,
+        traces: &TraceMap,
+        _metadata: &RunMetadata,
+    ) -> Result<(), RunError> {
+        let file_path = config.output_dir().join("tarpaulin-report.csv");
+        File::create(file_path)?.write_all(to_csv(config, traces).as_bytes())?;
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "CSV of the covered and coverable lines of each file"
+    }
+}
+
+fn to_csv(config: &Config, traces: &TraceMap) -> String {
+    let mut result = "path,covered,coverable\n".to_string();
+    for file in traces.files() {
+        result.push_str(&format!(
+            "{},{},{}\n",
+            config.strip_base_dir(file).display(),
+            traces.covered_in_path(file),
+            traces.coverable_in_path(file)
+        ));
+    }
+    result
+}
+
+fn main() {
+    // Formats must be registered before the configs are created
+    register_report("csv", CsvReport);
+    let matches = App::new("tarpaulin-csv")
+        .args_from_usage(
+            "--root -r [DIR] 'Calculates relative paths to root directory'
+             --out -o [FMT]... 'Output format of coverage report, csv is added by this wrapper'
+             --output-dir [PATH] 'Specify a custom directory to write report files'",
+        )
+        .get_matches();
+    let configs = ConfigWrapper::from(&matches).0;
+    if let Err(e) = run(&configs) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cargo_tarpaulin::config::OutputFormat;
+    use cargo_tarpaulin::report::get_report;
+    use cargo_tarpaulin::traces::{CoverageStat, Trace};
+    use std::collections::HashSet;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn csv_report_registered() {
+        register_report("Csv", CsvReport);
+        let format = "csv".parse::<OutputFormat>().unwrap();
+        assert_eq!(format, OutputFormat::Custom("csv".to_string()));
+        let report = get_report(&format).unwrap();
+
+        let mut traces = TraceMap::new();
+        for (line, hits) in &[(1, 1), (2, 0)] {
+            traces.add_trace(
+                &env::current_dir().unwrap().join("src/lib.rs"),
+                Trace {
+                    line: *line,
+                    address: HashSet::new(),
+                    length: 1,
+                    stats: CoverageStat::Line(*hits),
+                    fn_name: None,
+                    hit_span: None,
+                    sampled: false,
+                },
+            );
+        }
+        let mut config = Config::default();
+        config.output_directory = Some(env::temp_dir().join("tarpaulin_custom_report"));
+        fs::create_dir_all(config.output_dir()).unwrap();
+        report
+            .render(&config, &traces, &RunMetadata::default())
+            .unwrap();
+
+        let csv = fs::read_to_string(config.output_dir().join("tarpaulin-report.csv")).unwrap();
+        assert_eq!(csv, "path,covered,coverable\nsrc/lib.rs,1,2\n");
+    }
+}
@@ -0,0 +1,28 @@
+//! An `xtask` style runner which fails if the coverage of a crate drops too
+//! low. Copy it into a workspace's `xtask` package and run it with
+//! `cargo xtask` rather than from a test, as a check run from a traced test is
+//! refused. Takes the manifest of the crate to check, by default the one in
+//! the current directory.
+use cargo_tarpaulin::harness::CoverageCheck;
+use std::env;
+use std::process;
+
+fn main() {
+    let manifest = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "Cargo.toml".to_string());
+    let check = CoverageCheck::new(manifest)
+        .exclude_files("examples/*")
+        .min_total(80.0)
+        .min_file(50.0);
+    match check.run() {
+        Ok(traces) => println!(
+            "Coverage check passed, {:.2}% coverage",
+            traces.coverage_percentage() * 100.0
+        ),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -1,59 +1,46 @@
 use crate::ptrace_control::*;
-use crate::statemachine::*;
 use nix::unistd::Pid;
 use nix::{Error, Result};
-use std::collections::HashMap;
+use std::mem;
 
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 const INT: u64 = 0xCC;
 
 /// Breakpoint construct used to monitor program execution. As tarpaulin is an
 /// automated process, this will likely have less functionality than most
-/// breakpoint implementations.
-#[derive(Debug)]
+/// breakpoint implementations. Large projects have millions of breakpoints so
+/// they're kept small, which thread is stepping over a breakpoint is tracked
+/// per thread by the `TracerCore` instead.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Breakpoint {
     /// Program counter
     pub pc: u64,
+    /// Index of the trace the breakpoint counts the hits of
+    pub trace: u32,
     /// Bottom byte of address data.
     /// This is replaced to enable the interrupt. Rest of data is never changed.
     data: u8,
-    /// Reading from memory with ptrace gives addresses aligned to bytes.
-    /// We therefore need to know the shift to place the breakpoint in the right place
-    shift: u64,
-    /// Map of the state of the breakpoint on each thread/process
-    is_running: HashMap<Pid, bool>,
 }
 
 impl Breakpoint {
     /// Creates a new breakpoint for the given process and program counter.
-    pub fn new(pid: Pid, pc: u64) -> Result<Breakpoint> {
-        let aligned = pc & !0x7u64;
-        let data = read_address(pid, aligned)?;
-        let shift = 8 * (pc - aligned);
-        let data = ((data >> shift) & 0xFF) as u8;
-
-        let mut b = Breakpoint {
-            pc,
-            data,
-            shift,
-            is_running: HashMap::new(),
-        };
-        match b.enable(pid) {
-            Ok(_) => Ok(b),
-            Err(e) => Err(e),
-        }
+    pub fn new(pid: Pid, pc: u64, trace: u32) -> Result<Breakpoint> {
+        let mut b = Breakpoint { pc, trace, data: 0 };
+        let data = read_address(pid, b.aligned_address())?;
+        b.data = ((data >> b.shift()) & 0xFF) as u8;
+        b.enable(pid)?;
+        Ok(b)
     }
 
-    pub fn jump_to(&mut self, pid: Pid) -> Result<()> {
+    pub fn jump_to(&self, pid: Pid) -> Result<()> {
         set_instruction_pointer(pid, self.pc).map(|_| ())
     }
 
     /// Attaches the current breakpoint.
-    pub fn enable(&mut self, pid: Pid) -> Result<()> {
+    pub fn enable(&self, pid: Pid) -> Result<()> {
         let data = read_address(pid, self.aligned_address())?;
-        self.is_running.insert(pid, true);
-        let mut intdata = data & (!(0xFFu64 << self.shift) as i64);
-        intdata |= (INT << self.shift) as i64;
+        let mut intdata = data & (!(0xFFu64 << self.shift()) as i64);
+        intdata |= (INT << self.shift()) as i64;
         if data == intdata {
             Err(Error::UnsupportedOperation)
         } else {
@@ -64,53 +51,107 @@ impl Breakpoint {
     fn disable(&self, pid: Pid) -> Result<()> {
         // I require the bit fiddlin this end.
         let data = read_address(pid, self.aligned_address())?;
-        let mut orgdata = data & (!(0xFFu64 << self.shift) as i64);
-        orgdata |= i64::from(self.data) << self.shift;
+        let mut orgdata = data & (!(0xFFu64 << self.shift()) as i64);
+        orgdata |= i64::from(self.data) << self.shift();
         write_to_address(pid, self.aligned_address(), orgdata)
     }
 
-    /// Processes the breakpoint. This steps over the breakpoint
-    pub fn process(
-        &mut self,
-        pid: Pid,
-        reenable: bool,
-    ) -> Result<(bool, TracerAction<ProcessInfo>)> {
-        let is_running = match self.is_running.get(&pid) {
-            Some(r) => *r,
-            None => true,
-        };
-        if is_running {
-            let _ = self.enable(pid);
-            self.step(pid)?;
-            self.is_running.insert(pid, false);
-            Ok((true, TracerAction::Step(pid.into())))
-        } else {
-            self.disable(pid)?;
-            if reenable {
-                self.enable(pid)?;
-            }
-            self.is_running.insert(pid, true);
-            Ok((false, TracerAction::Continue(pid.into())))
-        }
+    /// Writes back the original instruction, used when tracing ends early
+    pub fn restore(&self, pid: Pid) -> Result<()> {
+        self.disable(pid)
     }
 
-    /// Call this when a ptrace thread is killed. Won't reenable the breakpoint
-    /// so may lose the ability to instrument this line.
-    pub fn thread_killed(&mut self, pid: Pid) {
-        self.is_running.remove(&pid);
-    }
-
-    /// Steps past the current breakpoint.
-    /// For more advanced coverage may interrogate the variables of a branch.
-    fn step(&mut self, pid: Pid) -> Result<()> {
-        // Remove the breakpoint, reset the program counter to step before it
-        // hit the breakpoint then step to execute the original instruction.
+    /// Removes the breakpoint and moves the program counter of the process back
+    /// to it, so the next single step executes the original instruction. The
+    /// breakpoint stays disabled until it's enabled again.
+    pub fn step_over(&self, pid: Pid) -> Result<()> {
         self.disable(pid)?;
-        self.jump_to(pid)?;
-        Ok(())
+        self.jump_to(pid)
     }
 
     fn aligned_address(&self) -> u64 {
         self.pc & !0x7u64
     }
+
+    /// Reading from memory with ptrace gives addresses aligned to bytes.
+    /// We therefore need to know the shift to place the breakpoint in the right place
+    fn shift(&self) -> u64 {
+        8 * (self.pc & 0x7u64)
+    }
+}
+
+/// The breakpoints in a process in a vector sorted by address, found with a
+/// binary search. A map would use several times the memory per breakpoint.
+#[derive(Debug, Default)]
+pub struct Breakpoints {
+    sorted: Vec<Breakpoint>,
+}
+
+impl Breakpoints {
+    /// Creates the set from breakpoints in any order, if several are at the
+    /// same address only the first is kept
+    pub fn new(mut breakpoints: Vec<Breakpoint>) -> Self {
+        breakpoints.sort_by_key(|bp| bp.pc);
+        breakpoints.dedup_by_key(|bp| bp.pc);
+        breakpoints.shrink_to_fit();
+        Self {
+            sorted: breakpoints,
+        }
+    }
+
+    pub fn get(&self, pc: u64) -> Option<&Breakpoint> {
+        self.sorted
+            .binary_search_by_key(&pc, |bp| bp.pc)
+            .ok()
+            .map(|i| &self.sorted[i])
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.sorted.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sorted.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sorted.is_empty()
+    }
+
+    /// Bytes of memory used by the breakpoints
+    pub fn memory_used(&self) -> usize {
+        mem::size_of::<Self>() + self.sorted.capacity() * mem::size_of::<Breakpoint>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn million_breakpoints_stay_small() {
+        const COUNT: u64 = 1_000_000;
+        // Addresses out of order with gaps like the debug info gives them
+        let plan = (0..COUNT)
+            .map(|i| Breakpoint {
+                pc: 0x40_0000 + ((i * 7919) % COUNT) * 3,
+                trace: (i / 4) as u32,
+                data: 0x55,
+            })
+            .collect::<Vec<_>>();
+        let breakpoints = Breakpoints::new(plan);
+        assert_eq!(breakpoints.len(), COUNT as usize);
+        assert!(breakpoints.memory_used() / breakpoints.len() <= 64);
+        assert!(breakpoints
+            .iter()
+            .zip(breakpoints.iter().skip(1))
+            .all(|(a, b)| a.pc < b.pc));
+
+        for i in (0..COUNT).step_by(997) {
+            let pc = 0x40_0000 + i * 3;
+            assert_eq!(breakpoints.get(pc).map(|bp| bp.pc), Some(pc));
+            assert_eq!(breakpoints.get(pc + 1), None);
+        }
+        assert_eq!(breakpoints.get(0), None);
+    }
 }
@@ -0,0 +1,250 @@
+//! Every breakpoint planned for the traced binaries and what happened to it,
+//! `--dump-breakpoints FILE`. When a line's coverage is disputed this is the
+//! raw mapping from addresses to lines along with where each mapping came
+//! from, whether the breakpoint could be inserted and whether it was hit. Like
+//! the run statistics it's collected as the run goes and written when the run
+//! ends however it ends. The file can be large so it's only collected when
+//! asked for.
+use crate::config::{Compression, Config};
+use crate::errors::write_error;
+use crate::report::compress::{compressed_path, create_compressed_file};
+use crate::traces::TraceMap;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+lazy_static! {
+    static ref DUMP: Mutex<Dump> = Mutex::new(Dump::default());
+}
+
+/// Where the line of an address came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MappingSource {
+    /// A line table row in the function the line is written in
+    LineTable,
+    /// A line table row in a function declared in another file, so an
+    /// inlined instance of the line
+    Inlined,
+    /// A `#[track_caller]` shim, attributed to the declaration of the
+    /// function it calls
+    CallerShim,
+}
+
+/// What the debug info says about an address, found while reading the line
+/// tables
+#[derive(Clone, Debug, PartialEq)]
+pub struct AddressOrigin {
+    /// Demangled name of the function containing the address
+    pub function: Option<String>,
+    pub source: MappingSource,
+    /// Copy of the source file the address was compiled from, if it wasn't
+    /// compiled from the project file itself
+    pub compiled_from: Option<PathBuf>,
+}
+
+/// A row of the dump
+#[derive(Clone, Debug, Serialize)]
+pub struct PlannedBreakpoint {
+    pub binary: PathBuf,
+    /// Hex address, as shown by disassemblers
+    pub address: String,
+    pub file: PathBuf,
+    pub line: u64,
+    pub function: Option<String>,
+    /// `None` if the address isn't in the line tables, such as traces loaded
+    /// from the trace cache
+    pub source: Option<MappingSource>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compiled_from: Option<PathBuf>,
+    pub inserted: bool,
+    pub hit: bool,
+}
+
+#[derive(Default)]
+struct Dump {
+    /// Breakpoints of each binary by address
+    binaries: BTreeMap<PathBuf, BTreeMap<u64, PlannedBreakpoint>>,
+    /// The binary being traced
+    current: Option<PathBuf>,
+}
+
+fn with_dump<F: FnOnce(&mut Dump)>(f: F) {
+    // Like the statistics the dump is best effort so a poisoned lock is used
+    let mut dump = DUMP.lock().unwrap_or_else(|e| e.into_inner());
+    f(&mut dump);
+}
+
+/// Records the breakpoints planned for a binary once its traces are read,
+/// the binary is then the one insertions and hits are recorded for. Planning
+/// a binary again, such as for a retry, keeps what was recorded before.
+pub fn plan(binary: &Path, traces: &TraceMap, origins: &HashMap<u64, AddressOrigin>) {
+    with_dump(|dump| {
+        let planned = dump.binaries.entry(binary.to_path_buf()).or_default();
+        for file in traces.files() {
+            for trace in traces.get_child_traces(file) {
+                for address in &trace.address {
+                    let origin = origins.get(address);
+                    planned
+                        .entry(*address)
+                        .or_insert_with(|| PlannedBreakpoint {
+                            binary: binary.to_path_buf(),
+                            address: format!("0x{:x}", address),
+                            file: file.clone(),
+                            line: trace.line,
+                            function: origin
+                                .and_then(|o| o.function.clone())
+                                .or_else(|| trace.fn_name.clone()),
+                            source: origin.map(|o| o.source),
+                            compiled_from: origin.and_then(|o| o.compiled_from.clone()),
+                            inserted: false,
+                            hit: false,
+                        });
+                }
+            }
+        }
+        dump.current = Some(binary.to_path_buf());
+    });
+}
+
+fn update<F: FnOnce(&mut PlannedBreakpoint)>(address: u64, f: F) {
+    with_dump(|dump| {
+        let bp = match dump.current {
+            Some(ref c) => dump.binaries.get_mut(c).and_then(|b| b.get_mut(&address)),
+            None => None,
+        };
+        if let Some(bp) = bp {
+            f(bp);
+        }
+    });
+}
+
+/// Records that the breakpoint at the address was inserted in the current
+/// binary
+pub fn inserted(address: u64) {
+    update(address, |bp| bp.inserted = true);
+}
+
+/// Records that the breakpoint at the address was hit in the current binary
+pub fn hit(address: u64) {
+    update(address, |bp| bp.hit = true);
+}
+
+fn rows() -> Vec<PlannedBreakpoint> {
+    let dump = DUMP.lock().unwrap_or_else(|e| e.into_inner());
+    dump.binaries
+        .values()
+        .flat_map(|b| b.values().cloned())
+        .collect()
+}
+
+/// The binary being traced or last traced and its breakpoints, when a run
+/// fails this is the binary it failed in
+pub fn current_binary() -> Option<(PathBuf, Vec<PlannedBreakpoint>)> {
+    let dump = DUMP.lock().unwrap_or_else(|e| e.into_inner());
+    let binary = dump.current.clone()?;
+    let rows = dump
+        .binaries
+        .get(&binary)
+        .map(|b| b.values().cloned().collect())
+        .unwrap_or_default();
+    Some((binary, rows))
+}
+
+/// Writes the dump when dropped so it's written however the run ends
+pub struct DumpWriter {
+    path: Option<PathBuf>,
+    compression: Option<Compression>,
+}
+
+impl DumpWriter {
+    /// Starts collecting the breakpoints if the config has a dump file
+    pub fn new(config: Option<&Config>) -> Self {
+        let path = config.and_then(|c| c.dump_breakpoints.clone());
+        if config.map_or(false, Config::records_breakpoints) {
+            with_dump(|d| *d = Dump::default());
+        }
+        DumpWriter {
+            path,
+            compression: config.and_then(|c| c.compress_output),
+        }
+    }
+
+    fn write(&self, path: &Path) -> io::Result<()> {
+        let mut file = create_compressed_file(path, self.compression)?;
+        serde_json::to_writer_pretty(&mut file, &rows())?;
+        file.write_all(b"\n")?;
+        file.finish()
+    }
+}
+
+impl Drop for DumpWriter {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.path {
+            let path = compressed_path(path.clone(), self.compression);
+            match self.write(&path) {
+                Ok(()) => info!("Breakpoints written to {}", path.display()),
+                Err(e) => warn!("Failed to write breakpoints: {}", write_error(&path, &e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traces::{CoverageStat, Trace};
+
+    #[test]
+    fn outcomes_recorded() {
+        let mut traces = TraceMap::new();
+        traces.add_trace(
+            Path::new("/project/src/lib.rs"),
+            Trace {
+                line: 3,
+                address: vec![0x10, 0x20].into_iter().collect(),
+                length: 1,
+                stats: CoverageStat::Line(0),
+                fn_name: Some("add".to_string()),
+                hit_span: None,
+                sampled: false,
+            },
+        );
+        traces.add_trace(Path::new("/project/src/lib.rs"), Trace::test_line(4, 0));
+        let mut origins = HashMap::new();
+        origins.insert(
+            0x20,
+            AddressOrigin {
+                function: Some("main".to_string()),
+                source: MappingSource::Inlined,
+                compiled_from: None,
+            },
+        );
+        let binary = Path::new("/project/target/debug/deps/dump_test");
+        plan(binary, &traces, &origins);
+        inserted(0x10);
+        inserted(0x20);
+        hit(0x20);
+        // Addresses that weren't planned are ignored
+        hit(0x30);
+
+        let rows = rows()
+            .into_iter()
+            .filter(|r| r.binary == binary)
+            .collect::<Vec<_>>();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].address, "0x10");
+        assert_eq!(rows[0].function.as_deref(), Some("add"));
+        assert_eq!(rows[0].source, None);
+        assert!(rows[0].inserted && !rows[0].hit);
+        assert_eq!(rows[1].address, "0x20");
+        assert_eq!(rows[1].line, 3);
+        assert_eq!(rows[1].function.as_deref(), Some("main"));
+        assert_eq!(rows[1].source, Some(MappingSource::Inlined));
+        assert!(rows[1].inserted && rows[1].hit);
+    }
+}
@@ -0,0 +1,74 @@
+//! Cancellation of an in progress coverage run for when tarpaulin is used as a
+//! library.
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How often the build is checked for cancellation
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Handle used to cancel a coverage run. Clones share the same state so one
+/// can be kept while another is passed to tarpaulin. Once cancelled the run
+/// stops building or tracing, kills any running tests and returns
+/// `RunError::Cancelled` with the results collected so far.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests the run using this token is cancelled
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Kills a child process if the token is cancelled before this is dropped.
+/// Used for children such as cargo where tarpaulin is blocked reading output.
+pub(crate) struct KillOnCancel {
+    done: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl KillOnCancel {
+    pub(crate) fn new(token: &CancellationToken, child: Pid) -> Self {
+        let done = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let done = done.clone();
+            let token = token.clone();
+            thread::spawn(move || {
+                while !done.load(Ordering::SeqCst) {
+                    if token.is_cancelled() {
+                        let _ = kill(child, Signal::SIGKILL);
+                        break;
+                    }
+                    thread::sleep(POLL_INTERVAL);
+                }
+            })
+        };
+        Self {
+            done,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for KillOnCancel {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
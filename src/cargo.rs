@@ -1,12 +1,18 @@
+use crate::cancel::{CancellationToken, KillOnCancel};
 use crate::config::*;
-use crate::errors::RunError;
+use crate::errors::{build_disk_full, is_build_disk_full, RunError};
+use crate::plan::{select_bins, selected_packages};
+use crate::traces::TraceMap;
 use cargo_metadata::{
     diagnostic::DiagnosticLevel, parse_messages, CargoOpt, Message, MetadataCommand,
 };
-use log::{error, trace};
+use log::{error, info, trace, warn};
+use nix::unistd::{isatty, Pid};
 use std::env;
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::thread;
 use walkdir::WalkDir;
 
 static DOCTEST_FOLDER: &str = "target/doctests";
@@ -56,9 +62,28 @@ impl TestBinary {
     pub fn pkg_authors(&self) -> &Option<Vec<String>> {
         &self.pkg_authors
     }
+
+    /// Name of the target the binary was built from, its file name without
+    /// the hash cargo adds
+    pub fn target_name(&self) -> String {
+        let name = self
+            .path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        match name.rfind('-') {
+            Some(i)
+                if name.len() - i - 1 == 16
+                    && name[i + 1..].chars().all(|c| c.is_ascii_hexdigit()) =>
+            {
+                name[..i].to_string()
+            }
+            _ => name,
+        }
+    }
 }
 
-pub fn get_tests(config: &Config) -> Result<Vec<TestBinary>, RunError> {
+pub fn get_tests(config: &Config, token: &CancellationToken) -> Result<Vec<TestBinary>, RunError> {
     let mut result = vec![];
     let manifest = match config.manifest.as_path().to_str() {
         Some(s) => s,
@@ -70,53 +95,116 @@ pub fn get_tests(config: &Config) -> Result<Vec<TestBinary>, RunError> {
         .exec()
         .map_err(|e| RunError::Cargo(e.to_string()))?;
 
+    if !config.unstable_features.is_empty() {
+        warn!(
+            "-Z {} requires a nightly toolchain",
+            config.unstable_features.join(" -Z ")
+        );
+    }
+    // Binaries of other packages are never run, cargo shouldn't build them
+    // but a selected package's dev-dependencies can be workspace members
+    let selected = if config.selects_packages() {
+        let (packages, _) = selected_packages(config, &metadata);
+        Some(
+            packages
+                .into_iter()
+                .map(|p| p.id.clone())
+                .collect::<Vec<_>>(),
+        )
+    } else {
+        None
+    };
     for ty in &config.run_types {
-        let mut cmd = create_command(manifest, config, ty);
+        let bins = if ty == &RunType::Bins {
+            select_bins(config, &metadata)?
+        } else {
+            None
+        };
+        if bins.as_ref().map_or(false, Vec::is_empty) {
+            warn!("No bins to run for the Bins run type");
+            continue;
+        }
+        let mut cmd = create_command(manifest, config, ty, bins.as_deref());
+        let build_output = config.build_output();
         cmd.stdout(Stdio::piped());
-        if !config.verbose {
-            cmd.stderr(Stdio::null());
+        if build_output != BuildOutput::Full {
+            cmd.stderr(Stdio::piped());
         }
         trace!("Running command {:?}", cmd);
         let mut child = cmd.spawn().map_err(|e| RunError::Cargo(e.to_string()))?;
+        let _cancel = KillOnCancel::new(token, Pid::from_raw(child.id() as _));
+        // Read on another thread so cargo can't block writing to a full pipe
+        let stderr = child.stderr.take().map(|mut stderr| {
+            thread::spawn(move || {
+                let mut output = String::new();
+                let _ = stderr.read_to_string(&mut output);
+                output
+            })
+        });
+        let live = isatty(libc::STDOUT_FILENO).unwrap_or(false);
+        let mut reporter = BuildReporter::new(build_output, io::stdout(), live);
+        let mut compile_error = None;
+        // Output of the compiler showing it ran out of disk space
+        let mut disk_full = None;
 
         if ty != &RunType::Doctests {
-            let mut package_ids = vec![];
             for msg in parse_messages(child.stdout.take().unwrap()) {
                 match msg {
                     Ok(Message::CompilerArtifact(art)) => {
+                        reporter.artifact(art.fresh);
                         if let Some(path) = art.executable {
                             if !art.profile.test && ty == &RunType::Tests {
                                 continue;
                             }
-                            result.push(TestBinary::new(path, *ty));
-                            package_ids.push(art.package_id.clone());
+                            if art.target.kind.iter().any(|x| x == "proc-macro") {
+                                // The unit tests of a proc-macro are a normal
+                                // executable linking the crate as an rlib so
+                                // they can be traced like any other test
+                                trace!("Found proc-macro test binary {}", path.display());
+                            }
+                            if let Some(ref selected) = selected {
+                                if !selected.contains(&art.package_id) {
+                                    trace!("Skipping {} of an unselected package", path.display());
+                                    continue;
+                                }
+                            }
+                            let mut binary = TestBinary::new(path, *ty);
+                            let package = &metadata[&art.package_id];
+                            binary.cargo_dir =
+                                package.manifest_path.parent().map(|x| x.to_path_buf());
+                            binary.pkg_name = Some(package.name.clone());
+                            binary.pkg_version = Some(package.version.to_string());
+                            binary.pkg_authors = Some(package.authors.clone());
+                            result.push(binary);
                         }
                     }
-                    Ok(Message::CompilerMessage(m)) => match m.message.level {
-                        DiagnosticLevel::Error | DiagnosticLevel::Ice => {
-                            let _ = child.wait();
-                            return Err(RunError::TestCompile(m.message.message));
+                    Ok(Message::CompilerMessage(m)) => {
+                        let text = m.message.rendered.as_ref().unwrap_or(&m.message.message);
+                        if disk_full.is_none() && is_build_disk_full(text) {
+                            disk_full = Some(text.clone());
+                        }
+                        match m.message.level {
+                            DiagnosticLevel::Error | DiagnosticLevel::Ice
+                                if compile_error.is_none() =>
+                            {
+                                compile_error = Some(m.message.message);
+                            }
+                            _ => {}
                         }
-                        _ => {}
-                    },
+                        reporter.diagnostic(m.message.rendered);
+                    }
                     Err(e) => {
                         error!("Error parsing cargo messages {}", e);
                     }
                     _ => {}
                 }
             }
-            for (res, package) in result.iter_mut().zip(package_ids.iter()) {
-                let package = &metadata[package];
-                res.cargo_dir = package.manifest_path.parent().map(|x| x.to_path_buf());
-                res.pkg_name = Some(package.name.clone());
-                res.pkg_version = Some(package.version.to_string());
-                res.pkg_authors = Some(package.authors.clone());
-            }
         } else {
             // Need to get the packages...
             let package_roots = config
                 .get_packages()
                 .iter()
+                .filter(|p| selected.as_ref().map_or(true, |s| s.contains(&p.id)))
                 .filter_map(|x| x.manifest_path.parent())
                 .map(|x| x.join(DOCTEST_FOLDER))
                 .collect::<Vec<PathBuf>>();
@@ -134,12 +222,118 @@ pub fn get_tests(config: &Config) -> Result<Vec<TestBinary>, RunError> {
                 }
             }
         }
-        child.wait().map_err(|e| RunError::Cargo(e.to_string()))?;
+        let status = child.wait().map_err(|e| RunError::Cargo(e.to_string()))?;
+        let stderr = stderr
+            .and_then(|handle| handle.join().ok())
+            .unwrap_or_default();
+        if token.is_cancelled() {
+            info!("Build cancelled");
+            return Err(RunError::Cancelled(TraceMap::new()));
+        }
+        // Running the doctests fails if a doctest does so that isn't a build
+        // failure, and they don't report progress
+        let failed = compile_error.is_some() || !status.success();
+        if ty != &RunType::Doctests {
+            reporter.finish(failed, &stderr);
+        }
+        if failed && ty != &RunType::Doctests {
+            if disk_full.is_none() && is_build_disk_full(&stderr) {
+                disk_full = Some(stderr.clone());
+            }
+            if let Some(output) = disk_full {
+                return Err(build_disk_full(&output));
+            }
+        }
+        if let Some(message) = compile_error {
+            return Err(RunError::TestCompile(message));
+        }
     }
     Ok(result)
 }
 
-fn create_command(manifest_path: &str, config: &Config, ty: &RunType) -> Command {
+/// Shows the progress of a build in the way `--build-output` asks for. The
+/// compiler diagnostics are kept so they can be shown if the build fails
+/// whatever the mode.
+struct BuildReporter<W: Write> {
+    mode: BuildOutput,
+    out: W,
+    /// Whether the progress line is updated in place, only done on a terminal
+    live: bool,
+    compiled: usize,
+    fresh: usize,
+    diagnostics: Vec<String>,
+}
+
+impl<W: Write> BuildReporter<W> {
+    fn new(mode: BuildOutput, out: W, live: bool) -> Self {
+        Self {
+            mode,
+            out,
+            live,
+            compiled: 0,
+            fresh: 0,
+            diagnostics: vec![],
+        }
+    }
+
+    fn progress(&self) -> String {
+        format!(
+            "Building tests: {} compiled, {} fresh",
+            self.compiled, self.fresh
+        )
+    }
+
+    /// A crate target was built, `fresh` if it was up to date
+    fn artifact(&mut self, fresh: bool) {
+        if fresh {
+            self.fresh += 1;
+        } else {
+            self.compiled += 1;
+        }
+        if self.mode == BuildOutput::Summary && self.live {
+            let _ = write!(self.out, "\r{}", self.progress());
+            let _ = self.out.flush();
+        }
+    }
+
+    fn diagnostic(&mut self, rendered: Option<String>) {
+        if let Some(rendered) = rendered {
+            if self.mode == BuildOutput::Full {
+                let _ = write!(self.out, "{}", rendered);
+            } else {
+                self.diagnostics.push(rendered);
+            }
+        }
+    }
+
+    /// Ends the progress line. If the build failed the diagnostics and what
+    /// cargo wrote to stderr are shown unless they already have been.
+    fn finish(&mut self, failed: bool, stderr: &str) {
+        if self.mode == BuildOutput::Summary {
+            if self.live {
+                let _ = writeln!(self.out);
+            } else {
+                let _ = writeln!(self.out, "{}", self.progress());
+            }
+        }
+        if failed && self.mode != BuildOutput::Full {
+            for diagnostic in &self.diagnostics {
+                let _ = write!(self.out, "{}", diagnostic);
+            }
+            let _ = write!(self.out, "{}", stderr);
+        }
+        let _ = self.out.flush();
+    }
+}
+
+/// Creates the cargo command to build the run type, `bins` are the bin targets
+/// built for the `Bins` run type if not all of them
+fn create_command(
+    manifest_path: &str,
+    config: &Config,
+    ty: &RunType,
+    bins: Option<&[String]>,
+) -> Command {
     let mut test_cmd = Command::new("cargo");
     if *ty == RunType::Doctests {
         test_cmd.args(&["+nightly", "test"]);
@@ -151,7 +345,7 @@ fn create_command(manifest_path: &str, config: &Config, ty: &RunType) -> Command
                 test_cmd.arg("+beta");
             }
         }
-        if *ty != RunType::Examples {
+        if *ty != RunType::Examples && *ty != RunType::Bins {
             test_cmd.args(&["test", "--no-run"]);
         } else {
             test_cmd.arg("build");
@@ -163,6 +357,15 @@ fn create_command(manifest_path: &str, config: &Config, ty: &RunType) -> Command
         RunType::Doctests => test_cmd.arg("--doc"),
         RunType::Benchmarks => test_cmd.arg("--benches"),
         RunType::Examples => test_cmd.arg("--examples"),
+        RunType::Bins => match bins {
+            Some(bins) => {
+                for bin in bins {
+                    test_cmd.args(&["--bin", bin]);
+                }
+                &mut test_cmd
+            }
+            None => test_cmd.arg("--bins"),
+        },
     };
     init_args(&mut test_cmd, config);
     setup_environment(&mut test_cmd, config);
@@ -172,6 +375,16 @@ fn create_command(manifest_path: &str, config: &Config, ty: &RunType) -> Command
 fn init_args(test_cmd: &mut Command, config: &Config) {
     if config.debug {
         test_cmd.arg("-vvv");
+    } else if config.cargo_verbosity > 0 {
+        test_cmd.arg(format!("-{}", "v".repeat(config.cargo_verbosity as usize)));
+    } else if config.quiet {
+        test_cmd.arg("--quiet");
+    }
+    if let Some(color) = config.color {
+        test_cmd.args(&["--color", color.as_str()]);
+    }
+    for value in &config.cargo_config {
+        test_cmd.args(&["--config", value]);
     }
     if config.locked {
         test_cmd.arg("--locked");
@@ -179,10 +392,10 @@ fn init_args(test_cmd: &mut Command, config: &Config) {
     if config.frozen {
         test_cmd.arg("--frozen");
     }
+    // One list as cargo would take following arguments as test names, each
+    // entry can itself be a space or comma separated list
     if !config.features.is_empty() {
-        let mut args = vec!["--features".to_string()];
-        args.extend_from_slice(&config.features);
-        test_cmd.args(args);
+        test_cmd.args(&["--features", &config.features.join(" ")]);
     }
     if config.all_features {
         test_cmd.arg("--all-features");
@@ -190,9 +403,16 @@ fn init_args(test_cmd: &mut Command, config: &Config) {
     if config.no_default_features {
         test_cmd.arg("--no-default-features");
     }
-    if config.all {
+    // cargo only takes --exclude alongside --workspace
+    if config.all || !config.exclude.is_empty() {
         test_cmd.arg("--workspace");
     }
+    for package in &config.packages {
+        test_cmd.args(&["--package", package]);
+    }
+    for package in &config.exclude {
+        test_cmd.args(&["--exclude", package]);
+    }
     if config.release {
         test_cmd.arg("--release");
     }
@@ -206,6 +426,9 @@ fn init_args(test_cmd: &mut Command, config: &Config) {
     if config.offline {
         test_cmd.arg("--offline");
     }
+    if let Some(ref target) = config.target {
+        test_cmd.args(&["--target", target]);
+    }
     for feat in &config.unstable_features {
         test_cmd.arg(format!("-Z{}", feat));
     }
@@ -241,3 +464,157 @@ fn setup_environment(cmd: &mut Command, config: &Config) {
     }
     cmd.env(rustdoc, value);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command_line(config: &Config) -> String {
+        format!(
+            "{:?}",
+            create_command("Cargo.toml", config, &RunType::Tests, None)
+        )
+    }
+
+    #[test]
+    fn passthrough_flags() {
+        let mut config = Config::default();
+        config.cargo_verbosity = 2;
+        config.color = Some(Color::Always);
+        config.cargo_config = vec!["build.jobs=2".to_string()];
+        config.unstable_features = vec!["build-std".to_string()];
+        config.target = Some("wasm32-wasi".to_string());
+        let cmd = command_line(&config);
+        assert!(cmd.contains("\"-vv\""), "{}", cmd);
+        assert!(cmd.contains("\"--color\" \"always\""), "{}", cmd);
+        assert!(cmd.contains("\"--config\" \"build.jobs=2\""), "{}", cmd);
+        assert!(cmd.contains("\"-Zbuild-std\""), "{}", cmd);
+        assert!(cmd.contains("\"--target\" \"wasm32-wasi\""), "{}", cmd);
+        assert!(!cmd.contains("--quiet"), "{}", cmd);
+    }
+
+    #[test]
+    fn package_selection() {
+        let mut config = Config::default();
+        config.packages = vec!["a".to_string(), "b".to_string()];
+        let cmd = command_line(&config);
+        assert!(
+            cmd.contains("\"--package\" \"a\" \"--package\" \"b\""),
+            "{}",
+            cmd
+        );
+        assert!(!cmd.contains("--workspace"), "{}", cmd);
+
+        let mut config = Config::default();
+        config.exclude = vec!["fuzz".to_string()];
+        let cmd = command_line(&config);
+        assert!(cmd.contains("\"--workspace\""), "{}", cmd);
+        assert!(cmd.contains("\"--exclude\" \"fuzz\""), "{}", cmd);
+    }
+
+    #[test]
+    fn feature_list() {
+        let mut config = Config::default();
+        config.features = vec!["a b".to_string(), "pkg/c".to_string()];
+        config.no_default_features = true;
+        let cmd = command_line(&config);
+        assert!(cmd.contains("\"--features\" \"a b pkg/c\""), "{}", cmd);
+        assert!(cmd.contains("\"--no-default-features\""), "{}", cmd);
+    }
+
+    #[test]
+    fn bin_selection() {
+        let config = Config::default();
+        let all = format!(
+            "{:?}",
+            create_command("Cargo.toml", &config, &RunType::Bins, None)
+        );
+        assert!(all.contains("\"build\""), "{}", all);
+        assert!(all.contains("\"--bins\""), "{}", all);
+        let bins = vec!["add".to_string(), "mul".to_string()];
+        let some = format!(
+            "{:?}",
+            create_command("Cargo.toml", &config, &RunType::Bins, Some(&bins))
+        );
+        assert!(
+            some.contains("\"--bin\" \"add\" \"--bin\" \"mul\""),
+            "{}",
+            some
+        );
+        assert!(!some.contains("--bins"), "{}", some);
+    }
+
+    #[test]
+    fn target_names() {
+        let name = |path: &str| TestBinary::new(PathBuf::from(path), RunType::Tests).target_name();
+        assert_eq!(
+            name("target/debug/deps/my_crate-0123456789abcdef"),
+            "my_crate"
+        );
+        assert_eq!(
+            name("target/debug/deps/flaky-tests-0123456789abcdef"),
+            "flaky-tests"
+        );
+        assert_eq!(name("target/debug/deps/flaky-tests"), "flaky-tests");
+        assert_eq!(name("target/doctests/rust_out"), "rust_out");
+    }
+
+    fn report(mode: BuildOutput, live: bool, failed: bool) -> String {
+        let mut reporter = BuildReporter::new(mode, vec![], live);
+        reporter.artifact(false);
+        reporter.diagnostic(Some("warning: unused variable\n".to_string()));
+        reporter.artifact(true);
+        reporter.artifact(false);
+        reporter.finish(failed, "error: could not compile\n");
+        String::from_utf8(reporter.out).unwrap()
+    }
+
+    #[test]
+    fn build_output_modes() {
+        assert_eq!(report(BuildOutput::Quiet, true, false), "");
+        assert_eq!(
+            report(BuildOutput::Summary, false, false),
+            "Building tests: 2 compiled, 1 fresh\n"
+        );
+        assert_eq!(
+            report(BuildOutput::Summary, true, false),
+            "\rBuilding tests: 1 compiled, 0 fresh\
+             \rBuilding tests: 1 compiled, 1 fresh\
+             \rBuilding tests: 2 compiled, 1 fresh\n"
+        );
+        assert_eq!(
+            report(BuildOutput::Full, false, false),
+            "warning: unused variable\n"
+        );
+    }
+
+    #[test]
+    fn build_failures_always_shown() {
+        let failure = "warning: unused variable\nerror: could not compile\n";
+        assert_eq!(report(BuildOutput::Quiet, false, true), failure);
+        assert_eq!(
+            report(BuildOutput::Summary, false, true),
+            format!("Building tests: 2 compiled, 1 fresh\n{}", failure)
+        );
+        // Cargo's stderr was already shown
+        assert_eq!(
+            report(BuildOutput::Full, false, true),
+            "warning: unused variable\n"
+        );
+    }
+
+    #[test]
+    fn quiet_flag() {
+        let mut config = Config::default();
+        config.quiet = true;
+        let cmd = command_line(&config);
+        assert!(cmd.contains("\"--quiet\""), "{}", cmd);
+        assert!(!cmd.contains("--color"), "{}", cmd);
+
+        // Debug output wins over quiet
+        config.debug = true;
+        let cmd = command_line(&config);
+        assert!(cmd.contains("\"-vvv\""), "{}", cmd);
+        assert!(!cmd.contains("--quiet"), "{}", cmd);
+    }
+}
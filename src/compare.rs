@@ -0,0 +1,200 @@
+//! Traces the project twice with different sets of features and reports which
+//! lines are only covered, or only coverable, when the second set is enabled.
+use crate::config::Config;
+use crate::errors::{with_write_context, RunError};
+use crate::trace;
+use crate::traces::{amount_covered, TraceMap};
+use log::info;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, File};
+use std::path::{Path, PathBuf};
+
+/// Breakdown of the lines in a single file between the two runs
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct FileComparison {
+    pub path: PathBuf,
+    /// Lines covered in both runs
+    pub both: Vec<u64>,
+    /// Lines only covered with features A
+    pub only_a: Vec<u64>,
+    /// Lines coverable in both runs but only covered with features B
+    pub only_b: Vec<u64>,
+    /// Lines which are only coverable with features B, usually due to `cfg`
+    pub coverable_only_b: Vec<u64>,
+}
+
+/// Result of a compare run which is written out as JSON
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Comparison {
+    pub features_a: Vec<String>,
+    pub features_b: Vec<String>,
+    pub files: Vec<FileComparison>,
+}
+
+/// Maps each coverable line in the file to whether it was covered
+fn line_coverage(traces: &TraceMap, path: &Path) -> BTreeMap<u64, bool> {
+    let mut result = BTreeMap::new();
+    for trace in traces.get_child_traces(path) {
+        let covered = amount_covered(&[trace]) > 0;
+        *result.entry(trace.line).or_insert(false) |= covered;
+    }
+    result
+}
+
+/// Diffs the results of the two runs. As the runs may have different sets of
+/// coverable lines they are compared by location rather than by address.
+pub fn diff(a: &TraceMap, b: &TraceMap) -> Vec<FileComparison> {
+    let mut files = a.files();
+    files.extend(b.files());
+    files.sort();
+    files.dedup();
+    files
+        .into_iter()
+        .map(|path| {
+            let lines_a = line_coverage(a, path);
+            let lines_b = line_coverage(b, path);
+            let mut result = FileComparison {
+                path: path.clone(),
+                ..Default::default()
+            };
+            for (line, covered) in &lines_a {
+                match (covered, lines_b.get(line)) {
+                    (true, Some(true)) => result.both.push(*line),
+                    (true, _) => result.only_a.push(*line),
+                    (false, Some(true)) => result.only_b.push(*line),
+                    _ => {}
+                }
+            }
+            result.coverable_only_b = lines_b
+                .keys()
+                .filter(|l| !lines_a.contains_key(l))
+                .copied()
+                .collect();
+            result
+        })
+        .collect()
+}
+
+/// Renders a table with the number of lines in each category per file
+pub fn render(config: &Config, files: &[FileComparison]) -> String {
+    let mut result = format!(
+        "{:<50} {:>8} {:>8} {:>8} {:>10}\n",
+        "File", "Both", "Only A", "Only B", "B coverable"
+    );
+    for file in files {
+        result.push_str(&format!(
+            "{:<50} {:>8} {:>8} {:>8} {:>10}\n",
+            config.display_path(&file.path),
+            file.both.len(),
+            file.only_a.len(),
+            file.only_b.len(),
+            file.coverable_only_b.len()
+        ));
+    }
+    let total = |f: fn(&FileComparison) -> usize| files.iter().map(f).sum::<usize>();
+    result.push_str(&format!(
+        "{:<50} {:>8} {:>8} {:>8} {:>10}\n",
+        "Total",
+        total(|f| f.both.len()),
+        total(|f| f.only_a.len()),
+        total(|f| f.only_b.len()),
+        total(|f| f.coverable_only_b.len())
+    ));
+    result
+}
+
+/// Splits feature strings such as "a b" or "a,b" into individual features
+pub fn split_features(features: &str) -> Vec<String> {
+    features
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|x| !x.is_empty())
+        .map(ToString::to_string)
+        .collect()
+}
+
+fn with_features(configs: &[Config], features: &[String]) -> Vec<Config> {
+    configs
+        .iter()
+        .map(|c| {
+            let mut c = c.clone();
+            c.features = features.to_vec();
+            c
+        })
+        .collect()
+}
+
+/// Traces the project once with each set of features, both runs share a
+/// target directory so dependencies are only rebuilt if features change them.
+/// Fails if the tests fail in either run.
+pub fn run(
+    configs: &[Config],
+    features_a: Vec<String>,
+    features_b: Vec<String>,
+) -> Result<(), RunError> {
+    info!("Tracing with features A: {:?}", features_a);
+    let a = trace(&with_features(configs, &features_a))?;
+    let mut configs_b = with_features(configs, &features_b);
+    for c in &mut configs_b {
+        // The first run already cleaned if asked to
+        c.force_clean = false;
+    }
+    info!("Tracing with features B: {:?}", features_b);
+    let b = trace(&configs_b)?;
+
+    let config = configs
+        .iter()
+        .find(|c| c.name == "report")
+        .or_else(|| configs.first())
+        .cloned()
+        .unwrap_or_default();
+    let comparison = Comparison {
+        features_a,
+        features_b,
+        files: diff(&a, &b),
+    };
+    println!("|| Feature Comparison:");
+    for line in render(&config, &comparison.files).lines() {
+        println!("|| {}", line);
+    }
+    let output_dir = config.output_dir();
+    create_dir_all(&output_dir).map_err(|e| with_write_context(&output_dir, e))?;
+    let file_path = output_dir.join("tarpaulin-compare.json");
+    let file = File::create(&file_path).map_err(|e| with_write_context(&file_path, e))?;
+    serde_json::to_writer(file, &comparison)
+        .map_err(|e| with_write_context(&file_path, e.into()))?;
+    info!("Comparison written to {}", file_path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traces::Trace;
+
+    #[test]
+    fn three_way_breakdown() {
+        let file = Path::new("/tmp/compare/src/lib.rs");
+        let mut a = TraceMap::new();
+        let mut b = TraceMap::new();
+        for (line, hits) in &[(1, 1), (2, 1), (3, 0), (4, 0)] {
+            a.add_trace(file, Trace::test_line(*line, *hits));
+        }
+        for (line, hits) in &[(1, 1), (2, 0), (3, 1), (4, 0), (7, 1), (8, 0)] {
+            b.add_trace(file, Trace::test_line(*line, *hits));
+        }
+        let res = diff(&a, &b);
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].both, vec![1]);
+        assert_eq!(res[0].only_a, vec![2]);
+        assert_eq!(res[0].only_b, vec![3]);
+        assert_eq!(res[0].coverable_only_b, vec![7, 8]);
+    }
+
+    #[test]
+    fn feature_lists() {
+        assert!(split_features("").is_empty());
+        assert_eq!(split_features("x"), vec!["x"]);
+        assert_eq!(split_features("x, y z"), vec!["x", "y", "z"]);
+    }
+}
@@ -1,6 +1,9 @@
 pub use self::types::*;
 
+pub use self::parse::find_manifest;
 use self::parse::*;
+use crate::plan::selected_packages;
+use crate::traces::TraceMap;
 use cargo_metadata::{Metadata, MetadataCommand, Package};
 use clap::ArgMatches;
 use coveralls_api::CiService;
@@ -9,7 +12,7 @@ use log::{error, info, warn};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::cell::{Ref, RefCell};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::env;
 use std::fs::File;
 use std::io::{Error, ErrorKind, Read};
@@ -21,6 +24,32 @@ pub mod types;
 
 pub struct ConfigWrapper(pub Vec<Config>);
 
+/// Default percentage points coverage must beat a ratchet floor by to raise it
+pub const DEFAULT_RATCHET_MARGIN: f64 = 0.5;
+
+/// Default number of addresses instrumented for a line before it's sampled
+pub const DEFAULT_MAX_ADDRESSES_PER_LINE: usize = 8;
+
+/// Name patterns of the test helper functions left out with
+/// `--exclude-test-scaffolding` if no others are given
+pub const DEFAULT_SCAFFOLDING_PATTERNS: &[&str] = &["setup*", "teardown*", "*_fixture"];
+
+/// Number of uncovered ranges annotated by the GitHub Actions output, GitHub
+/// only shows 10 warnings from a step
+pub const DEFAULT_MAX_ANNOTATIONS: usize = 10;
+
+/// Default percentage from which the coverage badge is yellow instead of red
+pub const DEFAULT_BADGE_YELLOW: f64 = 50.0;
+
+/// Default percentage from which the coverage badge is green
+pub const DEFAULT_BADGE_GREEN: f64 = 80.0;
+
+/// Number of suggestions listed when `--suggest` is given without a number
+pub const DEFAULT_SUGGESTIONS: usize = 10;
+
+/// Number of threads files are blamed on for `--age-buckets` by default
+pub const DEFAULT_AGE_ANALYSIS_JOBS: usize = 4;
+
 /// Specifies the current configuration tarpaulin is using.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(default)]
@@ -49,6 +78,20 @@ pub struct Config {
     pub verbose: bool,
     /// Debug flag for printing internal debugging information to the user
     pub debug: bool,
+    /// Only print warnings and errors, cargo is passed `--quiet`
+    pub quiet: bool,
+    /// Number of `-v` flags passed to cargo
+    #[serde(rename = "cargo-verbosity")]
+    pub cargo_verbosity: u8,
+    /// Whether output is coloured, passed to cargo with `--color`
+    pub color: Option<Color>,
+    /// How much of the build output is shown, if not set it's the full output
+    /// with `--verbose` and a summary otherwise. See `Config::build_output`
+    #[serde(rename = "build-output")]
+    pub build_output: Option<BuildOutput>,
+    /// Cargo `KEY=VALUE` config overrides passed to cargo with `--config`
+    #[serde(rename = "cargo-config")]
+    pub cargo_config: Vec<String>,
     /// Flag to count hits in coverage
     pub count: bool,
     /// Flag specifying to run line coverage (default)
@@ -57,9 +100,10 @@ pub struct Config {
     /// Flag specifying to run branch coverage
     #[serde(rename = "branch")]
     pub branch_coverage: bool,
-    /// Directory to write output files
+    /// Directory to write output files, relative to the root. `None` writes
+    /// them to the directory tarpaulin was run from.
     #[serde(rename = "output-dir")]
-    pub output_directory: PathBuf,
+    pub output_directory: Option<PathBuf>,
     /// Key relating to coveralls service or repo
     pub coveralls: Option<String>,
     /// Enum representing CI tool used.
@@ -70,6 +114,40 @@ pub struct Config {
     /// instead.
     #[serde(rename = "report-uri")]
     pub report_uri: Option<String>,
+    /// Upload the results to Codecov
+    #[serde(rename = "codecov-upload")]
+    pub codecov_upload: bool,
+    /// Token for the Codecov upload, the `CODECOV_TOKEN` environment variable
+    /// is used if it's not set
+    #[serde(rename = "codecov-token")]
+    pub codecov_token: Option<String>,
+    /// Path to write a JUnit report of the results of each test to
+    pub junit: Option<PathBuf>,
+    /// Only list this many of the worst covered files in the Markdown report
+    #[serde(rename = "markdown-max-files")]
+    pub markdown_max_files: Option<usize>,
+    /// Only list files with less coverage than this percentage in the
+    /// console summary and Markdown report, the totals are for every file
+    #[serde(rename = "report-below")]
+    pub report_below: Option<f64>,
+    /// Name files by their absolute path in the Clover report
+    #[serde(rename = "clover-absolute-paths")]
+    pub clover_absolute_paths: bool,
+    /// Directory the paths in the Sonar report are relative to, the SonarQube
+    /// scanner's working directory
+    #[serde(rename = "sonar-root")]
+    pub sonar_root: Option<PathBuf>,
+    /// Path to write an SVG coverage badge to
+    pub badge: Option<PathBuf>,
+    /// Coverage percentage from which the badge is yellow, it's red below
+    #[serde(rename = "badge-yellow")]
+    pub badge_yellow: f64,
+    /// Coverage percentage from which the badge is green
+    #[serde(rename = "badge-green")]
+    pub badge_green: f64,
+    /// Most uncovered ranges annotated by the GitHub Actions output
+    #[serde(rename = "max-annotations")]
+    pub max_annotations: usize,
     /// Forward unexpected signals back to the tracee. Used for tests which
     /// rely on signals to work.
     #[serde(rename = "forward")]
@@ -86,6 +164,14 @@ pub struct Config {
     /// Duration to wait before a timeout occurs
     #[serde(deserialize_with = "humantime_serde", rename = "timeout")]
     pub test_timeout: Duration,
+    /// Time a single libtest test can run without the harness printing a
+    /// result before it's killed as hung
+    #[serde(deserialize_with = "humantime_serde", rename = "per-test-timeout")]
+    pub per_test_timeout: Option<Duration>,
+    /// Megabytes of the captured output of each test binary kept for its
+    /// results, the rest is dropped
+    #[serde(rename = "capture-limit")]
+    pub capture_limit: Option<u64>,
     /// Build in release mode
     pub release: bool,
     /// Build the tests only don't run coverage
@@ -100,9 +186,22 @@ pub struct Config {
     pub target_dir: Option<PathBuf>,
     /// Run tarpaulin on project without accessing the network
     pub offline: bool,
+    /// Target triple to build for, the host if not set
+    pub target: Option<String>,
+    /// Count code under a `cfg` which is false for the target as coverable
+    #[serde(rename = "count-all-targets")]
+    pub count_all_targets: bool,
     /// Types of tests for tarpaulin to collect coverage on
     #[serde(rename = "run-types")]
     pub run_types: Vec<RunType>,
+    /// Bin targets run for the `Bins` run type, all of them if empty
+    pub bins: Vec<String>,
+    /// Only run the `default-run` bin of each package for the `Bins` run type
+    #[serde(rename = "default-bin-only")]
+    pub default_bin_only: bool,
+    /// Arguments each bin is run with for the `Bins` run type, by bin name
+    #[serde(rename = "bin-args")]
+    pub bin_args: BTreeMap<String, Vec<String>>,
     /// Packages to include when building the target project
     pub packages: Vec<String>,
     /// Packages to exclude from testing
@@ -123,9 +222,167 @@ pub struct Config {
     pub unstable_features: Vec<String>,
     /// Output files to generate
     #[serde(rename = "out")]
-    pub generate: Vec<OutputFile>,
+    pub generate: Vec<OutputFormat>,
+    /// Compress the report files written to the output directory
+    #[serde(rename = "compress-output")]
+    pub compress_output: Option<Compression>,
+    /// Read the debug info of every test binary instead of using the traces
+    /// cached from previous runs
+    #[serde(rename = "no-cache")]
+    pub no_cache: bool,
+    /// Write the HTML report as an index page and a page per source file
+    #[serde(rename = "html-split")]
+    pub html_split: bool,
+    /// Time each test binary untraced before tracing it to report the
+    /// slowdown caused by tracing
+    #[serde(rename = "measure-overhead")]
+    pub measure_overhead: bool,
+    /// Count the code derives generate on struct, enum and union definitions
+    #[serde(rename = "include-derives")]
+    pub include_derives: bool,
+    /// Report the coverage weighted by the complexity of each line alongside
+    /// the plain coverage
+    #[serde(rename = "weighted-coverage")]
+    pub weighted_coverage: bool,
+    /// Leave the lines of test helper functions such as `setup` out of the
+    /// coverable lines, their hits are still recorded
+    #[serde(rename = "exclude-test-scaffolding")]
+    pub exclude_test_scaffolding: bool,
+    /// Name patterns of the test helper functions excluded with
+    /// `exclude-test-scaffolding`, the defaults are used if empty
+    #[serde(rename = "scaffolding-patterns")]
+    pub scaffolding_patterns: Vec<String>,
+    /// Move the hits saved from the previous run to where their lines are now
+    /// in files that have changed since instead of dropping them
+    #[serde(rename = "merge-with-diff")]
+    pub merge_with_diff: bool,
+    /// How source files are named in the reports
+    #[serde(rename = "path-format")]
+    pub path_format: PathFormat,
+    /// Whether the console summary has a row per file or per directory
+    #[serde(rename = "report-by")]
+    pub report_by: ReportBy,
+    /// Newline delimited JSON file to append a summary of the results to
+    #[serde(rename = "append-history")]
+    pub append_history: Option<PathBuf>,
+    /// File to write statistics about the run to for monitoring builds
+    #[serde(rename = "stats-file")]
+    pub stats_file: Option<PathBuf>,
+    /// File to write every planned breakpoint and whether it was inserted and
+    /// hit to, for debugging the mapping of addresses to lines
+    #[serde(rename = "dump-breakpoints")]
+    pub dump_breakpoints: Option<PathBuf>,
+    /// File to write a bundle of diagnostics for a bug report to once the
+    /// run ends
+    #[serde(rename = "make-repro")]
+    pub make_repro: Option<PathBuf>,
+    /// Include the traced source files in the repro bundle
+    #[serde(rename = "include-sources")]
+    pub include_sources: bool,
+    /// Include the line tables of the last traced binary in the repro bundle
+    #[serde(rename = "repro-line-tables")]
+    pub repro_line_tables: bool,
+    /// Open an interactive terminal browser of the results after reporting
+    pub tui: bool,
+    /// Whether the tracer and test are pinned to a processor
+    pub affinity: Affinity,
+    /// Add the number of decision points in each file to the console summary
+    #[serde(rename = "extended-table")]
+    pub extended_table: bool,
+    /// List the uncovered lines of each file under its row of the summary
+    #[serde(rename = "show-missing")]
+    pub show_missing: bool,
+    /// Split uncovered lines by whether their function was entered
+    #[serde(rename = "missed-analysis")]
+    pub missed_analysis: bool,
+    /// Number of places most worth testing next to list after the report
+    pub suggest: Option<usize>,
+    /// Whether `debug_assert!` lines are coverable
+    #[serde(rename = "count-debug-asserts")]
+    pub count_debug_asserts: bool,
+    /// Percentage of coverage below which tarpaulin returns an error
+    #[serde(rename = "fail-under")]
+    pub fail_under: Option<f64>,
+    /// Branch or revision the files added by a change are found from, its
+    /// merge base with `HEAD` is diffed against the working tree
+    #[serde(rename = "diff-base")]
+    pub diff_base: Option<String>,
+    /// Percentage of coverage each file added since `diff_base` must reach
+    #[serde(rename = "fail-under-new-files")]
+    pub fail_under_new_files: Option<f64>,
+    /// Upper bounds in days of the buckets lines are grouped into by how long
+    /// ago they were last changed, empty to not blame the files
+    #[serde(rename = "age-buckets")]
+    pub age_buckets: Vec<u64>,
+    /// Number of threads files are blamed on for `age_buckets`
+    #[serde(rename = "age-analysis-jobs")]
+    pub age_analysis_jobs: usize,
+    /// JSON report of a previous run to show the change in coverage since
+    pub compare: Option<PathBuf>,
+    /// Percentage points the total can drop by since the `compare` report
+    /// before the run fails
+    #[serde(rename = "max-coverage-drop")]
+    pub max_coverage_drop: Option<f64>,
+    /// Run types whose hits count towards the `fail_under` threshold, if empty
+    /// all run types are used
+    #[serde(rename = "threshold-run-types")]
+    pub threshold_run_types: Vec<RunType>,
+    /// Fail if any lines are only covered by ignored tests
+    #[serde(rename = "fail-if-only-ignored")]
+    pub fail_if_only_ignored: bool,
+    /// File of coverage floors which fail the run if coverage drops below them
+    /// and are raised when coverage improves
+    pub ratchet: Option<PathBuf>,
+    /// Percentage points coverage must beat a ratchet floor by to raise it
+    #[serde(rename = "ratchet-margin")]
+    pub ratchet_margin: f64,
+    /// Percentage points coverage can drop below a ratchet floor without failing
+    #[serde(rename = "ratchet-tolerance")]
+    pub ratchet_tolerance: f64,
+    /// Lines with more addresses than this only have this many instrumented,
+    /// 0 instruments every address
+    #[serde(rename = "max-addresses-per-line")]
+    pub max_addresses_per_line: usize,
+    /// Record the time of the first and last hit of each trace
+    #[serde(rename = "profile-hits")]
+    pub profile_hits: bool,
+    /// Where the tests read stdin from
+    pub stdin: Stdin,
+    /// Fail instead of warning when traces are inconsistent with the source
+    #[serde(rename = "strict-instrumentation")]
+    pub strict_instrumentation: bool,
+    /// Fail if a source file can't be read for the reports instead of
+    /// reporting it without its source
+    #[serde(rename = "strict-reporting")]
+    pub strict_reporting: bool,
+    /// Paths to demote each kind of analysis warning to a debug log for,
+    /// these aren't counted or failed on by `strict-instrumentation`
+    pub suppress: BTreeMap<String, Vec<String>>,
+    /// Times a failing test binary is run again, the coverage of every
+    /// attempt is kept
+    pub retries: usize,
+    /// Names of test binaries which are never retried
+    #[serde(rename = "no-retry-tests")]
+    pub no_retry_tests: Vec<String>,
+    /// Environment variables which must be set for this config to run
+    #[serde(rename = "required-env")]
+    pub required_env: Vec<String>,
+    /// Environment variables which skip this config when any are set
+    #[serde(rename = "skip-if-env")]
+    pub skip_if_env: Vec<String>,
+    /// Names of configs to run regardless of their environment conditions
+    #[serde(rename = "force-config")]
+    pub force_config: Vec<String>,
     #[serde(skip_deserializing, skip_serializing)]
     pub metadata: RefCell<Option<Metadata>>,
+    /// Directories of the workspace members and whether they're selected by
+    /// `--packages` and `--exclude`, found on first use
+    #[serde(skip_deserializing, skip_serializing)]
+    package_dirs: RefCell<Option<Vec<(PathBuf, bool)>>>,
+    /// Directory tarpaulin was run from, relative paths given on the command
+    /// line are relative to it
+    #[serde(skip_deserializing, skip_serializing)]
+    pub invocation_dir: PathBuf,
 }
 
 impl Default for Config {
@@ -133,6 +390,9 @@ impl Default for Config {
         Config {
             name: String::new(),
             run_types: vec![RunType::Tests],
+            bins: vec![],
+            default_bin_only: false,
+            bin_args: BTreeMap::new(),
             manifest: default_manifest(),
             config: None,
             root: Default::default(),
@@ -142,14 +402,41 @@ impl Default for Config {
             force_clean: false,
             verbose: false,
             debug: false,
+            quiet: false,
+            cargo_verbosity: 0,
+            color: None,
+            build_output: None,
+            cargo_config: vec![],
             count: false,
             line_coverage: true,
             branch_coverage: false,
             generate: vec![],
-            output_directory: Default::default(),
+            output_directory: None,
+            compress_output: None,
+            no_cache: false,
+            html_split: false,
+            measure_overhead: false,
+            include_derives: false,
+            weighted_coverage: false,
+            exclude_test_scaffolding: false,
+            scaffolding_patterns: vec![],
+            merge_with_diff: false,
+            path_format: PathFormat::Relative,
+            report_by: ReportBy::File,
             coveralls: None,
             ci_tool: None,
             report_uri: None,
+            codecov_upload: false,
+            codecov_token: None,
+            junit: None,
+            markdown_max_files: None,
+            report_below: None,
+            clover_absolute_paths: false,
+            sonar_root: None,
+            badge: None,
+            badge_yellow: DEFAULT_BADGE_YELLOW,
+            badge_green: DEFAULT_BADGE_GREEN,
+            max_annotations: DEFAULT_MAX_ANNOTATIONS,
             forward_signals: false,
             no_default_features: false,
             features: vec![],
@@ -161,6 +448,8 @@ impl Default for Config {
             excluded_files_raw: vec![],
             varargs: vec![],
             test_timeout: Duration::from_secs(60),
+            per_test_timeout: None,
+            capture_limit: None,
             release: false,
             all_features: false,
             no_run: false,
@@ -168,7 +457,47 @@ impl Default for Config {
             frozen: false,
             target_dir: None,
             offline: false,
+            target: None,
+            count_all_targets: false,
+            append_history: None,
+            stats_file: None,
+            dump_breakpoints: None,
+            make_repro: None,
+            include_sources: false,
+            repro_line_tables: false,
+            tui: false,
+            affinity: Affinity::Auto,
+            extended_table: false,
+            show_missing: false,
+            missed_analysis: false,
+            suggest: None,
+            count_debug_asserts: true,
+            fail_under: None,
+            diff_base: None,
+            fail_under_new_files: None,
+            age_buckets: vec![],
+            age_analysis_jobs: DEFAULT_AGE_ANALYSIS_JOBS,
+            compare: None,
+            max_coverage_drop: None,
+            threshold_run_types: vec![],
+            fail_if_only_ignored: false,
+            ratchet: None,
+            ratchet_margin: DEFAULT_RATCHET_MARGIN,
+            ratchet_tolerance: 0.0,
+            max_addresses_per_line: DEFAULT_MAX_ADDRESSES_PER_LINE,
+            profile_hits: false,
+            stdin: Stdin::Null,
+            strict_instrumentation: false,
+            strict_reporting: false,
+            suppress: BTreeMap::new(),
+            retries: 0,
+            no_retry_tests: vec![],
+            required_env: vec![],
+            skip_if_env: vec![],
+            force_config: vec![],
             metadata: RefCell::new(None),
+            package_dirs: RefCell::new(None),
+            invocation_dir: env::current_dir().unwrap_or_default(),
         }
     }
 }
@@ -178,6 +507,8 @@ impl<'a> From<&'a ArgMatches<'a>> for ConfigWrapper {
         info!("Creating config");
         let debug = args.is_present("debug");
         let verbose = args.is_present("verbose") || debug;
+        // -v makes tarpaulin verbose and -vv also makes cargo verbose
+        let cargo_verbosity = args.occurrences_of("verbose").saturating_sub(1).min(2) as u8;
         let excluded_files = get_excluded(args);
         let excluded_files_raw = get_list(args, "exclude-files");
 
@@ -187,20 +518,50 @@ impl<'a> From<&'a ArgMatches<'a>> for ConfigWrapper {
             config: None,
             root: get_root(args),
             run_types: get_run_types(args),
+            bins: get_list(args, "bin"),
+            default_bin_only: args.is_present("default-bin-only"),
+            bin_args: BTreeMap::new(),
             run_ignored: args.is_present("ignored"),
             ignore_tests: args.is_present("ignore-tests"),
             ignore_panics: args.is_present("ignore-panics"),
             force_clean: args.is_present("force-clean"),
             verbose,
             debug,
+            quiet: args.is_present("quiet"),
+            cargo_verbosity,
+            color: get_color(args),
+            build_output: get_build_output(args),
+            cargo_config: get_cargo_config(args),
             count: args.is_present("count"),
             line_coverage: get_line_cov(args),
             branch_coverage: get_branch_cov(args),
             generate: get_outputs(args),
-            output_directory: get_output_directory(args),
+            output_directory: get_path(args, "output-dir"),
+            compress_output: get_compression(args),
+            no_cache: args.is_present("no-cache"),
+            html_split: args.is_present("html-split"),
+            measure_overhead: args.is_present("measure-overhead"),
+            include_derives: args.is_present("include-derives"),
+            weighted_coverage: args.is_present("weighted-coverage"),
+            exclude_test_scaffolding: args.is_present("exclude-test-scaffolding"),
+            scaffolding_patterns: get_list(args, "scaffolding-patterns"),
+            merge_with_diff: args.is_present("merge-with-diff"),
+            path_format: get_path_format(args),
+            report_by: get_report_by(args),
             coveralls: get_coveralls(args),
             ci_tool: get_ci(args),
             report_uri: get_report_uri(args),
+            codecov_upload: args.is_present("codecov-upload"),
+            codecov_token: args.value_of("codecov-token").map(ToString::to_string),
+            junit: get_path(args, "junit"),
+            markdown_max_files: get_markdown_max_files(args),
+            report_below: get_report_below(args),
+            clover_absolute_paths: args.is_present("clover-absolute-paths"),
+            sonar_root: get_path(args, "sonar-root"),
+            badge: get_path(args, "badge"),
+            badge_yellow: DEFAULT_BADGE_YELLOW,
+            badge_green: DEFAULT_BADGE_GREEN,
+            max_annotations: get_max_annotations(args),
             forward_signals: args.is_present("forward"),
             all_features: args.is_present("all-features"),
             no_default_features: args.is_present("no-default-features"),
@@ -213,18 +574,59 @@ impl<'a> From<&'a ArgMatches<'a>> for ConfigWrapper {
             excluded_files_raw: excluded_files_raw.clone(),
             varargs: get_list(args, "args"),
             test_timeout: get_timeout(args),
+            per_test_timeout: get_per_test_timeout(args),
+            capture_limit: get_capture_limit(args),
             release: args.is_present("release"),
             no_run: args.is_present("no-run"),
             locked: args.is_present("locked"),
             frozen: args.is_present("frozen"),
             target_dir: get_target_dir(args),
             offline: args.is_present("offline"),
+            target: args.value_of("target").map(ToString::to_string),
+            count_all_targets: args.is_present("count-all-targets"),
+            append_history: get_path(args, "append-history"),
+            stats_file: get_path(args, "stats-file"),
+            dump_breakpoints: get_path(args, "dump-breakpoints"),
+            make_repro: get_path(args, "make-repro"),
+            include_sources: args.is_present("include-sources"),
+            repro_line_tables: args.is_present("repro-line-tables"),
+            tui: args.is_present("tui"),
+            affinity: get_affinity(args),
+            extended_table: args.is_present("extended-table"),
+            show_missing: args.is_present("show-missing"),
+            missed_analysis: args.is_present("missed-analysis"),
+            suggest: get_suggest(args),
+            count_debug_asserts: args.value_of("count-debug-asserts") != Some("off"),
+            fail_under: get_fail_under(args),
+            diff_base: args.value_of("diff-base").map(ToString::to_string),
+            fail_under_new_files: get_fail_under_new_files(args),
+            age_buckets: get_age_buckets(args),
+            age_analysis_jobs: get_age_analysis_jobs(args),
+            compare: get_path(args, "compare"),
+            max_coverage_drop: get_max_coverage_drop(args),
+            threshold_run_types: get_threshold_run_types(args),
+            fail_if_only_ignored: args.is_present("fail-if-only-ignored"),
+            ratchet: get_path(args, "ratchet"),
+            ratchet_margin: get_ratchet_margin(args),
+            ratchet_tolerance: get_ratchet_tolerance(args),
+            max_addresses_per_line: get_max_addresses_per_line(args),
+            profile_hits: args.is_present("profile-hits"),
+            stdin: get_stdin(args),
+            strict_instrumentation: args.is_present("strict-instrumentation"),
+            strict_reporting: args.is_present("strict-reporting"),
+            suppress: BTreeMap::new(),
+            retries: get_retries(args),
+            no_retry_tests: get_list(args, "no-retry-tests"),
+            required_env: vec![],
+            skip_if_env: vec![],
+            force_config: get_list(args, "force-config"),
             metadata: RefCell::new(None),
+            package_dirs: RefCell::new(None),
+            invocation_dir: env::current_dir().unwrap(),
         };
         if args.is_present("ignore-config") {
             Self(vec![args_config])
-        } else if args.is_present("config") {
-            let mut path = PathBuf::from(args.value_of("config").unwrap());
+        } else if let Some(mut path) = get_config_file(args) {
             if path.is_relative() {
                 path = env::current_dir()
                     .unwrap()
@@ -297,11 +699,12 @@ impl Config {
         if let Some(root) = &self.root {
             Self::check_path_for_configs(&root)
         } else {
-            if let Some(root) = self.manifest.clone().parent() {
-                Self::check_path_for_configs(&root)
-            } else {
-                None
-            }
+            // Run from a workspace member the package's config is used over
+            // the workspace's
+            self.manifest
+                .parent()
+                .and_then(Self::check_path_for_configs)
+                .or_else(|| Self::check_path_for_configs(self.root()))
         }
     }
 
@@ -338,9 +741,32 @@ impl Config {
             Error::new(ErrorKind::InvalidData, format!("{}", e))
         })?;
 
+        for (name, key) in unknown_keys(buffer) {
+            warn!("Config {} has an unknown key {}, it's ignored", name, key);
+        }
+
         let mut result = Vec::new();
         for (name, mut conf) in map.iter_mut() {
             conf.name = name.to_string();
+            if let Some(var) = conf
+                .required_env
+                .iter()
+                .find(|v| conf.skip_if_env.contains(v))
+            {
+                let msg = format!(
+                    "Config {} can never run, {} is in both required-env and skip-if-env",
+                    name, var
+                );
+                error!("{}", msg);
+                return Err(Error::new(ErrorKind::InvalidData, msg));
+            }
+            for kind in conf.suppress.keys() {
+                if let Err(e) = kind.parse::<WarningKind>() {
+                    let msg = format!("Config {} has an invalid suppress table, {}", name, e);
+                    error!("{}", msg);
+                    return Err(Error::new(ErrorKind::InvalidData, msg));
+                }
+            }
             result.push(conf.clone());
         }
         if result.is_empty() {
@@ -359,8 +785,178 @@ impl Config {
         } else if other.verbose {
             self.verbose = other.verbose;
         }
+        self.quiet |= other.quiet;
+        if other.target.is_some() {
+            self.target = other.target.clone();
+        }
+        self.count_all_targets |= other.count_all_targets;
+        self.cargo_verbosity = self.cargo_verbosity.max(other.cargo_verbosity);
+        if other.color.is_some() {
+            self.color = other.color;
+        }
+        if other.build_output.is_some() {
+            self.build_output = other.build_output;
+        }
+        self.cargo_config.extend_from_slice(&other.cargo_config);
         self.manifest = other.manifest.clone();
         self.root = other.root.clone();
+        self.invocation_dir = other.invocation_dir.clone();
+        if other.append_history.is_some() {
+            self.append_history = other.append_history.clone();
+        }
+        if other.stats_file.is_some() {
+            self.stats_file = other.stats_file.clone();
+        }
+        if other.dump_breakpoints.is_some() {
+            self.dump_breakpoints = other.dump_breakpoints.clone();
+        }
+        if other.make_repro.is_some() {
+            self.make_repro = other.make_repro.clone();
+        }
+        self.include_sources |= other.include_sources;
+        self.repro_line_tables |= other.repro_line_tables;
+        // Formats given on the command line are generated as well as those in
+        // the config file
+        for format in &other.generate {
+            if !self.generate.contains(format) {
+                self.generate.push(format.clone());
+            }
+        }
+        if other.output_directory.is_some() {
+            self.output_directory = other.output_directory.clone();
+        }
+        if other.compress_output.is_some() {
+            self.compress_output = other.compress_output;
+        }
+        self.no_cache |= other.no_cache;
+        self.fail_if_only_ignored |= other.fail_if_only_ignored;
+        self.html_split |= other.html_split;
+        self.measure_overhead |= other.measure_overhead;
+        self.include_derives |= other.include_derives;
+        self.weighted_coverage |= other.weighted_coverage;
+        self.exclude_test_scaffolding |= other.exclude_test_scaffolding;
+        if !other.scaffolding_patterns.is_empty() {
+            self.scaffolding_patterns = other.scaffolding_patterns.clone();
+        }
+        self.merge_with_diff |= other.merge_with_diff;
+        self.codecov_upload |= other.codecov_upload;
+        if other.codecov_token.is_some() {
+            self.codecov_token = other.codecov_token.clone();
+        }
+        if other.junit.is_some() {
+            self.junit = other.junit.clone();
+        }
+        if other.per_test_timeout.is_some() {
+            self.per_test_timeout = other.per_test_timeout;
+        }
+        if other.capture_limit.is_some() {
+            self.capture_limit = other.capture_limit;
+        }
+        if other.markdown_max_files.is_some() {
+            self.markdown_max_files = other.markdown_max_files;
+        }
+        if other.report_below.is_some() {
+            self.report_below = other.report_below;
+        }
+        self.clover_absolute_paths |= other.clover_absolute_paths;
+        if other.sonar_root.is_some() {
+            self.sonar_root = other.sonar_root.clone();
+        }
+        if other.badge.is_some() {
+            self.badge = other.badge.clone();
+        }
+        if other.max_annotations != DEFAULT_MAX_ANNOTATIONS {
+            self.max_annotations = other.max_annotations;
+        }
+        if other.path_format != PathFormat::default() {
+            self.path_format = other.path_format;
+        }
+        if other.report_by != ReportBy::default() {
+            self.report_by = other.report_by;
+        }
+        self.tui |= other.tui;
+        self.missed_analysis |= other.missed_analysis;
+        if other.suggest.is_some() {
+            self.suggest = other.suggest;
+        }
+        self.extended_table |= other.extended_table;
+        self.show_missing |= other.show_missing;
+        self.profile_hits |= other.profile_hits;
+        self.strict_instrumentation |= other.strict_instrumentation;
+        self.strict_reporting |= other.strict_reporting;
+        for (kind, paths) in &other.suppress {
+            self.suppress
+                .entry(kind.clone())
+                .or_default()
+                .extend(paths.iter().cloned());
+        }
+        if other.stdin != Stdin::Null {
+            self.stdin = other.stdin.clone();
+        }
+        self.count_debug_asserts &= other.count_debug_asserts;
+        if other.fail_under.is_some() {
+            self.fail_under = other.fail_under;
+        }
+        if other.diff_base.is_some() {
+            self.diff_base = other.diff_base.clone();
+        }
+        if other.fail_under_new_files.is_some() {
+            self.fail_under_new_files = other.fail_under_new_files;
+        }
+        if !other.age_buckets.is_empty() {
+            self.age_buckets = other.age_buckets.clone();
+        }
+        if other.age_analysis_jobs != DEFAULT_AGE_ANALYSIS_JOBS {
+            self.age_analysis_jobs = other.age_analysis_jobs;
+        }
+        if other.compare.is_some() {
+            self.compare = other.compare.clone();
+        }
+        if other.max_coverage_drop.is_some() {
+            self.max_coverage_drop = other.max_coverage_drop;
+        }
+        // Run types given on the command line such as with --all-targets
+        // override the config file, tests is the default so is ignored
+        if other.run_types != [RunType::Tests] {
+            self.run_types = other.run_types.clone();
+        }
+        if !other.bins.is_empty() {
+            self.bins = other.bins.clone();
+        }
+        self.default_bin_only |= other.default_bin_only;
+        self.bin_args.extend(
+            other
+                .bin_args
+                .iter()
+                .map(|(name, args)| (name.clone(), args.clone())),
+        );
+        if other.ratchet.is_some() {
+            self.ratchet = other.ratchet.clone();
+        }
+        if other.ratchet_margin != DEFAULT_RATCHET_MARGIN {
+            self.ratchet_margin = other.ratchet_margin;
+        }
+        if other.ratchet_tolerance > 0.0 {
+            self.ratchet_tolerance = other.ratchet_tolerance;
+        }
+        if other.max_addresses_per_line != DEFAULT_MAX_ADDRESSES_PER_LINE {
+            self.max_addresses_per_line = other.max_addresses_per_line;
+        }
+        if other.retries > 0 {
+            self.retries = other.retries;
+        }
+        if !other.no_retry_tests.is_empty() {
+            self.no_retry_tests = other.no_retry_tests.clone();
+        }
+        if !other.threshold_run_types.is_empty() {
+            self.threshold_run_types = other.threshold_run_types.clone();
+        }
+        if other.affinity != Affinity::Auto {
+            self.affinity = other.affinity;
+        }
+        if !other.force_config.is_empty() {
+            self.force_config = other.force_config.clone();
+        }
         if !other.excluded_files_raw.is_empty() {
             self.excluded_files_raw
                 .extend_from_slice(&other.excluded_files_raw);
@@ -376,6 +972,127 @@ impl Config {
         self.coveralls.is_some()
     }
 
+    /// Whether the planned breakpoints are recorded, for `--dump-breakpoints`
+    /// or a repro bundle
+    pub fn records_breakpoints(&self) -> bool {
+        self.dump_breakpoints.is_some() || self.make_repro.is_some()
+    }
+
+    /// Whether the output of the tests is captured to get the result of each
+    /// test, only done for the JUnit report or to find hung tests
+    pub fn capture_test_output(&self) -> bool {
+        self.junit.is_some()
+            || self.per_test_timeout.is_some()
+            || self.generate.contains(&OutputFormat::Junit)
+    }
+
+    /// Name patterns of the test helper functions which aren't coverable, empty
+    /// unless `exclude-test-scaffolding` is set
+    pub fn scaffolding_patterns(&self) -> Vec<String> {
+        if !self.exclude_test_scaffolding {
+            vec![]
+        } else if self.scaffolding_patterns.is_empty() {
+            DEFAULT_SCAFFOLDING_PATTERNS
+                .iter()
+                .map(ToString::to_string)
+                .collect()
+        } else {
+            self.scaffolding_patterns.clone()
+        }
+    }
+
+    /// Times a failing test binary is retried, binaries named in
+    /// `no-retry-tests` by their target or package name aren't retried
+    pub fn retries_for(&self, target: &str, package: Option<&str>) -> usize {
+        let exempt = self
+            .no_retry_tests
+            .iter()
+            .any(|name| name == target || Some(name.as_str()) == package);
+        if exempt {
+            0
+        } else {
+            self.retries
+        }
+    }
+
+    /// Reason this config shouldn't run based on its `required-env` and
+    /// `skip-if-env` conditions, `None` if it should run or is forced with
+    /// `--force-config`
+    pub fn skip_reason(&self) -> Option<String> {
+        if self.force_config.contains(&self.name) {
+            None
+        } else if let Some(var) = self.required_env.iter().find(|v| env::var_os(v).is_none()) {
+            Some(format!("{} is not set", var))
+        } else if let Some(var) = self.skip_if_env.iter().find(|v| env::var_os(v).is_some()) {
+            Some(format!("{} is set", var))
+        } else {
+            None
+        }
+    }
+
+    /// Adds patterns of files to leave out of the coverage, as with
+    /// `--exclude-files`
+    pub fn add_excluded_files(&mut self, patterns: &[String]) {
+        self.excluded_files_raw.extend_from_slice(patterns);
+    }
+
+    /// Whether any files are excluded with `--exclude-files`
+    pub fn has_excluded_files(&self) -> bool {
+        !self.excluded_files_raw.is_empty()
+    }
+
+    /// The `--exclude-files` patterns as they were given
+    pub fn excluded_file_patterns(&self) -> &[String] {
+        &self.excluded_files_raw
+    }
+
+    /// Whether only some of the workspace members are traced, with
+    /// `--packages` or `--exclude`
+    pub fn selects_packages(&self) -> bool {
+        !self.packages.is_empty() || !self.exclude.is_empty()
+    }
+
+    /// Whether the file is in a workspace member left out by `--packages` or
+    /// `--exclude`, such as a path dependency of a selected member. A file is
+    /// in the member with the deepest directory containing it so members
+    /// nested in another's directory are told apart.
+    pub fn in_unselected_package(&self, path: &Path) -> bool {
+        if !self.selects_packages() {
+            return false;
+        }
+        if self.package_dirs.borrow().is_none() {
+            let dirs = match *self.get_metadata() {
+                Some(ref metadata) => {
+                    let (selected, _) = selected_packages(self, metadata);
+                    metadata
+                        .packages
+                        .iter()
+                        .filter(|p| metadata.workspace_members.contains(&p.id))
+                        .filter_map(|p| {
+                            let dir = p.manifest_path.parent()?.to_path_buf();
+                            Some((dir, selected.iter().any(|s| s.id == p.id)))
+                        })
+                        .collect()
+                }
+                None => vec![],
+            };
+            self.package_dirs.replace(Some(dirs));
+        }
+        self.package_dirs
+            .borrow()
+            .iter()
+            .flatten()
+            .filter(|(dir, _)| path.starts_with(dir))
+            .max_by_key(|(dir, _)| dir.components().count())
+            .map_or(false, |(_, selected)| !selected)
+    }
+
+    /// Whether the file is left out of the results, as it matches
+    /// `--exclude-files` or is in a package that isn't selected
+    pub fn excludes_file(&self, path: &Path) -> bool {
+        self.exclude_path(path) || self.in_unselected_package(path)
+    }
+
     #[inline]
     pub fn exclude_path(&self, path: &Path) -> bool {
         if self.excluded_files.borrow().len() != self.excluded_files_raw.len() {
@@ -392,9 +1109,19 @@ impl Config {
             .any(|x| x.is_match(project.to_str().unwrap_or("")))
     }
 
+    /// Whether warnings of this kind are suppressed for the file by the
+    /// `suppress` table, the patterns match the path from the base dir
+    pub fn is_suppressed(&self, kind: WarningKind, path: &Path) -> bool {
+        self.suppress
+            .iter()
+            .filter(|(k, _)| k.parse() == Ok(kind))
+            .any(|(_, patterns)| matches_any(patterns, &self.strip_base_dir(path)))
+    }
+
     ///
     /// returns the relative path from the base_dir
-    /// uses root if set, else env::current_dir()
+    /// uses root if set, else the workspace root so paths are the same
+    /// whichever directory tarpaulin is run from
     ///
     #[inline]
     pub fn get_base_dir(&self) -> PathBuf {
@@ -402,14 +1129,20 @@ impl Config {
             if Path::new(root).is_absolute() {
                 PathBuf::from(root)
             } else {
-                let base_dir = env::current_dir().unwrap();
-                base_dir.join(root).canonicalize().unwrap()
+                let base_dir = self.invocation_dir.join(root);
+                base_dir.canonicalize().unwrap_or(base_dir)
             }
         } else {
-            env::current_dir().unwrap()
+            self.root()
         }
     }
 
+    /// Directory tarpaulin saves results and caches in, `target/tarpaulin`
+    /// in the workspace root
+    pub fn tarpaulin_dir(&self) -> PathBuf {
+        self.root().join("target").join("tarpaulin")
+    }
+
     /// returns the relative path from the base_dir
     ///
     #[inline]
@@ -417,10 +1150,57 @@ impl Config {
         path_relative_from(path, &self.get_base_dir()).unwrap_or_else(|| path.to_path_buf())
     }
 
-    #[inline]
-    pub fn is_default_output_dir(&self) -> bool {
-        self.output_directory == env::current_dir().unwrap()
+    /// The name of a source file in reports. With `--path-format legacy` this
+    /// is relative to the base dir like `strip_base_dir`
+    pub fn display_path(&self, path: &Path) -> String {
+        match self.path_format {
+            PathFormat::Relative => TraceMap::file_id(path, &self.root()),
+            PathFormat::Legacy => self.strip_base_dir(path).display().to_string(),
+        }
+    }
+
+    /// How much of the build output to show, with `--verbose` and no
+    /// `--build-output` it's all shown
+    pub fn build_output(&self) -> BuildOutput {
+        match self.build_output {
+            Some(output) => output,
+            None if self.verbose => BuildOutput::Full,
+            None => BuildOutput::Summary,
+        }
+    }
+
+    /// Directory the report files are written to, a relative `output-dir`
+    /// is from the root
+    pub fn output_dir(&self) -> PathBuf {
+        match self.output_directory {
+            Some(ref dir) if dir.is_absolute() => dir.clone(),
+            Some(ref dir) => self.root().join(dir),
+            None => self.invocation_dir.clone(),
+        }
+    }
+}
+
+/// Keys in the tables of a config file which aren't config options, such as
+/// options from a newer tarpaulin, with the name of the table they're in
+fn unknown_keys(buffer: &[u8]) -> Vec<(String, String)> {
+    let mut known = match serde_json::to_value(Config::default()) {
+        Ok(serde_json::Value::Object(fields)) => fields.keys().cloned().collect::<BTreeSet<_>>(),
+        _ => return vec![],
+    };
+    known.insert("workspace".to_string());
+    let tables = match toml::from_slice::<toml::value::Table>(buffer) {
+        Ok(tables) => tables,
+        Err(_) => return vec![],
+    };
+    let mut unknown = vec![];
+    for (name, table) in &tables {
+        if let Some(table) = table.as_table() {
+            for key in table.keys().filter(|k| !known.contains(*k)) {
+                unknown.push((name.clone(), key.clone()));
+            }
+        }
     }
+    unknown
 }
 
 /// Gets the relative path from one directory to another, if it exists.
@@ -471,7 +1251,7 @@ fn path_relative_from(path: &Path, base: &Path) -> Option<PathBuf> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use clap::App;
+    use clap::{App, Arg};
 
     #[test]
     fn exclude_paths() {
@@ -515,6 +1295,105 @@ mod tests {
         assert!(!conf[0].exclude_path(Path::new("lib.rs")));
     }
 
+    #[test]
+    fn cargo_passthrough_args() {
+        let matches = App::new("tarpaulin")
+            .args(&[
+                Arg::from_usage("--config [FILE]... 'Config file'").number_of_values(1),
+                Arg::from_usage("--ignore-config 'Ignore config files'"),
+                Arg::from_usage("--verbose -v... 'Show extra output'"),
+                Arg::from_usage("--color [WHEN] 'Coloring of the output'"),
+            ])
+            .get_matches_from_safe(vec![
+                "tarpaulin",
+                "-vv",
+                "--color",
+                "never",
+                "--config",
+                "build.jobs=2",
+                "--ignore-config",
+            ])
+            .unwrap();
+        let conf = ConfigWrapper::from(&matches).0;
+        assert_eq!(conf.len(), 1);
+        assert!(conf[0].verbose);
+        assert_eq!(conf[0].cargo_verbosity, 1);
+        assert_eq!(conf[0].color, Some(Color::Never));
+        assert_eq!(conf[0].cargo_config, vec!["build.jobs=2".to_string()]);
+        assert_eq!(get_config_file(&matches), None);
+    }
+
+    #[test]
+    fn all_targets_run_types() {
+        let app = || {
+            App::new("tarpaulin").args(&[
+                Arg::from_usage("--all-targets 'Test all targets'").conflicts_with("run-types"),
+                Arg::from_usage("--run-types [TYPE]... 'Type of the coverage run'"),
+            ])
+        };
+        let matches = app()
+            .get_matches_from_safe(vec!["tarpaulin", "--all-targets"])
+            .unwrap();
+        let conf = ConfigWrapper::from(&matches).0;
+        assert_eq!(
+            conf[0].run_types,
+            vec![RunType::Tests, RunType::Benchmarks, RunType::Examples]
+        );
+        let res = app().get_matches_from_safe(vec![
+            "tarpaulin",
+            "--all-targets",
+            "--run-types",
+            "Doctests",
+        ]);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn retries_exemptions() {
+        let matches = App::new("tarpaulin")
+            .args(&[
+                Arg::from_usage("--retries [N] 'Retries'"),
+                Arg::from_usage("--no-retry-tests [NAME]... 'Never retried'").use_delimiter(true),
+                Arg::from_usage("--ignore-config 'Ignore config files'"),
+            ])
+            .get_matches_from_safe(vec![
+                "tarpaulin",
+                "--retries",
+                "2",
+                "--no-retry-tests",
+                "integration,my_crate",
+                "--ignore-config",
+            ])
+            .unwrap();
+        let conf = ConfigWrapper::from(&matches).0.remove(0);
+        assert_eq!(conf.retries_for("flaky_test", Some("other")), 2);
+        assert_eq!(conf.retries_for("integration", Some("other")), 0);
+        assert_eq!(conf.retries_for("lib", Some("my_crate")), 0);
+        assert_eq!(Config::default().retries_for("flaky_test", None), 0);
+    }
+
+    #[test]
+    fn parse_report_by() {
+        assert_eq!("file".parse::<ReportBy>(), Ok(ReportBy::File));
+        assert_eq!("dir".parse::<ReportBy>(), Ok(ReportBy::Dir(None)));
+        assert_eq!("Dir:3".parse::<ReportBy>(), Ok(ReportBy::Dir(Some(3))));
+        assert!("dir:0".parse::<ReportBy>().is_err());
+        assert!("dir:".parse::<ReportBy>().is_err());
+        assert!("package".parse::<ReportBy>().is_err());
+    }
+
+    #[test]
+    fn parse_stdin() {
+        assert_eq!("null".parse::<Stdin>(), Ok(Stdin::Null));
+        assert_eq!("inherit".parse::<Stdin>(), Ok(Stdin::Inherit));
+        assert_eq!(
+            "file:in.txt".parse::<Stdin>(),
+            Ok(Stdin::File(PathBuf::from("in.txt")))
+        );
+        assert!("file:".parse::<Stdin>().is_err());
+        assert!("pipe".parse::<Stdin>().is_err());
+    }
+
     #[test]
     fn relative_path_test() {
         let path_a = Path::new("/this/should/form/a/rel/path/");
@@ -589,6 +1468,133 @@ mod tests {
         assert_eq!(configs[0].excluded_files_raw.len(), 1);
     }
 
+    #[test]
+    fn unselected_package_files() {
+        let workspace = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/inline_workspace");
+        let a = workspace.join("a/src/lib.rs");
+        let b = workspace.join("b/src/lib.rs");
+        let mut config = Config::default();
+        config.manifest = workspace.join("Cargo.toml");
+        assert!(!config.in_unselected_package(&a));
+
+        // b depends on a by path so a is built but isn't selected
+        let mut selected = config.clone();
+        selected.packages = vec!["b".to_string()];
+        assert!(selected.in_unselected_package(&a));
+        assert!(!selected.in_unselected_package(&b));
+        assert!(selected.excludes_file(&a));
+        // Files outside every member aren't in an unselected package
+        assert!(!selected.in_unselected_package(Path::new("/elsewhere/lib.rs")));
+
+        let mut excluded = config.clone();
+        excluded.exclude = vec!["b".to_string()];
+        assert!(!excluded.in_unselected_package(&a));
+        assert!(excluded.in_unselected_package(&b));
+    }
+
+    #[test]
+    fn output_dir_from_root() {
+        let mut config = Config::default();
+        config.manifest = PathBuf::from("/project/Cargo.toml");
+        config.invocation_dir = PathBuf::from("/project/src");
+        assert_eq!(config.output_dir(), Path::new("/project/src"));
+        config.output_directory = Some(PathBuf::from("target/reports"));
+        assert_eq!(config.output_dir(), Path::new("/project/target/reports"));
+        config.output_directory = Some(PathBuf::from("/tmp/reports"));
+        assert_eq!(config.output_dir(), Path::new("/tmp/reports"));
+    }
+
+    #[test]
+    fn outputs_merged() {
+        let toml = r#"[a]
+        out = ["Lcov", "Html"]
+        [b]
+        out = ["Html", "Xml"]
+        "#;
+
+        let mut configs = Config::parse_config_toml(toml.as_bytes()).unwrap();
+        // The sections are read in any order
+        configs.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut config = configs.remove(0);
+        config.merge(&configs[0]);
+        assert_eq!(
+            config.generate,
+            vec![OutputFormat::Lcov, OutputFormat::Html, OutputFormat::Xml]
+        );
+    }
+
+    #[test]
+    fn env_conditions() {
+        let toml = r#"[db]
+        required-env = ["TARPAULIN_TEST_REQUIRED"]
+        skip-if-env = ["TARPAULIN_TEST_SKIP"]
+        "#;
+        let mut config = Config::parse_config_toml(toml.as_bytes())
+            .unwrap()
+            .remove(0);
+        env::remove_var("TARPAULIN_TEST_SKIP");
+        env::remove_var("TARPAULIN_TEST_REQUIRED");
+        assert!(config.skip_reason().is_some());
+        env::set_var("TARPAULIN_TEST_REQUIRED", "1");
+        assert_eq!(config.skip_reason(), None);
+        env::set_var("TARPAULIN_TEST_SKIP", "1");
+        assert!(config.skip_reason().is_some());
+
+        let mut args = Config::default();
+        args.force_config = vec!["db".to_string()];
+        config.merge(&args);
+        assert_eq!(config.skip_reason(), None);
+    }
+
+    #[test]
+    fn unsatisfiable_env_conditions() {
+        let toml = r#"[never]
+        required-env = ["A", "B"]
+        skip-if-env = ["B"]
+        "#;
+        assert!(Config::parse_config_toml(toml.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn unknown_keys_ignored() {
+        let toml = r#"[a]
+        features = ["x"]
+        workspace = true
+        coverage-engine = "llvm"
+
+        [b]
+        exclude-files = ["fuzz/*"]
+        "#;
+        let configs = Config::parse_config_toml(toml.as_bytes()).unwrap();
+        assert_eq!(configs.len(), 2);
+        assert_eq!(
+            unknown_keys(toml.as_bytes()),
+            vec![("a".to_string(), "coverage-engine".to_string())]
+        );
+    }
+
+    #[test]
+    fn suppressed_warnings() {
+        let toml = r#"[gen]
+        root = "/project"
+        suppress = { parse-fallback = ["src/generated/*"], stale-source = ["build.rs"] }
+        "#;
+        let config = Config::parse_config_toml(toml.as_bytes())
+            .unwrap()
+            .remove(0);
+        let generated = Path::new("/project/src/generated/api.rs");
+        assert!(config.is_suppressed(WarningKind::ParseFallback, generated));
+        assert!(!config.is_suppressed(WarningKind::OutOfRange, generated));
+        assert!(!config.is_suppressed(WarningKind::ParseFallback, Path::new("/project/src/lib.rs")));
+        assert!(config.is_suppressed(WarningKind::StaleSource, Path::new("/project/build.rs")));
+
+        let toml = r#"[gen]
+        suppress = { uninstrumentable = ["src/*"] }
+        "#;
+        let err = Config::parse_config_toml(toml.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("parse-fallback, out-of-range"));
+    }
+
     #[test]
     fn all_toml_options() {
         let toml = r#"[all]
@@ -602,6 +1608,17 @@ mod tests {
         forward = true
         coveralls = "hello"
         report-uri = "http://hello.com"
+        codecov-upload = true
+        codecov-token = "token"
+        junit = "results.xml"
+        markdown-max-files = 20
+        report-below = 70.0
+        clover-absolute-paths = true
+        sonar-root = "../.."
+        badge = "coverage.svg"
+        badge-yellow = 60.0
+        badge-green = 90.0
+        max-annotations = 25
         no-default-features = true
         features = ["a"]
         all-features = true
@@ -610,6 +1627,8 @@ mod tests {
         exclude = ["pack_2"]
         exclude-files = ["fuzz/*"]
         timeout = "5s"
+        per-test-timeout = "2s"
+        capture-limit = 8
         release = true
         no-run = true
         locked = true
@@ -617,16 +1636,135 @@ mod tests {
         target-dir = "/tmp"
         offline = true
         Z = ["something-nightly"]
-        out = ["Html"]
+        out = ["Html", "protobuf"]
         run-types = ["Doctests"]
+        bins = ["server"]
+        default-bin-only = true
+        bin-args = { server = ["--port", "0"] }
         root = "/home/rust"
         manifest-path = "/home/rust/foo/Cargo.toml"
         ciserver = "travis-ci"
         args = ["--nocapture"]
+        affinity = "Free"
+        count-debug-asserts = false
+        fail-under = 85.5
+        diff-base = "main"
+        fail-under-new-files = 90.0
+        age-buckets = [30, 90]
+        age-analysis-jobs = 2
+        compare = "previous/tarpaulin-report.json"
+        max-coverage-drop = 0.5
+        fail-if-only-ignored = true
+        threshold-run-types = ["Tests", "Doctests"]
+        profile-hits = true
+        stdin = { file = "input.txt" }
+        strict-instrumentation = true
+        strict-reporting = true
+        output-dir = "reports"
+        compress-output = "zstd"
+        build-output = "quiet"
+        path-format = "legacy"
+        report-by = "dir:2"
+        merge-with-diff = true
+        no-cache = true
+        stats-file = "tarpaulin-stats.json"
+        dump-breakpoints = "breakpoints.json"
+        make-repro = "repro.tar.gz"
+        include-sources = true
+        repro-line-tables = true
+        html-split = true
+        measure-overhead = true
+        include-derives = true
+        weighted-coverage = true
+        exclude-test-scaffolding = true
+        scaffolding-patterns = ["make_*"]
+        ratchet = "coverage-floor.toml"
+        ratchet-margin = 1.5
+        ratchet-tolerance = 0.25
+        max-addresses-per-line = 4
+        retries = 2
+        no-retry-tests = ["integration"]
+        extended-table = true
+        show-missing = true
+        suggest = 5
+        target = "x86_64-unknown-linux-musl"
+        count-all-targets = true
+        required-env = ["DATABASE_URL"]
+        skip-if-env = ["CI_SKIP_DB"]
+        suppress = { parse-fallback = ["src/generated/*"] }
         "#;
         let mut configs = Config::parse_config_toml(toml.as_bytes()).unwrap();
         assert_eq!(configs.len(), 1);
         let config = configs.remove(0);
+        assert_eq!(config.affinity, Affinity::Free);
+        assert_eq!(config.bins, vec!["server".to_string()]);
+        assert!(config.default_bin_only);
+        assert_eq!(
+            config.bin_args["server"],
+            vec!["--port".to_string(), "0".to_string()]
+        );
+        assert!(!config.count_debug_asserts);
+        assert_eq!(config.fail_under, Some(85.5));
+        assert_eq!(config.diff_base.as_deref(), Some("main"));
+        assert_eq!(config.fail_under_new_files, Some(90.0));
+        assert_eq!(config.age_buckets, vec![30, 90]);
+        assert_eq!(config.age_analysis_jobs, 2);
+        assert_eq!(
+            config.compare,
+            Some(PathBuf::from("previous/tarpaulin-report.json"))
+        );
+        assert_eq!(config.max_coverage_drop, Some(0.5));
+        assert!(config.fail_if_only_ignored);
+        assert!(config.profile_hits);
+        assert_eq!(config.stdin, Stdin::File(PathBuf::from("input.txt")));
+        assert!(config.strict_instrumentation);
+        assert!(config.strict_reporting);
+        assert_eq!(config.output_directory, Some(PathBuf::from("reports")));
+        assert_eq!(config.compress_output, Some(Compression::Zstd));
+        assert_eq!(config.build_output, Some(BuildOutput::Quiet));
+        assert_eq!(config.build_output(), BuildOutput::Quiet);
+        assert_eq!(config.path_format, PathFormat::Legacy);
+        assert_eq!(config.report_by, ReportBy::Dir(Some(2)));
+        assert!(config.merge_with_diff);
+        assert!(config.no_cache);
+        assert_eq!(
+            config.stats_file,
+            Some(PathBuf::from("tarpaulin-stats.json"))
+        );
+        assert_eq!(
+            config.dump_breakpoints,
+            Some(PathBuf::from("breakpoints.json"))
+        );
+        assert_eq!(config.make_repro, Some(PathBuf::from("repro.tar.gz")));
+        assert!(config.include_sources);
+        assert!(config.repro_line_tables);
+        assert!(config.html_split);
+        assert!(config.measure_overhead);
+        assert!(config.include_derives);
+        assert!(config.weighted_coverage);
+        assert!(config.exclude_test_scaffolding);
+        assert_eq!(config.scaffolding_patterns(), vec!["make_*".to_string()]);
+        assert_eq!(config.ratchet, Some(PathBuf::from("coverage-floor.toml")));
+        assert_eq!(config.ratchet_margin, 1.5);
+        assert_eq!(config.ratchet_tolerance, 0.25);
+        assert_eq!(config.max_addresses_per_line, 4);
+        assert_eq!(config.retries, 2);
+        assert_eq!(config.no_retry_tests, vec!["integration".to_string()]);
+        assert!(config.extended_table);
+        assert!(config.show_missing);
+        assert_eq!(config.suggest, Some(5));
+        assert_eq!(config.target, Some("x86_64-unknown-linux-musl".to_string()));
+        assert!(config.count_all_targets);
+        assert_eq!(config.required_env, vec!["DATABASE_URL".to_string()]);
+        assert_eq!(config.skip_if_env, vec!["CI_SKIP_DB".to_string()]);
+        assert_eq!(
+            config.suppress["parse-fallback"],
+            vec!["src/generated/*".to_string()]
+        );
+        assert_eq!(
+            config.threshold_run_types,
+            vec![RunType::Tests, RunType::Doctests]
+        );
         assert!(config.debug);
         assert!(config.verbose);
         assert!(config.ignore_panics);
@@ -637,6 +1775,17 @@ mod tests {
         assert!(config.forward_signals);
         assert_eq!(config.coveralls, Some("hello".to_string()));
         assert_eq!(config.report_uri, Some("http://hello.com".to_string()));
+        assert!(config.codecov_upload);
+        assert_eq!(config.codecov_token, Some("token".to_string()));
+        assert_eq!(config.junit, Some(PathBuf::from("results.xml")));
+        assert_eq!(config.markdown_max_files, Some(20));
+        assert_eq!(config.report_below, Some(70.0));
+        assert!(config.clover_absolute_paths);
+        assert_eq!(config.sonar_root, Some(PathBuf::from("../..")));
+        assert_eq!(config.badge, Some(PathBuf::from("coverage.svg")));
+        assert_eq!(config.badge_yellow, 60.0);
+        assert_eq!(config.badge_green, 90.0);
+        assert_eq!(config.max_annotations, 25);
         assert!(config.no_default_features);
         assert!(config.all_features);
         assert!(config.all);
@@ -646,6 +1795,8 @@ mod tests {
         assert!(config.frozen);
         assert!(config.offline);
         assert_eq!(config.test_timeout, Duration::from_secs(5));
+        assert_eq!(config.per_test_timeout, Some(Duration::from_secs(2)));
+        assert_eq!(config.capture_limit, Some(8));
         assert_eq!(config.unstable_features.len(), 1);
         assert_eq!(config.unstable_features[0], "something-nightly");
         assert_eq!(config.varargs.len(), 1);
@@ -658,8 +1809,12 @@ mod tests {
         assert_eq!(config.packages[0], "pack_1");
         assert_eq!(config.exclude.len(), 1);
         assert_eq!(config.exclude[0], "pack_2");
-        assert_eq!(config.generate.len(), 1);
-        assert_eq!(config.generate[0], OutputFile::Html);
+        assert_eq!(config.generate.len(), 2);
+        assert_eq!(config.generate[0], OutputFormat::Html);
+        assert_eq!(
+            config.generate[1],
+            OutputFormat::Custom("protobuf".to_string())
+        );
         assert_eq!(config.run_types.len(), 1);
         assert_eq!(config.run_types[0], RunType::Doctests);
         assert_eq!(config.ci_tool, Some(CiService::Travis));
@@ -7,7 +7,7 @@ use serde::de::{self, Deserializer};
 use std::env;
 use std::fmt;
 use std::fs::create_dir_all;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::time::Duration;
 
@@ -15,6 +15,29 @@ pub(super) fn get_list(args: &ArgMatches, key: &str) -> Vec<String> {
     args.values_of_lossy(key).unwrap_or_else(Vec::new)
 }
 
+/// Whether a `--config` value is a cargo `KEY=VALUE` override rather than the
+/// path of a tarpaulin config file
+fn is_cargo_config(value: &str) -> bool {
+    value.contains('=') && !Path::new(value).exists()
+}
+
+pub(super) fn get_config_file(args: &ArgMatches) -> Option<PathBuf> {
+    args.values_of("config")?
+        .find(|v| !is_cargo_config(v))
+        .map(PathBuf::from)
+}
+
+pub(super) fn get_cargo_config(args: &ArgMatches) -> Vec<String> {
+    get_list(args, "config")
+        .into_iter()
+        .filter(|v| is_cargo_config(v))
+        .collect()
+}
+
+pub(super) fn get_color(args: &ArgMatches) -> Option<Color> {
+    value_t!(args.value_of("color"), Color).ok()
+}
+
 pub(super) fn get_line_cov(args: &ArgMatches) -> bool {
     let cover_lines = args.is_present("line");
     let cover_branches = args.is_present("branch");
@@ -42,22 +65,31 @@ pub(super) fn get_manifest(args: &ArgMatches) -> PathBuf {
         return path;
     }
 
-    let mut manifest = env::current_dir().unwrap();
+    let mut dir = env::current_dir().unwrap();
 
     if let Some(path) = args.value_of("root") {
-        manifest.push(path);
+        dir.push(path);
     }
 
-    manifest.push("Cargo.toml");
+    let manifest = find_manifest(&dir).unwrap_or_else(|| dir.join("Cargo.toml"));
     manifest.canonicalize().unwrap_or(manifest)
 }
 
 pub(super) fn default_manifest() -> PathBuf {
-    let mut manifest = env::current_dir().unwrap();
-    manifest.push("Cargo.toml");
+    let dir = env::current_dir().unwrap();
+    let manifest = find_manifest(&dir).unwrap_or_else(|| dir.join("Cargo.toml"));
     manifest.canonicalize().unwrap_or(manifest)
 }
 
+/// Finds the manifest of the package containing `dir` by looking in it and
+/// then its parents like cargo does. Run from a workspace member this is the
+/// member's manifest so it's the package tested by default.
+pub fn find_manifest(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|d| d.join("Cargo.toml"))
+        .find(|manifest| manifest.is_file())
+}
+
 pub(super) fn get_target_dir(args: &ArgMatches) -> Option<PathBuf> {
     if let Some(path) = args.value_of("target-dir") {
         let path = PathBuf::from(path);
@@ -79,6 +111,10 @@ pub(super) fn get_target_dir(args: &ArgMatches) -> Option<PathBuf> {
     }
 }
 
+pub(super) fn get_path(args: &ArgMatches, key: &str) -> Option<PathBuf> {
+    args.value_of(key).map(PathBuf::from)
+}
+
 pub(super) fn get_root(args: &ArgMatches) -> Option<String> {
     args.value_of("root").map(ToString::to_string)
 }
@@ -95,19 +131,110 @@ pub(super) fn get_report_uri(args: &ArgMatches) -> Option<String> {
     args.value_of("report-uri").map(ToString::to_string)
 }
 
-pub(super) fn get_outputs(args: &ArgMatches) -> Vec<OutputFile> {
-    values_t!(args.values_of("out"), OutputFile).unwrap_or(vec![])
+pub(super) fn get_outputs(args: &ArgMatches) -> Vec<OutputFormat> {
+    values_t!(args.values_of("out"), OutputFormat).unwrap_or(vec![])
+}
+
+pub(super) fn get_affinity(args: &ArgMatches) -> Affinity {
+    value_t!(args.value_of("affinity"), Affinity).unwrap_or_default()
+}
+
+pub(super) fn get_stdin(args: &ArgMatches) -> Stdin {
+    value_t!(args.value_of("stdin"), Stdin).unwrap_or_default()
 }
 
-pub(super) fn get_output_directory(args: &ArgMatches) -> PathBuf {
-    if let Some(path) = args.value_of("output-dir") {
-        return PathBuf::from(path);
+pub(super) fn get_build_output(args: &ArgMatches) -> Option<BuildOutput> {
+    value_t!(args.value_of("build-output"), BuildOutput).ok()
+}
+
+pub(super) fn get_path_format(args: &ArgMatches) -> PathFormat {
+    value_t!(args.value_of("path-format"), PathFormat).unwrap_or_default()
+}
+
+pub(super) fn get_report_by(args: &ArgMatches) -> ReportBy {
+    value_t!(args.value_of("report-by"), ReportBy).unwrap_or_default()
+}
+
+pub(super) fn get_compression(args: &ArgMatches) -> Option<Compression> {
+    if args.is_present("compress-output") {
+        Some(value_t!(args.value_of("compress-output"), Compression).unwrap_or(Compression::Gzip))
+    } else {
+        None
     }
-    env::current_dir().unwrap()
+}
+
+pub(super) fn get_fail_under(args: &ArgMatches) -> Option<f64> {
+    value_t!(args.value_of("fail-under"), f64).ok()
+}
+
+pub(super) fn get_fail_under_new_files(args: &ArgMatches) -> Option<f64> {
+    value_t!(args.value_of("fail-under-new-files"), f64).ok()
+}
+
+pub(super) fn get_report_below(args: &ArgMatches) -> Option<f64> {
+    value_t!(args.value_of("report-below"), f64).ok()
+}
+
+pub(super) fn get_age_buckets(args: &ArgMatches) -> Vec<u64> {
+    values_t!(args.values_of("age-buckets"), u64).unwrap_or_default()
+}
+
+pub(super) fn get_age_analysis_jobs(args: &ArgMatches) -> usize {
+    value_t!(args.value_of("age-analysis-jobs"), usize).unwrap_or(super::DEFAULT_AGE_ANALYSIS_JOBS)
+}
+
+pub(super) fn get_max_coverage_drop(args: &ArgMatches) -> Option<f64> {
+    value_t!(args.value_of("max-coverage-drop"), f64).ok()
+}
+
+pub(super) fn get_ratchet_margin(args: &ArgMatches) -> f64 {
+    value_t!(args.value_of("ratchet-margin"), f64).unwrap_or(super::DEFAULT_RATCHET_MARGIN)
+}
+
+pub(super) fn get_ratchet_tolerance(args: &ArgMatches) -> f64 {
+    value_t!(args.value_of("ratchet-tolerance"), f64).unwrap_or(0.0)
+}
+
+pub(super) fn get_max_addresses_per_line(args: &ArgMatches) -> usize {
+    value_t!(args.value_of("max-addresses-per-line"), usize)
+        .unwrap_or(super::DEFAULT_MAX_ADDRESSES_PER_LINE)
+}
+
+pub(super) fn get_retries(args: &ArgMatches) -> usize {
+    value_t!(args.value_of("retries"), usize).unwrap_or(0)
+}
+
+pub(super) fn get_capture_limit(args: &ArgMatches) -> Option<u64> {
+    value_t!(args.value_of("capture-limit"), u64).ok()
+}
+
+pub(super) fn get_markdown_max_files(args: &ArgMatches) -> Option<usize> {
+    value_t!(args.value_of("markdown-max-files"), usize).ok()
+}
+
+pub(super) fn get_max_annotations(args: &ArgMatches) -> usize {
+    value_t!(args.value_of("max-annotations"), usize).unwrap_or(super::DEFAULT_MAX_ANNOTATIONS)
+}
+
+pub(super) fn get_suggest(args: &ArgMatches) -> Option<usize> {
+    if args.is_present("suggest") {
+        Some(value_t!(args.value_of("suggest"), usize).unwrap_or(super::DEFAULT_SUGGESTIONS))
+    } else {
+        None
+    }
+}
+
+pub(super) fn get_threshold_run_types(args: &ArgMatches) -> Vec<RunType> {
+    values_t!(args.values_of("threshold-run-types"), RunType).unwrap_or_default()
 }
 
 pub(super) fn get_run_types(args: &ArgMatches) -> Vec<RunType> {
-    values_t!(args.values_of("run-types"), RunType).unwrap_or(vec![RunType::Tests])
+    if args.is_present("all-targets") {
+        // Same targets as `cargo test --all-targets`, doc tests aren't included
+        vec![RunType::Tests, RunType::Benchmarks, RunType::Examples]
+    } else {
+        values_t!(args.values_of("run-types"), RunType).unwrap_or(vec![RunType::Tests])
+    }
 }
 
 pub(super) fn get_excluded(args: &ArgMatches) -> Vec<Regex> {
@@ -138,6 +265,12 @@ pub(super) fn get_timeout(args: &ArgMatches) -> Duration {
     }
 }
 
+pub(super) fn get_per_test_timeout(args: &ArgMatches) -> Option<Duration> {
+    value_t!(args.value_of("per-test-timeout"), u64)
+        .ok()
+        .map(Duration::from_secs)
+}
+
 pub fn deserialize_ci_server<'de, D>(d: D) -> Result<Option<CiService>, D::Error>
 where
     D: Deserializer<'de>,
@@ -1,6 +1,10 @@
 use clap::arg_enum;
 use coveralls_api::CiService;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use void::Void;
 
@@ -11,25 +15,415 @@ arg_enum! {
         Doctests,
         Benchmarks,
         Examples,
+        Bins,
+    }
+}
+
+/// Format of a report to generate. Each built in format has its own variant
+/// which the reports are matched on, other names are kept as `Custom` and
+/// looked up in the registry of custom reports when reporting.
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Deserialize, Serialize)]
+#[serde(from = "String", into = "String")]
+pub enum OutputFormat {
+    Json,
+    Stdout,
+    Xml,
+    Html,
+    Lcov,
+    Codecov,
+    Github,
+    Junit,
+    Markdown,
+    Badge,
+    Clover,
+    Sonar,
+    Teamcity,
+    Custom(String),
+}
+
+impl OutputFormat {
+    /// Names of the built in formats
+    pub fn variants() -> [&'static str; 13] {
+        [
+            "Json", "Stdout", "Xml", "Html", "Lcov", "Codecov", "Github", "Junit", "Markdown",
+            "Badge", "Clover", "Sonar", "Teamcity",
+        ]
+    }
+
+    /// Name of the format, custom reports are registered under this name
+    pub fn name(&self) -> &str {
+        match self {
+            OutputFormat::Json => "Json",
+            OutputFormat::Stdout => "Stdout",
+            OutputFormat::Xml => "Xml",
+            OutputFormat::Html => "Html",
+            OutputFormat::Lcov => "Lcov",
+            OutputFormat::Codecov => "Codecov",
+            OutputFormat::Github => "Github",
+            OutputFormat::Junit => "Junit",
+            OutputFormat::Markdown => "Markdown",
+            OutputFormat::Badge => "Badge",
+            OutputFormat::Clover => "Clover",
+            OutputFormat::Sonar => "Sonar",
+            OutputFormat::Teamcity => "Teamcity",
+            OutputFormat::Custom(name) => name,
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    /// Unknown names are custom formats, so the error type is uninhabited.
+    type Err = Void;
+
+    fn from_str(x: &str) -> Result<OutputFormat, Self::Err> {
+        let res = match x.to_ascii_lowercase().as_str() {
+            "json" => OutputFormat::Json,
+            "stdout" => OutputFormat::Stdout,
+            "xml" | "cobertura" => OutputFormat::Xml,
+            "html" => OutputFormat::Html,
+            "lcov" => OutputFormat::Lcov,
+            "codecov" => OutputFormat::Codecov,
+            "github" => OutputFormat::Github,
+            "junit" => OutputFormat::Junit,
+            "markdown" => OutputFormat::Markdown,
+            "badge" => OutputFormat::Badge,
+            "clover" => OutputFormat::Clover,
+            "sonar" => OutputFormat::Sonar,
+            "teamcity" => OutputFormat::Teamcity,
+            _ => OutputFormat::Custom(x.to_string()),
+        };
+        Ok(res)
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl From<String> for OutputFormat {
+    fn from(x: String) -> Self {
+        match x.parse() {
+            Ok(res) => res,
+            Err(e) => void::unreachable(e),
+        }
+    }
+}
+
+impl From<OutputFormat> for String {
+    fn from(x: OutputFormat) -> Self {
+        x.name().to_string()
     }
 }
 
 arg_enum! {
-    #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Deserialize, Serialize)]
-    pub enum OutputFile {
-        Json,
-        Toml,
-        Stdout,
-        Xml,
-        Html,
-        Lcov,
+    /// Whether tarpaulin pins the tracer and test to a processor
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+    pub enum Affinity {
+        Auto,
+        Pinned,
+        Free,
+    }
+}
+
+impl Default for Affinity {
+    #[inline]
+    fn default() -> Self {
+        Affinity::Auto
+    }
+}
+
+/// Compression applied to report files with `--compress-output`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn variants() -> [&'static str; 2] {
+        ["gzip", "zstd"]
     }
+
+    /// Extension added to the names of compressed files
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(x: &str) -> Result<Compression, Self::Err> {
+        match x.to_ascii_lowercase().as_str() {
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(String::from("compression must be gzip or zstd")),
+        }
+    }
+}
+
+/// When output is coloured, passed through to cargo with `--color`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+impl Color {
+    pub fn variants() -> [&'static str; 3] {
+        ["auto", "always", "never"]
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Color::Auto => "auto",
+            Color::Always => "always",
+            Color::Never => "never",
+        }
+    }
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(x: &str) -> Result<Color, Self::Err> {
+        match x.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Color::Auto),
+            "always" => Ok(Color::Always),
+            "never" => Ok(Color::Never),
+            _ => Err(String::from("color must be auto, always or never")),
+        }
+    }
+}
+
+/// How much of cargo's output is shown while building the tests, set with
+/// `--build-output`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildOutput {
+    /// Nothing unless the build fails
+    Quiet,
+    /// A progress line with the number of crates built
+    Summary,
+    /// Cargo's own output and every compiler diagnostic
+    Full,
+}
+
+impl BuildOutput {
+    pub fn variants() -> [&'static str; 3] {
+        ["quiet", "summary", "full"]
+    }
+}
+
+impl Default for BuildOutput {
+    #[inline]
+    fn default() -> Self {
+        BuildOutput::Summary
+    }
+}
+
+impl FromStr for BuildOutput {
+    type Err = String;
+
+    fn from_str(x: &str) -> Result<BuildOutput, Self::Err> {
+        match x.to_ascii_lowercase().as_str() {
+            "quiet" => Ok(BuildOutput::Quiet),
+            "summary" => Ok(BuildOutput::Summary),
+            "full" => Ok(BuildOutput::Full),
+            _ => Err(String::from("build output must be quiet, summary or full")),
+        }
+    }
+}
+
+/// How source files are named in reports, set with `--path-format`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PathFormat {
+    /// Relative to the workspace root with forward slashes in every report
+    Relative,
+    /// The paths each report used before, absolute in some and relative in others
+    Legacy,
+}
+
+impl PathFormat {
+    pub fn variants() -> [&'static str; 2] {
+        ["relative", "legacy"]
+    }
+}
+
+impl Default for PathFormat {
+    #[inline]
+    fn default() -> Self {
+        PathFormat::Relative
+    }
+}
+
+impl FromStr for PathFormat {
+    type Err = String;
+
+    fn from_str(x: &str) -> Result<PathFormat, Self::Err> {
+        match x.to_ascii_lowercase().as_str() {
+            "relative" => Ok(PathFormat::Relative),
+            "legacy" => Ok(PathFormat::Legacy),
+            _ => Err(String::from("path format must be relative or legacy")),
+        }
+    }
+}
+
+/// Rows of the console summary, set with `--report-by`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ReportBy {
+    /// A row per source file
+    File,
+    /// A row per directory, directories below the given depth are rolled up
+    /// into their ancestor at that depth
+    Dir(Option<usize>),
+}
+
+impl Default for ReportBy {
+    #[inline]
+    fn default() -> Self {
+        ReportBy::File
+    }
+}
+
+impl FromStr for ReportBy {
+    type Err = String;
+
+    fn from_str(x: &str) -> Result<ReportBy, Self::Err> {
+        let err = || String::from("report by must be file, dir or dir:DEPTH");
+        match x.to_ascii_lowercase().as_str() {
+            "file" => Ok(ReportBy::File),
+            "dir" => Ok(ReportBy::Dir(None)),
+            x if x.starts_with("dir:") => match x[4..].parse::<usize>() {
+                Ok(depth) if depth > 0 => Ok(ReportBy::Dir(Some(depth))),
+                _ => Err(err()),
+            },
+            _ => Err(err()),
+        }
+    }
+}
+
+impl TryFrom<String> for ReportBy {
+    type Error = String;
+
+    fn try_from(x: String) -> Result<Self, Self::Error> {
+        x.parse()
+    }
+}
+
+impl From<ReportBy> for String {
+    fn from(x: ReportBy) -> Self {
+        match x {
+            ReportBy::File => "file".to_string(),
+            ReportBy::Dir(None) => "dir".to_string(),
+            ReportBy::Dir(Some(depth)) => format!("dir:{}", depth),
+        }
+    }
+}
+
+/// Where the stdin of the test executables is read from
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stdin {
+    /// Read from `/dev/null` so reads see EOF straight away
+    Null,
+    /// Use the stdin of tarpaulin
+    Inherit,
+    /// Read from the given file
+    File(PathBuf),
+}
+
+impl Default for Stdin {
+    #[inline]
+    fn default() -> Self {
+        Stdin::Null
+    }
+}
+
+impl FromStr for Stdin {
+    type Err = String;
+
+    fn from_str(x: &str) -> Result<Stdin, Self::Err> {
+        match x {
+            "null" => Ok(Stdin::Null),
+            "inherit" => Ok(Stdin::Inherit),
+            _ if x.starts_with("file:") && x.len() > 5 => Ok(Stdin::File(PathBuf::from(&x[5..]))),
+            _ => Err(String::from("stdin must be null, inherit or file:PATH")),
+        }
+    }
+}
+
+/// Kinds of warning the `suppress` table of the config file can demote to
+/// debug logs for some files
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Ord, PartialOrd)]
+pub enum WarningKind {
+    /// A file couldn't be parsed so had a textual analysis instead
+    ParseFallback,
+    /// A trace was on a line past the end of its file
+    OutOfRange,
+    /// A trace was on a line the source analysis ignores
+    IgnoredLine,
+    /// A file had more covered than coverable so was recounted
+    Recounted,
+    /// A source file changed after the test was built
+    StaleSource,
+}
+
+impl WarningKind {
+    pub fn variants() -> [&'static str; 5] {
+        [
+            "parse-fallback",
+            "out-of-range",
+            "ignored-line",
+            "recounted",
+            "stale-source",
+        ]
+    }
+}
+
+impl FromStr for WarningKind {
+    type Err = String;
+
+    fn from_str(x: &str) -> Result<WarningKind, Self::Err> {
+        match x {
+            "parse-fallback" => Ok(WarningKind::ParseFallback),
+            "out-of-range" => Ok(WarningKind::OutOfRange),
+            "ignored-line" => Ok(WarningKind::IgnoredLine),
+            "recounted" => Ok(WarningKind::Recounted),
+            "stale-source" => Ok(WarningKind::StaleSource),
+            _ => Err(format!(
+                "unknown warning kind {}, valid kinds are {}",
+                x,
+                WarningKind::variants().join(", ")
+            )),
+        }
+    }
+}
+
+/// Whether a path relative to the root matches one of the patterns, `*`
+/// matches anything
+pub(crate) fn matches_any(patterns: &[String], path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    patterns.iter().any(|p| {
+        let re = format!("^{}$", regex::escape(p).replace(r"\*", ".*"));
+        Regex::new(&re).map_or(false, |re| re.is_match(&path))
+    })
 }
 
-impl Default for OutputFile {
+impl Default for OutputFormat {
     #[inline]
     fn default() -> Self {
-        OutputFile::Stdout
+        OutputFormat::Stdout
     }
 }
 
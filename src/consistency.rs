@@ -0,0 +1,215 @@
+//! Checks the final results for impossible values which indicate a mismatch
+//! between the debug information and the source analysis, for example after
+//! heavy macro use. Inconsistent traces are corrected and each correction is
+//! reported so the totals aren't silently skewed.
+use crate::config::WarningKind;
+use crate::source_analysis::{LineAnalysis, SourceAnalysisQuery};
+use crate::traces::TraceMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A correction made to the results
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Correction {
+    /// Trace on a line past the end of the file or line 0 was dropped
+    OutOfRange {
+        path: PathBuf,
+        line: u64,
+        lines_in_file: usize,
+    },
+    /// Trace on a line the source analysis ignores was dropped
+    IgnoredLine { path: PathBuf, line: u64 },
+    /// More was covered than coverable so duplicate traces were merged
+    Recounted {
+        path: PathBuf,
+        covered: usize,
+        coverable: usize,
+    },
+}
+
+impl Correction {
+    /// Kind of warning to suppress it with in the config file
+    pub fn kind(&self) -> WarningKind {
+        match self {
+            Correction::OutOfRange { .. } => WarningKind::OutOfRange,
+            Correction::IgnoredLine { .. } => WarningKind::IgnoredLine,
+            Correction::Recounted { .. } => WarningKind::Recounted,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            Correction::OutOfRange { path, .. }
+            | Correction::IgnoredLine { path, .. }
+            | Correction::Recounted { path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for Correction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Correction::OutOfRange {
+                path,
+                line,
+                lines_in_file,
+            } => write!(
+                f,
+                "{}:{} is outside the file which has {} lines, dropping trace",
+                path.display(),
+                line,
+                lines_in_file
+            ),
+            Correction::IgnoredLine { path, line } => write!(
+                f,
+                "{}:{} is ignored by source analysis but was traced, dropping trace",
+                path.display(),
+                line
+            ),
+            Correction::Recounted {
+                path,
+                covered,
+                coverable,
+            } => write!(
+                f,
+                "{} had {} covered of {} coverable, recounting",
+                path.display(),
+                covered,
+                coverable
+            ),
+        }
+    }
+}
+
+/// Gets the number of lines in each file of the results, files which can't be
+/// read are left out and won't have their line numbers checked.
+pub fn line_counts(traces: &TraceMap) -> HashMap<PathBuf, usize> {
+    traces
+        .files()
+        .into_iter()
+        .filter_map(|path| {
+            fs::read_to_string(path)
+                .ok()
+                .map(|x| (path.clone(), x.lines().count()))
+        })
+        .collect()
+}
+
+/// Checks the traces against the source analysis and the number of lines in
+/// each file, correcting any inconsistencies and returning what was changed.
+pub fn check(
+    traces: &mut TraceMap,
+    analysis: &HashMap<PathBuf, LineAnalysis>,
+    line_counts: &HashMap<PathBuf, usize>,
+) -> Vec<Correction> {
+    let mut result = vec![];
+    traces.retain(|path: &Path, trace| {
+        if let Some(&lines_in_file) = line_counts.get(path) {
+            if trace.line == 0 || trace.line as usize > lines_in_file {
+                result.push(Correction::OutOfRange {
+                    path: path.to_path_buf(),
+                    line: trace.line,
+                    lines_in_file,
+                });
+                return false;
+            }
+        }
+        if analysis.should_ignore(path, &(trace.line as usize)) {
+            result.push(Correction::IgnoredLine {
+                path: path.to_path_buf(),
+                line: trace.line,
+            });
+            return false;
+        }
+        true
+    });
+    let overcounted = traces
+        .files()
+        .into_iter()
+        .filter(|path| traces.covered_in_path(path) > traces.coverable_in_path(path))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !overcounted.is_empty() {
+        for path in overcounted {
+            result.push(Correction::Recounted {
+                covered: traces.covered_in_path(&path),
+                coverable: traces.coverable_in_path(&path),
+                path,
+            });
+        }
+        traces.dedup();
+    }
+    result.sort();
+    result.dedup();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source_analysis::Lines;
+    use crate::traces::Trace;
+
+    #[test]
+    fn out_of_range_dropped() {
+        let file = Path::new("lib.rs");
+        let mut traces = TraceMap::new();
+        for (line, hits) in &[(0, 1), (2, 1), (300, 0), (5000, 3)] {
+            traces.add_trace(file, Trace::test_line(*line, *hits));
+        }
+        let mut counts = HashMap::new();
+        counts.insert(file.to_path_buf(), 300);
+        let res = check(&mut traces, &HashMap::new(), &counts);
+        assert_eq!(res.len(), 2);
+        assert_eq!(
+            res[1],
+            Correction::OutOfRange {
+                path: file.to_path_buf(),
+                line: 5000,
+                lines_in_file: 300
+            }
+        );
+        let lines = traces
+            .get_child_traces(file)
+            .iter()
+            .map(|t| t.line)
+            .collect::<Vec<_>>();
+        assert_eq!(lines, vec![2, 300]);
+        assert_eq!(traces.total_covered(), 1);
+    }
+
+    #[test]
+    fn ignored_lines_dropped() {
+        let file = Path::new("lib.rs");
+        let mut traces = TraceMap::new();
+        traces.add_trace(file, Trace::test_line(1, 1));
+        traces.add_trace(file, Trace::test_line(2, 1));
+        let mut lines = LineAnalysis::default();
+        lines.ignore.insert(Lines::Line(2));
+        let mut analysis = HashMap::new();
+        analysis.insert(file.to_path_buf(), lines);
+        let res = check(&mut traces, &analysis, &HashMap::new());
+        assert_eq!(
+            res,
+            vec![Correction::IgnoredLine {
+                path: file.to_path_buf(),
+                line: 2
+            }]
+        );
+        assert_eq!(traces.total_coverable(), 1);
+    }
+
+    #[test]
+    fn consistent_unchanged() {
+        let file = Path::new("lib.rs");
+        let mut traces = TraceMap::new();
+        traces.add_trace(file, Trace::test_line(1, 1));
+        traces.add_trace(file, Trace::test_line(2, 0));
+        let mut counts = HashMap::new();
+        counts.insert(file.to_path_buf(), 2);
+        assert!(check(&mut traces, &HashMap::new(), &counts).is_empty());
+        assert_eq!(traces.total_coverable(), 2);
+    }
+}
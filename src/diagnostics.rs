@@ -0,0 +1,433 @@
+//! Repro bundles for bug reports, `--make-repro FILE`. Tracer bugs usually
+//! depend on the binary and the machine so they're rarely reproducible from a
+//! description alone. Once the run ends, however it ends, the effective config,
+//! the run's diagnostics, the planned breakpoints of the binary being traced,
+//! the state machine's log and optionally the binary's line tables are put in
+//! a gzipped tarball. Source code and environment variables are left out
+//! unless `--include-sources` adds the sources, tokens are redacted and the
+//! project and home directories are replaced in paths. Every file is capped in
+//! size and the bundle has a manifest of what's in it, which is shown before
+//! asking whether to write it so it can be checked before it's shared.
+use crate::breakpoint_dump::{self, PlannedBreakpoint};
+use crate::config::Config;
+use crate::errors::{write_error, RunError};
+use crate::stats::{self, RunStats};
+use crate::test_loader;
+use flate2::write::GzEncoder;
+use lazy_static::lazy_static;
+use log::{info, warn, Record};
+use nix::sys::utsname::uname;
+use serde::Serialize;
+use std::collections::{BTreeSet, VecDeque};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Most lines of the state machine's log kept, the latest are kept as a
+/// failure is usually at the end
+const MAX_LOG_LINES: usize = 50_000;
+/// Most bytes of each file in the bundle, larger files are truncated
+const MAX_FILE_BYTES: usize = 16 * 1024 * 1024;
+/// Most bytes of all the files in the bundle, files past it are truncated or
+/// left out
+const MAX_BUNDLE_BYTES: usize = 64 * 1024 * 1024;
+/// Module path of the state machine's log records
+const STATE_MACHINE_TARGET: &str = "cargo_tarpaulin::statemachine";
+/// Config keys whose values are secrets
+const REDACTED_KEYS: [&str; 2] = ["coveralls", "codecov-token"];
+
+lazy_static! {
+    static ref LOG: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+}
+
+/// Keeps a log record if it's from the state machine, the logger passes every
+/// record here when a repro bundle is asked for
+pub fn record_log(record: &Record) {
+    if !record.target().starts_with(STATE_MACHINE_TARGET) {
+        return;
+    }
+    // Like the statistics the log is best effort so a poisoned lock is used
+    let mut log = LOG.lock().unwrap_or_else(|e| e.into_inner());
+    if log.len() == MAX_LOG_LINES {
+        log.pop_front();
+    }
+    log.push_back(format!(
+        "[{} {}] {}",
+        record.level(),
+        record.target(),
+        record.args()
+    ));
+}
+
+/// A file in the bundle as listed in the manifest
+#[derive(Clone, Debug, Serialize)]
+pub struct ManifestEntry {
+    pub name: String,
+    pub description: String,
+    /// Size in the bundle, after any truncation
+    pub bytes: usize,
+    pub truncated: bool,
+}
+
+/// What a bundle contains, written to it as `manifest.json`
+#[derive(Clone, Debug, Serialize)]
+pub struct Manifest {
+    pub tarpaulin_version: String,
+    pub files: Vec<ManifestEntry>,
+    /// What was deliberately left out
+    pub excluded: Vec<String>,
+}
+
+/// Diagnostics of the run, written to the bundle as `diagnostics.json`
+#[derive(Serialize)]
+struct Diagnostics {
+    os: String,
+    kernel: String,
+    machine: String,
+    /// The error the run ended with if any
+    error: Option<String>,
+    stats: RunStats,
+}
+
+/// Everything collected for a repro bundle, the config and run details are
+/// passed in so the bundle is only made from what the run recorded
+pub struct ReproInputs<'a> {
+    pub configs: &'a [Config],
+    pub config: &'a Config,
+    pub error: Option<String>,
+    pub stats: RunStats,
+    /// Binary being traced when the run ended and its planned breakpoints
+    pub binary: Option<(PathBuf, Vec<PlannedBreakpoint>)>,
+    pub log: Vec<String>,
+}
+
+/// The files of a repro bundle and their manifest
+pub struct Bundle {
+    manifest: Manifest,
+    files: Vec<Vec<u8>>,
+    total: usize,
+}
+
+impl Bundle {
+    fn new() -> Self {
+        Bundle {
+            manifest: Manifest {
+                tarpaulin_version: env!("CARGO_PKG_VERSION").to_string(),
+                files: vec![],
+                excluded: vec![],
+            },
+            files: vec![],
+            total: 0,
+        }
+    }
+
+    /// Adds a file truncated to the size caps, files once the bundle is full
+    /// are only listed in the excluded files
+    fn add(&mut self, name: &str, description: &str, mut contents: Vec<u8>) {
+        let limit = MAX_FILE_BYTES.min(MAX_BUNDLE_BYTES - self.total);
+        if limit == 0 {
+            self.exclude(&format!("{}, the bundle is full", name));
+            return;
+        }
+        let truncated = contents.len() > limit;
+        contents.truncate(limit);
+        self.total += contents.len();
+        self.manifest.files.push(ManifestEntry {
+            name: name.to_string(),
+            description: description.to_string(),
+            bytes: contents.len(),
+            truncated,
+        });
+        self.files.push(contents);
+    }
+
+    fn exclude(&mut self, what: &str) {
+        self.manifest.excluded.push(what.to_string());
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Writes the bundle as a gzipped tarball with the manifest first
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let manifest = serde_json::to_vec_pretty(&self.manifest)?;
+        let file = File::create(path)?;
+        let mut tar = tar::Builder::new(GzEncoder::new(file, flate2::Compression::default()));
+        let entries = Some(("manifest.json", &manifest)).into_iter().chain(
+            self.manifest
+                .files
+                .iter()
+                .map(|f| f.name.as_str())
+                .zip(&self.files),
+        );
+        for (name, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            tar.append_data(&mut header, name, contents.as_slice())?;
+        }
+        tar.into_inner()?.finish()?.flush()
+    }
+}
+
+/// Replaces the project and home directories in the text so the bundle
+/// doesn't give away where the project is or the user's name
+fn scrub(text: &str, root: &Path) -> String {
+    let mut text = text.replace(&root.display().to_string(), "<root>");
+    if let Some(home) = env::var_os("HOME").filter(|h| h.len() > 1) {
+        text = text.replace(&Path::new(&home).display().to_string(), "<home>");
+    }
+    text
+}
+
+/// The configs with their secrets redacted
+fn effective_config(configs: &[Config]) -> serde_json::Result<Vec<serde_json::Value>> {
+    configs
+        .iter()
+        .map(|config| {
+            let mut value = serde_json::to_value(config)?;
+            if let Some(map) = value.as_object_mut() {
+                for key in &REDACTED_KEYS {
+                    if let Some(v) = map.get_mut(*key).filter(|v| !v.is_null()) {
+                        *v = serde_json::Value::from("<redacted>");
+                    }
+                }
+            }
+            Ok(value)
+        })
+        .collect()
+}
+
+/// Collects the bundle, `--include-sources` adds the project's source files
+/// with planned breakpoints and `--repro-line-tables` the line tables of the
+/// binary
+pub fn collect(inputs: ReproInputs) -> Bundle {
+    let config = inputs.config;
+    let root = config.root();
+    let json = |value: serde_json::Result<String>| {
+        value
+            .map(|text| scrub(&text, &root).into_bytes())
+            .unwrap_or_else(|e| format!("Failed to serialise: {}", e).into_bytes())
+    };
+    let mut bundle = Bundle::new();
+    bundle.add(
+        "config.json",
+        "The effective config of each run, with tokens redacted",
+        json(effective_config(inputs.configs).and_then(|c| serde_json::to_string_pretty(&c))),
+    );
+    let (os, kernel, machine) = {
+        let uts = uname();
+        (
+            uts.sysname().to_string(),
+            uts.release().to_string(),
+            uts.machine().to_string(),
+        )
+    };
+    let diagnostics = Diagnostics {
+        os,
+        kernel,
+        machine,
+        error: inputs.error,
+        stats: inputs.stats,
+    };
+    bundle.add(
+        "diagnostics.json",
+        "The system, how the run ended and the run statistics",
+        json(serde_json::to_string_pretty(&diagnostics)),
+    );
+    let (binary, breakpoints) = match inputs.binary {
+        Some((binary, breakpoints)) => (Some(binary), breakpoints),
+        None => (None, vec![]),
+    };
+    bundle.add(
+        "breakpoints.json",
+        "The planned breakpoints of the binary being traced, as --dump-breakpoints writes them",
+        json(serde_json::to_string_pretty(&breakpoints)),
+    );
+    let mut log = inputs.log.join("\n");
+    log.push('\n');
+    bundle.add(
+        "statemachine.log",
+        "The state machine's log, the latest lines if there were too many",
+        scrub(&log, &root).into_bytes(),
+    );
+    match binary {
+        Some(ref binary) if config.repro_line_tables => {
+            match test_loader::line_table_rows(binary) {
+                Ok(rows) => bundle.add(
+                    "line-tables.json",
+                    "Addresses and line numbers of the binary's line tables, files are indexes",
+                    json(serde_json::to_string(&rows)),
+                ),
+                Err(e) => bundle.exclude(&format!("line tables, they couldn't be read: {}", e)),
+            }
+        }
+        _ => bundle.exclude("line tables, add them with --repro-line-tables"),
+    }
+    if config.include_sources {
+        let sources = breakpoints
+            .iter()
+            .map(|bp| &bp.file)
+            .filter(|f| f.starts_with(&root))
+            .collect::<BTreeSet<_>>();
+        for source in sources {
+            let name = format!("sources/{}", config.display_path(source));
+            match fs::read(source) {
+                Ok(contents) => bundle.add(&name, "Source file", contents),
+                Err(e) => bundle.exclude(&format!("{}, it couldn't be read: {}", name, e)),
+            }
+        }
+    } else {
+        bundle.exclude("source code, add it with --include-sources");
+    }
+    bundle.exclude("environment variables");
+    bundle
+}
+
+/// Lists what the bundle contains and asks whether to write it, the answer is
+/// read from the terminal as the tests may have had stdin
+fn confirm(manifest: &Manifest, path: &Path) -> bool {
+    eprintln!("The repro bundle {} will contain:", path.display());
+    for file in &manifest.files {
+        eprintln!(
+            "  {} ({} bytes{}): {}",
+            file.name,
+            file.bytes,
+            if file.truncated { ", truncated" } else { "" },
+            file.description
+        );
+    }
+    for excluded in &manifest.excluded {
+        eprintln!("  Not included: {}", excluded);
+    }
+    let tty = match File::open("/dev/tty") {
+        Ok(tty) => tty,
+        Err(e) => {
+            warn!(
+                "The repro bundle wasn't written, there's no terminal to confirm it on: {}",
+                e
+            );
+            return false;
+        }
+    };
+    eprint!("Write the bundle? [y/N] ");
+    let mut answer = String::new();
+    BufReader::new(tty).read_line(&mut answer).is_ok() && answer.trim().eq_ignore_ascii_case("y")
+}
+
+/// Collects a repro bundle for the run and writes it to `--make-repro` once
+/// the user confirms, failing to write it doesn't change the run's result
+pub fn make_repro(configs: &[Config], config: &Config, result: &Result<(), RunError>) {
+    let path = match config.make_repro {
+        Some(ref path) => path,
+        None => return,
+    };
+    let log = LOG
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .cloned()
+        .collect();
+    let bundle = collect(ReproInputs {
+        configs,
+        config,
+        error: result.as_ref().err().map(ToString::to_string),
+        stats: stats::snapshot(),
+        binary: breakpoint_dump::current_binary(),
+        log,
+    });
+    if !confirm(bundle.manifest(), path) {
+        return;
+    }
+    match bundle.write(path) {
+        Ok(()) => info!("Repro bundle written to {}", path.display()),
+        Err(e) => warn!(
+            "Failed to write the repro bundle: {}",
+            write_error(path, &e)
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::breakpoint_dump::MappingSource;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn bundle_names(config: &Config, path: &Path) -> Vec<String> {
+        let breakpoint = PlannedBreakpoint {
+            binary: config.root().join("target/debug/deps/repro_test"),
+            address: "0x10".to_string(),
+            file: config.root().join("src/lib.rs"),
+            line: 1,
+            function: Some("secret_sauce".to_string()),
+            source: Some(MappingSource::LineTable),
+            compiled_from: None,
+            inserted: true,
+            hit: false,
+        };
+        let bundle = collect(ReproInputs {
+            configs: std::slice::from_ref(config),
+            config,
+            error: Some("Failed to trace! Error: lost the test".to_string()),
+            stats: RunStats::default(),
+            binary: Some((breakpoint.binary.clone(), vec![breakpoint])),
+            log: vec!["[TRACE cargo_tarpaulin::statemachine] Stopped".to_string()],
+        });
+        bundle.write(path).unwrap();
+        let mut archive = tar::Archive::new(GzDecoder::new(File::open(path).unwrap()));
+        archive
+            .entries()
+            .unwrap()
+            .map(|e| {
+                let mut entry = e.unwrap();
+                let name = entry.path().unwrap().display().to_string();
+                let mut contents = String::new();
+                entry.read_to_string(&mut contents).unwrap();
+                assert!(!contents.contains("sk-live"), "{} has the token", name);
+                name
+            })
+            .collect()
+    }
+
+    #[test]
+    fn manifest_and_no_sources_by_default() {
+        let dir = env::temp_dir().join(format!("tarpaulin-repro-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/lib.rs"), "pub fn secret_sauce() {}\n").unwrap();
+        let mut config = Config::default();
+        config.manifest = dir.join("Cargo.toml");
+        config.codecov_token = Some("sk-live".to_string());
+        let path = dir.join("repro.tar.gz");
+
+        let names = bundle_names(&config, &path);
+        assert_eq!(names[0], "manifest.json");
+        assert!(names.contains(&"config.json".to_string()));
+        assert!(names.contains(&"statemachine.log".to_string()));
+        assert!(!names.iter().any(|n| n.starts_with("sources/")));
+
+        config.include_sources = true;
+        let names = bundle_names(&config, &path);
+        assert!(names.contains(&"sources/src/lib.rs".to_string()));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn files_capped() {
+        let mut bundle = Bundle::new();
+        bundle.add("big", "", vec![0; MAX_FILE_BYTES + 1]);
+        assert!(bundle.manifest.files[0].truncated);
+        assert_eq!(bundle.manifest.files[0].bytes, MAX_FILE_BYTES);
+        for i in 0..4 {
+            bundle.add(&i.to_string(), "", vec![0; MAX_FILE_BYTES]);
+        }
+        assert_eq!(bundle.total, MAX_BUNDLE_BYTES);
+        assert_eq!(bundle.manifest.files.len(), 4);
+        assert_eq!(bundle.manifest.excluded, vec!["3, the bundle is full"]);
+    }
+}
@@ -1,5 +1,13 @@
 use crate::report::cobertura;
+use crate::traces::TraceMap;
 use failure::Fail;
+use std::io;
+use std::path::Path;
+
+/// Added to errors caused by running out of disk space
+const DISK_FULL_HINT: &str = "the disk is full, free some space or use --target-dir and \
+                              --output-dir to build and write the reports elsewhere";
+
 /// Error states that could be returned from tarpaulin
 #[derive(Fail, Debug)]
 pub enum RunError {
@@ -12,6 +20,9 @@ pub enum RunError {
     /// Error trying to resolve package configuration in manifest
     #[fail(display = "Failed to resolve package in manifest! Error: {}", _0)]
     Packages(String),
+    /// The build failed for a reason other than the code not compiling
+    #[fail(display = "Failed to build tests! Error: {}", _0)]
+    Build(String),
     /// Tests failed to compile
     #[fail(display = "Failed to compile tests! Error: {}", _0)]
     TestCompile(String),
@@ -32,6 +43,9 @@ pub enum RunError {
     CovReport(String),
     #[fail(display = "{}", _0)]
     OutFormat(String),
+    /// The requested reports can't be written in the same run
+    #[fail(display = "{}", _0)]
+    Report(String),
     #[fail(display = "{}", _0)]
     IO(std::io::Error),
     #[fail(display = "Error running test: {}", _0)]
@@ -47,8 +61,48 @@ pub enum RunError {
     Lcov(String),
     #[fail(display = "Failed to generate JSON report! Error: {}", _0)]
     Json(String),
+    #[fail(display = "Failed to update coverage history! Error: {}", _0)]
+    History(String),
+    #[fail(
+        display = "Coverage is below the failure threshold {:.2}% < {:.2}%",
+        _0, _1
+    )]
+    BelowThreshold(f64, f64),
+    #[fail(display = "Coverage is below the ratchet floor: {}", _0)]
+    Ratchet(String),
+    #[fail(
+        display = "New files are below the failure threshold {:.2}%: {}",
+        _0, _1
+    )]
+    NewFilesBelowThreshold(f64, String),
+    #[fail(display = "Files are below the failure threshold {:.2}%: {}", _0, _1)]
+    FilesBelowThreshold(f64, String),
+    #[fail(display = "Failed to read the report to compare against {}", _0)]
+    Compare(String),
+    #[fail(
+        display = "Coverage dropped by {:.2}% since the compared run, more than the allowed {:.2}%",
+        _0, _1
+    )]
+    CoverageDropped(f64, f64),
+    #[fail(display = "Failed to find the files changed since {}", _0)]
+    DiffBase(String),
+    #[fail(display = "{} lines are only covered by ignored tests", _0)]
+    OnlyIgnored(usize),
+    #[fail(display = "Terminal UI failed: {}", _0)]
+    Tui(String),
+    #[fail(display = "Coverage server failed: {}", _0)]
+    Serve(String),
+    #[fail(display = "Inconsistent instrumentation: {}", _0)]
+    Instrumentation(String),
+    #[fail(display = "Problems found in the dry run: {}", _0)]
+    Plan(String),
+    #[fail(display = "Tarpaulin can't check coverage from a test it's tracing")]
+    Recursive,
     #[fail(display = "Tarpaulin experienced an internal error")]
     Internal,
+    /// The run was cancelled, contains the results collected before that
+    #[fail(display = "Coverage run was cancelled")]
+    Cancelled(TraceMap),
 }
 
 impl From<std::io::Error> for RunError {
@@ -74,3 +128,87 @@ impl From<serde_json::error::Error> for RunError {
         RunError::Json(e.to_string())
     }
 }
+
+/// Whether an io error was caused by running out of disk space
+pub fn is_disk_full(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(libc::ENOSPC)
+}
+
+/// Whether the error output of a build shows it ran out of disk space
+pub fn is_build_disk_full(output: &str) -> bool {
+    output.contains("No space left on device") || output.contains("(os error 28)")
+}
+
+/// Describes a failed write of `path`, with a hint if the disk is full
+pub fn write_error(path: &Path, e: &io::Error) -> String {
+    if is_disk_full(e) {
+        format!(
+            "Failed to write {}: {}, {}",
+            path.display(),
+            e,
+            DISK_FULL_HINT
+        )
+    } else {
+        format!("Failed to write {}: {}", path.display(), e)
+    }
+}
+
+/// Adds the path being written and the disk full hint to an io error, keeping
+/// its kind
+pub fn with_write_context(path: &Path, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), write_error(path, &e))
+}
+
+/// Error for a build which ran out of disk space, `output` is the error
+/// output of cargo or the compiler
+pub fn build_disk_full(output: &str) -> RunError {
+    let cause = output
+        .lines()
+        .find(|l| is_build_disk_full(l))
+        .unwrap_or("No space left on device")
+        .trim();
+    RunError::Build(format!("{}, {}", cause, DISK_FULL_HINT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn disk_full_hint() {
+        let path = PathBuf::from("target/tarpaulin/coverage.json");
+        let full = io::Error::from_raw_os_error(libc::ENOSPC);
+        let message = write_error(&path, &full);
+        assert!(message.starts_with("Failed to write target/tarpaulin/coverage.json: "));
+        assert!(message.ends_with(DISK_FULL_HINT), "{}", message);
+
+        let denied = io::Error::from_raw_os_error(libc::EACCES);
+        let message = write_error(&path, &denied);
+        assert!(message.contains("target/tarpaulin/coverage.json"));
+        assert!(!message.contains(DISK_FULL_HINT), "{}", message);
+
+        let wrapped = with_write_context(&path, full);
+        assert_eq!(
+            wrapped.kind(),
+            io::Error::from_raw_os_error(libc::ENOSPC).kind()
+        );
+        assert!(wrapped.to_string().contains("coverage.json"));
+    }
+
+    #[test]
+    fn build_disk_full_detected() {
+        let stderr = "   Compiling foo v0.1.0\n\
+                      error: failed to write `target/debug/deps/foo.o`: \
+                      No space left on device (os error 28)\n";
+        assert!(is_build_disk_full(stderr));
+        assert!(!is_build_disk_full("error[E0308]: mismatched types"));
+        match build_disk_full(stderr) {
+            RunError::Build(message) => {
+                assert!(message.starts_with("error: failed to write"), "{}", message);
+                assert!(message.ends_with(DISK_FULL_HINT), "{}", message);
+            }
+            e => panic!("Unexpected error {}", e),
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Checks a crate's coverage from an outer runner, such as an `xtask` binary,
+//! so a project can assert its own coverage in-tree:
+//!
+//! ```no_run
+//! use cargo_tarpaulin::harness::CoverageCheck;
+//!
+//! let check = CoverageCheck::new("Cargo.toml")
+//!     .package("my_crate")
+//!     .exclude_files("src/generated/*")
+//!     .min_total(85.0);
+//! if let Err(e) = check.run() {
+//!     eprintln!("{}", e);
+//!     std::process::exit(1);
+//! }
+//! ```
+//!
+//! The `check!` macro does the same for the crate it's used in and returns
+//! whether the thresholds were met, `assert!(cargo_tarpaulin::check!(min_total
+//! = 85.0))`.
+//!
+//! The check must not run inside the tests it traces as each traced test would
+//! start another run, tarpaulin sets `TARPAULIN` in the environment of the
+//! tests so this is refused with `RunError::Recursive`.
+//!
+//! This module follows semver separately from the rest of the library, which
+//! changes with tarpaulin's internals. Builder methods may be added in any
+//! release but none will be removed or change meaning before 1.0 without a
+//! minor version bump and a deprecation in the release before it.
+use crate::config::Config;
+use crate::errors::RunError;
+use crate::launch_tarpaulin;
+use crate::traces::TraceMap;
+use std::env;
+use std::path::PathBuf;
+
+/// Environment variable tarpaulin sets for the tests it runs
+const TRACED_VAR: &str = "TARPAULIN";
+
+/// Checks the coverage of the crate with the given manifest meets thresholds
+#[derive(Clone, Debug)]
+pub struct CoverageCheck {
+    config: Config,
+    min_total: Option<f64>,
+    min_file: Option<f64>,
+}
+
+impl CoverageCheck {
+    pub fn new(manifest: impl Into<PathBuf>) -> Self {
+        let mut config = Config::default();
+        let manifest = manifest.into();
+        config.manifest = if manifest.is_relative() {
+            config.invocation_dir.join(manifest)
+        } else {
+            manifest
+        };
+        Self {
+            config,
+            min_total: None,
+            min_file: None,
+        }
+    }
+
+    /// Only trace this package of the workspace, can be given more than once
+    pub fn package(mut self, name: &str) -> Self {
+        self.config.packages.push(name.to_string());
+        self
+    }
+
+    /// Leave files matching the pattern out of the coverage, as with
+    /// `--exclude-files`
+    pub fn exclude_files(mut self, pattern: &str) -> Self {
+        self.config.add_excluded_files(&[pattern.to_string()]);
+        self
+    }
+
+    /// Fail if the total coverage is below this percentage
+    pub fn min_total(mut self, percent: f64) -> Self {
+        self.min_total = Some(percent);
+        self
+    }
+
+    /// Fail if any file's coverage is below this percentage
+    pub fn min_file(mut self, percent: f64) -> Self {
+        self.min_file = Some(percent);
+        self
+    }
+
+    /// Traces the crate and returns its coverage if the tests passed and the
+    /// thresholds were met
+    pub fn run(&self) -> Result<TraceMap, RunError> {
+        if env::var_os(TRACED_VAR).is_some() {
+            return Err(RunError::Recursive);
+        }
+        let (traces, ret) = launch_tarpaulin(&self.config)?;
+        if ret != 0 {
+            return Err(RunError::TestFailed);
+        }
+        check(&traces, self.min_total, self.min_file)?;
+        Ok(traces)
+    }
+
+    /// Runs the check printing why it failed if it did, for use in `assert!`
+    pub fn passes(&self) -> bool {
+        match self.run() {
+            Ok(_) => true,
+            Err(e) => {
+                eprintln!("Coverage check failed: {}", e);
+                false
+            }
+        }
+    }
+}
+
+fn check(traces: &TraceMap, min_total: Option<f64>, min_file: Option<f64>) -> Result<(), RunError> {
+    if let Some(threshold) = min_total {
+        let percentage = traces.coverage_percentage() * 100.0;
+        if percentage < threshold {
+            return Err(RunError::BelowThreshold(percentage, threshold));
+        }
+    }
+    if let Some(threshold) = min_file {
+        let below = traces
+            .files()
+            .into_iter()
+            .filter(|f| {
+                let coverable = traces.coverable_in_path(f);
+                coverable > 0
+                    && 100.0 * traces.covered_in_path(f) as f64 / (coverable as f64) < threshold
+            })
+            .map(|f| f.display().to_string())
+            .collect::<Vec<_>>();
+        if !below.is_empty() {
+            return Err(RunError::FilesBelowThreshold(threshold, below.join(", ")));
+        }
+    }
+    Ok(())
+}
+
+/// Checks the coverage of the crate this is used in, the arguments are
+/// `CoverageCheck` methods and their values. Evaluates to whether the tests
+/// passed and the thresholds were met.
+///
+/// ```no_run
+/// assert!(cargo_tarpaulin::check!(min_total = 85.0, exclude_files = "benches/*"));
+/// ```
+#[macro_export]
+macro_rules! check {
+    ($($method:ident = $value:expr),* $(,)?) => {
+        $crate::harness::CoverageCheck::new(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"))
+            $(.$method($value))*
+            .passes()
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traces::Trace;
+    use std::path::Path;
+
+    #[test]
+    fn thresholds() {
+        let mut traces = TraceMap::new();
+        for (file, line, hits) in &[("lib.rs", 1, 1), ("lib.rs", 2, 1), ("gen.rs", 1, 0)] {
+            traces.add_trace(
+                &Path::new("/project/src").join(file),
+                Trace::test_line(*line, *hits),
+            );
+        }
+        assert!(check(&traces, Some(60.0), None).is_ok());
+        assert!(matches!(
+            check(&traces, Some(70.0), None),
+            Err(RunError::BelowThreshold(_, _))
+        ));
+        match check(&traces, None, Some(50.0)) {
+            Err(RunError::FilesBelowThreshold(_, files)) => {
+                assert_eq!(files, "/project/src/gen.rs")
+            }
+            r => panic!("Unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn refuses_recursion() {
+        env::set_var(TRACED_VAR, "1");
+        let res = CoverageCheck::new("Cargo.toml").run();
+        env::remove_var(TRACED_VAR);
+        assert!(matches!(res, Err(RunError::Recursive)));
+    }
+}
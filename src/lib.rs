@@ -1,55 +1,150 @@
+use crate::breakpoint_dump::DumpWriter;
+pub use crate::cancel::CancellationToken;
 use crate::cargo::TestBinary;
 use crate::config::*;
 use crate::errors::*;
 use crate::process_handling::*;
-use crate::report::report_coverage;
+use crate::report::age;
+use crate::report::delta::{self, CoverageDelta, PreviousReport};
+use crate::report::new_files::{self, NewFile};
+use crate::report::overhead::{self, BinaryOverhead};
+use crate::report::targets::{summarise, BinRun, FlakyBinary, TargetCounts};
+use crate::report::{report_coverage, RunMetadata, SkippedConfig};
 use crate::source_analysis::LineAnalysis;
 use crate::statemachine::*;
+use crate::stats::{Phase, StatsWriter};
 use crate::test_loader::*;
+use crate::test_results::{CapturedOutput, OutputCapture, TestSuite};
 use crate::traces::*;
-use log::{info, trace, warn};
+use log::{debug, error, info, trace, warn};
+use nix::fcntl::OFlag;
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::*;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::convert::TryFrom;
 use std::env;
 use std::ffi::CString;
 use std::fs::create_dir_all;
+use std::os::unix::io::RawFd;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
+use std::ptr;
+use std::thread;
+use std::time::{Duration, Instant};
 
 pub mod breakpoint;
+pub mod breakpoint_dump;
+mod cancel;
 mod cargo;
+pub mod compare;
 pub mod config;
+mod consistency;
+pub mod diagnostics;
 pub mod errors;
+pub mod harness;
+pub mod line_map;
+pub mod plan;
 mod process_handling;
+pub mod ratchet;
 pub mod report;
-mod source_analysis;
+pub mod self_test;
+pub mod source_analysis;
 mod statemachine;
+pub mod stats;
 pub mod test_loader;
+pub mod test_results;
+pub mod trace_cache;
 pub mod traces;
 
 mod ptrace_control;
 
 pub fn trace(configs: &[Config]) -> Result<TraceMap, RunError> {
+    trace_cancellable(configs, &CancellationToken::new())
+}
+
+/// Traces the projects for all the configs, if the token is cancelled this
+/// stops early returning `RunError::Cancelled` with the partial results.
+pub fn trace_cancellable(
+    configs: &[Config],
+    token: &CancellationToken,
+) -> Result<TraceMap, RunError> {
     let mut tracemap = TraceMap::new();
+    for t in trace_by_run_type(configs, token)?.traces.values() {
+        tracemap.merge(t);
+    }
+    tracemap.dedup();
+    Ok(tracemap)
+}
+
+/// Results of tracing a project with each run type kept separate
+#[derive(Default)]
+pub struct RunTypeResults {
+    pub traces: BTreeMap<RunType, TraceMap>,
+    /// Number of binaries traced for each run type
+    pub targets: BTreeMap<RunType, TargetCounts>,
+    /// Overhead of tracing each binary if it was measured
+    pub overhead: Vec<BinaryOverhead>,
+    /// Binaries which only passed when retried
+    pub flaky: Vec<FlakyBinary>,
+    /// Results of the tests if they were captured
+    pub test_suites: Vec<TestSuite>,
+    /// Bins run with the arguments they were given
+    pub bin_runs: Vec<BinRun>,
+    /// Source analysis of the project the binaries were traced against
+    pub analysis: HashMap<PathBuf, LineAnalysis>,
+}
+
+/// Traces the projects for all the configs keeping the results of each run
+/// type separate along with the number of binaries traced for each
+fn trace_by_run_type(
+    configs: &[Config],
+    token: &CancellationToken,
+) -> Result<RunTypeResults, RunError> {
+    let mut results = RunTypeResults::default();
     let mut ret = 0i32;
     let mut failure = Ok(());
 
-    for config in configs.iter() {
-        if config.name == "report" {
+    for (config, skipped) in plan::traced_configs(configs) {
+        if let Some(reason) = skipped {
+            warn!(
+                "Skipping config {} as {}, use --force-config {} to run it",
+                config.name, reason, config.name
+            );
             continue;
         }
         if let Some(tgt) = &config.target_dir {
             if !tgt.exists() {
                 let ret = create_dir_all(&tgt);
                 if let Err(e) = ret {
-                    warn!("Failed to create target-dir {}", e);
+                    warn!("Failed to create target-dir: {}", write_error(tgt, &e));
                 }
             }
         }
-        match launch_tarpaulin(config) {
-            Ok((t, r)) => {
-                tracemap.merge(&t);
+        match launch_tarpaulin_by_run_type(config, token) {
+            Ok((res, r)) => {
+                for (ty, traces) in &res.traces {
+                    results.traces.entry(*ty).or_default().merge(traces);
+                }
+                for (ty, count) in res.targets {
+                    *results.targets.entry(ty).or_default() += count;
+                }
+                results.overhead.extend(res.overhead);
+                results.flaky.extend(res.flaky);
+                results.test_suites.extend(res.test_suites);
+                results.bin_runs.extend(res.bin_runs);
+                // Kept so the reports don't analyse the crate again
+                if report_config(configs).map_or(false, |c| ptr::eq(c, config)) {
+                    results.analysis = res.analysis;
+                }
                 ret |= r;
             }
+            Err(RunError::Cancelled(partial)) => {
+                let mut result = merge_run_types(&results.traces);
+                result.merge(&partial);
+                result.dedup();
+                return Err(RunError::Cancelled(result));
+            }
             Err(e) => {
                 info!("Failure {}", e);
                 if failure.is_ok() {
@@ -58,127 +153,890 @@ pub fn trace(configs: &[Config]) -> Result<TraceMap, RunError> {
             }
         }
     }
-    tracemap.dedup();
+    for t in results.traces.values_mut() {
+        t.dedup();
+    }
     if ret == 0 {
-        Ok(tracemap)
+        Ok(results)
     } else {
         Err(RunError::TestFailed)
     }
 }
 
 pub fn run(configs: &[Config]) -> Result<(), RunError> {
-    let tracemap = trace(configs)?;
+    run_cancellable(configs, &CancellationToken::new())
+}
+
+/// Traces and reports the coverage of the projects, if the token is cancelled
+/// no reports are generated and `RunError::Cancelled` is returned.
+pub fn run_cancellable(configs: &[Config], token: &CancellationToken) -> Result<(), RunError> {
+    let mut stats = StatsWriter::new(report_config(configs).and_then(|c| c.stats_file.clone()));
+    let _dump = DumpWriter::new(report_config(configs));
+    let result = trace_and_report(configs, token);
+    stats.finish(&result);
+    if let Some(config) = report_config(configs) {
+        diagnostics::make_repro(configs, config, &result);
+    }
+    result
+}
+
+fn trace_and_report(configs: &[Config], token: &CancellationToken) -> Result<(), RunError> {
+    // Checked before tracing as well as when reporting so a long run isn't
+    // wasted on formats that can't be used together
+    if let Some(config) = report_config(configs) {
+        report::check_stdout_formats(config)?;
+    }
+    let previous = match report_config(configs).and_then(|c| c.compare.as_ref()) {
+        Some(path) => Some(delta::load(path)?),
+        None => None,
+    };
+    let mut results = trace_by_run_type(configs, token)?;
+    // Each config's excluded files are removed as it's traced, the report
+    // config's are removed from the results of them all
+    if let Some(config) = report_config(configs) {
+        remove_excluded_files(config, &mut results.traces);
+    }
+    let tracemap = merge_run_types(&results.traces);
+    stats::set_trace_map(&tracemap);
+    let report_config = match report_config(configs) {
+        Some(c) => c,
+        None => return Ok(()),
+    };
+    let metadata = run_metadata(configs, report_config, &tracemap, results, previous)?;
+    let report_phase = Phase::start("report");
+    report_coverage(report_config, &tracemap, &metadata)?;
+    drop(report_phase);
+    check_thresholds(
+        report_config,
+        &tracemap,
+        metadata.gated.as_ref(),
+        metadata.new_files.as_deref(),
+        metadata.delta.as_ref(),
+    )
+}
+
+/// Prints what running the configs would build, analyse and report without
+/// building or tracing anything, returning an error if there are problems
+/// with the configs
+pub fn dry_run(configs: &[Config]) -> Result<(), RunError> {
+    plan::dry_run(configs, report_config(configs))
+}
+
+/// The config used for the reports of a run of the configs, the one named
+/// report if there's more than one
+fn report_config(configs: &[Config]) -> Option<&Config> {
     if configs.len() == 1 {
-        report_coverage(&configs[0], &tracemap)?;
-    } else if !configs.is_empty() {
-        let mut reported = false;
-        for c in configs.iter() {
-            if c.name == "report" {
-                reported = true;
-                report_coverage(c, &tracemap)?;
-            }
+        configs.first()
+    } else if let Some(c) = configs.iter().find(|c| c.name == "report") {
+        Some(c)
+    } else {
+        configs.first()
+    }
+}
+
+/// Configs not traced because of their environment conditions
+fn skipped_configs(configs: &[Config]) -> Vec<SkippedConfig> {
+    configs
+        .iter()
+        .filter(|c| c.name != "report")
+        .filter_map(|c| {
+            c.skip_reason().map(|reason| SkippedConfig {
+                name: c.name.clone(),
+                reason,
+            })
+        })
+        .collect()
+}
+
+/// Checks the coverage meets the `--fail-under` threshold and the floors in
+/// the `--ratchet` file. The `gated` results, from `--threshold-run-types`,
+/// are checked instead of the merged ones if there are any. New files are
+/// held to `--fail-under-new-files` on their own and the total can't drop by
+/// more than `--max-coverage-drop` since the `--compare` report.
+fn check_thresholds(
+    config: &Config,
+    tracemap: &TraceMap,
+    gated: Option<&TraceMap>,
+    new_files: Option<&[NewFile]>,
+    delta: Option<&CoverageDelta>,
+) -> Result<(), RunError> {
+    if config.fail_under.is_none()
+        && config.ratchet.is_none()
+        && !config.fail_if_only_ignored
+        && config.fail_under_new_files.is_none()
+        && config.max_coverage_drop.is_none()
+    {
+        return Ok(());
+    }
+    if config.fail_if_only_ignored {
+        let lines = tracemap
+            .only_ignored_lines()
+            .values()
+            .map(BTreeSet::len)
+            .sum::<usize>();
+        if lines > 0 {
+            return Err(RunError::OnlyIgnored(lines));
         }
-        if !reported {
-            report_coverage(&configs[0], &tracemap)?;
+    }
+    let threshold_traces = gated.unwrap_or(tracemap);
+    if let Some(threshold) = config.fail_under {
+        let percentage = threshold_traces.coverage_percentage() * 100.0;
+        if percentage < threshold {
+            return Err(RunError::BelowThreshold(percentage, threshold));
         }
     }
-
+    if let Some(ref path) = config.ratchet {
+        ratchet::check(path, config, threshold_traces)?;
+    }
+    match (config.fail_under_new_files, new_files) {
+        (Some(threshold), Some(files)) => new_files::check(files, threshold)?,
+        (Some(_), None) => warn!("--fail-under-new-files has no effect without --diff-base"),
+        _ => {}
+    }
+    if let (Some(max_drop), Some(delta)) = (config.max_coverage_drop, delta) {
+        delta::check(delta, max_drop)?;
+    }
     Ok(())
 }
 
+/// Gets the merged results where only lines hit by one of the given run types
+/// are covered
+fn gated_coverage(
+    tracemap: &TraceMap,
+    by_run_type: &BTreeMap<RunType, TraceMap>,
+    run_types: &[RunType],
+) -> TraceMap {
+    let mut gate = TraceMap::new();
+    for (_, t) in by_run_type.iter().filter(|(ty, _)| run_types.contains(ty)) {
+        gate.merge(t);
+    }
+    gate.dedup();
+    tracemap.covered_by(&gate)
+}
+
+/// Information about the run for the reports from the results of tracing the
+/// configs. The analysis the report config was traced with is used, the crate
+/// is only analysed again if the report config wasn't traced.
+fn run_metadata(
+    configs: &[Config],
+    config: &Config,
+    tracemap: &TraceMap,
+    results: RunTypeResults,
+    previous: Option<PreviousReport>,
+) -> Result<RunMetadata, RunError> {
+    let analysis = if results.analysis.is_empty() {
+        source_analysis::get_line_analysis(config)
+    } else {
+        results.analysis
+    };
+    let new_files = match config.diff_base {
+        Some(ref base) => {
+            let added = new_files::added_files(&config.root(), base)?;
+            Some(new_files::coverage(config, tracemap, &added))
+        }
+        None => None,
+    };
+    Ok(RunMetadata {
+        targets: summarise(
+            &results.targets,
+            &results.traces,
+            tracemap.total_coverable(),
+        ),
+        skipped_configs: skipped_configs(configs),
+        branch_points: analysis
+            .iter()
+            .map(|(path, a)| (path.clone(), a.branch_points()))
+            .collect(),
+        public_functions: analysis
+            .iter()
+            .map(|(path, a)| {
+                (
+                    path.clone(),
+                    a.public_functions().map(|l| l as u64).collect(),
+                )
+            })
+            .collect(),
+        ignored_lines: analysis
+            .iter()
+            .map(|(path, a)| (path.clone(), a.ignored_lines().map(|l| l as u64).collect()))
+            .collect(),
+        line_weights: if config.weighted_coverage {
+            analysis
+                .iter()
+                .map(|(path, a)| (path.clone(), a.line_weights()))
+                .collect()
+        } else {
+            BTreeMap::new()
+        },
+        non_target_lines: analysis.values().map(|a| a.non_target_lines()).sum(),
+        unparsed_files: analysis.values().filter(|a| a.is_unparsed()).count(),
+        debug_assert_lines: analysis
+            .values()
+            .map(|a| a.debug_assert_lines().count())
+            .sum(),
+        gated: if config.threshold_run_types.is_empty() {
+            None
+        } else {
+            Some(gated_coverage(
+                tracemap,
+                &results.traces,
+                &config.threshold_run_types,
+            ))
+        },
+        derived_lines: tracemap.derived_lines(),
+        overhead: overhead::summarise(results.overhead),
+        flaky: results.flaky,
+        test_suites: results.test_suites,
+        bin_runs: results.bin_runs,
+        new_files,
+        age_buckets: if config.age_buckets.is_empty() {
+            None
+        } else {
+            Some(age::coverage(config, tracemap))
+        },
+        delta: previous.map(|p| delta::compare(config, tracemap, &p)),
+        run_types: results.traces,
+        post_filter: None,
+    })
+}
+
+/// Merges the results of each run type into a single tracemap
+fn merge_run_types(by_run_type: &BTreeMap<RunType, TraceMap>) -> TraceMap {
+    let mut result = TraceMap::new();
+    for t in by_run_type.values() {
+        result.merge(t);
+    }
+    result.dedup();
+    result
+}
+
 /// Launches tarpaulin with the given configuration.
 pub fn launch_tarpaulin(config: &Config) -> Result<(TraceMap, i32), RunError> {
+    launch_tarpaulin_cancellable(config, &CancellationToken::new())
+}
+
+/// Launches tarpaulin with the given configuration, if the token is cancelled
+/// the build or test being traced is killed and `RunError::Cancelled` is
+/// returned with the partial results.
+pub fn launch_tarpaulin_cancellable(
+    config: &Config,
+    token: &CancellationToken,
+) -> Result<(TraceMap, i32), RunError> {
+    let (results, return_code) = launch_tarpaulin_by_run_type(config, token)?;
+    Ok((merge_run_types(&results.traces), return_code))
+}
+
+/// Launches tarpaulin with the given configuration keeping the results and
+/// number of binaries of each run type separate, along with the source
+/// analysis they were traced against.
+pub fn launch_tarpaulin_by_run_type(
+    config: &Config,
+    token: &CancellationToken,
+) -> Result<(RunTypeResults, i32), RunError> {
     if !config.name.is_empty() {
         info!("Running config {}", config.name);
     }
 
     info!("Running Tarpaulin");
+    debug!("Processor affinity mode: {}", config.affinity);
 
-    let mut result = TraceMap::new();
+    let mut result: BTreeMap<RunType, TraceMap> = BTreeMap::new();
+    let mut targets: BTreeMap<RunType, TargetCounts> = BTreeMap::new();
+    let mut overhead = vec![];
+    let mut flaky = vec![];
+    let mut test_suites = vec![];
+    let mut bin_runs = vec![];
     let mut return_code = 0i32;
     info!("Building project");
-    let executables = cargo::get_tests(config)?;
+    let build_phase = Phase::start("build");
+    let executables = cargo::get_tests(config, token)?;
+    drop(build_phase);
+    stats::add_binaries(executables.len());
+    let analysis_phase = Phase::start("analysis");
     let project_analysis = source_analysis::get_line_analysis(config);
+    drop(analysis_phase);
+    let debug_asserts = project_analysis
+        .values()
+        .map(|x| x.debug_asserts.len())
+        .sum::<usize>();
+    if debug_asserts > 0 {
+        if config.count_debug_asserts {
+            info!("{} lines are debug assertions that don't exist in release builds, use --count-debug-asserts off to exclude them", debug_asserts);
+        } else {
+            info!("Excluded {} lines of debug assertions", debug_asserts);
+        }
+    }
+    let non_target = project_analysis
+        .values()
+        .map(|x| x.non_target_lines())
+        .sum::<usize>();
+    if non_target > 0 {
+        info!(
+            "{} lines excluded as non-target code, use --count-all-targets to include them",
+            non_target
+        );
+    }
     for exe in &executables {
-        let coverage = get_test_coverage(&exe, &project_analysis, config, false)?;
+        if token.is_cancelled() {
+            return Err(RunError::Cancelled(merge_run_types(&result)));
+        }
+        let traces = result.entry(exe.run_type()).or_default();
+        let counts = targets.entry(exe.run_type()).or_default();
+        counts.binaries += 1;
+        let untraced = if config.measure_overhead {
+            time_untraced(&exe, config)
+        } else {
+            None
+        };
+        let coverage = match trace_with_retries(&exe, &project_analysis, config, token) {
+            Err(RunError::Cancelled(partial)) => {
+                traces.merge(&partial);
+                return Err(RunError::Cancelled(merge_run_types(&result)));
+            }
+            coverage => coverage?,
+        };
         if let Some(res) = coverage {
-            result.merge(&res.0);
-            return_code |= res.1;
+            if let Some(ref output) = res.output {
+                test_suites.push(TestSuite::from_capture(
+                    exe.target_name(),
+                    exe.run_type(),
+                    output,
+                    res.code,
+                ));
+            }
+            if exe.run_type() == RunType::Bins {
+                bin_runs.push(BinRun {
+                    name: exe.target_name(),
+                    package: exe.pkg_name().clone(),
+                    args: test_args(exe, config).to_vec(),
+                    exit_code: res.code,
+                });
+            }
+            traces.merge(&res.traces);
+            return_code |= res.code;
+            counts.executions += res.attempts;
+            if res.code == 0 && res.attempts > 1 {
+                warn!(
+                    "{} passed after failing {} times, its coverage includes every attempt",
+                    exe.path().display(),
+                    res.attempts - 1
+                );
+                flaky.push(FlakyBinary {
+                    binary: exe.path().to_path_buf(),
+                    run_type: exe.run_type(),
+                    failures: res.attempts - 1,
+                });
+            }
+            // Retries would be counted as tracing overhead
+            if let (Some(untraced), 1) = (untraced, res.attempts) {
+                overhead.push(BinaryOverhead::new(
+                    exe.path().to_path_buf(),
+                    untraced,
+                    res.elapsed,
+                ));
+            }
         }
         if config.run_ignored && exe.run_type() == RunType::Tests {
-            let coverage = get_test_coverage(&exe, &project_analysis, config, true)?;
-            if let Some(res) = coverage {
-                result.merge(&res.0);
-                return_code |= res.1;
+            // Hits from the ignored tests are tagged so the lines only they
+            // cover can be reported
+            let coverage = match get_test_coverage(&exe, &project_analysis, config, true, token) {
+                Err(RunError::Cancelled(mut partial)) => {
+                    partial.mark_ignored();
+                    traces.merge(&partial);
+                    return Err(RunError::Cancelled(merge_run_types(&result)));
+                }
+                coverage => coverage?,
+            };
+            if let Some(TracedRun {
+                traces: mut ignored,
+                code,
+                output,
+                ..
+            }) = coverage
+            {
+                if let Some(ref output) = output {
+                    let suite =
+                        TestSuite::from_capture(exe.target_name(), exe.run_type(), output, code);
+                    match test_suites.last_mut() {
+                        Some(s) if s.name == suite.name => s.merge_ignored(suite),
+                        _ => test_suites.push(suite),
+                    }
+                }
+                ignored.mark_ignored();
+                traces.merge(&ignored);
+                return_code |= code;
+                counts.executions += 1;
             }
         }
     }
-    result.dedup();
-    Ok((result, return_code))
+    let mut corrections = BTreeSet::new();
+    for t in result.values_mut() {
+        t.dedup();
+        let line_counts = consistency::line_counts(t);
+        corrections.extend(consistency::check(t, &project_analysis, &line_counts));
+    }
+    report_corrections(config, &corrections)?;
+    if config.exclude_test_scaffolding {
+        let scaffolding: BTreeMap<PathBuf, BTreeSet<u64>> = project_analysis
+            .iter()
+            .map(|(path, a)| {
+                (
+                    path.clone(),
+                    a.scaffolding_lines().map(|l| l as u64).collect(),
+                )
+            })
+            .collect();
+        let moved = result
+            .values_mut()
+            .map(|t| t.exclude_scaffolding(&scaffolding))
+            .sum::<usize>();
+        info!("Excluded {} lines of test scaffolding", moved);
+    }
+    remove_excluded_files(config, &mut result);
+    let results = RunTypeResults {
+        traces: result,
+        targets,
+        overhead,
+        flaky,
+        test_suites,
+        bin_runs,
+        analysis: project_analysis,
+    };
+    Ok((results, return_code))
+}
+
+/// Removes the files matching `--exclude-files` or in unselected packages from
+/// the results. They aren't analysed or traced, this catches the files whose
+/// traces came from elsewhere such as the trace cache or a merged config.
+fn remove_excluded_files(config: &Config, results: &mut BTreeMap<RunType, TraceMap>) {
+    if !config.has_excluded_files() && !config.selects_packages() {
+        return;
+    }
+    for traces in results.values_mut() {
+        let removed = traces.remove_files(|path| config.excludes_file(path));
+        if removed > 0 {
+            debug!("Removed {} excluded files", removed);
+        }
+    }
+}
+
+/// Coverage of every attempt at running a test binary
+struct Attempts {
+    traces: TraceMap,
+    /// Exit code of the last attempt
+    code: i32,
+    attempts: usize,
+    /// Output of the last attempt if it was captured
+    output: Option<CapturedOutput>,
+    /// Time the last attempt ran for once its traces were read
+    elapsed: Duration,
 }
 
-/// Returns the coverage statistics for a test executable in the given workspace
+/// Traces a test binary, running it again while it fails up to the number of
+/// times given by `--retries`. The coverage of all the attempts is merged so
+/// lines hit by a failed attempt are still covered.
+fn trace_with_retries(
+    test: &TestBinary,
+    analysis: &HashMap<PathBuf, LineAnalysis>,
+    config: &Config,
+    token: &CancellationToken,
+) -> Result<Option<Attempts>, RunError> {
+    let retries = config.retries_for(&test.target_name(), test.pkg_name().as_deref());
+    let mut result: Option<Attempts> = None;
+    loop {
+        let coverage = match get_test_coverage(test, analysis, config, false, token) {
+            Err(RunError::Cancelled(mut partial)) => {
+                if let Some(ref res) = result {
+                    partial.merge(&res.traces);
+                }
+                return Err(RunError::Cancelled(partial));
+            }
+            coverage => coverage?,
+        };
+        let run = match coverage {
+            Some(run) => run,
+            None => return Ok(result),
+        };
+        let res = result.get_or_insert_with(|| Attempts {
+            traces: TraceMap::new(),
+            code: run.code,
+            attempts: 0,
+            output: None,
+            elapsed: run.elapsed,
+        });
+        res.traces.merge(&run.traces);
+        res.code = run.code;
+        res.elapsed = run.elapsed;
+        res.attempts += 1;
+        res.output = run.output;
+        if res.code == 0 || res.attempts > retries {
+            return Ok(result);
+        }
+        warn!(
+            "{} failed with exit code {}, retrying ({} of {})",
+            test.path().display(),
+            res.code,
+            res.attempts,
+            retries
+        );
+    }
+}
+
+/// Warns about each correction made to the results, or returns an error if
+/// `--strict-instrumentation` is set
+fn report_corrections(
+    config: &Config,
+    corrections: &BTreeSet<consistency::Correction>,
+) -> Result<(), RunError> {
+    let mut reported = 0;
+    for c in corrections {
+        if config.is_suppressed(c.kind(), c.path()) {
+            debug!("{}", c);
+        } else if config.strict_instrumentation {
+            error!("{}", c);
+            reported += 1;
+        } else {
+            warn!("{}", c);
+            reported += 1;
+        }
+    }
+    if config.strict_instrumentation && reported > 0 {
+        Err(RunError::Instrumentation(format!(
+            "{} inconsistencies between the traces and source",
+            reported
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Warns about source files changed since the test was built, or returns an
+/// error if `--strict-instrumentation` is set
+fn report_stale_sources(config: &Config, test: &Path, stale: &[PathBuf]) -> Result<(), RunError> {
+    let (suppressed, stale): (Vec<_>, Vec<_>) = stale
+        .iter()
+        .partition(|f| config.is_suppressed(WarningKind::StaleSource, f));
+    for f in &suppressed {
+        debug!(
+            "{} was built before {} changed",
+            test.display(),
+            config.display_path(f)
+        );
+    }
+    if stale.is_empty() {
+        return Ok(());
+    }
+    let files = stale
+        .iter()
+        .map(|f| format!("    {}", config.display_path(f)))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let msg = format!(
+        "{} was built before these source files changed, their coverage may be wrong:\n{}",
+        test.display(),
+        files
+    );
+    if config.strict_instrumentation {
+        error!("{}", msg);
+        Err(RunError::Instrumentation(format!(
+            "{} source files changed since the tests were built",
+            stale.len()
+        )))
+    } else {
+        warn!("{}", msg);
+        Ok(())
+    }
+}
+
+/// A traced run of a test executable
+pub struct TracedRun {
+    pub traces: TraceMap,
+    pub code: i32,
+    /// Output of the test if it was captured
+    pub output: Option<CapturedOutput>,
+    /// Time the test ran for once its traces were read from the debug info
+    pub elapsed: Duration,
+}
+
+/// Returns the coverage statistics for a test executable in the given
+/// workspace, its exit code and its output if it was captured
 pub fn get_test_coverage(
     test: &TestBinary,
     analysis: &HashMap<PathBuf, LineAnalysis>,
     config: &Config,
     ignored: bool,
-) -> Result<Option<(TraceMap, i32)>, RunError> {
+    token: &CancellationToken,
+) -> Result<Option<TracedRun>, RunError> {
     if !test.path().exists() {
         return Ok(None);
     }
-    if let Err(e) = limit_affinity() {
+    if let Err(e) = limit_affinity(config.affinity) {
         warn!("Failed to set processor affinity {}", e);
     }
+    // The test's stdout is piped back to read the result of each test
+    let pipe = if config.capture_test_output() {
+        Some(pipe2(OFlag::O_CLOEXEC)?)
+    } else {
+        None
+    };
     match fork() {
         Ok(ForkResult::Parent { child }) => {
-            match collect_coverage(test.path(), child, analysis, config) {
-                Ok(t) => Ok(Some(t)),
+            let capture = pipe.map(|(read, write)| {
+                let _ = close(write);
+                OutputCapture::start(read, config.capture_limit)
+            });
+            let coverage = collect_coverage(
+                test.path(),
+                child,
+                analysis,
+                config,
+                token,
+                capture.as_ref(),
+            );
+            let output = capture.map(OutputCapture::finish);
+            if output.as_ref().map_or(false, |o| o.truncated) {
+                warn!(
+                    "The output of {} was truncated at the capture limit, results printed after it aren't reported",
+                    test.path().display()
+                );
+            }
+            match coverage {
+                Ok((traces, code, elapsed)) => Ok(Some(TracedRun {
+                    traces,
+                    code,
+                    output,
+                    elapsed,
+                })),
+                Err(e @ RunError::Internal) | Err(e @ RunError::Cancelled(_)) => Err(e),
                 Err(e) => Err(RunError::TestCoverage(e.to_string())),
             }
         }
         Ok(ForkResult::Child) => {
             info!("Launching test");
-            execute_test(test, ignored, config)?;
+            let output = pipe.map(|(read, write)| {
+                let _ = close(read);
+                write
+            });
+            execute_test(test, ignored, config, output)?;
             Ok(None)
         }
-        Err(err) => Err(RunError::TestCoverage(format!(
-            "Failed to run test {}, Error: {}",
-            test.path().display(),
-            err.to_string()
-        ))),
+        Err(err) => {
+            if let Some((read, write)) = pipe {
+                let _ = close(read);
+                let _ = close(write);
+            }
+            Err(RunError::TestCoverage(format!(
+                "Failed to run test {}, Error: {}",
+                test.path().display(),
+                err.to_string()
+            )))
+        }
+    }
+}
+
+/// Runs the test without tracing it and returns how long it took, used to
+/// measure the overhead of tracing. If the test can't be run or fails this
+/// warns and returns `None` so the traced run still goes ahead.
+fn time_untraced(test: &TestBinary, config: &Config) -> Option<Duration> {
+    if !test.path().exists() {
+        return None;
+    }
+    let start = Instant::now();
+    match fork() {
+        Ok(ForkResult::Parent { child }) => match wait_untraced(child, config.test_timeout) {
+            Ok(WaitStatus::Exited(_, 0)) => Some(start.elapsed()),
+            Ok(status) => {
+                warn!(
+                    "{} failed when run untraced ({:?}), its overhead isn't measured",
+                    test.path().display(),
+                    status
+                );
+                None
+            }
+            Err(e) => {
+                warn!("Failed to time {} untraced: {}", test.path().display(), e);
+                None
+            }
+        },
+        Ok(ForkResult::Child) => {
+            if let Ok((program, argv, envars)) = test_command(test, false, config) {
+                let _ = execute_untraced(program, &argv, envars.as_slice());
+            }
+            // Exiting normally would run the parent's exit handlers and
+            // flush its buffers a second time in the forked child
+            unsafe { libc::_exit(127) }
+        }
+        Err(e) => {
+            warn!("Failed to time {} untraced: {}", test.path().display(), e);
+            None
+        }
+    }
+}
+
+/// Waits for an untraced test to exit, killing it if it runs for longer than
+/// the timeout
+fn wait_untraced(child: Pid, timeout: Duration) -> Result<WaitStatus, RunError> {
+    let start = Instant::now();
+    loop {
+        match waitpid(child, Some(WaitPidFlag::WNOHANG))? {
+            WaitStatus::StillAlive if start.elapsed() > timeout => {
+                let _ = kill(child, Signal::SIGKILL);
+                let _ = waitpid(child, None);
+                return Err(RunError::TestRuntime(format!(
+                    "timed out after {}s",
+                    timeout.as_secs()
+                )));
+            }
+            WaitStatus::StillAlive => thread::sleep(Duration::from_millis(5)),
+            status @ WaitStatus::Exited(..) | status @ WaitStatus::Signaled(..) => {
+                return Ok(status)
+            }
+            _ => {}
+        }
     }
 }
 
-/// Collects the coverage data from the launched test
+/// Collects the coverage data from the launched test, along with how long it
+/// ran for once its traces were read
 fn collect_coverage(
     test_path: &Path,
     test: Pid,
     analysis: &HashMap<PathBuf, LineAnalysis>,
     config: &Config,
-) -> Result<(TraceMap, i32), RunError> {
-    let mut ret_code = 0;
-    let mut traces = generate_tracemap(test_path, analysis, config)?;
-    {
-        trace!("Test PID is {}", test);
-        let (mut state, mut data) = create_state_machine(test, &mut traces, config);
-        loop {
-            state = state.step(&mut data, config)?;
-            if state.is_finished() {
-                if let TestState::End(i) = state {
-                    ret_code = i;
-                }
-                break;
+    token: &CancellationToken,
+    capture: Option<&OutputCapture>,
+) -> Result<(TraceMap, i32, Duration), RunError> {
+    let (mut traces, digests) = if config.records_breakpoints() {
+        // Planned before sampling so sampled out addresses are listed as not
+        // inserted
+        let (traces, digests, origins) =
+            generate_tracemap_with_origins(test_path, analysis, config)?;
+        breakpoint_dump::plan(test_path, &traces, &origins);
+        (traces, digests)
+    } else {
+        generate_tracemap(test_path, analysis, config)?
+    };
+    // The test waits to be traced while the debug info is read, that isn't
+    // tracing overhead
+    let start = Instant::now();
+    match stale_sources(test_path, &traces.files(), &digests) {
+        Ok(stale) => report_stale_sources(config, test_path, &stale)?,
+        Err(e) => debug!(
+            "Couldn't check {} is up to date: {}",
+            test_path.display(),
+            e
+        ),
+    }
+    let removed = traces.sample_addresses(config.max_addresses_per_line);
+    if removed > 0 {
+        info!(
+            "Sampled {} lines compiled to more than {} addresses, {} addresses not instrumented",
+            traces.total_sampled(),
+            config.max_addresses_per_line,
+            removed
+        );
+    }
+    let trace_phase = Phase::start("trace");
+    let exit = catch_tracer_panic(|| trace_test(test, &mut traces, config, token, capture));
+    drop(trace_phase);
+    match exit? {
+        Some(exit) => {
+            if let TestExit::Signal {
+                signal,
+                core_dumped,
+            } = exit
+            {
+                let name = Signal::try_from(signal)
+                    .map(|s| format!("{:?}", s))
+                    .unwrap_or_else(|_| signal.to_string());
+                warn!(
+                    "{} was killed by {}{}, keeping the coverage collected before it ended",
+                    test_path.display(),
+                    name,
+                    if core_dumped { " (core dumped)" } else { "" }
+                );
             }
+            Ok((traces, exit.code(), start.elapsed()))
         }
+        None => Err(RunError::Cancelled(traces)),
     }
-    Ok((traces, ret_code))
 }
 
-/// Launches the test executable
-fn execute_test(test: &TestBinary, ignored: bool, config: &Config) -> Result<(), RunError> {
+/// Steps the state machine until the test ends returning how it ended, or
+/// `None` if the token was cancelled. When cancelled the state machine data is
+/// dropped which kills and reaps the test. With `--per-test-timeout` the test
+/// is killed if its output shows a test has hung, the state machine then sees
+/// it end by the signal so the coverage so far is kept.
+fn trace_test(
+    test: Pid,
+    traces: &mut TraceMap,
+    config: &Config,
+    token: &CancellationToken,
+    capture: Option<&OutputCapture>,
+) -> Result<Option<TestExit>, RunError> {
+    trace!("Test PID is {}", test);
+    let (mut state, mut data) = create_state_machine(test, traces, config);
+    loop {
+        if token.is_cancelled() {
+            info!("Cancelling trace of test {}", test);
+            return Ok(None);
+        }
+        if let (Some(capture), Some(timeout)) = (capture, config.per_test_timeout) {
+            if let Some(name) = capture.hung_test(timeout) {
+                warn!(
+                    "{} ran for more than {}s without a result, killing its binary",
+                    name,
+                    timeout.as_secs()
+                );
+                let _ = kill(test, Signal::SIGKILL);
+            }
+        }
+        state = state.step(&mut data, config)?;
+        if let TestState::End(exit) = state {
+            return Ok(Some(exit));
+        }
+    }
+}
+
+/// Converts a panic while tracing into an error. The state machine data is
+/// dropped while unwinding which restores any breakpoints and reaps the test.
+fn catch_tracer_panic<T>(f: impl FnOnce() -> Result<T, RunError>) -> Result<T, RunError> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(res) => res,
+        Err(_) => {
+            error!("Tarpaulin panicked while tracing the test");
+            Err(RunError::Internal)
+        }
+    }
+}
+
+/// Launches the test executable, writing its stdout to the given pipe if its
+/// output is captured
+fn execute_test(
+    test: &TestBinary,
+    ignored: bool,
+    config: &Config,
+    output: Option<RawFd>,
+) -> Result<(), RunError> {
+    let (program, argv, envars) = test_command(test, ignored, config)?;
+    if let Some(fd) = output {
+        redirect_stdout(fd)?;
+    }
+    execute(program, &argv, envars.as_slice())
+}
+
+/// Prepares the forked child to run the test, returning the program,
+/// arguments and environment to exec it with
+fn test_command(
+    test: &TestBinary,
+    ignored: bool,
+    config: &Config,
+) -> Result<(CString, Vec<CString>, Vec<CString>), RunError> {
     let exec_path = CString::new(test.path().to_str().unwrap()).unwrap();
     info!("running {}", test.path().display());
+    // Opened before changing directory so relative paths are from where
+    // tarpaulin was launched
+    redirect_stdin(&config.stdin)?;
     let _ = match test.manifest_dir() {
         Some(md) => env::set_current_dir(&md),
         None => env::set_current_dir(&config.root()),
@@ -198,10 +1056,17 @@ fn execute_test(test: &TestBinary, ignored: bool, config: &Config) -> Result<(),
     } else {
         vec![exec_path.clone()]
     };
+    // Lets the tests and `harness` tell they're being traced
+    envars.push(CString::new("TARPAULIN=1").unwrap());
     if config.verbose {
         envars.push(CString::new("RUST_BACKTRACE=1").unwrap());
     }
-    for s in &config.varargs {
+    // On one thread libtest prints each test's name as it starts so a hung
+    // test can be named
+    if config.per_test_timeout.is_some() && env::var_os("RUST_TEST_THREADS").is_none() {
+        envars.push(CString::new("RUST_TEST_THREADS=1").unwrap());
+    }
+    for s in test_args(test, config) {
         argv.push(CString::new(s.as_bytes()).unwrap_or_default());
     }
 
@@ -219,5 +1084,134 @@ fn execute_test(test: &TestBinary, ignored: bool, config: &Config) -> Result<(),
             .push(CString::new(format!("CARGO_MANIFEST_DIR={}", s.display())).unwrap_or_default());
     }
 
-    execute(exec_path, &argv, envars.as_slice())
+    Ok((exec_path, argv, envars))
+}
+
+/// Arguments the binary is run with, bins are given the ones for them in
+/// `bin-args` rather than the arguments for the test harness
+fn test_args<'a>(test: &TestBinary, config: &'a Config) -> &'a [String] {
+    if test.run_type() == RunType::Bins {
+        config
+            .bin_args
+            .get(&test.target_name())
+            .map_or(&[], Vec::as_slice)
+    } else {
+        &config.varargs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::errno::Errno;
+    use std::sync::Mutex;
+
+    /// The state machine waits on any child so tests tracing a child can't run
+    /// in parallel
+    static TRACE_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Forks a traced child that sleeps so it won't finish during the test
+    fn spawn_sleeper() -> Pid {
+        match fork().unwrap() {
+            ForkResult::Child => {
+                let program = CString::new("/bin/sleep").unwrap();
+                let argv = [program.clone(), CString::new("30").unwrap()];
+                let _ = execute(program, &argv, &[]);
+                std::process::exit(1);
+            }
+            ForkResult::Parent { child } => child,
+        }
+    }
+
+    fn assert_reaped(child: Pid) {
+        assert_eq!(
+            waitpid(child, Some(WaitPidFlag::WNOHANG)),
+            Err(nix::Error::Sys(Errno::ECHILD))
+        );
+        assert_eq!(kill(child, None), Err(nix::Error::Sys(Errno::ESRCH)));
+    }
+
+    #[test]
+    fn panic_while_tracing_reaps_test() {
+        let _lock = TRACE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let child = spawn_sleeper();
+        let config = Config::default();
+        let mut traces = TraceMap::new();
+        let res = catch_tracer_panic(|| -> Result<(), RunError> {
+            let (mut state, mut data) = create_state_machine(child, &mut traces, &config);
+            while let TestState::Start { .. } | TestState::Initialise = state {
+                state = state.step(&mut data, &config)?;
+            }
+            panic!("Injected tracer panic");
+        });
+        assert!(matches!(res, Err(RunError::Internal)));
+        assert_reaped(child);
+    }
+
+    #[test]
+    fn cancel_before_test_starts() {
+        let _lock = TRACE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let child = spawn_sleeper();
+        let config = Config::default();
+        let mut traces = TraceMap::new();
+        let token = CancellationToken::new();
+        token.cancel();
+        let res = trace_test(child, &mut traces, &config, &token, None);
+        assert!(matches!(res, Ok(None)));
+        assert_reaped(child);
+    }
+
+    #[test]
+    fn cancel_while_test_running() {
+        let _lock = TRACE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let child = spawn_sleeper();
+        let config = Config::default();
+        let mut traces = TraceMap::new();
+        let token = CancellationToken::new();
+        let canceller = {
+            let token = token.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(200));
+                token.cancel();
+            })
+        };
+        let res = trace_test(child, &mut traces, &config, &token, None);
+        canceller.join().unwrap();
+        assert!(matches!(res, Ok(None)));
+        assert_reaped(child);
+    }
+
+    #[test]
+    fn suppressed_warnings_not_strict() {
+        let mut config = Config::default();
+        config.manifest = PathBuf::from("/project/Cargo.toml");
+        config.strict_instrumentation = true;
+        config
+            .suppress
+            .insert("out-of-range".to_string(), vec!["src/gen/*".to_string()]);
+        config
+            .suppress
+            .insert("stale-source".to_string(), vec!["build.rs".to_string()]);
+        let mut corrections = BTreeSet::new();
+        corrections.insert(consistency::Correction::OutOfRange {
+            path: PathBuf::from("/project/src/gen/api.rs"),
+            line: 40,
+            lines_in_file: 30,
+        });
+        assert!(report_corrections(&config, &corrections).is_ok());
+        corrections.insert(consistency::Correction::IgnoredLine {
+            path: PathBuf::from("/project/src/gen/api.rs"),
+            line: 4,
+        });
+        match report_corrections(&config, &corrections) {
+            Err(RunError::Instrumentation(msg)) => assert!(msg.starts_with("1 ")),
+            r => panic!("Unexpected result {:?}", r),
+        }
+
+        let test = Path::new("/project/target/debug/deps/tests");
+        let build = PathBuf::from("/project/build.rs");
+        assert!(report_stale_sources(&config, test, &[build.clone()]).is_ok());
+        let lib = PathBuf::from("/project/src/lib.rs");
+        assert!(report_stale_sources(&config, test, &[build, lib]).is_err());
+    }
 }
@@ -0,0 +1,249 @@
+//! Maps the lines of the source a run was traced against onto the source as
+//! it is now, so saved traces of a file that has changed since can be kept.
+//! Lines are matched with a patience diff: lines which are unique in both
+//! versions anchor the matching and everything between anchors that isn't a
+//! common prefix or suffix is a changed region. Lines in changed regions are
+//! never mapped, even if they happen to equal a line in the other version.
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// A source file as it was when it was traced
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SourceSnapshot {
+    /// Hash of the content from `content_hash`
+    pub hash: u64,
+    pub content: String,
+}
+
+impl SourceSnapshot {
+    pub fn new(content: String) -> Self {
+        SourceSnapshot {
+            hash: content_hash(&content),
+            content,
+        }
+    }
+
+    pub fn read(path: &Path) -> io::Result<Self> {
+        fs::read_to_string(path).map(SourceSnapshot::new)
+    }
+}
+
+/// FNV-1a hash of a file's content, unlike `DefaultHasher` it's the same
+/// between tarpaulin versions so can be saved
+pub fn content_hash(content: &str) -> u64 {
+    content.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Maps the 1-based line numbers of the unchanged lines in `old` to where
+/// they are in `new`
+pub fn line_map(old: &str, new: &str) -> HashMap<u64, u64> {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+    let mut map = HashMap::new();
+    match_region(&old, &new, (0, 0), &mut map);
+    map
+}
+
+fn match_region(old: &[&str], new: &[&str], start: (usize, usize), map: &mut HashMap<u64, u64>) {
+    let mut add = |o: usize, n: usize| {
+        map.insert((start.0 + o + 1) as u64, (start.1 + n + 1) as u64);
+    };
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    for i in 0..prefix {
+        add(i, i);
+    }
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    for i in 1..=suffix {
+        add(old.len() - i, new.len() - i);
+    }
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+    if old_middle.is_empty() || new_middle.is_empty() {
+        return;
+    }
+    let anchors = unique_anchors(old_middle, new_middle);
+    // Without anchors the whole region is treated as changed
+    let (mut o, mut n) = (0, 0);
+    for (a, b) in anchors {
+        let inner = (start.0 + prefix + o, start.1 + prefix + n);
+        match_region(&old_middle[o..a], &new_middle[n..b], inner, map);
+        map.insert(
+            (start.0 + prefix + a + 1) as u64,
+            (start.1 + prefix + b + 1) as u64,
+        );
+        o = a + 1;
+        n = b + 1;
+    }
+    if o > 0 {
+        let inner = (start.0 + prefix + o, start.1 + prefix + n);
+        match_region(&old_middle[o..], &new_middle[n..], inner, map);
+    }
+}
+
+/// Pairs of indexes of lines which appear once in both `old` and `new`, the
+/// longest run of them that is in the same order in both
+fn unique_anchors(old: &[&str], new: &[&str]) -> Vec<(usize, usize)> {
+    // Occurrences in old and new and the index of the line in each
+    let mut counts: HashMap<&str, (usize, usize, usize, usize)> = HashMap::new();
+    for (i, line) in old.iter().enumerate() {
+        let entry = counts.entry(*line).or_default();
+        entry.0 += 1;
+        entry.2 = i;
+    }
+    for (i, line) in new.iter().enumerate() {
+        if let Some(entry) = counts.get_mut(*line) {
+            entry.1 += 1;
+            entry.3 = i;
+        }
+    }
+    let mut unique = counts
+        .values()
+        .filter(|(in_old, in_new, _, _)| *in_old == 1 && *in_new == 1)
+        .map(|(_, _, o, n)| (*o, *n))
+        .collect::<Vec<_>>();
+    unique.sort_unstable();
+    longest_increasing(&unique)
+}
+
+/// Longest subsequence of `pairs`, which are sorted by the first index, where
+/// the second index is increasing
+fn longest_increasing(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    // Index into pairs of the smallest tail of an increasing run of each length
+    let mut tails: Vec<usize> = vec![];
+    let mut previous = vec![None; pairs.len()];
+    for (i, pair) in pairs.iter().enumerate() {
+        let len = match tails.binary_search_by(|t| pairs[*t].1.cmp(&pair.1)) {
+            Ok(len) | Err(len) => len,
+        };
+        if len > 0 {
+            previous[i] = Some(tails[len - 1]);
+        }
+        if len == tails.len() {
+            tails.push(i);
+        } else {
+            tails[len] = i;
+        }
+    }
+    let mut result = vec![];
+    let mut next = tails.last().copied();
+    while let Some(i) = next {
+        result.push(pairs[i]);
+        next = previous[i];
+    }
+    result.reverse();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BEFORE: &str = "fn add(a: u32, b: u32) -> u32 {
+    a + b
+}
+
+fn sub(a: u32, b: u32) -> u32 {
+    a - b
+}
+
+fn mul(a: u32, b: u32) -> u32 {
+    a * b
+}
+";
+
+    fn mapped(old: &str, new: &str) -> Vec<(u64, u64)> {
+        let mut map = line_map(old, new).into_iter().collect::<Vec<_>>();
+        map.sort_unstable();
+        map
+    }
+
+    /// Every mapped line has the same content in both versions
+    fn assert_unchanged(old: &str, new: &str) {
+        let old_lines = old.lines().collect::<Vec<_>>();
+        let new_lines = new.lines().collect::<Vec<_>>();
+        for (o, n) in mapped(old, new) {
+            assert_eq!(old_lines[o as usize - 1], new_lines[n as usize - 1]);
+        }
+    }
+
+    #[test]
+    fn unchanged_file() {
+        let map = mapped(BEFORE, BEFORE);
+        assert_eq!(map.len(), 11);
+        assert!(map.iter().all(|(o, n)| o == n));
+    }
+
+    #[test]
+    fn insertion_shifts_later_lines() {
+        let after = BEFORE.replace("    a - b\n", "    let c = a - b;\n    c\n");
+        let map = line_map(BEFORE, &after);
+        assert_unchanged(BEFORE, &after);
+        assert_eq!(map[&5], 5);
+        // The changed line isn't mapped to either of the new lines
+        assert!(!map.contains_key(&6));
+        assert!(!map.values().any(|n| *n == 6 || *n == 7));
+        assert_eq!(map[&7], 8);
+        assert_eq!(map[&10], 11);
+    }
+
+    #[test]
+    fn deletion_shifts_later_lines() {
+        let after = BEFORE.replace("fn sub(a: u32, b: u32) -> u32 {\n    a - b\n}\n\n", "");
+        let map = line_map(BEFORE, &after);
+        assert_unchanged(BEFORE, &after);
+        assert_eq!(map[&2], 2);
+        assert!(!map.contains_key(&6));
+        assert_eq!(map[&9], 5);
+        assert_eq!(map[&10], 6);
+    }
+
+    #[test]
+    fn moved_block_is_never_mapped_out_of_place() {
+        let after = "fn mul(a: u32, b: u32) -> u32 {
+    a * b
+}
+
+fn add(a: u32, b: u32) -> u32 {
+    a + b
+}
+
+fn sub(a: u32, b: u32) -> u32 {
+    a - b
+}
+";
+        assert_unchanged(BEFORE, after);
+        let map = line_map(BEFORE, after);
+        // Either the moved function or the two it moved past keep their hits
+        let moved_kept = map.get(&10) == Some(&2);
+        let others_kept = map.get(&2) == Some(&6) && map.get(&6) == Some(&10);
+        assert!(moved_kept != others_kept);
+    }
+
+    #[test]
+    fn rewritten_region_is_unmapped() {
+        let old = "a\n}\nb\n}\nc\n";
+        let new = "a\nx\n}\ny\nc\n";
+        let map = mapped(old, new);
+        assert_eq!(map, vec![(1, 1), (5, 5)]);
+    }
+
+    #[test]
+    fn hash_is_stable() {
+        assert_eq!(content_hash(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(content_hash("a"), 0xaf63_dc4c_8601_ec8c);
+        assert_ne!(
+            content_hash(BEFORE),
+            content_hash(&BEFORE.replace('+', "-"))
+        );
+    }
+}
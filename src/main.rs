@@ -1,10 +1,11 @@
 use cargo_tarpaulin::config::*;
-use cargo_tarpaulin::run;
-use clap::{crate_version, App, Arg, ArgSettings, SubCommand};
-use env_logger::Builder;
-use log::trace;
+use cargo_tarpaulin::report::{self, history};
+use cargo_tarpaulin::{compare, diagnostics, dry_run, run, self_test};
+use clap::{crate_version, value_t, App, Arg, ArgMatches, ArgSettings, SubCommand};
+use env_logger::{Builder, WriteStyle};
+use log::{trace, Log};
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 fn is_dir(d: String) -> Result<(), String> {
     if Path::new(&d).is_dir() {
@@ -14,30 +15,72 @@ fn is_dir(d: String) -> Result<(), String> {
     }
 }
 
-fn set_up_logging(debug: bool, verbose: bool) {
+fn is_percentage(p: String) -> Result<(), String> {
+    match p.parse::<f64>() {
+        Ok(x) if (0.0..=100.0).contains(&x) => Ok(()),
+        _ => Err(String::from("must be a number between 0 and 100")),
+    }
+}
+
+fn set_up_logging(args: &ArgMatches) {
     let mut builder = Builder::new();
 
     // NOTE: This overwrites RUST_LOG
-    if debug {
+    if args.is_present("debug") {
         builder.filter_module("cargo_tarpaulin", log::LevelFilter::Trace);
-    } else if verbose {
+    } else if args.is_present("verbose") {
         builder.filter_module("cargo_tarpaulin", log::LevelFilter::Debug);
+    } else if args.is_present("quiet") {
+        builder.filter_module("cargo_tarpaulin", log::LevelFilter::Warn);
     } else {
         builder.filter_module("cargo_tarpaulin", log::LevelFilter::Info);
     }
+    match value_t!(args, "color", Color) {
+        Ok(Color::Always) => builder.write_style(WriteStyle::Always),
+        Ok(Color::Never) => builder.write_style(WriteStyle::Never),
+        _ => builder.write_style(WriteStyle::Auto),
+    };
+
+    builder.format_timestamp(None).format(|buf, record| {
+        let level_style = buf.default_level_style(record.level());
+        writeln!(
+            buf,
+            "[{} tarpaulin] {}",
+            level_style.value(record.level()),
+            record.args()
+        )
+    });
+    if args.is_present("make-repro") {
+        // Every record is passed on so the state machine's log can be kept
+        // for the repro bundle whatever is printed
+        let logger = ReproLogger(builder.build());
+        if log::set_boxed_logger(Box::new(logger)).is_ok() {
+            log::set_max_level(log::LevelFilter::Trace);
+        }
+    } else {
+        builder.init();
+    }
+}
+
+/// Prints what the configured logger would and keeps the state machine's
+/// records for `--make-repro`
+struct ReproLogger(env_logger::Logger);
+
+impl Log for ReproLogger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
 
-    builder
-        .format_timestamp(None)
-        .format(|buf, record| {
-            let level_style = buf.default_level_style(record.level());
-            writeln!(
-                buf,
-                "[{} tarpaulin] {}",
-                level_style.value(record.level()),
-                record.args()
-            )
-        })
-        .init();
+    fn log(&self, record: &log::Record) {
+        diagnostics::record_log(record);
+        if self.0.matches(record) {
+            self.0.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush();
+    }
 }
 
 const CI_SERVER_HELP: &'static str = "Name of service, supported services are:
@@ -45,6 +88,14 @@ travis-ci, travis-pro, circle-ci, semaphore, jenkins and codeship.
 If you are interfacing with coveralls.io or another site you can \
 also specify a name that they will recognise. Refer to their documentation for this.";
 
+fn print_history(args: &ArgMatches) -> Result<(), String> {
+    let file = Path::new(args.value_of("file").unwrap_or_default());
+    let last = value_t!(args, "last", usize).unwrap_or(20);
+    let entries = history::read(file).map_err(|e| e.to_string())?;
+    print!("{}", history::render(&entries, last));
+    Ok(())
+}
+
 fn main() -> Result<(), String> {
     let args = App::new("cargo-tarpaulin")
         .author("Daniel McKenna, <danielmckenna93@gmail.com>")
@@ -55,20 +106,29 @@ fn main() -> Result<(), String> {
             .about("Tool to analyse test coverage of cargo projects")
             .version(concat!("version: ", crate_version!()))
             .args_from_usage(
-                 "--config [FILE] 'Path to a toml file specifying a list of options this will override any other options set'
-                 --ignore-config 'Ignore any project config files'
+                 "--ignore-config 'Ignore any project config files'
                  --debug 'Show debug output - this is used for diagnosing issues with tarpaulin'
-                 --verbose -v 'Show extra output'
+                 --verbose -v... 'Show extra output, -vv also makes cargo verbose'
                  --ignore-tests 'Ignore lines of test functions when collecting coverage'
-                 --ignore-panics 'Ignore panic macros in tests'
+                 --include-derives 'Count the code derives generate on struct, enum and union definitions'
+                 --weighted-coverage 'Report coverage weighted by the decision points and nesting of each line alongside the plain coverage'
+                 --exclude-test-scaffolding 'Leave test helper functions such as setup and teardown out of the coverable lines'
+                 --ignore-panics 'Ignore panic macros, and assert!(false) as the only statement of an else block or match arm'
                  --count   'Counts the number of hits during coverage'
                  --ignored -i 'Run ignored tests as well'
+                 --fail-if-only-ignored 'Fail if any lines are only covered by ignored tests, used with --ignored'
                  --line -l    'Line coverage'
                  --force-clean 'Adds a clean stage to work around cargo bugs that may affect coverage results'
                  --branch -b  'Branch coverage: NOT IMPLEMENTED'
                  --forward -f 'Forwards unexpected signals to test. Tarpaulin will still take signals it is expecting.'
                  --coveralls [KEY]  'Coveralls key, either the repo token, or if you're using travis use $TRAVIS_JOB_ID and specify travis-{ci|pro} in --ciserver'
                  --report-uri [URI] 'URI to send report to, only used if the option --coveralls is used'
+                 --codecov-upload 'Upload the results to Codecov'
+                 --codecov-token [TOKEN] 'Token for --codecov-upload, defaults to $CODECOV_TOKEN'
+                 --junit [PATH] 'Write the result of each test to PATH as JUnit XML'
+                 --badge [PATH] 'Write an SVG badge of the coverage to PATH'
+                 --clover-absolute-paths 'Name files by their absolute path in the Clover report'
+                 --sonar-root [DIR] 'Directory the paths in the Sonar report are relative to, where the scanner runs (default is the root)'
                  --no-default-features 'Do not include default features'
                  --features [FEATURE]... 'Features to be included in the target project'
                  --all-features 'Build all available features'
@@ -80,19 +140,123 @@ fn main() -> Result<(), String> {
                  --timeout -t [SECONDS] 'Integer for the maximum time in seconds without response from test before timeout (default is 1 minute).'
                  --release   'Build in release mode.'
                  --no-run 'Compile tests but don't run coverage'
+                 --dry-run 'Print the packages, targets, source files and reports a run would use without building or tracing, with --out Json as JSON'
                  --locked 'Do not update Cargo.lock'
                  --frozen 'Do not update Cargo.lock or any caches'
                  --target-dir [DIR] 'Directory for all generated artifacts'
                  --offline 'Run without accessing the network'
-                 -Z [FEATURES]...   'List of unstable nightly only flags'")
+                 --target [TRIPLE] 'Build for the target triple, code under a cfg that is false for it is not coverable'
+                 --count-all-targets 'Count code under a cfg that is false for the target or enabled features, such as cfg(windows) on Linux, as coverable'
+                 --extended-table 'Add the number of decision points in each file to the summary'
+                 --show-missing 'List the uncovered lines of each file in the summary'
+                 --missed-analysis 'Show which uncovered lines are in functions that were entered and which functions were never entered'
+                 --profile-hits 'Record when each line was first and last hit and list the longest spans'
+                 --strict-instrumentation 'Fail instead of warning when traces are inconsistent with the source'
+                 --strict-reporting 'Fail when a source file is unreadable for the reports instead of reporting it without its source'
+                 --no-cache 'Read the debug info of every test binary instead of using the traces cached from previous runs'
+                 --html-split 'Write the HTML report as an index page and a page per source file in tarpaulin-report/'
+                 --measure-overhead 'Run each test binary untraced before tracing it and report how much slower it is when traced, doubles the time taken'
+                 --merge-with-diff 'Keep the hits saved from the previous run for files changed since by moving them to where their lines are now'
+                 --tui 'Browse the results in an interactive terminal UI (requires the tui feature)'
+                 -Z [FEATURES]...   'List of unstable nightly only flags passed to cargo'")
             .args(&[
-                Arg::from_usage("--out -o [FMT]   'Output format of coverage report'")
-                    .possible_values(&OutputFile::variants())
+                Arg::from_usage("--config [FILE]... 'Path to a toml file specifying a list of options this will override any other options set, KEY=VALUE is passed to cargo as a config override'")
+                    .number_of_values(1),
+                Arg::from_usage("--quiet -q 'Only print warnings and errors, also passed to cargo'")
+                    .conflicts_with_all(&["verbose", "debug"]),
+                Arg::from_usage("--color [WHEN] 'Coloring of the output, also passed to cargo'")
+                    .possible_values(&Color::variants())
+                    .case_insensitive(true),
+                Arg::from_usage("--out -o [FMT]   'Output format of coverage report [possible values: Json, Stdout, Xml, Html, Lcov, Codecov, Github, Junit, Markdown, Badge, Clover, Sonar, Teamcity], Cobertura is another name for Xml and help lists the formats'")
+                    .validator(|s| match s.parse().ok().and_then(|f| report::get_report(&f)) {
+                        Some(_) => Ok(()),
+                        None if s.eq_ignore_ascii_case("help") => Ok(()),
+                        None => Err(report::unknown_format(&s)),
+                    })
                     .multiple(true),
-                Arg::from_usage("--output-dir [PATH] 'Specify a custom directory to write report files'"),
+                Arg::from_usage("--output-dir [PATH] 'Directory to write report files to, a relative PATH is from the root (default is the current directory)'"),
+                Arg::from_usage("--markdown-max-files [N] 'Only list the N worst covered files in the Markdown report'")
+                    .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string())),
+                Arg::from_usage("--report-below [PERCENTAGE] 'Only list files with less coverage than PERCENTAGE in the summary and Markdown report, the totals include every file'")
+                    .validator(is_percentage),
+                Arg::from_usage("--max-annotations [N] 'Most uncovered ranges annotated by --out Github (default is 10)'")
+                    .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string())),
+                Arg::from_usage("--compress-output [ALGO] 'Compress the report files written to the output directory with gzip (default) or zstd'")
+                    .min_values(0)
+                    .possible_values(&Compression::variants())
+                    .case_insensitive(true),
+                Arg::from_usage("--build-output [MODE] 'How much of the build output to show: nothing unless it fails, a progress summary (default) or all of it'")
+                    .possible_values(&BuildOutput::variants())
+                    .case_insensitive(true),
+                Arg::from_usage("--path-format [FORMAT] 'How files are named in reports, relative to the workspace root (default) or legacy for the paths used before'")
+                    .possible_values(&PathFormat::variants())
+                    .case_insensitive(true),
+                Arg::from_usage("--report-by [ROWS] 'Rows of the console summary: file (default), dir or dir:DEPTH to roll up directories below DEPTH'")
+                    .validator(|s| s.parse::<ReportBy>().map(|_| ())),
+                Arg::from_usage("--suggest [N] 'After reporting list the N uncovered places most worth testing next (default is 10)'")
+                    .min_values(0)
+                    .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string())),
+                Arg::from_usage("--per-test-timeout [SECONDS] 'Kill a test that runs longer than SECONDS without a result, keeping the coverage of the other tests in its binary'")
+                    .validator(|n| n.parse::<u64>().map(|_| ()).map_err(|e| e.to_string())),
+                Arg::from_usage("--capture-limit [MB] 'Megabytes of the output of each test binary kept for the JUnit report and --per-test-timeout, the rest is dropped (default is 64)'")
+                    .validator(|n| n.parse::<u64>().map(|_| ()).map_err(|e| e.to_string())),
+                Arg::from_usage("--append-history [FILE] 'Append a JSON line summarising the results to the given file'"),
+                Arg::from_usage("--stats-file [FILE] 'Write timings and sizes of the run as JSON to the given file, it is never sent anywhere'"),
+                Arg::from_usage("--dump-breakpoints [FILE] 'Write every planned breakpoint with its line, whether it was inserted and whether it was hit to FILE as JSON'"),
+                Arg::from_usage("--make-repro [FILE] 'Once the run ends write a bundle of diagnostics for a bug report to FILE, after listing what it contains and asking'"),
+                Arg::from_usage("--include-sources 'Include the source files of the traced binary in the --make-repro bundle'")
+                    .requires("make-repro"),
+                Arg::from_usage("--repro-line-tables 'Include the addresses and line numbers of the traced binary in the --make-repro bundle'")
+                    .requires("make-repro"),
+                Arg::from_usage("--affinity [MODE] 'Whether to pin the tests to a processor, auto only pins when a single test is traced at a time'")
+                    .possible_values(&Affinity::variants())
+                    .case_insensitive(true),
+                Arg::from_usage("--count-debug-asserts [SETTING] 'Whether debug_assert lines are coverable, they don't exist in release builds'")
+                    .possible_values(&["on", "off"]),
+                Arg::from_usage("--all-targets 'Test all targets, the same as --run-types Tests Benchmarks Examples'")
+                    .conflicts_with("run-types"),
+                Arg::from_usage("--stdin [SOURCE] 'Where tests read stdin from: null, inherit or file:PATH (default is null)'")
+                    .validator(|s| s.parse::<Stdin>().map(|_| ())),
                 Arg::from_usage("--run-types [TYPE]... 'Type of the coverage run'")
                     .possible_values(&RunType::variants())
                     .multiple(true),
+                Arg::from_usage("--bin [NAME]... 'Bin targets to run for the Bins run type, all of them if not given'")
+                    .multiple(true)
+                    .number_of_values(1),
+                Arg::from_usage("--default-bin-only 'Only run the default-run bin of each package for the Bins run type'"),
+                Arg::from_usage("--fail-under [PERCENTAGE] 'Sets a percentage threshold for failure ranging from 0-100, if coverage is below exit with a non-zero code'")
+                    .validator(is_percentage),
+                Arg::from_usage("--diff-base [REF] 'Branch or revision to find the files added since, checked as a branch of origin first'"),
+                Arg::from_usage("--fail-under-new-files [PERCENTAGE] 'Fails if any file added since --diff-base has less coverage than this percentage'")
+                    .validator(is_percentage)
+                    .requires("diff-base"),
+                Arg::from_usage("--age-buckets [DAYS]... 'Group the coverable lines by how many days ago they were last changed, such as 30,90,365, using git blame'")
+                    .use_delimiter(true)
+                    .validator(|n| n.parse::<u64>().map(|_| ()).map_err(|e| e.to_string())),
+                Arg::from_usage("--age-analysis-jobs [N] 'Number of files blamed at once for --age-buckets (default is 4)'")
+                    .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string())),
+                Arg::from_usage("--threshold-run-types [TYPE]... 'Run types whose hits count towards --fail-under, by default all run types count'")
+                    .possible_values(&RunType::variants())
+                    .multiple(true),
+                Arg::from_usage("--compare [FILE] 'JSON report of a previous run to show the change in coverage of each file and the total since'"),
+                Arg::from_usage("--max-coverage-drop [PERCENTAGE] 'Fails if the total coverage dropped by more than this many percentage points since the --compare report'")
+                    .validator(is_percentage)
+                    .requires("compare"),
+                Arg::from_usage("--ratchet [FILE] 'File of coverage floors, fails if coverage drops below them and raises them when coverage improves'"),
+                Arg::from_usage("--ratchet-margin [PERCENTAGE] 'Percentage points coverage must beat a ratchet floor by to raise it (default is 0.5)'")
+                    .validator(is_percentage),
+                Arg::from_usage("--ratchet-tolerance [PERCENTAGE] 'Percentage points coverage can drop below a ratchet floor without failing (default is 0)'")
+                    .validator(is_percentage),
+                Arg::from_usage("--max-addresses-per-line [N] 'Lines compiled to more addresses than this only have N of them instrumented, 0 instruments all (default is 8)'")
+                    .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string())),
+                Arg::from_usage("--retries [N] 'Run a failing test binary up to N more times, keeping the coverage of every attempt and passing if any attempt passes'")
+                    .validator(|n| n.parse::<usize>().map(|_| ()).map_err(|e| e.to_string())),
+                Arg::from_usage("--no-retry-tests [NAME]... 'Test binaries, by target or package name, which are never retried'")
+                    .use_delimiter(true),
+                Arg::from_usage("--scaffolding-patterns [PATTERN]... 'Names of the test helper functions left out with --exclude-test-scaffolding, * matches anything (default is setup*,teardown*,*_fixture)'")
+                    .use_delimiter(true),
+                Arg::from_usage("--force-config [NAME]... 'Run the named configs even if their required-env or skip-if-env conditions say to skip them'")
+                    .multiple(true),
                 Arg::from_usage("--root -r [DIR]  'Calculates relative paths to root directory. If --manifest-path isn't specified it will look for a Cargo.toml in root'")
                     .validator(is_dir),
                 Arg::from_usage("--manifest-path [PATH] 'Path to Cargo.toml'"),
@@ -102,12 +266,82 @@ fn main() -> Result<(), String> {
                     .set(ArgSettings::Last)
                     .multiple(true)
                     .help("Arguments to be passed to the test executables can be used to filter or skip certain tests")
-            ]))
+            ])
+            .subcommand(SubCommand::with_name("history")
+                .about("Print the coverage trend recorded with --append-history")
+                .args_from_usage(
+                    "--file <FILE> 'History file written by --append-history'
+                     --last [N] 'Number of most recent entries to show (default is 20)'"))
+            .subcommand(SubCommand::with_name("compare")
+                .about("Trace with two sets of features and report the coverage only reachable with the second")
+                .args_from_usage(
+                    "--features-a <FEATURES> 'Space or comma separated features for the first run, can be empty'
+                     --features-b <FEATURES> 'Space or comma separated features for the second run'"))
+            .subcommand(SubCommand::with_name("serve")
+                .about("Serve saved results as HTML pages and JSON over HTTP (requires the serve feature)")
+                .args_from_usage(
+                    "--from-traces [DIR] 'Directory of the saved results to serve, reloaded when they change (default is target/tarpaulin)'
+                     --host [HOST] 'Address to listen on (default is 127.0.0.1 so only this machine can connect)'
+                     --port [PORT] 'Port to listen on (default is 8080)'"))
+            .subcommand(SubCommand::with_name("report")
+                .about("Write the --out reports from saved results without running the tests, leaving out the --exclude-files")
+                .args_from_usage(
+                    "--from-traces [PATH] 'Saved results or the directory they are in (default is target/tarpaulin)'"))
+            .subcommand(SubCommand::with_name("self-test")
+                .about("Trace a small built in crate to check tarpaulin works in this environment")))
         .get_matches();
 
     let args = args.subcommand_matches("tarpaulin").unwrap_or(&args);
-    set_up_logging(args.is_present("debug"), args.is_present("verbose"));
+    set_up_logging(args);
+    if let Some(mut formats) = args.values_of("out") {
+        if formats.any(|f| f.eq_ignore_ascii_case("help")) {
+            print!("{}", report::format_list());
+            return Ok(());
+        }
+    }
+    if let Some(history) = args.subcommand_matches("history") {
+        return print_history(history);
+    }
     let config = ConfigWrapper::from(args);
+    if let Some(compare) = args.subcommand_matches("compare") {
+        let features_a =
+            compare::split_features(compare.value_of("features-a").unwrap_or_default());
+        let features_b =
+            compare::split_features(compare.value_of("features-b").unwrap_or_default());
+        return compare::run(&config.0, features_a, features_b).map_err(|e| e.to_string());
+    }
+    if let Some(serve) = args.subcommand_matches("serve") {
+        let config = &config.0[0];
+        let dir = serve
+            .value_of("from-traces")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| config.tarpaulin_dir());
+        let address = format!(
+            "{}:{}",
+            serve.value_of("host").unwrap_or("127.0.0.1"),
+            serve.value_of("port").unwrap_or("8080")
+        );
+        return report::serve_results(config, &dir, &address).map_err(|e| e.to_string());
+    }
+    if let Some(report) = args.subcommand_matches("report") {
+        let config = &config.0[0];
+        let path = report
+            .value_of("from-traces")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| config.tarpaulin_dir());
+        return report::report_saved_results(config, &path).map_err(|e| e.to_string());
+    }
+    if args.subcommand_matches("self-test").is_some() {
+        return match self_test::run(&config.0[0]) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err("Self test failed".to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+    }
+
+    if args.is_present("dry-run") {
+        return dry_run(&config.0).map_err(|e| e.to_string());
+    }
 
     trace!("Debug mode activated");
     // Since this is the last function we run and don't do any error mitigations (other than
@@ -0,0 +1,563 @@
+//! `--dry-run`, works out what a run would do from the configs without
+//! building or tracing anything. Cargo metadata is read to find the packages
+//! and their targets and the source tree is walked to find the files that
+//! would be analysed, so mistakes in a config can be caught before a long
+//! coverage job starts.
+use crate::config::{Config, OutputFormat, RunType};
+use crate::errors::RunError;
+use crate::report;
+use crate::source_analysis::{source_files, TargetCfg};
+use cargo_metadata::{Metadata, MetadataCommand, Package};
+use serde::Serialize;
+use std::fs;
+use std::path::PathBuf;
+
+/// A target of a package which would be built
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct PlannedTarget {
+    pub run_type: RunType,
+    pub kind: String,
+    pub name: String,
+}
+
+/// A package which would be built and the targets traced for it
+#[derive(Clone, Debug, Serialize)]
+pub struct PlannedPackage {
+    pub name: String,
+    pub targets: Vec<PlannedTarget>,
+}
+
+/// A report which would be generated and the file it's written to
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct PlannedReport {
+    pub format: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+}
+
+/// What would be done for a single config
+#[derive(Clone, Debug, Serialize)]
+pub struct ConfigPlan {
+    pub name: String,
+    /// Why the config would be skipped because of its environment conditions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<String>,
+    pub manifest: PathBuf,
+    pub root: PathBuf,
+    /// Target triple built for, the host if `None`
+    pub target: Option<String>,
+    pub run_types: Vec<RunType>,
+    pub packages: Vec<PlannedPackage>,
+    /// Source files which would be analysed, relative to the project root
+    pub source_files: Vec<PathBuf>,
+    /// Source files excluded with `--exclude-files`
+    pub excluded_files: Vec<PathBuf>,
+    /// Problems which would stop the run or make its results wrong
+    pub errors: Vec<String>,
+}
+
+/// The work a run of the configs would do
+#[derive(Clone, Debug, Serialize)]
+pub struct Plan {
+    pub configs: Vec<ConfigPlan>,
+    /// Reports generated from the combined results
+    pub reports: Vec<PlannedReport>,
+    /// Problems with the reports
+    pub report_errors: Vec<String>,
+}
+
+impl Plan {
+    /// Plans the run of the configs, `report_config` is the config used for
+    /// the reports
+    pub fn new(configs: &[Config], report_config: Option<&Config>) -> Self {
+        let configs = traced_configs(configs)
+            .into_iter()
+            .map(|(config, skipped)| ConfigPlan::new(config, skipped))
+            .collect();
+        let (reports, report_errors) = match report_config {
+            Some(config) => planned_reports(config),
+            None => (vec![], vec![]),
+        };
+        Self {
+            configs,
+            reports,
+            report_errors,
+        }
+    }
+
+    /// All the problems found, prefixed with the name of the config
+    pub fn errors(&self) -> Vec<String> {
+        let mut errors = vec![];
+        for config in &self.configs {
+            for e in &config.errors {
+                if config.name.is_empty() {
+                    errors.push(e.clone());
+                } else {
+                    errors.push(format!("{}: {}", config.name, e));
+                }
+            }
+        }
+        errors.extend(self.report_errors.iter().cloned());
+        errors
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.errors().is_empty()
+    }
+
+    /// Prints the plan in the style of the console report, with `verbose` the
+    /// source files which would be analysed are listed as well as counted
+    pub fn print(&self, verbose: bool) {
+        for config in &self.configs {
+            if config.name.is_empty() {
+                println!("|| Plan:");
+            } else {
+                println!("|| Plan for config {}:", config.name);
+            }
+            if let Some(ref reason) = config.skipped {
+                println!("|| Skipped as {}", reason);
+            }
+            println!("|| Manifest: {}", config.manifest.display());
+            println!("|| Target: {}", config.target.as_deref().unwrap_or("host"));
+            let run_types = config
+                .run_types
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            println!("|| Run types: {}", run_types.join(", "));
+            for package in &config.packages {
+                println!("|| Package {}:", package.name);
+                for t in &package.targets {
+                    println!("||   {} {} ({})", t.kind, t.name, t.run_type);
+                }
+            }
+            println!(
+                "|| {} source files analysed under {}",
+                config.source_files.len(),
+                config.root.display()
+            );
+            if verbose {
+                for file in &config.source_files {
+                    println!("||   {}", file.display());
+                }
+            }
+            if !config.excluded_files.is_empty() {
+                println!("|| Excluded by --exclude-files:");
+                for file in &config.excluded_files {
+                    println!("||   {}", file.display());
+                }
+            }
+        }
+        println!("|| Reports:");
+        for r in &self.reports {
+            match r.path {
+                Some(ref path) => println!("||   {}: {}", r.format, path.display()),
+                None => println!("||   {}", r.format),
+            }
+        }
+        let errors = self.errors();
+        if !errors.is_empty() {
+            println!("|| Problems:");
+            for e in &errors {
+                println!("||   {}", e);
+            }
+        }
+    }
+}
+
+impl ConfigPlan {
+    /// Plans a config, `skipped` is why it's skipped as from `traced_configs`
+    pub fn new(config: &Config, skipped: Option<String>) -> Self {
+        let mut plan = Self {
+            name: config.name.clone(),
+            skipped,
+            manifest: config.manifest.clone(),
+            root: config
+                .manifest
+                .parent()
+                .map(PathBuf::from)
+                .unwrap_or_default(),
+            target: config.target.clone(),
+            run_types: config.run_types.clone(),
+            packages: vec![],
+            source_files: vec![],
+            excluded_files: vec![],
+            errors: vec![],
+        };
+        match MetadataCommand::new()
+            .manifest_path(&config.manifest)
+            .exec()
+        {
+            Ok(metadata) => {
+                plan.root = metadata.workspace_root.clone();
+                let (packages, errors) = selected_packages(config, &metadata);
+                plan.errors.extend(errors);
+                let bins = if config.run_types.contains(&RunType::Bins) {
+                    select_bins(config, &metadata).unwrap_or_else(|e| {
+                        plan.errors.push(e.to_string());
+                        None
+                    })
+                } else {
+                    None
+                };
+                plan.packages = packages
+                    .into_iter()
+                    .map(|p| PlannedPackage {
+                        name: p.name.clone(),
+                        targets: planned_targets(p, &config.run_types, bins.as_deref()),
+                    })
+                    .collect();
+            }
+            Err(e) => plan.errors.push(format!(
+                "Couldn't read the cargo metadata of {}: {}",
+                config.manifest.display(),
+                e
+            )),
+        }
+        if let Some(ref target) = config.target {
+            if !config.count_all_targets && TargetCfg::query(Some(target)).is_none() {
+                plan.errors
+                    .push(format!("Target {} isn't supported by rustc", target));
+            }
+        }
+        for file in source_files(&plan.root) {
+            let relative = file.strip_prefix(&plan.root).unwrap_or(&file).to_path_buf();
+            if config.excludes_file(&file) {
+                plan.excluded_files.push(relative);
+            } else {
+                plan.source_files.push(relative);
+            }
+        }
+        if plan.source_files.is_empty() && plan.errors.is_empty() {
+            plan.errors.push(format!(
+                "No source files would be analysed under {}",
+                plan.root.display()
+            ));
+        }
+        plan
+    }
+}
+
+/// The configs which are traced in the order they're run, each with the
+/// reason it's skipped if its environment conditions aren't met. The `report`
+/// config only has the settings for the reports so isn't traced.
+pub(crate) fn traced_configs(configs: &[Config]) -> Vec<(&Config, Option<String>)> {
+    configs
+        .iter()
+        .filter(|c| c.name != "report")
+        .map(|c| (c, c.skip_reason()))
+        .collect()
+}
+
+/// The workspace packages that would be built. These are the `--packages` if
+/// given, otherwise every member with `--workspace`, `--exclude` or a virtual
+/// manifest and just the root package if not, less the `--exclude` packages.
+/// As cargo only takes `--exclude` with `--workspace` it implies it. Packages
+/// named in either list which aren't in the workspace are errors.
+pub(crate) fn selected_packages<'a>(
+    config: &Config,
+    metadata: &'a Metadata,
+) -> (Vec<&'a Package>, Vec<String>) {
+    let members = metadata
+        .packages
+        .iter()
+        .filter(|p| metadata.workspace_members.contains(&p.id))
+        .collect::<Vec<_>>();
+    let mut errors = vec![];
+    for name in config.packages.iter().chain(config.exclude.iter()) {
+        if !members.iter().any(|p| &p.name == name) {
+            errors.push(format!("Package {} isn't in the workspace", name));
+        }
+    }
+    let manifest = config
+        .manifest
+        .canonicalize()
+        .unwrap_or_else(|_| config.manifest.clone());
+    let root = members.iter().find(|p| p.manifest_path == manifest);
+    let selected = members
+        .iter()
+        .filter(|p| {
+            if !config.packages.is_empty() {
+                config.packages.contains(&p.name)
+            } else if config.all || root.is_none() || !config.exclude.is_empty() {
+                true
+            } else {
+                root.map(|r| r.id == p.id).unwrap_or(false)
+            }
+        })
+        .filter(|p| !config.exclude.contains(&p.name))
+        .copied()
+        .collect();
+    (selected, errors)
+}
+
+/// The bin targets the `Bins` run type runs, `None` for all of them. Named
+/// bins must be a target of one of the selected packages, with
+/// `--default-bin-only` the `default-run` bin of each package is used or its
+/// only bin if it has one.
+pub(crate) fn select_bins(
+    config: &Config,
+    metadata: &Metadata,
+) -> Result<Option<Vec<String>>, RunError> {
+    let (packages, _) = selected_packages(config, metadata);
+    let bins_of = |package: &Package| {
+        package
+            .targets
+            .iter()
+            .filter(|t| t.kind.iter().any(|k| k == "bin"))
+            .map(|t| t.name.clone())
+            .collect::<Vec<_>>()
+    };
+    let mut available = packages.iter().flat_map(|p| bins_of(p)).collect::<Vec<_>>();
+    available.sort();
+    if !config.bins.is_empty() {
+        if let Some(name) = config.bins.iter().find(|b| !available.contains(b)) {
+            return Err(RunError::Packages(format!(
+                "No bin target named {}, available bins: {}",
+                name,
+                available.join(", ")
+            )));
+        }
+        Ok(Some(config.bins.clone()))
+    } else if config.default_bin_only {
+        let mut selected = vec![];
+        for package in &packages {
+            let bins = bins_of(package);
+            match (default_run(package), bins.as_slice()) {
+                (Some(name), _) => selected.push(name),
+                (None, []) => {}
+                (None, [bin]) => selected.push(bin.clone()),
+                (None, _) => {
+                    return Err(RunError::Packages(format!(
+                        "{} has several bins and no default-run, choose them with --bin, available bins: {}",
+                        package.name,
+                        bins.join(", ")
+                    )))
+                }
+            }
+        }
+        Ok(Some(selected))
+    } else {
+        Ok(None)
+    }
+}
+
+/// The `default-run` key of the package's manifest
+fn default_run(package: &Package) -> Option<String> {
+    let manifest = fs::read_to_string(&package.manifest_path).ok()?;
+    let manifest = manifest.parse::<toml::Value>().ok()?;
+    manifest
+        .get("package")?
+        .get("default-run")?
+        .as_str()
+        .map(ToString::to_string)
+}
+
+/// Whether a target of the kind is built for the run type
+fn builds_kind(run_type: RunType, kind: &str) -> bool {
+    let is_lib = match kind {
+        "lib" | "rlib" | "dylib" | "cdylib" | "staticlib" | "proc-macro" => true,
+        _ => false,
+    };
+    match run_type {
+        RunType::Tests => is_lib || kind == "bin" || kind == "test",
+        RunType::Doctests => is_lib,
+        RunType::Benchmarks => kind == "bench",
+        RunType::Examples => kind == "example",
+        RunType::Bins => kind == "bin",
+    }
+}
+
+/// The targets of the package which the run types build, `bins` are the bin
+/// targets of the `Bins` run type if not all of them, see `select_bins`
+pub fn planned_targets(
+    package: &Package,
+    run_types: &[RunType],
+    bins: Option<&[String]>,
+) -> Vec<PlannedTarget> {
+    let mut targets = vec![];
+    for run_type in run_types {
+        for target in &package.targets {
+            let unselected_bin = bins.map_or(false, |b| !b.contains(&target.name));
+            if *run_type == RunType::Bins && unselected_bin {
+                continue;
+            }
+            if let Some(kind) = target.kind.iter().find(|k| builds_kind(*run_type, k)) {
+                targets.push(PlannedTarget {
+                    run_type: *run_type,
+                    kind: kind.clone(),
+                    name: target.name.clone(),
+                });
+            }
+        }
+    }
+    targets
+}
+
+/// The reports which would be generated for the config and the problems with
+/// them, such as formats which aren't registered
+pub fn planned_reports(config: &Config) -> (Vec<PlannedReport>, Vec<String>) {
+    let mut reports = vec![PlannedReport {
+        format: OutputFormat::Stdout.to_string(),
+        path: None,
+    }];
+    let mut errors = vec![];
+    for format in report::requested_formats(config) {
+        match report::get_report(format) {
+            Some(r) => reports.push(PlannedReport {
+                format: format.to_string(),
+                path: report::output_path(config, format, &*r),
+            }),
+            None => errors.push(report::unknown_format(format.name())),
+        }
+    }
+    if let Err(e) = report::check_stdout_formats(config) {
+        errors.push(e.to_string());
+    }
+    if config.is_coveralls() {
+        reports.push(PlannedReport {
+            format: "Coveralls".to_string(),
+            path: None,
+        });
+    }
+    if let Some(ref history) = config.append_history {
+        reports.push(PlannedReport {
+            format: "History".to_string(),
+            path: Some(history.clone()),
+        });
+    }
+    let output_dir = config.output_dir();
+    if output_dir.exists() && !output_dir.is_dir() {
+        errors.push(format!(
+            "Output directory {} isn't a directory",
+            output_dir.display()
+        ));
+    }
+    (reports, errors)
+}
+
+/// Prints the plan for the configs, as JSON if the report config generates a
+/// JSON report. Returns an error listing the problems if the plan isn't valid.
+pub fn dry_run(configs: &[Config], report_config: Option<&Config>) -> Result<(), RunError> {
+    let plan = Plan::new(configs, report_config);
+    let json = report_config
+        .map(|c| c.generate.contains(&OutputFormat::Json))
+        .unwrap_or(false);
+    if json {
+        let out = serde_json::to_string_pretty(&plan).map_err(|e| RunError::Json(e.to_string()))?;
+        println!("{}", out);
+    } else {
+        plan.print(report_config.map(|c| c.verbose).unwrap_or(false));
+    }
+    if plan.is_valid() {
+        Ok(())
+    } else {
+        Err(RunError::Plan(plan.errors().join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn target_kinds_per_run_type() {
+        assert!(builds_kind(RunType::Tests, "lib"));
+        assert!(builds_kind(RunType::Tests, "bin"));
+        assert!(builds_kind(RunType::Tests, "test"));
+        assert!(builds_kind(RunType::Tests, "proc-macro"));
+        assert!(!builds_kind(RunType::Tests, "bench"));
+        assert!(!builds_kind(RunType::Tests, "custom-build"));
+        assert!(builds_kind(RunType::Doctests, "rlib"));
+        assert!(!builds_kind(RunType::Doctests, "bin"));
+        assert!(builds_kind(RunType::Benchmarks, "bench"));
+        assert!(builds_kind(RunType::Examples, "example"));
+        assert!(!builds_kind(RunType::Examples, "lib"));
+    }
+
+    #[test]
+    fn report_config_not_traced() {
+        let mut report = Config::default();
+        report.name = "report".to_string();
+        let mut skipped = Config::default();
+        skipped.name = "skipped".to_string();
+        skipped.required_env = vec!["TARPAULIN_PLAN_UNSET".to_string()];
+        let configs = vec![Config::default(), report, skipped];
+        let traced = traced_configs(&configs);
+        assert_eq!(traced.len(), 2);
+        assert_eq!(traced[0].1, None);
+        assert_eq!(traced[1].0.name, "skipped");
+        assert_eq!(
+            traced[1].1.as_deref(),
+            Some("TARPAULIN_PLAN_UNSET is not set")
+        );
+    }
+
+    #[test]
+    fn reports_and_their_files() {
+        let mut config = Config::default();
+        config.manifest = PathBuf::from("/project/Cargo.toml");
+        config.output_directory = Some(PathBuf::from("out"));
+        config.generate = vec![
+            OutputFormat::Stdout,
+            OutputFormat::Lcov,
+            OutputFormat::Html,
+            OutputFormat::Custom("protobuf".to_string()),
+        ];
+        config.append_history = Some(PathBuf::from("history.jsonl"));
+        let (reports, errors) = planned_reports(&config);
+        let formats = reports
+            .iter()
+            .map(|r| r.format.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(formats, vec!["Stdout", "Lcov", "Html", "History"]);
+        assert_eq!(
+            reports[1].path,
+            Some(PathBuf::from("/project/out/lcov.info"))
+        );
+        assert_eq!(
+            reports[2].path,
+            Some(PathBuf::from("/project/out/tarpaulin-report.html"))
+        );
+        assert_eq!(
+            errors,
+            vec![
+                "no report format is registered for protobuf, `--out help` lists the formats"
+                    .to_string()
+            ]
+        );
+
+        // Repeats are only generated once
+        config.generate = vec![
+            OutputFormat::Lcov,
+            OutputFormat::Github,
+            OutputFormat::Github,
+        ];
+        let (reports, errors) = planned_reports(&config);
+        assert_eq!(reports.len(), 4);
+        assert!(errors.is_empty());
+
+        config.generate.push(OutputFormat::Teamcity);
+        let (_, errors) = planned_reports(&config);
+        assert_eq!(
+            errors,
+            vec![
+                "Output formats Github, Teamcity all print to stdout and can't be used in the same run"
+                    .to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn output_directory_must_be_a_directory() {
+        let file = env::temp_dir().join("tarpaulin-plan-not-a-dir");
+        std::fs::write(&file, "").unwrap();
+        let mut config = Config::default();
+        config.output_directory = Some(file.clone());
+        let (_, errors) = planned_reports(&config);
+        let _ = std::fs::remove_file(&file);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].ends_with("isn't a directory"));
+    }
+}
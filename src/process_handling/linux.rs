@@ -1,11 +1,16 @@
+use crate::config::types::{Affinity, Stdin};
 use crate::errors::*;
 use crate::ptrace_control::*;
 use nix::errno::Errno;
-use nix::libc::{c_int, c_long};
+use nix::fcntl::{open, OFlag};
+use nix::libc::{c_int, c_long, STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO};
 use nix::sched::*;
+use nix::sys::stat::Mode;
 use nix::unistd::*;
 use nix::Error;
 use std::ffi::{CStr, CString};
+use std::os::unix::io::RawFd;
+use std::path::Path;
 
 #[cfg(any(target_arch = "x86", target_arch = "x86_64", target_arch = "arm"))]
 type Persona = c_long;
@@ -42,11 +47,66 @@ fn disable_aslr() -> nix::Result<i32> {
     }
 }
 
-pub fn limit_affinity() -> nix::Result<()> {
-    let mut cpu_set = CpuSet::new();
-    cpu_set.set(0)?;
+/// Chooses the processor each of the `jobs` tracers is pinned to, `None` means
+/// it isn't pinned. `Auto` only pins when there's a single tracer as pinning
+/// parallel tracers to one processor serialises them, `Pinned` gives each tracer
+/// a different processor (wrapping round if there are more tracers than
+/// processors) and `Free` never pins.
+pub fn select_cpus(mode: Affinity, available: &[usize], jobs: usize) -> Vec<Option<usize>> {
+    match mode {
+        _ if available.is_empty() => vec![None; jobs],
+        Affinity::Auto if jobs == 1 => vec![Some(available[0])],
+        Affinity::Pinned => (0..jobs)
+            .map(|i| Some(available[i % available.len()]))
+            .collect(),
+        _ => vec![None; jobs],
+    }
+}
+
+/// Pins the process to a processor according to the affinity mode. Tests are
+/// traced one at a time so there is only ever one tracer.
+pub fn limit_affinity(mode: Affinity) -> nix::Result<()> {
     let this = Pid::this();
-    sched_setaffinity(this, &cpu_set)
+    let current = sched_getaffinity(this)?;
+    let available = (0..CpuSet::count())
+        .filter(|i| current.is_set(*i).unwrap_or(false))
+        .collect::<Vec<_>>();
+    if let Some(Some(cpu)) = select_cpus(mode, &available, 1).first() {
+        let mut cpu_set = CpuSet::new();
+        cpu_set.set(*cpu)?;
+        sched_setaffinity(this, &cpu_set)
+    } else {
+        Ok(())
+    }
+}
+
+/// Replaces the stdin of the current process, called in the forked child
+/// before exec. Tests inherit an open stdin by default which can leave them
+/// blocked on a read forever in non-interactive environments such as CI.
+pub fn redirect_stdin(stdin: &Stdin) -> Result<(), RunError> {
+    let path = match stdin {
+        Stdin::Inherit => return Ok(()),
+        Stdin::Null => Path::new("/dev/null"),
+        Stdin::File(path) => path.as_path(),
+    };
+    let fd = open(path, OFlag::O_RDONLY, Mode::empty()).map_err(|e| {
+        RunError::TestRuntime(format!(
+            "Failed to open {} for stdin: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    dup2(fd, STDIN_FILENO)?;
+    close(fd)?;
+    Ok(())
+}
+
+/// Replaces stdout with the write end of the pipe the test's output is
+/// captured through
+pub fn redirect_stdout(fd: RawFd) -> Result<(), RunError> {
+    dup2(fd, STDOUT_FILENO)?;
+    close(fd)?;
+    Ok(())
 }
 
 pub fn execute(program: CString, argv: &[CString], envar: &[CString]) -> Result<(), RunError> {
@@ -60,3 +120,54 @@ pub fn execute(program: CString, argv: &[CString], envar: &[CString]) -> Result<
         .map_err(|_| RunError::Internal)
         .map(|_| ())
 }
+
+/// Runs the program without tracing it, used to time the test untraced for
+/// `--measure-overhead`. The output is discarded so it's only shown for the
+/// traced run.
+pub fn execute_untraced(
+    program: CString,
+    argv: &[CString],
+    envar: &[CString],
+) -> Result<(), RunError> {
+    disable_aslr().map_err(|e| RunError::TestRuntime(format!("ASLR disable failed: {}", e)))?;
+    let null = open("/dev/null", OFlag::O_WRONLY, Mode::empty())?;
+    dup2(null, STDOUT_FILENO)?;
+    dup2(null, STDERR_FILENO)?;
+    close(null)?;
+
+    let arg_ref = argv.iter().map(|x| x.as_ref()).collect::<Vec<&CStr>>();
+    let env_ref = envar.iter().map(|x| x.as_ref()).collect::<Vec<&CStr>>();
+    execve(&program, &arg_ref, &env_ref)
+        .map_err(|_| RunError::Internal)
+        .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_affinity() {
+        assert_eq!(select_cpus(Affinity::Auto, &[2, 3], 1), vec![Some(2)]);
+        assert_eq!(select_cpus(Affinity::Auto, &[2, 3], 2), vec![None, None]);
+        assert_eq!(select_cpus(Affinity::Auto, &[], 1), vec![None]);
+    }
+
+    #[test]
+    fn pinned_affinity() {
+        assert_eq!(
+            select_cpus(Affinity::Pinned, &[0, 1, 2], 2),
+            vec![Some(0), Some(1)]
+        );
+        assert_eq!(
+            select_cpus(Affinity::Pinned, &[4, 6], 3),
+            vec![Some(4), Some(6), Some(4)]
+        );
+    }
+
+    #[test]
+    fn free_affinity() {
+        assert_eq!(select_cpus(Affinity::Free, &[0, 1], 1), vec![None]);
+        assert_eq!(select_cpus(Affinity::Free, &[0, 1], 2), vec![None, None]);
+    }
+}
@@ -4,19 +4,54 @@ use nix::sys::ptrace::*;
 use nix::sys::signal::Signal;
 use nix::unistd::Pid;
 use nix::{Error, Result};
+use std::fs;
 use std::ptr;
 
 const RIP: u8 = 128;
 
-pub fn trace_children(pid: Pid) -> Result<()> {
+fn trace_options() -> Options {
     //TODO need to check support.
-    let options: Options = Options::PTRACE_O_TRACESYSGOOD
+    // EXITKILL makes sure the test doesn't outlive tarpaulin if it's killed
+    Options::PTRACE_O_EXITKILL
+        | Options::PTRACE_O_TRACESYSGOOD
         | Options::PTRACE_O_TRACEEXEC
         | Options::PTRACE_O_TRACEEXIT
         | Options::PTRACE_O_TRACECLONE
         | Options::PTRACE_O_TRACEFORK
-        | Options::PTRACE_O_TRACEVFORK;
-    setoptions(pid, options)
+        | Options::PTRACE_O_TRACEVFORK
+}
+
+pub fn trace_children(pid: Pid) -> Result<()> {
+    setoptions(pid, trace_options())
+}
+
+/// Ids of the threads of the process listed in `/proc/<pid>/task`, empty if
+/// it can't be read
+pub fn thread_ids(pid: Pid) -> Vec<Pid> {
+    let entries = match fs::read_dir(format!("/proc/{}/task", pid)) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().to_str().and_then(|s| s.parse().ok()))
+        .map(Pid::from_raw)
+        .collect()
+}
+
+/// Traces a thread that wasn't created under tracing with the same options as
+/// the test. The thread isn't stopped, from then on its traps and events are
+/// reported like those of any other thread.
+pub fn seize_thread(tid: Pid) -> Result<()> {
+    let ret = unsafe {
+        libc::ptrace(
+            Request::PTRACE_SEIZE as RequestType,
+            libc::pid_t::from(tid),
+            ptr::null_mut::<c_void>(),
+            trace_options().bits() as *mut c_void,
+        )
+    };
+    Errno::result(ret).map(drop)
 }
 
 pub fn detach_child(pid: Pid) -> Result<()> {
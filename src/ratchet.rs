@@ -0,0 +1,236 @@
+//! Coverage floors stored in a file committed alongside the project,
+//! `--ratchet FILE`. A run fails if coverage drops below a floor and when it
+//! improves on a floor by more than a margin the file is updated, so the floor
+//! only ever moves up without numbers being maintained by hand.
+use crate::config::Config;
+use crate::errors::{write_error, RunError};
+use crate::report::package_percentages;
+use crate::traces::TraceMap;
+use log::info;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// Coverage floors as percentages ranging from 0.0-100.0
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Floors {
+    pub total: f64,
+    pub packages: BTreeMap<String, f64>,
+}
+
+/// Coverage that has dropped below its floor
+#[derive(Clone, Debug, PartialEq)]
+pub struct Regression {
+    /// Package with the regression or `None` for the total
+    pub package: Option<String>,
+    pub floor: f64,
+    pub current: f64,
+}
+
+impl fmt::Display for Regression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.package {
+            Some(ref p) => write!(f, "{}", p)?,
+            None => write!(f, "total")?,
+        }
+        write!(f, " {:.2}% < {:.2}%", self.current, self.floor)
+    }
+}
+
+/// Rounds down to the precision stored in the file, so a floor is never above
+/// the coverage it was taken from
+fn to_precision(x: f64) -> f64 {
+    (x * 100.0).floor() / 100.0
+}
+
+impl Floors {
+    /// Floors for the current results
+    pub fn current(config: &Config, traces: &TraceMap) -> Self {
+        Self {
+            total: traces.coverage_percentage() * 100.0,
+            packages: package_percentages(config, traces),
+        }
+    }
+
+    /// Reads the floors from the file, a missing file has no floors
+    pub fn load(path: &Path) -> Result<Self, RunError> {
+        match fs::read_to_string(path) {
+            Ok(s) => toml::from_str(&s).map_err(|e| {
+                RunError::Ratchet(format!("Invalid ratchet file {}: {}", path.display(), e))
+            }),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(RunError::Ratchet(format!(
+                "Failed to read {}: {}",
+                path.display(),
+                e
+            ))),
+        }
+    }
+
+    /// Renders the file contents. Packages are sorted and every value has the
+    /// same precision so an update only changes the lines of floors that moved
+    pub fn render(&self) -> String {
+        let mut result = format!("total = {:.2}\n", self.total);
+        if !self.packages.is_empty() {
+            result.push_str("\n[packages]\n");
+            for (name, floor) in &self.packages {
+                result.push_str(&format!("{} = {:.2}\n", name, floor));
+            }
+        }
+        result
+    }
+
+    /// Compares the current coverage against the floors. Coverage more than
+    /// `tolerance` below a floor is a regression. If there are none the floors
+    /// that were beaten by more than `margin` are raised and packages without
+    /// a floor get one, returning `None` if nothing changed.
+    pub fn ratchet(
+        &self,
+        current: &Floors,
+        margin: f64,
+        tolerance: f64,
+    ) -> Result<Option<Floors>, Vec<Regression>> {
+        let mut regressions = vec![];
+        if current.total < self.total - tolerance {
+            regressions.push(Regression {
+                package: None,
+                floor: self.total,
+                current: current.total,
+            });
+        }
+        // Packages which have been removed or renamed are skipped
+        for (name, floor) in &self.packages {
+            match current.packages.get(name) {
+                Some(c) if *c < floor - tolerance => regressions.push(Regression {
+                    package: Some(name.clone()),
+                    floor: *floor,
+                    current: *c,
+                }),
+                _ => {}
+            }
+        }
+        if !regressions.is_empty() {
+            return Err(regressions);
+        }
+
+        let raise = |floor: f64, current: f64| {
+            if current > floor + margin {
+                to_precision(current)
+            } else {
+                floor
+            }
+        };
+        let mut result = self.clone();
+        result.total = raise(self.total, current.total);
+        for (name, c) in &current.packages {
+            result
+                .packages
+                .entry(name.clone())
+                .and_modify(|f| *f = raise(*f, *c))
+                .or_insert_with(|| to_precision(*c));
+        }
+        if result == *self {
+            Ok(None)
+        } else {
+            Ok(Some(result))
+        }
+    }
+}
+
+/// Checks the results against the ratchet file, updating it if coverage has
+/// improved enough
+pub fn check(path: &Path, config: &Config, traces: &TraceMap) -> Result<(), RunError> {
+    let floors = Floors::load(path)?;
+    let current = Floors::current(config, traces);
+    match floors.ratchet(&current, config.ratchet_margin, config.ratchet_tolerance) {
+        Ok(Some(raised)) => {
+            fs::write(path, raised.render())
+                .map_err(|e| RunError::Ratchet(write_error(path, &e)))?;
+            info!(
+                "Raised coverage floor in {} to {:.2}%",
+                path.display(),
+                raised.total
+            );
+            Ok(())
+        }
+        Ok(None) => Ok(()),
+        Err(regressions) => Err(RunError::Ratchet(
+            regressions
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn floors(total: f64, packages: &[(&str, f64)]) -> Floors {
+        Floors {
+            total,
+            packages: packages.iter().map(|(n, f)| (n.to_string(), *f)).collect(),
+        }
+    }
+
+    #[test]
+    fn improve_then_regress() {
+        let start = Floors::default();
+        let first = floors(60.456, &[("a", 70.0), ("b", 50.0)]);
+        let raised = start.ratchet(&first, 0.5, 0.0).unwrap().unwrap();
+        assert_eq!(raised, floors(60.45, &[("a", 70.0), ("b", 50.0)]));
+
+        // Improvements within the margin don't touch the file
+        let small = floors(60.8, &[("a", 70.2), ("b", 50.0)]);
+        assert_eq!(raised.ratchet(&small, 0.5, 0.0), Ok(None));
+
+        let better = floors(65.0, &[("a", 70.2), ("b", 55.555)]);
+        let raised = raised.ratchet(&better, 0.5, 0.0).unwrap().unwrap();
+        assert_eq!(raised, floors(65.0, &[("a", 70.0), ("b", 55.55)]));
+
+        let worse = floors(64.0, &[("a", 70.4), ("b", 55.6)]);
+        let regressions = raised.ratchet(&worse, 0.5, 0.0).unwrap_err();
+        assert_eq!(
+            regressions,
+            vec![Regression {
+                package: None,
+                floor: 65.0,
+                current: 64.0
+            }]
+        );
+        // Within the tolerance it's not a regression
+        assert_eq!(raised.ratchet(&worse, 0.5, 1.0), Ok(None));
+
+        let package_drop = floors(65.0, &[("a", 60.0)]);
+        let regressions = raised.ratchet(&package_drop, 0.5, 0.0).unwrap_err();
+        assert_eq!(regressions.len(), 1);
+        assert_eq!(regressions[0].package, Some("a".to_string()));
+    }
+
+    #[test]
+    fn new_packages_get_floor() {
+        let start = floors(50.0, &[("a", 50.0)]);
+        let current = floors(50.0, &[("a", 50.0), ("c", 12.345)]);
+        let raised = start.ratchet(&current, 0.5, 0.0).unwrap().unwrap();
+        assert_eq!(raised.packages.get("c"), Some(&12.34));
+    }
+
+    #[test]
+    fn stable_rendering() {
+        let f = floors(80.5, &[("zeta", 1.0), ("alpha-beta", 99.99)]);
+        let rendered = f.render();
+        assert_eq!(
+            rendered,
+            "total = 80.50\n\n[packages]\nalpha-beta = 99.99\nzeta = 1.00\n"
+        );
+        let parsed: Floors = toml::from_str(&rendered).unwrap();
+        assert_eq!(parsed.render(), rendered);
+        assert_eq!(floors(12.0, &[]).render(), "total = 12.00\n");
+    }
+}
@@ -0,0 +1,354 @@
+//! Coverage of lines grouped by how long ago they were last changed,
+//! `--age-buckets 30,90,365`, so recently written code can be tested first.
+//! Each traced file is blamed with git and its coverable lines are counted in
+//! the first bucket their age fits in, lines older than every bucket are
+//! `older` and lines of files which can't be blamed, such as files outside of
+//! the repository, are `unknown`. Lines changed in the working tree count as
+//! changed now. Blaming is slow so files are blamed on `--age-analysis-jobs`
+//! threads and the results are cached in `target/tarpaulin/blame-cache.json`
+//! by the hash of the file's contents.
+use crate::config::Config;
+use crate::errors::write_error;
+use crate::traces::{amount_coverable, amount_covered, TraceMap};
+use chrono::offset::Utc;
+use git2::{BlameOptions, ObjectType, Oid, Patch, Repository};
+use log::{debug, warn};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Coverage of the lines last changed in a range of days
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AgeBucket {
+    pub label: String,
+    /// Oldest a line in the bucket can be in days, `None` for the older and
+    /// unknown buckets
+    pub max_days: Option<u64>,
+    pub covered: usize,
+    pub coverable: usize,
+    pub percent: f64,
+}
+
+/// Commit time of each line of the files, in seconds since the epoch
+type LineTimes = BTreeMap<PathBuf, Vec<i64>>;
+
+/// Commit time of each line of a file, lines changed in the working tree are
+/// `now`. The second value is false if there were any, as those times
+/// shouldn't be cached. The committed file is blamed and its lines are mapped
+/// to the contents with a diff.
+fn blame_file(
+    repo: &Repository,
+    path: &Path,
+    content: &[u8],
+    now: i64,
+) -> Result<(Vec<i64>, bool), git2::Error> {
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| git2::Error::from_str("repository has no working directory"))?;
+    let relative = path
+        .strip_prefix(workdir)
+        .map_err(|_| git2::Error::from_str("file is outside of the repository"))?;
+    let head = repo.head()?.peel_to_commit()?;
+    let blob = repo.find_blob(head.tree()?.get_path(relative)?.id())?;
+    let mut options = BlameOptions::new();
+    options.newest_commit(head.id());
+    let blame = repo.blame_file(relative, Some(&mut options))?;
+    let mut committed_times = vec![];
+    for hunk in blame.iter() {
+        let time = hunk.final_signature().when().seconds();
+        committed_times.extend(iter::repeat(time).take(hunk.lines_in_hunk()));
+    }
+
+    let patch = Patch::from_blob_and_buffer(&blob, Some(relative), content, Some(relative), None)?;
+    let mut times = vec![];
+    let mut old_line = 0;
+    let mut committed = true;
+    let unchanged_until = |times: &mut Vec<i64>, old_line: &mut usize, new_line: usize| {
+        while times.len() < new_line && *old_line < committed_times.len() {
+            times.push(committed_times[*old_line]);
+            *old_line += 1;
+        }
+    };
+    for h in 0..patch.num_hunks() {
+        let (hunk, lines) = patch.hunk(h)?;
+        unchanged_until(
+            &mut times,
+            &mut old_line,
+            (hunk.new_start() as usize).saturating_sub(1),
+        );
+        for l in 0..lines {
+            match patch.line_in_hunk(h, l)?.origin() {
+                '+' => {
+                    times.push(now);
+                    committed = false;
+                }
+                '-' => old_line += 1,
+                ' ' => {
+                    times.push(committed_times.get(old_line).copied().unwrap_or(now));
+                    old_line += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+    unchanged_until(&mut times, &mut old_line, usize::MAX);
+    Ok((times, committed))
+}
+
+fn cache_path(config: &Config) -> PathBuf {
+    config.tarpaulin_dir().join("blame-cache.json")
+}
+
+fn load_cache(config: &Config) -> HashMap<String, Vec<i64>> {
+    fs::read(cache_path(config))
+        .ok()
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or_default()
+}
+
+/// Saves the blames of this run's files, files which weren't traced this run
+/// are dropped so the cache doesn't grow with every edit
+fn save_cache(config: &Config, cache: &HashMap<String, Vec<i64>>) {
+    let path = cache_path(config);
+    let result = fs::create_dir_all(config.tarpaulin_dir())
+        .and_then(|_| fs::write(&path, serde_json::to_vec(cache).unwrap_or_default()));
+    if let Err(e) = result {
+        debug!("Couldn't save blame cache: {}", write_error(&path, &e));
+    }
+}
+
+/// Blames the files on up to `jobs` threads using the cached blames where the
+/// contents haven't changed, files which can't be blamed are left out
+fn line_times(config: &Config, files: &[&PathBuf], now: i64) -> LineTimes {
+    let cache = load_cache(config);
+    let mut result = LineTimes::new();
+    let mut used = HashMap::new();
+    let mut queue = vec![];
+    for path in files {
+        let content = match fs::read(path) {
+            Ok(c) => c,
+            Err(_) => continue,
+        };
+        let hash = match Oid::hash_object(ObjectType::Blob, &content) {
+            Ok(h) => h.to_string(),
+            Err(_) => continue,
+        };
+        match cache.get(&hash) {
+            Some(times) => {
+                result.insert(path.to_path_buf(), times.clone());
+                used.insert(hash, times.clone());
+            }
+            None => queue.push((path.to_path_buf(), content, hash)),
+        }
+    }
+    let root = config.root();
+    let jobs = config.age_analysis_jobs.max(1).min(queue.len());
+    let queue = Arc::new(Mutex::new(queue));
+    let (sender, receiver) = mpsc::channel();
+    let workers = (0..jobs)
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let sender = sender.clone();
+            let root = root.clone();
+            thread::spawn(move || {
+                let repo = match Repository::discover(&root) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        debug!("Can't blame files: {}", e);
+                        return;
+                    }
+                };
+                loop {
+                    let next = queue.lock().unwrap_or_else(|e| e.into_inner()).pop();
+                    let (path, content, hash) = match next {
+                        Some(n) => n,
+                        None => break,
+                    };
+                    match blame_file(&repo, &path, &content, now) {
+                        Ok(blame) => {
+                            let _ = sender.send((path, hash, blame));
+                        }
+                        Err(e) => debug!("Can't blame {}: {}", path.display(), e.message()),
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    drop(sender);
+    for (path, hash, (times, committed)) in receiver {
+        if committed {
+            used.insert(hash, times.clone());
+        }
+        result.insert(path, times);
+    }
+    for worker in workers {
+        if worker.join().is_err() {
+            warn!("Blaming files for --age-buckets failed");
+        }
+    }
+    save_cache(config, &used);
+    result
+}
+
+fn bucket(label: String, max_days: Option<u64>) -> AgeBucket {
+    AgeBucket {
+        label,
+        max_days,
+        covered: 0,
+        coverable: 0,
+        percent: 0.0,
+    }
+}
+
+/// Counts the coverable lines in the bucket for their age, `days` are the
+/// upper bounds of the buckets in ascending order
+fn bucket_lines(days: &[u64], traces: &TraceMap, times: &LineTimes, now: i64) -> Vec<AgeBucket> {
+    let mut buckets = vec![];
+    let mut previous = None;
+    for max in days {
+        let label = match previous {
+            None => format!("last {} days", max),
+            Some(p) => format!("{}-{} days", p + 1, max),
+        };
+        buckets.push(bucket(label, Some(*max)));
+        previous = Some(*max);
+    }
+    let older = buckets.len();
+    buckets.push(bucket(
+        match previous {
+            Some(p) => format!("older than {} days", p),
+            None => "older".to_string(),
+        },
+        None,
+    ));
+    buckets.push(bucket("unknown".to_string(), None));
+    let unknown = older + 1;
+    for (path, file_traces) in traces.iter() {
+        let file_times = times.get(path);
+        for trace in file_traces {
+            let time = (trace.line as usize)
+                .checked_sub(1)
+                .and_then(|i| file_times.and_then(|t| t.get(i)));
+            let index = match time {
+                Some(time) => {
+                    let age = ((now - time).max(0) / SECONDS_PER_DAY) as u64;
+                    days.iter().position(|max| age <= *max).unwrap_or(older)
+                }
+                None => unknown,
+            };
+            buckets[index].covered += amount_covered(&[trace]);
+            buckets[index].coverable += amount_coverable(&[trace]);
+        }
+    }
+    for bucket in &mut buckets {
+        if bucket.coverable > 0 {
+            bucket.percent = 100.0 * bucket.covered as f64 / bucket.coverable as f64;
+        }
+    }
+    buckets
+}
+
+/// Coverage of the lines by how long ago they were last changed
+pub fn coverage(config: &Config, traces: &TraceMap) -> Vec<AgeBucket> {
+    let mut days = config.age_buckets.clone();
+    days.sort_unstable();
+    days.dedup();
+    let now = Utc::now().timestamp();
+    let times = line_times(config, &traces.files(), now);
+    bucket_lines(&days, traces, &times, now)
+}
+
+pub fn print_age_buckets(buckets: &[AgeBucket]) {
+    println!("|| Tested/Total Lines by Age:");
+    // Every file can usually be blamed so an empty unknown bucket is noise
+    let shown = match buckets.split_last() {
+        Some((unknown, known)) if unknown.coverable == 0 => known,
+        _ => buckets,
+    };
+    for bucket in shown {
+        println!(
+            "|| {}: {}/{} ({:.2}%)",
+            bucket.label, bucket.covered, bucket.coverable, bucket.percent
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traces::Trace;
+    use git2::{IndexAddOption, Signature, Time};
+    use std::env;
+
+    fn traces(path: &Path, lines: &[(u64, u64)]) -> TraceMap {
+        let mut traces = TraceMap::new();
+        for (line, hits) in lines {
+            traces.add_trace(path, Trace::test_line(*line, *hits));
+        }
+        traces
+    }
+
+    #[test]
+    fn lines_bucketed_by_age() {
+        let now = 1_000 * SECONDS_PER_DAY;
+        let path = Path::new("/project/src/lib.rs");
+        let mut traces = traces(path, &[(1, 1), (2, 0), (3, 1), (4, 0)]);
+        traces.merge(&self::traces(Path::new("/elsewhere/lib.rs"), &[(1, 1)]));
+        let mut times = LineTimes::new();
+        let days_ago = |d| now - d * SECONDS_PER_DAY;
+        times.insert(
+            path.to_path_buf(),
+            vec![days_ago(1), days_ago(30), days_ago(60), days_ago(400)],
+        );
+        let buckets = bucket_lines(&[30, 90], &traces, &times, now);
+        let summary = buckets
+            .iter()
+            .map(|b| (b.label.as_str(), b.covered, b.coverable))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("last 30 days", 1, 2),
+                ("31-90 days", 1, 1),
+                ("older than 90 days", 0, 1),
+                ("unknown", 1, 1),
+            ]
+        );
+        assert_eq!(buckets[0].percent, 50.0);
+    }
+
+    #[test]
+    fn uncommitted_lines_are_new() {
+        let dir = env::temp_dir().join(format!("tarpaulin-age-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let repo = Repository::init(&dir).unwrap();
+        let file = repo.workdir().unwrap().join("lib.rs");
+        fs::write(&file, "fn a() {}\nfn b() {}\nfn c() {}\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(&["*"], IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let time = Time::new(1_500_000_000, 0);
+        let signature = Signature::new("tarpaulin", "tarpaulin@example.com", &time).unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "base", &tree, &[])
+            .unwrap();
+
+        let content = b"fn a() {}\nfn b() { changed() }\nfn c() {}\n";
+        let now = Utc::now().timestamp();
+        let (times, committed) = blame_file(&repo, &file, content, now).unwrap();
+        assert_eq!(times, vec![1_500_000_000, now, 1_500_000_000]);
+        assert!(!committed);
+        assert!(blame_file(&repo, Path::new("/not/in/repo.rs"), content, now).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
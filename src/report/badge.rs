@@ -0,0 +1,156 @@
+//! SVG coverage badge in the style of shields.io, `--out Badge` or `--badge
+//! <PATH>`, so a badge can be served without a third party service. The
+//! percentage is rounded to one decimal place and nothing else about the run
+//! is in the file, so a committed badge only changes when the coverage does.
+use crate::config::Config;
+use crate::errors::RunError;
+use crate::report::compress::create_report_file;
+use crate::report::{Report, RunMetadata};
+use crate::traces::TraceMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// Name of the report file in the output directory
+pub const FILE_NAME: &str = "tarpaulin-badge.svg";
+
+const LABEL: &str = "coverage";
+/// Width of the label side, the same as shields.io uses for "coverage"
+const LABEL_WIDTH: u32 = 61;
+
+const RED: &str = "#e05d44";
+const YELLOW: &str = "#dfb317";
+const GREEN: &str = "#4c1";
+
+/// Badge report, `--out Badge`
+pub struct BadgeReport;
+
+impl Report for BadgeReport {
+    fn render(
+        &self,
+        config: &Config,
+        traces: &TraceMap,
+        _metadata: &RunMetadata,
+    ) -> Result<(), RunError> {
+        let mut file = create_report_file(config, FILE_NAME)?;
+        file.write_all(badge(traces, config).as_bytes())?;
+        file.finish().map_err(RunError::from)
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        Some(FILE_NAME)
+    }
+
+    fn description(&self) -> &str {
+        "SVG badge of the total coverage"
+    }
+}
+
+/// Writes the badge to the path given with `--badge`
+pub fn export(path: &Path, traces: &TraceMap, config: &Config) -> Result<(), RunError> {
+    fs::write(path, badge(traces, config))
+        .map_err(|e| RunError::OutFormat(format!("Failed to write {}: {}", path.display(), e)))
+}
+
+/// Colour of the badge, red below the `badge-yellow` percentage, green from
+/// `badge-green` and yellow in between
+fn colour(percent: f64, config: &Config) -> &'static str {
+    if percent < config.badge_yellow {
+        RED
+    } else if percent < config.badge_green {
+        YELLOW
+    } else {
+        GREEN
+    }
+}
+
+/// Approximate width of text in 11px Verdana, it only has to be close enough
+/// for the text to fit
+fn text_width(text: &str) -> u32 {
+    let width = text
+        .chars()
+        .map(|c| match c {
+            '.' => 3.9,
+            '%' => 11.9,
+            _ => 7.0,
+        })
+        .sum::<f64>();
+    width.ceil() as u32
+}
+
+fn badge(traces: &TraceMap, config: &Config) -> String {
+    // Rounded first so the colour agrees with the percentage shown
+    let percent = if traces.total_coverable() == 0 {
+        0.0
+    } else {
+        (traces.coverage_percentage() * 1000.0).round() / 10.0
+    };
+    let value = format!("{:.1}%", percent);
+    let colour = colour(percent, config);
+    let value_width = text_width(&value) + 10;
+    let width = LABEL_WIDTH + value_width;
+    let label_x = f64::from(LABEL_WIDTH) / 2.0;
+    let value_x = f64::from(LABEL_WIDTH) + f64::from(value_width) / 2.0;
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="20" role="img" aria-label="{label}: {value}">
+<title>{label}: {value}</title>
+<linearGradient id="s" x2="0" y2="100%"><stop offset="0" stop-color="#bbb" stop-opacity=".1"/><stop offset="1" stop-opacity=".1"/></linearGradient>
+<clipPath id="r"><rect width="{width}" height="20" rx="3" fill="#fff"/></clipPath>
+<g clip-path="url(#r)"><rect width="{label_width}" height="20" fill="#555"/><rect x="{label_width}" width="{value_width}" height="20" fill="{colour}"/><rect width="{width}" height="20" fill="url(#s)"/></g>
+<g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11">
+<text x="{label_x}" y="15" fill="#010101" fill-opacity=".3">{label}</text><text x="{label_x}" y="14">{label}</text>
+<text x="{value_x}" y="15" fill="#010101" fill-opacity=".3">{value}</text><text x="{value_x}" y="14">{value}</text>
+</g>
+</svg>
+"##,
+        width = width,
+        label = LABEL,
+        label_width = LABEL_WIDTH,
+        label_x = label_x,
+        value = value,
+        value_width = value_width,
+        value_x = value_x,
+        colour = colour,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traces::Trace;
+
+    fn traces(covered: u64, coverable: u64) -> TraceMap {
+        let mut traces = TraceMap::new();
+        for line in 0..coverable {
+            traces.add_trace(
+                Path::new("/project/src/lib.rs"),
+                Trace::test_line(line + 1, if line < covered { 1 } else { 0 }),
+            );
+        }
+        traces
+    }
+
+    #[test]
+    fn rounded_and_coloured() {
+        let config = Config::default();
+        let svg = badge(&traces(2, 3), &config);
+        assert!(svg.contains(r#"aria-label="coverage: 66.7%""#));
+        assert!(svg.contains(YELLOW));
+        assert_eq!(svg, badge(&traces(2, 3), &config));
+
+        assert!(badge(&traces(1, 3), &config).contains(RED));
+        assert!(badge(&traces(4, 5), &config).contains(GREEN));
+        // 79.96% is shown as 80.0% so it's green too
+        assert!(badge(&traces(1999, 2500), &config).contains("80.0%"));
+        assert!(badge(&traces(1999, 2500), &config).contains(GREEN));
+    }
+
+    #[test]
+    fn configured_thresholds() {
+        let mut config = Config::default();
+        config.badge_yellow = 70.0;
+        config.badge_green = 95.0;
+        assert!(badge(&traces(2, 3), &config).contains(RED));
+        assert!(badge(&traces(4, 5), &config).contains(YELLOW));
+    }
+}
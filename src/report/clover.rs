@@ -0,0 +1,206 @@
+//! Clover XML report, `--out Clover`, for tools such as Atlassian Bamboo. Files
+//! are grouped into a package per crate and each coverable line is a `stmt`
+//! line with its hit count. Files are named relative to the root like the
+//! other reports, or by their absolute path with `--clover-absolute-paths` as
+//! some tools only match one or the other.
+use crate::config::Config;
+use crate::errors::RunError;
+use crate::report::compress::create_report_file;
+use crate::report::{files_by_package, Report, RunMetadata};
+use crate::traces::{CoverageStat, TraceMap};
+use chrono::offset::Utc;
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, Event};
+use quick_xml::Writer;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Name of the report file in the output directory
+pub const FILE_NAME: &str = "clover.xml";
+
+/// Clover report, `--out Clover`
+pub struct CloverReport;
+
+impl Report for CloverReport {
+    fn render(
+        &self,
+        config: &Config,
+        traces: &TraceMap,
+        _metadata: &RunMetadata,
+    ) -> Result<(), RunError> {
+        let file = create_report_file(config, FILE_NAME)?;
+        let mut writer = Writer::new(file);
+        write_document(&mut writer, config, traces, Utc::now().timestamp())
+            .map_err(|e| RunError::OutFormat(format!("Failed to write Clover report: {}", e)))?;
+        writer.into_inner().finish().map_err(RunError::from)
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        Some(FILE_NAME)
+    }
+
+    fn description(&self) -> &str {
+        "Clover XML"
+    }
+}
+
+/// Name of the directory of the project root
+fn root_name(config: &Config) -> String {
+    config
+        .root()
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// The files of each package, files outside of every package are put in one
+/// named after the root directory
+fn packages<'a>(config: &Config, traces: &'a TraceMap) -> BTreeMap<String, Vec<&'a PathBuf>> {
+    let mut packages = files_by_package(config, traces);
+    let owned = packages
+        .values()
+        .flatten()
+        .cloned()
+        .collect::<BTreeSet<_>>();
+    let others = traces
+        .files()
+        .into_iter()
+        .filter(|f| !owned.contains(f))
+        .collect::<Vec<_>>();
+    if !others.is_empty() {
+        packages
+            .entry(root_name(config))
+            .or_default()
+            .extend(others);
+    }
+    packages
+}
+
+fn metrics<W: Write>(
+    writer: &mut Writer<W>,
+    statements: usize,
+    covered: usize,
+    extra: &[(&str, usize)],
+) -> Result<(), quick_xml::Error> {
+    let mut metrics = BytesStart::borrowed(b"metrics", 7);
+    metrics.push_attribute(("statements", statements.to_string().as_str()));
+    metrics.push_attribute(("coveredstatements", covered.to_string().as_str()));
+    // Only statements are recorded so they're all the elements
+    metrics.push_attribute(("elements", statements.to_string().as_str()));
+    metrics.push_attribute(("coveredelements", covered.to_string().as_str()));
+    for (name, value) in extra {
+        metrics.push_attribute((*name, value.to_string().as_str()));
+    }
+    writer.write_event(Event::Empty(metrics))?;
+    Ok(())
+}
+
+fn write_document<W: Write>(
+    writer: &mut Writer<W>,
+    config: &Config,
+    traces: &TraceMap,
+    timestamp: i64,
+) -> Result<(), quick_xml::Error> {
+    let timestamp = timestamp.to_string();
+    let packages = packages(config, traces);
+    writer.write_event(Event::Decl(BytesDecl::new(b"1.0", Some(b"UTF-8"), None)))?;
+    let mut coverage = BytesStart::borrowed(b"coverage", 8);
+    coverage.push_attribute(("generated", timestamp.as_str()));
+    coverage.push_attribute(("clover", "4.4.1"));
+    writer.write_event(Event::Start(coverage))?;
+    let mut project = BytesStart::borrowed(b"project", 7);
+    project.push_attribute(("timestamp", timestamp.as_str()));
+    project.push_attribute(("name", root_name(config).as_str()));
+    writer.write_event(Event::Start(project))?;
+    metrics(
+        writer,
+        traces.total_coverable(),
+        traces.total_covered(),
+        &[
+            ("files", traces.files().len()),
+            ("packages", packages.len()),
+        ],
+    )?;
+    for (name, files) in &packages {
+        let mut package = BytesStart::borrowed(b"package", 7);
+        package.push_attribute(("name", name.as_str()));
+        writer.write_event(Event::Start(package))?;
+        metrics(
+            writer,
+            files.iter().map(|f| traces.coverable_in_path(f)).sum(),
+            files.iter().map(|f| traces.covered_in_path(f)).sum(),
+            &[("files", files.len())],
+        )?;
+        for file in files {
+            let path = if config.clover_absolute_paths {
+                file.display().to_string()
+            } else {
+                config.display_path(file)
+            };
+            let name = file
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let mut start = BytesStart::borrowed(b"file", 4);
+            start.push_attribute(("name", name.as_str()));
+            start.push_attribute(("path", path.as_str()));
+            writer.write_event(Event::Start(start))?;
+            metrics(
+                writer,
+                traces.coverable_in_path(file),
+                traces.covered_in_path(file),
+                &[],
+            )?;
+            let mut lines = traces.get_child_traces(file);
+            lines.sort_by_key(|t| t.line);
+            for trace in lines {
+                if let CoverageStat::Line(hits) = trace.stats {
+                    let mut line = BytesStart::borrowed(b"line", 4);
+                    line.push_attribute(("num", trace.line.to_string().as_str()));
+                    line.push_attribute(("type", "stmt"));
+                    line.push_attribute(("count", hits.to_string().as_str()));
+                    writer.write_event(Event::Empty(line))?;
+                }
+            }
+            writer.write_event(Event::End(BytesEnd::borrowed(b"file")))?;
+        }
+        writer.write_event(Event::End(BytesEnd::borrowed(b"package")))?;
+    }
+    writer.write_event(Event::End(BytesEnd::borrowed(b"project")))?;
+    writer.write_event(Event::End(BytesEnd::borrowed(b"coverage")))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traces::Trace;
+    use std::path::Path;
+
+    fn render(config: &Config) -> String {
+        let mut traces = TraceMap::new();
+        for (line, hits) in &[(4, 3), (2, 0), (3, 1)] {
+            traces.add_trace(
+                Path::new("/project/src/lib.rs"),
+                Trace::test_line(*line, *hits),
+            );
+        }
+        let mut writer = Writer::new(vec![]);
+        write_document(&mut writer, config, &traces, 1_600_000_000).unwrap();
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn lines_and_metrics() {
+        let mut config = Config::default();
+        config.manifest = PathBuf::from("/project/Cargo.toml");
+        let xml = render(&config);
+        assert!(xml.starts_with(r#"<?xml version="1.0" encoding="UTF-8"?><coverage generated="1600000000" clover="4.4.1"><project timestamp="1600000000" name="project">"#));
+        assert!(xml.contains(r#"<metrics statements="3" coveredstatements="2" elements="3" coveredelements="2" files="1" packages="1"/><package name="project">"#));
+        assert!(xml.contains(r#"<file name="lib.rs" path="src/lib.rs"><metrics statements="3" coveredstatements="2" elements="3" coveredelements="2"/><line num="2" type="stmt" count="0"/><line num="3" type="stmt" count="1"/><line num="4" type="stmt" count="3"/></file>"#));
+        assert!(xml.ends_with("</package></project></coverage>"));
+
+        config.clover_absolute_paths = true;
+        assert!(render(&config).contains(r#"<file name="lib.rs" path="/project/src/lib.rs">"#));
+    }
+}
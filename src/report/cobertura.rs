@@ -38,11 +38,10 @@
 ///   </packages>
 /// </coverage>
 /// ```
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::error;
 use std::fmt;
-use std::fs::File;
-use std::io::{Cursor, Write};
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -53,9 +52,37 @@ use quick_xml::{
 
 use chrono::offset::Utc;
 
-use crate::config::Config;
+use crate::config::{Config, PathFormat};
+use crate::errors::RunError;
+use crate::report::compress::create_report_file;
+use crate::report::{directories, files_by_package};
 use crate::traces::{CoverageStat, Trace, TraceMap};
 
+/// Name of the report file in the output directory
+pub const FILE_NAME: &str = "cobertura.xml";
+
+/// Cobertura XML report, `--out Xml`
+pub struct CoberturaReport;
+
+impl super::Report for CoberturaReport {
+    fn render(
+        &self,
+        config: &Config,
+        traces: &TraceMap,
+        _metadata: &super::RunMetadata,
+    ) -> Result<(), RunError> {
+        report(traces, config).map_err(RunError::XML)
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        Some(FILE_NAME)
+    }
+
+    fn description(&self) -> &str {
+        "Cobertura XML, also named Cobertura"
+    }
+}
+
 pub fn report(traces: &TraceMap, config: &Config) -> Result<(), Error> {
     let result = Report::render(config, traces)?;
     result.export(config)
@@ -97,20 +124,22 @@ impl Report {
         let timestamp = Utc::now().timestamp();
         let sources = render_sources(config);
         let packages = render_packages(config, traces);
-        let mut line_rate = 0.0;
         let mut branch_rate = 0.0;
 
         if packages.len() > 0 {
-            line_rate = packages.iter().map(|x| x.line_rate).sum::<f64>() / packages.len() as f64;
             branch_rate =
                 packages.iter().map(|x| x.branch_rate).sum::<f64>() / packages.len() as f64;
         }
+        // The rates are from the same totals as the console summary so the
+        // reports agree
+        let lines_covered = traces.total_covered();
+        let lines_valid = traces.total_coverable();
 
         Ok(Report {
             timestamp: timestamp,
-            lines_covered: traces.total_covered(),
-            lines_valid: traces.total_coverable(),
-            line_rate: line_rate,
+            lines_covered,
+            lines_valid,
+            line_rate: line_rate(lines_covered, lines_valid),
             branches_covered: 0,
             branches_valid: 0,
             branch_rate: branch_rate,
@@ -120,11 +149,10 @@ impl Report {
     }
 
     pub fn export(&self, config: &Config) -> Result<(), Error> {
-        let file_path = config.output_directory.join("cobertura.xml");
-        let mut file =
-            File::create(file_path).map_err(|e| Error::ExportError(quick_xml::Error::Io(e)))?;
+        let file = create_report_file(config, FILE_NAME)
+            .map_err(|e| Error::ExportError(quick_xml::Error::Io(e)))?;
 
-        let mut writer = Writer::new(Cursor::new(vec![]));
+        let mut writer = Writer::new(file);
         writer
             .write_event(Event::Decl(BytesDecl::new(b"1.0", None, None)))
             .map_err(|e| Error::ExportError(e))?;
@@ -163,8 +191,9 @@ impl Report {
             .write_event(Event::End(BytesEnd::borrowed(cov_tag)))
             .map_err(|e| Error::ExportError(e))?;
 
-        let result = writer.into_inner().into_inner();
-        file.write_all(&result)
+        writer
+            .into_inner()
+            .finish()
             .map_err(|e| Error::ExportError(quick_xml::Error::Io(e)))
     }
 
@@ -283,7 +312,10 @@ impl Report {
 }
 
 fn render_sources(config: &Config) -> Vec<PathBuf> {
-    vec![config.get_base_dir()]
+    match config.path_format {
+        PathFormat::Relative => vec![config.root()],
+        PathFormat::Legacy => vec![config.get_base_dir()],
+    }
 }
 
 #[derive(Debug)]
@@ -295,30 +327,54 @@ struct Package {
     classes: Vec<Class>,
 }
 
+/// Covered lines over coverable lines, 0 if nothing is coverable
+fn line_rate(covered: usize, coverable: usize) -> f64 {
+    if coverable == 0 {
+        0.0
+    } else {
+        covered as f64 / coverable as f64
+    }
+}
+
+/// A package named after each crate with the files it owns, files outside of
+/// every crate are in a package for their directory. Ordered by name.
 fn render_packages(config: &Config, traces: &TraceMap) -> Vec<Package> {
-    let dirs: HashSet<&Path> = traces
-        .files()
-        .into_iter()
-        .filter_map(|x| x.parent())
-        .collect();
+    let crates = files_by_package(config, traces);
+    let owned = crates.values().flatten().cloned().collect::<BTreeSet<_>>();
+    // Only the files directly in a directory are in its package, files in
+    // subdirectories are in their own package
+    let mut dirs: BTreeMap<&Path, Vec<&PathBuf>> = BTreeMap::new();
+    for file in traces.files().into_iter().filter(|f| !owned.contains(f)) {
+        if let Some(dir) = file.parent() {
+            dirs.entry(dir).or_default().push(file);
+        }
+    }
 
-    dirs.into_iter()
-        .map(|x| render_package(config, traces, x))
-        .collect()
+    let mut packages = crates
+        .iter()
+        .map(|(name, files)| render_package(config, traces, name.clone(), files))
+        .collect::<Vec<_>>();
+    packages.extend(dirs.into_iter().map(|(dir, files)| {
+        let mut name = config.display_path(dir);
+        if name.is_empty() {
+            name = directories::ROOT_DIR.to_string();
+        }
+        render_package(config, traces, name, &files)
+    }));
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+    packages
 }
 
-fn render_package(config: &Config, traces: &TraceMap, pkg: &Path) -> Package {
-    let name = config.strip_base_dir(pkg).to_str().unwrap().to_string();
-
-    let line_cover = traces.covered_in_path(pkg) as f64;
-    let line_rate = line_cover / (traces.coverable_in_path(pkg) as f64);
+fn render_package(config: &Config, traces: &TraceMap, name: String, files: &[&PathBuf]) -> Package {
+    let covered = files.iter().map(|x| traces.covered_in_path(x)).sum();
+    let coverable = files.iter().map(|x| traces.coverable_in_path(x)).sum();
 
     Package {
         name: name,
-        line_rate: line_rate,
+        line_rate: line_rate(covered, coverable),
         branch_rate: 0.0,
         complexity: 0.0,
-        classes: render_classes(config, traces, pkg),
+        classes: render_classes(config, traces, files),
     }
 }
 
@@ -333,11 +389,9 @@ struct Class {
     methods: Vec<Method>,
 }
 
-fn render_classes(config: &Config, traces: &TraceMap, pkg: &Path) -> Vec<Class> {
-    traces
-        .files()
+fn render_classes(config: &Config, traces: &TraceMap, files: &[&PathBuf]) -> Vec<Class> {
+    files
         .iter()
-        .filter(|x| x.parent() == Some(pkg))
         .map(|x| render_class(config, traces, x))
         .collect()
 }
@@ -357,10 +411,9 @@ fn render_class(config: &Config, traces: &TraceMap, file: &Path) -> Class {
         .unwrap_or_default()
         .to_string();
 
-    let file_name = config.strip_base_dir(file).to_str().unwrap().to_string();
+    let file_name = config.display_path(file);
 
-    let covered = traces.covered_in_path(file) as f64;
-    let line_rate = covered / traces.coverable_in_path(file) as f64;
+    let line_rate = line_rate(traces.covered_in_path(file), traces.coverable_in_path(file));
     let lines = traces
         .get_child_traces(file)
         .iter()
@@ -435,3 +488,47 @@ struct Condition {
 enum ConditionType {
     Jump,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_lines(traces: &mut TraceMap, file: &Path, hits: &[u64]) {
+        for (i, hits) in hits.iter().enumerate() {
+            traces.add_trace(file, Trace::test_line(i as u64 + 1, *hits));
+        }
+    }
+
+    #[test]
+    fn rates_agree_with_console() {
+        // Without the project metadata files are grouped by directory
+        let mut config = Config::default();
+        config.manifest = PathBuf::from("/project/Cargo.toml");
+        let root = config.root();
+        let mut traces = TraceMap::new();
+        add_lines(&mut traces, &root.join("build.rs"), &[1, 0, 0]);
+        add_lines(&mut traces, &root.join("src/lib.rs"), &[1, 1, 1, 0]);
+        add_lines(&mut traces, &root.join("src/net/tcp.rs"), &[0, 0]);
+
+        let report = Report::render(&config, &traces).unwrap();
+        assert_eq!(report.lines_covered, traces.total_covered());
+        assert_eq!(report.lines_valid, traces.total_coverable());
+        assert_eq!(report.line_rate, traces.coverage_percentage());
+        let packages = report
+            .packages
+            .iter()
+            .map(|p| (p.name.as_str(), p.line_rate))
+            .collect::<Vec<_>>();
+        // The src package doesn't include src/net
+        assert_eq!(
+            packages,
+            vec![(".", 1.0 / 3.0), ("src", 0.75), ("src/net", 0.0)]
+        );
+        let classes = report.packages[1]
+            .classes
+            .iter()
+            .map(|c| (c.file_name.as_str(), c.line_rate))
+            .collect::<Vec<_>>();
+        assert_eq!(classes, vec![("src/lib.rs", 0.75)]);
+    }
+}
@@ -0,0 +1,416 @@
+//! Codecov report, `--out Codecov`, written to `codecov.json` in Codecov's
+//! JSON coverage format with the hits of each line. With `--codecov-upload` the
+//! results are also uploaded with Codecov's v4 upload API so their uploader
+//! script isn't needed. The commit and branch come from the CI's environment
+//! variables if it's a known one, otherwise from the git repository.
+use crate::config::Config;
+use crate::errors::RunError;
+use crate::report::compress::create_report_file;
+use crate::report::{Report, RunMetadata};
+use crate::traces::{CoverageStat, TraceMap};
+use curl::easy::{Easy, List};
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// Name of the report file in the output directory
+pub const FILE_NAME: &str = "codecov.json";
+
+/// Where reports are uploaded to
+const CODECOV_URL: &str = "https://codecov.io";
+
+/// Times a request is retried after a transient failure
+const RETRIES: u32 = 2;
+
+/// Codecov report, `--out Codecov`
+pub struct CodecovReport;
+
+impl Report for CodecovReport {
+    fn render(
+        &self,
+        config: &Config,
+        traces: &TraceMap,
+        _metadata: &RunMetadata,
+    ) -> Result<(), RunError> {
+        export(traces, config)
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        Some(FILE_NAME)
+    }
+
+    fn description(&self) -> &str {
+        "Codecov JSON coverage format"
+    }
+}
+
+/// Hits of each line by file, Codecov reads the line numbers as strings
+#[derive(Serialize)]
+struct Coverage {
+    coverage: BTreeMap<String, BTreeMap<u64, u64>>,
+}
+
+fn coverage(coverage_data: &TraceMap, config: &Config) -> Coverage {
+    let coverage = coverage_data
+        .iter()
+        .map(|(path, traces)| {
+            let mut lines = BTreeMap::new();
+            for trace in traces {
+                if let CoverageStat::Line(hits) = trace.stats {
+                    *lines.entry(trace.line).or_insert(0) += hits;
+                }
+            }
+            (config.display_path(path), lines)
+        })
+        .collect();
+    Coverage { coverage }
+}
+
+pub fn export(coverage_data: &TraceMap, config: &Config) -> Result<(), RunError> {
+    let report = coverage(coverage_data, config);
+    let mut file = create_report_file(config, FILE_NAME)?;
+    serde_json::to_writer(&mut file, &report)?;
+    file.finish().map_err(RunError::from)
+}
+
+/// What Codecov is told about the upload
+#[derive(Debug, Default, PartialEq)]
+struct UploadInfo {
+    commit: String,
+    branch: Option<String>,
+    /// Codecov's name for the CI service
+    service: Option<&'static str>,
+    build: Option<String>,
+    pr: Option<String>,
+    /// Owner and name of the repository
+    slug: Option<String>,
+}
+
+impl UploadInfo {
+    /// Finds the commit and branch from the variables set by the CI service
+    /// running tarpaulin, `None` if it isn't one that's known
+    fn from_ci(var: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        if var("GITHUB_ACTIONS").is_some() {
+            let head_ref = var("GITHUB_HEAD_REF").filter(|r| !r.is_empty());
+            let pr = head_ref.as_ref().and_then(|_| {
+                var("GITHUB_REF")?
+                    .strip_prefix("refs/pull/")?
+                    .split('/')
+                    .next()
+                    .map(ToString::to_string)
+            });
+            let branch = head_ref.or_else(|| {
+                var("GITHUB_REF")?
+                    .strip_prefix("refs/heads/")
+                    .map(ToString::to_string)
+            });
+            Some(UploadInfo {
+                commit: var("GITHUB_SHA")?,
+                branch,
+                service: Some("github-actions"),
+                build: var("GITHUB_RUN_ID"),
+                pr,
+                slug: var("GITHUB_REPOSITORY"),
+            })
+        } else if var("TRAVIS").is_some() {
+            Some(UploadInfo {
+                commit: var("TRAVIS_COMMIT")?,
+                branch: var("TRAVIS_BRANCH"),
+                service: Some("travis"),
+                build: var("TRAVIS_JOB_ID"),
+                pr: var("TRAVIS_PULL_REQUEST").filter(|pr| pr != "false"),
+                slug: var("TRAVIS_REPO_SLUG"),
+            })
+        } else if var("GITLAB_CI").is_some() {
+            Some(UploadInfo {
+                commit: var("CI_COMMIT_SHA")?,
+                branch: var("CI_COMMIT_REF_NAME"),
+                service: Some("gitlab"),
+                build: var("CI_JOB_ID"),
+                pr: var("CI_MERGE_REQUEST_IID"),
+                slug: var("CI_PROJECT_PATH"),
+            })
+        } else if var("CIRCLECI").is_some() {
+            let slug = match (
+                var("CIRCLE_PROJECT_USERNAME"),
+                var("CIRCLE_PROJECT_REPONAME"),
+            ) {
+                (Some(user), Some(repo)) => Some(format!("{}/{}", user, repo)),
+                _ => None,
+            };
+            Some(UploadInfo {
+                commit: var("CIRCLE_SHA1")?,
+                branch: var("CIRCLE_BRANCH"),
+                service: Some("circleci"),
+                build: var("CIRCLE_BUILD_NUM"),
+                pr: var("CIRCLE_PR_NUMBER"),
+                slug,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Reads the commit and branch checked out in the repository containing
+    /// the manifest
+    fn from_git(manifest: &Path) -> Result<Self, String> {
+        let dir = manifest.parent().unwrap_or(manifest);
+        let repo = git2::Repository::discover(dir)
+            .map_err(|e| format!("failed to open git repository: {}", e))?;
+        let head = repo
+            .head()
+            .map_err(|e| format!("failed to get repository head: {}", e))?;
+        let commit = head
+            .peel_to_commit()
+            .map_err(|e| format!("failed to get commit: {}", e))?;
+        // A detached head has no branch
+        let branch = if head.is_branch() {
+            head.shorthand().map(ToString::to_string)
+        } else {
+            None
+        };
+        Ok(UploadInfo {
+            commit: commit.id().to_string(),
+            branch,
+            ..Default::default()
+        })
+    }
+
+    /// Query of the upload request
+    fn query(&self, token: &str) -> String {
+        let mut params = vec![
+            (
+                "package",
+                format!("tarpaulin-{}", env!("CARGO_PKG_VERSION")),
+            ),
+            ("token", token.to_string()),
+            ("commit", self.commit.clone()),
+        ];
+        let optional = [
+            ("branch", self.branch.clone()),
+            ("service", self.service.map(ToString::to_string)),
+            ("build", self.build.clone()),
+            ("pr", self.pr.clone()),
+            ("slug", self.slug.clone()),
+        ];
+        params.extend(
+            optional
+                .iter()
+                .filter_map(|(name, value)| Some((*name, value.clone()?))),
+        );
+        let mut easy = Easy::new();
+        params
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, easy.url_encode(value.as_bytes())))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+}
+
+/// Body of the upload, the report wrapped in the uploader's file markers
+fn upload_body(report: &str) -> String {
+    format!("# path={}\n{}\n<<<<<< EOF\n", FILE_NAME, report)
+}
+
+/// The URL of the report and the URL to upload it to from the response to
+/// the upload request, they're on the first two lines
+fn parse_upload_urls(response: &str) -> Option<(&str, &str)> {
+    let mut lines = response.lines().map(str::trim).filter(|l| !l.is_empty());
+    let report_url = lines.next()?;
+    let upload_url = lines.next()?;
+    Some((report_url, upload_url))
+}
+
+/// A failed request, retried if it might succeed next time
+enum RequestError {
+    Transient(String),
+    Failed(String),
+}
+
+/// Sends a request, returning the body of a successful response
+fn request(method: &str, url: &str, headers: &[&str], body: &[u8]) -> Result<String, RequestError> {
+    let transient = |e: curl::Error| RequestError::Transient(e.to_string());
+    let mut easy = Easy::new();
+    easy.url(url).map_err(transient)?;
+    easy.custom_request(method).map_err(transient)?;
+    easy.post_fields_copy(body).map_err(transient)?;
+    let mut list = List::new();
+    for header in headers {
+        list.append(header).map_err(transient)?;
+    }
+    easy.http_headers(list).map_err(transient)?;
+    let mut response = vec![];
+    {
+        let mut transfer = easy.transfer();
+        transfer
+            .write_function(|data| {
+                response.extend_from_slice(data);
+                Ok(data.len())
+            })
+            .map_err(transient)?;
+        transfer.perform().map_err(transient)?;
+    }
+    let code = easy.response_code().map_err(transient)?;
+    let response = String::from_utf8_lossy(&response).trim().to_string();
+    match code {
+        200..=299 => Ok(response),
+        429 | 500..=599 => Err(RequestError::Transient(format!("{} {}", code, response))),
+        _ => Err(RequestError::Failed(format!("{} {}", code, response))),
+    }
+}
+
+/// Sends a request, retrying transient failures a couple of times
+fn request_with_retries(
+    method: &str,
+    url: &str,
+    headers: &[&str],
+    body: &[u8],
+) -> Result<String, String> {
+    let mut attempt = 0;
+    loop {
+        match request(method, url, headers, body) {
+            Ok(response) => return Ok(response),
+            Err(RequestError::Transient(e)) if attempt < RETRIES => {
+                attempt += 1;
+                warn!("Codecov request failed, retrying: {}", e);
+                thread::sleep(Duration::from_secs(u64::from(attempt)));
+            }
+            Err(RequestError::Transient(e)) | Err(RequestError::Failed(e)) => return Err(e),
+        }
+    }
+}
+
+/// Uploads the results to Codecov, `--codecov-upload`
+pub fn upload(coverage_data: &TraceMap, config: &Config) -> Result<(), RunError> {
+    let token = config
+        .codecov_token
+        .clone()
+        .or_else(|| env::var("CODECOV_TOKEN").ok())
+        .ok_or_else(|| {
+            RunError::CovReport(
+                "No Codecov token, pass --codecov-token or set CODECOV_TOKEN".to_string(),
+            )
+        })?;
+    let info = match UploadInfo::from_ci(|name| env::var(name).ok()) {
+        Some(info) => info,
+        None => UploadInfo::from_git(&config.manifest).map_err(|e| {
+            RunError::CovReport(format!("Couldn't find the commit to upload for: {}", e))
+        })?,
+    };
+    let report = serde_json::to_string(&coverage(coverage_data, config))?;
+    let failed = |e| RunError::CovReport(format!("Codecov upload failed. {}", e));
+
+    info!("Uploading coverage for {} to Codecov", info.commit);
+    let url = format!("{}/upload/v4?{}", CODECOV_URL, info.query(&token));
+    let response =
+        request_with_retries("POST", &url, &["Accept: text/plain"], &[]).map_err(failed)?;
+    let (report_url, upload_url) = parse_upload_urls(&response)
+        .ok_or_else(|| failed(format!("unexpected response: {}", response)))?;
+    request_with_retries(
+        "PUT",
+        upload_url,
+        &["Content-Type: text/plain", "x-amz-acl: public-read"],
+        upload_body(&report).as_bytes(),
+    )
+    .map_err(failed)?;
+    info!("Coverage uploaded to Codecov: {}", report_url);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traces::Trace;
+    use std::collections::HashMap;
+
+    fn vars(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn line_hits_by_file() {
+        let mut config = Config::default();
+        config.manifest = Path::new("/project/Cargo.toml").to_path_buf();
+        let mut traces = TraceMap::new();
+        for (line, hits) in &[(3, 2), (10, 0), (3, 1)] {
+            traces.add_trace(
+                Path::new("/project/src/lib.rs"),
+                Trace::test_line(*line, *hits),
+            );
+        }
+        let json = serde_json::to_string(&coverage(&traces, &config)).unwrap();
+        assert_eq!(json, r#"{"coverage":{"src/lib.rs":{"3":3,"10":0}}}"#);
+        assert!(upload_body(&json).starts_with("# path=codecov.json\n{"));
+        assert!(upload_body(&json).ends_with("}\n<<<<<< EOF\n"));
+    }
+
+    #[test]
+    fn upload_info_from_ci() {
+        assert_eq!(UploadInfo::from_ci(vars(&[])), None);
+
+        let push = UploadInfo::from_ci(vars(&[
+            ("GITHUB_ACTIONS", "true"),
+            ("GITHUB_SHA", "abc123"),
+            ("GITHUB_REF", "refs/heads/main"),
+            ("GITHUB_HEAD_REF", ""),
+            ("GITHUB_RUN_ID", "42"),
+            ("GITHUB_REPOSITORY", "owner/repo"),
+        ]))
+        .unwrap();
+        assert_eq!(push.commit, "abc123");
+        assert_eq!(push.branch.as_deref(), Some("main"));
+        assert_eq!(push.pr, None);
+        assert_eq!(push.service, Some("github-actions"));
+
+        let pr = UploadInfo::from_ci(vars(&[
+            ("GITHUB_ACTIONS", "true"),
+            ("GITHUB_SHA", "def456"),
+            ("GITHUB_REF", "refs/pull/7/merge"),
+            ("GITHUB_HEAD_REF", "feature"),
+        ]))
+        .unwrap();
+        assert_eq!(pr.branch.as_deref(), Some("feature"));
+        assert_eq!(pr.pr.as_deref(), Some("7"));
+
+        let travis = UploadInfo::from_ci(vars(&[
+            ("TRAVIS", "true"),
+            ("TRAVIS_COMMIT", "0a1b"),
+            ("TRAVIS_BRANCH", "dev"),
+            ("TRAVIS_PULL_REQUEST", "false"),
+        ]))
+        .unwrap();
+        assert_eq!(travis.pr, None);
+        assert_eq!(
+            travis.query("secret token"),
+            format!(
+                "package=tarpaulin-{}&token=secret%20token&commit=0a1b&branch=dev&service=travis",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+
+        // The commit is required
+        assert_eq!(UploadInfo::from_ci(vars(&[("GITLAB_CI", "true")])), None);
+    }
+
+    #[test]
+    fn upload_urls_parsed() {
+        assert_eq!(
+            parse_upload_urls(
+                "https://codecov.io/github/o/r/commit/abc\nhttps://storage/put?sig=1\n"
+            ),
+            Some((
+                "https://codecov.io/github/o/r/commit/abc",
+                "https://storage/put?sig=1"
+            ))
+        );
+        assert_eq!(parse_upload_urls("Could not find a repository"), None);
+    }
+}
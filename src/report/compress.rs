@@ -0,0 +1,192 @@
+//! Optional compression of the report files written to the output directory,
+//! `--compress-output`. Reports are compressed as they're written so the
+//! uncompressed report is never held in memory. Files read back by tarpaulin
+//! are decompressed based on their magic bytes so it doesn't matter whether
+//! they were compressed when written.
+use crate::config::{Compression, Config};
+use crate::errors::with_write_context;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Writer for a report file which may be compressed. `finish` must be called
+/// once the report is written so the compressed stream is terminated. Errors
+/// say which file was being written.
+pub struct ReportWriter {
+    path: PathBuf,
+    inner: Inner,
+}
+
+enum Inner {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(zstd::stream::write::Encoder<BufWriter<File>>),
+}
+
+impl ReportWriter {
+    /// Finishes the compressed stream and flushes the file
+    pub fn finish(self) -> io::Result<()> {
+        let path = self.path;
+        let file = match self.inner {
+            Inner::Plain(w) => Ok(w),
+            Inner::Gzip(w) => w.finish(),
+            Inner::Zstd(w) => w.finish(),
+        };
+        file.and_then(|mut f| f.flush())
+            .map_err(|e| with_write_context(&path, e))
+    }
+}
+
+impl Write for ReportWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.inner {
+            Inner::Plain(ref mut w) => w.write(buf),
+            Inner::Gzip(ref mut w) => w.write(buf),
+            Inner::Zstd(ref mut w) => w.write(buf),
+        }
+        .map_err(|e| with_write_context(&self.path, e))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.inner {
+            Inner::Plain(ref mut w) => w.flush(),
+            Inner::Gzip(ref mut w) => w.flush(),
+            Inner::Zstd(ref mut w) => w.flush(),
+        }
+        .map_err(|e| with_write_context(&self.path, e))
+    }
+}
+
+/// Path of the report file with the extension of the compression added
+pub fn report_path(config: &Config, name: &str) -> PathBuf {
+    compressed_path(config.output_dir().join(name), config.compress_output)
+}
+
+/// The path with the extension of the compression added
+pub fn compressed_path(path: PathBuf, compression: Option<Compression>) -> PathBuf {
+    match compression {
+        Some(c) => {
+            let mut path = path.into_os_string();
+            path.push(".");
+            path.push(c.extension());
+            PathBuf::from(path)
+        }
+        None => path,
+    }
+}
+
+/// Creates the report file `name` in the output directory, compressing it if
+/// `--compress-output` is set
+pub fn create_report_file(config: &Config, name: &str) -> io::Result<ReportWriter> {
+    create_compressed_file(&report_path(config, name), config.compress_output)
+}
+
+/// Creates a file at the path, which should already have the extension of the
+/// compression
+pub fn create_compressed_file(
+    path: &Path,
+    compression: Option<Compression>,
+) -> io::Result<ReportWriter> {
+    let path = path.to_path_buf();
+    let file = File::create(&path).map_err(|e| with_write_context(&path, e))?;
+    let file = BufWriter::new(file);
+    let inner = match compression {
+        None => Inner::Plain(file),
+        Some(Compression::Gzip) => {
+            Inner::Gzip(GzEncoder::new(file, flate2::Compression::default()))
+        }
+        Some(Compression::Zstd) => Inner::Zstd(
+            zstd::stream::write::Encoder::new(file, 0).map_err(|e| with_write_context(&path, e))?,
+        ),
+    };
+    Ok(ReportWriter { path, inner })
+}
+
+/// Opens a file for reading, decompressing it if it starts with the gzip or
+/// zstd magic bytes
+pub fn open_maybe_compressed(path: &Path) -> io::Result<Box<dyn Read>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let magic = reader.fill_buf()?;
+    let (is_gzip, is_zstd) = (
+        magic.starts_with(&GZIP_MAGIC),
+        magic.starts_with(&ZSTD_MAGIC),
+    );
+    if is_gzip {
+        Ok(Box::new(GzDecoder::new(reader)))
+    } else if is_zstd {
+        Ok(Box::new(zstd::stream::read::Decoder::with_buffer(reader)?))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn round_trip(compression: Option<Compression>) {
+        let dir = env::temp_dir().join(format!("tarpaulin-compress-{:?}", compression));
+        fs::create_dir_all(&dir).unwrap();
+        let mut config = Config::default();
+        config.output_directory = Some(dir.clone());
+        config.compress_output = compression;
+
+        let mut writer = create_report_file(&config, "report.txt").unwrap();
+        for i in 0..1000 {
+            writeln!(writer, "line {}", i).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let path = report_path(&config, "report.txt");
+        let mut contents = String::new();
+        open_maybe_compressed(&path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents.lines().count(), 1000);
+        assert_eq!(contents.lines().last(), Some("line 999"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn uncompressed_round_trip() {
+        round_trip(None);
+    }
+
+    #[test]
+    fn gzip_round_trip() {
+        round_trip(Some(Compression::Gzip));
+    }
+
+    #[test]
+    fn zstd_round_trip() {
+        round_trip(Some(Compression::Zstd));
+    }
+
+    #[test]
+    fn compressed_extension() {
+        let mut config = Config::default();
+        config.output_directory = Some(PathBuf::from("/out"));
+        assert_eq!(
+            report_path(&config, "lcov.info"),
+            Path::new("/out/lcov.info")
+        );
+        config.compress_output = Some(Compression::Gzip);
+        assert_eq!(
+            report_path(&config, "tarpaulin-report.json"),
+            Path::new("/out/tarpaulin-report.json.gz")
+        );
+        config.compress_output = Some(Compression::Zstd);
+        assert_eq!(
+            report_path(&config, "cobertura.xml"),
+            Path::new("/out/cobertura.xml.zst")
+        );
+    }
+}
@@ -5,7 +5,7 @@ use coveralls_api::*;
 use log::{info, trace, warn};
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 fn get_git_info(manifest_path: &Path) -> Result<GitInfo, String> {
     let dir_path = manifest_path
@@ -84,7 +84,7 @@ pub fn export(coverage_data: &TraceMap, config: &Config) -> Result<(), RunError>
 
         let mut report = CoverallsReport::new(id);
         for file in &coverage_data.files() {
-            let rel_path = config.strip_base_dir(file);
+            let rel_path = PathBuf::from(config.display_path(file));
             let mut lines: HashMap<usize, usize> = HashMap::new();
             let fcov = coverage_data.get_child_traces(file);
 
@@ -124,7 +124,7 @@ pub fn export(coverage_data: &TraceMap, config: &Config) -> Result<(), RunError>
         if config.debug {
             if let Ok(text) = serde_json::to_string(&report) {
                 info!("Attempting to write coveralls report to coveralls.json");
-                let file_path = config.output_directory.join("coveralls.json");
+                let file_path = config.output_dir().join("coveralls.json");
                 let _ = fs::write(file_path, text);
             } else {
                 warn!("Failed to serialise coverage report");
@@ -0,0 +1,234 @@
+//! Change in coverage since a previous run, `--compare FILE` where the file is
+//! the JSON report of that run. Files are matched by their path in the
+//! reports so both runs need the same `--path-format`. Files which only one
+//! of the runs has are listed as new or deleted rather than compared, and
+//! `--max-coverage-drop` fails the run if the total dropped by more than the
+//! given percentage points.
+use crate::config::Config;
+use crate::errors::RunError;
+use crate::report::compress::open_maybe_compressed;
+use crate::report::path_components;
+use crate::traces::TraceMap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The parts of a JSON report which are compared
+#[derive(Clone, Debug, Deserialize)]
+pub struct PreviousReport {
+    percent: f64,
+    files: Vec<PreviousFile>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct PreviousFile {
+    path: Vec<String>,
+    percent: f64,
+}
+
+/// Coverage of a file in the previous and current run
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FileDelta {
+    pub path: String,
+    /// `None` if the file is new
+    pub previous: Option<f64>,
+    /// `None` if the file was deleted
+    pub current: Option<f64>,
+}
+
+impl FileDelta {
+    /// Change in percentage points, `None` unless both runs have the file
+    pub fn change(&self) -> Option<f64> {
+        match (self.previous, self.current) {
+            (Some(previous), Some(current)) => Some(current - previous),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CoverageDelta {
+    pub previous: f64,
+    pub current: f64,
+    /// Files ordered by path
+    pub files: Vec<FileDelta>,
+}
+
+impl CoverageDelta {
+    /// Change in the total in percentage points
+    pub fn change(&self) -> f64 {
+        self.current - self.previous
+    }
+
+    /// Delta of the file with the given path in the reports
+    pub fn file(&self, path: &str) -> Option<&FileDelta> {
+        self.files.iter().find(|f| f.path == path)
+    }
+}
+
+/// Reads the JSON report to compare against, it may be compressed
+pub fn load(path: &Path) -> Result<PreviousReport, RunError> {
+    let error = |e: String| RunError::Compare(format!("{}: {}", path.display(), e));
+    let reader = open_maybe_compressed(path).map_err(|e| error(e.to_string()))?;
+    serde_json::from_reader(reader).map_err(|e| error(e.to_string()))
+}
+
+/// Path of a file as it's named in the JSON report
+pub(crate) fn report_path(config: &Config, path: &Path) -> String {
+    path_components(config, path).join("/")
+}
+
+/// Name of a file in the console from its path in the JSON report, which is
+/// absolute with `--path-format legacy`
+fn display_name(config: &Config, path: &str) -> String {
+    config.display_path(Path::new(path))
+}
+
+pub fn compare(config: &Config, traces: &TraceMap, previous: &PreviousReport) -> CoverageDelta {
+    let mut files = BTreeMap::new();
+    for file in &previous.files {
+        let path = file.path.join("/");
+        files.insert(
+            path.clone(),
+            FileDelta {
+                path,
+                previous: Some(file.percent),
+                current: None,
+            },
+        );
+    }
+    for file in traces.files() {
+        let coverable = traces.coverable_in_path(file);
+        let percent = if coverable == 0 {
+            0.0
+        } else {
+            100.0 * traces.covered_in_path(file) as f64 / coverable as f64
+        };
+        let path = report_path(config, file);
+        files
+            .entry(path.clone())
+            .or_insert(FileDelta {
+                path,
+                previous: None,
+                current: None,
+            })
+            .current = Some(percent);
+    }
+    CoverageDelta {
+        previous: previous.percent,
+        current: traces.coverage_percentage() * 100.0,
+        files: files.into_iter().map(|(_, f)| f).collect(),
+    }
+}
+
+/// Fails if the total dropped by more than `max_drop` percentage points
+pub fn check(delta: &CoverageDelta, max_drop: f64) -> Result<(), RunError> {
+    if -delta.change() > max_drop {
+        Err(RunError::CoverageDropped(-delta.change(), max_drop))
+    } else {
+        Ok(())
+    }
+}
+
+/// Lists the files whose coverage dropped and the new and deleted files,
+/// the change of each file is shown in the summary rows
+pub fn print_delta(config: &Config, delta: &CoverageDelta) {
+    let regressed = delta
+        .files
+        .iter()
+        .filter(|f| f.change().map_or(false, |c| c < 0.0))
+        .collect::<Vec<_>>();
+    if !regressed.is_empty() {
+        println!("|| Regressed files:");
+        for file in regressed {
+            println!(
+                "|| {}: {:.2}% -> {:.2}%",
+                display_name(config, &file.path),
+                file.previous.unwrap_or_default(),
+                file.current.unwrap_or_default()
+            );
+        }
+    }
+    let new = delta
+        .files
+        .iter()
+        .filter(|f| f.previous.is_none())
+        .map(|f| display_name(config, &f.path))
+        .collect::<Vec<_>>();
+    if !new.is_empty() {
+        println!("|| New files: {}", new.join(", "));
+    }
+    let deleted = delta
+        .files
+        .iter()
+        .filter(|f| f.current.is_none())
+        .map(|f| display_name(config, &f.path))
+        .collect::<Vec<_>>();
+    if !deleted.is_empty() {
+        println!("|| Deleted files: {}", deleted.join(", "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PathFormat;
+    use crate::traces::Trace;
+    use std::path::PathBuf;
+
+    #[test]
+    fn files_compared() {
+        let mut traces = TraceMap::new();
+        let lines = [
+            ("src/lib.rs", 1, 1),
+            ("src/lib.rs", 2, 0),
+            ("src/new.rs", 1, 1),
+            ("src/same.rs", 1, 1),
+        ];
+        for (file, line, hits) in &lines {
+            traces.add_trace(
+                &Path::new("/project").join(file),
+                Trace::test_line(*line, *hits),
+            );
+        }
+        let mut config = Config::default();
+        config.manifest = PathBuf::from("/project/Cargo.toml");
+        let previous: PreviousReport = serde_json::from_str(
+            r#"{"schema_version": 1, "covered": 3, "coverable": 4, "percent": 80.0, "files": [
+                {"path": ["src", "lib.rs"], "covered": 3, "coverable": 4, "percent": 75.0},
+                {"path": ["src", "old.rs"], "covered": 0, "coverable": 1, "percent": 0.0},
+                {"path": ["src", "same.rs"], "covered": 1, "coverable": 1, "percent": 100.0}
+            ]}"#,
+        )
+        .unwrap();
+        let delta = compare(&config, &traces, &previous);
+        assert_eq!(delta.change(), -5.0);
+        assert_eq!(delta.file("src/lib.rs").unwrap().change(), Some(-25.0));
+        assert_eq!(delta.file("src/same.rs").unwrap().change(), Some(0.0));
+        let new = delta.file("src/new.rs").unwrap();
+        assert_eq!((new.previous, new.current), (None, Some(100.0)));
+        let deleted = delta.file("src/old.rs").unwrap();
+        assert_eq!((deleted.previous, deleted.current), (Some(0.0), None));
+
+        assert!(check(&delta, 5.0).is_ok());
+        match check(&delta, 2.5) {
+            Err(RunError::CoverageDropped(drop, max)) => assert_eq!((drop, max), (5.0, 2.5)),
+            r => panic!("Unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn display_names_follow_path_format() {
+        let mut config = Config::default();
+        config.manifest = PathBuf::from("/project/Cargo.toml");
+        let file = Path::new("/project/src/lib.rs");
+        let path = report_path(&config, file);
+        assert_eq!(display_name(&config, &path), "src/lib.rs");
+
+        // The JSON report has the absolute path
+        config.path_format = PathFormat::Legacy;
+        let path = report_path(&config, file);
+        assert_ne!(path, "src/lib.rs");
+        assert_eq!(display_name(&config, &path), "src/lib.rs");
+    }
+}
@@ -0,0 +1,211 @@
+//! Coverage rolled up by directory. The console summary prints a row per
+//! directory with `--report-by dir[:depth]`, and the HTML and JSON reports
+//! include the whole directory tree. Directories are named the same way as
+//! files in the reports, files at the workspace root are in the `.` directory.
+//! Excluded files contribute nothing to their directories.
+use crate::config::Config;
+use crate::traces::TraceMap;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Name of the directory of the files at the workspace root
+pub const ROOT_DIR: &str = ".";
+
+/// Coverage of the files in a directory and its subdirectories
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct DirectoryCoverage {
+    pub path: String,
+    pub covered: usize,
+    pub coverable: usize,
+    /// Covered lines as a percentage of the coverable ones, 0.0 if there are
+    /// no coverable lines
+    pub percent: f64,
+    /// Subdirectories ordered by path
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<DirectoryCoverage>,
+}
+
+impl DirectoryCoverage {
+    fn new(path: String, covered: usize, coverable: usize) -> Self {
+        let percent = if coverable == 0 {
+            0.0
+        } else {
+            covered as f64 * 100.0 / coverable as f64
+        };
+        DirectoryCoverage {
+            path,
+            covered,
+            coverable,
+            percent,
+            children: vec![],
+        }
+    }
+}
+
+/// Components of the directory a file is in, empty for files at the root
+fn directory_of(config: &Config, file: &Path) -> Vec<String> {
+    let name = config.display_path(file);
+    let mut components = name
+        .split(|c| c == '/' || c == '\\')
+        .filter(|c| !c.is_empty() && *c != ".")
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    components.pop();
+    components
+}
+
+fn join(components: &[String]) -> String {
+    if components.is_empty() {
+        ROOT_DIR.to_string()
+    } else {
+        components.join("/")
+    }
+}
+
+/// Covered and coverable lines of the files directly in each directory,
+/// leaving out excluded files
+fn file_totals(config: &Config, traces: &TraceMap) -> BTreeMap<Vec<String>, (usize, usize)> {
+    let mut totals = BTreeMap::new();
+    for file in traces.files() {
+        if config.excludes_file(file) {
+            continue;
+        }
+        let entry = totals.entry(directory_of(config, file)).or_insert((0, 0));
+        entry.0 += traces.covered_in_path(file);
+        entry.1 += traces.coverable_in_path(file);
+    }
+    totals
+}
+
+/// Tree of the directories containing the files in the results. The first
+/// node is `.` for the files at the root if there are any, followed by the top
+/// level directories. The totals of each directory include its subdirectories.
+pub fn directory_tree(config: &Config, traces: &TraceMap) -> Vec<DirectoryCoverage> {
+    let totals = file_totals(config, traces);
+    let mut result = vec![];
+    if let Some(&(covered, coverable)) = totals.get(&Vec::<String>::new()) {
+        result.push(DirectoryCoverage::new(
+            ROOT_DIR.to_string(),
+            covered,
+            coverable,
+        ));
+    }
+    result.extend(subdirectories(&[], &totals));
+    result
+}
+
+/// Builds the nodes for the subdirectories of `parent` from the bottom up
+fn subdirectories(
+    parent: &[String],
+    totals: &BTreeMap<Vec<String>, (usize, usize)>,
+) -> Vec<DirectoryCoverage> {
+    let mut names = totals
+        .keys()
+        .filter(|dir| dir.len() > parent.len() && dir.starts_with(parent))
+        .map(|dir| dir[parent.len()].clone())
+        .collect::<Vec<_>>();
+    names.dedup();
+    names
+        .into_iter()
+        .map(|name| {
+            let mut dir = parent.to_vec();
+            dir.push(name);
+            let children = subdirectories(&dir, totals);
+            let (mut covered, mut coverable) = totals.get(&dir).copied().unwrap_or((0, 0));
+            for child in &children {
+                covered += child.covered;
+                coverable += child.coverable;
+            }
+            let mut node = DirectoryCoverage::new(join(&dir), covered, coverable);
+            node.children = children;
+            node
+        })
+        .collect()
+}
+
+/// Rows of the console summary with `--report-by dir[:depth]`. Without a
+/// depth there is a row for the files directly in each directory, otherwise
+/// directories deeper than `depth` are counted in their ancestor at `depth`.
+pub fn directory_rows(
+    config: &Config,
+    traces: &TraceMap,
+    depth: Option<usize>,
+) -> Vec<DirectoryCoverage> {
+    let mut rows: BTreeMap<Vec<String>, (usize, usize)> = BTreeMap::new();
+    for (mut dir, (covered, coverable)) in file_totals(config, traces) {
+        if let Some(depth) = depth {
+            dir.truncate(depth);
+        }
+        let entry = rows.entry(dir).or_insert((0, 0));
+        entry.0 += covered;
+        entry.1 += coverable;
+    }
+    rows.into_iter()
+        .map(|(dir, (covered, coverable))| DirectoryCoverage::new(join(&dir), covered, coverable))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traces::Trace;
+
+    fn add_lines(traces: &mut TraceMap, file: &Path, hits: &[u64]) {
+        for (i, hits) in hits.iter().enumerate() {
+            traces.add_trace(file, Trace::test_line(i as u64 + 1, *hits));
+        }
+    }
+
+    #[test]
+    fn directories_rolled_up() {
+        let root = Path::new("/project");
+        let mut config: Config = toml::from_str(r#"exclude-files = ["src/generated/*"]"#).unwrap();
+        config.manifest = root.join("Cargo.toml");
+        let mut traces = TraceMap::new();
+        add_lines(&mut traces, &root.join("build.rs"), &[1, 0]);
+        add_lines(&mut traces, &root.join("src/lib.rs"), &[1, 1, 0, 0]);
+        add_lines(&mut traces, &root.join("src/network/mod.rs"), &[1, 1]);
+        add_lines(&mut traces, &root.join("src/network/tcp/conn.rs"), &[0, 1]);
+        add_lines(&mut traces, &root.join("src/generated/gen.rs"), &[0, 0, 0]);
+
+        let tree = directory_tree(&config, &traces);
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree[0].path, ".");
+        assert_eq!((tree[0].covered, tree[0].coverable), (1, 2));
+        assert!(tree[0].children.is_empty());
+        let src = &tree[1];
+        assert_eq!(src.path, "src");
+        assert_eq!((src.covered, src.coverable), (5, 8));
+        assert_eq!(src.percent, 62.5);
+        assert_eq!(src.children.len(), 1);
+        let network = &src.children[0];
+        assert_eq!(network.path, "src/network");
+        assert_eq!((network.covered, network.coverable), (3, 4));
+        assert_eq!(network.children[0].path, "src/network/tcp");
+        assert_eq!(
+            (network.children[0].covered, network.children[0].coverable),
+            (1, 2)
+        );
+
+        let rows = |depth| {
+            directory_rows(&config, &traces, depth)
+                .into_iter()
+                .map(|r| (r.path, r.covered, r.coverable))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            rows(Some(1)),
+            vec![(".".to_string(), 1, 2), ("src".to_string(), 5, 8)]
+        );
+        assert_eq!(
+            rows(None),
+            vec![
+                (".".to_string(), 1, 2),
+                ("src".to_string(), 2, 4),
+                ("src/network".to_string(), 2, 2),
+                ("src/network/tcp".to_string(), 1, 2),
+            ]
+        );
+    }
+}
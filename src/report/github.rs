@@ -0,0 +1,229 @@
+//! GitHub Actions output, `--out Github`. Prints a warning workflow command for
+//! each run of uncovered lines so they're annotated inline on the pull
+//! request, and adds the overall coverage to the job summary. On a pull
+//! request only the files changed from the base branch are annotated, and with
+//! `--diff-base` the coverage of new files is added to the summary. GitHub
+//! only shows the first few annotations of a step so at most
+//! `--max-annotations` are printed.
+use crate::config::Config;
+use crate::errors::RunError;
+use crate::report::{new_files, Report, RunMetadata};
+use crate::traces::TraceMap;
+use git2::Repository;
+use log::{debug, info};
+use std::collections::BTreeSet;
+use std::env;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// GitHub Actions annotations, `--out Github`
+pub struct GithubReport;
+
+impl Report for GithubReport {
+    fn render(
+        &self,
+        config: &Config,
+        traces: &TraceMap,
+        metadata: &RunMetadata,
+    ) -> Result<(), RunError> {
+        let changed = env::var("GITHUB_BASE_REF")
+            .ok()
+            .filter(|base| !base.is_empty())
+            .and_then(|base| changed_files(&config.root(), &base));
+        let workspace = env::var_os("GITHUB_WORKSPACE").map(PathBuf::from);
+        let (annotations, skipped) =
+            annotations(traces, config, changed.as_ref(), workspace.as_deref());
+        for annotation in &annotations {
+            println!("{}", annotation);
+        }
+        if skipped > 0 {
+            info!(
+                "{} more uncovered ranges weren't annotated, raise --max-annotations to show them",
+                skipped
+            );
+        }
+        if let Some(path) = env::var_os("GITHUB_STEP_SUMMARY") {
+            let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+            file.write_all(step_summary(traces).as_bytes())?;
+            if let Some(ref files) = metadata.new_files {
+                let summary = new_files::summary(files, config.fail_under_new_files);
+                file.write_all(summary.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn prints_to_stdout(&self) -> bool {
+        true
+    }
+
+    fn description(&self) -> &str {
+        "GitHub Actions annotations of uncovered lines, printed to stdout"
+    }
+}
+
+/// Files changed since the commit the branch was made from, `None` if it
+/// can't be found such as when the base branch wasn't fetched
+fn changed_files(root: &Path, base: &str) -> Option<BTreeSet<PathBuf>> {
+    let repo = Repository::discover(root).ok()?;
+    let base_commit = repo
+        .revparse_single(&format!("origin/{}", base))
+        .or_else(|_| repo.revparse_single(base))
+        .and_then(|o| o.peel_to_commit());
+    let base_commit = match base_commit {
+        Ok(c) => c,
+        Err(e) => {
+            debug!("Couldn't find base branch {}: {}", base, e);
+            return None;
+        }
+    };
+    let head = repo.head().ok()?.peel_to_commit().ok()?;
+    let fork_point = repo.merge_base(base_commit.id(), head.id()).ok()?;
+    let tree = repo.find_commit(fork_point).ok()?.tree().ok()?;
+    let diff = repo
+        .diff_tree_to_workdir_with_index(Some(&tree), None)
+        .ok()?;
+    let workdir = repo.workdir()?;
+    Some(
+        diff.deltas()
+            .filter_map(|d| d.new_file().path())
+            .map(|p| workdir.join(p))
+            .collect(),
+    )
+}
+
+/// The workflow commands for the uncovered lines, and how many ranges were
+/// left out because of the limit
+fn annotations(
+    traces: &TraceMap,
+    config: &Config,
+    changed: Option<&BTreeSet<PathBuf>>,
+    workspace: Option<&Path>,
+) -> (Vec<String>, usize) {
+    let mut annotations = vec![];
+    let mut skipped = 0;
+    for file in traces.files() {
+        if changed.map_or(false, |c| !c.contains(file)) {
+            continue;
+        }
+        // Annotations need the path from the root of the repository
+        let path = workspace
+            .and_then(|w| file.strip_prefix(w).ok())
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|| config.display_path(file));
+        for (start, end) in traces.uncovered_ranges(file) {
+            if annotations.len() >= config.max_annotations {
+                skipped += 1;
+                continue;
+            }
+            let message = if start == end {
+                "Line not covered".to_string()
+            } else {
+                format!("Lines {}-{} not covered", start, end)
+            };
+            annotations.push(format!(
+                "::warning file={},line={},endLine={},title=Coverage::{}",
+                escape_property(&path),
+                start,
+                end,
+                escape_data(&message)
+            ));
+        }
+    }
+    (annotations, skipped)
+}
+
+fn step_summary(traces: &TraceMap) -> String {
+    format!(
+        "### Coverage\n\n**{:.2}%** coverage, {}/{} lines covered\n\n",
+        traces.coverage_percentage() * 100.0,
+        traces.total_covered(),
+        traces.total_coverable()
+    )
+}
+
+fn escape_data(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_property(s: &str) -> String {
+    escape_data(s).replace(':', "%3A").replace(',', "%2C")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traces::Trace;
+
+    fn traces() -> TraceMap {
+        let mut traces = TraceMap::new();
+        let lines = [
+            ("src/lib.rs", 1, 1),
+            ("src/lib.rs", 2, 0),
+            ("src/lib.rs", 3, 0),
+            ("src/lib.rs", 5, 0),
+            ("src/a,b.rs", 7, 0),
+            ("src/main.rs", 1, 2),
+        ];
+        for (file, line, hits) in &lines {
+            traces.add_trace(
+                &Path::new("/project").join(file),
+                Trace::test_line(*line, *hits),
+            );
+        }
+        traces
+    }
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.manifest = Path::new("/project/Cargo.toml").to_path_buf();
+        config
+    }
+
+    #[test]
+    fn uncovered_lines_annotated() {
+        let (annotations, skipped) = annotations(&traces(), &config(), None, None);
+        assert_eq!(
+            annotations,
+            vec![
+                "::warning file=src/a%2Cb.rs,line=7,endLine=7,title=Coverage::Line not covered",
+                "::warning file=src/lib.rs,line=2,endLine=3,title=Coverage::Lines 2-3 not covered",
+                "::warning file=src/lib.rs,line=5,endLine=5,title=Coverage::Line not covered",
+            ]
+        );
+        assert_eq!(skipped, 0);
+    }
+
+    #[test]
+    fn annotations_limited_to_changed_files() {
+        let mut config = config();
+        config.max_annotations = 1;
+        let changed = vec![PathBuf::from("/project/src/lib.rs")]
+            .into_iter()
+            .collect();
+        let (annotations, skipped) = annotations(
+            &traces(),
+            &config,
+            Some(&changed),
+            Some(Path::new("/project")),
+        );
+        assert_eq!(
+            annotations,
+            vec![
+                "::warning file=src/lib.rs,line=2,endLine=3,title=Coverage::Lines 2-3 not covered"
+            ]
+        );
+        assert_eq!(skipped, 1);
+    }
+
+    #[test]
+    fn summary_block() {
+        assert_eq!(
+            step_summary(&traces()),
+            "### Coverage\n\n**33.33%** coverage, 2/6 lines covered\n\n"
+        );
+    }
+}
@@ -0,0 +1,215 @@
+//! Newline delimited JSON file tracking coverage over time. Each successful
+//! report appends a single line, older tarpaulin versions reading a file
+//! written by a newer one ignore any fields they don't know about.
+use crate::config::Config;
+use crate::errors::{write_error, RunError};
+use crate::report::package_percentages;
+use crate::traces::TraceMap;
+use chrono::offset::Utc;
+use log::{info, warn};
+use nix::fcntl::{flock, FlockArg};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+/// Version of the history line schema. Only bumped for breaking changes,
+/// adding fields is always done in a backwards compatible manner.
+pub const HISTORY_VERSION: u32 = 1;
+
+/// Width of the bar used when rendering the history table
+const BAR_WIDTH: usize = 40;
+
+/// A single line in the history file
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HistoryEntry {
+    pub version: u32,
+    /// RFC3339 timestamp of when the entry was recorded
+    pub timestamp: String,
+    pub git_sha: Option<String>,
+    pub branch: Option<String>,
+    pub covered: usize,
+    pub coverable: usize,
+    /// Coverage percentage of each package in the workspace
+    pub packages: BTreeMap<String, f64>,
+}
+
+impl HistoryEntry {
+    pub fn new(config: &Config, traces: &TraceMap) -> Self {
+        let (git_sha, branch) = get_git_head(&config.manifest);
+        let packages = package_percentages(config, traces);
+        Self {
+            version: HISTORY_VERSION,
+            timestamp: Utc::now().to_rfc3339(),
+            git_sha,
+            branch,
+            covered: traces.total_covered(),
+            coverable: traces.total_coverable(),
+            packages,
+        }
+    }
+
+    /// Coverage percentage of the entry ranging from 0.0-100.0
+    pub fn percentage(&self) -> f64 {
+        if self.coverable == 0 {
+            0.0
+        } else {
+            100.0 * self.covered as f64 / self.coverable as f64
+        }
+    }
+}
+
+fn get_git_head(manifest: &Path) -> (Option<String>, Option<String>) {
+    let repo = manifest
+        .parent()
+        .and_then(|dir| git2::Repository::discover(dir).ok());
+    match repo {
+        Some(repo) => match repo.head() {
+            Ok(head) => {
+                let sha = head.target().map(|x| x.to_string());
+                let branch = head.shorthand().map(ToString::to_string);
+                (sha, branch)
+            }
+            Err(_) => (None, None),
+        },
+        None => (None, None),
+    }
+}
+
+/// Appends an entry for the current results to the history file, creating the
+/// file if it doesn't exist. The file is locked while writing so concurrent
+/// runs can't interleave lines.
+pub fn append(path: &Path, config: &Config, traces: &TraceMap) -> Result<(), RunError> {
+    let entry = HistoryEntry::new(config, traces);
+    let mut line = serde_json::to_string(&entry)?;
+    line.push('\n');
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| RunError::History(format!("Failed to open {}: {}", path.display(), e)))?;
+    flock(file.as_raw_fd(), FlockArg::LockExclusive)
+        .map_err(|e| RunError::History(format!("Failed to lock {}: {}", path.display(), e)))?;
+    let res = file
+        .write_all(line.as_bytes())
+        .map_err(|e| RunError::History(write_error(path, &e)));
+    let _ = flock(file.as_raw_fd(), FlockArg::Unlock);
+    if res.is_ok() {
+        info!("Appended coverage history to {}", path.display());
+    }
+    res
+}
+
+/// Reads all the entries in a history file. Lines which can't be parsed are
+/// skipped with a warning.
+pub fn read(path: &Path) -> Result<Vec<HistoryEntry>, RunError> {
+    let file = File::open(path)
+        .map_err(|e| RunError::History(format!("Failed to open {}: {}", path.display(), e)))?;
+    let mut result = vec![];
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<HistoryEntry>(&line) {
+            Ok(e) if e.version > HISTORY_VERSION => {
+                warn!(
+                    "{}:{} has a newer schema version {}, some data may be missing",
+                    path.display(),
+                    i + 1,
+                    e.version
+                );
+                result.push(e);
+            }
+            Ok(e) => result.push(e),
+            Err(e) => warn!("Skipping invalid history line {}: {}", i + 1, e),
+        }
+    }
+    Ok(result)
+}
+
+/// Renders the last `count` entries as a table with an ASCII bar for each
+/// coverage percentage.
+pub fn render(entries: &[HistoryEntry], count: usize) -> String {
+    let start = entries.len().saturating_sub(count);
+    let mut result = String::new();
+    for entry in &entries[start..] {
+        let percent = entry.percentage();
+        let filled = ((percent / 100.0) * BAR_WIDTH as f64).round() as usize;
+        let sha = entry
+            .git_sha
+            .as_ref()
+            .map(|x| x.chars().take(8).collect::<String>())
+            .unwrap_or_else(|| "-".to_string());
+        result.push_str(&format!(
+            "{:<25} {:<8} |{:<width$}| {:>6.2}% ({}/{})\n",
+            entry.timestamp.chars().take(25).collect::<String>(),
+            sha,
+            "#".repeat(filled.min(BAR_WIDTH)),
+            percent,
+            entry.covered,
+            entry.coverable,
+            width = BAR_WIDTH
+        ));
+    }
+    if let (Some(first), Some(last)) = (entries[start..].first(), entries.last()) {
+        result.push_str(&format!(
+            "{:+.2}% change over {} entries\n",
+            last.percentage() - first.percentage(),
+            entries.len() - start
+        ));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_fields_ignored() {
+        let line = r#"{"version":2,"timestamp":"2020-04-20T10:00:00+00:00","covered":5,"coverable":10,"something_new":[1,2,3]}"#;
+        let entry: HistoryEntry = serde_json::from_str(line).unwrap();
+        assert_eq!(entry.version, 2);
+        assert_eq!(entry.covered, 5);
+        assert_eq!(entry.coverable, 10);
+        assert!(entry.packages.is_empty());
+        assert_eq!(entry.percentage(), 50.0);
+    }
+
+    #[test]
+    fn append_creates_and_extends() {
+        let path = std::env::temp_dir().join("tarpaulin_history_append_test.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let config = Config::default();
+        let traces = TraceMap::new();
+        append(&path, &config, &traces).unwrap();
+        append(&path, &config, &traces).unwrap();
+        let entries = read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|x| x.version == HISTORY_VERSION));
+    }
+
+    #[test]
+    fn render_last_entries() {
+        let entries = (1..=5)
+            .map(|i| HistoryEntry {
+                version: HISTORY_VERSION,
+                covered: i,
+                coverable: 10,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let table = render(&entries, 2);
+        let lines = table.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].contains("40.00%"));
+        assert!(lines[1].contains("50.00%"));
+        assert!(lines[2].starts_with("+10.00%"));
+    }
+}
@@ -1,15 +1,22 @@
 use crate::config::Config;
 use crate::errors::*;
-use crate::report::{get_previous_result, safe_json};
+use crate::report::compress::create_report_file;
+use crate::report::directories::{directory_tree, DirectoryCoverage};
+use crate::report::{
+    get_previous_result, html_split, path_components, read_source, safe_json, Report, RunMetadata,
+};
 use crate::traces::{Trace, TraceMap};
 use serde::Serialize;
-use std::fs::{read_to_string, File};
 use std::io::{self, Write};
 
 #[derive(Serialize)]
 struct SourceFile {
     pub path: Vec<String>,
+    /// Empty if the source couldn't be read
     pub content: String,
+    /// Why the source couldn't be read, the file is shown without its source
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
     pub traces: Vec<Trace>,
     pub covered: usize,
     pub coverable: usize,
@@ -18,6 +25,9 @@ struct SourceFile {
 #[derive(Serialize)]
 struct CoverageReport {
     pub files: Vec<SourceFile>,
+    /// Directory tree shown on the index, only for the current results
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub directories: Vec<DirectoryCoverage>,
 }
 
 #[derive(PartialEq)]
@@ -26,60 +36,90 @@ enum Context {
     PreviousResults,
 }
 
-fn get_json(coverage_data: &TraceMap, context: Context) -> Result<String, RunError> {
-    let mut report = CoverageReport { files: Vec::new() };
+fn get_report(
+    coverage_data: &TraceMap,
+    config: &Config,
+    context: Context,
+) -> Result<CoverageReport, RunError> {
+    let mut report = CoverageReport {
+        files: Vec::new(),
+        directories: Vec::new(),
+    };
+    if context == Context::CurrentResults {
+        report.directories = directory_tree(config, coverage_data);
+    }
 
     for (path, traces) in coverage_data.iter() {
-        let content = match read_to_string(path) {
-            Ok(k) => k,
+        let (content, error) = match read_source(path) {
+            Ok(k) => (k, None),
             Err(e) => {
-                if context == Context::PreviousResults && e.kind() == io::ErrorKind::NotFound {
-                    // Assume the file has been deleted since the last run.
+                if context == Context::PreviousResults {
+                    // Assume the file has been deleted since the last run,
+                    // only its totals are used so it's no loss to skip it
                     continue;
                 }
-
-                return Err(RunError::Html(format!(
-                    "Unable to read source file to string: {}",
-                    e.to_string()
-                )));
+                (String::new(), Some(e.to_string()))
             }
         };
 
         report.files.push(SourceFile {
-            path: path
-                .components()
-                .map(|c| c.as_os_str().to_string_lossy().to_string())
-                .collect(),
+            path: path_components(config, path),
             content,
+            error,
             traces: traces.clone(),
             covered: coverage_data.covered_in_path(path),
             coverable: coverage_data.coverable_in_path(path),
         });
     }
+    Ok(report)
+}
 
-    safe_json::to_string_safe(&report)
+fn write_json<W: Write>(writer: W, report: &CoverageReport) -> Result<(), RunError> {
+    safe_json::to_writer_safe(writer, report)
         .map_err(|e| RunError::Html(format!("Report isn't serializable: {}", e.to_string())))
 }
 
-pub fn export(coverage_data: &TraceMap, config: &Config) -> Result<(), RunError> {
-    let file_path = config.output_directory.join("tarpaulin-report.html");
-    let mut file = match File::create(file_path) {
-        Ok(k) => k,
-        Err(e) => {
-            return Err(RunError::Html(format!(
-                "File is not writeable: {}",
-                e.to_string()
-            )))
+/// Name of the report file in the output directory
+pub const FILE_NAME: &str = "tarpaulin-report.html";
+
+/// HTML report viewer, `--out Html`
+pub struct HtmlReport;
+
+impl Report for HtmlReport {
+    fn render(
+        &self,
+        config: &Config,
+        traces: &TraceMap,
+        metadata: &RunMetadata,
+    ) -> Result<(), RunError> {
+        if config.html_split {
+            html_split::export(traces, config, metadata)
+        } else {
+            export(traces, config)
         }
-    };
+    }
 
-    let report_json = get_json(coverage_data, Context::CurrentResults)?;
-    let previous_report_json = match get_previous_result(&config) {
-        Some(result) => get_json(&result, Context::PreviousResults)?,
-        None => String::from("null"),
+    fn file_name(&self) -> Option<&str> {
+        Some(FILE_NAME)
+    }
+
+    fn description(&self) -> &str {
+        "HTML report to browse the source and its coverage"
+    }
+}
+
+pub fn export(coverage_data: &TraceMap, config: &Config) -> Result<(), RunError> {
+    let report = get_report(coverage_data, config, Context::CurrentResults)?;
+    let previous_report = match get_previous_result(&config) {
+        Some(result) => Some(get_report(&result, config, Context::PreviousResults)?),
+        None => None,
     };
 
-    let html_write = match write!(
+    let mut file = create_report_file(config, FILE_NAME)
+        .map_err(|e| RunError::Html(format!("File is not writeable: {}", e.to_string())))?;
+    let html_err = |e: io::Error| RunError::Html(e.to_string());
+
+    write!(
         file,
         r##"<!doctype html>
 <html>
@@ -90,22 +130,28 @@ pub fn export(coverage_data: &TraceMap, config: &Config) -> Result<(), RunError>
 <body>
     <div id="root"></div>
     <script>
-        var data = {};
-        var previousData = {};
+        var data = "##,
+        include_str!("report_viewer.css")
+    )
+    .map_err(html_err)?;
+    write_json(&mut file, &report)?;
+    write!(file, ";\n        var previousData = ").map_err(html_err)?;
+    match previous_report {
+        Some(previous) => write_json(&mut file, &previous)?,
+        None => write!(file, "null").map_err(html_err)?,
+    }
+    write!(
+        file,
+        r##";
     </script>
     <script crossorigin src="https://unpkg.com/react@16/umd/react.production.min.js"></script>
     <script crossorigin src="https://unpkg.com/react-dom@16/umd/react-dom.production.min.js"></script>
     <script>{}</script>
 </body>
 </html>"##,
-        include_str!("report_viewer.css"),
-        report_json,
-        previous_report_json,
         include_str!("report_viewer.js")
-    ) {
-        Ok(_) => (),
-        Err(e) => return Err(RunError::Html(e.to_string())),
-    };
+    )
+    .map_err(html_err)?;
 
-    Ok(html_write)
+    file.finish().map_err(html_err)
 }
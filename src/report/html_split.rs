@@ -0,0 +1,461 @@
+//! HTML report split into an index page and a page per source file,
+//! `--out Html --html-split`. The single page report embeds every source file
+//! so for large projects it becomes too big for a browser to open. The pages
+//! share a stylesheet and script and all links are relative so the directory
+//! can be zipped or served from anywhere. Pages are written one at a time as
+//! the sources are read so only one source file is held in memory.
+//!
+//! Nothing is loaded from a CDN so the report can be viewed offline. Sources
+//! are read when the report is written, a file which can't be read is listed
+//! without a page and one which changed since the run gets a notice.
+use crate::config::Config;
+use crate::errors::*;
+use crate::report::directories::{directory_tree, DirectoryCoverage};
+use crate::report::{read_source, RunMetadata};
+use crate::traces::{amount_coverable, amount_covered, CoverageStat, Trace, TraceMap};
+use std::collections::BTreeSet;
+use std::fs::{self, create_dir_all, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Name of the report directory in the output directory
+pub const DIR_NAME: &str = "tarpaulin-report";
+
+/// Source files larger than this are listed in the index without a page
+pub const MAX_PAGE_SOURCE_BYTES: u64 = 1024 * 1024;
+
+/// A file in the summary table of the index page
+pub(crate) struct IndexRow {
+    pub(crate) name: String,
+    /// Page relative to the report directory, `None` if the file didn't get
+    /// one
+    pub(crate) page: Option<String>,
+    /// Why there's no page, shown after the name in the index
+    pub(crate) note: Option<&'static str>,
+    pub(crate) covered: usize,
+    pub(crate) coverable: usize,
+}
+
+/// Stylesheet shared by the pages
+pub(crate) const STYLE: &str = include_str!("report_viewer.css");
+
+/// Script shared by the pages
+pub(crate) const SCRIPT: &str = include_str!("report_split.js");
+
+/// Error for a failed write of `path`
+fn write_err(path: &Path) -> impl Fn(io::Error) -> RunError + '_ {
+    move |e| RunError::Html(write_error(path, &e))
+}
+
+pub fn export(
+    coverage_data: &TraceMap,
+    config: &Config,
+    metadata: &RunMetadata,
+) -> Result<(), RunError> {
+    let dir = config.output_dir().join(DIR_NAME);
+    let files_dir = dir.join("files");
+    // Pages of files no longer in the results would otherwise be left behind
+    if files_dir.exists() {
+        fs::remove_dir_all(&files_dir).map_err(write_err(&files_dir))?;
+    }
+    create_dir_all(&files_dir).map_err(write_err(&files_dir))?;
+    let css = dir.join("report.css");
+    fs::write(&css, STYLE).map_err(write_err(&css))?;
+    let js = dir.join("report.js");
+    fs::write(&js, SCRIPT).map_err(write_err(&js))?;
+
+    let root = config.root();
+    let mut rows = vec![];
+    for (path, traces) in coverage_data.iter() {
+        let mut row = IndexRow {
+            name: config.display_path(path),
+            page: None,
+            note: None,
+            covered: coverage_data.covered_in_path(path),
+            coverable: coverage_data.coverable_in_path(path),
+        };
+        let content = match fs::metadata(path) {
+            Ok(m) if m.len() > MAX_PAGE_SOURCE_BYTES => Err("too large to show"),
+            // Files which can't be read are listed once the reports are done
+            _ => read_source(path).map_err(|_| "source unreadable"),
+        };
+        match content {
+            Ok(content) => {
+                let page = page_path(&TraceMap::file_id(path, &root));
+                let ignored = metadata.ignored_lines.get(path);
+                write_page(&dir, &page, &row, &content, traces, ignored)?;
+                row.page = Some(page);
+            }
+            Err(note) => row.note = Some(note),
+        }
+        rows.push(row);
+    }
+    let directories = directory_tree(config, coverage_data);
+    write_index(&dir, &rows, &directories, coverage_data)
+}
+
+/// Page of a file relative to the report directory, mirroring where the file
+/// is in the project
+fn page_path(file_id: &str) -> String {
+    let components = file_id
+        .split('/')
+        .filter(|c| !c.is_empty())
+        .map(|c| if c == ".." { "__" } else { c })
+        .collect::<Vec<_>>();
+    format!("files/{}.html", components.join("/"))
+}
+
+/// Relative link from a page back to the report directory
+fn link_to_root(page: &str) -> String {
+    "../".repeat(page.matches('/').count())
+}
+
+fn create_page(path: &Path) -> Result<BufWriter<File>, RunError> {
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent).map_err(write_err(parent))?;
+    }
+    File::create(path)
+        .map(BufWriter::new)
+        .map_err(write_err(path))
+}
+
+fn write_header<W: Write>(w: &mut W, title: &str, root: &str) -> io::Result<()> {
+    writeln!(
+        w,
+        r##"<!doctype html>
+<html>
+<head>
+    <meta charset="utf-8">
+    <title>{}</title>
+    <link rel="stylesheet" href="{}report.css">
+</head>
+<body>
+<div class="app">"##,
+        escape(title),
+        root
+    )
+}
+
+fn write_footer<W: Write>(w: &mut W, root: &str) -> io::Result<()> {
+    writeln!(
+        w,
+        r##"</div>
+<script src="{}report.js"></script>
+</body>
+</html>"##,
+        root
+    )
+}
+
+fn write_page(
+    dir: &Path,
+    page: &str,
+    row: &IndexRow,
+    content: &str,
+    traces: &[Trace],
+    ignored: Option<&BTreeSet<u64>>,
+) -> Result<(), RunError> {
+    let root = link_to_root(page);
+    let path = dir.join(page);
+    let err = write_err(&path);
+    let mut w = create_page(&path)?;
+    let traces = traces.iter().collect::<Vec<_>>();
+    write_file_view(&mut w, &root, row, content, &traces, ignored).map_err(&err)?;
+    w.flush().map_err(&err)
+}
+
+/// Annotated source of a file, `root` is the link to the directory with the
+/// index and the shared stylesheet and script. Lines the source analysis
+/// ignored are greyed out if `ignored` is given.
+pub(crate) fn write_file_view<W: Write>(
+    w: &mut W,
+    root: &str,
+    row: &IndexRow,
+    content: &str,
+    traces: &[&Trace],
+    ignored: Option<&BTreeSet<u64>>,
+) -> io::Result<()> {
+    write_header(w, &row.name, root)?;
+    writeln!(
+        w,
+        r##"<div class="display-file">
+<div class="file-header">
+    <a class="file-header__back" href="{}index.html">Back</a>
+    <div class="file-header__name">{}</div>
+    <div class="file-header__stat">{}</div>
+</div>
+<div class="file-content">"##,
+        root,
+        escape(&row.name),
+        coverage_stat(row.covered, row.coverable)
+    )?;
+    let lines = content.lines().count() as u64;
+    if traces.iter().any(|t| t.line > lines) {
+        writeln!(
+            w,
+            r#"<div class="file-notice">The file has changed since the coverage run, some lines may be shaded wrongly.</div>"#
+        )?;
+    }
+    for (i, line) in content.lines().enumerate() {
+        let number = i as u64 + 1;
+        let on_line = traces
+            .iter()
+            .filter(|t| t.line == number)
+            .copied()
+            .collect::<Vec<_>>();
+        let is_ignored = on_line.is_empty()
+            && line.chars().any(char::is_alphanumeric)
+            && ignored.map_or(false, |lines| lines.contains(&number));
+        let (class, title) = if is_ignored {
+            (
+                "code-line code-line_ignored",
+                format!("Line {}, ignored", number),
+            )
+        } else {
+            (line_class(&on_line), line_title(number, &on_line))
+        };
+        writeln!(
+            w,
+            r#"<pre id="L{}" class="{}" title="{}">{}</pre>"#,
+            number,
+            class,
+            title,
+            escape(line)
+        )?;
+    }
+    writeln!(w, "</div>\n</div>")?;
+    write_footer(w, root)
+}
+
+fn write_index(
+    dir: &Path,
+    rows: &[IndexRow],
+    directories: &[DirectoryCoverage],
+    coverage_data: &TraceMap,
+) -> Result<(), RunError> {
+    let path = dir.join("index.html");
+    let err = write_err(&path);
+    let mut w = create_page(&path)?;
+    write_index_view(&mut w, "", rows, directories, coverage_data).map_err(&err)?;
+    w.flush().map_err(&err)
+}
+
+/// Summary of the results with the directory tree and a row per file, links
+/// to the pages of the files are relative to `root`
+pub(crate) fn write_index_view<W: Write>(
+    w: &mut W,
+    root: &str,
+    rows: &[IndexRow],
+    directories: &[DirectoryCoverage],
+    coverage_data: &TraceMap,
+) -> io::Result<()> {
+    write_header(w, "Coverage report", root)?;
+    writeln!(
+        w,
+        r##"<div class="display-folder">
+<div class="file-header">
+    <div class="file-header__name">Coverage report</div>
+    <div class="file-header__stat">{}</div>
+</div>"##,
+        coverage_stat(
+            coverage_data.total_covered(),
+            coverage_data.total_coverable()
+        )
+    )?;
+    write_directory_tree(w, directories)?;
+    writeln!(
+        w,
+        r##"<table class="files-list">
+<thead class="files-list__head">
+    <tr><th>Path</th><th>Coverage</th></tr>
+</thead>
+<tbody class="files-list__body">"##
+    )?;
+    for row in rows {
+        let name = match row.page {
+            Some(ref page) => format!(
+                r#"<a href="{}{}">{}</a>"#,
+                root,
+                escape(page),
+                escape(&row.name)
+            ),
+            None => match row.note {
+                Some(note) => format!("{} ({})", escape(&row.name), note),
+                None => escape(&row.name),
+            },
+        };
+        writeln!(
+            w,
+            r#"    <tr class="files-list__file{}"><td>{}</td><td>{}</td></tr>"#,
+            level_class("files-list__file", row.covered, row.coverable),
+            name,
+            coverage_stat(row.covered, row.coverable)
+        )?;
+    }
+    writeln!(w, "</tbody>\n</table>\n</div>")?;
+    write_footer(w, root)
+}
+
+/// Collapsible tree of the directories with their coverage, the top level is
+/// expanded
+fn write_directory_tree<W: Write>(w: &mut W, directories: &[DirectoryCoverage]) -> io::Result<()> {
+    if directories.is_empty() {
+        return Ok(());
+    }
+    writeln!(w, r#"<div class="dir-tree">"#)?;
+    for dir in directories {
+        write_directory(w, dir)?;
+    }
+    writeln!(w, "</div>")
+}
+
+fn write_directory<W: Write>(w: &mut W, dir: &DirectoryCoverage) -> io::Result<()> {
+    let name = dir.path.rsplit('/').next().unwrap_or(&dir.path);
+    writeln!(
+        w,
+        r#"<details class="dir-tree__dir"{}><summary class="dir-tree__summary{}" title="{}">{}: {}</summary>"#,
+        if dir.path.contains('/') { "" } else { " open" },
+        level_class("dir-tree__summary", dir.covered, dir.coverable),
+        escape(&dir.path),
+        escape(name),
+        coverage_stat(dir.covered, dir.coverable)
+    )?;
+    for child in &dir.children {
+        write_directory(w, child)?;
+    }
+    writeln!(w, "</details>")
+}
+
+fn coverage_stat(covered: usize, coverable: usize) -> String {
+    if coverable == 0 {
+        format!("Covered: {} of {}", covered, coverable)
+    } else {
+        format!(
+            "Covered: {} of {} ({:.2}%)",
+            covered,
+            coverable,
+            covered as f64 * 100.0 / coverable as f64
+        )
+    }
+}
+
+/// Colour of a row in the summary table or a directory in the tree, the same
+/// thresholds as the single page report
+fn coverage_level(covered: usize, coverable: usize) -> Option<&'static str> {
+    if coverable == 0 {
+        return None;
+    }
+    let coverage = covered as f64 * 100.0 / coverable as f64;
+    if coverage < 50.0 {
+        Some("low")
+    } else if coverage < 80.0 {
+        Some("medium")
+    } else {
+        Some("high")
+    }
+}
+
+/// The class modifier of `block` for the coverage level
+fn level_class(block: &str, covered: usize, coverable: usize) -> String {
+    coverage_level(covered, coverable)
+        .map(|level| format!(" {}_{}", block, level))
+        .unwrap_or_default()
+}
+
+fn line_class(traces: &[&Trace]) -> &'static str {
+    if traces.is_empty() {
+        "code-line"
+    } else if amount_covered(traces) > 0 {
+        "code-line code-line_covered"
+    } else if amount_coverable(traces) > 0 {
+        "code-line code-line_uncovered"
+    } else {
+        "code-line"
+    }
+}
+
+fn line_title(number: u64, traces: &[&Trace]) -> String {
+    let hits = traces
+        .iter()
+        .filter_map(|t| match t.stats {
+            CoverageStat::Line(hits) => Some(hits),
+            _ => None,
+        })
+        .max();
+    match hits {
+        Some(hits) => format!("Line {}, hits: {}", number, hits),
+        None => format!("Line {}", number),
+    }
+}
+
+pub(crate) fn escape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_link_back_to_root() {
+        assert_eq!(page_path("src/lib.rs"), "files/src/lib.rs.html");
+        assert_eq!(page_path("/home/me/lib.rs"), "files/home/me/lib.rs.html");
+        assert_eq!(page_path("../other/lib.rs"), "files/__/other/lib.rs.html");
+        assert_eq!(link_to_root("files/src/lib.rs.html"), "../../");
+        assert_eq!(link_to_root("index.html"), "");
+        assert_eq!(
+            level_class("files-list__file", 1, 4),
+            " files-list__file_low"
+        );
+        assert_eq!(
+            level_class("dir-tree__summary", 4, 4),
+            " dir-tree__summary_high"
+        );
+        assert_eq!(level_class("dir-tree__summary", 0, 0), "");
+        assert_eq!(
+            escape(r#"<a href="x">&</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn file_view_shades_lines() {
+        let row = IndexRow {
+            name: "src/lib.rs".to_string(),
+            page: None,
+            note: None,
+            covered: 1,
+            coverable: 2,
+        };
+        let content = "fn a() {\n    b();\n}\n#[test]\nfn t() {}\n";
+        let traces = vec![Trace::test_line(1, 1), Trace::test_line(2, 0)];
+        let traces = traces.iter().collect::<Vec<_>>();
+        let ignored = [4, 5].iter().copied().collect::<BTreeSet<u64>>();
+        let mut page = vec![];
+        write_file_view(&mut page, "../", &row, content, &traces, Some(&ignored)).unwrap();
+        let page = String::from_utf8(page).unwrap();
+        assert!(page.contains(r#"id="L1" class="code-line code-line_covered""#));
+        assert!(page.contains(r#"id="L2" class="code-line code-line_uncovered""#));
+        assert!(page.contains(r#"id="L3" class="code-line" "#));
+        assert!(page.contains(r#"id="L5" class="code-line code-line_ignored""#));
+        assert!(!page.contains("file-notice"));
+
+        // Traces past the end of the file mean it was edited after the run
+        let edited = vec![Trace::test_line(1, 1), Trace::test_line(9, 0)];
+        let edited = edited.iter().collect::<Vec<_>>();
+        let mut page = vec![];
+        write_file_view(&mut page, "../", &row, "fn a() {}\n", &edited, None).unwrap();
+        let page = String::from_utf8(page).unwrap();
+        assert!(page.contains(r#"<div class="file-notice">"#));
+    }
+}
@@ -1,25 +1,115 @@
 use std::convert::From;
-use std::{fs, io::Write};
 
 use crate::config::Config;
 use crate::errors::*;
-use crate::traces::{Trace, TraceMap};
+use crate::report::age::AgeBucket;
+use crate::report::compress::create_report_file;
+use crate::report::delta::CoverageDelta;
+use crate::report::directories::{directory_tree, DirectoryCoverage};
+use crate::report::missed::{classify, MissedKind};
+use crate::report::new_files::NewFile;
+use crate::report::overhead::OverheadSummary;
+use crate::report::suggest::{suggestions, Suggestion};
+use crate::report::targets::{BinRun, FlakyBinary, TargetSummary};
+use crate::report::weighted::{self, LineWeights};
+use crate::report::{
+    branch_density, path_components, read_source, PostFilter, Report, RunMetadata, SkippedConfig,
+};
+use crate::traces::{CoverageStat, FunctionCoverage, Trace, TraceMap};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 use std::slice::Iter;
 
 use serde::Serialize;
 
+/// Version of the layout of the report, increased when a field is removed or
+/// changes meaning. New fields may be added without changing it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct JsonTrace {
+    #[serde(flatten)]
+    trace: Trace,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    missed_kind: Option<MissedKind>,
+}
+
+/// Hits of a coverable line, summed if there's more than one trace on it
+#[derive(Serialize)]
+struct LineHits {
+    line: u64,
+    hits: u64,
+}
+
 #[derive(Serialize)]
 struct SourceFile {
     path: Vec<String>,
+    absolute_path: PathBuf,
+    /// Empty if the source couldn't be read
     content: String,
-    traces: Vec<Trace>,
+    /// Why the source couldn't be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_error: Option<String>,
+    /// Traces ordered by line
+    traces: Vec<JsonTrace>,
+    /// Coverable lines ordered by line
+    lines: Vec<LineHits>,
     covered: usize,
     coverable: usize,
+    percent: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    branch_points: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    branch_density: Option<f64>,
+    /// Coverage weighted by the complexity of each line, only with
+    /// `--weighted-coverage`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    weighted_percent: Option<f64>,
+    /// Covered lines where all the hits came from ignored tests
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    only_ignored: Vec<u64>,
+    /// Lines of test scaffolding left out of the coverable lines with
+    /// `--exclude-test-scaffolding`, with their hits
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    scaffolding: Vec<LineHits>,
+    /// Functions ordered by the line they're declared on
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    functions: Vec<FunctionCoverage>,
 }
 
 #[derive(Serialize)]
 pub struct CoverageReport {
+    schema_version: u32,
+    covered: usize,
+    coverable: usize,
+    percent: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    weighted_percent: Option<f64>,
+    /// Files ordered by path
     files: Vec<SourceFile>,
+    /// Coverage of each directory, nested the same as the directories
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    directories: Vec<DirectoryCoverage>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    targets: Vec<TargetSummary>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    skipped_configs: Vec<SkippedConfig>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    suggestions: Vec<Suggestion>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    overhead: Option<OverheadSummary>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    flaky: Vec<FlakyBinary>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    bins: Vec<BinRun>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    new_files: Option<Vec<NewFile>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    age_buckets: Option<Vec<AgeBucket>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delta: Option<CoverageDelta>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    post_filter: Option<PostFilter>,
 }
 
 impl CoverageReport {
@@ -34,34 +124,172 @@ impl CoverageReport {
     pub fn coverable(&self) -> Vec<usize> {
         self.iter().map(|r| r.coverable).collect()
     }
+
+    /// Adds the number of decision points and their density to each file
+    fn add_branch_points(&mut self, branch_points: &BTreeMap<PathBuf, usize>) {
+        for file in &mut self.files {
+            let path = file.path.iter().collect::<PathBuf>();
+            if let Some(points) = branch_points.get(&path) {
+                file.branch_points = Some(*points);
+                file.branch_density = Some(branch_density(*points, file.coverable));
+            }
+        }
+    }
+
+    /// Adds the weighted coverage of each file and the total
+    fn add_weighted_coverage(&mut self, traces: &TraceMap, weights: &LineWeights) {
+        let (files, total) = weighted::coverage(traces, weights);
+        for file in &mut self.files {
+            let path = file.path.iter().collect::<PathBuf>();
+            if let Some(coverage) = files.get(&path) {
+                file.weighted_percent = Some(coverage.percent());
+            }
+        }
+        self.weighted_percent = Some(total.percent());
+    }
+
+    /// Names each file the way the other reports do, the branch points and
+    /// weighted coverage have to be added first as they're keyed by the
+    /// absolute path
+    fn name_files(&mut self, config: &Config) {
+        for file in &mut self.files {
+            let path = file.path.iter().collect::<PathBuf>();
+            file.path = path_components(config, &path);
+        }
+        self.files.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+
+    /// Adds the `missed_kind` of each uncovered line, this has to be done
+    /// before the files are named as the functions are keyed by the absolute
+    /// path
+    fn add_missed_kinds(&mut self, traces: &TraceMap) {
+        for file in &mut self.files {
+            let path = file.path.iter().collect::<PathBuf>();
+            let file_traces = file
+                .traces
+                .iter()
+                .map(|t| t.trace.clone())
+                .collect::<Vec<_>>();
+            let kinds = classify(&file_traces, &traces.get_functions(&path));
+            for t in &mut file.traces {
+                t.missed_kind = kinds.get(&t.trace.line).cloned();
+            }
+        }
+    }
+}
+
+/// Covered lines as a percentage of the coverable ones, 0.0 if there are no
+/// coverable lines
+fn percent(covered: usize, coverable: usize) -> f64 {
+    if coverable == 0 {
+        0.0
+    } else {
+        covered as f64 * 100.0 / coverable as f64
+    }
+}
+
+fn line_hits(traces: &[Trace]) -> Vec<LineHits> {
+    let mut lines = BTreeMap::new();
+    for trace in traces {
+        if let CoverageStat::Line(hits) = trace.stats {
+            *lines.entry(trace.line).or_insert(0) += hits;
+        }
+    }
+    lines
+        .into_iter()
+        .map(|(line, hits)| LineHits { line, hits })
+        .collect()
 }
 
 impl From<&TraceMap> for Vec<SourceFile> {
     fn from(coverage_data: &TraceMap) -> Self {
+        let only_ignored = coverage_data.only_ignored_lines();
+        let scaffolding = coverage_data.scaffolding_hits();
+        // Files where every line is scaffolding have no traces left
+        let no_traces: Vec<Trace> = vec![];
+        let scaffolding_only = scaffolding
+            .keys()
+            .filter(|path| !coverage_data.contains_file(path))
+            .map(|path| (path, &no_traces));
         coverage_data
             .iter()
-            .map(|(path, traces)| -> Result<SourceFile, RunError> {
-                let content = fs::read_to_string(path).map_err(RunError::from)?;
-                Ok(SourceFile {
+            .chain(scaffolding_only)
+            .map(|(path, traces)| {
+                let (content, source_error) = match read_source(path) {
+                    Ok(content) => (content, None),
+                    Err(e) => (String::new(), Some(e.to_string())),
+                };
+                let mut traces = traces.clone();
+                traces.sort_by_key(|t| t.line);
+                let covered = coverage_data.covered_in_path(path);
+                let coverable = coverage_data.coverable_in_path(path);
+                SourceFile {
                     path: path
                         .components()
                         .map(|c| c.as_os_str().to_string_lossy().to_string())
                         .collect(),
-                    content: content,
-                    traces: traces.clone(),
-                    covered: coverage_data.covered_in_path(path),
-                    coverable: coverage_data.coverable_in_path(path),
-                })
+                    absolute_path: path.canonicalize().unwrap_or_else(|_| path.clone()),
+                    content,
+                    source_error,
+                    lines: line_hits(&traces),
+                    traces: traces
+                        .into_iter()
+                        .map(|trace| JsonTrace {
+                            trace,
+                            missed_kind: None,
+                        })
+                        .collect(),
+                    covered,
+                    coverable,
+                    percent: percent(covered, coverable),
+                    branch_points: None,
+                    branch_density: None,
+                    weighted_percent: None,
+                    only_ignored: only_ignored
+                        .get(path)
+                        .map(|lines| lines.iter().copied().collect())
+                        .unwrap_or_default(),
+                    scaffolding: scaffolding
+                        .get(path)
+                        .map(|lines| {
+                            lines
+                                .iter()
+                                .map(|(line, hits)| LineHits {
+                                    line: *line,
+                                    hits: *hits,
+                                })
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                    functions: coverage_data.function_coverage(path),
+                }
             })
-            .filter_map(Result::ok)
             .collect()
     }
 }
 
 impl From<&TraceMap> for CoverageReport {
     fn from(coverage_data: &TraceMap) -> Self {
+        let covered = coverage_data.total_covered();
+        let coverable = coverage_data.total_coverable();
         CoverageReport {
+            schema_version: SCHEMA_VERSION,
+            covered,
+            coverable,
+            percent: percent(covered, coverable),
+            weighted_percent: None,
             files: Vec::<SourceFile>::from(coverage_data),
+            directories: vec![],
+            targets: vec![],
+            skipped_configs: vec![],
+            suggestions: vec![],
+            overhead: None,
+            flaky: vec![],
+            bins: vec![],
+            new_files: None,
+            age_buckets: None,
+            delta: None,
+            post_filter: None,
         }
     }
 }
@@ -74,10 +302,57 @@ impl Into<JsonStringResult> for &TraceMap {
     }
 }
 
-pub fn export(coverage_data: &TraceMap, config: &Config) -> Result<(), RunError> {
-    let file_path = config.output_directory.join("tarpaulin-report.json");
-    let report: JsonStringResult = coverage_data.into();
-    fs::File::create(file_path)?
-        .write_all(report?.as_bytes())
-        .map_err(RunError::from)
+/// Name of the report file in the output directory
+pub const FILE_NAME: &str = "tarpaulin-report.json";
+
+/// JSON report, `--out Json`
+pub struct JsonReport;
+
+impl Report for JsonReport {
+    fn render(
+        &self,
+        config: &Config,
+        traces: &TraceMap,
+        metadata: &RunMetadata,
+    ) -> Result<(), RunError> {
+        export(traces, config, metadata)
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        Some(FILE_NAME)
+    }
+
+    fn description(&self) -> &str {
+        "JSON of the coverage of every line"
+    }
+}
+
+pub fn export(
+    coverage_data: &TraceMap,
+    config: &Config,
+    metadata: &RunMetadata,
+) -> Result<(), RunError> {
+    let mut report = CoverageReport::from(coverage_data);
+    report.directories = directory_tree(config, coverage_data);
+    report.targets = metadata.targets.clone();
+    report.skipped_configs = metadata.skipped_configs.clone();
+    report.overhead = metadata.overhead.clone();
+    report.flaky = metadata.flaky.clone();
+    report.bins = metadata.bin_runs.clone();
+    report.new_files = metadata.new_files.clone();
+    report.age_buckets = metadata.age_buckets.clone();
+    report.delta = metadata.delta.clone();
+    report.post_filter = metadata.post_filter.clone();
+    report.add_branch_points(&metadata.branch_points);
+    if config.weighted_coverage {
+        report.add_weighted_coverage(coverage_data, &metadata.line_weights);
+    }
+    if config.missed_analysis {
+        report.add_missed_kinds(coverage_data);
+    }
+    report.name_files(config);
+    report.suggestions = suggestions(config, coverage_data, metadata);
+    let mut file = create_report_file(config, FILE_NAME)?;
+    serde_json::to_writer(&mut file, &report)?;
+    file.finish().map_err(RunError::from)
 }
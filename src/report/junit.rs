@@ -0,0 +1,178 @@
+//! JUnit XML report of the results of each test, `--out Junit` or `--junit
+//! <PATH>`. There's a test suite per test binary, failed tests include what
+//! they printed so CI servers like Jenkins can show it with the failure.
+use crate::config::Config;
+use crate::errors::RunError;
+use crate::report::compress::create_report_file;
+use crate::report::{Report, RunMetadata};
+use crate::test_results::{Outcome, TestSuite};
+use crate::traces::TraceMap;
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::Writer;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// Name of the report file in the output directory
+pub const FILE_NAME: &str = "junit.xml";
+
+/// JUnit report, `--out Junit`
+pub struct JunitReport;
+
+impl Report for JunitReport {
+    fn render(
+        &self,
+        config: &Config,
+        _traces: &TraceMap,
+        metadata: &RunMetadata,
+    ) -> Result<(), RunError> {
+        let file = create_report_file(config, FILE_NAME)?;
+        let file = write_suites(file, &metadata.test_suites)?;
+        file.finish().map_err(RunError::from)
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        Some(FILE_NAME)
+    }
+
+    fn description(&self) -> &str {
+        "JUnit XML of the test results"
+    }
+}
+
+/// Writes the report to the path given with `--junit`
+pub fn export(path: &Path, suites: &[TestSuite]) -> Result<(), RunError> {
+    let file = File::create(path)
+        .map_err(|e| RunError::OutFormat(format!("Failed to create {}: {}", path.display(), e)))?;
+    let mut file = write_suites(BufWriter::new(file), suites)?;
+    file.flush().map_err(RunError::from)
+}
+
+fn write_suites<W: Write>(w: W, suites: &[TestSuite]) -> Result<W, RunError> {
+    let mut writer = Writer::new(w);
+    write_document(&mut writer, suites)
+        .map_err(|e| RunError::OutFormat(format!("Failed to write JUnit report: {}", e)))?;
+    Ok(writer.into_inner())
+}
+
+fn write_document<W: Write>(
+    writer: &mut Writer<W>,
+    suites: &[TestSuite],
+) -> Result<(), quick_xml::Error> {
+    writer.write_event(Event::Decl(BytesDecl::new(b"1.0", Some(b"UTF-8"), None)))?;
+    let tests = suites.iter().map(TestSuite::tests).sum::<usize>();
+    let failures = suites
+        .iter()
+        .map(|s| s.count(Outcome::Failed))
+        .sum::<usize>();
+    let skipped = suites
+        .iter()
+        .map(|s| s.count(Outcome::Ignored))
+        .sum::<usize>();
+    let time = suites.iter().map(TestSuite::duration).sum::<Duration>();
+    let mut root = BytesStart::borrowed(b"testsuites", 10);
+    root.push_attribute(("name", "tarpaulin"));
+    root.push_attribute(("tests", tests.to_string().as_str()));
+    root.push_attribute(("failures", failures.to_string().as_str()));
+    root.push_attribute(("skipped", skipped.to_string().as_str()));
+    root.push_attribute(("time", seconds(time).as_str()));
+    writer.write_event(Event::Start(root))?;
+    for suite in suites {
+        write_suite(writer, suite)?;
+    }
+    writer.write_event(Event::End(BytesEnd::borrowed(b"testsuites")))?;
+    Ok(())
+}
+
+fn write_suite<W: Write>(
+    writer: &mut Writer<W>,
+    suite: &TestSuite,
+) -> Result<(), quick_xml::Error> {
+    let mut start = BytesStart::borrowed(b"testsuite", 9);
+    start.push_attribute(("name", suite.name.as_str()));
+    start.push_attribute(("tests", suite.tests().to_string().as_str()));
+    start.push_attribute((
+        "failures",
+        suite.count(Outcome::Failed).to_string().as_str(),
+    ));
+    start.push_attribute((
+        "skipped",
+        suite.count(Outcome::Ignored).to_string().as_str(),
+    ));
+    start.push_attribute(("time", seconds(suite.duration()).as_str()));
+    writer.write_event(Event::Start(start))?;
+    for case in &suite.cases {
+        let mut start = BytesStart::borrowed(b"testcase", 8);
+        start.push_attribute(("name", case.name.as_str()));
+        start.push_attribute(("classname", suite.name.as_str()));
+        if let Some(duration) = case.duration {
+            start.push_attribute(("time", seconds(duration).as_str()));
+        }
+        match case.outcome {
+            Outcome::Passed => {
+                writer.write_event(Event::Empty(start))?;
+            }
+            Outcome::Ignored => {
+                writer.write_event(Event::Start(start))?;
+                writer.write_event(Event::Empty(BytesStart::borrowed(b"skipped", 7)))?;
+                writer.write_event(Event::End(BytesEnd::borrowed(b"testcase")))?;
+            }
+            Outcome::Failed => {
+                writer.write_event(Event::Start(start))?;
+                let mut failure = BytesStart::borrowed(b"failure", 7);
+                failure.push_attribute(("message", "test failed"));
+                writer.write_event(Event::Start(failure))?;
+                if let Some(ref output) = case.output {
+                    writer.write_event(Event::Text(BytesText::from_plain_str(output)))?;
+                }
+                writer.write_event(Event::End(BytesEnd::borrowed(b"failure")))?;
+                writer.write_event(Event::End(BytesEnd::borrowed(b"testcase")))?;
+            }
+        }
+    }
+    writer.write_event(Event::End(BytesEnd::borrowed(b"testsuite")))?;
+    Ok(())
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.3}", duration.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RunType;
+
+    #[test]
+    fn failures_include_output() {
+        let suite = TestSuite::new(
+            "simple_project".to_string(),
+            RunType::Tests,
+            "test tests::works ... ok <0.250s>
+test tests::skipped ... ignored
+test tests::breaks ... FAILED <0.500s>
+
+failures:
+
+---- tests::breaks stdout ----
+thread 'tests::breaks' panicked at 'assertion failed: 1 < 0', src/lib.rs:9:9
+
+failures:
+    tests::breaks
+",
+        );
+        let xml = write_suites(vec![], &[suite]).unwrap();
+        let xml = String::from_utf8(xml).unwrap();
+        assert!(xml.starts_with(r#"<?xml version="1.0" encoding="UTF-8"?>"#));
+        assert!(xml.contains(
+            r#"<testsuites name="tarpaulin" tests="3" failures="1" skipped="1" time="0.750">"#
+        ));
+        assert!(xml.contains(
+            r#"<testcase name="tests::works" classname="simple_project" time="0.250"/>"#
+        ));
+        assert!(xml.contains("<skipped/>"));
+        assert!(xml.contains("<failure message=\"test failed\">thread "));
+        assert!(xml.contains("assertion failed: 1 &lt; 0"));
+    }
+}
@@ -1,12 +1,67 @@
-use crate::config::Config;
+//! LCOV tracefile, `--out Lcov`. When several run types are traced each one's
+//! results are written as separate records with the run type as the test
+//! name, tools reading the file combine the records of a file.
+use crate::config::{Config, PathFormat, RunType};
 use crate::errors::RunError;
+use crate::report::compress::create_report_file;
+use crate::report::{Report, RunMetadata};
 use crate::traces::{CoverageStat, TraceMap};
-use std::fs::File;
+use std::collections::BTreeMap;
 use std::io::Write;
 
+/// Name of the report file in the output directory
+pub const FILE_NAME: &str = "lcov.info";
+
+/// LCOV tracefile, `--out Lcov`
+pub struct LcovReport;
+
+impl Report for LcovReport {
+    fn render(
+        &self,
+        config: &Config,
+        traces: &TraceMap,
+        metadata: &RunMetadata,
+    ) -> Result<(), RunError> {
+        if metadata.run_types.len() > 1 {
+            export_by_run_type(&metadata.run_types, config)
+        } else {
+            export(traces, config)
+        }
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        Some(FILE_NAME)
+    }
+
+    fn description(&self) -> &str {
+        "LCOV tracefile"
+    }
+}
+
 pub fn export(coverage_data: &TraceMap, config: &Config) -> Result<(), RunError> {
-    let file_path = config.output_directory.join("lcov.info");
-    let mut file = match File::create(file_path) {
+    export_tests(&[("", coverage_data)], config)
+}
+
+/// Writes the results of each run type as its own test
+pub fn export_by_run_type(
+    run_types: &BTreeMap<RunType, TraceMap>,
+    config: &Config,
+) -> Result<(), RunError> {
+    let names = run_types
+        .keys()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    let tests = names
+        .iter()
+        .map(String::as_str)
+        .zip(run_types.values())
+        .collect::<Vec<_>>();
+    export_tests(&tests, config)
+}
+
+/// Writes a record for each file of each named test
+fn export_tests(tests: &[(&str, &TraceMap)], config: &Config) -> Result<(), RunError> {
+    let mut file = match create_report_file(config, FILE_NAME) {
         Ok(k) => k,
         Err(e) => {
             return Err(RunError::Lcov(format!(
@@ -16,9 +71,16 @@ pub fn export(coverage_data: &TraceMap, config: &Config) -> Result<(), RunError>
         }
     };
 
-    for (path, traces) in coverage_data.iter() {
-        writeln!(file, "TN:")?;
-        writeln!(file, "SF:{}", path.to_str().unwrap())?;
+    for (path, traces, test) in tests
+        .iter()
+        .flat_map(|(test, data)| data.iter().map(move |(path, traces)| (path, traces, test)))
+    {
+        writeln!(file, "TN:{}", test)?;
+        let name = match config.path_format {
+            PathFormat::Relative => config.display_path(path),
+            PathFormat::Legacy => path.display().to_string(),
+        };
+        writeln!(file, "SF:{}", name)?;
 
         let mut fns: Vec<String> = vec![];
         let mut fnda: Vec<String> = vec![];
@@ -76,5 +138,5 @@ pub fn export(coverage_data: &TraceMap, config: &Config) -> Result<(), RunError>
         writeln!(file, "end_of_record")?;
     }
 
-    Ok(())
+    file.finish().map_err(RunError::from)
 }
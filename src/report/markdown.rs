@@ -0,0 +1,219 @@
+//! Markdown summary, `--out Markdown`, a table of the coverage of each file
+//! meant to be posted as a pull request comment. With `--markdown-max-files`
+//! only the worst covered files are listed so the comment stays short, and
+//! with `--report-below` only files under that coverage, the totals are still
+//! for every file. With `--diff-base` a table of the new files follows.
+use crate::config::Config;
+use crate::errors::RunError;
+use crate::report::compress::create_report_file;
+use crate::report::{self, new_files, Report, RunMetadata};
+use crate::traces::TraceMap;
+use std::cmp::Ordering;
+use std::fmt::Write as _;
+use std::io::Write;
+
+/// Name of the report file in the output directory
+pub const FILE_NAME: &str = "tarpaulin-report.md";
+
+/// Markdown report, `--out Markdown`
+pub struct MarkdownReport;
+
+impl Report for MarkdownReport {
+    fn render(
+        &self,
+        config: &Config,
+        traces: &TraceMap,
+        metadata: &RunMetadata,
+    ) -> Result<(), RunError> {
+        let mut file = create_report_file(config, FILE_NAME)?;
+        file.write_all(summary(traces, config).as_bytes())?;
+        if let Some(ref files) = metadata.new_files {
+            file.write_all(new_files::summary(files, config.fail_under_new_files).as_bytes())?;
+        }
+        file.finish().map_err(RunError::from)
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        Some(FILE_NAME)
+    }
+
+    fn description(&self) -> &str {
+        "Markdown table of each file, for pull request comments"
+    }
+}
+
+struct Row {
+    path: String,
+    coverable: usize,
+    covered: usize,
+}
+
+impl Row {
+    fn percent(&self) -> f64 {
+        percent(self.covered, self.coverable)
+    }
+}
+
+fn percent(covered: usize, coverable: usize) -> f64 {
+    if coverable == 0 {
+        0.0
+    } else {
+        100.0 * covered as f64 / coverable as f64
+    }
+}
+
+/// Pipes would end the table cell
+fn escape(path: &str) -> String {
+    path.replace('|', "\\|")
+}
+
+fn summary(traces: &TraceMap, config: &Config) -> String {
+    let mut rows = traces
+        .files()
+        .into_iter()
+        .map(|file| Row {
+            path: config.display_path(file),
+            coverable: traces.coverable_in_path(file),
+            covered: traces.covered_in_path(file),
+        })
+        .filter(|r| r.coverable > 0)
+        .collect::<Vec<_>>();
+    let total = rows.len();
+    rows.retain(|r| report::listed(config, r.percent()));
+    if let Some(max) = config.markdown_max_files {
+        rows.sort_by(|a, b| {
+            a.percent()
+                .partial_cmp(&b.percent())
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        rows.truncate(max);
+    }
+
+    let mut s = String::new();
+    let _ = writeln!(s, "## Coverage\n");
+    let _ = writeln!(
+        s,
+        "**{:.2}%** coverage, {}/{} lines covered\n",
+        traces.coverage_percentage() * 100.0,
+        traces.total_covered(),
+        traces.total_coverable()
+    );
+    let _ = writeln!(s, "| File | Coverable | Covered | Coverage |");
+    let _ = writeln!(s, "| --- | ---: | ---: | ---: |");
+    for row in &rows {
+        let _ = writeln!(
+            s,
+            "| {} | {} | {} | {:.2}% |",
+            escape(&row.path),
+            row.coverable,
+            row.covered,
+            row.percent()
+        );
+    }
+    if rows.len() < total {
+        let hidden = total - rows.len();
+        let _ = writeln!(
+            s,
+            "| _{} more file{}_ | | | |",
+            hidden,
+            if hidden == 1 { "" } else { "s" }
+        );
+    }
+    let _ = writeln!(
+        s,
+        "| **Total** | {} | {} | {:.2}% |",
+        traces.total_coverable(),
+        traces.total_covered(),
+        percent(traces.total_covered(), traces.total_coverable())
+    );
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traces::Trace;
+    use std::path::Path;
+
+    fn traces() -> TraceMap {
+        let mut traces = TraceMap::new();
+        let files = [
+            ("src/lib.rs", 4, 3),
+            ("src/a|b.rs", 2, 0),
+            ("src/main.rs", 4, 2),
+        ];
+        for (file, lines, hit) in &files {
+            for line in 0..*lines {
+                traces.add_trace(
+                    &Path::new("/project").join(file),
+                    Trace::test_line(line + 1, if line < *hit { 1 } else { 0 }),
+                );
+            }
+        }
+        traces
+    }
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.manifest = Path::new("/project/Cargo.toml").to_path_buf();
+        config
+    }
+
+    #[test]
+    fn table_of_files() {
+        let md = summary(&traces(), &config());
+        assert_eq!(
+            md,
+            "## Coverage
+
+**50.00%** coverage, 5/10 lines covered
+
+| File | Coverable | Covered | Coverage |
+| --- | ---: | ---: | ---: |
+| src/a\\|b.rs | 2 | 0 | 0.00% |
+| src/lib.rs | 4 | 3 | 75.00% |
+| src/main.rs | 4 | 2 | 50.00% |
+| **Total** | 10 | 5 | 50.00% |
+"
+        );
+    }
+
+    #[test]
+    fn worst_files_kept() {
+        let mut config = config();
+        config.markdown_max_files = Some(2);
+        let md = summary(&traces(), &config);
+        let rows = md
+            .lines()
+            .filter(|l| l.starts_with("| src"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            vec![
+                "| src/a\\|b.rs | 2 | 0 | 0.00% |",
+                "| src/main.rs | 4 | 2 | 50.00% |"
+            ]
+        );
+        assert!(md.contains("| _1 more file_ | | | |\n| **Total** | 10 | 5 | 50.00% |"));
+    }
+
+    #[test]
+    fn files_below_threshold() {
+        let mut config = config();
+        config.report_below = Some(75.0);
+        let md = summary(&traces(), &config);
+        let rows = md
+            .lines()
+            .filter(|l| l.starts_with("| src"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            vec![
+                "| src/a\\|b.rs | 2 | 0 | 0.00% |",
+                "| src/main.rs | 4 | 2 | 50.00% |"
+            ]
+        );
+        assert!(md.contains("| _1 more file_ | | | |\n| **Total** | 10 | 5 | 50.00% |"));
+    }
+}
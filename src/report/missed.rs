@@ -0,0 +1,146 @@
+//! Splits uncovered lines into those inside functions the tests entered and
+//! those in functions which were never called. The first usually needs extra
+//! assertions or inputs, the second a whole new test.
+use crate::config::Config;
+use crate::report::line_ranges;
+use crate::traces::{amount_coverable, amount_covered, Function, Trace, TraceMap};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MissedKind {
+    /// The enclosing function was entered but this line wasn't hit
+    Missed,
+    /// The enclosing function was never entered
+    NeverEntered,
+}
+
+pub(crate) fn is_uncovered(trace: &Trace) -> bool {
+    amount_coverable(&[trace]) > amount_covered(&[trace])
+}
+
+/// Classifies the uncovered lines of a single file. A line belongs to the
+/// innermost function whose lines in the debug info span it, see
+/// `TraceMap::add_function`, and a function counts as entered if any of its
+/// lines were hit. Lines outside every function, such as those of functions
+/// which were only ever inlined into other files, are never entered.
+pub fn classify(traces: &[Trace], functions: &[&Function]) -> BTreeMap<u64, MissedKind> {
+    let contains = |f: &Function, line| line >= f.start && line <= f.end;
+    let mut result = BTreeMap::new();
+    for trace in traces.iter().filter(|t| is_uncovered(t)) {
+        let function = functions
+            .iter()
+            .filter(|f| contains(f, trace.line))
+            .min_by_key(|f| f.end - f.start);
+        let entered = function.map_or(false, |f| {
+            let body = traces
+                .iter()
+                .filter(|t| contains(f, t.line))
+                .collect::<Vec<_>>();
+            amount_covered(&body) > 0
+        });
+        let kind = if entered {
+            MissedKind::Missed
+        } else {
+            MissedKind::NeverEntered
+        };
+        result.insert(trace.line, kind);
+    }
+    result
+}
+
+pub fn print_missed_analysis(config: &Config, result: &TraceMap) {
+    println!("|| Missed Line Analysis:");
+    for (path, traces) in result.iter() {
+        let classified = classify(traces, &result.get_functions(path));
+        if classified.is_empty() {
+            continue;
+        }
+        let lines_of = |kind| {
+            line_ranges(
+                classified
+                    .iter()
+                    .filter(|(_, k)| **k == kind)
+                    .map(|(l, _)| *l)
+                    .collect(),
+            )
+        };
+        let missed = lines_of(MissedKind::Missed);
+        let never_entered = lines_of(MissedKind::NeverEntered);
+        println!("|| {}:", config.display_path(path));
+        if !missed.is_empty() {
+            println!("||   missed in entered functions: {}", missed.join(", "));
+        }
+        if !never_entered.is_empty() {
+            println!("||   functions never entered: {}", never_entered.join(", "));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn function(start: u64, end: u64) -> Function {
+        Function {
+            name: String::new(),
+            start,
+            end,
+        }
+    }
+
+    #[test]
+    fn entered_and_never_entered() {
+        let traces = vec![
+            Trace::test_line(1, 1),
+            Trace::test_line(2, 1),
+            Trace::test_line(3, 0),
+            Trace::test_line(6, 0),
+            Trace::test_line(7, 0),
+        ];
+        let functions = vec![function(1, 3), function(6, 7)];
+        let res = classify(&traces, &functions.iter().collect::<Vec<_>>());
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[&3], MissedKind::Missed);
+        assert_eq!(res[&6], MissedKind::NeverEntered);
+        assert_eq!(res[&7], MissedKind::NeverEntered);
+    }
+
+    #[test]
+    fn inlined_prologue() {
+        // Entry point line was inlined away so has no hits but the body ran
+        let traces = vec![
+            Trace::test_line(1, 0),
+            Trace::test_line(2, 3),
+            Trace::test_line(3, 0),
+            Trace::test_line(5, 0),
+            Trace::test_line(6, 0),
+        ];
+        let functions = vec![function(1, 3), function(5, 6)];
+        let res = classify(&traces, &functions.iter().collect::<Vec<_>>());
+        assert_eq!(res[&1], MissedKind::Missed);
+        assert_eq!(res[&3], MissedKind::Missed);
+        assert_eq!(res[&5], MissedKind::NeverEntered);
+        assert_eq!(res[&6], MissedKind::NeverEntered);
+    }
+
+    #[test]
+    fn nested_functions() {
+        // A function declared inside another belongs to neither, and a line
+        // outside every function was never entered
+        let traces = vec![
+            Trace::test_line(2, 1),
+            Trace::test_line(4, 0),
+            Trace::test_line(5, 0),
+            Trace::test_line(7, 0),
+            Trace::test_line(10, 0),
+        ];
+        let functions = vec![function(1, 8), function(4, 5)];
+        let res = classify(&traces, &functions.iter().collect::<Vec<_>>());
+        assert_eq!(res[&4], MissedKind::NeverEntered);
+        assert_eq!(res[&5], MissedKind::NeverEntered);
+        assert_eq!(res[&7], MissedKind::Missed);
+        assert_eq!(res[&10], MissedKind::NeverEntered);
+    }
+}
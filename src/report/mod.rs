@@ -1,43 +1,487 @@
 use crate::config::*;
 use crate::errors::*;
-use crate::test_loader::TracerData;
+use crate::line_map::SourceSnapshot;
+use crate::test_results::TestSuite;
 use crate::traces::*;
-use log::{error, info};
-use serde::Serialize;
-use std::fs::{create_dir_all, File};
-use std::io::BufReader;
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, create_dir_all, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock};
+use targets::{
+    print_bin_runs, print_flaky_binaries, print_target_summary, BinRun, FlakyBinary, TargetSummary,
+};
 
+pub mod age;
+pub mod badge;
+pub mod clover;
 pub mod cobertura;
+pub mod codecov;
+pub mod compress;
 pub mod coveralls;
+pub mod delta;
+pub mod directories;
+pub mod github;
+pub mod history;
 pub mod html;
+pub mod html_split;
 pub mod json;
+pub mod junit;
 pub mod lcov;
+pub mod markdown;
+pub mod missed;
+pub mod new_files;
+pub mod overhead;
 mod safe_json;
-/// Trait for report formats to implement.
-/// Currently reports must be serializable using serde
-pub trait Report<Out: Serialize> {
-    /// Export coverage report
-    fn export(coverage_data: &[TracerData], config: &Config);
+#[cfg(feature = "serve")]
+pub mod serve;
+pub mod sonar;
+pub mod suggest;
+pub mod targets;
+pub mod teamcity;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod weighted;
+/// Information about the run passed to reports alongside the results
+#[derive(Clone, Debug, Default)]
+pub struct RunMetadata {
+    /// What each run type contributed, may be empty
+    pub targets: Vec<TargetSummary>,
+    /// Configs that weren't run because of their environment conditions
+    pub skipped_configs: Vec<SkippedConfig>,
+    /// Number of decision points in each source file
+    pub branch_points: BTreeMap<PathBuf, usize>,
+    /// Lines `pub` functions are declared on in each source file
+    pub public_functions: BTreeMap<PathBuf, BTreeSet<u64>>,
+    /// Lines the source analysis ignored in each source file, such as tests
+    /// with `--ignore-tests`, filled in along with `branch_points`
+    pub ignored_lines: BTreeMap<PathBuf, BTreeSet<u64>>,
+    /// Weights of the lines of each source file which weigh more than 1, only
+    /// filled in with `--weighted-coverage`
+    pub line_weights: weighted::LineWeights,
+    /// Lines which aren't coverable as they're under a `cfg` which is false
+    /// for the target
+    pub non_target_lines: usize,
+    /// Files which couldn't be parsed and had a textual analysis instead
+    pub unparsed_files: usize,
+    /// Lines of `debug_assert!` calls, only coverable with
+    /// `--count-debug-asserts on`
+    pub debug_assert_lines: usize,
+    /// Results where only the hits from the `--threshold-run-types` are
+    /// covered, `None` without them
+    pub gated: Option<TraceMap>,
+    /// Lines of type definitions with derive generated code that isn't
+    /// counted, see `--include-derives`
+    pub derived_lines: usize,
+    /// Slowdown of the tests when traced, only measured with
+    /// `--measure-overhead`
+    pub overhead: Option<overhead::OverheadSummary>,
+    /// Test binaries which passed after being retried with `--retries`
+    pub flaky: Vec<FlakyBinary>,
+    /// Results of the tests in each binary, only captured for the JUnit report
+    pub test_suites: Vec<TestSuite>,
+    /// Bins run for the `Bins` run type and the arguments they were given
+    pub bin_runs: Vec<BinRun>,
+    /// Coverage of the files added since `--diff-base`, `None` without a diff
+    /// base
+    pub new_files: Option<Vec<new_files::NewFile>>,
+    /// Coverage of the lines by how long ago they were changed, `None`
+    /// without `--age-buckets`
+    pub age_buckets: Option<Vec<age::AgeBucket>>,
+    /// Change in coverage since the run of the `--compare` report
+    pub delta: Option<delta::CoverageDelta>,
+    /// Results of each run type the reported results were merged from, empty
+    /// if they aren't known such as for saved results
+    pub run_types: BTreeMap<RunType, TraceMap>,
+    /// Files removed from saved results when reporting them, `None` for the
+    /// results of a run
+    pub post_filter: Option<PostFilter>,
+}
+
+/// Files removed from saved results as they're reported, the saved results
+/// are left as they were
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct PostFilter {
+    /// The `--exclude-files` patterns applied
+    pub patterns: Vec<String>,
+    /// Number of files removed, including those outside the project
+    pub removed_files: usize,
+}
+
+/// A config skipped by its `required-env` or `skip-if-env` conditions
+#[derive(Clone, Debug, Serialize)]
+pub struct SkippedConfig {
+    pub name: String,
+    pub reason: String,
+}
+
+/// Trait for report formats to implement. The built in formats each have an
+/// `OutputFormat` variant, other formats are registered by name with
+/// `register_report` and generated when that name is passed to `--out`.
+pub trait Report: Send + Sync {
+    /// Render the report for the results, usually writing a file to the
+    /// output directory
+    fn render(
+        &self,
+        config: &Config,
+        traces: &TraceMap,
+        metadata: &RunMetadata,
+    ) -> Result<(), RunError>;
+
+    /// Name of the file the report writes in the output directory, if any.
+    /// Used to list the outputs with `--dry-run`.
+    fn file_name(&self) -> Option<&str> {
+        None
+    }
+
+    /// Whether the report prints output for another tool to read to stdout,
+    /// only one of these can be generated in a run as their output would be
+    /// interleaved
+    fn prints_to_stdout(&self) -> bool {
+        false
+    }
+
+    /// One line description of the format listed by `--out help`
+    fn description(&self) -> &str {
+        ""
+    }
+}
+
+lazy_static! {
+    static ref REPORTS: RwLock<BTreeMap<String, Arc<dyn Report>>> = RwLock::new(BTreeMap::new());
+}
+
+/// Registers a custom report format under the given name, replacing any
+/// format already registered with that name. The names of the built in formats
+/// are parsed as those formats so they can't be replaced. Register formats
+/// before calling `run` or parsing the configs. Names are case insensitive.
+pub fn register_report(name: &str, report: impl Report + 'static) {
+    REPORTS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(name.to_ascii_lowercase(), Arc::new(report));
+}
+
+/// Gets the report for a format, `None` for a custom format which isn't
+/// registered
+pub fn get_report(format: &OutputFormat) -> Option<Arc<dyn Report>> {
+    let report: Arc<dyn Report> = match format {
+        OutputFormat::Json => Arc::new(json::JsonReport),
+        OutputFormat::Stdout => Arc::new(ConsoleReport),
+        OutputFormat::Xml => Arc::new(cobertura::CoberturaReport),
+        OutputFormat::Html => Arc::new(html::HtmlReport),
+        OutputFormat::Lcov => Arc::new(lcov::LcovReport),
+        OutputFormat::Codecov => Arc::new(codecov::CodecovReport),
+        OutputFormat::Github => Arc::new(github::GithubReport),
+        OutputFormat::Junit => Arc::new(junit::JunitReport),
+        OutputFormat::Markdown => Arc::new(markdown::MarkdownReport),
+        OutputFormat::Badge => Arc::new(badge::BadgeReport),
+        OutputFormat::Clover => Arc::new(clover::CloverReport),
+        OutputFormat::Sonar => Arc::new(sonar::SonarReport),
+        OutputFormat::Teamcity => Arc::new(teamcity::TeamcityReport),
+        OutputFormat::Custom(name) => {
+            return REPORTS
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .get(&name.to_ascii_lowercase())
+                .cloned()
+        }
+    };
+    Some(report)
+}
+
+/// Names of all the report formats, the built in ones and those registered
+pub fn registered_reports() -> Vec<String> {
+    let mut names = OutputFormat::variants()
+        .iter()
+        .map(|name| name.to_ascii_lowercase())
+        .collect::<BTreeSet<_>>();
+    names.extend(
+        REPORTS
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .keys()
+            .cloned(),
+    );
+    names.into_iter().collect()
+}
+
+/// Number of single character edits to turn one name into the other
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substituted = previous + if ca == *cb { 0 } else { 1 };
+            previous = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Error for a format which isn't registered, naming the closest registered
+/// format if it looks like a typo of one
+pub fn unknown_format(name: &str) -> String {
+    let lowercase = name.to_ascii_lowercase();
+    let closest = registered_reports()
+        .into_iter()
+        .map(|r| (edit_distance(&lowercase, &r), r))
+        .filter(|(distance, r)| *distance <= 2 && *distance < r.len())
+        .min();
+    match closest {
+        Some((_, r)) => format!(
+            "no report format is registered for {}, did you mean {}?",
+            name, r
+        ),
+        None => format!(
+            "no report format is registered for {}, `--out help` lists the formats",
+            name
+        ),
+    }
+}
+
+/// The registered formats and their descriptions for `--out help`
+pub fn format_list() -> String {
+    let mut list = String::from("Output formats:\n");
+    for name in registered_reports() {
+        let description = name
+            .parse()
+            .ok()
+            .and_then(|format| get_report(&format))
+            .map(|r| r.description().to_string())
+            .unwrap_or_default();
+        list.push_str(&format!("    {:<12}{}\n", name, description));
+    }
+    list
+}
+
+/// Reads a source file for a report, invalid UTF-8 is replaced rather than
+/// failing so the rest of the file can still be shown
+pub(crate) fn read_source(path: &Path) -> io::Result<String> {
+    fs::read(path).map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Source files of the results which can't be read, the reports show them
+/// without their source
+fn unreadable_sources(result: &TraceMap) -> Vec<(&PathBuf, io::Error)> {
+    result
+        .files()
+        .into_iter()
+        .filter_map(|path| fs::read(path).err().map(|e| (path, e)))
+        .collect()
+}
+
+/// Warns about the source files the reports couldn't read, it's only an error
+/// with `--strict-reporting`
+fn check_sources(config: &Config, result: &TraceMap) -> Result<(), RunError> {
+    let unreadable = unreadable_sources(result);
+    for (path, e) in &unreadable {
+        warn!(
+            "Unable to read {} for the reports, it's reported without its source: {}",
+            config.display_path(path),
+            e
+        );
+    }
+    if config.strict_reporting && !unreadable.is_empty() {
+        Err(RunError::CovReport(format!(
+            "{} source files couldn't be read: {}",
+            unreadable.len(),
+            unreadable
+                .iter()
+                .map(|(path, _)| config.display_path(path))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// The formats requested with `--out` other than the console summary, each
+/// only once however many times and in whatever case it was given
+pub fn requested_formats(config: &Config) -> Vec<&OutputFormat> {
+    let mut seen = BTreeSet::new();
+    config
+        .generate
+        .iter()
+        .filter(|g| **g != OutputFormat::Stdout)
+        .filter(|g| seen.insert(g.name().to_ascii_lowercase()))
+        .collect()
+}
+
+/// Fails if more than one of the requested formats prints to stdout for
+/// another tool to read
+pub fn check_stdout_formats(config: &Config) -> Result<(), RunError> {
+    let formats = requested_formats(config)
+        .into_iter()
+        .filter(|g| get_report(g).map_or(false, |r| r.prints_to_stdout()))
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    if formats.len() > 1 {
+        Err(RunError::Report(format!(
+            "Output formats {} all print to stdout and can't be used in the same run",
+            formats.join(", ")
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// The summary printed to stdout, this is always rendered
+pub struct ConsoleReport;
+
+impl Report for ConsoleReport {
+    fn render(
+        &self,
+        config: &Config,
+        traces: &TraceMap,
+        metadata: &RunMetadata,
+    ) -> Result<(), RunError> {
+        if config.verbose {
+            print_missing_lines(config, traces);
+        }
+        if config.missed_analysis {
+            missed::print_missed_analysis(config, traces);
+        }
+        if !metadata.targets.is_empty() {
+            print_target_summary(&metadata.targets);
+        }
+        if !metadata.bin_runs.is_empty() {
+            print_bin_runs(&metadata.bin_runs);
+        }
+        if !metadata.flaky.is_empty() {
+            print_flaky_binaries(config, &metadata.flaky);
+        }
+        if let Some(ref summary) = metadata.overhead {
+            overhead::print_overhead(config, summary);
+        }
+        print_function_coverage(config, traces);
+        if let Some(ref buckets) = metadata.age_buckets {
+            age::print_age_buckets(buckets);
+        }
+        if let Some(ref delta) = metadata.delta {
+            delta::print_delta(config, delta);
+        }
+        print_summary(config, traces, metadata);
+        let sampled = traces.total_sampled();
+        if config.count && sampled > 0 {
+            println!(
+                "|| Hit counts are approximate for {} lines where only some addresses were instrumented, see --max-addresses-per-line",
+                sampled
+            );
+        }
+        if metadata.non_target_lines > 0 {
+            println!(
+                "|| {} lines excluded as non-target code",
+                metadata.non_target_lines
+            );
+        }
+        if metadata.unparsed_files > 0 {
+            println!(
+                "|| {} files couldn't be parsed and had a less accurate textual analysis, upgrading tarpaulin may fix this",
+                metadata.unparsed_files
+            );
+        }
+        if metadata.debug_assert_lines > 0 {
+            if config.count_debug_asserts {
+                println!(
+                    "|| {} lines are debug assertions which don't exist in release builds, see --count-debug-asserts",
+                    metadata.debug_assert_lines
+                );
+            } else {
+                println!(
+                    "|| {} lines excluded as debug assertions",
+                    metadata.debug_assert_lines
+                );
+            }
+        }
+        if metadata.derived_lines > 0 {
+            println!(
+                "|| {} lines excluded as derive-generated code",
+                metadata.derived_lines
+            );
+        }
+        print_only_ignored(config, traces);
+        if let Some(ref filter) = metadata.post_filter {
+            println!(
+                "|| {} files removed from the saved results, excluded files: {}",
+                filter.removed_files,
+                filter.patterns.join(", ")
+            );
+        }
+        if !metadata.skipped_configs.is_empty() {
+            println!("|| Skipped configs, coverage is partial:");
+            for skipped in &metadata.skipped_configs {
+                println!("|| {}: {}", skipped.name, skipped.reason);
+            }
+        }
+        if config.profile_hits {
+            print_hit_spans(config, traces);
+        }
+        suggest::print_suggestions(&suggest::suggestions(config, traces, metadata));
+        Ok(())
+    }
+
+    fn description(&self) -> &str {
+        "Summary printed to the console, always shown"
+    }
 }
 
 /// Reports the test coverage using the users preferred method. See config.rs
 /// or help text for details.
-pub fn report_coverage(config: &Config, result: &TraceMap) -> Result<(), RunError> {
+pub fn report_coverage(
+    config: &Config,
+    result: &TraceMap,
+    metadata: &RunMetadata,
+) -> Result<(), RunError> {
+    check_stdout_formats(config)?;
     if !result.is_empty() {
         info!("Coverage Results:");
-        if config.verbose {
-            print_missing_lines(config, result);
-        }
-        print_summary(config, result);
-        generate_requested_reports(config, result)?;
-        if let Some(project_dir) = config.manifest.parent() {
-            let mut report_dir = project_dir.join("target");
-            report_dir.push("tarpaulin");
-            report_dir.push("coverage.json");
-            let file = File::create(&report_dir)
-                .map_err(|_| RunError::CovReport("Failed to create run report".to_string()))?;
-            serde_json::to_writer(&file, &result)
-                .map_err(|_| RunError::CovReport("Failed to save run report".to_string()))?;
+        render_report(&OutputFormat::Stdout, config, result, metadata)?;
+        // The summary is printed first so it's shown even if writing the
+        // files fails, e.g. when the disk is full. One file failing doesn't
+        // stop the rest being attempted, the first error is returned.
+        let mut failure = None;
+        let mut written = vec![];
+        keep_first_error(
+            &mut failure,
+            generate_requested_reports(config, result, metadata, &mut written),
+        );
+        if let Some(ref history) = config.append_history {
+            let appended = history::append(history, config, result);
+            if appended.is_ok() {
+                written.push(history.clone());
+            }
+            keep_first_error(&mut failure, appended);
+        }
+        let path = config.tarpaulin_dir().join("coverage.json");
+        let saved =
+            save_results(&path, result).map_err(|e| RunError::CovReport(write_error(&path, &e)));
+        keep_first_error(&mut failure, saved);
+        keep_first_error(&mut failure, check_sources(config, result));
+        if !written.is_empty() {
+            // Absolute paths so scripts can find the files wherever they run
+            let written = written
+                .iter()
+                .map(|p| {
+                    p.canonicalize()
+                        .unwrap_or_else(|_| config.invocation_dir.join(p))
+                })
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>();
+            info!("Reports written: {}", written.join(", "));
+        }
+        if let Some(e) = failure {
+            return Err(e);
+        }
+        if config.tui {
+            browse_results(config, result)?;
         }
         Ok(())
     } else if !config.no_run {
@@ -49,49 +493,242 @@ pub fn report_coverage(config: &Config, result: &TraceMap) -> Result<(), RunErro
     }
 }
 
-fn generate_requested_reports(config: &Config, result: &TraceMap) -> Result<(), RunError> {
+#[cfg(feature = "tui")]
+fn browse_results(config: &Config, result: &TraceMap) -> Result<(), RunError> {
+    if nix::unistd::isatty(libc::STDOUT_FILENO).unwrap_or(false) {
+        tui::browse(config, result)
+    } else {
+        info!("Stdout isn't a terminal, skipping terminal UI");
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "tui"))]
+fn browse_results(_config: &Config, _result: &TraceMap) -> Result<(), RunError> {
+    log::warn!("Tarpaulin was built without the tui feature, skipping terminal UI");
+    Ok(())
+}
+
+/// Serves the results saved in `dir` over HTTP on `address` until the process
+/// is stopped, `cargo tarpaulin serve`
+#[cfg(feature = "serve")]
+pub fn serve_results(config: &Config, dir: &Path, address: &str) -> Result<(), RunError> {
+    serve::run(config, dir, address)
+}
+
+#[cfg(not(feature = "serve"))]
+pub fn serve_results(_config: &Config, _dir: &Path, _address: &str) -> Result<(), RunError> {
+    Err(RunError::Serve(
+        "Tarpaulin was built without the serve feature".to_string(),
+    ))
+}
+
+/// Writes the reports for results saved by a previous run without running the
+/// tests, `cargo tarpaulin report`. `path` is the saved results or the
+/// directory they're in. Files excluded by the config are left out of the
+/// reports, the saved results aren't changed.
+pub fn report_saved_results(config: &Config, path: &Path) -> Result<(), RunError> {
+    check_stdout_formats(config)?;
+    let path = if path.is_dir() {
+        path.join("coverage.json")
+    } else {
+        path.to_path_buf()
+    };
+    let mut result = read_saved_results(config, &path)
+        .ok_or_else(|| RunError::CovReport(format!("No saved results in {}", path.display())))?;
+    let metadata = RunMetadata {
+        post_filter: filter_saved_results(config, &mut result),
+        ..RunMetadata::default()
+    };
+    if result.is_empty() {
+        return Err(RunError::CovReport(
+            "No results left once the excluded files were removed".to_string(),
+        ));
+    }
+    info!("Coverage Results:");
+    render_report(&OutputFormat::Stdout, config, &result, &metadata)?;
+    let mut written = vec![];
+    let generated = generate_requested_reports(config, &result, &metadata, &mut written);
+    if !written.is_empty() {
+        let written = written
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect::<Vec<_>>();
+        info!("Reports written: {}", written.join(", "));
+    }
+    generated
+}
+
+/// Removes the files the config excludes from saved results, which may have
+/// been saved with other exclusions: those matching `--exclude-files` and
+/// those outside the project. `None` if nothing was removed.
+pub(crate) fn filter_saved_results(config: &Config, result: &mut TraceMap) -> Option<PostFilter> {
+    let root = config.root();
+    let removed_files =
+        result.remove_files(|path| !path.starts_with(&root) || config.excludes_file(path));
+    if removed_files == 0 {
+        return None;
+    }
+    info!(
+        "Removed {} excluded files from the saved results",
+        removed_files
+    );
+    Some(PostFilter {
+        patterns: config.excluded_file_patterns().to_vec(),
+        removed_files,
+    })
+}
+
+fn render_report(
+    format: &OutputFormat,
+    config: &Config,
+    result: &TraceMap,
+    metadata: &RunMetadata,
+) -> Result<(), RunError> {
+    match get_report(format) {
+        Some(report) => report.render(config, result, metadata),
+        None => Err(RunError::OutFormat(unknown_format(format.name()))),
+    }
+}
+
+fn generate_requested_reports(
+    config: &Config,
+    result: &TraceMap,
+    metadata: &RunMetadata,
+    written: &mut Vec<PathBuf>,
+) -> Result<(), RunError> {
+    let output_dir = config.output_dir();
+    if create_dir_all(&output_dir).is_err() {
+        return Err(RunError::OutFormat(format!(
+            "Failed to create or locate custom output directory: {:?}",
+            output_dir,
+        )));
+    }
     if config.is_coveralls() {
         coveralls::export(result, config)?;
         info!("Coverage data sent");
     }
+    if config.codecov_upload {
+        codecov::upload(result, config)?;
+    }
 
-    if !config.is_default_output_dir() {
-        if create_dir_all(&config.output_directory).is_err() {
-            return Err(RunError::OutFormat(format!(
-                "Failed to create or locate custom output directory: {:?}",
-                config.output_directory,
-            )));
+    // The console report has already been printed
+    let mut failure = None;
+    for g in requested_formats(config) {
+        let rendered = render_report(g, config, result, metadata);
+        if rendered.is_ok() {
+            written.extend(get_report(g).and_then(|r| output_path(config, g, &*r)));
+        }
+        keep_first_error(&mut failure, rendered);
+    }
+    if let Some(ref path) = config.junit {
+        let exported = junit::export(path, &metadata.test_suites);
+        if exported.is_ok() {
+            written.push(path.clone());
         }
+        keep_first_error(&mut failure, exported);
     }
+    if let Some(ref path) = config.badge {
+        let exported = badge::export(path, result, config);
+        if exported.is_ok() {
+            written.push(path.clone());
+        }
+        keep_first_error(&mut failure, exported);
+    }
+    failure.map_or(Ok(()), Err)
+}
 
-    for g in &config.generate {
-        match *g {
-            OutputFile::Xml => {
-                cobertura::report(result, config).map_err(|e| RunError::XML(e))?;
-            }
-            OutputFile::Html => {
-                html::export(result, config)?;
-            }
-            OutputFile::Lcov => {
-                lcov::export(result, config)?;
-            }
-            OutputFile::Json => {
-                json::export(result, config)?;
-            }
-            _ => {
-                return Err(RunError::OutFormat(
-                    "Output format is currently not supported!".to_string(),
-                ));
-            }
+/// File a report writes, for the split HTML report this is its index page
+pub(crate) fn output_path(
+    config: &Config,
+    format: &OutputFormat,
+    report: &dyn Report,
+) -> Option<PathBuf> {
+    if *format == OutputFormat::Html && config.html_split {
+        Some(
+            config
+                .output_dir()
+                .join(html_split::DIR_NAME)
+                .join("index.html"),
+        )
+    } else {
+        report
+            .file_name()
+            .map(|name| compress::report_path(config, name))
+    }
+}
+
+/// Keeps the first error so the remaining outputs can still be written, later
+/// errors are only logged
+fn keep_first_error(first: &mut Option<RunError>, res: Result<(), RunError>) {
+    if let Err(e) = res {
+        match first {
+            None => *first = Some(e),
+            Some(_) => error!("{}", e),
         }
     }
-    Ok(())
+}
+
+/// Saves the results so the next run can compare against them
+fn save_results(path: &Path, result: &TraceMap) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(&mut file, &SavedResults::new(result))?;
+    file.flush()
+}
+
+/// Groups the files in the results by the package that contains them. Files
+/// are assigned to the package with the deepest manifest directory containing
+/// them so nested workspace members aren't attributed to their parent.
+pub(crate) fn files_by_package<'a>(
+    config: &Config,
+    result: &'a TraceMap,
+) -> BTreeMap<String, Vec<&'a PathBuf>> {
+    let packages = config
+        .get_packages()
+        .into_iter()
+        .filter_map(|p| {
+            p.manifest_path
+                .parent()
+                .map(|x| (p.name.clone(), x.to_path_buf()))
+        })
+        .collect::<Vec<_>>();
+    let mut files: BTreeMap<String, Vec<&PathBuf>> = BTreeMap::new();
+    for file in result.files() {
+        let owner = packages
+            .iter()
+            .filter(|(_, dir)| file.starts_with(dir))
+            .max_by_key(|(_, dir)| dir.components().count());
+        if let Some((name, _)) = owner {
+            files.entry(name.clone()).or_default().push(file);
+        }
+    }
+    files
+}
+
+/// Coverage percentage ranging from 0.0-100.0 of each package in the results
+pub(crate) fn package_percentages(config: &Config, result: &TraceMap) -> BTreeMap<String, f64> {
+    files_by_package(config, result)
+        .into_iter()
+        .map(|(name, files)| {
+            let traces = files
+                .iter()
+                .flat_map(|x| result.get_child_traces(x))
+                .collect::<Vec<_>>();
+            let coverable = amount_coverable(&traces);
+            let percent = if coverable == 0 {
+                0.0
+            } else {
+                100.0 * amount_covered(&traces) as f64 / coverable as f64
+            };
+            (name, percent)
+        })
+        .collect()
 }
 
 fn print_missing_lines(config: &Config, result: &TraceMap) {
     println!("|| Uncovered Lines:");
     for (ref key, ref value) in result.iter() {
-        let path = config.strip_base_dir(key);
+        let path = config.display_path(key);
         let mut uncovered_lines = vec![];
         for v in value.iter() {
             match v.stats {
@@ -101,69 +738,150 @@ fn print_missing_lines(config: &Config, result: &TraceMap) {
                 _ => (),
             }
         }
-        uncovered_lines.sort();
-        let (groups, last_group) = uncovered_lines
-            .into_iter()
-            .fold((vec![], vec![]), accumulate_lines);
-        let (groups, _) = accumulate_lines((groups, last_group), u64::max_value());
+        let groups = line_ranges(uncovered_lines);
         if !groups.is_empty() {
-            println!("|| {}: {}", path.display(), groups.join(", "));
+            println!("|| {}: {}", path, groups.join(", "));
+        }
+    }
+}
+
+/// How many functions in each file were entered, with `--verbose` the ones
+/// which never were are named
+fn print_function_coverage(config: &Config, result: &TraceMap) {
+    let files = result
+        .files()
+        .into_iter()
+        .map(|f| (f, result.function_coverage(f)))
+        .filter(|(_, functions)| !functions.is_empty())
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        return;
+    }
+    println!("|| Entered/Total Functions:");
+    for (file, functions) in &files {
+        let entered = functions.iter().filter(|f| f.entered).count();
+        println!(
+            "|| {}: {}/{}",
+            config.display_path(file),
+            entered,
+            functions.len()
+        );
+        if config.verbose && entered < functions.len() {
+            let never_entered = functions
+                .iter()
+                .filter(|f| !f.entered)
+                .map(|f| format!("{} (line {})", f.name, f.line))
+                .collect::<Vec<_>>();
+            println!("||     never entered: {}", never_entered.join(", "));
         }
     }
 }
 
+/// Lists the lines covered only by ignored tests, which are only run with
+/// `--ignored`
+fn print_only_ignored(config: &Config, result: &TraceMap) {
+    let only_ignored = result.only_ignored_lines();
+    if only_ignored.is_empty() {
+        return;
+    }
+    println!("|| Lines covered only by ignored tests:");
+    for (path, lines) in &only_ignored {
+        let groups = line_ranges(lines.iter().copied().collect());
+        println!("|| {}: {}", config.display_path(path), groups.join(", "));
+    }
+}
+
+fn print_hit_spans(config: &Config, result: &TraceMap) {
+    println!("|| Longest time between first and last hit:");
+    for (path, trace) in result.longest_hit_spans(10) {
+        if let Some(span) = trace.hit_span {
+            println!(
+                "|| {}:{}: {}ms ({}ms-{}ms)",
+                config.display_path(path),
+                trace.line,
+                span.duration(),
+                span.first,
+                span.last
+            );
+        }
+    }
+}
+
+/// Results saved in `target/tarpaulin/coverage.json` for the next run to
+/// compare against, with the source they were traced from
+#[derive(Deserialize, Serialize)]
+struct SavedResults {
+    #[serde(flatten)]
+    traces: TraceMap,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    sources: BTreeMap<PathBuf, SourceSnapshot>,
+}
+
+impl SavedResults {
+    fn new(traces: &TraceMap) -> Self {
+        let sources = traces
+            .files()
+            .into_iter()
+            .filter_map(|path| Some((path.clone(), SourceSnapshot::read(path).ok()?)))
+            .collect();
+        SavedResults {
+            traces: traces.clone(),
+            sources,
+        }
+    }
+}
+
+/// Reads results saved by a previous run, `None` if the file can't be read or
+/// isn't saved results. Traces of files changed since are dropped or moved as
+/// for merging.
+pub(crate) fn read_saved_results(config: &Config, path: &Path) -> Option<TraceMap> {
+    let reader = compress::open_maybe_compressed(path).ok()?;
+    let mut saved: SavedResults = serde_json::from_reader(reader).ok()?;
+    saved
+        .traces
+        .align_to_sources(&saved.sources, config.merge_with_diff, |path| {
+            std::fs::read_to_string(path).ok()
+        });
+    Some(saved.traces)
+}
+
 fn get_previous_result(config: &Config) -> Option<TraceMap> {
     // Check for previous report
-    if let Some(project_dir) = config.manifest.parent() {
-        let mut report_dir = project_dir.join("target");
-        report_dir.push("tarpaulin");
-        if report_dir.exists() {
-            // is report there?
-            report_dir.push("coverage.json");
-            let file = File::open(&report_dir).ok()?;
-            let reader = BufReader::new(file);
-            serde_json::from_reader(reader).ok()
-        } else {
-            // make directory
-            std::fs::create_dir(&report_dir)
-                .unwrap_or_else(|e| error!("Failed to create report directory: {}", e));
-            None
-        }
+    let mut report_dir = config.tarpaulin_dir();
+    if report_dir.exists() {
+        // is report there?
+        report_dir.push("coverage.json");
+        read_saved_results(config, &report_dir)
     } else {
+        // make directory
+        create_dir_all(&report_dir)
+            .unwrap_or_else(|e| error!("Failed to create report directory: {}", e));
         None
     }
 }
 
-fn print_summary(config: &Config, result: &TraceMap) {
+fn print_summary(config: &Config, result: &TraceMap, metadata: &RunMetadata) {
+    // The changes since the saved results are replaced by the changes since
+    // the `--compare` report
     let last = match get_previous_result(config) {
-        Some(l) => l,
-        None => TraceMap::new(),
+        Some(l) if metadata.delta.is_none() => l,
+        _ => TraceMap::new(),
     };
     println!("|| Tested/Total Lines:");
-    for file in result.files() {
-        let path = config.strip_base_dir(file);
-        if last.contains_file(file) {
-            let last_percent = coverage_percentage(&last.get_child_traces(file));
-            let current_percent = coverage_percentage(&result.get_child_traces(file));
-            let delta = 100.0f64 * (current_percent - last_percent);
-            println!(
-                "|| {}: {}/{} {:+}%",
-                path.display(),
-                result.covered_in_path(&file),
-                result.coverable_in_path(&file),
-                delta
-            );
-        } else {
-            println!(
-                "|| {}: {}/{}",
-                path.display(),
-                result.covered_in_path(&file),
-                result.coverable_in_path(&file)
-            );
-        }
+    match config.report_by {
+        ReportBy::File => print_file_rows(config, result, &last, metadata),
+        ReportBy::Dir(depth) => print_directory_rows(config, result, &last, depth),
     }
     let percent = result.coverage_percentage() * 100.0f64;
-    if last.is_empty() {
+    if let Some(ref delta) = metadata.delta {
+        println!(
+            "|| \n{:.2}% coverage, {}/{} lines covered, {:+.2}% change since the compared run",
+            percent,
+            result.total_covered(),
+            result.total_coverable(),
+            delta.change()
+        );
+    } else if last.is_empty() {
         println!(
             "|| \n{:.2}% coverage, {}/{} lines covered",
             percent,
@@ -180,6 +898,183 @@ fn print_summary(config: &Config, result: &TraceMap) {
             delta
         );
     }
+    if config.weighted_coverage {
+        let (_, total) = weighted::coverage(result, &metadata.line_weights);
+        println!(
+            "{:.2}% weighted coverage, {}/{} weighted lines covered",
+            total.percent(),
+            total.covered,
+            total.coverable
+        );
+    }
+    if let Some(ref gated) = metadata.gated {
+        let types = config
+            .threshold_run_types
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        println!(
+            "{:.2}% gated coverage, {}/{} lines covered by {}",
+            gated.coverage_percentage() * 100.0,
+            gated.total_covered(),
+            gated.total_coverable(),
+            types.join(", ")
+        );
+    }
+}
+
+/// Whether a file or directory with this coverage is listed in the human
+/// readable reports with `--report-below`
+pub(crate) fn listed(config: &Config, percent: f64) -> bool {
+    config.report_below.map_or(true, |below| percent < below)
+}
+
+fn print_hidden_rows(config: &Config, hidden: usize, kind: (&str, &str)) {
+    if let (Some(below), true) = (config.report_below, hidden > 0) {
+        println!(
+            "|| {} {} with {:.2}% coverage or more not listed",
+            hidden,
+            if hidden == 1 { kind.0 } else { kind.1 },
+            below
+        );
+    }
+}
+
+fn print_file_rows(config: &Config, result: &TraceMap, last: &TraceMap, metadata: &RunMetadata) {
+    let mut hidden = 0;
+    for file in result.files() {
+        let path = config.display_path(file);
+        let coverable = result.coverable_in_path(&file);
+        // Files without coverable lines have nothing left to test
+        let percent = if coverable == 0 {
+            100.0
+        } else {
+            100.0 * result.covered_in_path(file) as f64 / coverable as f64
+        };
+        if !listed(config, percent) {
+            hidden += 1;
+            continue;
+        }
+        let branches = match metadata.branch_points.get(file.as_path()) {
+            Some(points) if config.extended_table => format!(
+                " ({} branch points, {:.1} per 100 lines)",
+                points,
+                branch_density(*points, coverable)
+            ),
+            _ => String::new(),
+        };
+        let branches = if config.weighted_coverage {
+            let weighted = weighted::file_coverage(result, &metadata.line_weights, file);
+            format!("{} (weighted {:.2}%)", branches, weighted.percent())
+        } else {
+            branches
+        };
+        let compared = metadata
+            .delta
+            .as_ref()
+            .and_then(|d| d.file(&delta::report_path(config, file)))
+            .and_then(delta::FileDelta::change);
+        if let Some(change) = compared {
+            println!(
+                "|| {}: {}/{} {:+.2}%{}",
+                path,
+                result.covered_in_path(&file),
+                coverable,
+                change,
+                branches
+            );
+        } else if last.contains_file(file) {
+            let last_percent = coverage_percentage(&last.get_child_traces(file));
+            let current_percent = coverage_percentage(&result.get_child_traces(file));
+            let delta = 100.0f64 * (current_percent - last_percent);
+            println!(
+                "|| {}: {}/{} {:+}%{}",
+                path,
+                result.covered_in_path(&file),
+                coverable,
+                delta,
+                branches
+            );
+        } else {
+            println!(
+                "|| {}: {}/{}{}",
+                path,
+                result.covered_in_path(&file),
+                coverable,
+                branches
+            );
+        }
+        if config.show_missing {
+            if let Some(missing) = missing_lines(result, file) {
+                println!("||     uncovered: {}", missing);
+            }
+        }
+    }
+    print_hidden_rows(config, hidden, ("file", "files"));
+}
+
+/// Uncovered lines of a file for `--show-missing`, only coverable lines are
+/// traced so a range never covers lines the analysis ignored
+fn missing_lines(result: &TraceMap, file: &Path) -> Option<String> {
+    let ranges = result.uncovered_ranges(file);
+    if ranges.is_empty() {
+        None
+    } else {
+        Some(new_files::format_ranges(&ranges))
+    }
+}
+
+/// Rows of the summary with `--report-by dir[:depth]`
+fn print_directory_rows(config: &Config, result: &TraceMap, last: &TraceMap, depth: Option<usize>) {
+    let last_rows = directories::directory_rows(config, last, depth)
+        .into_iter()
+        .map(|row| (row.path.clone(), row))
+        .collect::<BTreeMap<_, _>>();
+    let mut hidden = 0;
+    for row in directories::directory_rows(config, result, depth) {
+        if !listed(config, row.percent) {
+            hidden += 1;
+            continue;
+        }
+        let delta = match last_rows.get(&row.path) {
+            Some(previous) => format!(" {:+}%", row.percent - previous.percent),
+            None => String::new(),
+        };
+        println!(
+            "|| {}: {}/{} ({:.2}%){}",
+            row.path, row.covered, row.coverable, row.percent, delta
+        );
+    }
+    print_hidden_rows(config, hidden, ("directory", "directories"));
+}
+
+/// Components of the name of a file in the JSON and HTML reports, with
+/// `--path-format legacy` these are the components of the absolute path
+pub(crate) fn path_components(config: &Config, path: &Path) -> Vec<String> {
+    let name = match config.path_format {
+        PathFormat::Relative => PathBuf::from(config.display_path(path)),
+        PathFormat::Legacy => path.to_path_buf(),
+    };
+    name.components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect()
+}
+
+/// Decision points per 100 coverable lines
+pub(crate) fn branch_density(branch_points: usize, coverable: usize) -> f64 {
+    if coverable == 0 {
+        0.0
+    } else {
+        branch_points as f64 * 100.0 / coverable as f64
+    }
+}
+
+/// Sorts the lines and formats them as a list of ranges such as `1-3` or `5`
+pub(crate) fn line_ranges(mut lines: Vec<u64>) -> Vec<String> {
+    lines.sort();
+    let (groups, last_group) = lines.into_iter().fold((vec![], vec![]), accumulate_lines);
+    let (groups, _) = accumulate_lines((groups, last_group), u64::max_value());
+    groups
 }
 
 fn accumulate_lines(
@@ -207,3 +1102,171 @@ fn accumulate_lines(
         (acc, group)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn unreadable_sources_reported_without_source() {
+        let dir = env::temp_dir().join(format!("tarpaulin-unreadable-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).unwrap();
+        let locked = dir.join("src/locked.rs");
+        let latin1 = dir.join("src/latin1.rs");
+        fs::write(&locked, "fn locked() {}\n").unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        fs::write(&latin1, b"// caf\xe9\nfn latin1() {}\n").unwrap();
+        let mut traces = TraceMap::new();
+        for path in &[&locked, &latin1] {
+            traces.add_trace(path, Trace::test_line(1, 1));
+        }
+        let mut config = Config::default();
+        config.manifest = dir.join("Cargo.toml");
+        config.output_directory = Some(dir.clone());
+        config.generate = vec![OutputFormat::Html, OutputFormat::Json];
+
+        assert!(read_source(&latin1)
+            .unwrap()
+            .starts_with("// caf\u{fffd}\n"));
+        let mut written = vec![];
+        let metadata = RunMetadata::default();
+        assert!(generate_requested_reports(&config, &traces, &metadata, &mut written).is_ok());
+        assert_eq!(
+            written,
+            vec![dir.join(html::FILE_NAME), dir.join(json::FILE_NAME)]
+        );
+        assert!(written.iter().all(|p| p.exists()));
+        assert!(check_sources(&config, &traces).is_ok());
+
+        // Permissions don't stop root reading the file
+        if fs::read(&locked).is_err() {
+            assert_eq!(unreadable_sources(&traces).len(), 1);
+            config.strict_reporting = true;
+            match check_sources(&config, &traces) {
+                Err(RunError::CovReport(e)) => {
+                    assert_eq!(e, "1 source files couldn't be read: src/locked.rs")
+                }
+                r => panic!("Unexpected result {:?}", r),
+            }
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn saved_results_filtered() {
+        let dir = env::temp_dir().join(format!("tarpaulin-post-filter-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).unwrap();
+        let lib = dir.join("src/lib.rs");
+        let generated = dir.join("src/generated.rs");
+        fs::write(&lib, "fn a() {}\nfn b() {}\n").unwrap();
+        fs::write(&generated, "fn c() {}\nfn d() {}\n").unwrap();
+        let mut traces = TraceMap::new();
+        for (path, line, hits) in &[
+            (&lib, 1, 1),
+            (&lib, 2, 0),
+            (&generated, 1, 0),
+            (&generated, 2, 0),
+        ] {
+            traces.add_trace(path, Trace::test_line(*line, *hits));
+        }
+        let saved = dir.join("coverage.json");
+        save_results(&saved, &traces).unwrap();
+        let before = fs::read(&saved).unwrap();
+
+        let mut config = Config::default();
+        config.manifest = dir.join("Cargo.toml");
+        config.output_directory = Some(dir.clone());
+        config.generate = vec![OutputFormat::Json];
+        config.add_excluded_files(&["src/generated.rs".to_string()]);
+        assert!(report_saved_results(&config, &dir).is_ok());
+
+        let report = fs::read_to_string(dir.join(json::FILE_NAME)).unwrap();
+        let report: serde_json::Value = serde_json::from_str(&report).unwrap();
+        assert_eq!(report["files"].as_array().unwrap().len(), 1);
+        assert_eq!(report["coverable"], 2);
+        assert_eq!(report["covered"], 1);
+        assert_eq!(report["post_filter"]["removed_files"], 1);
+        assert_eq!(report["post_filter"]["patterns"][0], "src/generated.rs");
+        assert_eq!(fs::read(&saved).unwrap(), before);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn missing_lines_ranges() {
+        let mut traces = TraceMap::new();
+        let file = Path::new("/project/src/parser.rs");
+        let covered = Path::new("/project/src/lib.rs");
+        // Line 41 isn't coverable so 40 and 42 aren't merged over it
+        let lines = [(14, 0), (15, 0), (16, 0), (17, 1), (40, 0), (42, 0)];
+        for (path, line, hits) in lines
+            .iter()
+            .map(|(l, h)| (file, *l, *h))
+            .chain(Some((covered, 1, 2)))
+        {
+            traces.add_trace(path, Trace::test_line(line, hits));
+        }
+        assert_eq!(
+            missing_lines(&traces, file),
+            Some("14-16, 40, 42".to_string())
+        );
+        assert_eq!(missing_lines(&traces, covered), None);
+    }
+
+    #[test]
+    fn every_format_renders() {
+        let dir = env::temp_dir().join(format!("tarpaulin-formats-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let source =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/simple_project/src/lib.rs");
+        let mut traces = TraceMap::new();
+        for (line, hits) in &[(4, 1), (5, 0), (7, 1)] {
+            traces.add_trace(&source, Trace::test_line(*line, *hits));
+        }
+        let mut config = Config::default();
+        config.manifest = dir.join("Cargo.toml");
+        config.output_directory = Some(dir.clone());
+        for name in &OutputFormat::variants() {
+            let format = name.parse::<OutputFormat>().unwrap();
+            assert_ne!(format, OutputFormat::Custom(name.to_string()));
+            let report = get_report(&format).unwrap();
+            assert!(
+                !report.description().is_empty(),
+                "{} has no description",
+                name
+            );
+            let rendered = report.render(&config, &traces, &RunMetadata::default());
+            assert!(rendered.is_ok(), "{} failed: {:?}", name, rendered);
+            if let Some(file) = report.file_name() {
+                assert!(dir.join(file).exists(), "{} didn't write {}", name, file);
+            }
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn conflicting_stdout_formats_rejected() {
+        let mut config = Config::default();
+        config.generate = vec![OutputFormat::Github, OutputFormat::Lcov];
+        assert!(check_stdout_formats(&config).is_ok());
+        config.generate.push(OutputFormat::Teamcity);
+        assert!(matches!(
+            check_stdout_formats(&config),
+            Err(RunError::Report(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_formats_suggested() {
+        assert_eq!(
+            unknown_format("Lcvo"),
+            "no report format is registered for Lcvo, did you mean lcov?"
+        );
+        assert!(unknown_format("protobuf").ends_with("`--out help` lists the formats"));
+        assert!(format_list().contains("    markdown    Markdown table"));
+    }
+}
@@ -0,0 +1,264 @@
+//! Coverage of the files added since `--diff-base`, gated by
+//! `--fail-under-new-files`. A project wide threshold lets a new file go in
+//! with barely any tests as long as the rest of the project makes up for it,
+//! this holds new files to their own bar. Git's rename detection is used so a
+//! moved file isn't new, and a new file with nothing coverable always passes.
+use crate::config::Config;
+use crate::errors::RunError;
+use crate::traces::TraceMap;
+use git2::{Delta, DiffFindOptions, DiffOptions, Repository};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+/// Coverage of a file added since the diff base
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct NewFile {
+    /// Path as shown in the reports
+    pub path: String,
+    pub coverable: usize,
+    pub covered: usize,
+    /// A file with nothing coverable counts as fully covered
+    pub percent: f64,
+    /// Runs of uncovered lines as inclusive start and end lines
+    pub uncovered: Vec<(u64, u64)>,
+}
+
+/// Files added since the commit the branch was made from, including
+/// uncommitted and untracked files. The base is looked for as a remote branch
+/// on origin first and then as any revision.
+pub fn added_files(root: &Path, base: &str) -> Result<BTreeSet<PathBuf>, RunError> {
+    let git_error = |e: git2::Error| RunError::DiffBase(format!("{}: {}", base, e.message()));
+    let repo = Repository::discover(root).map_err(git_error)?;
+    let base_commit = repo
+        .revparse_single(&format!("origin/{}", base))
+        .or_else(|_| repo.revparse_single(base))
+        .and_then(|o| o.peel_to_commit())
+        .map_err(git_error)?;
+    let head = repo
+        .head()
+        .and_then(|h| h.peel_to_commit())
+        .map_err(git_error)?;
+    let fork_point = repo
+        .merge_base(base_commit.id(), head.id())
+        .map_err(git_error)?;
+    let tree = repo
+        .find_commit(fork_point)
+        .and_then(|c| c.tree())
+        .map_err(git_error)?;
+    let mut options = DiffOptions::new();
+    options.include_untracked(true).recurse_untracked_dirs(true);
+    let mut diff = repo
+        .diff_tree_to_workdir_with_index(Some(&tree), Some(&mut options))
+        .map_err(git_error)?;
+    diff.find_similar(Some(
+        DiffFindOptions::new().renames(true).for_untracked(true),
+    ))
+    .map_err(git_error)?;
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| RunError::DiffBase("repository has no working directory".to_string()))?;
+    Ok(diff
+        .deltas()
+        .filter(|d| d.status() == Delta::Added || d.status() == Delta::Untracked)
+        .filter_map(|d| d.new_file().path())
+        .map(|p| workdir.join(p))
+        .collect())
+}
+
+/// Coverage of each traced file which is in the added files, files without
+/// traces have nothing coverable so are left out
+pub fn coverage(config: &Config, traces: &TraceMap, added: &BTreeSet<PathBuf>) -> Vec<NewFile> {
+    traces
+        .files()
+        .into_iter()
+        .filter(|f| added.contains(*f))
+        .map(|file| {
+            let coverable = traces.coverable_in_path(file);
+            let covered = traces.covered_in_path(file);
+            NewFile {
+                path: config.display_path(file),
+                coverable,
+                covered,
+                percent: if coverable == 0 {
+                    100.0
+                } else {
+                    100.0 * covered as f64 / coverable as f64
+                },
+                uncovered: traces.uncovered_ranges(file),
+            }
+        })
+        .collect()
+}
+
+/// Ranges as they're written in the failure message, `3-5, 9`
+pub(crate) fn format_ranges(ranges: &[(u64, u64)]) -> String {
+    ranges
+        .iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}-{}", start, end)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Fails listing every new file below the threshold with its uncovered lines
+pub fn check(files: &[NewFile], threshold: f64) -> Result<(), RunError> {
+    let below = files
+        .iter()
+        .filter(|f| f.percent < threshold)
+        .map(|f| {
+            format!(
+                "{} {:.2}% (uncovered lines {})",
+                f.path,
+                f.percent,
+                format_ranges(&f.uncovered)
+            )
+        })
+        .collect::<Vec<_>>();
+    if below.is_empty() {
+        Ok(())
+    } else {
+        Err(RunError::NewFilesBelowThreshold(
+            threshold,
+            below.join("; "),
+        ))
+    }
+}
+
+/// Markdown table of the new files for the job summary and the Markdown
+/// report, empty if there are none
+pub fn summary(files: &[NewFile], threshold: Option<f64>) -> String {
+    if files.is_empty() {
+        return String::new();
+    }
+    let mut s = String::new();
+    let _ = writeln!(s, "\n### New files\n");
+    let _ = writeln!(s, "| File | Coverable | Covered | Coverage | Uncovered |");
+    let _ = writeln!(s, "| --- | ---: | ---: | ---: | --- |");
+    for file in files {
+        let _ = writeln!(
+            s,
+            "| {} | {} | {} | {:.2}% | {} |",
+            file.path.replace('|', "\\|"),
+            file.coverable,
+            file.covered,
+            file.percent,
+            format_ranges(&file.uncovered)
+        );
+    }
+    if let Some(threshold) = threshold {
+        let below = files.iter().filter(|f| f.percent < threshold).count();
+        let _ = writeln!(
+            s,
+            "\n{} of {} new files below the {:.2}% threshold",
+            below,
+            files.len(),
+            threshold
+        );
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traces::Trace;
+    use git2::{IndexAddOption, Signature};
+    use std::env;
+    use std::fs;
+
+    fn traces() -> TraceMap {
+        let mut traces = TraceMap::new();
+        let lines = [
+            ("src/new.rs", 1, 1),
+            ("src/new.rs", 2, 0),
+            ("src/new.rs", 3, 0),
+            ("src/new.rs", 5, 0),
+            ("src/old.rs", 1, 0),
+        ];
+        for (file, line, hits) in &lines {
+            traces.add_trace(
+                &Path::new("/project").join(file),
+                Trace::test_line(*line, *hits),
+            );
+        }
+        traces
+    }
+
+    fn new_files() -> Vec<NewFile> {
+        let mut config = Config::default();
+        config.manifest = PathBuf::from("/project/Cargo.toml");
+        let added = vec![
+            PathBuf::from("/project/src/new.rs"),
+            PathBuf::from("/project/src/consts.rs"),
+        ]
+        .into_iter()
+        .collect();
+        coverage(&config, &traces(), &added)
+    }
+
+    #[test]
+    fn new_files_gated() {
+        let files = new_files();
+        // consts.rs has nothing coverable so isn't traced and can't fail
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, "src/new.rs");
+        assert_eq!(files[0].uncovered, vec![(2, 3), (5, 5)]);
+        assert!(check(&files, 25.0).is_ok());
+        match check(&files, 80.0) {
+            Err(RunError::NewFilesBelowThreshold(t, files)) => {
+                assert_eq!(t, 80.0);
+                assert_eq!(files, "src/new.rs 25.00% (uncovered lines 2-3, 5)");
+            }
+            r => panic!("Unexpected result {:?}", r),
+        }
+        assert!(summary(&files, Some(80.0)).contains(
+            "| src/new.rs | 4 | 1 | 25.00% | 2-3, 5 |\n\n1 of 1 new files below the 80.00% threshold"
+        ));
+        assert_eq!(summary(&[], Some(80.0)), "");
+    }
+
+    #[test]
+    fn renamed_files_not_new() {
+        let dir = env::temp_dir().join(format!("tarpaulin-new-files-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let repo = Repository::init(&dir).unwrap();
+        let source = "pub fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n";
+        fs::write(dir.join("old.rs"), source).unwrap();
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(&["*"], IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("tarpaulin", "tarpaulin@example.com").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "base", &tree, &[])
+            .unwrap();
+        repo.branch(
+            "base",
+            &repo.head().unwrap().peel_to_commit().unwrap(),
+            false,
+        )
+        .unwrap();
+
+        // A staged rename and an untracked new file
+        fs::rename(dir.join("old.rs"), dir.join("moved.rs")).unwrap();
+        index.remove_path(Path::new("old.rs")).unwrap();
+        index.add_path(Path::new("moved.rs")).unwrap();
+        index.write().unwrap();
+        fs::write(dir.join("new.rs"), "pub fn new() {}\n").unwrap();
+
+        let added = added_files(&dir, "base").unwrap();
+        let workdir = repo.workdir().unwrap();
+        assert_eq!(added, vec![workdir.join("new.rs")].into_iter().collect());
+        assert!(added_files(&dir, "no-such-branch").is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,102 @@
+//! How much slower the tests run when traced, measured with
+//! `--measure-overhead` by running each test binary untraced before tracing
+//! it. This doubles the time a run takes so it's only done when asked for.
+use crate::config::Config;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Times of the untraced and traced runs of a test binary
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BinaryOverhead {
+    pub binary: PathBuf,
+    pub untraced_ms: f64,
+    pub traced_ms: f64,
+    /// How many times longer the traced run took
+    pub factor: f64,
+}
+
+impl BinaryOverhead {
+    pub fn new(binary: PathBuf, untraced: Duration, traced: Duration) -> Self {
+        let untraced_ms = untraced.as_secs_f64() * 1000.0;
+        let traced_ms = traced.as_secs_f64() * 1000.0;
+        BinaryOverhead {
+            binary,
+            untraced_ms,
+            traced_ms,
+            factor: slowdown(untraced_ms, traced_ms),
+        }
+    }
+}
+
+/// Overhead of each test binary and of the run as a whole
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct OverheadSummary {
+    pub binaries: Vec<BinaryOverhead>,
+    /// Total traced time over total untraced time
+    pub factor: f64,
+}
+
+/// Summarises the overhead of the binaries, `None` if none were measured
+pub fn summarise(binaries: Vec<BinaryOverhead>) -> Option<OverheadSummary> {
+    if binaries.is_empty() {
+        return None;
+    }
+    let untraced = binaries.iter().map(|b| b.untraced_ms).sum::<f64>();
+    let traced = binaries.iter().map(|b| b.traced_ms).sum::<f64>();
+    Some(OverheadSummary {
+        binaries,
+        factor: slowdown(untraced, traced),
+    })
+}
+
+fn slowdown(untraced_ms: f64, traced_ms: f64) -> f64 {
+    if untraced_ms > 0.0 {
+        traced_ms / untraced_ms
+    } else {
+        1.0
+    }
+}
+
+pub fn print_overhead(config: &Config, summary: &OverheadSummary) {
+    println!("|| Tracing overhead:");
+    println!(
+        "|| {:<40} {:>12} {:>12} {:>8}",
+        "Binary", "Untraced ms", "Traced ms", "Factor"
+    );
+    for b in &summary.binaries {
+        println!(
+            "|| {:<40} {:>12.0} {:>12.0} {:>7.2}x",
+            config.display_path(&b.binary),
+            b.untraced_ms,
+            b.traced_ms,
+            b.factor
+        );
+    }
+    println!("|| Tests ran {:.2}x slower when traced", summary.factor);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overall_factor_weighted_by_time() {
+        let binaries = vec![
+            BinaryOverhead::new(
+                PathBuf::from("a"),
+                Duration::from_millis(100),
+                Duration::from_millis(1000),
+            ),
+            BinaryOverhead::new(
+                PathBuf::from("b"),
+                Duration::from_millis(900),
+                Duration::from_millis(1000),
+            ),
+        ];
+        assert!((binaries[0].factor - 10.0).abs() < 1e-9);
+        let summary = summarise(binaries).unwrap();
+        assert!((summary.factor - 2.0).abs() < 1e-9);
+        assert_eq!(summarise(vec![]), None);
+    }
+}
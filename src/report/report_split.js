@@ -0,0 +1,21 @@
+// Keyboard navigation for the pages of the split HTML report, `n` and `p` move
+// to the next and previous uncovered line.
+(function() {
+  const lines = Array.from(document.querySelectorAll('.code-line_uncovered'));
+  let current = -1;
+
+  document.addEventListener('keydown', (event) => {
+    if (lines.length === 0 || event.ctrlKey || event.metaKey || event.altKey) {
+      return;
+    }
+    if (event.key === 'n') {
+      current = (current + 1) % lines.length;
+    } else if (event.key === 'p') {
+      current = (current + lines.length - 1) % lines.length;
+    } else {
+      return;
+    }
+    lines[current].scrollIntoView({block: 'center'});
+    window.location.hash = lines[current].id;
+  });
+})();
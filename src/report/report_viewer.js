@@ -157,6 +157,7 @@ function FilesList({folder, onSelectFile, onBack}) {
   let files = folder.children;
   return e('div', {className: 'display-folder'},
     e(FileHeader, {file: folder, onBack}),
+    onBack ? null : e(DirectoryTree, {directories: data.directories || []}),
     e('table', {className: 'files-list'},
       e('thead', {className: 'files-list__head'},
         e('tr', null,
@@ -171,6 +172,32 @@ function FilesList({folder, onSelectFile, onBack}) {
   );
 }
 
+// Collapsible tree of the directories with the coverage rolled up from their
+// files, shown on the index. Top level directories start expanded.
+function DirectoryTree({directories}) {
+  if (!directories.length) {
+    return null;
+  }
+  return e('div', {className: 'dir-tree'},
+    directories.map(dir => e(Directory, {dir, key: dir.path}))
+  );
+}
+
+function Directory({dir}) {
+  const level = !dir.coverable ? ''
+    : dir.percent < 50 ? ' dir-tree__summary_low'
+    : dir.percent < 80 ? ' dir-tree__summary_medium'
+    : ' dir-tree__summary_high';
+  const name = dir.path.split('/').pop();
+  return e('details', {className: 'dir-tree__dir', open: !dir.path.includes('/')},
+    e('summary', {className: 'dir-tree__summary' + level, title: dir.path},
+      name + ': ' + dir.covered + ' / ' + dir.coverable +
+      (dir.coverable ? ' (' + dir.percent.toFixed(2) + '%)' : '')
+    ),
+    (dir.children || []).map(child => e(Directory, {dir: child, key: child.path}))
+  );
+}
+
 function File({file, onClick}) {
   const coverage = file.coverable ? file.covered / file.coverable * 100 : -1;
   const coverageDelta = file.prevRun &&
@@ -218,6 +245,11 @@ function FileHeader({file, onBack}) {
 }
 
 function FileContent({file}) {
+  if (file.error) {
+    return e('div', {className: 'file-content'},
+      e('pre', {className: 'code-line'}, 'Source unavailable: ' + file.error)
+    );
+  }
   return e('div', {className: 'file-content'},
     file.content.split(/\r?\n/).map((line, index) => {
       const trace = file.traces.find(trace => trace.line === index + 1);
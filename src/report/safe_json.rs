@@ -41,12 +41,14 @@ impl serde_json::ser::Formatter for SafeFormatter {
     }
 }
 
-pub fn to_string_safe<T: serde::Serialize + ?Sized>(value: &T) -> Result<String, String> {
-    let mut writer = Vec::new();
-    let mut ser = serde_json::Serializer::with_formatter(&mut writer, SafeFormatter::default());
-    value.serialize(&mut ser).map_err(|e| e.to_string())?;
-    let string = String::from_utf8(writer).map_err(|e| e.to_string())?;
-    Ok(string)
+/// Serializes the value straight into the writer so it's never held in memory
+pub fn to_writer_safe<W, T>(writer: W, value: &T) -> Result<(), String>
+where
+    W: io::Write,
+    T: serde::Serialize + ?Sized,
+{
+    let mut ser = serde_json::Serializer::with_formatter(writer, SafeFormatter::default());
+    value.serialize(&mut ser).map_err(|e| e.to_string())
 }
 
 #[cfg(test)]
@@ -54,6 +56,13 @@ mod tests {
     use super::*;
     use serde_json::{self, json};
 
+    fn to_string_safe<T: serde::Serialize + ?Sized>(value: &T) -> Result<String, String> {
+        let mut writer = Vec::new();
+        to_writer_safe(&mut writer, value)?;
+        let string = String::from_utf8(writer).map_err(|e| e.to_string())?;
+        Ok(string)
+    }
+
     #[test]
     fn test_json_without_html() {
         let x = json!({
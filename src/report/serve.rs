@@ -0,0 +1,497 @@
+//! Local HTTP server for browsing saved results, `cargo tarpaulin serve`. The
+//! pages are the same as the split HTML report's but are rendered from the
+//! `TraceMap` for each request, alongside a JSON API with the summary and the
+//! traces of each file. The results are reloaded when the saved files change
+//! and the pages poll the summary's etag to reload themselves. The server is
+//! read only and only serves the source of files which are in the results.
+use crate::config::Config;
+use crate::errors::RunError;
+use crate::line_map::content_hash;
+use crate::report::directories::{directory_tree, DirectoryCoverage};
+use crate::report::html_split::{self, IndexRow, MAX_PAGE_SOURCE_BYTES};
+use crate::report::{filter_saved_results, read_saved_results};
+use crate::traces::{Trace, TraceMap};
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tiny_http::{Header, Response, Server};
+
+const RELOAD_SCRIPT: &str = include_str!("serve_reload.js");
+
+/// Summary of the results, `/api/summary`
+#[derive(Serialize)]
+struct Summary {
+    covered: usize,
+    coverable: usize,
+    /// Covered lines as a percentage of the coverable ones
+    coverage: f64,
+    files: Vec<FileSummary>,
+    directories: Vec<DirectoryCoverage>,
+}
+
+#[derive(Serialize)]
+struct FileSummary {
+    path: String,
+    covered: usize,
+    coverable: usize,
+}
+
+/// Traces of a single file, `/api/file?path=`
+#[derive(Serialize)]
+struct FileDetail<'a> {
+    path: String,
+    covered: usize,
+    coverable: usize,
+    traces: Vec<&'a Trace>,
+}
+
+/// Response to a request, kept apart from the HTTP library so the routes can
+/// be tested without a socket
+#[derive(Debug)]
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+    etag: Option<String>,
+}
+
+impl Reply {
+    fn ok(content_type: &'static str, body: String) -> Self {
+        Reply {
+            status: 200,
+            content_type,
+            body,
+            etag: None,
+        }
+    }
+
+    fn json<T: Serialize>(value: &T) -> Self {
+        match serde_json::to_string(value) {
+            Ok(body) => Reply::ok("application/json", body),
+            Err(e) => Reply::error(500, &e.to_string()),
+        }
+    }
+
+    fn html(render: impl FnOnce(&mut Vec<u8>) -> std::io::Result<()>) -> Self {
+        let mut page = vec![];
+        match render(&mut page) {
+            Ok(()) => Reply::ok(
+                "text/html; charset=utf-8",
+                String::from_utf8_lossy(&page).into_owned(),
+            ),
+            Err(e) => Reply::error(500, &e.to_string()),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Reply {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: message.to_string(),
+            etag: None,
+        }
+    }
+
+    fn into_response(self) -> Response<std::io::Cursor<Vec<u8>>> {
+        let mut response =
+            Response::from_data(self.body.into_bytes()).with_status_code(self.status);
+        if let Ok(header) = Header::from_bytes(&b"Content-Type"[..], self.content_type) {
+            response.add_header(header);
+        }
+        if let Some(etag) = self.etag {
+            if let Ok(header) = Header::from_bytes(&b"ETag"[..], etag) {
+                response.add_header(header);
+            }
+        }
+        response
+    }
+}
+
+/// The results being served and the fingerprint of the files they're from
+struct Results {
+    dir: PathBuf,
+    fingerprint: String,
+    traces: TraceMap,
+}
+
+impl Results {
+    fn load(config: &Config, dir: &Path) -> Self {
+        let files = saved_files(dir);
+        let mut traces = TraceMap::new();
+        for file in &files {
+            match read_saved_results(config, file) {
+                Some(saved) => traces.merge(&saved),
+                None => debug!("{} isn't saved results, skipping", file.display()),
+            }
+        }
+        filter_saved_results(config, &mut traces);
+        if traces.is_empty() {
+            warn!("No saved results in {}", dir.display());
+        }
+        Results {
+            dir: dir.to_path_buf(),
+            fingerprint: fingerprint(&files),
+            traces,
+        }
+    }
+
+    /// Reloads the results if the saved files have changed since
+    fn refresh(&mut self, config: &Config) {
+        if fingerprint(&saved_files(&self.dir)) != self.fingerprint {
+            *self = Results::load(config, &self.dir);
+            info!("Reloaded the results in {}", self.dir.display());
+        }
+    }
+
+    fn handle(
+        &self,
+        config: &Config,
+        method: &str,
+        url: &str,
+        if_none_match: Option<&str>,
+    ) -> Reply {
+        if method != "GET" {
+            return Reply::error(405, "Only GET requests are supported");
+        }
+        let mut parts = url.splitn(2, '?');
+        let route = parts.next().unwrap_or_default();
+        let query = parts.next().unwrap_or_default();
+        match route {
+            "/" | "/index.html" => Reply::html(|w| self.index(config, w)),
+            "/report.css" => Reply::ok("text/css", html_split::STYLE.to_string()),
+            "/report.js" => Reply::ok(
+                "application/javascript",
+                format!("{}\n{}", html_split::SCRIPT, RELOAD_SCRIPT),
+            ),
+            "/file" => match self.find_file(config, query) {
+                Ok(file) => self.file_page(config, file),
+                Err(reply) => reply,
+            },
+            "/api/summary" if if_none_match == Some(self.fingerprint.as_str()) => Reply {
+                etag: Some(self.fingerprint.clone()),
+                ..Reply::error(304, "")
+            },
+            "/api/summary" => Reply {
+                etag: Some(self.fingerprint.clone()),
+                ..Reply::json(&self.summary(config))
+            },
+            "/api/file" => match self.find_file(config, query) {
+                Ok(file) => Reply::json(&FileDetail {
+                    path: config.display_path(file),
+                    covered: self.traces.covered_in_path(file),
+                    coverable: self.traces.coverable_in_path(file),
+                    traces: self.traces.get_child_traces(file),
+                }),
+                Err(reply) => reply,
+            },
+            _ => Reply::error(404, "Not found"),
+        }
+    }
+
+    /// The file named by the `path` parameter, only files in the results can
+    /// be found so nothing else on the machine is served
+    fn find_file(&self, config: &Config, query: &str) -> Result<&PathBuf, Reply> {
+        let name = query_param(query, "path")
+            .ok_or_else(|| Reply::error(400, "Missing the path parameter"))?;
+        self.traces
+            .files()
+            .into_iter()
+            .find(|file| config.display_path(file) == name)
+            .ok_or_else(|| Reply::error(404, "File isn't in the results"))
+    }
+
+    fn summary(&self, config: &Config) -> Summary {
+        let covered = self.traces.total_covered();
+        let coverable = self.traces.total_coverable();
+        Summary {
+            covered,
+            coverable,
+            coverage: if coverable == 0 {
+                0.0
+            } else {
+                covered as f64 * 100.0 / coverable as f64
+            },
+            files: self
+                .traces
+                .files()
+                .into_iter()
+                .map(|file| FileSummary {
+                    path: config.display_path(file),
+                    covered: self.traces.covered_in_path(file),
+                    coverable: self.traces.coverable_in_path(file),
+                })
+                .collect(),
+            directories: directory_tree(config, &self.traces),
+        }
+    }
+
+    fn index(&self, config: &Config, w: &mut Vec<u8>) -> std::io::Result<()> {
+        let rows = self
+            .traces
+            .files()
+            .into_iter()
+            .map(|file| {
+                let name = config.display_path(file);
+                let page = format!("file?path={}", percent_encode(&name));
+                let large = too_large(file);
+                IndexRow {
+                    page: if large { None } else { Some(page) },
+                    note: if large {
+                        Some("too large to show")
+                    } else {
+                        None
+                    },
+                    name,
+                    covered: self.traces.covered_in_path(file),
+                    coverable: self.traces.coverable_in_path(file),
+                }
+            })
+            .collect::<Vec<_>>();
+        let directories = directory_tree(config, &self.traces);
+        html_split::write_index_view(w, "/", &rows, &directories, &self.traces)
+    }
+
+    fn file_page(&self, config: &Config, file: &Path) -> Reply {
+        if too_large(file) {
+            return Reply::error(413, "File is too large to show");
+        }
+        let content = match fs::read_to_string(file) {
+            Ok(content) => content,
+            Err(e) => return Reply::error(404, &format!("Unable to read the file: {}", e)),
+        };
+        let row = IndexRow {
+            name: config.display_path(file),
+            page: None,
+            note: None,
+            covered: self.traces.covered_in_path(file),
+            coverable: self.traces.coverable_in_path(file),
+        };
+        let traces = self.traces.get_child_traces(file);
+        Reply::html(|w| html_split::write_file_view(w, "/", &row, &content, &traces, None))
+    }
+}
+
+fn too_large(file: &Path) -> bool {
+    fs::metadata(file)
+        .map(|m| m.len() > MAX_PAGE_SOURCE_BYTES)
+        .unwrap_or(false)
+}
+
+/// Files in `dir` which may be saved results, sorted by name
+fn saved_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|path| {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                path.is_file()
+                    && [".json", ".json.gz", ".json.zst"]
+                        .iter()
+                        .any(|ext| name.ends_with(ext))
+            })
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            warn!("Unable to read {}: {}", dir.display(), e);
+            vec![]
+        }
+    };
+    files.sort();
+    files
+}
+
+/// Etag of the saved files, changes when any of them is added, removed or
+/// modified
+fn fingerprint(files: &[PathBuf]) -> String {
+    let mut state = String::new();
+    for file in files {
+        let (len, modified) = fs::metadata(file)
+            .map(|m| {
+                let modified = m
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_nanos())
+                    .unwrap_or_default();
+                (m.len(), modified)
+            })
+            .unwrap_or_default();
+        state.push_str(&format!("{}:{}:{}\n", file.display(), len, modified));
+    }
+    format!("\"{:016x}\"", content_hash(&state))
+}
+
+/// Decoded value of a parameter in a query string
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            Some((parts.next()?, parts.next().unwrap_or_default()))
+        })
+        .find(|(key, _)| *key == name)
+        .and_then(|(_, value)| percent_decode(value))
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                result.push(b as char)
+            }
+            b => result.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    result
+}
+
+/// Decodes `%XX` escapes and `+` for spaces, `None` if an escape is invalid or
+/// the result isn't UTF-8
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+                result.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            b'+' => {
+                result.push(b' ');
+                i += 1;
+            }
+            b => {
+                result.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(result).ok()
+}
+
+/// Serves the results saved in `dir` on `address` until the process is
+/// stopped
+pub fn run(config: &Config, dir: &Path, address: &str) -> Result<(), RunError> {
+    if !dir.is_dir() {
+        return Err(RunError::Serve(format!(
+            "{} isn't a directory",
+            dir.display()
+        )));
+    }
+    let mut results = Results::load(config, dir);
+    let server = Server::http(address)
+        .map_err(|e| RunError::Serve(format!("Unable to listen on {}: {}", address, e)))?;
+    info!(
+        "Serving the results in {} on http://{}",
+        dir.display(),
+        address
+    );
+    for request in server.incoming_requests() {
+        results.refresh(config);
+        let if_none_match = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("If-None-Match"))
+            .map(|h| h.value.as_str().to_string());
+        let reply = results.handle(
+            config,
+            request.method().as_str(),
+            request.url(),
+            if_none_match.as_deref(),
+        );
+        if let Err(e) = request.respond(reply.into_response()) {
+            debug!("Failed to respond to a request: {}", e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::save_results;
+    use std::env;
+
+    fn add_lines(traces: &mut TraceMap, file: &Path, hits: &[u64]) {
+        for (i, hits) in hits.iter().enumerate() {
+            traces.add_trace(file, Trace::test_line(i as u64 + 1, *hits));
+        }
+    }
+
+    #[test]
+    fn routes_only_serve_results() {
+        let dir = env::temp_dir().join(format!("tarpaulin-serve-{}", std::process::id()));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        let source = dir.join("src/lib.rs");
+        fs::write(&source, "fn a() {}\nfn b() {}\n").unwrap();
+        let mut config = Config::default();
+        config.manifest = dir.join("Cargo.toml");
+        let mut traces = TraceMap::new();
+        add_lines(&mut traces, &source, &[1, 0]);
+        save_results(&dir.join("coverage.json"), &traces).unwrap();
+        fs::write(dir.join("notes.json"), "{}").unwrap();
+
+        let results = Results::load(&config, &dir);
+        assert_eq!(results.traces.total_coverable(), 2);
+        let get = |url: &str| results.handle(&config, "GET", url, None);
+
+        let index = get("/");
+        assert_eq!(index.status, 200);
+        assert!(index.body.contains(r#"href="/file?path=src/lib.rs""#));
+        let page = get("/file?path=src%2Flib.rs");
+        assert_eq!(page.status, 200);
+        assert!(page.body.contains("code-line_covered"));
+        assert!(page.body.contains(r#"src="/report.js""#));
+
+        let summary = get("/api/summary");
+        let json: serde_json::Value = serde_json::from_str(&summary.body).unwrap();
+        assert_eq!(json["covered"], 1);
+        assert_eq!(json["files"][0]["path"], "src/lib.rs");
+        let etag = summary.etag.unwrap();
+        let unchanged = results.handle(&config, "GET", "/api/summary", Some(&etag));
+        assert_eq!(unchanged.status, 304);
+
+        let file = get("/api/file?path=src/lib.rs");
+        let json: serde_json::Value = serde_json::from_str(&file.body).unwrap();
+        assert_eq!(json["coverable"], 2);
+        assert_eq!(json["traces"].as_array().map(Vec::len), Some(2));
+
+        assert_eq!(get("/file?path=..%2FCargo.toml").status, 404);
+        assert_eq!(get("/api/file?path=/etc/passwd").status, 404);
+        assert_eq!(get("/api/file").status, 400);
+        assert_eq!(get("/missing").status, 404);
+        assert_eq!(
+            results.handle(&config, "POST", "/api/summary", None).status,
+            405
+        );
+
+        // Changing the saved results changes the etag
+        let mut results = results;
+        add_lines(&mut traces, &dir.join("src/other.rs"), &[0]);
+        save_results(&dir.join("coverage.json"), &traces).unwrap();
+        results.refresh(&config);
+        assert_ne!(results.fingerprint, etag);
+        assert_eq!(results.traces.total_coverable(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn query_strings_decoded() {
+        assert_eq!(
+            query_param("a=1&path=src%2Fmy+file.rs", "path"),
+            Some("src/my file.rs".to_string())
+        );
+        assert_eq!(query_param("path=%zz", "path"), None);
+        assert_eq!(query_param("other=1", "path"), None);
+        assert_eq!(percent_encode("src/a b&c.rs"), "src/a%20b%26c.rs");
+        assert_eq!(
+            percent_decode(&percent_encode("src/ünï code.rs")),
+            Some("src/ünï code.rs".to_string())
+        );
+    }
+}
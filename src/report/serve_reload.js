@@ -0,0 +1,24 @@
+// Reloads the page served by `cargo tarpaulin serve` when the results change.
+// The summary is polled with the etag of the results it was last loaded with,
+// the server only sends it again once the saved results are different.
+(function() {
+  let etag = null;
+
+  const poll = () => {
+    const headers = etag === null ? {} : {'If-None-Match': etag};
+    fetch('/api/summary', {headers, cache: 'no-store'})
+      .then((response) => {
+        const latest = response.headers.get('ETag');
+        if (response.status === 200 && etag !== null && latest !== etag) {
+          window.location.reload();
+          return;
+        }
+        if (response.status === 200) {
+          etag = latest;
+        }
+        setTimeout(poll, 2000);
+      })
+      .catch(() => setTimeout(poll, 2000));
+  };
+  poll();
+})();
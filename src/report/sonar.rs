@@ -0,0 +1,175 @@
+//! SonarQube generic coverage XML, `--out Sonar`. Each coverable line is a
+//! `lineToCover` and lines with branch or condition coverage also have the
+//! number of branches and how many were covered. The scanner matches files by
+//! their path from the directory it runs in, which is the project root unless
+//! `--sonar-root` says otherwise, such as the repository root of a monorepo.
+use crate::config::Config;
+use crate::errors::RunError;
+use crate::report::compress::create_report_file;
+use crate::report::{Report, RunMetadata};
+use crate::traces::{CoverageStat, Trace, TraceMap};
+use quick_xml::events::{BytesEnd, BytesStart, Event};
+use quick_xml::Writer;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Name of the report file in the output directory
+pub const FILE_NAME: &str = "sonar-coverage.xml";
+
+/// SonarQube report, `--out Sonar`
+pub struct SonarReport;
+
+impl Report for SonarReport {
+    fn render(
+        &self,
+        config: &Config,
+        traces: &TraceMap,
+        _metadata: &RunMetadata,
+    ) -> Result<(), RunError> {
+        let file = create_report_file(config, FILE_NAME)?;
+        let mut writer = Writer::new(file);
+        write_document(&mut writer, config, traces)
+            .map_err(|e| RunError::OutFormat(format!("Failed to write Sonar report: {}", e)))?;
+        writer.into_inner().finish().map_err(RunError::from)
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        Some(FILE_NAME)
+    }
+
+    fn description(&self) -> &str {
+        "Sonar generic coverage XML"
+    }
+}
+
+/// Directory the paths are relative to, a relative `--sonar-root` is from
+/// where tarpaulin was run
+fn sonar_root(config: &Config) -> PathBuf {
+    match config.sonar_root {
+        Some(ref root) if root.is_absolute() => root.clone(),
+        Some(ref root) => {
+            let root = config.invocation_dir.join(root);
+            root.canonicalize().unwrap_or(root)
+        }
+        None => config.root(),
+    }
+}
+
+/// Coverage of a line, covered and total branches are 0 for lines without
+/// branch data
+#[derive(Default)]
+struct Line {
+    covered: bool,
+    branches: usize,
+    covered_branches: usize,
+}
+
+fn lines(traces: &[&Trace]) -> BTreeMap<u64, Line> {
+    let mut lines = BTreeMap::<u64, Line>::new();
+    for trace in traces {
+        let line = lines.entry(trace.line).or_default();
+        match trace.stats {
+            CoverageStat::Line(hits) => line.covered |= hits > 0,
+            CoverageStat::Branch(ref state) => {
+                line.branches += 2;
+                line.covered_branches += state.been_true as usize + state.been_false as usize;
+                line.covered |= state.been_true || state.been_false;
+            }
+            CoverageStat::Condition(ref states) => {
+                for state in states {
+                    line.branches += 2;
+                    line.covered_branches += state.been_true as usize + state.been_false as usize;
+                    line.covered |= state.been_true || state.been_false;
+                }
+            }
+        }
+    }
+    lines
+}
+
+fn write_document<W: Write>(
+    writer: &mut Writer<W>,
+    config: &Config,
+    traces: &TraceMap,
+) -> Result<(), quick_xml::Error> {
+    let root = sonar_root(config);
+    let mut coverage = BytesStart::borrowed(b"coverage", 8);
+    coverage.push_attribute(("version", "1"));
+    writer.write_event(Event::Start(coverage))?;
+    for file in traces.files() {
+        let path = TraceMap::file_id(file, &root);
+        let mut start = BytesStart::borrowed(b"file", 4);
+        start.push_attribute(("path", path.as_str()));
+        writer.write_event(Event::Start(start))?;
+        for (number, line) in lines(&traces.get_child_traces(file)) {
+            let mut element = BytesStart::borrowed(b"lineToCover", 11);
+            element.push_attribute(("lineNumber", number.to_string().as_str()));
+            element.push_attribute(("covered", if line.covered { "true" } else { "false" }));
+            if line.branches > 0 {
+                element.push_attribute(("branchesToCover", line.branches.to_string().as_str()));
+                element.push_attribute((
+                    "coveredBranches",
+                    line.covered_branches.to_string().as_str(),
+                ));
+            }
+            writer.write_event(Event::Empty(element))?;
+        }
+        writer.write_event(Event::End(BytesEnd::borrowed(b"file")))?;
+    }
+    writer.write_event(Event::End(BytesEnd::borrowed(b"coverage")))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traces::LogicState;
+    use std::collections::HashSet;
+    use std::path::Path;
+
+    fn render(config: &Config) -> String {
+        let mut traces = TraceMap::new();
+        let stats = vec![
+            (4, CoverageStat::Line(3)),
+            (2, CoverageStat::Line(0)),
+            (
+                5,
+                CoverageStat::Branch(LogicState {
+                    been_true: true,
+                    been_false: false,
+                }),
+            ),
+        ];
+        for (line, stats) in stats {
+            traces.add_trace(
+                Path::new("/repo/crates/app/src/lib.rs"),
+                Trace {
+                    line,
+                    address: HashSet::new(),
+                    length: 1,
+                    stats,
+                    fn_name: None,
+                    hit_span: None,
+                    sampled: false,
+                },
+            );
+        }
+        let mut writer = Writer::new(vec![]);
+        write_document(&mut writer, config, &traces).unwrap();
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn lines_to_cover() {
+        let mut config = Config::default();
+        config.manifest = PathBuf::from("/repo/crates/app/Cargo.toml");
+        assert_eq!(
+            render(&config),
+            r#"<coverage version="1"><file path="src/lib.rs"><lineToCover lineNumber="2" covered="false"/><lineToCover lineNumber="4" covered="true"/><lineToCover lineNumber="5" covered="true" branchesToCover="2" coveredBranches="1"/></file></coverage>"#
+        );
+
+        config.sonar_root = Some(PathBuf::from("/repo"));
+        assert!(render(&config).contains(r#"<file path="crates/app/src/lib.rs">"#));
+    }
+}
@@ -0,0 +1,277 @@
+//! Ranks the uncovered code by how worthwhile a test for it looks, for when
+//! `--fail-under` fails and it isn't obvious where to start. Public functions
+//! which were never called come first, they're part of the API and usually
+//! need a test of their own. After them come the runs of uncovered lines,
+//! weighted by the coverage of the rest of their file so a gap in an otherwise
+//! well tested file ranks above a file which has no tests at all.
+use crate::config::Config;
+use crate::report::missed::is_uncovered;
+use crate::report::RunMetadata;
+use crate::traces::{amount_coverable, amount_covered, Trace, TraceMap};
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SuggestionKind {
+    /// A `pub` function which was never entered
+    PublicFunction,
+    /// Consecutive uncovered lines
+    Range,
+}
+
+/// An uncovered region worth writing a test for
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Suggestion {
+    pub kind: SuggestionKind,
+    pub path: PathBuf,
+    pub start_line: u64,
+    pub end_line: u64,
+    /// Name of the function for uncovered public functions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function: Option<String>,
+    /// Number of uncovered lines in the region
+    pub uncovered: usize,
+    /// Coverage of the rest of the file as a percentage
+    pub file_coverage: f64,
+}
+
+impl Suggestion {
+    fn new(kind: SuggestionKind, path: &Path, traces: &[&Trace], file: (usize, usize)) -> Self {
+        let (covered, coverable) = file;
+        let uncovered = traces.iter().filter(|t| is_uncovered(t)).count();
+        let file_coverage = if coverable > uncovered {
+            100.0 * covered as f64 / (coverable - uncovered) as f64
+        } else {
+            0.0
+        };
+        let function = match kind {
+            SuggestionKind::PublicFunction => traces[0].fn_name.clone(),
+            SuggestionKind::Range => None,
+        };
+        Self {
+            kind,
+            path: path.to_path_buf(),
+            start_line: traces[0].line,
+            end_line: traces[traces.len() - 1].line,
+            function,
+            uncovered,
+            file_coverage,
+        }
+    }
+
+    /// The `path:line` of the start of the region, as understood by editors
+    pub fn anchor(&self) -> String {
+        format!("{}:{}", self.path.display(), self.start_line)
+    }
+
+    fn score(&self) -> f64 {
+        self.uncovered as f64 * self.file_coverage
+    }
+}
+
+/// Splits the sorted traces of a file into functions, each starting at a line
+/// with a function entry point
+fn split_functions<'a>(sorted: &[&'a Trace]) -> Vec<Vec<&'a Trace>> {
+    let mut functions: Vec<Vec<&Trace>> = vec![];
+    for trace in sorted {
+        match functions.last_mut() {
+            Some(func) if trace.fn_name.is_none() => func.push(trace),
+            _ => functions.push(vec![trace]),
+        }
+    }
+    functions
+}
+
+/// Ranks the uncovered regions of the results returning at most `limit` of
+/// them. `public_functions` has the lines `pub` functions are declared on in
+/// each file, a function counts as public if its entry point is on one.
+pub fn rank(
+    traces: &TraceMap,
+    public_functions: &BTreeMap<PathBuf, BTreeSet<u64>>,
+    limit: usize,
+) -> Vec<Suggestion> {
+    let no_functions = BTreeSet::new();
+    let mut functions = vec![];
+    let mut ranges = vec![];
+    for (path, file_traces) in traces.iter() {
+        let public = public_functions.get(path).unwrap_or(&no_functions);
+        let mut sorted = file_traces.iter().collect::<Vec<_>>();
+        sorted.sort();
+        let file = (amount_covered(&sorted), amount_coverable(&sorted));
+
+        let mut claimed = HashSet::new();
+        for func in split_functions(&sorted) {
+            if public.contains(&func[0].line)
+                && amount_covered(&func) == 0
+                && func.iter().any(|t| is_uncovered(t))
+            {
+                claimed.extend(func.iter().map(|t| t.line));
+                functions.push(Suggestion::new(
+                    SuggestionKind::PublicFunction,
+                    path,
+                    &func,
+                    file,
+                ));
+            }
+        }
+
+        let mut run = vec![];
+        for trace in &sorted {
+            if is_uncovered(trace) && !claimed.contains(&trace.line) {
+                run.push(*trace);
+            } else if !run.is_empty() {
+                ranges.push(Suggestion::new(SuggestionKind::Range, path, &run, file));
+                run.clear();
+            }
+        }
+        if !run.is_empty() {
+            ranges.push(Suggestion::new(SuggestionKind::Range, path, &run, file));
+        }
+    }
+    let location = |a: &Suggestion, b: &Suggestion| {
+        a.path
+            .cmp(&b.path)
+            .then_with(|| a.start_line.cmp(&b.start_line))
+    };
+    functions.sort_by(|a, b| b.uncovered.cmp(&a.uncovered).then_with(|| location(a, b)));
+    ranges.sort_by(|a, b| {
+        b.score()
+            .partial_cmp(&a.score())
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| b.uncovered.cmp(&a.uncovered))
+            .then_with(|| location(a, b))
+    });
+    functions.extend(ranges);
+    functions.truncate(limit);
+    functions
+}
+
+/// The suggestions for the results if `--suggest` is set, with paths relative
+/// to the project root
+pub fn suggestions(config: &Config, traces: &TraceMap, metadata: &RunMetadata) -> Vec<Suggestion> {
+    match config.suggest {
+        Some(limit) => rank(traces, &metadata.public_functions, limit)
+            .into_iter()
+            .map(|mut s| {
+                s.path = PathBuf::from(config.display_path(&s.path));
+                s
+            })
+            .collect(),
+        None => vec![],
+    }
+}
+
+pub fn print_suggestions(suggestions: &[Suggestion]) {
+    if suggestions.is_empty() {
+        return;
+    }
+    println!("|| Suggested places to test next:");
+    for (i, s) in suggestions.iter().enumerate() {
+        match s.kind {
+            SuggestionKind::PublicFunction => println!(
+                "|| {}. {} public function {} is never called ({} lines)",
+                i + 1,
+                s.anchor(),
+                s.function.as_deref().unwrap_or("<unknown>"),
+                s.uncovered
+            ),
+            SuggestionKind::Range => println!(
+                "|| {}. {} lines {}-{} are uncovered ({} lines, rest of file {:.2}% covered)",
+                i + 1,
+                s.anchor(),
+                s.start_line,
+                s.end_line,
+                s.uncovered,
+                s.file_coverage
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace(line: u64, hits: u64, fn_name: Option<&str>) -> Trace {
+        Trace {
+            fn_name: fn_name.map(ToString::to_string),
+            ..Trace::test_line(line, hits)
+        }
+    }
+
+    fn public(entries: &[(&str, &[u64])]) -> BTreeMap<PathBuf, BTreeSet<u64>> {
+        entries
+            .iter()
+            .map(|(p, lines)| (PathBuf::from(p), lines.iter().copied().collect()))
+            .collect()
+    }
+
+    fn sample_map() -> TraceMap {
+        let mut map = TraceMap::new();
+        let a = Path::new("a.rs");
+        for t in &[
+            trace(1, 0, Some("pub_a")),
+            trace(2, 0, None),
+            trace(4, 1, Some("b")),
+            trace(5, 1, None),
+            trace(6, 0, None),
+            trace(7, 0, None),
+            trace(8, 1, None),
+            trace(10, 0, Some("c")),
+            trace(11, 0, None),
+            trace(12, 0, None),
+        ] {
+            map.add_trace(a, t.clone());
+        }
+        let b = Path::new("b.rs");
+        map.add_trace(b, trace(1, 0, Some("x")));
+        for line in 2..6 {
+            map.add_trace(b, trace(line, 0, None));
+        }
+        map
+    }
+
+    #[test]
+    fn public_functions_then_ranges() {
+        let map = sample_map();
+        let res = rank(&map, &public(&[("a.rs", &[1])]), 10);
+        let summary = res
+            .iter()
+            .map(|s| (s.anchor(), s.kind, s.end_line, s.uncovered))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("a.rs:1".to_string(), SuggestionKind::PublicFunction, 2, 2),
+                ("a.rs:10".to_string(), SuggestionKind::Range, 12, 3),
+                ("a.rs:6".to_string(), SuggestionKind::Range, 7, 2),
+                ("b.rs:1".to_string(), SuggestionKind::Range, 5, 5),
+            ]
+        );
+        assert_eq!(res[0].function, Some("pub_a".to_string()));
+        assert_eq!(res[1].function, None);
+        // 3 of the 7 lines outside the range are covered
+        assert!((res[1].file_coverage - 300.0 / 7.0).abs() < 1e-9);
+        assert_eq!(res[3].file_coverage, 0.0);
+
+        let res = rank(&map, &public(&[("a.rs", &[1])]), 2);
+        assert_eq!(res.len(), 2);
+        assert!(rank(&map, &public(&[]), 0).is_empty());
+    }
+
+    #[test]
+    fn entered_public_function_is_a_range() {
+        let map = sample_map();
+        // b is public but was entered so only its missed lines are suggested
+        let res = rank(&map, &public(&[("a.rs", &[4]), ("c.rs", &[1])]), 10);
+        assert!(res.iter().all(|s| s.kind == SuggestionKind::Range));
+        assert_eq!(res[0].anchor(), "a.rs:10");
+        // The never entered function is an ordinary range without `pub`
+        assert!(res
+            .iter()
+            .any(|s| s.anchor() == "a.rs:1" && s.end_line == 2));
+    }
+}
@@ -0,0 +1,158 @@
+//! Summary of what each run type contributed to the results so it's clear
+//! which kinds of targets were included in a run.
+use crate::config::{Config, RunType};
+use crate::traces::TraceMap;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ops::AddAssign;
+use std::path::PathBuf;
+
+/// Number of test binaries found and executed for a run type
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TargetCounts {
+    pub binaries: usize,
+    /// Times a binary was run, a binary runs twice if ignored tests are run
+    pub executions: usize,
+}
+
+impl AddAssign for TargetCounts {
+    fn add_assign(&mut self, other: Self) {
+        self.binaries += other.binaries;
+        self.executions += other.executions;
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TargetSummary {
+    pub run_type: RunType,
+    pub binaries: usize,
+    pub executions: usize,
+    /// Lines covered by this run type
+    pub covered: usize,
+    /// Percentage of all coverable lines covered by this run type
+    pub contribution: f64,
+}
+
+/// Creates a summary for each traced run type, `coverable` is the number of
+/// coverable lines once the results of all run types are merged.
+pub fn summarise(
+    counts: &BTreeMap<RunType, TargetCounts>,
+    traces: &BTreeMap<RunType, TraceMap>,
+    coverable: usize,
+) -> Vec<TargetSummary> {
+    counts
+        .iter()
+        .map(|(run_type, count)| {
+            let covered = traces.get(run_type).map_or(0, TraceMap::total_covered);
+            let contribution = if coverable == 0 {
+                0.0
+            } else {
+                100.0 * covered as f64 / coverable as f64
+            };
+            TargetSummary {
+                run_type: *run_type,
+                binaries: count.binaries,
+                executions: count.executions,
+                covered,
+                contribution,
+            }
+        })
+        .collect()
+}
+
+/// A test binary which failed and then passed when retried with `--retries`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FlakyBinary {
+    pub binary: PathBuf,
+    pub run_type: RunType,
+    /// Attempts which failed before one passed
+    pub failures: usize,
+}
+
+/// A bin run for the `Bins` run type and the arguments it was given
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BinRun {
+    pub name: String,
+    pub package: Option<String>,
+    pub args: Vec<String>,
+    pub exit_code: i32,
+}
+
+pub fn print_bin_runs(bins: &[BinRun]) {
+    println!("|| Bins run:");
+    for bin in bins {
+        println!(
+            "|| {}{}{}: exit code {}",
+            bin.name,
+            if bin.args.is_empty() { "" } else { " " },
+            bin.args.join(" "),
+            bin.exit_code
+        );
+    }
+}
+
+pub fn print_flaky_binaries(config: &Config, flaky: &[FlakyBinary]) {
+    println!("|| Flaky test binaries, passed after failing:");
+    for binary in flaky {
+        println!(
+            "|| {}: failed {} time{}",
+            config.display_path(&binary.binary),
+            binary.failures,
+            if binary.failures == 1 { "" } else { "s" }
+        );
+    }
+}
+
+pub fn print_target_summary(targets: &[TargetSummary]) {
+    println!("|| Run Types:");
+    println!(
+        "|| {:<12} {:>8} {:>10} {:>8} {:>12}",
+        "Type", "Binaries", "Executions", "Covered", "Contribution"
+    );
+    for target in targets {
+        println!(
+            "|| {:<12} {:>8} {:>10} {:>8} {:>11.2}%",
+            target.run_type.to_string(),
+            target.binaries,
+            target.executions,
+            target.covered,
+            target.contribution
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traces::Trace;
+    use std::path::Path;
+
+    #[test]
+    fn summary_per_run_type() {
+        let mut tests = TraceMap::new();
+        for (line, hits) in &[(1, 1), (2, 1), (3, 0), (4, 0)] {
+            tests.add_trace(Path::new("lib.rs"), Trace::test_line(*line, *hits));
+        }
+        let mut traces = BTreeMap::new();
+        traces.insert(RunType::Tests, tests);
+        let mut counts = BTreeMap::new();
+        counts.insert(
+            RunType::Tests,
+            TargetCounts {
+                binaries: 3,
+                executions: 3,
+            },
+        );
+        counts.insert(RunType::Examples, TargetCounts::default());
+
+        let res = summarise(&counts, &traces, 4);
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].run_type, RunType::Tests);
+        assert_eq!(res[0].binaries, 3);
+        assert_eq!(res[0].covered, 2);
+        assert_eq!(res[0].contribution, 50.0);
+        assert_eq!(res[1].run_type, RunType::Examples);
+        assert_eq!(res[1].covered, 0);
+        assert_eq!(res[1].contribution, 0.0);
+    }
+}
@@ -0,0 +1,136 @@
+//! TeamCity service messages, `--out Teamcity`. TeamCity reads
+//! `##teamcity[...]` messages from the build log, so the covered and total
+//! lines and the percentage are printed as build statistics for it to chart
+//! and fail builds on. With `--verbose` each file's coverage is also printed
+//! in a collapsible block of the log. Nothing is written to the output
+//! directory so it can be used alongside any file report.
+use crate::config::Config;
+use crate::errors::RunError;
+use crate::report::new_files::format_ranges;
+use crate::report::{Report, RunMetadata};
+use crate::traces::TraceMap;
+
+/// TeamCity service messages, `--out Teamcity`
+pub struct TeamcityReport;
+
+impl Report for TeamcityReport {
+    fn render(
+        &self,
+        config: &Config,
+        traces: &TraceMap,
+        _metadata: &RunMetadata,
+    ) -> Result<(), RunError> {
+        for message in messages(config, traces) {
+            println!("{}", message);
+        }
+        Ok(())
+    }
+
+    fn prints_to_stdout(&self) -> bool {
+        true
+    }
+
+    fn description(&self) -> &str {
+        "TeamCity service messages, printed to stdout"
+    }
+}
+
+/// Escapes a value for a service message, TeamCity uses `|` as the escape
+/// character
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '|' => escaped.push_str("||"),
+            '\'' => escaped.push_str("|'"),
+            '\n' => escaped.push_str("|n"),
+            '\r' => escaped.push_str("|r"),
+            '[' => escaped.push_str("|["),
+            ']' => escaped.push_str("|]"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn statistic(key: &str, value: &str) -> String {
+    format!(
+        "##teamcity[buildStatisticValue key='{}' value='{}']",
+        key, value
+    )
+}
+
+fn messages(config: &Config, traces: &TraceMap) -> Vec<String> {
+    let mut messages = vec![];
+    if config.verbose {
+        for file in traces.files() {
+            let name = escape(&config.display_path(file));
+            messages.push(format!(
+                "##teamcity[blockOpened name='{}' description='Coverage']",
+                name
+            ));
+            let coverable = traces.coverable_in_path(file);
+            let covered = traces.covered_in_path(file);
+            let percent = if coverable == 0 {
+                0.0
+            } else {
+                100.0 * covered as f64 / coverable as f64
+            };
+            let mut text = format!("{}/{} lines covered, {:.2}%", covered, coverable, percent);
+            let uncovered = traces.uncovered_ranges(file);
+            if !uncovered.is_empty() {
+                text.push_str(&format!(", uncovered lines {}", format_ranges(&uncovered)));
+            }
+            messages.push(format!("##teamcity[message text='{}']", escape(&text)));
+            messages.push(format!("##teamcity[blockClosed name='{}']", name));
+        }
+    }
+    let coverable = traces.total_coverable();
+    let percent = if coverable == 0 {
+        0.0
+    } else {
+        traces.coverage_percentage() * 100.0
+    };
+    messages.push(statistic(
+        "CodeCoverageAbsLCovered",
+        &traces.total_covered().to_string(),
+    ));
+    messages.push(statistic("CodeCoverageAbsLTotal", &coverable.to_string()));
+    messages.push(statistic("CodeCoverageL", &format!("{:.2}", percent)));
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traces::Trace;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn statistics_and_blocks() {
+        let mut traces = TraceMap::new();
+        for (line, hits) in &[(1, 2), (2, 0), (3, 0), (5, 1)] {
+            traces.add_trace(
+                Path::new("/project/src/[lib].rs"),
+                Trace::test_line(*line, *hits),
+            );
+        }
+        let mut config = Config::default();
+        config.manifest = PathBuf::from("/project/Cargo.toml");
+        let statistics = vec![
+            "##teamcity[buildStatisticValue key='CodeCoverageAbsLCovered' value='2']",
+            "##teamcity[buildStatisticValue key='CodeCoverageAbsLTotal' value='4']",
+            "##teamcity[buildStatisticValue key='CodeCoverageL' value='50.00']",
+        ];
+        assert_eq!(messages(&config, &traces), statistics);
+
+        config.verbose = true;
+        let mut expected = vec![
+            "##teamcity[blockOpened name='src/|[lib|].rs' description='Coverage']",
+            "##teamcity[message text='2/4 lines covered, 50.00%, uncovered lines 2-3']",
+            "##teamcity[blockClosed name='src/|[lib|].rs']",
+        ];
+        expected.extend(statistics);
+        assert_eq!(messages(&config, &traces), expected);
+    }
+}
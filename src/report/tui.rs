@@ -0,0 +1,407 @@
+//! Interactive terminal browser for the coverage results. This only reads the
+//! `TraceMap` and the source files so it can be used on any set of results.
+use crate::config::Config;
+use crate::errors::RunError;
+use crate::traces::{amount_coverable, amount_covered, CoverageStat, Trace, TraceMap};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::PathBuf;
+
+const HELP: &str = " q: quit | /: search | tab: switch pane | n/N: next/previous uncovered ";
+
+/// Coverage summary for a single file in the file list
+#[derive(Clone, Debug, PartialEq)]
+struct FileStats {
+    path: PathBuf,
+    /// Path relative to the project root used for display and searching
+    name: String,
+    covered: usize,
+    coverable: usize,
+}
+
+impl FileStats {
+    fn percentage(&self) -> f64 {
+        if self.coverable == 0 {
+            100.0
+        } else {
+            100.0 * self.covered as f64 / self.coverable as f64
+        }
+    }
+}
+
+/// Gets the coverage of every file sorted from least to most covered
+fn file_stats(config: &Config, traces: &TraceMap) -> Vec<FileStats> {
+    let mut result = traces
+        .iter()
+        .map(|(path, t)| {
+            let t = t.iter().collect::<Vec<_>>();
+            FileStats {
+                path: path.clone(),
+                name: config.display_path(path),
+                covered: amount_covered(&t),
+                coverable: amount_coverable(&t),
+            }
+        })
+        .collect::<Vec<_>>();
+    result.sort_by(|a, b| {
+        a.percentage()
+            .partial_cmp(&b.percentage())
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    result
+}
+
+fn is_uncovered(trace: &Trace) -> bool {
+    amount_coverable(&[trace]) > amount_covered(&[trace])
+}
+
+/// Returns the first line of each contiguous run of uncovered lines
+fn uncovered_regions(traces: &[Trace]) -> Vec<u64> {
+    let mut lines = traces
+        .iter()
+        .filter(|x| is_uncovered(x))
+        .map(|x| x.line)
+        .collect::<Vec<_>>();
+    lines.sort();
+    lines.dedup();
+    let mut result = vec![];
+    let mut last = None;
+    for line in lines {
+        if last.map(|l| l + 1) != Some(line) {
+            result.push(line);
+        }
+        last = Some(line);
+    }
+    result
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Focus {
+    Files,
+    Source,
+}
+
+struct App<'a> {
+    traces: &'a TraceMap,
+    files: Vec<FileStats>,
+    /// Indexes into `files` which match the current search
+    visible: Vec<usize>,
+    filter: String,
+    searching: bool,
+    focus: Focus,
+    list: ListState,
+    source: Vec<String>,
+    lines: HashMap<u64, &'a Trace>,
+    regions: Vec<u64>,
+    /// Zero indexed line at the top of the source view
+    scroll: usize,
+}
+
+impl<'a> App<'a> {
+    fn new(config: &Config, traces: &'a TraceMap) -> Self {
+        let files = file_stats(config, traces);
+        let mut app = Self {
+            traces,
+            visible: (0..files.len()).collect(),
+            files,
+            filter: String::new(),
+            searching: false,
+            focus: Focus::Files,
+            list: ListState::default(),
+            source: vec![],
+            lines: HashMap::new(),
+            regions: vec![],
+            scroll: 0,
+        };
+        app.select(0);
+        app
+    }
+
+    fn selected_file(&self) -> Option<&FileStats> {
+        self.list
+            .selected()
+            .and_then(|i| self.visible.get(i))
+            .map(|&i| &self.files[i])
+    }
+
+    fn select(&mut self, index: usize) {
+        if self.visible.is_empty() {
+            self.list.select(None);
+            self.source.clear();
+            self.lines.clear();
+            self.regions.clear();
+            return;
+        }
+        let index = index.min(self.visible.len() - 1);
+        self.list.select(Some(index));
+        let path = self.files[self.visible[index]].path.clone();
+        self.source = read_to_string(&path)
+            .map(|s| s.lines().map(ToString::to_string).collect())
+            .unwrap_or_else(|e| vec![format!("Unable to read {}: {}", path.display(), e)]);
+        let traces = self
+            .traces
+            .iter()
+            .find(|(k, _)| *k == &path)
+            .map(|(_, v)| v.as_slice())
+            .unwrap_or(&[]);
+        self.lines = traces.iter().map(|t| (t.line, t)).collect();
+        self.regions = uncovered_regions(traces);
+        self.scroll = 0;
+    }
+
+    fn update_filter(&mut self) {
+        let filter = self.filter.to_lowercase();
+        self.visible = self
+            .files
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| f.name.to_lowercase().contains(&filter))
+            .map(|(i, _)| i)
+            .collect();
+        self.select(0);
+    }
+
+    /// Moves the source view to the next uncovered region after the top of
+    /// the view or the previous one before it.
+    fn jump_uncovered(&mut self, forward: bool) {
+        let current = self.scroll as u64 + 1;
+        let target = if forward {
+            self.regions.iter().find(|&&l| l > current)
+        } else {
+            self.regions.iter().rev().find(|&&l| l < current)
+        };
+        if let Some(line) = target {
+            self.scroll = (*line as usize).saturating_sub(1);
+        }
+    }
+
+    /// Handles a key press, returning false when the browser should exit
+    fn handle_key(&mut self, key: KeyCode) -> bool {
+        if self.searching {
+            match key {
+                KeyCode::Esc | KeyCode::Enter => self.searching = false,
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                    self.update_filter();
+                }
+                KeyCode::Char(c) => {
+                    self.filter.push(c);
+                    self.update_filter();
+                }
+                _ => {}
+            }
+            return true;
+        }
+        let selected = self.list.selected().unwrap_or(0);
+        match (key, self.focus) {
+            (KeyCode::Char('q'), _) | (KeyCode::Esc, _) => return false,
+            (KeyCode::Char('/'), _) => {
+                self.searching = true;
+                self.focus = Focus::Files;
+            }
+            (KeyCode::Tab, Focus::Files) | (KeyCode::Enter, Focus::Files) => {
+                self.focus = Focus::Source
+            }
+            (KeyCode::Tab, Focus::Source) => self.focus = Focus::Files,
+            (KeyCode::Char('n'), _) => self.jump_uncovered(true),
+            (KeyCode::Char('N'), _) => self.jump_uncovered(false),
+            (KeyCode::Down, Focus::Files) | (KeyCode::Char('j'), Focus::Files) => {
+                self.select(selected + 1)
+            }
+            (KeyCode::Up, Focus::Files) | (KeyCode::Char('k'), Focus::Files) => {
+                self.select(selected.saturating_sub(1))
+            }
+            (KeyCode::Down, Focus::Source) | (KeyCode::Char('j'), Focus::Source) => {
+                self.scroll = (self.scroll + 1).min(self.source.len().saturating_sub(1))
+            }
+            (KeyCode::Up, Focus::Source) | (KeyCode::Char('k'), Focus::Source) => {
+                self.scroll = self.scroll.saturating_sub(1)
+            }
+            (KeyCode::PageDown, _) => {
+                self.scroll = (self.scroll + 20).min(self.source.len().saturating_sub(1))
+            }
+            (KeyCode::PageUp, _) => self.scroll = self.scroll.saturating_sub(20),
+            _ => {}
+        }
+        true
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, footer] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(35), Constraint::Percentage(65)])
+                .areas(main);
+
+        let focus = self.focus;
+        let focused = |f| {
+            if focus == f {
+                Style::default().fg(Color::Yellow)
+            } else {
+                Style::default()
+            }
+        };
+
+        let items = self
+            .visible
+            .iter()
+            .map(|&i| {
+                let f = &self.files[i];
+                ListItem::new(format!("{:>6.2}% {}", f.percentage(), f.name))
+                    .style(Style::default().fg(percentage_colour(f.percentage())))
+            })
+            .collect::<Vec<_>>();
+        let title = if self.searching || !self.filter.is_empty() {
+            format!(" Files /{} ", self.filter)
+        } else {
+            " Files ".to_string()
+        };
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .title(title)
+                    .borders(Borders::ALL)
+                    .border_style(focused(Focus::Files)),
+            )
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, left, &mut self.list);
+
+        let source = self
+            .source
+            .iter()
+            .enumerate()
+            .skip(self.scroll)
+            .map(|(i, text)| {
+                let line = i as u64 + 1;
+                let (marker, style) = match self.lines.get(&line) {
+                    Some(t) if is_uncovered(t) => ("   0", Style::default().fg(Color::Red)),
+                    Some(t) => match t.stats {
+                        CoverageStat::Line(hits) => {
+                            return Line::from(vec![
+                                Span::raw(format!("{:>5} ", line)),
+                                Span::styled(
+                                    format!("{:>4} ", hits),
+                                    Style::default().fg(Color::Green),
+                                ),
+                                Span::styled(text.clone(), Style::default().fg(Color::Green)),
+                            ]);
+                        }
+                        _ => ("   +", Style::default().fg(Color::Green)),
+                    },
+                    None => ("    ", Style::default()),
+                };
+                Line::from(vec![
+                    Span::raw(format!("{:>5} ", line)),
+                    Span::styled(format!("{} ", marker), style),
+                    Span::styled(text.clone(), style),
+                ])
+            })
+            .collect::<Vec<_>>();
+        let title = self
+            .selected_file()
+            .map(|f| format!(" {} ({}/{}) ", f.name, f.covered, f.coverable))
+            .unwrap_or_default();
+        let source = Paragraph::new(source).block(
+            Block::default()
+                .title(title)
+                .borders(Borders::ALL)
+                .border_style(focused(Focus::Source)),
+        );
+        frame.render_widget(source, right);
+        frame.render_widget(Paragraph::new(HELP), footer);
+    }
+}
+
+fn percentage_colour(percent: f64) -> Color {
+    if percent >= 80.0 {
+        Color::Green
+    } else if percent >= 50.0 {
+        Color::Yellow
+    } else {
+        Color::Red
+    }
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, app: &mut App) -> std::io::Result<()> {
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press && !app.handle_key(key.code) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Opens the browser, blocking until the user quits it.
+pub fn browse(config: &Config, traces: &TraceMap) -> Result<(), RunError> {
+    let mut app = App::new(config, traces);
+    let mut terminal = ratatui::try_init()
+        .map_err(|e| RunError::Tui(format!("Failed to start terminal UI: {}", e)))?;
+    let res = event_loop(&mut terminal, &mut app);
+    ratatui::restore();
+    res.map_err(|e| RunError::Tui(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uncovered_region_starts() {
+        let traces = vec![
+            Trace::test_line(1, 1),
+            Trace::test_line(2, 0),
+            Trace::test_line(3, 0),
+            Trace::test_line(5, 2),
+            Trace::test_line(6, 0),
+            Trace::test_line(8, 0),
+        ];
+        assert_eq!(uncovered_regions(&traces), vec![2, 6, 8]);
+    }
+
+    #[test]
+    fn files_sorted_by_coverage() {
+        let config = Config::default();
+        let mut traces = TraceMap::new();
+        traces.add_trace(&PathBuf::from("a.rs"), Trace::test_line(1, 1));
+        traces.add_trace(&PathBuf::from("b.rs"), Trace::test_line(1, 0));
+        traces.add_trace(&PathBuf::from("b.rs"), Trace::test_line(2, 1));
+        traces.add_trace(&PathBuf::from("c.rs"), Trace::test_line(1, 0));
+        let names = file_stats(&config, &traces)
+            .into_iter()
+            .map(|x| x.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["c.rs", "b.rs", "a.rs"]);
+    }
+
+    #[test]
+    fn search_and_jump() {
+        let config = Config::default();
+        let mut traces = TraceMap::new();
+        traces.add_trace(&PathBuf::from("src/lib.rs"), Trace::test_line(1, 1));
+        traces.add_trace(&PathBuf::from("src/lib.rs"), Trace::test_line(4, 0));
+        traces.add_trace(&PathBuf::from("src/main.rs"), Trace::test_line(1, 0));
+        let mut app = App::new(&config, &traces);
+        assert_eq!(app.visible.len(), 2);
+        app.handle_key(KeyCode::Char('/'));
+        for c in "lib".chars() {
+            app.handle_key(KeyCode::Char(c));
+        }
+        app.handle_key(KeyCode::Enter);
+        assert!(!app.searching);
+        assert_eq!(app.selected_file().unwrap().name, "src/lib.rs");
+        app.handle_key(KeyCode::Char('n'));
+        assert_eq!(app.scroll, 3);
+        app.handle_key(KeyCode::Char('N'));
+        assert_eq!(app.scroll, 3);
+        assert!(!app.handle_key(KeyCode::Char('q')));
+    }
+}
@@ -0,0 +1,96 @@
+//! Coverage weighted by the complexity of each line, `--weighted-coverage`.
+//! Plain line coverage counts a line of boilerplate the same as a line with
+//! several decisions on it, here each coverable line counts as its weight
+//! from `LineAnalysis::line_weight` instead: 1, plus one for each decision
+//! point on the line, plus one for each `if`, `else`, loop or match arm body
+//! it's nested in. Lines without a weight, such as those of files the analysis
+//! didn't see, weigh 1.
+use crate::traces::{amount_coverable, amount_covered, TraceMap};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Weights above 1 of the lines of each source file
+pub type LineWeights = BTreeMap<PathBuf, BTreeMap<u64, u64>>;
+
+/// Weighted coverable and covered lines of a file or of all the files
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct WeightedCoverage {
+    pub covered: u64,
+    pub coverable: u64,
+}
+
+impl WeightedCoverage {
+    /// Weighted coverage as a percentage, 0.0 if nothing is coverable
+    pub fn percent(&self) -> f64 {
+        if self.coverable == 0 {
+            0.0
+        } else {
+            self.covered as f64 * 100.0 / self.coverable as f64
+        }
+    }
+}
+
+/// Weighted coverage of a file
+pub fn file_coverage(traces: &TraceMap, weights: &LineWeights, path: &Path) -> WeightedCoverage {
+    let lines = weights.get(path);
+    let mut result = WeightedCoverage::default();
+    for trace in traces.get_child_traces(path) {
+        let weight = lines.and_then(|l| l.get(&trace.line)).copied().unwrap_or(1);
+        result.coverable += weight * amount_coverable(&[trace]) as u64;
+        result.covered += weight * amount_covered(&[trace]) as u64;
+    }
+    result
+}
+
+/// Weighted coverage of every file and of all of them together
+pub fn coverage(
+    traces: &TraceMap,
+    weights: &LineWeights,
+) -> (BTreeMap<PathBuf, WeightedCoverage>, WeightedCoverage) {
+    let files = traces
+        .files()
+        .into_iter()
+        .map(|path| (path.clone(), file_coverage(traces, weights, path)))
+        .collect::<BTreeMap<_, _>>();
+    let total = files
+        .values()
+        .fold(WeightedCoverage::default(), |acc, f| WeightedCoverage {
+            covered: acc.covered + f.covered,
+            coverable: acc.coverable + f.coverable,
+        });
+    (files, total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traces::Trace;
+
+    #[test]
+    fn lines_weighted() {
+        let mut traces = TraceMap::new();
+        let lines = [
+            ("/project/src/lib.rs", 1, 1),
+            ("/project/src/lib.rs", 2, 0),
+            ("/project/src/lib.rs", 3, 1),
+            ("/project/src/gen.rs", 1, 0),
+        ];
+        for (file, line, hits) in &lines {
+            traces.add_trace(Path::new(file), Trace::test_line(*line, *hits));
+        }
+        let mut weights = LineWeights::new();
+        weights.insert(
+            PathBuf::from("/project/src/lib.rs"),
+            vec![(2, 4), (3, 2)].into_iter().collect(),
+        );
+        let (files, total) = coverage(&traces, &weights);
+        let lib = files[Path::new("/project/src/lib.rs")];
+        assert_eq!((lib.covered, lib.coverable), (3, 7));
+        let gen = files[Path::new("/project/src/gen.rs")];
+        assert_eq!((gen.covered, gen.coverable), (0, 1));
+        assert_eq!((total.covered, total.coverable), (3, 8));
+        assert_eq!(total.percent(), 37.5);
+        assert_eq!(WeightedCoverage::default().percent(), 0.0);
+    }
+}
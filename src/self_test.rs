@@ -0,0 +1,246 @@
+//! Traces a small known crate to check whether tarpaulin works in the current
+//! environment. This helps tell apart problems with a project from problems
+//! with the machine or container tarpaulin is running in.
+use crate::config::Config;
+use crate::errors::RunError;
+use crate::launch_tarpaulin;
+use crate::traces::{CoverageStat, TraceMap};
+use log::{info, warn};
+use std::fs::{self, create_dir_all};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const MANIFEST: &str = r#"[package]
+name = "tarpaulin_self_test"
+version = "0.1.0"
+edition = "2018"
+
+[dependencies]
+
+[workspace]
+"#;
+
+const LIB: &str = r#"pub fn basic(x: u32) -> u32 {
+    let y = x * 2;
+    y + 1
+}
+
+pub fn threaded(x: u32) -> u32 {
+    let handle = std::thread::spawn(move || {
+        let y = x + 1;
+        y * 3
+    });
+    handle.join().unwrap()
+}
+
+pub fn forked(x: u32) -> u32 {
+    x * 4
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" {
+        fn fork() -> i32;
+        fn waitpid(pid: i32, status: *mut i32, options: i32) -> i32;
+        fn _exit(status: i32) -> !;
+    }
+
+    #[test]
+    fn check_basic() {
+        assert_eq!(basic(2), 5);
+    }
+
+    #[test]
+    fn check_threaded() {
+        assert_eq!(threaded(1), 6);
+    }
+
+    #[test]
+    fn check_fork() {
+        unsafe {
+            let child = fork();
+            if child == 0 {
+                // Exit without unwinding into the test harness
+                _exit(if forked(1) == 4 { 0 } else { 1 });
+            }
+            let mut status = -1;
+            assert_eq!(waitpid(child, &mut status, 0), child);
+            assert_eq!(status, 0);
+        }
+    }
+}
+"#;
+
+/// Outcome of checking a single capability
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    Pass,
+    Fail,
+    /// The capability isn't supported or enabled so wasn't checked
+    Skipped,
+}
+
+/// Lines in the fixture `lib.rs` which have to be hit for each capability
+const CAPABILITIES: &[(&str, &[u64])] = &[
+    ("basic lines", &[2, 3]),
+    ("threads", &[8, 9]),
+    ("fork-follow", &[15]),
+];
+
+fn check_lines(traces: &TraceMap, file: &Path, lines: &[u64]) -> Outcome {
+    let hit = traces
+        .get_child_traces(file)
+        .iter()
+        .filter(|t| lines.contains(&t.line))
+        .filter(|t| match t.stats {
+            CoverageStat::Line(hits) => hits > 0,
+            _ => false,
+        })
+        .count();
+    if hit == lines.len() {
+        Outcome::Pass
+    } else {
+        Outcome::Fail
+    }
+}
+
+/// Checks which capabilities work given the results of tracing the fixture
+pub fn check_results(
+    traces: &TraceMap,
+    lib: &Path,
+    config: &Config,
+) -> Vec<(&'static str, Outcome)> {
+    let mut result = CAPABILITIES
+        .iter()
+        .map(|(name, lines)| (*name, check_lines(traces, lib, lines)))
+        .collect::<Vec<_>>();
+    // Forked children are traced, line 15 only runs in one. Branch coverage
+    // isn't collected yet
+    if config.branch_coverage {
+        result.push(("branch", Outcome::Skipped));
+    }
+    result
+}
+
+fn write_fixture(dir: &Path) -> std::io::Result<PathBuf> {
+    create_dir_all(dir.join("src"))?;
+    fs::write(dir.join("Cargo.toml"), MANIFEST)?;
+    fs::write(dir.join("src").join("lib.rs"), LIB)?;
+    dir.canonicalize()
+}
+
+/// Creates a config for the fixture keeping the users flags that affect how
+/// tests are built and traced but none relating to their project.
+fn fixture_config(config: &Config, dir: &Path) -> Config {
+    let mut result = Config::default();
+    result.manifest = dir.join("Cargo.toml");
+    result.debug = config.debug;
+    result.verbose = config.verbose;
+    result.count = config.count;
+    result.branch_coverage = config.branch_coverage;
+    result.forward_signals = config.forward_signals;
+    result.test_timeout = config.test_timeout;
+    result.release = config.release;
+    result.locked = config.locked;
+    result.frozen = config.frozen;
+    result.offline = config.offline;
+    result.unstable_features = config.unstable_features.clone();
+    result.affinity = config.affinity;
+    result
+}
+
+fn print_diagnostics() {
+    println!("|| Environment:");
+    let uname = nix::sys::utsname::uname();
+    println!("||   kernel: {} {}", uname.sysname(), uname.release());
+    let ptrace_scope = fs::read_to_string("/proc/sys/kernel/yama/ptrace_scope")
+        .map(|x| x.trim().to_string())
+        .unwrap_or_else(|_| "unknown".to_string());
+    println!("||   ptrace_scope: {}", ptrace_scope);
+    let container = Path::new("/.dockerenv").exists()
+        || fs::read_to_string("/proc/1/cgroup")
+            .map(|x| x.contains("docker") || x.contains("kubepods"))
+            .unwrap_or(false);
+    println!("||   container: {}", container);
+    let rustc = Command::new("rustc")
+        .arg("--version")
+        .output()
+        .map(|x| String::from_utf8_lossy(&x.stdout).trim().to_string())
+        .unwrap_or_else(|_| "unknown".to_string());
+    println!("||   rustc: {}", rustc);
+    if ptrace_scope != "0" && ptrace_scope != "unknown" {
+        println!("||   ptrace may be restricted, in docker try --security-opt seccomp=unconfined");
+    }
+}
+
+/// Builds and traces the fixture with the given config, printing the outcome
+/// of each capability. Returns false if basic line coverage doesn't work.
+pub fn run(config: &Config) -> Result<bool, RunError> {
+    let dir = std::env::temp_dir().join(format!("tarpaulin-self-test-{}", std::process::id()));
+    let dir = write_fixture(&dir)?;
+    info!("Running self test in {}", dir.display());
+    let fixture = fixture_config(config, &dir);
+    let traces = launch_tarpaulin(&fixture);
+    if let Err(e) = fs::remove_dir_all(&dir) {
+        warn!("Failed to remove {}: {}", dir.display(), e);
+    }
+    let results = match traces {
+        Ok((traces, _)) => check_results(&traces, &dir.join("src").join("lib.rs"), config),
+        Err(e) => {
+            warn!("Failed to trace self test crate: {}", e);
+            check_results(&TraceMap::new(), &dir, config)
+        }
+    };
+    println!("|| Self test results:");
+    for (name, outcome) in &results {
+        let outcome = match outcome {
+            Outcome::Pass => "pass",
+            Outcome::Fail => "FAIL",
+            Outcome::Skipped => "skipped (not supported)",
+        };
+        println!("||   {}: {}", name, outcome);
+    }
+    if results.iter().any(|(_, o)| *o == Outcome::Fail) {
+        print_diagnostics();
+    }
+    Ok(results[0].1 == Outcome::Pass)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traces::Trace;
+
+    #[test]
+    fn capability_outcomes() {
+        let lib = PathBuf::from("/tmp/self_test/src/lib.rs");
+        let mut traces = TraceMap::new();
+        for (line, hits) in &[(2, 1), (3, 1), (8, 1), (9, 0), (15, 1)] {
+            traces.add_trace(&lib, Trace::test_line(*line, *hits));
+        }
+        let res = check_results(&traces, &lib, &Config::default());
+        assert_eq!(res[0], ("basic lines", Outcome::Pass));
+        assert_eq!(res[1], ("threads", Outcome::Fail));
+        assert_eq!(res[2], ("fork-follow", Outcome::Pass));
+        assert_eq!(res.len(), 3);
+
+        let mut config = Config::default();
+        config.branch_coverage = true;
+        let res = check_results(&TraceMap::new(), &lib, &config);
+        assert_eq!(res[2], ("fork-follow", Outcome::Fail));
+        assert_eq!(res[3], ("branch", Outcome::Skipped));
+    }
+
+    #[test]
+    fn fixture_isolated_from_project() {
+        let mut config = Config::default();
+        config.packages = vec!["foo".to_string()];
+        config.release = true;
+        let fixture = fixture_config(&config, Path::new("/tmp/self_test"));
+        assert!(fixture.packages.is_empty());
+        assert!(fixture.release);
+        assert_eq!(fixture.manifest, PathBuf::from("/tmp/self_test/Cargo.toml"));
+    }
+}
@@ -10,20 +10,8 @@ pub(crate) fn check_attr_list(
     for attr in attrs {
         analysis.ignore_tokens(attr);
         if let Ok(x) = attr.parse_meta() {
-            if check_cfg_attr(&x) {
+            if check_cfg_attr(&x, ctx.settings) || (ctx.settings.ignore_tests && is_cfg_test(&x)) {
                 check_cover = false;
-            } else if ctx.config.ignore_tests && x.path().is_ident("cfg") {
-                if let Meta::List(ref ml) = x {
-                    let mut skip = false;
-                    for c in &ml.nested {
-                        if let NestedMeta::Meta(Meta::Path(ref i)) = c {
-                            skip |= i.is_ident("test");
-                        }
-                    }
-                    if skip {
-                        check_cover = false;
-                    }
-                }
             }
         }
         if !check_cover {
@@ -33,7 +21,45 @@ pub(crate) fn check_attr_list(
     check_cover
 }
 
-pub(crate) fn check_cfg_attr(attr: &Meta) -> bool {
+/// Returns true for `#[cfg(test)]`
+pub(crate) fn is_cfg_test(attr: &Meta) -> bool {
+    match attr {
+        Meta::List(ref ml) if ml.path.is_ident("cfg") => ml.nested.iter().any(|x| match x {
+            NestedMeta::Meta(Meta::Path(ref i)) => i.is_ident("test"),
+            _ => false,
+        }),
+        _ => false,
+    }
+}
+
+/// Why an item or method with the attributes is test code, `None` if it
+/// isn't. Test attributes are only looked for on functions.
+pub(crate) fn test_reason(attrs: &[Attribute], is_fn: bool) -> Option<TestReason> {
+    let metas = attrs
+        .iter()
+        .filter_map(|x| x.parse_meta().ok())
+        .collect::<Vec<_>>();
+    if metas.iter().any(is_cfg_test) {
+        Some(TestReason::CfgTest)
+    } else if is_fn
+        && metas
+            .iter()
+            .any(|x| x.path().is_ident("test") || is_runtime_attr(x.path(), "test"))
+    {
+        Some(TestReason::TestAttribute)
+    } else {
+        None
+    }
+}
+
+/// Returns true if the attribute means the item it's attached to should be
+/// ignored, either `#[cfg_attr(tarpaulin, skip)]`, one of the attributes
+/// turning off coverage in the instrumentation based coverage or one of the
+/// skip markers in the settings.
+pub(crate) fn check_cfg_attr(attr: &Meta, settings: &AnalysisSettings) -> bool {
+    if coverage_state(attr) == Some(false) || is_skip_marker(attr, &settings.skip_markers) {
+        return true;
+    }
     let mut ignore_span = false;
     let id = attr.path();
     if id.is_ident("cfg_attr") {
@@ -56,3 +82,64 @@ pub(crate) fn check_cfg_attr(attr: &Meta) -> bool {
     }
     ignore_span
 }
+
+/// Whether the attribute is one of the paths in `AnalysisSettings::skip_markers`
+fn is_skip_marker(attr: &Meta, markers: &[String]) -> bool {
+    if markers.is_empty() {
+        return false;
+    }
+    let path = attr
+        .path()
+        .segments
+        .iter()
+        .map(|s| s.ident.to_string())
+        .collect::<Vec<_>>()
+        .join("::");
+    markers.contains(&path)
+}
+
+/// Returns true if any of the attributes is `#[coverage(on)]`
+pub(crate) fn is_coverage_on(attrs: &[Attribute]) -> bool {
+    attrs
+        .iter()
+        .filter_map(|x| x.parse_meta().ok())
+        .any(|x| coverage_state(&x) == Some(true))
+}
+
+/// Gets whether an attribute turns coverage on or off. `#[coverage(off)]` and
+/// the older `#[no_coverage]` turn it off and `#[coverage(on)]` turns it on.
+/// As these attributes are nightly only they're commonly wrapped in a
+/// `cfg_attr` so they're recognised there regardless of the predicate.
+fn coverage_state(attr: &Meta) -> Option<bool> {
+    match attr {
+        Meta::Path(ref p) if p.is_ident("no_coverage") => Some(false),
+        Meta::List(ref ml) if ml.path.is_ident("coverage") && ml.nested.len() == 1 => {
+            match ml.nested.first() {
+                Some(NestedMeta::Meta(Meta::Path(ref p))) if p.is_ident("off") => Some(false),
+                Some(NestedMeta::Meta(Meta::Path(ref p))) if p.is_ident("on") => Some(true),
+                _ => None,
+            }
+        }
+        Meta::List(ref ml) if ml.path.is_ident("cfg_attr") => ml
+            .nested
+            .iter()
+            .skip(1)
+            .filter_map(|x| match x {
+                NestedMeta::Meta(ref m) => coverage_state(m),
+                _ => None,
+            })
+            .last(),
+        _ => None,
+    }
+}
+
+/// Returns true for async runtime attributes such as `#[tokio::main]` or
+/// `#[async_std::test]` where the last path segment is `name`
+pub(crate) fn is_runtime_attr(path: &syn::Path, name: &str) -> bool {
+    path.segments.len() > 1
+        && path
+            .segments
+            .last()
+            .map(|x| x.ident == name)
+            .unwrap_or(false)
+}
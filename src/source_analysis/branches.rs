@@ -0,0 +1,118 @@
+use std::ops::Range;
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+use syn::*;
+
+/// Finds the lines of the decision points in a file, roughly what adds to its
+/// cyclomatic complexity: `if` and `while` conditions, `for` loops, every match
+/// arm after the first, `&&`, `||` and `?`. A line appears once per decision
+/// point on it.
+pub(crate) fn find_branch_points(file: &File) -> Vec<usize> {
+    let mut points = BranchPoints::default();
+    points.visit_file(file);
+    points.lines
+}
+
+/// Finds the lines nested in each `if`, `else`, loop and match arm body, not
+/// counting the line the body starts on. A line appears in one range per body
+/// it's nested in.
+pub(crate) fn find_nested_lines(file: &File) -> Vec<Range<usize>> {
+    let mut nesting = Nesting::default();
+    nesting.visit_file(file);
+    nesting.bodies
+}
+
+#[derive(Default)]
+struct BranchPoints {
+    lines: Vec<usize>,
+}
+
+impl BranchPoints {
+    fn add<T: Spanned>(&mut self, token: &T) {
+        self.lines.push(token.span().start().line);
+    }
+}
+
+impl<'ast> Visit<'ast> for BranchPoints {
+    fn visit_expr_if(&mut self, i: &'ast ExprIf) {
+        self.add(&i.if_token);
+        visit::visit_expr_if(self, i);
+    }
+
+    fn visit_expr_while(&mut self, i: &'ast ExprWhile) {
+        self.add(&i.while_token);
+        visit::visit_expr_while(self, i);
+    }
+
+    fn visit_expr_for_loop(&mut self, i: &'ast ExprForLoop) {
+        self.add(&i.for_token);
+        visit::visit_expr_for_loop(self, i);
+    }
+
+    fn visit_expr_match(&mut self, i: &'ast ExprMatch) {
+        for arm in i.arms.iter().skip(1) {
+            self.add(arm);
+        }
+        visit::visit_expr_match(self, i);
+    }
+
+    fn visit_expr_binary(&mut self, i: &'ast ExprBinary) {
+        if let BinOp::And(_) | BinOp::Or(_) = i.op {
+            self.add(&i.op);
+        }
+        visit::visit_expr_binary(self, i);
+    }
+
+    fn visit_expr_try(&mut self, i: &'ast ExprTry) {
+        self.add(&i.question_token);
+        visit::visit_expr_try(self, i);
+    }
+}
+
+#[derive(Default)]
+struct Nesting {
+    bodies: Vec<Range<usize>>,
+}
+
+impl Nesting {
+    fn add<T: Spanned>(&mut self, body: &T) {
+        let span = body.span();
+        let (start, end) = (span.start().line, span.end().line);
+        if end > start {
+            self.bodies.push((start + 1)..(end + 1));
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for Nesting {
+    fn visit_expr_if(&mut self, i: &'ast ExprIf) {
+        self.add(&i.then_branch);
+        // An `else if` is nested in the bodies of its own branches
+        if let Some((_, ref expr)) = i.else_branch {
+            if let Expr::Block(ref block) = **expr {
+                self.add(block);
+            }
+        }
+        visit::visit_expr_if(self, i);
+    }
+
+    fn visit_expr_while(&mut self, i: &'ast ExprWhile) {
+        self.add(&i.body);
+        visit::visit_expr_while(self, i);
+    }
+
+    fn visit_expr_for_loop(&mut self, i: &'ast ExprForLoop) {
+        self.add(&i.body);
+        visit::visit_expr_for_loop(self, i);
+    }
+
+    fn visit_expr_loop(&mut self, i: &'ast ExprLoop) {
+        self.add(&i.body);
+        visit::visit_expr_loop(self, i);
+    }
+
+    fn visit_arm(&mut self, i: &'ast Arm) {
+        self.add(&i.body);
+        visit::visit_arm(self, i);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::source_analysis::prelude::*;
+use proc_macro2::TokenTree;
 use std::collections::HashSet;
 use syn::{punctuated::Pair, punctuated::Punctuated, spanned::Spanned, token::Comma, *};
 
@@ -114,7 +115,10 @@ fn visit_match(mat: &ExprMatch, ctx: &Context, analysis: &mut LineAnalysis) -> S
     // a match with some arms is unreachable iff all its arms are unreachable
     let mut reachable_arm = false;
     for arm in &mat.arms {
-        if check_attr_list(&arm.attrs, ctx, analysis) {
+        if ctx.settings.ignore_panics && is_assert_false(&arm.body) {
+            analysis.ignore_tokens(&arm.body);
+            reachable_arm = true;
+        } else if check_attr_list(&arm.attrs, ctx, analysis) {
             if let SubResult::Ok = process_expr(&arm.body, ctx, analysis) {
                 reachable_arm = true
             }
@@ -140,7 +144,10 @@ fn visit_if(if_block: &ExprIf, ctx: &Context, analysis: &mut LineAnalysis) -> Su
         reachable_arm = true;
     }
     if let Some((_, ref else_block)) = if_block.else_branch {
-        if let SubResult::Ok = process_expr(&else_block, ctx, analysis) {
+        if ctx.settings.ignore_panics && is_assert_false(&else_block) {
+            analysis.ignore_tokens(else_block);
+            reachable_arm = true;
+        } else if let SubResult::Ok = process_expr(&else_block, ctx, analysis) {
             reachable_arm = true;
         }
     } else {
@@ -155,6 +162,36 @@ fn visit_if(if_block: &ExprIf, ctx: &Context, analysis: &mut LineAnalysis) -> Su
     }
 }
 
+/// Whether an else block or match arm is only an `assert!(false, ..)`, used to
+/// mark it as never meant to run
+fn is_assert_false(expr: &Expr) -> bool {
+    let mac = match expr {
+        Expr::Macro(m) => &m.mac,
+        Expr::Block(b) => match b.block.stmts.as_slice() {
+            [Stmt::Expr(Expr::Macro(m))] | [Stmt::Semi(Expr::Macro(m), _)] => &m.mac,
+            _ => return false,
+        },
+        _ => return false,
+    };
+    let is_assert = mac
+        .path
+        .segments
+        .last()
+        .map(|s| s.ident == "assert")
+        .unwrap_or(false);
+    let mut args = mac.tokens.clone().into_iter();
+    let first_false = match args.next() {
+        Some(TokenTree::Ident(i)) => i == "false",
+        _ => false,
+    };
+    let arg_ends = match args.next() {
+        None => true,
+        Some(TokenTree::Punct(p)) => p.as_char() == ',',
+        _ => false,
+    };
+    is_assert && first_false && arg_ends
+}
+
 fn visit_while(whl: &ExprWhile, ctx: &Context, analysis: &mut LineAnalysis) -> SubResult {
     if check_attr_list(&whl.attrs, ctx, analysis) {
         // a while block is unreachable iff its body is
@@ -219,8 +256,9 @@ fn visit_callable(call: &ExprCall, ctx: &Context, analysis: &mut LineAnalysis) -
     if check_attr_list(&call.attrs, ctx, analysis) {
         if !call.args.is_empty() {
             let lines = get_coverable_args(&call.args);
+            let func = get_line_range(&call.func);
             let lines = get_line_range(call)
-                .filter(|x| !lines.contains(&x))
+                .filter(|x| !lines.contains(&x) && !func.contains(x))
                 .collect::<Vec<_>>();
             analysis.add_to_ignore(&lines);
         }
@@ -1,6 +1,5 @@
 use crate::source_analysis::prelude::*;
 use quote::ToTokens;
-use std::path::PathBuf;
 use syn::{spanned::Spanned, *};
 
 pub(crate) fn process_items(
@@ -10,20 +9,30 @@ pub(crate) fn process_items(
 ) -> SubResult {
     let mut res = SubResult::Ok;
     for item in items.iter() {
+        if let Some(reason) = item_test_reason(item) {
+            analysis.add_test_tokens(item, reason);
+            if let Item::Mod(ref i) = *item {
+                if i.content.is_none() {
+                    let path = module_path(ctx.file, &ctx.inline_mods.borrow(), i);
+                    ctx.test_mods.borrow_mut().insert(path);
+                }
+            }
+            if ctx.settings.ignore_tests {
+                match *item {
+                    Item::Mod(ref i) => visit_mod(i, analysis, ctx),
+                    _ => analysis.ignore_tokens(item),
+                }
+                continue;
+            }
+        }
         match *item {
             Item::ExternCrate(ref i) => analysis.ignore_tokens(i),
             Item::Use(ref i) => analysis.ignore_tokens(i),
             Item::Mod(ref i) => visit_mod(&i, analysis, ctx),
             Item::Fn(ref i) => visit_fn(&i, analysis, ctx),
-            Item::Struct(ref i) => {
-                analysis.ignore_tokens(i);
-            }
-            Item::Enum(ref i) => {
-                analysis.ignore_tokens(i);
-            }
-            Item::Union(ref i) => {
-                analysis.ignore_tokens(i);
-            }
+            Item::Struct(ref i) => visit_type_definition(i, analysis, ctx),
+            Item::Enum(ref i) => visit_type_definition(i, analysis, ctx),
+            Item::Union(ref i) => visit_type_definition(i, analysis, ctx),
             Item::Trait(ref i) => visit_trait(&i, analysis, ctx),
             Item::Impl(ref i) => visit_impl(&i, analysis, ctx),
             Item::Macro(ref i) => {
@@ -40,70 +49,86 @@ pub(crate) fn process_items(
     res
 }
 
+/// Why the item is test code, `None` if it isn't
+fn item_test_reason(item: &Item) -> Option<TestReason> {
+    let (attrs, is_fn) = match *item {
+        Item::Fn(ref i) => (&i.attrs, true),
+        Item::Mod(ref i) => (&i.attrs, false),
+        Item::Impl(ref i) => (&i.attrs, false),
+        Item::Trait(ref i) => (&i.attrs, false),
+        Item::Struct(ref i) => (&i.attrs, false),
+        Item::Enum(ref i) => (&i.attrs, false),
+        Item::Union(ref i) => (&i.attrs, false),
+        Item::Const(ref i) => (&i.attrs, false),
+        Item::Static(ref i) => (&i.attrs, false),
+        Item::Use(ref i) => (&i.attrs, false),
+        Item::Macro(ref i) => (&i.attrs, false),
+        _ => return None,
+    };
+    test_reason(attrs, is_fn)
+}
+
+fn visit_type_definition<T: ToTokens>(item: &T, analysis: &mut LineAnalysis, ctx: &Context) {
+    analysis.type_definitions.push(get_line_range(item));
+    if !ctx.settings.include_derives {
+        analysis.ignore_tokens(item);
+    }
+}
+
 fn visit_mod(module: &ItemMod, analysis: &mut LineAnalysis, ctx: &Context) {
     analysis.ignore_tokens(module.mod_token);
     let mut check_insides = true;
     for attr in &module.attrs {
         if let Ok(x) = attr.parse_meta() {
-            if check_cfg_attr(&x) {
+            if check_cfg_attr(&x, ctx.settings) {
                 analysis.ignore_tokens(module);
-                if let Some((ref braces, _)) = module.content {
+                if let Some((ref braces, ref items)) = module.content {
                     analysis.ignore_span(braces.span);
+                    visit_coverage_on(items, analysis, ctx);
                 }
                 check_insides = false;
                 break;
-            } else if ctx.config.ignore_tests && x.path().is_ident("cfg") {
-                if let Meta::List(ref ml) = x {
-                    for nested in &ml.nested {
-                        if let NestedMeta::Meta(Meta::Path(ref i)) = *nested {
-                            if i.is_ident("test") {
-                                check_insides = false;
-                                analysis.ignore_tokens(module.mod_token);
-                                if let Some((ref braces, _)) = module.content {
-                                    analysis.ignore_span(braces.span);
-                                }
-                            }
-                        }
-                    }
+            } else if ctx.settings.ignore_tests && is_cfg_test(&x) {
+                check_insides = false;
+                analysis.ignore_tokens(module.mod_token);
+                if let Some((ref braces, _)) = module.content {
+                    analysis.ignore_span(braces.span);
                 }
             }
         }
     }
     if check_insides {
         if let Some((_, ref items)) = module.content {
+            ctx.inline_mods.borrow_mut().push(module.ident.to_string());
             process_items(items, ctx, analysis);
+            ctx.inline_mods.borrow_mut().pop();
         }
     } else {
-        // Get the file or directory name of the module
-        let mut p = if let Some(parent) = ctx.file.parent() {
-            parent.join(module.ident.to_string())
-        } else {
-            PathBuf::from(module.ident.to_string())
-        };
-        if !p.exists() {
-            p.set_extension("rs");
-        }
-        ctx.ignore_mods.borrow_mut().insert(p);
+        let path = module_path(ctx.file, &ctx.inline_mods.borrow(), module);
+        ctx.ignore_mods.borrow_mut().insert(path);
     }
 }
 
 fn visit_fn(func: &ItemFn, analysis: &mut LineAnalysis, ctx: &Context) {
-    let mut test_func = false;
     let mut ignored_attr = false;
     let mut is_inline = false;
     let mut ignore_span = false;
+    let mut runtime_attr_lines = vec![];
     for attr in &func.attrs {
         if let Ok(x) = attr.parse_meta() {
             let id = x.path();
-            if id.is_ident("test") {
-                test_func = true;
+            if is_runtime_attr(id, "main") || is_runtime_attr(id, "test") {
+                // Async runtimes rewrite the function and the generated runtime
+                // setup is attributed to the attribute's lines. Tests are
+                // already ignored with `ignore_tests`
+                runtime_attr_lines.extend(get_line_range(attr));
             } else if id.is_ident("derive") {
                 analysis.ignore_span(attr.bracket_token.span);
             } else if id.is_ident("inline") {
                 is_inline = true;
             } else if id.is_ident("ignore") {
                 ignored_attr = true;
-            } else if check_cfg_attr(&x) {
+            } else if check_cfg_attr(&x, ctx.settings) {
                 ignore_span = true;
                 break;
             }
@@ -111,7 +136,17 @@ fn visit_fn(func: &ItemFn, analysis: &mut LineAnalysis, ctx: &Context) {
     }
     if ignore_span {
         analysis.ignore_tokens(func);
-    } else if (test_func && ctx.config.ignore_tests) || (ignored_attr && !ctx.config.run_ignored) {
+        let items = func
+            .block
+            .stmts
+            .iter()
+            .filter_map(|x| match x {
+                Stmt::Item(ref i) => Some(i.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        visit_coverage_on(&items, analysis, ctx);
+    } else if ignored_attr && !ctx.settings.run_ignored {
         analysis.ignore_tokens(func);
     } else {
         if is_inline {
@@ -124,9 +159,15 @@ fn visit_fn(func: &ItemFn, analysis: &mut LineAnalysis, ctx: &Context) {
             analysis.ignore_tokens(func);
             return;
         }
+        analysis.add_code_tokens(&func.sig.fn_token);
+        analysis.add_code_tokens(&func.block);
         visit_generics(&func.sig.generics, analysis);
         let line_number = func.sig.fn_token.span().start().line;
         analysis.ignore.remove(&Lines::Line(line_number));
+        for line in runtime_attr_lines {
+            analysis.logical_lines.insert(line, line_number);
+            analysis.runtime_attrs.insert(line);
+        }
         // Ignore multiple lines of fn decl
         let decl_start = func.sig.fn_token.span().start().line + 1;
         let stmts_start = func.block.span().start().line;
@@ -140,10 +181,15 @@ fn visit_trait(trait_item: &ItemTrait, analysis: &mut LineAnalysis, ctx: &Contex
     if check_cover {
         for item in &trait_item.items {
             if let TraitItem::Method(ref i) = *item {
+                if let Some(reason) = test_reason(&i.attrs, true) {
+                    analysis.add_test_tokens(i, reason);
+                }
                 if check_attr_list(&i.attrs, ctx, analysis) {
                     if let Some(ref block) = i.default {
                         analysis
                             .cover_token_stream(item.into_token_stream(), Some(ctx.file_contents));
+                        analysis.add_code_tokens(&i.sig.fn_token);
+                        analysis.add_code_tokens(block);
                         visit_generics(&i.sig.generics, analysis);
                         analysis
                             .ignore
@@ -174,25 +220,13 @@ fn visit_impl(impl_blk: &ItemImpl, analysis: &mut LineAnalysis, ctx: &Context) {
     if check_cover {
         for item in &impl_blk.items {
             if let ImplItem::Method(ref i) = *item {
+                if let Some(reason) = test_reason(&i.attrs, true) {
+                    analysis.add_test_tokens(i, reason);
+                }
                 if check_attr_list(&i.attrs, ctx, analysis) {
-                    analysis.cover_token_stream(i.into_token_stream(), Some(ctx.file_contents));
-                    if let SubResult::Unreachable =
-                        process_statements(&i.block.stmts, ctx, analysis)
-                    {
-                        // if the body of this method is unreachable, this means that the method
-                        // cannot be called, and is unreachable
-                        analysis.ignore_tokens(i);
+                    if let SubResult::Unreachable = visit_impl_method(i, analysis, ctx) {
                         return;
                     }
-
-                    visit_generics(&i.sig.generics, analysis);
-                    analysis.ignore.remove(&Lines::Line(i.span().start().line));
-
-                    // Ignore multiple lines of fn decl
-                    let decl_start = i.sig.fn_token.span().start().line + 1;
-                    let stmts_start = i.block.span().start().line;
-                    let lines = (decl_start..(stmts_start + 1)).collect::<Vec<_>>();
-                    analysis.add_to_ignore(&lines);
                 } else {
                     analysis.ignore_tokens(item);
                 }
@@ -204,5 +238,75 @@ fn visit_impl(impl_blk: &ItemImpl, analysis: &mut LineAnalysis, ctx: &Context) {
         visit_generics(&impl_blk.generics, analysis);
     } else {
         analysis.ignore_tokens(impl_blk);
+        visit_coverage_on_methods(impl_blk, analysis, ctx);
+    }
+}
+
+fn visit_impl_method(
+    method: &ImplItemMethod,
+    analysis: &mut LineAnalysis,
+    ctx: &Context,
+) -> SubResult {
+    analysis.cover_token_stream(method.into_token_stream(), Some(ctx.file_contents));
+    if let SubResult::Unreachable = process_statements(&method.block.stmts, ctx, analysis) {
+        // if the body of this method is unreachable, this means that the method
+        // cannot be called, and is unreachable
+        analysis.ignore_tokens(method);
+        return SubResult::Unreachable;
+    }
+
+    analysis.add_code_tokens(&method.sig.fn_token);
+    analysis.add_code_tokens(&method.block);
+    visit_generics(&method.sig.generics, analysis);
+    analysis
+        .ignore
+        .remove(&Lines::Line(method.span().start().line));
+
+    // Ignore multiple lines of fn decl
+    let decl_start = method.sig.fn_token.span().start().line + 1;
+    let stmts_start = method.block.span().start().line;
+    let lines = (decl_start..(stmts_start + 1)).collect::<Vec<_>>();
+    analysis.add_to_ignore(&lines);
+    SubResult::Ok
+}
+
+/// Items marked `#[coverage(on)]` inside an item with coverage turned off are
+/// included again. This is called after the outer item has been ignored.
+fn visit_coverage_on(items: &[Item], analysis: &mut LineAnalysis, ctx: &Context) {
+    for item in items {
+        let attrs = match *item {
+            Item::Fn(ref i) => &i.attrs,
+            Item::Impl(ref i) => &i.attrs,
+            Item::Mod(ref i) => &i.attrs,
+            _ => continue,
+        };
+        if is_coverage_on(attrs) {
+            analysis.unignore_tokens(item);
+            process_items(std::slice::from_ref(item), ctx, analysis);
+            continue;
+        }
+        match *item {
+            Item::Mod(ref i) => {
+                if let Some((_, ref items)) = i.content {
+                    visit_coverage_on(items, analysis, ctx);
+                }
+            }
+            Item::Impl(ref i) => visit_coverage_on_methods(i, analysis, ctx),
+            _ => {}
+        }
+    }
+}
+
+fn visit_coverage_on_methods(impl_blk: &ItemImpl, analysis: &mut LineAnalysis, ctx: &Context) {
+    for item in &impl_blk.items {
+        if let ImplItem::Method(ref i) = *item {
+            if is_coverage_on(&i.attrs) {
+                analysis.unignore_tokens(i);
+                visit_impl_method(i, analysis, ctx);
+                for a in &i.attrs {
+                    analysis.ignore_tokens(a);
+                }
+            }
+        }
     }
 }
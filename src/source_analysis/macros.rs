@@ -44,8 +44,14 @@ pub(crate) fn visit_macro_call(
         let unreachable = ident == "unreachable";
         let standard_ignores =
             ident == "unimplemented" || ident == "include" || ident == "cfg" || ident == "todo";
-        let ignore_panic = ctx.config.ignore_panics && ident == "panic";
-        if standard_ignores || ignore_panic || unreachable {
+        let ignore_panic = ctx.settings.ignore_panics && ident == "panic";
+        let debug_assert =
+            ident == "debug_assert" || ident == "debug_assert_eq" || ident == "debug_assert_ne";
+        if debug_assert {
+            analysis.debug_asserts.extend(get_line_range(mac));
+        }
+        let ignore_debug_assert = debug_assert && !ctx.settings.count_debug_asserts;
+        if standard_ignores || ignore_panic || ignore_debug_assert || unreachable {
             analysis.ignore_tokens(mac);
             skip = true;
         }
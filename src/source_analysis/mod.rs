@@ -1,26 +1,67 @@
-use crate::config::{Config, RunType};
+//! Source analysis finds which lines of a crate are executable Rust logic
+//! that should be covered and which are ignored, for example because they're
+//! tests, unreachable or only contain syntax such as closing braces. This is
+//! the half of tarpaulin that doesn't trace, exposed for other tools such as
+//! mutation testers which want the same classification.
+//!
+//! # Stability
+//!
+//! `analyze_crate`, `AnalysisSettings`, `IgnoreReason`, `TestReason`,
+//! `TargetCfg`, `PackageFeatures` and the public methods of `LineAnalysis`
+//! follow semver. Fields may be added to `AnalysisSettings` in minor releases
+//! so construct it with `..Default::default()`. Which lines are classified as
+//! coverable isn't part of the contract, it's refined between releases as the
+//! analysis improves.
+//! Which lines are test code is, see `TestReason`.
+//!
+//! ```no_run
+//! use cargo_tarpaulin::source_analysis::{analyze_crate, AnalysisSettings};
+//! use std::path::Path;
+//!
+//! let settings = AnalysisSettings {
+//!     ignore_tests: true,
+//!     ..Default::default()
+//! };
+//! for (file, analysis) in analyze_crate(Path::new("."), &settings) {
+//!     let lines = analysis.coverable_lines().collect::<Vec<_>>();
+//!     println!("{}: {:?}", file.display(), lines);
+//! }
+//! ```
+use crate::config::{matches_any, Config, RunType, WarningKind};
+use branches::{find_branch_points, find_nested_lines};
 use items::process_items;
 use lazy_static::lazy_static;
-use log::trace;
-use proc_macro2::{Span, TokenStream};
+use log::{debug, trace, warn};
+use proc_macro2::{Span, TokenStream, TokenTree};
 use quote::ToTokens;
 use regex::Regex;
+use scaffolding::find_scaffolding;
 use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
 use std::ffi::OsStr;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Read};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use syn::*;
+use target::{count_module_lines, find_non_target_code, parse_cfg, rustflags_cfgs, BuildCfg};
+use visibility::find_public_functions;
 use walkdir::{DirEntry, WalkDir};
 
 mod attributes;
+mod branches;
 mod expressions;
 mod items;
 mod macros;
+mod scaffolding;
 mod statements;
+mod target;
 #[cfg(test)]
 mod tests;
+mod visibility;
+
+pub use target::{PackageFeatures, TargetCfg};
 
 pub(crate) mod prelude {
     pub(crate) use super::*;
@@ -30,9 +71,148 @@ pub(crate) mod prelude {
     pub(crate) use statements::*;
 }
 
+/// Settings which change the results of the analysis
+#[derive(Clone, Debug)]
+pub struct AnalysisSettings {
+    /// Ignore test code, see `TestReason` for what's test code
+    pub ignore_tests: bool,
+    /// Whether `#[ignore]` tests are run, if not they're ignored
+    pub run_ignored: bool,
+    /// Ignore `panic!` calls
+    pub ignore_panics: bool,
+    /// Whether `debug_assert!` lines are coverable
+    pub count_debug_asserts: bool,
+    /// Analyse the files in the examples directory
+    pub include_examples: bool,
+    /// Whether the lines of struct, enum and union definitions are coverable,
+    /// code placed on them comes from derives
+    pub include_derives: bool,
+    /// The cfg values of the target, code under a `cfg` which is false for it
+    /// isn't coverable. If `None` code for every target is counted
+    pub target: Option<TargetCfg>,
+    /// The features enabled for each package, code under a `cfg(feature)`
+    /// which is false for the file's package isn't coverable. If `None` code
+    /// for every feature is counted
+    pub features: Option<PackageFeatures>,
+    /// Cfg options set for the build besides the target's, `name` or
+    /// `name="value"` as given to rustc with `--cfg`. Code under a `cfg`
+    /// which is false with them isn't coverable, from a `Config` they're read
+    /// from `RUSTFLAGS`
+    pub cfgs: Vec<String>,
+    /// Paths of attributes which mark an item to skip like
+    /// `#[cfg_attr(tarpaulin, skip)]` does, such as `mutants::skip` for
+    /// `#[mutants::skip]`
+    pub skip_markers: Vec<String>,
+    /// Name patterns of the test helper functions reported by
+    /// `scaffolding_lines`, `*` matches any characters
+    pub scaffolding: Vec<String>,
+    /// Path patterns relative to the root of files whose parse failures are
+    /// debug logs rather than warnings and aren't counted as unparsed
+    pub suppress_parse_fallback: Vec<String>,
+    /// Files excluded from the coverage which aren't analysed
+    pub excluded_files: HashSet<PathBuf>,
+}
+
+impl Default for AnalysisSettings {
+    fn default() -> Self {
+        Self {
+            ignore_tests: false,
+            run_ignored: false,
+            ignore_panics: false,
+            count_debug_asserts: true,
+            include_examples: false,
+            include_derives: false,
+            target: None,
+            features: None,
+            cfgs: vec![],
+            skip_markers: vec![],
+            scaffolding: vec![],
+            suppress_parse_fallback: vec![],
+            excluded_files: HashSet::new(),
+        }
+    }
+}
+
+impl<'a> From<&'a Config> for AnalysisSettings {
+    fn from(config: &'a Config) -> Self {
+        Self {
+            ignore_tests: config.ignore_tests,
+            run_ignored: config.run_ignored,
+            ignore_panics: config.ignore_panics,
+            count_debug_asserts: config.count_debug_asserts,
+            include_examples: config.run_types.contains(&RunType::Examples),
+            include_derives: config.include_derives,
+            target: if config.count_all_targets {
+                None
+            } else {
+                TargetCfg::query(config.target.as_deref())
+            },
+            features: if config.count_all_targets {
+                None
+            } else {
+                PackageFeatures::query(config)
+            },
+            cfgs: match env::var("RUSTFLAGS") {
+                Ok(flags) if !config.count_all_targets => rustflags_cfgs(&flags),
+                _ => vec![],
+            },
+            skip_markers: vec![],
+            scaffolding: config.scaffolding_patterns(),
+            suppress_parse_fallback: config
+                .suppress
+                .iter()
+                .filter(|(k, _)| k.parse() == Ok(WarningKind::ParseFallback))
+                .flat_map(|(_, patterns)| patterns.iter().cloned())
+                .collect(),
+            excluded_files: if config.has_excluded_files() || config.selects_packages() {
+                source_files(&config.root())
+                    .into_iter()
+                    .filter(|f| config.excludes_file(f))
+                    .collect()
+            } else {
+                HashSet::new()
+            },
+        }
+    }
+}
+
+/// Why a line isn't coverable
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum IgnoreReason {
+    /// Every line in the file is ignored, such as a module only built for tests
+    File,
+    /// A `debug_assert!` which isn't counted
+    DebugAssert,
+    /// The line was ignored by the analysis or an attribute
+    Line,
+}
+
+/// Why a line is test code. Test code is found the same way whether or not
+/// `ignore_tests` is set, only with it set is test code ignored. Lines are
+/// classified by attributes and paths alone, a function without a test
+/// attribute is never test code however it's used:
+///
+/// * Files in the `tests` directory of a package are test code.
+/// * Files declared with `#[cfg(test)] mod name;` are test code.
+/// * Items with `#[cfg(test)]`, including helper functions, are test code.
+/// * Functions with `#[test]` or an async runtime's test attribute are test
+///   code.
+///
+/// Where test code is nested the outermost reason is given, so a `#[test]`
+/// function in a `#[cfg(test)]` module is `CfgTest`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum TestReason {
+    /// The file is in the `tests` directory of a package
+    TestsDirectory,
+    /// The file or item is only built with `#[cfg(test)]`
+    CfgTest,
+    /// The function has a test attribute
+    TestAttribute,
+}
+
 /// Enumeration representing which lines to ignore
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
-pub enum Lines {
+pub(crate) enum Lines {
     /// Ignore all lines in the file
     All,
     /// A single line to ignore in the file
@@ -45,15 +225,43 @@ pub enum Lines {
 pub struct LineAnalysis {
     /// This represents lines that should be ignored in coverage
     /// but may be identifed as coverable in the DWARF tables
-    pub ignore: HashSet<Lines>,
+    pub(crate) ignore: HashSet<Lines>,
     /// This represents lines that should be included in coverage
     /// But may be ignored. Doesn't make sense to cover ALL the lines so this
     /// is just an index.
-    pub cover: HashSet<usize>,
+    pub(crate) cover: HashSet<usize>,
+    /// Lines with code in function signatures and bodies, a line with only a
+    /// closing delimiter has none
+    pub(crate) code_lines: HashSet<usize>,
     /// Some logical lines may be split between physical lines this shows the
     /// mapping from physical line to logical line to prevent false positives
     /// from expressions split across physical lines
-    pub logical_lines: HashMap<usize, usize>,
+    pub(crate) logical_lines: HashMap<usize, usize>,
+    /// Lines of `debug_assert!` macros which don't exist in release builds
+    pub(crate) debug_asserts: HashSet<usize>,
+    /// Line of each decision point, a line is repeated if it has several
+    pub(crate) branch_lines: Vec<usize>,
+    /// Lines nested in each `if`, `else`, loop and match arm body
+    pub(crate) nested_lines: Vec<Range<usize>>,
+    /// Lines of functions and methods declared `pub`
+    pub(crate) public_fns: HashSet<usize>,
+    /// Number of lines excluded as they aren't built for the target
+    pub(crate) non_target_lines: usize,
+    /// Lines spanned by each struct, enum and union definition, code the
+    /// debug info places here comes from derives on the type
+    pub(crate) type_definitions: Vec<Range<usize>>,
+    /// Lines of async runtime attributes such as `#[tokio::main]`, functions
+    /// the debug info declares here are wrappers generated by the runtime
+    pub(crate) runtime_attrs: HashSet<usize>,
+    /// Whether the file couldn't be parsed and had the textual analysis
+    /// instead, files whose parse failures are suppressed aren't marked
+    pub(crate) unparsed: bool,
+    /// Why the whole file is test code if it is
+    pub(crate) test_file: Option<TestReason>,
+    /// Why each line of test code in the file is test code
+    pub(crate) test_lines: HashMap<usize, TestReason>,
+    /// Lines spanned by each function matching the scaffolding patterns
+    pub(crate) scaffolding: Vec<Range<usize>>,
 }
 
 /// When the `LineAnalysis` results are mapped to their files there needs to be
@@ -100,15 +308,165 @@ impl LineAnalysis {
         Default::default()
     }
 
+    /// Whether the line is executable logic that should be covered, code in a
+    /// function which isn't ignored. Only the first line of a statement split
+    /// over several lines is coverable, see `logical_line`.
+    pub fn is_coverable(&self, line: usize) -> bool {
+        (self.code_lines.contains(&line) || self.cover.contains(&line))
+            && self.logical_line(line) == line
+            && !self.should_ignore(line)
+    }
+
+    /// Why the line is ignored, `None` if it isn't
+    pub fn ignore_reason(&self, line: usize) -> Option<IgnoreReason> {
+        if self.ignore.contains(&Lines::All) {
+            Some(IgnoreReason::File)
+        } else if !self.ignore.contains(&Lines::Line(line)) {
+            None
+        } else if self.debug_asserts.contains(&line) {
+            Some(IgnoreReason::DebugAssert)
+        } else {
+            Some(IgnoreReason::Line)
+        }
+    }
+
+    /// Whether every line in the file is ignored
+    pub fn ignores_all(&self) -> bool {
+        self.ignore.contains(&Lines::All)
+    }
+
+    /// Lines found to be coverable, in no particular order. Lines which don't
+    /// contain code such as blank lines are neither coverable nor ignored.
+    pub fn coverable_lines(&self) -> impl Iterator<Item = usize> + '_ {
+        self.code_lines
+            .union(&self.cover)
+            .copied()
+            .filter(move |l| self.is_coverable(*l))
+    }
+
+    /// Lines which are explicitly ignored, in no particular order. This is
+    /// empty if the whole file is ignored, see `ignores_all`
+    pub fn ignored_lines(&self) -> impl Iterator<Item = usize> + '_ {
+        self.ignore.iter().filter_map(|l| match l {
+            Lines::Line(l) => Some(*l),
+            Lines::All => None,
+        })
+    }
+
+    /// Lines of `debug_assert!` calls, these don't exist in release builds
+    pub fn debug_assert_lines(&self) -> impl Iterator<Item = usize> + '_ {
+        self.debug_asserts.iter().copied()
+    }
+
+    /// Number of decision points in the lines which aren't ignored: `if`,
+    /// `while` and `for` expressions, match arms after the first, `&&`, `||`
+    /// and `?`. Files which couldn't be parsed have none.
+    pub fn branch_points(&self) -> usize {
+        self.branch_lines
+            .iter()
+            .filter(|l| !self.should_ignore(**l))
+            .count()
+    }
+
+    /// Weight of a line in the weighted coverage: 1, plus one for each
+    /// decision point on the line, plus one for each `if`, `else`, loop or
+    /// match arm body the line is nested in. The line a body starts on isn't
+    /// nested in it, so single line arms have the weight of the code around
+    /// them. Files which couldn't be parsed weigh 1 per line.
+    pub fn line_weight(&self, line: usize) -> u64 {
+        let points = self.branch_lines.iter().filter(|l| **l == line).count();
+        let depth = self
+            .nested_lines
+            .iter()
+            .filter(|r| r.contains(&line))
+            .count();
+        (1 + points + depth) as u64
+    }
+
+    /// Weights of the lines which aren't ignored and weigh more than 1, see
+    /// `line_weight`
+    pub fn line_weights(&self) -> BTreeMap<u64, u64> {
+        self.branch_lines
+            .iter()
+            .copied()
+            .chain(self.nested_lines.iter().cloned().flatten())
+            .filter(|l| !self.should_ignore(*l))
+            .map(|l| (l as u64, self.line_weight(l)))
+            .collect()
+    }
+
+    /// Lines where a `pub` function or method is declared, in no particular
+    /// order. A declaration may contribute two lines if `pub` and `fn` are
+    /// split, ignored functions aren't included.
+    pub fn public_functions(&self) -> impl Iterator<Item = usize> + '_ {
+        self.public_fns
+            .iter()
+            .copied()
+            .filter(move |l| !self.should_ignore(*l))
+    }
+
+    /// Number of lines excluded because they aren't built for the target, such
+    /// as `#[cfg(windows)]` code on Linux. This includes the files of excluded
+    /// modules declared in this file.
+    pub fn non_target_lines(&self) -> usize {
+        self.non_target_lines
+    }
+
+    /// Whether the line is part of a struct, enum or union definition
+    pub fn in_type_definition(&self, line: usize) -> bool {
+        self.type_definitions.iter().any(|r| r.contains(&line))
+    }
+
+    /// Why the line is test code, `None` if it isn't. This is the same whether
+    /// or not `ignore_tests` is set, see `TestReason`
+    pub fn test_reason(&self, line: usize) -> Option<TestReason> {
+        self.test_file
+            .or_else(|| self.test_lines.get(&line).copied())
+    }
+
+    /// Why the whole file is test code, `None` if it isn't
+    pub fn test_file_reason(&self) -> Option<TestReason> {
+        self.test_file
+    }
+
+    /// Lines of the test code functions whose names match the `scaffolding`
+    /// patterns, in no particular order. Tests themselves are never
+    /// scaffolding, nor are functions outside test code.
+    pub fn scaffolding_lines(&self) -> impl Iterator<Item = usize> + '_ {
+        self.scaffolding
+            .iter()
+            .flat_map(|r| r.clone())
+            .filter(move |l| self.test_reason(*l).is_some())
+    }
+
+    /// The line a statement split over several lines is reported on, lines
+    /// which aren't part of a split statement map to themselves
+    pub fn logical_line(&self, line: usize) -> usize {
+        self.logical_lines.get(&line).copied().unwrap_or(line)
+    }
+
+    /// Whether the file couldn't be parsed so had a less accurate textual
+    /// analysis
+    pub fn is_unparsed(&self) -> bool {
+        self.unparsed
+    }
+
+    /// Whether the line is an async runtime attribute which rewrites the
+    /// function it's on
+    pub fn is_runtime_attr(&self, line: usize) -> bool {
+        self.runtime_attrs.contains(&line)
+    }
+
     /// Ignore all lines in the file
-    pub fn ignore_all(&mut self) {
+    pub(crate) fn ignore_all(&mut self) {
         self.ignore.clear();
         self.cover.clear();
+        self.code_lines.clear();
         self.ignore.insert(Lines::All);
     }
 
     /// Ignore all tokens in the given token stream
-    pub fn ignore_tokens<T>(&mut self, tokens: T)
+    pub(crate) fn ignore_tokens<T>(&mut self, tokens: T)
     where
         T: ToTokens,
     {
@@ -117,8 +475,22 @@ impl LineAnalysis {
         }
     }
 
+    /// Records the lines of the tokens as test code, lines already found to
+    /// be test code keep their reason
+    pub(crate) fn add_test_tokens<T>(&mut self, tokens: T, reason: TestReason)
+    where
+        T: ToTokens,
+    {
+        for token in tokens.into_token_stream() {
+            let span = token.span();
+            for i in span.start().line..(span.end().line + 1) {
+                self.test_lines.entry(i).or_insert(reason);
+            }
+        }
+    }
+
     /// Adds the lines of the provided span to the ignore set
-    pub fn ignore_span(&mut self, span: Span) {
+    pub(crate) fn ignore_span(&mut self, span: Span) {
         // If we're already ignoring everything no need to ignore this span
         if !self.ignore.contains(&Lines::All) {
             for i in span.start().line..(span.end().line + 1) {
@@ -130,15 +502,48 @@ impl LineAnalysis {
         }
     }
 
+    /// Removes the lines of all tokens in the given token stream from the
+    /// ignore set
+    fn unignore_tokens<T>(&mut self, tokens: T)
+    where
+        T: ToTokens,
+    {
+        for token in tokens.into_token_stream() {
+            let span = token.span();
+            for i in span.start().line..(span.end().line + 1) {
+                self.ignore.remove(&Lines::Line(i));
+            }
+        }
+    }
+
+    /// Records the lines of the tokens as code
+    pub(crate) fn add_code_tokens<T: ToTokens>(&mut self, tokens: &T) {
+        self.add_code_stream(tokens.to_token_stream());
+    }
+
+    fn add_code_stream(&mut self, tokens: TokenStream) {
+        for token in tokens {
+            match token {
+                TokenTree::Group(ref group) => {
+                    self.code_lines.insert(group.span_open().start().line);
+                    self.add_code_stream(group.stream());
+                }
+                ref token => {
+                    self.code_lines.insert(token.span().start().line);
+                }
+            }
+        }
+    }
+
     /// Cover all tokens in the given tokenstream
-    pub fn cover_token_stream(&mut self, tokens: TokenStream, contents: Option<&str>) {
+    pub(crate) fn cover_token_stream(&mut self, tokens: TokenStream, contents: Option<&str>) {
         for token in tokens {
             self.cover_span(token.span(), contents);
         }
     }
 
     /// Adds the lines of the provided span to the cover set
-    pub fn cover_span(&mut self, span: Span, contents: Option<&str>) {
+    pub(crate) fn cover_span(&mut self, span: Span, contents: Option<&str>) {
         // Not checking for Lines::All because I trust we've called cover_span
         // for a reason.
         let mut useful_lines: HashSet<usize> = HashSet::new();
@@ -194,6 +599,46 @@ impl LineAnalysis {
     }
 }
 
+/// Gets the file or directory of a module declared with `mod name;` inside the
+/// inline modules `parents`. Modules declared in a `mod.rs` or a crate root
+/// are in the file's directory, those declared in any other file are in a
+/// directory named after it.
+pub(crate) fn module_path(file: &Path, parents: &[String], module: &ItemMod) -> PathBuf {
+    let mut p = file.parent().map(Path::to_path_buf).unwrap_or_default();
+    if !owns_directory(file) {
+        if let Some(stem) = file.file_stem() {
+            p.push(stem);
+        }
+    }
+    p.extend(parents);
+    p.push(module.ident.to_string());
+    if !p.exists() {
+        p.set_extension("rs");
+    }
+    p
+}
+
+/// Whether the modules a file declares are in its own directory, true for
+/// `mod.rs` files and the crate roots cargo finds by default
+fn owns_directory(file: &Path) -> bool {
+    fn name(p: Option<&Path>) -> Option<&str> {
+        p.and_then(Path::file_name).and_then(OsStr::to_str)
+    }
+    match name(Some(file)) {
+        Some("mod.rs") | Some("lib.rs") | Some("main.rs") | Some("build.rs") | None => true,
+        _ => {
+            let dir = file.parent();
+            match name(dir) {
+                Some("bin") => name(dir.and_then(Path::parent)) == Some("src"),
+                Some("tests") | Some("examples") | Some("benches") => dir
+                    .and_then(Path::parent)
+                    .map_or(false, |p| p.join("Cargo.toml").is_file()),
+                _ => false,
+            }
+        }
+    }
+}
+
 /// Returns true if the file is a rust source file
 fn is_source_file(entry: &DirEntry) -> bool {
     let p = entry.path();
@@ -214,41 +659,83 @@ fn is_hidden(entry: &DirEntry) -> bool {
 }
 
 /// Returns a list of files and line numbers to ignore (not indexes!)
-pub fn get_line_analysis(config: &Config) -> HashMap<PathBuf, LineAnalysis> {
+pub(crate) fn get_line_analysis(config: &Config) -> HashMap<PathBuf, LineAnalysis> {
+    let result = analyze_crate(&config.root(), &AnalysisSettings::from(config));
+    debug_printout(&result, config);
+    result
+}
+
+/// The Rust source files under `root` which are analysed, skipping the
+/// target directory and hidden directories
+pub fn source_files(root: &Path) -> Vec<PathBuf> {
+    WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|e| !(is_target_folder(e, root) || is_hidden(e)))
+        .filter_map(|e| e.ok())
+        .filter(|e| is_source_file(e))
+        .map(|e| e.into_path())
+        .collect()
+}
+
+/// Analyses every Rust source file under `root`, skipping the target
+/// directory and hidden directories. Files without an entry weren't analysed,
+/// such as examples unless they're included, and files which can't be parsed
+/// get a less accurate textual analysis.
+pub fn analyze_crate(root: &Path, settings: &AnalysisSettings) -> HashMap<PathBuf, LineAnalysis> {
     let mut result: HashMap<PathBuf, LineAnalysis> = HashMap::new();
 
     let mut ignored_files: HashSet<PathBuf> = HashSet::new();
-    let root = config.root();
+    let mut test_mods: HashSet<PathBuf> = HashSet::new();
+    let mut unparsed = 0;
 
-    let walker = WalkDir::new(&root).into_iter();
-    for e in walker
-        .filter_entry(|e| !(is_target_folder(e, &root) || is_hidden(e)))
-        .filter_map(|e| e.ok())
-        .filter(|e| is_source_file(e))
-    {
-        if !ignored_files.contains(e.path()) {
-            analyse_package(e.path(), &root, &config, &mut result, &mut ignored_files);
+    for path in source_files(root) {
+        if settings.excluded_files.contains(&path) {
+            continue;
+        }
+        if !ignored_files.contains(&path) {
+            if analyse_package(
+                &path,
+                root,
+                settings,
+                &mut result,
+                &mut ignored_files,
+                &mut test_mods,
+            ) {
+                unparsed += 1;
+            }
         } else {
             let mut analysis = LineAnalysis::new();
             analysis.ignore_all();
-            result.insert(e.path().to_path_buf(), analysis);
-            ignored_files.remove(e.path());
+            ignored_files.remove(&path);
+            result.insert(path, analysis);
         }
     }
-    for e in &ignored_files {
+    for e in ignored_files.difference(&settings.excluded_files) {
         let mut analysis = LineAnalysis::new();
         analysis.ignore_all();
         result.insert(e.to_path_buf(), analysis);
     }
-
-    debug_printout(&result, config);
-
+    // Test modules are test code whichever order the files were analysed in
+    for (path, analysis) in result.iter_mut() {
+        if analysis.test_file.is_none() && test_mods.iter().any(|m| path.starts_with(m)) {
+            analysis.test_file = Some(TestReason::CfgTest);
+        }
+        if settings.ignore_tests && analysis.test_file.is_some() {
+            analysis.ignore_all();
+        }
+    }
+    if unparsed > 0 {
+        warn!(
+            "{} files couldn't be parsed and had less accurate textual analysis, upgrading tarpaulin may fix this",
+            unparsed
+        );
+    }
     result
 }
 
 /// Printout a debug summary of the results of source analysis if debug logging
 /// is enabled
-pub fn debug_printout(result: &HashMap<PathBuf, LineAnalysis>, config: &Config) {
+pub(crate) fn debug_printout(result: &HashMap<PathBuf, LineAnalysis>, config: &Config) {
     if config.debug {
         for (ref path, ref analysis) in result {
             trace!(
@@ -308,8 +795,8 @@ fn analyse_lib_rs(file: &Path, result: &mut HashMap<PathBuf, LineAnalysis>) {
 /// Provides context to the source analysis stage including the tarpaulin
 /// config and the source code being analysed.
 pub(crate) struct Context<'a> {
-    /// Program config
-    config: &'a Config,
+    /// Settings for the analysis
+    settings: &'a AnalysisSettings,
     /// Contents of the source file
     file_contents: &'a str,
     /// path to the file being analysed
@@ -317,60 +804,174 @@ pub(crate) struct Context<'a> {
     /// Other parts of context are immutable like tarpaulin config and users
     /// source code. This is discovered during hence use of interior mutability
     ignore_mods: RefCell<HashSet<PathBuf>>,
+    /// Files and directories of the modules declared with `#[cfg(test)]`
+    test_mods: RefCell<HashSet<PathBuf>>,
+    /// Inline modules enclosing the items being processed, innermost last
+    inline_mods: RefCell<Vec<String>>,
 }
 
-/// Analyses a package of the target crate.
+/// Whether the file is in the `tests` directory of a package under `root`,
+/// where cargo finds integration tests. A `tests` directory that isn't next to
+/// a `Cargo.toml`, such as a module directory in `src`, doesn't count.
+fn in_tests_directory(root: &Path, path: &Path) -> bool {
+    let relative = match path.strip_prefix(root) {
+        Ok(relative) => relative,
+        Err(_) => return false,
+    };
+    let mut dir = root.to_path_buf();
+    for component in relative.parent().into_iter().flat_map(Path::components) {
+        if component.as_os_str() == "tests" && dir.join("Cargo.toml").is_file() {
+            return true;
+        }
+        dir.push(component);
+    }
+    false
+}
+
+/// Analyses a package of the target crate. Returns true if the file couldn't
+/// be parsed and the textual fallback was used, unless the warning for it was
+/// suppressed.
 fn analyse_package(
     path: &Path,
     root: &Path,
-    config: &Config,
+    settings: &AnalysisSettings,
     result: &mut HashMap<PathBuf, LineAnalysis>,
     filtered_files: &mut HashSet<PathBuf>,
-) {
+    test_mods: &mut HashSet<PathBuf>,
+) -> bool {
+    let mut fallback = false;
     if let Some(file) = path.to_str() {
-        let skip_cause_test = config.ignore_tests && path.starts_with(root.join("tests"));
-        let skip_cause_example = path.starts_with(root.join("examples"))
-            && !config.run_types.contains(&RunType::Examples);
-        if !(skip_cause_test || skip_cause_example) {
+        let test_file = if in_tests_directory(root, path) {
+            Some(TestReason::TestsDirectory)
+        } else {
+            None
+        };
+        let skip_cause_example =
+            path.starts_with(root.join("examples")) && !settings.include_examples;
+        if test_file.is_some() && settings.ignore_tests {
+            // Ignored entirely so there's no need to parse it
+            let mut analysis = LineAnalysis::new();
+            analysis.test_file = test_file;
+            analysis.ignore_all();
+            result.insert(path.to_path_buf(), analysis);
+        } else if !skip_cause_example {
             let file = File::open(file);
             if let Ok(mut file) = file {
                 let mut content = String::new();
                 let _ = file.read_to_string(&mut content);
                 let file = parse_file(&content);
-                if let Ok(file) = file {
-                    let mut analysis = LineAnalysis::new();
-                    let ctx = Context {
-                        config,
-                        file_contents: &content,
-                        file: path,
-                        ignore_mods: RefCell::new(HashSet::new()),
-                    };
-
-                    find_ignorable_lines(&content, &mut analysis);
-                    process_items(&file.items, &ctx, &mut analysis);
-                    // Check there's no conflict!
-                    result.insert(path.to_path_buf(), analysis);
-
-                    let mut ignored_files = ctx.ignore_mods.into_inner();
-                    for f in ignored_files.drain() {
-                        if f.is_file() {
-                            filtered_files.insert(f);
-                        } else {
-                            let walker = WalkDir::new(f).into_iter();
-                            for e in walker.filter_map(|e| e.ok()).filter(|e| is_source_file(e)) {
-                                filtered_files.insert(e.path().to_path_buf());
+                match file {
+                    Ok(file) => {
+                        let mut analysis = LineAnalysis::new();
+                        analysis.test_file = test_file;
+                        let ctx = Context {
+                            settings,
+                            file_contents: &content,
+                            file: path,
+                            ignore_mods: RefCell::new(HashSet::new()),
+                            test_mods: RefCell::new(HashSet::new()),
+                            inline_mods: RefCell::new(vec![]),
+                        };
+
+                        find_ignorable_lines(&content, &mut analysis);
+                        process_items(&file.items, &ctx, &mut analysis);
+                        let cfgs = settings
+                            .cfgs
+                            .iter()
+                            .map(|c| parse_cfg(c))
+                            .collect::<HashSet<_>>();
+                        let build = BuildCfg {
+                            target: settings.target.as_ref(),
+                            features: settings.features.as_ref().and_then(|f| f.of_file(path)),
+                            cfgs: Some(&cfgs).filter(|c| !c.is_empty()),
+                        };
+                        if build.is_known() {
+                            let non_target = find_non_target_code(&file, path, build);
+                            let lines = non_target.lines.into_iter().collect::<Vec<_>>();
+                            analysis.add_to_ignore(&lines);
+                            analysis.non_target_lines =
+                                lines.len() + count_module_lines(&non_target.modules);
+                            ctx.ignore_mods.borrow_mut().extend(non_target.modules);
+                        }
+                        analysis.branch_lines = find_branch_points(&file);
+                        analysis.nested_lines = find_nested_lines(&file);
+                        analysis.public_fns = find_public_functions(&file).into_iter().collect();
+                        analysis.scaffolding = find_scaffolding(&file, &settings.scaffolding);
+                        // Check there's no conflict!
+                        result.insert(path.to_path_buf(), analysis);
+
+                        test_mods.extend(ctx.test_mods.into_inner());
+                        let mut ignored_files = ctx.ignore_mods.into_inner();
+                        for f in ignored_files.drain() {
+                            if f.is_file() {
+                                filtered_files.insert(f);
+                            } else {
+                                let walker = WalkDir::new(f).into_iter();
+                                for e in walker.filter_map(|e| e.ok()).filter(|e| is_source_file(e))
+                                {
+                                    filtered_files.insert(e.path().to_path_buf());
+                                }
                             }
                         }
+                        // This could probably be done with the DWARF if I could find a discriminating factor
+                        // to why lib.rs:1 shows up as a real line!
+                        if path.ends_with("src/lib.rs") {
+                            analyse_lib_rs(path, result);
+                        }
                     }
-                    // This could probably be done with the DWARF if I could find a discriminating factor
-                    // to why lib.rs:1 shows up as a real line!
-                    if path.ends_with("src/lib.rs") {
-                        analyse_lib_rs(path, result);
+                    Err(e) => {
+                        let start = e.span().start();
+                        let relative = path.strip_prefix(root).unwrap_or(path);
+                        let message = format!(
+                            "Failed to parse {}:{}:{} ({}), falling back to textual analysis",
+                            relative.display(),
+                            start.line,
+                            start.column + 1,
+                            e
+                        );
+                        let suppressed = matches_any(&settings.suppress_parse_fallback, relative);
+                        if suppressed {
+                            debug!("{}", message);
+                        } else {
+                            warn!("{}", message);
+                            fallback = true;
+                        }
+                        let mut analysis = LineAnalysis::new();
+                        analysis.unparsed = !suppressed;
+                        analysis.test_file = test_file;
+                        textual_analysis(&content, &mut analysis);
+                        result.insert(path.to_path_buf(), analysis);
                     }
                 }
             }
         }
     }
+    fallback
+}
+
+/// Fallback analysis for files which can't be parsed, likely due to syntax
+/// newer than the version of syn tarpaulin uses. Blank lines, comments and
+/// lines with only an attribute aren't coverable and everything else is.
+fn textual_analysis(content: &str, analysis: &mut LineAnalysis) {
+    find_ignorable_lines(content, analysis);
+    let mut is_comment = false;
+    for (i, line) in content.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.trim();
+        let ignore = if is_comment || line.starts_with("/*") {
+            is_comment = !line.contains("*/");
+            true
+        } else {
+            let is_attr =
+                (line.starts_with("#[") || line.starts_with("#![")) && line.ends_with(']');
+            line.is_empty() || line.starts_with("//") || is_attr
+        };
+        if ignore {
+            analysis.add_to_ignore(&[line_number]);
+        } else if !analysis.should_ignore(line_number) {
+            analysis.cover.insert(line_number);
+        }
+    }
 }
 
 /// Finds lines from the raw string which are ignorable.
@@ -398,7 +999,7 @@ fn find_ignorable_lines(content: &str, analysis: &mut LineAnalysis) {
     analysis.add_to_ignore(&lines);
 }
 
-pub fn visit_generics(generics: &Generics, analysis: &mut LineAnalysis) {
+pub(crate) fn visit_generics(generics: &Generics, analysis: &mut LineAnalysis) {
     if let Some(ref wh) = generics.where_clause {
         analysis.ignore_tokens(wh);
     }
@@ -0,0 +1,59 @@
+use crate::source_analysis::attributes::test_reason;
+use crate::source_analysis::macros::get_line_range;
+use crate::source_analysis::TestReason;
+use quote::ToTokens;
+use regex::Regex;
+use std::ops::Range;
+use syn::visit::{self, Visit};
+use syn::*;
+
+/// Finds the lines of the functions and methods whose names match one of the
+/// patterns, such as `setup` helpers shared by tests. Functions with a test
+/// attribute are tests themselves so aren't included. Whether the functions
+/// are test code is left to the caller.
+pub(crate) fn find_scaffolding(file: &File, patterns: &[String]) -> Vec<Range<usize>> {
+    if patterns.is_empty() {
+        return vec![];
+    }
+    let patterns = patterns
+        .iter()
+        .filter_map(|p| {
+            let re = format!("^{}$", regex::escape(p).replace(r"\*", ".*"));
+            Regex::new(&re).ok()
+        })
+        .collect::<Vec<_>>();
+    let mut scaffolding = Scaffolding {
+        patterns,
+        functions: vec![],
+    };
+    scaffolding.visit_file(file);
+    scaffolding.functions
+}
+
+struct Scaffolding {
+    patterns: Vec<Regex>,
+    functions: Vec<Range<usize>>,
+}
+
+impl Scaffolding {
+    fn add<T: ToTokens>(&mut self, item: &T, attrs: &[Attribute], sig: &Signature) {
+        let name = sig.ident.to_string();
+        if test_reason(attrs, true) != Some(TestReason::TestAttribute)
+            && self.patterns.iter().any(|p| p.is_match(&name))
+        {
+            self.functions.push(get_line_range(item));
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for Scaffolding {
+    fn visit_item_fn(&mut self, i: &'ast ItemFn) {
+        self.add(i, &i.attrs, &i.sig);
+        visit::visit_item_fn(self, i);
+    }
+
+    fn visit_impl_item_method(&mut self, i: &'ast ImplItemMethod) {
+        self.add(i, &i.attrs, &i.sig);
+        visit::visit_impl_item_method(self, i);
+    }
+}
@@ -0,0 +1,389 @@
+use crate::source_analysis::prelude::*;
+use cargo_metadata::MetadataCommand;
+use std::env;
+use std::fs;
+use std::process::Command;
+use syn::visit::{self, Visit};
+use walkdir::WalkDir;
+
+/// The `cfg` values of the target being built, used to exclude code such as
+/// `#[cfg(windows)]` functions which can't run on it. Only the target options
+/// are known (`unix`, `windows` and the `target_*` keys), features are
+/// evaluated with `PackageFeatures` and predicates using anything else like
+/// `test` are never excluded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TargetCfg {
+    cfgs: HashSet<(String, Option<String>)>,
+}
+
+impl TargetCfg {
+    /// Gets the cfg values for the target triple or the host if `None`, from
+    /// `rustc --print cfg`. Returns `None` if rustc couldn't be run.
+    pub fn query(target: Option<&str>) -> Option<Self> {
+        let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+        let mut cmd = Command::new(rustc);
+        cmd.args(&["--print", "cfg"]);
+        if let Some(target) = target {
+            cmd.args(&["--target", target]);
+        }
+        match cmd.output() {
+            Ok(out) if out.status.success() => {
+                Some(Self::parse(&String::from_utf8_lossy(&out.stdout)))
+            }
+            Ok(out) => {
+                warn!(
+                    "Couldn't get the cfg values of the target, counting code for all targets: {}",
+                    String::from_utf8_lossy(&out.stderr).trim()
+                );
+                None
+            }
+            Err(e) => {
+                warn!(
+                    "Couldn't run rustc to get the cfg values of the target, counting code for all targets: {}",
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Parses the output of `rustc --print cfg`, one `name` or `name="value"`
+    /// per line
+    pub fn parse(cfgs: &str) -> Self {
+        let cfgs = cfgs
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(parse_cfg)
+            .collect();
+        Self { cfgs }
+    }
+}
+
+/// Parses a cfg option, `name` or `name="value"`
+pub(crate) fn parse_cfg(cfg: &str) -> (String, Option<String>) {
+    match cfg.find('=') {
+        Some(i) => (
+            cfg[..i].to_string(),
+            Some(cfg[(i + 1)..].trim_matches('"').to_string()),
+        ),
+        None => (cfg.to_string(), None),
+    }
+}
+
+/// The cfg options set with `--cfg` in rustc flags
+pub(crate) fn rustflags_cfgs(flags: &str) -> Vec<String> {
+    let mut cfgs = vec![];
+    let mut args = flags.split_whitespace();
+    while let Some(arg) = args.next() {
+        if arg == "--cfg" {
+            cfgs.extend(args.next().map(ToString::to_string));
+        } else if arg.starts_with("--cfg=") {
+            cfgs.push(arg["--cfg=".len()..].to_string());
+        }
+    }
+    cfgs
+}
+
+/// The features enabled for each workspace member in the build, used to
+/// exclude code under a `#[cfg(feature = "..")]` which isn't built. Features
+/// are resolved by cargo so ones enabled by default or by other features are
+/// included.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PackageFeatures {
+    /// Manifest directory of each package and its enabled features
+    packages: Vec<(PathBuf, HashSet<String>)>,
+}
+
+impl PackageFeatures {
+    /// Resolves the features of the workspace members with `cargo metadata`
+    /// given the same feature options as the build. Returns `None` if cargo
+    /// couldn't resolve them.
+    pub fn query(config: &Config) -> Option<Self> {
+        let mut cmd = MetadataCommand::new();
+        cmd.manifest_path(&config.manifest);
+        // `MetadataCommand::features` keeps only the last option so pass the
+        // flags the same way the build command gets them
+        let mut options = vec![];
+        if !config.features.is_empty() {
+            options.push("--features".to_string());
+            options.push(config.features.join(" "));
+        }
+        if config.all_features {
+            options.push("--all-features".to_string());
+        }
+        if config.no_default_features {
+            options.push("--no-default-features".to_string());
+        }
+        cmd.other_options(options);
+        let metadata = match cmd.exec() {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                warn!(
+                    "Couldn't resolve the enabled features, counting code for all features: {}",
+                    e
+                );
+                return None;
+            }
+        };
+        let nodes = metadata.resolve.as_ref()?.nodes.as_slice();
+        let packages = metadata
+            .packages
+            .iter()
+            .filter(|p| metadata.workspace_members.contains(&p.id))
+            .filter_map(|p| {
+                let dir = p.manifest_path.parent()?.to_path_buf();
+                let node = nodes.iter().find(|n| n.id == p.id)?;
+                Some((dir, node.features.iter().cloned().collect()))
+            });
+        Some(Self::new(packages))
+    }
+
+    /// Features of packages given by their manifest directory
+    pub fn new(packages: impl IntoIterator<Item = (PathBuf, HashSet<String>)>) -> Self {
+        Self {
+            packages: packages.into_iter().collect(),
+        }
+    }
+
+    /// Features enabled for the package the file is in, the one with the
+    /// deepest directory containing it. `None` if it's in none of them.
+    pub fn of_file(&self, path: &Path) -> Option<&HashSet<String>> {
+        self.packages
+            .iter()
+            .filter(|(dir, _)| path.starts_with(dir))
+            .max_by_key(|(dir, _)| dir.components().count())
+            .map(|(_, features)| features)
+    }
+}
+
+/// What's known of how a file is built, either part may be unknown
+#[derive(Clone, Copy, Default)]
+pub(crate) struct BuildCfg<'a> {
+    /// The cfg values of the target
+    pub(crate) target: Option<&'a TargetCfg>,
+    /// Features enabled for the file's package
+    pub(crate) features: Option<&'a HashSet<String>>,
+    /// Other cfg options known to be set
+    pub(crate) cfgs: Option<&'a HashSet<(String, Option<String>)>>,
+}
+
+impl<'a> From<&'a TargetCfg> for BuildCfg<'a> {
+    fn from(target: &'a TargetCfg) -> Self {
+        Self {
+            target: Some(target),
+            ..Default::default()
+        }
+    }
+}
+
+impl<'a> BuildCfg<'a> {
+    fn is_target_key(name: &str) -> bool {
+        name == "unix" || name == "windows" || name.starts_with("target_")
+    }
+
+    fn is_set(&self, name: &str, value: Option<String>) -> bool {
+        self.cfgs
+            .map_or(false, |c| c.contains(&(name.to_string(), value)))
+    }
+
+    /// Whether anything is known so code could be excluded
+    pub(crate) fn is_known(&self) -> bool {
+        self.target.is_some() || self.features.is_some() || self.cfgs.is_some()
+    }
+
+    /// Evaluates a cfg predicate, `None` if it depends on something that
+    /// isn't known
+    pub(crate) fn evaluate(&self, predicate: &Meta) -> Option<bool> {
+        match predicate {
+            Meta::Path(ref p) => {
+                let name = p.get_ident()?.to_string();
+                if self.is_set(&name, None) {
+                    Some(true)
+                } else if Self::is_target_key(&name) {
+                    Some(self.target?.cfgs.contains(&(name, None)))
+                } else {
+                    None
+                }
+            }
+            Meta::NameValue(ref nv) => {
+                let name = nv.path.get_ident()?.to_string();
+                match nv.lit {
+                    Lit::Str(ref s) if self.is_set(&name, Some(s.value())) => Some(true),
+                    Lit::Str(ref s) if Self::is_target_key(&name) => {
+                        Some(self.target?.cfgs.contains(&(name, Some(s.value()))))
+                    }
+                    Lit::Str(ref s) if name == "feature" => {
+                        Some(self.features?.contains(&s.value()))
+                    }
+                    _ => None,
+                }
+            }
+            Meta::List(ref ml) => {
+                let mut values = ml.nested.iter().map(|n| match n {
+                    NestedMeta::Meta(ref m) => self.evaluate(m),
+                    NestedMeta::Lit(_) => None,
+                });
+                if ml.path.is_ident("not") && ml.nested.len() == 1 {
+                    values.next()?.map(|x| !x)
+                } else if ml.path.is_ident("all") {
+                    let values = values.collect::<Vec<_>>();
+                    if values.contains(&Some(false)) {
+                        Some(false)
+                    } else if values.iter().all(Option::is_some) {
+                        Some(true)
+                    } else {
+                        None
+                    }
+                } else if ml.path.is_ident("any") {
+                    let values = values.collect::<Vec<_>>();
+                    if values.contains(&Some(true)) {
+                        Some(true)
+                    } else if values.iter().all(|x| *x == Some(false)) {
+                        Some(false)
+                    } else {
+                        None
+                    }
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Whether the attributes include a `#[cfg(..)]` which is false for the
+    /// build
+    pub(crate) fn excludes(&self, attrs: &[Attribute]) -> bool {
+        attrs
+            .iter()
+            .filter(|a| a.path.is_ident("cfg"))
+            .filter_map(|a| match a.parse_meta() {
+                Ok(Meta::List(ml)) if ml.nested.len() == 1 => match ml.nested.first() {
+                    Some(NestedMeta::Meta(m)) => Some(self.evaluate(m)),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .any(|x| x == Some(false))
+    }
+}
+
+/// Code excluded because it isn't built for the target or features
+#[derive(Default)]
+pub(crate) struct NonTargetCode {
+    /// Lines of the excluded items in the file
+    pub(crate) lines: HashSet<usize>,
+    /// Files of excluded modules declared with `mod name;`
+    pub(crate) modules: Vec<PathBuf>,
+}
+
+struct NonTargetVisitor<'a> {
+    build: BuildCfg<'a>,
+    file: &'a Path,
+    /// Inline modules enclosing the node being visited, innermost last
+    inline_mods: Vec<String>,
+    result: NonTargetCode,
+}
+
+impl<'a> NonTargetVisitor<'a> {
+    /// Records the lines of the node if its attributes exclude it, returning
+    /// true if it was excluded
+    fn exclude<T: ToTokens>(&mut self, attrs: &[Attribute], node: &T) -> bool {
+        let excluded = self.build.excludes(attrs);
+        if excluded {
+            self.result.lines.extend(get_line_range(node));
+        }
+        excluded
+    }
+}
+
+impl<'a, 'ast> Visit<'ast> for NonTargetVisitor<'a> {
+    fn visit_item_fn(&mut self, i: &'ast ItemFn) {
+        if !self.exclude(&i.attrs, i) {
+            visit::visit_item_fn(self, i);
+        }
+    }
+
+    fn visit_item_impl(&mut self, i: &'ast ItemImpl) {
+        if !self.exclude(&i.attrs, i) {
+            visit::visit_item_impl(self, i);
+        }
+    }
+
+    fn visit_item_trait(&mut self, i: &'ast ItemTrait) {
+        if !self.exclude(&i.attrs, i) {
+            visit::visit_item_trait(self, i);
+        }
+    }
+
+    fn visit_item_mod(&mut self, i: &'ast ItemMod) {
+        if self.exclude(&i.attrs, i) {
+            if i.content.is_none() {
+                let path = module_path(self.file, &self.inline_mods, i);
+                self.result.modules.push(path);
+            }
+        } else {
+            self.inline_mods.push(i.ident.to_string());
+            visit::visit_item_mod(self, i);
+            self.inline_mods.pop();
+        }
+    }
+
+    fn visit_impl_item_method(&mut self, i: &'ast ImplItemMethod) {
+        if !self.exclude(&i.attrs, i) {
+            visit::visit_impl_item_method(self, i);
+        }
+    }
+
+    fn visit_trait_item_method(&mut self, i: &'ast TraitItemMethod) {
+        if !self.exclude(&i.attrs, i) {
+            visit::visit_trait_item_method(self, i);
+        }
+    }
+
+    fn visit_local(&mut self, i: &'ast Local) {
+        if !self.exclude(&i.attrs, i) {
+            visit::visit_local(self, i);
+        }
+    }
+
+    fn visit_arm(&mut self, i: &'ast Arm) {
+        if !self.exclude(&i.attrs, i) {
+            visit::visit_arm(self, i);
+        }
+    }
+
+    fn visit_expr_block(&mut self, i: &'ast ExprBlock) {
+        if !self.exclude(&i.attrs, i) {
+            visit::visit_expr_block(self, i);
+        }
+    }
+}
+
+/// Finds the code in the file which isn't built for the target or features
+pub(crate) fn find_non_target_code(
+    file: &syn::File,
+    path: &Path,
+    build: BuildCfg,
+) -> NonTargetCode {
+    let mut visitor = NonTargetVisitor {
+        build,
+        file: path,
+        inline_mods: vec![],
+        result: NonTargetCode::default(),
+    };
+    visitor.visit_file(file);
+    visitor.result
+}
+
+/// Number of lines in the files of excluded modules, for a directory module
+/// this is every source file in the directory
+pub(crate) fn count_module_lines(modules: &[PathBuf]) -> usize {
+    modules
+        .iter()
+        .flat_map(|m| WalkDir::new(m).into_iter().filter_map(|e| e.ok()))
+        .filter(|e| is_source_file(e))
+        .filter_map(|e| fs::read_to_string(e.path()).ok())
+        .map(|s| s.lines().count())
+        .sum()
+}
@@ -3,10 +3,10 @@ use syn::parse_file;
 
 #[test]
 fn logical_lines_let_bindings() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "fn foo() {
             let x 
                   =
@@ -14,6 +14,8 @@ fn logical_lines_let_bindings() {
         }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -21,7 +23,7 @@ fn logical_lines_let_bindings() {
     assert_eq!(lines.logical_lines.get(&4).copied(), Some(2));
 
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "fn foo() {
         let x = (0..15).iter()
             .filter(|x| {
@@ -36,6 +38,8 @@ fn logical_lines_let_bindings() {
         }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
 
     let mut lines = LineAnalysis::new();
@@ -68,9 +72,9 @@ fn line_analysis_works() {
 #[test]
 fn filter_str_literals() {
     let mut lines = LineAnalysis::new();
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "fn test() {
             writeln!(#\"test
                      \ttest
@@ -78,6 +82,8 @@ fn filter_str_literals() {
         }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -86,7 +92,7 @@ fn filter_str_literals() {
     assert!(lines.ignore.contains(&Lines::Line(4)));
 
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "fn test() {
             write(\"test
                   test
@@ -95,6 +101,8 @@ fn filter_str_literals() {
         fn write(s:&str){}",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     let mut lines = LineAnalysis::new();
@@ -105,7 +113,7 @@ fn filter_str_literals() {
 
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "
 
             fn test() {
@@ -116,6 +124,8 @@ fn filter_str_literals() {
         ",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -124,13 +134,15 @@ fn filter_str_literals() {
 
 #[test]
 fn filter_struct_members() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "#[derive(Debug)]\npub struct Struct {\npub i: i32,\nj:String,\n}",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -141,10 +153,12 @@ fn filter_struct_members() {
     assert!(lines.ignore.contains(&Lines::Line(4)));
 
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "#[derive(Debug)]\npub struct Struct (\n i32\n);",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -153,15 +167,66 @@ fn filter_struct_members() {
     assert!(lines.ignore.contains(&Lines::Line(3)));
 }
 
+#[test]
+fn type_definitions_found() {
+    let settings = AnalysisSettings::default();
+    let mut lines = LineAnalysis::new();
+    let ctx = Context {
+        settings: &settings,
+        file_contents: "#[derive(Clone, Debug)]
+pub struct Struct {
+    i: i32,
+}
+
+fn foo() -> i32 {
+    4
+}
+
+#[derive(PartialEq)]
+enum Enum {
+    A,
+}",
+        file: Path::new(""),
+        ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
+    };
+    let parser = parse_file(ctx.file_contents).unwrap();
+    process_items(&parser.items, &ctx, &mut lines);
+
+    for l in (1..=4).chain(10..=13) {
+        assert!(lines.in_type_definition(l));
+    }
+    for l in 5..=9 {
+        assert!(!lines.in_type_definition(l));
+    }
+    assert!(lines.should_ignore(1));
+
+    let settings = AnalysisSettings {
+        include_derives: true,
+        ..Default::default()
+    };
+    let mut lines = LineAnalysis::new();
+    let ctx = Context {
+        settings: &settings,
+        ..ctx
+    };
+    process_items(&parser.items, &ctx, &mut lines);
+    assert!(lines.in_type_definition(1));
+    assert!(!lines.should_ignore(1));
+}
+
 #[test]
 fn filter_enum_members() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "#[derive(Debug)]\npub enum E {\nI1,\nI2(u32),\nI3{\nx:u32,\n},\n}",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -176,10 +241,10 @@ fn filter_enum_members() {
 
 #[test]
 fn filter_struct_consts() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "struct T{x:String, y:i32}
             fn test()-> T {
                 T{
@@ -189,6 +254,8 @@ fn filter_struct_consts() {
             }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -198,15 +265,17 @@ fn filter_struct_consts() {
 
 #[test]
 fn filter_unreachable_unchecked() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "fn test() {
                 core::hint::unreachable_unchecked();
             }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -215,10 +284,10 @@ fn filter_unreachable_unchecked() {
 
 #[test]
 fn filter_loop_attr() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "fn test() {
                 #[allow(clippy::option_unwrap_used)]
                 loop {
@@ -232,6 +301,8 @@ fn filter_loop_attr() {
             }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -242,12 +313,14 @@ fn filter_loop_attr() {
 
 #[test]
 fn filter_mods() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "mod foo {\nfn double(x:i32)->i32 {\n x*2\n}\n}",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     let mut lines = LineAnalysis::new();
@@ -256,10 +329,12 @@ fn filter_mods() {
 
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "mod foo;",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -267,10 +342,12 @@ fn filter_mods() {
 
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "mod foo{}",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -279,13 +356,15 @@ fn filter_mods() {
 
 #[test]
 fn filter_macros() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "\n\nfn unused() {\nunimplemented!();\n}",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -295,10 +374,12 @@ fn filter_macros() {
     assert!(lines.ignore.contains(&Lines::Line(4)));
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "\n\nfn unused() {\nunreachable!();\n}",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -307,7 +388,7 @@ fn filter_macros() {
 
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "fn unreachable_match(x: u32) -> u32 {
             match x {
                 1 => 5,
@@ -317,6 +398,8 @@ fn filter_macros() {
         }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -324,10 +407,12 @@ fn filter_macros() {
 
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "fn unused() {\nprintln!(\"text\");\n}",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -336,13 +421,13 @@ fn filter_macros() {
 
 #[test]
 fn filter_tests() {
-    let config = Config::default();
-    let mut igconfig = Config::default();
-    igconfig.ignore_tests = true;
+    let settings = AnalysisSettings::default();
+    let mut ig_settings = AnalysisSettings::default();
+    ig_settings.ignore_tests = true;
 
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "#[cfg(test)]
             mod tests {
                 fn boo(){
@@ -350,13 +435,15 @@ fn filter_tests() {
                 }\n}",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
     assert!(!lines.ignore.contains(&Lines::Line(4)));
 
     let ctx = Context {
-        config: &igconfig,
+        settings: &ig_settings,
         file_contents: "#[cfg(test)]
             mod tests {
                 fn boo(){
@@ -365,6 +452,8 @@ fn filter_tests() {
             }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
 
     let mut lines = LineAnalysis::new();
@@ -372,10 +461,12 @@ fn filter_tests() {
     assert!(lines.ignore.contains(&Lines::Line(4)));
 
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "#[test]\nfn mytest() { \n assert!(true);\n}",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     let mut lines = LineAnalysis::new();
@@ -384,10 +475,12 @@ fn filter_tests() {
     assert!(!lines.ignore.contains(&Lines::Line(3)));
 
     let ctx = Context {
-        config: &igconfig,
+        settings: &ig_settings,
         file_contents: "#[test]\nfn mytest() { \n assert!(true);\n}",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let mut lines = LineAnalysis::new();
     process_items(&parser.items, &ctx, &mut lines);
@@ -397,12 +490,12 @@ fn filter_tests() {
 
 #[test]
 fn filter_test_utilities() {
-    let mut config = Config::default();
-    config.ignore_tests = true;
+    let mut settings = AnalysisSettings::default();
+    settings.ignore_tests = true;
 
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "trait Thing {
             #[cfg(test)]
             fn boo(){
@@ -411,6 +504,8 @@ fn filter_test_utilities() {
         }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -418,11 +513,11 @@ fn filter_test_utilities() {
     assert!(lines.ignore.contains(&Lines::Line(3)));
     assert!(lines.ignore.contains(&Lines::Line(4)));
 
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
 
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "trait Thing {
             #[cfg(test)]
             fn boo(){
@@ -431,6 +526,8 @@ fn filter_test_utilities() {
         }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -440,15 +537,17 @@ fn filter_test_utilities() {
 
 #[test]
 fn filter_where() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "fn boop<T>() -> T  where T:Default {
             T::default()
         }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -456,13 +555,15 @@ fn filter_where() {
 
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "fn boop<T>() -> T
             where T:Default {
                 T::default()
             }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -470,7 +571,7 @@ fn filter_where() {
 
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "trait foof {
             fn boop<T>() -> T
             where T:Default {
@@ -479,6 +580,8 @@ fn filter_where() {
         }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -487,13 +590,15 @@ fn filter_where() {
 
 #[test]
 fn filter_derives() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "#[derive(Debug)]\nstruct T;",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -501,10 +606,12 @@ fn filter_derives() {
 
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "\n#[derive(Copy, Eq)]\nunion x { x:i32, y:f32}",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -513,13 +620,15 @@ fn filter_derives() {
 
 #[test]
 fn filter_unsafe() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "fn unsafe_fn() {\n let x=1;\nunsafe {\nprintln!(\"{}\", x);\n}\n}",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -528,10 +637,12 @@ fn filter_unsafe() {
 
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "fn unsafe_fn() {\n let x=1;\nunsafe {println!(\"{}\", x);}\n}",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -540,10 +651,10 @@ fn filter_unsafe() {
 
 #[test]
 fn cover_generic_impl_methods() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "struct GenericStruct<T>(T);
         impl<T> GenericStruct<T> {
             fn hw(&self) {
@@ -552,6 +663,8 @@ fn cover_generic_impl_methods() {
         }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -560,7 +673,7 @@ fn cover_generic_impl_methods() {
 
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "struct GenericStruct<T>{v:Vec<T>}
         impl<T> Default for GenericStruct<T> {
             fn default() -> Self {
@@ -571,6 +684,8 @@ fn cover_generic_impl_methods() {
         }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -579,10 +694,10 @@ fn cover_generic_impl_methods() {
 
 #[test]
 fn cover_default_trait_methods() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "trait Thing {
             fn hw(&self) {
                 println!(\"hello world\");
@@ -590,6 +705,8 @@ fn cover_default_trait_methods() {
             }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -599,10 +716,10 @@ fn cover_default_trait_methods() {
 
 #[test]
 fn filter_method_args() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "struct Thing;
         impl Thing{
             fn hw(&self, name: &str) {
@@ -626,6 +743,8 @@ fn filter_method_args() {
         }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -635,14 +754,16 @@ fn filter_method_args() {
 
 #[test]
 fn filter_use_statements() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "use std::collections::HashMap;
         use std::{ffi::CString, os::raw::c_char};",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -652,10 +773,10 @@ fn filter_use_statements() {
 
 #[test]
 fn include_inline_fns() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "#[inline]
             fn inline_func() {
                 // I shouldn't be covered
@@ -667,6 +788,8 @@ fn include_inline_fns() {
             }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -680,15 +803,17 @@ fn include_inline_fns() {
 
 #[test]
 fn cover_callable_noargs() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "fn foo() {
                 std::ptr::null::<i32>();
             }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -697,10 +822,10 @@ fn cover_callable_noargs() {
 
 #[test]
 fn filter_closure_contents() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "fn inline_func() {
                 (0..0).iter().foreach(|x| {
                     unreachable!();
@@ -708,6 +833,8 @@ fn filter_closure_contents() {
             }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -716,10 +843,10 @@ fn filter_closure_contents() {
 
 #[test]
 fn tarpaulin_skip_attr() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "#[cfg_attr(tarpaulin, skip)]
             fn skipped() {
                 println!(\"Hello world\");
@@ -732,6 +859,8 @@ fn tarpaulin_skip_attr() {
         ",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -742,7 +871,7 @@ fn tarpaulin_skip_attr() {
 
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "#[cfg_attr(tarpaulin, skip)]
         mod ignore_all {
             fn skipped() {
@@ -757,6 +886,8 @@ fn tarpaulin_skip_attr() {
         ",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -766,12 +897,112 @@ fn tarpaulin_skip_attr() {
     assert!(lines.ignore.contains(&Lines::Line(9)));
 }
 
+#[test]
+fn coverage_off_attrs() {
+    let settings = AnalysisSettings::default();
+    let mut lines = LineAnalysis::new();
+    let ctx = Context {
+        settings: &settings,
+        file_contents: "#[coverage(off)]
+        fn skipped() {
+            println!(\"Hello world\");
+        }
+
+        #[no_coverage]
+        fn also_skipped() {
+            println!(\"Hello world\");
+        }
+
+        #[cfg_attr(coverage_nightly, coverage(off))]
+        fn nightly_skipped() {
+            println!(\"Hello world\");
+        }
+
+        #[coverage(on)]
+        fn covered() {
+            println!(\"hell world\");
+        }
+        ",
+        file: Path::new(""),
+        ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
+    };
+    let parser = parse_file(ctx.file_contents).unwrap();
+    process_items(&parser.items, &ctx, &mut lines);
+    for l in &[1, 2, 3, 4, 6, 7, 8, 9, 11, 12, 13, 14] {
+        assert!(lines.ignore.contains(&Lines::Line(*l)), "line {}", l);
+    }
+    assert!(!lines.ignore.contains(&Lines::Line(17)));
+    assert!(!lines.ignore.contains(&Lines::Line(18)));
+}
+
+#[test]
+fn coverage_on_inside_off() {
+    let settings = AnalysisSettings::default();
+    let mut lines = LineAnalysis::new();
+    let ctx = Context {
+        settings: &settings,
+        file_contents: "#[cfg_attr(coverage_nightly, coverage(off))]
+        mod outer {
+            fn skipped() {
+                println!(\"Hello world\");
+            }
+
+            #[coverage(on)]
+            fn covered() {
+                println!(\"hell world\");
+            }
+
+            struct Foo;
+
+            impl Foo {
+                fn skipped_method(&self) {
+                    println!(\"Hello world\");
+                }
+
+                #[cfg_attr(coverage_nightly, coverage(on))]
+                fn covered_method(&self) {
+                    println!(\"hell world\");
+                }
+            }
+
+            #[coverage(on)]
+            mod inner {
+                fn covered() {
+                    println!(\"hell world\");
+                }
+
+                #[coverage(off)]
+                fn skipped() {
+                    println!(\"Hello world\");
+                }
+            }
+        }
+        ",
+        file: Path::new(""),
+        ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
+    };
+    let parser = parse_file(ctx.file_contents).unwrap();
+    process_items(&parser.items, &ctx, &mut lines);
+    let ignored = [1, 2, 3, 4, 11, 14, 15, 16, 19, 31, 32, 33];
+    let covered = [8, 9, 20, 21, 27];
+    for l in &ignored {
+        assert!(lines.ignore.contains(&Lines::Line(*l)), "line {}", l);
+    }
+    for l in &covered {
+        assert!(!lines.ignore.contains(&Lines::Line(*l)), "line {}", l);
+    }
+}
+
 #[test]
 fn tarpaulin_skip_trait_attrs() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "#[cfg_attr(tarpaulin, skip)]
             trait Foo {
                 fn bar() {
@@ -786,6 +1017,8 @@ fn tarpaulin_skip_trait_attrs() {
         ",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -796,7 +1029,7 @@ fn tarpaulin_skip_trait_attrs() {
 
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "trait Foo {
                 fn bar() {
                     println!(\"Hello world\");
@@ -810,6 +1043,8 @@ fn tarpaulin_skip_trait_attrs() {
         ",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -821,10 +1056,10 @@ fn tarpaulin_skip_trait_attrs() {
 
 #[test]
 fn tarpaulin_skip_impl_attrs() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "struct Foo;
             #[cfg_attr(tarpaulin, skip)]
             impl Foo {
@@ -840,6 +1075,8 @@ fn tarpaulin_skip_impl_attrs() {
         ",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -850,7 +1087,7 @@ fn tarpaulin_skip_impl_attrs() {
 
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "struct Foo;
             impl Foo {
                 fn bar() {
@@ -866,6 +1103,8 @@ fn tarpaulin_skip_impl_attrs() {
         ",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -877,10 +1116,10 @@ fn tarpaulin_skip_impl_attrs() {
 
 #[test]
 fn filter_block_contents() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "fn unreachable_match(x: u32) -> u32 {
             match x {
                 1 => 5,
@@ -893,6 +1132,8 @@ fn filter_block_contents() {
         }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -902,15 +1143,17 @@ fn filter_block_contents() {
 
 #[test]
 fn filter_consts() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "fn boo() {
         const x: u32 = 3;
         }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -919,10 +1162,10 @@ fn filter_consts() {
 
 #[test]
 fn optional_panic_ignore() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "fn unreachable_match(x: u32) -> u32 {
             match x {
                 1 => 5,
@@ -932,16 +1175,18 @@ fn optional_panic_ignore() {
         }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
     assert!(!lines.ignore.contains(&Lines::Line(5)));
 
-    let mut config = Config::default();
-    config.ignore_panics = true;
+    let mut settings = AnalysisSettings::default();
+    settings.ignore_panics = true;
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "fn unreachable_match(x: u32) -> u32 {
             match x {
                 1 => 5,
@@ -951,6 +1196,8 @@ fn optional_panic_ignore() {
         }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
 
     let parser = parse_file(ctx.file_contents).unwrap();
@@ -958,12 +1205,170 @@ fn optional_panic_ignore() {
     assert!(lines.ignore.contains(&Lines::Line(5)));
 }
 
+#[test]
+fn todo_ignored() {
+    let settings = AnalysisSettings::default();
+    let mut lines = LineAnalysis::new();
+    let ctx = Context {
+        settings: &settings,
+        file_contents: "fn later(x: u32) -> u32 {
+            if x > 1 {
+                todo!(
+                    \"handle {}\",
+                    x
+                )
+            }
+            x
+        }",
+        file: Path::new(""),
+        ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
+    };
+    let parser = parse_file(ctx.file_contents).unwrap();
+    process_items(&parser.items, &ctx, &mut lines);
+    for l in 3..6 {
+        assert!(lines.ignore.contains(&Lines::Line(l)), "line {}", l);
+    }
+    assert!(!lines.ignore.contains(&Lines::Line(8)));
+}
+
+#[test]
+fn multiline_panic_ignored() {
+    let contents = "fn parse(s: &str) -> u32 {
+            match s.parse() {
+                Ok(x) => x,
+                Err(e) => panic!(
+                    \"{} isn't a number: {}\",
+                    s,
+                    e
+                ),
+            }
+        }";
+    let mut settings = AnalysisSettings::default();
+    settings.ignore_panics = true;
+    let mut lines = LineAnalysis::new();
+    let ctx = Context {
+        settings: &settings,
+        file_contents: contents,
+        file: Path::new(""),
+        ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
+    };
+    let parser = parse_file(ctx.file_contents).unwrap();
+    process_items(&parser.items, &ctx, &mut lines);
+    for l in 4..8 {
+        assert!(lines.ignore.contains(&Lines::Line(l)), "line {}", l);
+    }
+    assert!(!lines.ignore.contains(&Lines::Line(3)));
+}
+
+#[test]
+fn assert_false_arms_ignored() {
+    let contents = "fn checked(x: u32) {
+            let y = match x {
+                0 => 1,
+                _ => {
+                    assert!(false, \"x is {}\", x);
+                }
+            };
+            if y > 0 {
+                println!(\"{}\", y);
+            } else {
+                assert!(false)
+            }
+            assert!(false, \"not an arm\");
+        }";
+    let settings = AnalysisSettings::default();
+    let mut lines = LineAnalysis::new();
+    let ctx = Context {
+        settings: &settings,
+        file_contents: contents,
+        file: Path::new(""),
+        ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
+    };
+    let parser = parse_file(ctx.file_contents).unwrap();
+    process_items(&parser.items, &ctx, &mut lines);
+    assert!(!lines.ignore.contains(&Lines::Line(5)));
+    assert!(!lines.ignore.contains(&Lines::Line(11)));
+
+    let mut settings = AnalysisSettings::default();
+    settings.ignore_panics = true;
+    let mut lines = LineAnalysis::new();
+    let ctx = Context {
+        settings: &settings,
+        file_contents: contents,
+        file: Path::new(""),
+        ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
+    };
+    let parser = parse_file(ctx.file_contents).unwrap();
+    process_items(&parser.items, &ctx, &mut lines);
+    assert!(lines.ignore.contains(&Lines::Line(5)));
+    assert!(lines.ignore.contains(&Lines::Line(11)));
+    assert!(!lines.ignore.contains(&Lines::Line(9)));
+    // Only an arm or else block that does nothing else is known to be unreachable
+    assert!(!lines.ignore.contains(&Lines::Line(13)));
+}
+
+#[test]
+fn count_debug_asserts() {
+    let contents = "fn checks(x: u32) -> u32 {
+            debug_assert!(x > 1);
+            debug_assert_eq!(
+                x,
+                2
+            );
+            std::debug_assert_ne!(x, 3);
+            x + 1
+        }";
+    let settings = AnalysisSettings::default();
+    let mut lines = LineAnalysis::new();
+    let ctx = Context {
+        settings: &settings,
+        file_contents: contents,
+        file: Path::new(""),
+        ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
+    };
+    let parser = parse_file(ctx.file_contents).unwrap();
+    process_items(&parser.items, &ctx, &mut lines);
+    assert!(!lines.ignore.contains(&Lines::Line(2)));
+    assert!(!lines.ignore.contains(&Lines::Line(3)));
+    assert!(!lines.ignore.contains(&Lines::Line(7)));
+    assert_eq!(lines.debug_asserts.len(), 6);
+
+    let mut settings = AnalysisSettings::default();
+    settings.count_debug_asserts = false;
+    let mut lines = LineAnalysis::new();
+    let ctx = Context {
+        settings: &settings,
+        file_contents: contents,
+        file: Path::new(""),
+        ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
+    };
+    let parser = parse_file(ctx.file_contents).unwrap();
+    process_items(&parser.items, &ctx, &mut lines);
+    for l in 2..8 {
+        assert!(lines.ignore.contains(&Lines::Line(l)), "line {}", l);
+    }
+    assert!(!lines.ignore.contains(&Lines::Line(8)));
+    assert_eq!(lines.debug_asserts.len(), 6);
+}
+
 #[test]
 fn filter_nested_blocks() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "fn block() {
             {
                 loop {
@@ -983,6 +1388,8 @@ fn filter_nested_blocks() {
         }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -991,10 +1398,10 @@ fn filter_nested_blocks() {
 
 #[test]
 fn filter_multi_line_decls() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "fn print_it(x:u32,
             y:u32,
             z:u32) {
@@ -1002,6 +1409,8 @@ fn filter_multi_line_decls() {
         }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -1010,7 +1419,7 @@ fn filter_multi_line_decls() {
 
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "struct Boo;
         impl Boo {
             fn print_it(x:u32,
@@ -1021,6 +1430,8 @@ fn filter_multi_line_decls() {
         }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -1029,7 +1440,7 @@ fn filter_multi_line_decls() {
 
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "trait Boo {
             fn print_it(x:u32,
                 y:u32,
@@ -1039,6 +1450,8 @@ fn filter_multi_line_decls() {
         }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -1048,10 +1461,10 @@ fn filter_multi_line_decls() {
 
 #[test]
 fn unreachable_propagate() {
-    let config = Config::default();
+    let settings = AnalysisSettings::default();
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "enum Void {}
         fn empty_match(x: Void) -> u32 {
             match x {
@@ -1059,6 +1472,8 @@ fn unreachable_propagate() {
         }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -1069,7 +1484,7 @@ fn unreachable_propagate() {
 
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "fn foo() {
             if random() {
                 loop {
@@ -1084,6 +1499,8 @@ fn unreachable_propagate() {
         }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -1096,7 +1513,7 @@ fn unreachable_propagate() {
 
     let mut lines = LineAnalysis::new();
     let ctx = Context {
-        config: &config,
+        settings: &settings,
         file_contents: "fn test_unreachable() {
             let x: u32 = foo();
             if x > 5 {
@@ -1106,6 +1523,8 @@ fn unreachable_propagate() {
         }",
         file: Path::new(""),
         ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
     };
     let parser = parse_file(ctx.file_contents).unwrap();
     process_items(&parser.items, &ctx, &mut lines);
@@ -1117,3 +1536,674 @@ fn unreachable_propagate() {
     assert!(lines.ignore.contains(&Lines::Line(6)));
     assert!(lines.ignore.contains(&Lines::Line(7)));
 }
+
+#[test]
+fn textual_fallback() {
+    let contents = "#[inline]
+fn let_else(x: Option<u32>) -> u32 {
+    // Not supported in the syn version used
+    let Some(y) = x else {
+        return 0;
+    };
+
+    /* a
+       comment */
+    y + 1
+}
+";
+    assert!(parse_file(contents).is_err());
+    let mut lines = LineAnalysis::new();
+    textual_analysis(contents, &mut lines);
+    for l in &[1, 3, 6, 7, 8, 9, 11] {
+        assert!(lines.should_ignore(*l), "line {}", l);
+    }
+    for l in &[2, 4, 5, 10] {
+        assert!(!lines.should_ignore(*l), "line {}", l);
+        assert!(lines.cover.contains(l), "line {}", l);
+    }
+}
+
+#[test]
+fn unparsed_files_marked() {
+    let root = std::env::temp_dir().join(format!("tarpaulin-unparsed-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("src")).unwrap();
+    let lib = root.join("src/lib.rs");
+    std::fs::write(
+        &lib,
+        "fn f(x: Option<u32>) {\n    let Some(_) = x else { return };\n}\n",
+    )
+    .unwrap();
+
+    let analysis = analyze_crate(&root, &AnalysisSettings::default());
+    assert!(analysis[&lib].is_unparsed());
+    assert!(analysis[&lib].is_coverable(2));
+
+    // Suppressed parse failures still get the textual analysis
+    let settings = AnalysisSettings {
+        suppress_parse_fallback: vec!["src/*".to_string()],
+        ..Default::default()
+    };
+    let analysis = analyze_crate(&root, &settings);
+    assert!(!analysis[&lib].is_unparsed());
+    assert!(analysis[&lib].is_coverable(2));
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+#[test]
+fn async_runtime_attrs() {
+    let mut settings = AnalysisSettings::default();
+    settings.ignore_tests = true;
+    let mut lines = LineAnalysis::new();
+    let ctx = Context {
+        settings: &settings,
+        file_contents: "#[tokio::main(flavor = \"current_thread\")]
+        async fn main() {
+            println!(\"Hello world\");
+        }
+
+        #[async_std::test]
+        async fn a_test() {
+            assert!(true);
+        }",
+        file: Path::new(""),
+        ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
+    };
+    let parser = parse_file(ctx.file_contents).unwrap();
+    process_items(&parser.items, &ctx, &mut lines);
+    assert_eq!(lines.logical_lines.get(&1).copied(), Some(2));
+    assert!(lines.is_runtime_attr(1));
+    assert!(!lines.is_runtime_attr(2));
+    assert!(!lines.ignore.contains(&Lines::Line(2)));
+    assert!(!lines.ignore.contains(&Lines::Line(3)));
+    assert!(lines.ignore.contains(&Lines::Line(7)));
+    assert!(lines.ignore.contains(&Lines::Line(8)));
+}
+
+#[test]
+fn line_analysis_accessors() {
+    let mut settings = AnalysisSettings::default();
+    settings.count_debug_asserts = false;
+    let mut lines = LineAnalysis::new();
+    let ctx = Context {
+        settings: &settings,
+        file_contents: "fn foo(x: u32) -> u32 {
+            debug_assert!(x > 0);
+            let y =
+                x + 1;
+            y
+        }",
+        file: Path::new(""),
+        ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
+    };
+    find_ignorable_lines(ctx.file_contents, &mut lines);
+    let parser = parse_file(ctx.file_contents).unwrap();
+    process_items(&parser.items, &ctx, &mut lines);
+
+    assert_eq!(lines.ignore_reason(2), Some(IgnoreReason::DebugAssert));
+    assert_eq!(lines.ignore_reason(6), Some(IgnoreReason::Line));
+    assert_eq!(lines.ignore_reason(5), None);
+    assert!(lines.is_coverable(5));
+    assert!(!lines.is_coverable(2));
+    assert_eq!(lines.logical_line(4), 3);
+    assert_eq!(lines.logical_line(5), 5);
+    assert_eq!(lines.debug_assert_lines().collect::<Vec<_>>(), vec![2]);
+    assert!(lines.coverable_lines().all(|l| lines.is_coverable(l)));
+    assert!(lines.ignored_lines().any(|l| l == 2));
+    assert!(!lines.ignores_all());
+
+    lines.ignore_all();
+    assert_eq!(lines.ignore_reason(5), Some(IgnoreReason::File));
+    assert_eq!(lines.coverable_lines().count(), 0);
+}
+
+#[test]
+fn plain_function_body_coverable() {
+    let settings = AnalysisSettings::default();
+    let mut lines = LineAnalysis::new();
+    let ctx = Context {
+        settings: &settings,
+        file_contents: "fn add(x: u32, y: u32) -> u32 {
+            let z = x + y;
+
+            z * 2
+        }",
+        file: Path::new(""),
+        ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
+    };
+    find_ignorable_lines(ctx.file_contents, &mut lines);
+    let parser = parse_file(ctx.file_contents).unwrap();
+    process_items(&parser.items, &ctx, &mut lines);
+
+    assert!(lines.is_coverable(1));
+    assert!(lines.is_coverable(2));
+    assert!(!lines.is_coverable(3));
+    assert!(lines.is_coverable(4));
+    assert!(!lines.is_coverable(5));
+    let mut coverable = lines.coverable_lines().collect::<Vec<_>>();
+    coverable.sort_unstable();
+    assert_eq!(coverable, vec![1, 2, 4]);
+}
+
+#[test]
+fn skip_markers_ignored() {
+    let settings = AnalysisSettings {
+        skip_markers: vec!["mutants::skip".to_string()],
+        ..Default::default()
+    };
+    let mut lines = LineAnalysis::new();
+    let ctx = Context {
+        settings: &settings,
+        file_contents: "#[mutants::skip]
+        fn skipped() {
+            println!(\"skipped\");
+        }
+
+        #[other::skip]
+        fn kept() {
+            println!(\"kept\");
+        }",
+        file: Path::new(""),
+        ignore_mods: RefCell::new(HashSet::new()),
+        test_mods: RefCell::new(HashSet::new()),
+        inline_mods: RefCell::new(vec![]),
+    };
+    let parser = parse_file(ctx.file_contents).unwrap();
+    process_items(&parser.items, &ctx, &mut lines);
+
+    assert!(!lines.is_coverable(3));
+    assert_eq!(lines.ignore_reason(3), Some(IgnoreReason::Line));
+    assert!(lines.is_coverable(8));
+}
+
+#[test]
+fn rustflags_cfgs_parsed() {
+    assert_eq!(
+        rustflags_cfgs("-C opt-level=1 --cfg loom --cfg=tokio_unstable --cfg feature=\"x\""),
+        vec!["loom", "tokio_unstable", "feature=\"x\""]
+    );
+    let cfgs = ["loom", "mode=\"fast\""]
+        .iter()
+        .map(|c| parse_cfg(c))
+        .collect::<HashSet<_>>();
+    let build = BuildCfg {
+        cfgs: Some(&cfgs),
+        ..Default::default()
+    };
+    let excluded = |cfg: &str| {
+        let file = parse_file(&format!("#[cfg({})]\nfn a() {{}}", cfg)).unwrap();
+        match file.items[0] {
+            Item::Fn(ref f) => build.excludes(&f.attrs),
+            _ => unreachable!(),
+        }
+    };
+    assert!(excluded("not(loom)"));
+    assert!(excluded("not(mode = \"fast\")"));
+    assert!(!excluded("loom"));
+    // Other cfgs aren't known to be unset
+    assert!(!excluded("mode = \"slow\""));
+    assert!(!excluded("not(tokio_unstable)"));
+}
+
+#[test]
+fn analyze_crate_entry_point() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/simple_project");
+    let lib = root.join("src/lib.rs");
+
+    let analysis = analyze_crate(&root, &AnalysisSettings::default());
+    assert!(analysis.contains_key(&root.join("src/unused.rs")));
+    assert!(analysis[&lib].is_coverable(5));
+    assert_eq!(analysis[&lib].ignore_reason(22), None);
+
+    let settings = AnalysisSettings {
+        ignore_tests: true,
+        ..Default::default()
+    };
+    let analysis = analyze_crate(&root, &settings);
+    assert!(analysis[&lib].is_coverable(5));
+    assert_eq!(analysis[&lib].ignore_reason(22), Some(IgnoreReason::Line));
+}
+
+#[test]
+fn excluded_files_not_analysed() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/simple_project");
+    let unused = root.join("src/unused.rs");
+    let settings = AnalysisSettings {
+        excluded_files: vec![unused.clone()].into_iter().collect(),
+        ..Default::default()
+    };
+    let analysis = analyze_crate(&root, &settings);
+    assert!(!analysis.contains_key(&unused));
+    assert!(analysis.contains_key(&root.join("src/lib.rs")));
+}
+
+#[test]
+fn count_branch_points() {
+    let contents = "fn foo(x: Option<u32>, y: bool) -> Result<u32, ()> {
+        let z = x.ok_or(())?;
+        if y && z > 2 || z == 0 {
+            return Ok(0);
+        } else if z == 1 {
+            return Ok(1);
+        }
+        for i in 0..z {
+            while i > 2 {}
+        }
+        match x {
+            Some(1) => Ok(1),
+            Some(_) => Ok(2),
+            None => Ok(3),
+        }
+    }";
+    let file = parse_file(contents).unwrap();
+    let mut lines = find_branch_points(&file);
+    lines.sort();
+    assert_eq!(lines, vec![2, 3, 3, 3, 5, 8, 9, 13, 14]);
+}
+
+#[test]
+fn line_weights() {
+    let contents = "fn foo(v: &[u32]) -> u32 {
+        let mut n = 0;
+        for x in v {
+            if *x > 2 && *x < 10 {
+                n += x;
+            }
+        }
+        match n {
+            0 => 1,
+            _ => {
+                n
+            }
+        }
+    }";
+    let file = parse_file(contents).unwrap();
+    let mut analysis = LineAnalysis::new();
+    analysis.branch_lines = find_branch_points(&file);
+    analysis.nested_lines = find_nested_lines(&file);
+    let weights = (1..=14)
+        .map(|l| analysis.line_weight(l))
+        .collect::<Vec<_>>();
+    assert_eq!(weights, vec![1, 1, 2, 4, 3, 3, 2, 1, 1, 2, 2, 2, 1, 1]);
+    // Only the lines heavier than 1 are listed
+    assert_eq!(analysis.line_weights().len(), 8);
+}
+
+#[test]
+fn fixture_line_weights() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/ifelse");
+    let lib = root.join("src/lib.rs");
+    let analysis = analyze_crate(&root, &AnalysisSettings::default());
+    let weight = |line| analysis[&lib].line_weight(line);
+    // A line with two decision points
+    assert_eq!(weight(14), 3);
+    assert_eq!(weight(16), 2);
+    // The `if` and `else` bodies of `is_even`
+    assert_eq!(
+        (weight(20), weight(21), weight(22), weight(24)),
+        (1, 2, 2, 2)
+    );
+    // An `else if` is a decision point nested in the first body
+    assert_eq!(
+        (weight(30), weight(32), weight(33), weight(35)),
+        (2, 3, 2, 2)
+    );
+}
+
+#[test]
+fn fixture_branch_points() {
+    let data = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+    let points = |project: &str, settings: &AnalysisSettings| {
+        let root = data.join(project);
+        analyze_crate(&root, settings)[&root.join("src/lib.rs")].branch_points()
+    };
+    let settings = AnalysisSettings::default();
+    assert_eq!(points("simple_project", &settings), 1);
+    assert_eq!(points("matches", &settings), 8);
+    assert_eq!(points("ifelse", &settings), 7);
+
+    // Branches in ignored tests aren't counted
+    let settings = AnalysisSettings {
+        ignore_tests: true,
+        ..Default::default()
+    };
+    assert_eq!(points("ifelse", &settings), 3);
+}
+
+#[test]
+fn public_function_lines() {
+    let contents = "pub fn a() {}
+    fn b() {}
+    pub(crate) fn c() {}
+    struct D;
+    impl D {
+        pub fn e(&self) {}
+        fn f(&self) {}
+        pub
+        fn g() {}
+    }
+    mod inner {
+        pub fn h() {}
+    }";
+    let file = parse_file(contents).unwrap();
+    let mut lines = find_public_functions(&file);
+    lines.sort();
+    assert_eq!(lines, vec![1, 6, 8, 9, 12]);
+}
+
+fn linux_target() -> TargetCfg {
+    TargetCfg::parse(
+        "debug_assertions
+        target_arch=\"x86_64\"
+        target_endian=\"little\"
+        target_family=\"unix\"
+        target_os=\"linux\"
+        target_pointer_width=\"64\"
+        unix",
+    )
+}
+
+#[test]
+fn evaluate_target_cfgs() {
+    let target = linux_target();
+    let build = BuildCfg::from(&target);
+    let excluded = |cfg: &str| {
+        let file = parse_file(&format!("#[cfg({})]\nfn a() {{}}", cfg)).unwrap();
+        match file.items[0] {
+            Item::Fn(ref f) => build.excludes(&f.attrs),
+            _ => unreachable!(),
+        }
+    };
+    assert!(!excluded("unix"));
+    assert!(!excluded("target_os = \"linux\""));
+    assert!(excluded("windows"));
+    assert!(excluded("target_os = \"windows\""));
+    assert!(excluded("target_arch = \"wasm32\""));
+    assert!(excluded("not(unix)"));
+    assert!(excluded("all(unix, target_pointer_width = \"32\")"));
+    assert!(!excluded("any(windows, unix)"));
+    assert!(excluded("all(windows, feature = \"a\")"));
+
+    // Predicates which don't only depend on the target are never excluded
+    assert!(!excluded("test"));
+    assert!(!excluded("not(debug_assertions)"));
+    assert!(!excluded("feature = \"windows\""));
+    assert!(!excluded("any(windows, feature = \"a\")"));
+}
+
+#[test]
+fn non_target_code() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/target_cfgs");
+    let lib = root.join("src/lib.rs");
+    let win = root.join("src/win.rs");
+
+    let settings = AnalysisSettings {
+        target: Some(linux_target()),
+        ..Default::default()
+    };
+    let analysis = analyze_crate(&root, &settings);
+    assert!(analysis[&lib].is_coverable(6));
+    assert!(!analysis[&lib].is_coverable(11));
+    assert!(!analysis[&lib].is_coverable(16));
+    assert!(!analysis[&lib].is_coverable(21));
+    // Three excluded methods, the module declaration and the module's file
+    assert_eq!(analysis[&lib].non_target_lines(), 17);
+    assert!(analysis[&win].ignores_all());
+
+    // Without a target, as with --count-all-targets, everything is counted
+    let analysis = analyze_crate(&root, &AnalysisSettings::default());
+    assert!(analysis[&lib].is_coverable(11));
+    assert!(analysis[&lib].is_coverable(21));
+    assert_eq!(analysis[&lib].non_target_lines(), 0);
+    assert!(!analysis[&win].ignores_all());
+}
+
+fn features(names: &[&str]) -> HashSet<String> {
+    names.iter().map(ToString::to_string).collect()
+}
+
+#[test]
+fn evaluate_feature_cfgs() {
+    let target = linux_target();
+    let enabled = features(&["std", "extra"]);
+    let build = BuildCfg {
+        target: Some(&target),
+        features: Some(&enabled),
+        ..Default::default()
+    };
+    let excluded = |cfg: &str| {
+        let file = parse_file(&format!("#[cfg({})]\nfn a() {{}}", cfg)).unwrap();
+        match file.items[0] {
+            Item::Fn(ref f) => build.excludes(&f.attrs),
+            _ => unreachable!(),
+        }
+    };
+    assert!(!excluded("feature = \"std\""));
+    assert!(excluded("feature = \"fast\""));
+    assert!(excluded("not(feature = \"extra\")"));
+    assert!(excluded("all(unix, feature = \"fast\")"));
+    assert!(!excluded("any(windows, feature = \"std\")"));
+    assert!(excluded("any(windows, feature = \"fast\")"));
+    assert!(!excluded("all(feature = \"std\", test)"));
+
+    // Features are evaluated without knowing the target
+    let build = BuildCfg {
+        features: Some(&enabled),
+        ..Default::default()
+    };
+    let file = parse_file("#[cfg(all(windows, feature = \"std\"))]\nfn a() {}").unwrap();
+    match file.items[0] {
+        Item::Fn(ref f) => assert!(!build.excludes(&f.attrs)),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn non_feature_code() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/feature_cfgs");
+    let lib = root.join("src/lib.rs");
+    let settings = |enabled: &[&str]| AnalysisSettings {
+        features: Some(PackageFeatures::new(vec![(
+            root.clone(),
+            features(enabled),
+        )])),
+        ..Default::default()
+    };
+
+    let analysis = analyze_crate(&root, &settings(&["std"]));
+    assert!(analysis[&lib].is_coverable(3));
+    assert!(!analysis[&lib].is_coverable(8));
+    assert!(!analysis[&lib].is_coverable(16));
+    assert_eq!(analysis[&lib].non_target_lines(), 12);
+
+    let analysis = analyze_crate(&root, &settings(&["extra", "fast"]));
+    assert!(!analysis[&lib].is_coverable(3));
+    assert!(analysis[&lib].is_coverable(8));
+    assert!(analysis[&lib].is_coverable(16));
+    assert_eq!(analysis[&lib].non_target_lines(), 4);
+
+    // Files outside the packages are counted for every feature
+    let analysis = analyze_crate(
+        &root,
+        &AnalysisSettings {
+            features: Some(PackageFeatures::new(vec![(
+                root.join("other"),
+                HashSet::new(),
+            )])),
+            ..Default::default()
+        },
+    );
+    assert_eq!(analysis[&lib].non_target_lines(), 0);
+}
+
+#[test]
+fn features_resolved_like_cargo() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/feature_cfgs");
+    let lib = root.join("src/lib.rs");
+    let resolve = |options: &dyn Fn(&mut Config)| {
+        let mut config = Config::default();
+        config.manifest = root.join("Cargo.toml");
+        options(&mut config);
+        PackageFeatures::query(&config)
+            .unwrap()
+            .of_file(&lib)
+            .cloned()
+            .unwrap()
+    };
+
+    assert_eq!(resolve(&|_| {}), features(&["default", "std"]));
+    // Features enabled by other features are included
+    assert_eq!(
+        resolve(&|c| c.features = vec!["extra".to_string()]),
+        features(&["default", "std", "extra", "fast"])
+    );
+    // The same lists of features cargo takes
+    assert_eq!(
+        resolve(&|c| {
+            c.features = vec!["extra std".to_string()];
+            c.no_default_features = true;
+        }),
+        features(&["std", "extra", "fast"])
+    );
+    assert_eq!(resolve(&|c| c.no_default_features = true), HashSet::new());
+    assert_eq!(
+        resolve(&|c| c.all_features = true),
+        features(&["default", "std", "extra", "fast"])
+    );
+}
+
+#[test]
+fn test_code_classification() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/test_classification");
+    let lib = root.join("src/lib.rs");
+    let file_tests = root.join("src/file_tests.rs");
+    let integration = root.join("tests/integration.rs");
+    let common = root.join("tests/common/mod.rs");
+    let parser = root.join("src/parser.rs");
+    let parser_tests = root.join("src/parser/tests.rs");
+    let file_checks = root.join("src/parser/inline/file_checks.rs");
+
+    // The classification is the same with and without ignore_tests, which
+    // only decides whether test code is ignored
+    for ignore_tests in &[false, true] {
+        let settings = AnalysisSettings {
+            ignore_tests: *ignore_tests,
+            ..Default::default()
+        };
+        let analysis = analyze_crate(&root, &settings);
+        let lib = &analysis[&lib];
+        let expected = [
+            // Production code and a helper used by both tests and production
+            (2, None),
+            (6, None),
+            // Helper only built for tests
+            (10, Some(TestReason::CfgTest)),
+            (11, Some(TestReason::CfgTest)),
+            // Test outside a test module
+            (16, Some(TestReason::TestAttribute)),
+            // Test in an inline test module
+            (24, Some(TestReason::CfgTest)),
+            (25, Some(TestReason::CfgTest)),
+            (26, Some(TestReason::CfgTest)),
+        ];
+        for (line, reason) in &expected {
+            assert_eq!(lib.test_reason(*line), *reason, "line {}", line);
+        }
+        for line in &[2, 6, 11, 16, 25, 26] {
+            let ignored = *ignore_tests && lib.test_reason(*line).is_some();
+            assert_eq!(lib.should_ignore(*line), ignored, "line {}", line);
+        }
+        assert_eq!(lib.test_file_reason(), None);
+
+        // A test nested in a module inside a test module
+        let parser = &analysis[&parser];
+        assert_eq!(parser.test_reason(2), None);
+        assert_eq!(parser.test_reason(10), None);
+        assert_eq!(parser.test_reason(18), Some(TestReason::CfgTest));
+        assert!(!parser.should_ignore(10));
+        assert_eq!(parser.should_ignore(18), *ignore_tests);
+
+        // Test modules in their own files, declared in a file that isn't a
+        // `mod.rs` and in an inline module, an integration test and its helper
+        let files = [
+            (&file_tests, TestReason::CfgTest, 5),
+            (&parser_tests, TestReason::CfgTest, 3),
+            (&file_checks, TestReason::CfgTest, 3),
+            (&integration, TestReason::TestsDirectory, 7),
+            (&common, TestReason::TestsDirectory, 2),
+        ];
+        for (path, reason, line) in &files {
+            let file = &analysis[*path];
+            assert_eq!(file.test_file_reason(), Some(*reason), "{:?}", path);
+            assert_eq!(file.test_reason(*line), Some(*reason), "{:?}", path);
+            assert_eq!(file.ignores_all(), *ignore_tests, "{:?}", path);
+            assert_eq!(file.should_ignore(*line), *ignore_tests, "{:?}", path);
+        }
+    }
+}
+
+#[test]
+fn tests_directory_needs_manifest() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/test_classification");
+    assert!(in_tests_directory(
+        &root,
+        &root.join("tests/integration.rs")
+    ));
+    assert!(in_tests_directory(&root, &root.join("tests/common/mod.rs")));
+    assert!(!in_tests_directory(&root, &root.join("src/tests/mod.rs")));
+    assert!(!in_tests_directory(&root, &root.join("src/lib.rs")));
+    assert!(!in_tests_directory(
+        &root,
+        Path::new("/elsewhere/tests/a.rs")
+    ));
+}
+
+#[test]
+fn test_scaffolding() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/test_scaffolding");
+    let lib = root.join("src/lib.rs");
+    let common = root.join("tests/common/mod.rs");
+    let deposits = root.join("tests/deposits.rs");
+    let lines = |analysis: &HashMap<PathBuf, LineAnalysis>, path: &Path| {
+        let mut lines = analysis[path].scaffolding_lines().collect::<Vec<_>>();
+        lines.sort_unstable();
+        lines.dedup();
+        lines
+    };
+
+    let settings = AnalysisSettings {
+        scaffolding: vec![
+            "setup*".to_string(),
+            "teardown*".to_string(),
+            "*_fixture".to_string(),
+        ],
+        ..Default::default()
+    };
+    let analysis = analyze_crate(&root, &settings);
+    // Production code named like a helper and tests named like one aren't
+    // scaffolding, a helper in a test module is
+    assert_eq!(lines(&analysis, &lib), vec![13, 14, 15]);
+    assert!(lines(&analysis, &deposits).is_empty());
+    assert_eq!(
+        lines(&analysis, &common),
+        vec![5, 6, 7, 8, 10, 11, 12, 14, 15, 16]
+    );
+    // The lines are still traced, they're left out of the results afterwards
+    assert!(!analysis[&common].should_ignore(6));
+
+    let settings = AnalysisSettings {
+        scaffolding: vec!["teardown".to_string()],
+        ..Default::default()
+    };
+    let analysis = analyze_crate(&root, &settings);
+    assert_eq!(lines(&analysis, &common), vec![10, 11, 12]);
+    assert!(lines(&analysis, &lib).is_empty());
+
+    let analysis = analyze_crate(&root, &AnalysisSettings::default());
+    assert!(lines(&analysis, &common).is_empty());
+}
@@ -0,0 +1,42 @@
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+use syn::*;
+
+/// Finds the lines of functions and methods declared `pub`. Both the line of
+/// the visibility and of the `fn` token are included as the entry point in the
+/// debug info can be on either. Items in private modules are still included.
+pub(crate) fn find_public_functions(file: &File) -> Vec<usize> {
+    let mut functions = PublicFunctions::default();
+    functions.visit_file(file);
+    functions.lines
+}
+
+#[derive(Default)]
+struct PublicFunctions {
+    lines: Vec<usize>,
+}
+
+impl PublicFunctions {
+    fn add(&mut self, vis: &Visibility, sig: &Signature) {
+        if let Visibility::Public(ref p) = vis {
+            let vis_line = p.pub_token.span().start().line;
+            let fn_line = sig.fn_token.span().start().line;
+            self.lines.push(vis_line);
+            if fn_line != vis_line {
+                self.lines.push(fn_line);
+            }
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for PublicFunctions {
+    fn visit_item_fn(&mut self, i: &'ast ItemFn) {
+        self.add(&i.vis, &i.sig);
+        visit::visit_item_fn(self, i);
+    }
+
+    fn visit_impl_item_method(&mut self, i: &'ast ImplItemMethod) {
+        self.add(&i.vis, &i.sig);
+        visit::visit_impl_item_method(self, i);
+    }
+}
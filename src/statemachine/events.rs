@@ -0,0 +1,1307 @@
+//! The decisions of the Linux tracer separated from the ptrace calls. Wait
+//! statuses are turned into `Event`s carrying anything read from the tracee
+//! and `TracerCore::handle_event` returns the `Action`s to take, so sequences
+//! of events can be tested without a process to trace.
+use crate::errors::RunError;
+use crate::statemachine::{TestExit, TestState, TracerAction};
+use log::{debug, trace};
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ProcessInfo {
+    pub(crate) pid: Pid,
+    pub(crate) signal: Option<Signal>,
+}
+
+impl ProcessInfo {
+    pub(crate) fn new(pid: Pid, signal: Option<Signal>) -> Self {
+        Self { pid, signal }
+    }
+}
+
+impl From<Pid> for ProcessInfo {
+    fn from(pid: Pid) -> Self {
+        ProcessInfo::new(pid, None)
+    }
+}
+
+impl From<&Pid> for ProcessInfo {
+    fn from(pid: &Pid) -> Self {
+        ProcessInfo::new(*pid, None)
+    }
+}
+
+/// Something reported by `waitpid` along with anything read from the tracee
+/// that's needed to handle it
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// Stopped with `SIGTRAP`, `pc` is the program counter if it could be read
+    Trap { pid: Pid, pc: Option<u64> },
+    /// Stopped with any other signal
+    Stopped { pid: Pid, signal: Signal },
+    /// A new thread was created, `thread` is `None` if its id couldn't be read
+    Clone { pid: Pid, thread: Option<Pid> },
+    /// A fork or vfork, `child` is `None` if its id couldn't be read. A vforked
+    /// child shares the memory of the parent until it execs or exits.
+    Fork {
+        pid: Pid,
+        child: Option<Pid>,
+        vfork: bool,
+    },
+    /// The process called exec. `image` is the device and inode of the new
+    /// executable and `path` its path and whether it's setuid or setgid.
+    Exec {
+        pid: Pid,
+        image: Option<(u64, u64)>,
+        path: Option<(PathBuf, bool)>,
+    },
+    /// The thread is about to exit
+    Exiting { pid: Pid },
+    /// A ptrace event which isn't handled or came with a signal other than
+    /// `SIGTRAP`
+    UnknownPtraceEvent {
+        pid: Pid,
+        signal: Signal,
+        event: i32,
+    },
+    /// The thread exited
+    Exited { pid: Pid, code: i32 },
+    /// The thread was killed by a signal
+    Signaled {
+        pid: Pid,
+        signal: Signal,
+        core_dumped: bool,
+    },
+    /// The `StepOver` action couldn't remove the breakpoint
+    StepOverFailed { pid: Pid, addr: u64 },
+    /// Any other wait status
+    Unexpected,
+}
+
+/// A ptrace operation or change to the results for the tracer to carry out
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    /// Resume or detach a thread, done after every event waited for has been
+    /// handled
+    Resume(TracerAction<ProcessInfo>),
+    /// Write back the original instruction at the breakpoint and move the
+    /// thread's program counter back onto it
+    StepOver { pid: Pid, addr: u64 },
+    /// Move the thread's program counter back onto the breakpoint
+    Rewind { pid: Pid, addr: u64 },
+    /// Insert the breakpoint again
+    Enable { pid: Pid, addr: u64 },
+    /// Count a hit of the trace at the address
+    Hit { addr: u64 },
+    /// Insert every breakpoint into the process again
+    Reinstrument { pid: Pid },
+    /// A thread has gone, anything the tracer keeps for it can be dropped
+    Forget { pid: Pid },
+}
+
+/// Tracer state which doesn't need the tracee to update
+#[derive(Debug)]
+pub struct TracerCore {
+    /// Parent PID of test process
+    pub(crate) parent: Pid,
+    /// Addresses of the inserted breakpoints
+    pub(crate) breakpoints: HashSet<u64>,
+    /// Thread count. Hopefully getting rid of in future
+    pub(crate) thread_count: isize,
+    /// Threads and processes currently being traced
+    pub(crate) processes: HashSet<Pid>,
+    /// Set once the test process has exited so there's nothing to clean up
+    pub(crate) finished: bool,
+    /// Device and inode of the instrumented binary, used to spot the test
+    /// re-executing itself
+    pub(crate) image: Option<(u64, u64)>,
+    /// Breakpoint address each thread is single stepping over. A hit is only
+    /// counted once the step has moved the thread past the instruction so a
+    /// trap that's reported again isn't counted twice
+    pub(crate) pending_steps: HashMap<Pid, u64>,
+    /// Signals received while a thread was stepping over a breakpoint, they're
+    /// delivered once the step completes
+    pub(crate) deferred_signals: HashMap<Pid, Signal>,
+    /// Process whose memory each thread uses, threads that aren't listed use
+    /// the test's. A child forked by the test has its own copy of the memory
+    /// and so of the breakpoints.
+    pub(crate) address_spaces: HashMap<Pid, Pid>,
+    /// Forked children which haven't stopped yet, with the breakpoints to
+    /// insert again in their memory once they have
+    pub(crate) new_forks: HashMap<Pid, Vec<u64>>,
+    /// New threads and children which stopped before the event creating them
+    /// was handled, they're resumed once it is
+    pub(crate) early_stops: HashSet<Pid>,
+    /// Breakpoints stepped over since the start of the batch of events, by
+    /// the address space they were stepped over in
+    batch_steps: HashSet<(Pid, u64)>,
+    /// Re-enable breakpoints after a hit to count every hit
+    count: bool,
+    /// Pass signals other than those tarpaulin uses on to the test
+    forward_signals: bool,
+}
+
+type Outcome = Result<(TestState, Vec<Action>), RunError>;
+
+fn waiting(actions: Vec<Action>) -> Outcome {
+    Ok((TestState::wait_state(), actions))
+}
+
+fn resume(action: TracerAction<ProcessInfo>) -> Action {
+    Action::Resume(action)
+}
+
+impl TracerCore {
+    pub fn new(parent: Pid, count: bool, forward_signals: bool) -> Self {
+        let mut processes = HashSet::new();
+        processes.insert(parent);
+        Self {
+            parent,
+            breakpoints: HashSet::new(),
+            thread_count: 0,
+            processes,
+            finished: false,
+            image: None,
+            pending_steps: HashMap::new(),
+            deferred_signals: HashMap::new(),
+            address_spaces: HashMap::new(),
+            new_forks: HashMap::new(),
+            early_stops: HashSet::new(),
+            batch_steps: HashSet::new(),
+            count,
+            forward_signals,
+        }
+    }
+
+    /// Called before handling the events from a round of waiting. Threads
+    /// which hit the same breakpoint in one batch are stepped over by the
+    /// first and the rest go back to the breakpoint.
+    pub fn start_batch(&mut self) {
+        self.batch_steps.clear();
+    }
+
+    /// Updates the state for the event and returns the next state of the
+    /// state machine and the actions to take, in order
+    pub fn handle_event(&mut self, event: Event) -> Outcome {
+        match event {
+            Event::Trap { pid, pc } => self.handle_trap(pid, pc),
+            Event::Stopped {
+                pid,
+                signal: Signal::SIGSTOP,
+            } => waiting(self.handle_sigstop(pid)),
+            Event::Stopped {
+                signal: Signal::SIGSEGV,
+                ..
+            } => Err(RunError::TestRuntime(
+                "A segfault occurred while executing tests".to_string(),
+            )),
+            Event::Stopped {
+                pid,
+                signal: Signal::SIGILL,
+            } => Err(RunError::TestRuntime(format!(
+                "Error running test - SIGILL raised in {}",
+                pid
+            ))),
+            Event::Stopped { pid, signal } => {
+                // An abort has to be delivered or `abort()` falls back to
+                // crashing the test, so it's forwarded regardless
+                let signal = if self.forward_signals || signal == Signal::SIGABRT {
+                    Some(signal)
+                } else {
+                    None
+                };
+                waiting(vec![self.resume(pid, signal, false)])
+            }
+            Event::Clone { pid, thread } => match thread {
+                Some(t) => {
+                    trace!("New thread spawned {}", t);
+                    self.thread_count += 1;
+                    self.processes.insert(t);
+                    let space = self.address_space(pid);
+                    self.address_spaces.insert(t, space);
+                    let mut actions = vec![resume(TracerAction::Continue(pid.into()))];
+                    if self.early_stops.remove(&t) {
+                        actions.push(resume(TracerAction::Continue(t.into())));
+                    }
+                    waiting(actions)
+                }
+                None => Err(ptrace_event_error(
+                    "Error occurred upon test executable thread creation",
+                )),
+            },
+            Event::Fork { pid, child, vfork } => match child {
+                Some(child) => waiting(self.handle_fork(pid, child, vfork)),
+                None => {
+                    trace!("Caught fork event without the child's id");
+                    waiting(vec![resume(TracerAction::Continue(pid.into()))])
+                }
+            },
+            Event::Exec { pid, image, path } => Ok(self.handle_exec(pid, image, path)),
+            Event::Exiting { pid } => {
+                trace!("Child exiting");
+                self.thread_count -= 1;
+                waiting(vec![resume(TracerAction::TryContinue(pid.into()))])
+            }
+            Event::UnknownPtraceEvent { signal, event, .. } => {
+                if signal == Signal::SIGTRAP {
+                    Err(ptrace_event_error(&format!(
+                        "Unrecognised ptrace event {}",
+                        event
+                    )))
+                } else {
+                    trace!("Unexpected signal with ptrace event {}", event);
+                    trace!("Signal: {:?}", signal);
+                    Err(ptrace_event_error("Unexpected signal"))
+                }
+            }
+            Event::Exited { pid, code } => {
+                trace!("Exited {:?} parent {:?}", pid, self.parent);
+                let forget = self.forget_process(pid);
+                if pid == self.parent {
+                    self.finished = true;
+                    Ok((TestState::End(TestExit::Code(code)), vec![forget]))
+                } else {
+                    // Process may have already been destroyed. This is just incase
+                    waiting(vec![
+                        forget,
+                        resume(TracerAction::TryContinue(self.parent.into())),
+                    ])
+                }
+            }
+            Event::Signaled {
+                pid,
+                signal: Signal::SIGTRAP,
+                core_dumped: true,
+            } => waiting(vec![resume(TracerAction::Continue(pid.into()))]),
+            Event::Signaled {
+                pid,
+                signal,
+                core_dumped,
+            } => {
+                trace!("{} killed by {:?}", pid, signal);
+                let forget = self.forget_process(pid);
+                if pid == self.parent {
+                    // The test didn't unwind, but every hit was counted as it
+                    // happened so the coverage up to the signal is kept
+                    self.finished = true;
+                    let exit = TestExit::Signal {
+                        signal: signal as i32,
+                        core_dumped,
+                    };
+                    Ok((TestState::End(exit), vec![forget]))
+                } else {
+                    waiting(vec![
+                        forget,
+                        resume(TracerAction::TryContinue(self.parent.into())),
+                    ])
+                }
+            }
+            Event::StepOverFailed { pid, addr } => {
+                // So failed to process a breakpoint.. Still continue to
+                // avoid stalling
+                debug!("Failed to step over breakpoint at 0x{:x}", addr);
+                self.pending_steps.remove(&pid);
+                let space = self.address_space(pid);
+                self.batch_steps.remove(&(space, addr));
+                waiting(vec![resume(TracerAction::Continue(pid.into()))])
+            }
+            Event::Unexpected => Err(RunError::TestRuntime(
+                "An unexpected signal has been caught by tarpaulin!".to_string(),
+            )),
+        }
+    }
+
+    fn handle_trap(&mut self, pid: Pid, pc: Option<u64>) -> Outcome {
+        if let Some(addr) = self.pending_steps.get(&pid).copied() {
+            return waiting(self.finish_step(pid, pc, addr));
+        }
+        if let Some(rip) = pc.map(|pc| pc.wrapping_sub(1)) {
+            trace!("Hit address 0x{:x}", rip);
+            if self.breakpoints.contains(&rip) {
+                let step = (self.address_space(pid), rip);
+                return if self.batch_steps.contains(&step) {
+                    // Another thread is already stepping over this breakpoint
+                    waiting(vec![
+                        Action::Rewind { pid, addr: rip },
+                        resume(TracerAction::Continue(pid.into())),
+                    ])
+                } else {
+                    self.batch_steps.insert(step);
+                    self.pending_steps.insert(pid, rip);
+                    waiting(vec![
+                        Action::StepOver { pid, addr: rip },
+                        resume(TracerAction::Step(pid.into())),
+                    ])
+                };
+            }
+        }
+        waiting(vec![resume(TracerAction::Continue(pid.into()))])
+    }
+
+    /// Handles the trap after single stepping over the breakpoint at `addr`.
+    /// If the thread didn't move, for example the step was interrupted, it's
+    /// stepped again without counting. Otherwise the hit is counted exactly
+    /// once and the breakpoint is enabled again if hits are being counted.
+    fn finish_step(&mut self, pid: Pid, pc: Option<u64>, addr: u64) -> Vec<Action> {
+        if pc == Some(addr) {
+            trace!("Step over 0x{:x} didn't advance, stepping again", addr);
+            return vec![resume(TracerAction::Step(pid.into()))];
+        }
+        self.pending_steps.remove(&pid);
+        let mut actions = vec![Action::Hit { addr }];
+        if self.count {
+            actions.push(Action::Enable { pid, addr });
+        }
+        let signal = self.deferred_signals.remove(&pid);
+        actions.push(resume(TracerAction::Continue(ProcessInfo::new(
+            pid, signal,
+        ))));
+        actions
+    }
+
+    /// The exec replaces the address space so the breakpoints are gone. If the
+    /// test re-executed itself the image is instrumented again, otherwise the
+    /// new process is detached. The hits before the exec are already in the
+    /// traces so they're kept either way.
+    fn handle_exec(
+        &mut self,
+        pid: Pid,
+        image: Option<(u64, u64)>,
+        path: Option<(PathBuf, bool)>,
+    ) -> (TestState, Vec<Action>) {
+        if pid == self.parent && self.image.is_some() && image == self.image {
+            debug!("Test re-executed itself, reinserting breakpoints");
+            // Every other thread is destroyed by the exec, children it forked
+            // keep running with their own memory
+            let parent = self.parent;
+            self.address_spaces.retain(|_, space| *space != parent);
+            let forked = &self.address_spaces;
+            self.processes.retain(|p| forked.contains_key(p));
+            self.pending_steps.retain(|p, _| forked.contains_key(p));
+            self.deferred_signals.retain(|p, _| forked.contains_key(p));
+            self.batch_steps.retain(|(space, _)| *space != parent);
+            self.thread_count = self.processes.len() as isize;
+            self.processes.insert(pid);
+            // Without PIE the new image is loaded at the same address so the
+            // trace addresses are still valid
+            (
+                TestState::wait_state(),
+                vec![
+                    Action::Reinstrument { pid },
+                    resume(TracerAction::Continue(pid.into())),
+                ],
+            )
+        } else {
+            // The kernel doesn't apply setuid or setgid bits to a traced exec
+            // so the binary runs without its privileges, detaching before it
+            // runs at least stops its events being confused with the test's
+            match path {
+                Some((path, true)) => debug!(
+                    "{} executed setuid/setgid binary {}, detaching",
+                    pid,
+                    path.display()
+                ),
+                Some((path, false)) => debug!(
+                    "{} executed untraced binary {}, detaching",
+                    pid,
+                    path.display()
+                ),
+                None => debug!("{} executed another binary, detaching", pid),
+            }
+            let forget = self.forget_process(pid);
+            (
+                TestState::wait_state(),
+                vec![forget, resume(TracerAction::Detach(pid.into()))],
+            )
+        }
+    }
+
+    /// Removes everything tracked for a process that's exited or been detached
+    fn forget_process(&mut self, pid: Pid) -> Action {
+        self.processes.remove(&pid);
+        self.pending_steps.remove(&pid);
+        self.deferred_signals.remove(&pid);
+        self.address_spaces.remove(&pid);
+        self.new_forks.remove(&pid);
+        self.early_stops.remove(&pid);
+        Action::Forget { pid }
+    }
+
+    /// The process whose memory, and so breakpoints, the thread uses
+    fn address_space(&self, pid: Pid) -> Pid {
+        self.address_spaces
+            .get(&pid)
+            .copied()
+            .unwrap_or(self.parent)
+    }
+
+    /// A new thread or child starts stopped and may be reported before the
+    /// event creating it, it's held until then so a forked child doesn't run
+    /// before its breakpoints are consistent
+    fn handle_sigstop(&mut self, pid: Pid) -> Vec<Action> {
+        if let Some(missing) = self.new_forks.remove(&pid) {
+            let mut actions = missing
+                .into_iter()
+                .map(|addr| Action::Enable { pid, addr })
+                .collect::<Vec<_>>();
+            actions.push(resume(TracerAction::Continue(pid.into())));
+            actions
+        } else if self.processes.contains(&pid) || self.pending_steps.contains_key(&pid) {
+            vec![self.resume(pid, None, true)]
+        } else {
+            trace!("{} stopped before the event creating it", pid);
+            self.early_stops.insert(pid);
+            vec![]
+        }
+    }
+
+    /// A child forked by the test copies its memory, including any breakpoint
+    /// which a thread had removed to step over. When hits are counted those
+    /// are inserted again in the child before it runs, otherwise they're left
+    /// out the same as they will be in the parent once the step completes.
+    /// The child's id may be reused from an exited thread so nothing tracked
+    /// for that thread applies to it.
+    fn handle_fork(&mut self, pid: Pid, child: Pid, vfork: bool) -> Vec<Action> {
+        trace!("{} forked {}", pid, child);
+        self.thread_count += 1;
+        self.processes.insert(child);
+        self.pending_steps.remove(&child);
+        self.deferred_signals.remove(&child);
+        let space = self.address_space(pid);
+        let missing = if vfork || !self.count {
+            vec![]
+        } else {
+            self.pending_steps
+                .iter()
+                .filter(|(t, _)| self.address_space(**t) == space)
+                .map(|(_, addr)| *addr)
+                .collect()
+        };
+        // A vforked child borrows the parent's memory until it execs or exits
+        self.address_spaces
+            .insert(child, if vfork { space } else { child });
+        let mut actions = vec![resume(TracerAction::Continue(pid.into()))];
+        if self.early_stops.remove(&child) {
+            actions.extend(
+                missing
+                    .into_iter()
+                    .map(|addr| Action::Enable { pid: child, addr }),
+            );
+            actions.push(resume(TracerAction::Continue(child.into())));
+        } else {
+            self.new_forks.insert(child, missing);
+        }
+        actions
+    }
+
+    /// Action to resume a thread after a stop that wasn't a breakpoint. If the
+    /// thread is stepping over a breakpoint it's stepped again and any signal
+    /// is held back until the step completes, otherwise a signal delivered
+    /// mid-step could run a handler and then re-execute the breakpoint. If
+    /// `must` is false failing to continue the thread isn't an error.
+    fn resume(&mut self, pid: Pid, signal: Option<Signal>, must: bool) -> Action {
+        let action = if self.pending_steps.contains_key(&pid) {
+            if let Some(s) = signal {
+                self.deferred_signals.insert(pid, s);
+            }
+            TracerAction::Step(pid.into())
+        } else if must {
+            TracerAction::Continue(ProcessInfo::new(pid, signal))
+        } else {
+            TracerAction::TryContinue(ProcessInfo::new(pid, signal))
+        };
+        Action::Resume(action)
+    }
+}
+
+fn ptrace_event_error(msg: &str) -> RunError {
+    RunError::TestRuntime(format!(
+        "Error occurred when handling ptrace event: {}",
+        RunError::TestRuntime(msg.to_string())
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BP: u64 = 0x100;
+    const OTHER_BP: u64 = 0x200;
+    const IMAGE: Option<(u64, u64)> = Some((8, 1234));
+
+    fn pid(n: i32) -> Pid {
+        Pid::from_raw(n)
+    }
+
+    fn tracer(count: bool, forward_signals: bool) -> TracerCore {
+        let mut core = TracerCore::new(pid(1), count, forward_signals);
+        core.breakpoints.insert(BP);
+        core.breakpoints.insert(OTHER_BP);
+        core.image = IMAGE;
+        core.start_batch();
+        core
+    }
+
+    fn handle(core: &mut TracerCore, event: Event) -> Vec<Action> {
+        let (state, actions) = core.handle_event(event).unwrap();
+        assert!(matches!(state, TestState::Waiting { .. }));
+        actions
+    }
+
+    fn error(core: &mut TracerCore, event: Event) -> String {
+        core.handle_event(event).unwrap_err().to_string()
+    }
+
+    /// A trap just after executing the breakpoint instruction at `addr`
+    fn hit(n: i32, addr: u64) -> Event {
+        Event::Trap {
+            pid: pid(n),
+            pc: Some(addr + 1),
+        }
+    }
+
+    /// A trap after single stepping to `pc`
+    fn stepped(n: i32, pc: u64) -> Event {
+        Event::Trap {
+            pid: pid(n),
+            pc: Some(pc),
+        }
+    }
+
+    fn stopped(n: i32, signal: Signal) -> Event {
+        Event::Stopped {
+            pid: pid(n),
+            signal,
+        }
+    }
+
+    fn cont(n: i32) -> Action {
+        Action::Resume(TracerAction::Continue(pid(n).into()))
+    }
+
+    fn cont_with(n: i32, signal: Signal) -> Action {
+        Action::Resume(TracerAction::Continue(ProcessInfo::new(
+            pid(n),
+            Some(signal),
+        )))
+    }
+
+    fn try_cont(n: i32) -> Action {
+        Action::Resume(TracerAction::TryContinue(pid(n).into()))
+    }
+
+    fn step(n: i32) -> Action {
+        Action::Resume(TracerAction::Step(pid(n).into()))
+    }
+
+    fn step_over(n: i32, addr: u64) -> Action {
+        Action::StepOver { pid: pid(n), addr }
+    }
+
+    fn hits(actions: &[Action]) -> usize {
+        actions
+            .iter()
+            .filter(|a| matches!(a, Action::Hit { .. }))
+            .count()
+    }
+
+    #[test]
+    fn breakpoint_hit_is_stepped_over_then_counted() {
+        let mut core = tracer(false, false);
+        assert_eq!(
+            handle(&mut core, hit(1, BP)),
+            vec![step_over(1, BP), step(1)]
+        );
+        assert_eq!(core.pending_steps.get(&pid(1)), Some(&BP));
+
+        core.start_batch();
+        let actions = handle(&mut core, stepped(1, BP + 4));
+        assert_eq!(actions, vec![Action::Hit { addr: BP }, cont(1)]);
+        assert!(core.pending_steps.is_empty());
+    }
+
+    #[test]
+    fn count_enables_breakpoint_after_step() {
+        let mut core = tracer(true, false);
+        handle(&mut core, hit(1, BP));
+        core.start_batch();
+        let actions = handle(&mut core, stepped(1, BP + 4));
+        assert_eq!(
+            actions,
+            vec![
+                Action::Hit { addr: BP },
+                Action::Enable {
+                    pid: pid(1),
+                    addr: BP
+                },
+                cont(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn step_without_progress_isnt_counted() {
+        let mut core = tracer(false, false);
+        handle(&mut core, hit(1, BP));
+        core.start_batch();
+        assert_eq!(handle(&mut core, stepped(1, BP)), vec![step(1)]);
+        assert_eq!(core.pending_steps.get(&pid(1)), Some(&BP));
+
+        core.start_batch();
+        let actions = handle(&mut core, stepped(1, BP + 2));
+        assert_eq!(hits(&actions), 1);
+    }
+
+    #[test]
+    fn step_with_unreadable_pc_is_counted() {
+        let mut core = tracer(false, false);
+        handle(&mut core, hit(1, BP));
+        core.start_batch();
+        let actions = handle(
+            &mut core,
+            Event::Trap {
+                pid: pid(1),
+                pc: None,
+            },
+        );
+        assert_eq!(actions, vec![Action::Hit { addr: BP }, cont(1)]);
+    }
+
+    #[test]
+    fn trap_outside_breakpoints_continues() {
+        let mut core = tracer(false, false);
+        assert_eq!(handle(&mut core, hit(1, 0x300)), vec![cont(1)]);
+        let actions = handle(
+            &mut core,
+            Event::Trap {
+                pid: pid(1),
+                pc: None,
+            },
+        );
+        assert_eq!(actions, vec![cont(1)]);
+        // A program counter of zero mustn't underflow
+        assert_eq!(handle(&mut core, stepped(1, 0)), vec![cont(1)]);
+        assert!(core.pending_steps.is_empty());
+    }
+
+    #[test]
+    fn second_thread_on_same_breakpoint_is_rewound() {
+        let mut core = tracer(false, false);
+        handle(&mut core, hit(1, BP));
+        let actions = handle(&mut core, hit(2, BP));
+        assert_eq!(
+            actions,
+            vec![
+                Action::Rewind {
+                    pid: pid(2),
+                    addr: BP
+                },
+                cont(2)
+            ]
+        );
+        assert!(!core.pending_steps.contains_key(&pid(2)));
+    }
+
+    #[test]
+    fn same_breakpoint_in_next_batch_is_stepped_over() {
+        let mut core = tracer(false, false);
+        handle(&mut core, hit(1, BP));
+        core.start_batch();
+        handle(&mut core, stepped(1, BP + 4));
+        assert_eq!(
+            handle(&mut core, hit(2, BP)),
+            vec![step_over(2, BP), step(2)]
+        );
+    }
+
+    #[test]
+    fn threads_on_different_breakpoints_step_together() {
+        let mut core = tracer(false, false);
+        assert_eq!(
+            handle(&mut core, hit(1, BP)),
+            vec![step_over(1, BP), step(1)]
+        );
+        assert_eq!(
+            handle(&mut core, hit(2, OTHER_BP)),
+            vec![step_over(2, OTHER_BP), step(2)]
+        );
+        core.start_batch();
+        let first = handle(&mut core, stepped(2, OTHER_BP + 1));
+        let second = handle(&mut core, stepped(1, BP + 1));
+        assert_eq!(first[0], Action::Hit { addr: OTHER_BP });
+        assert_eq!(second[0], Action::Hit { addr: BP });
+    }
+
+    #[test]
+    fn thread_exits_while_another_hits_breakpoint() {
+        let mut core = tracer(false, false);
+        handle(
+            &mut core,
+            Event::Clone {
+                pid: pid(1),
+                thread: Some(pid(2)),
+            },
+        );
+        core.start_batch();
+        assert_eq!(
+            handle(&mut core, hit(1, BP)),
+            vec![step_over(1, BP), step(1)]
+        );
+        assert_eq!(
+            handle(&mut core, Event::Exiting { pid: pid(2) }),
+            vec![try_cont(2)]
+        );
+        let actions = handle(
+            &mut core,
+            Event::Exited {
+                pid: pid(2),
+                code: 0,
+            },
+        );
+        assert_eq!(actions, vec![Action::Forget { pid: pid(2) }, try_cont(1)]);
+        assert_eq!(core.pending_steps.get(&pid(1)), Some(&BP));
+
+        core.start_batch();
+        assert_eq!(hits(&handle(&mut core, stepped(1, BP + 1))), 1);
+        assert_eq!(core.thread_count, 0);
+        assert!(!core.processes.contains(&pid(2)));
+    }
+
+    #[test]
+    fn thread_exiting_mid_step_is_forgotten() {
+        let mut core = tracer(false, false);
+        handle(&mut core, hit(2, BP));
+        core.deferred_signals.insert(pid(2), Signal::SIGUSR1);
+        handle(
+            &mut core,
+            Event::Exited {
+                pid: pid(2),
+                code: 1,
+            },
+        );
+        assert!(core.pending_steps.is_empty());
+        assert!(core.deferred_signals.is_empty());
+    }
+
+    #[test]
+    fn parent_exit_ends_test() {
+        let mut core = tracer(false, false);
+        let (state, actions) = core
+            .handle_event(Event::Exited {
+                pid: pid(1),
+                code: 101,
+            })
+            .unwrap();
+        assert_eq!(state, TestState::End(TestExit::Code(101)));
+        assert_eq!(actions, vec![Action::Forget { pid: pid(1) }]);
+        assert!(core.finished);
+        assert!(core.processes.is_empty());
+    }
+
+    #[test]
+    fn reexec_during_step_over_reinstruments() {
+        let mut core = tracer(false, false);
+        handle(
+            &mut core,
+            Event::Clone {
+                pid: pid(1),
+                thread: Some(pid(2)),
+            },
+        );
+        handle(&mut core, hit(1, BP));
+        core.deferred_signals.insert(pid(1), Signal::SIGUSR1);
+        let actions = handle(
+            &mut core,
+            Event::Exec {
+                pid: pid(1),
+                image: IMAGE,
+                path: None,
+            },
+        );
+        assert_eq!(actions, vec![Action::Reinstrument { pid: pid(1) }, cont(1)]);
+        assert!(core.pending_steps.is_empty());
+        assert!(core.deferred_signals.is_empty());
+        assert_eq!(core.thread_count, 0);
+        assert_eq!(core.processes.iter().collect::<Vec<_>>(), vec![&pid(1)]);
+        // The stepped over breakpoint can be stepped over again straight away
+        assert_eq!(
+            handle(&mut core, hit(1, BP)),
+            vec![step_over(1, BP), step(1)]
+        );
+    }
+
+    #[test]
+    fn foreign_exec_during_step_over_detaches() {
+        let mut core = tracer(false, false);
+        handle(
+            &mut core,
+            Event::Clone {
+                pid: pid(1),
+                thread: Some(pid(3)),
+            },
+        );
+        handle(&mut core, hit(3, BP));
+        let actions = handle(
+            &mut core,
+            Event::Exec {
+                pid: pid(3),
+                image: Some((8, 99)),
+                path: Some((PathBuf::from("/bin/sh"), false)),
+            },
+        );
+        assert_eq!(
+            actions,
+            vec![
+                Action::Forget { pid: pid(3) },
+                Action::Resume(TracerAction::Detach(pid(3).into()))
+            ]
+        );
+        assert!(core.pending_steps.is_empty());
+        assert!(!core.processes.contains(&pid(3)));
+    }
+
+    #[test]
+    fn exec_of_parent_into_other_image_detaches() {
+        let mut core = tracer(false, false);
+        let actions = handle(
+            &mut core,
+            Event::Exec {
+                pid: pid(1),
+                image: Some((8, 99)),
+                path: Some((PathBuf::from("/usr/bin/sudo"), true)),
+            },
+        );
+        assert!(actions.contains(&Action::Resume(TracerAction::Detach(pid(1).into()))));
+
+        // Without the image of the test nothing counts as a re-exec
+        let mut core = tracer(false, false);
+        core.image = None;
+        let actions = handle(
+            &mut core,
+            Event::Exec {
+                pid: pid(1),
+                image: None,
+                path: None,
+            },
+        );
+        assert!(actions.contains(&Action::Resume(TracerAction::Detach(pid(1).into()))));
+    }
+
+    #[test]
+    fn signal_storm_during_step_is_deferred() {
+        let mut core = tracer(false, true);
+        handle(&mut core, hit(1, BP));
+        for signal in &[Signal::SIGUSR1, Signal::SIGALRM, Signal::SIGUSR2] {
+            assert_eq!(handle(&mut core, stopped(1, *signal)), vec![step(1)]);
+        }
+        // Only the latest signal is kept for delivery
+        assert_eq!(core.deferred_signals.get(&pid(1)), Some(&Signal::SIGUSR2));
+
+        core.start_batch();
+        let actions = handle(&mut core, stepped(1, BP + 1));
+        assert_eq!(
+            actions,
+            vec![Action::Hit { addr: BP }, cont_with(1, Signal::SIGUSR2)]
+        );
+        assert!(core.deferred_signals.is_empty());
+    }
+
+    #[test]
+    fn signals_during_step_dropped_without_forwarding() {
+        let mut core = tracer(false, false);
+        handle(&mut core, hit(1, BP));
+        assert_eq!(
+            handle(&mut core, stopped(1, Signal::SIGUSR1)),
+            vec![step(1)]
+        );
+        assert!(core.deferred_signals.is_empty());
+        core.start_batch();
+        let actions = handle(&mut core, stepped(1, BP + 1));
+        assert_eq!(actions.last(), Some(&cont(1)));
+    }
+
+    #[test]
+    fn signals_forwarded_outside_step() {
+        let mut core = tracer(false, true);
+        let actions = handle(&mut core, stopped(2, Signal::SIGCHLD));
+        assert_eq!(
+            actions,
+            vec![Action::Resume(TracerAction::TryContinue(ProcessInfo::new(
+                pid(2),
+                Some(Signal::SIGCHLD)
+            )))]
+        );
+
+        let mut core = tracer(false, false);
+        assert_eq!(
+            handle(&mut core, stopped(2, Signal::SIGCHLD)),
+            vec![try_cont(2)]
+        );
+    }
+
+    #[test]
+    fn sigstop_is_never_forwarded() {
+        let mut core = tracer(false, true);
+        handle(
+            &mut core,
+            Event::Clone {
+                pid: pid(1),
+                thread: Some(pid(2)),
+            },
+        );
+        assert_eq!(
+            handle(&mut core, stopped(2, Signal::SIGSTOP)),
+            vec![cont(2)]
+        );
+        handle(&mut core, hit(2, BP));
+        assert_eq!(
+            handle(&mut core, stopped(2, Signal::SIGSTOP)),
+            vec![step(2)]
+        );
+        assert!(core.deferred_signals.is_empty());
+    }
+
+    #[test]
+    fn fatal_signals_are_errors() {
+        let mut core = tracer(false, true);
+        assert!(error(&mut core, stopped(1, Signal::SIGSEGV)).contains("segfault"));
+        assert!(error(&mut core, stopped(4, Signal::SIGILL)).contains("SIGILL raised in 4"));
+        assert!(error(&mut core, Event::Unexpected).contains("unexpected signal"));
+    }
+
+    #[test]
+    fn abort_is_always_delivered() {
+        let mut core = tracer(false, false);
+        assert_eq!(
+            handle(&mut core, stopped(1, Signal::SIGABRT)),
+            vec![Action::Resume(TracerAction::TryContinue(ProcessInfo::new(
+                pid(1),
+                Some(Signal::SIGABRT)
+            )))]
+        );
+        // Mid-step it waits for the step like any other signal
+        handle(&mut core, hit(1, BP));
+        assert_eq!(
+            handle(&mut core, stopped(1, Signal::SIGABRT)),
+            vec![step(1)]
+        );
+        core.start_batch();
+        let actions = handle(&mut core, stepped(1, BP + 1));
+        assert_eq!(actions.last(), Some(&cont_with(1, Signal::SIGABRT)));
+    }
+
+    #[test]
+    fn signaled_parent_ends_test() {
+        let mut core = tracer(false, false);
+        handle(&mut core, hit(1, BP));
+        let (state, actions) = core
+            .handle_event(Event::Signaled {
+                pid: pid(1),
+                signal: Signal::SIGABRT,
+                core_dumped: true,
+            })
+            .unwrap();
+        let exit = TestExit::Signal {
+            signal: Signal::SIGABRT as i32,
+            core_dumped: true,
+        };
+        assert_eq!(state, TestState::End(exit));
+        assert_eq!(exit.code(), 134);
+        assert_eq!(actions, vec![Action::Forget { pid: pid(1) }]);
+        assert!(core.finished);
+        assert!(core.pending_steps.is_empty());
+    }
+
+    #[test]
+    fn signaled_child_is_forgotten() {
+        let mut core = tracer(false, false);
+        handle(
+            &mut core,
+            Event::Clone {
+                pid: pid(1),
+                thread: Some(pid(2)),
+            },
+        );
+        let actions = handle(
+            &mut core,
+            Event::Signaled {
+                pid: pid(2),
+                signal: Signal::SIGKILL,
+                core_dumped: false,
+            },
+        );
+        assert_eq!(actions, vec![Action::Forget { pid: pid(2) }, try_cont(1)]);
+        assert!(!core.finished);
+        assert!(!core.processes.contains(&pid(2)));
+    }
+
+    #[test]
+    fn signaled_trap_with_core_dump_continues() {
+        let mut core = tracer(false, false);
+        let actions = handle(
+            &mut core,
+            Event::Signaled {
+                pid: pid(1),
+                signal: Signal::SIGTRAP,
+                core_dumped: true,
+            },
+        );
+        assert_eq!(actions, vec![cont(1)]);
+    }
+
+    #[test]
+    fn clone_and_fork_are_tracked() {
+        let mut core = tracer(false, false);
+        for t in 2..5 {
+            let actions = handle(
+                &mut core,
+                Event::Clone {
+                    pid: pid(1),
+                    thread: Some(pid(t)),
+                },
+            );
+            assert_eq!(actions, vec![cont(1)]);
+        }
+        assert_eq!(core.thread_count, 3);
+        assert_eq!(core.processes.len(), 4);
+        assert_eq!(
+            handle(
+                &mut core,
+                Event::Fork {
+                    pid: pid(3),
+                    child: None,
+                    vfork: false
+                }
+            ),
+            vec![cont(3)]
+        );
+
+        let err = error(
+            &mut core,
+            Event::Clone {
+                pid: pid(1),
+                thread: None,
+            },
+        );
+        assert!(err.starts_with("Failed to run tests: Error occurred when handling ptrace event"));
+        assert!(err.ends_with("Error occurred upon test executable thread creation"));
+    }
+
+    #[test]
+    fn unknown_ptrace_events_are_errors() {
+        let mut core = tracer(false, false);
+        let err = error(
+            &mut core,
+            Event::UnknownPtraceEvent {
+                pid: pid(1),
+                signal: Signal::SIGTRAP,
+                event: 42,
+            },
+        );
+        assert!(err.ends_with("Unrecognised ptrace event 42"));
+        let err = error(
+            &mut core,
+            Event::UnknownPtraceEvent {
+                pid: pid(1),
+                signal: Signal::SIGUSR1,
+                event: 1,
+            },
+        );
+        assert!(err.ends_with("Unexpected signal"));
+    }
+
+    #[test]
+    fn failed_step_over_continues_without_counting() {
+        let mut core = tracer(false, false);
+        handle(&mut core, hit(1, BP));
+        let actions = handle(
+            &mut core,
+            Event::StepOverFailed {
+                pid: pid(1),
+                addr: BP,
+            },
+        );
+        assert_eq!(actions, vec![cont(1)]);
+        assert!(core.pending_steps.is_empty());
+        // Another thread in the same batch gets to try
+        assert_eq!(
+            handle(&mut core, hit(2, BP)),
+            vec![step_over(2, BP), step(2)]
+        );
+    }
+
+    #[test]
+    fn every_hit_counted_once_across_threads() {
+        let mut core = tracer(true, false);
+        let mut counted = 0;
+        for round in 0..5 {
+            core.start_batch();
+            // Thread 1 hits first, thread 2 is rewound and hits next round
+            let first = if round % 2 == 0 { 1 } else { 2 };
+            let second = 3 - first;
+            counted += hits(&handle(&mut core, hit(first, BP)));
+            counted += hits(&handle(&mut core, hit(second, BP)));
+            core.start_batch();
+            counted += hits(&handle(&mut core, stepped(first, BP + 1)));
+        }
+        assert_eq!(counted, 5);
+        assert!(core.pending_steps.is_empty());
+    }
+
+    fn fork(core: &mut TracerCore, parent: i32, child: i32, vfork: bool) -> Vec<Action> {
+        handle(
+            core,
+            Event::Fork {
+                pid: pid(parent),
+                child: Some(pid(child)),
+                vfork,
+            },
+        )
+    }
+
+    #[test]
+    fn forked_child_has_its_own_breakpoints() {
+        let mut core = tracer(true, false);
+        handle(
+            &mut core,
+            Event::Clone {
+                pid: pid(1),
+                thread: Some(pid(2)),
+            },
+        );
+        // Thread 2 is stepping over the breakpoint when thread 1 forks
+        handle(&mut core, hit(2, BP));
+        assert_eq!(fork(&mut core, 1, 10, false), vec![cont(1)]);
+        assert_eq!(core.thread_count, 2);
+        assert!(core.processes.contains(&pid(10)));
+        // The breakpoint is put back in the child's copy before it runs
+        assert_eq!(
+            handle(&mut core, stopped(10, Signal::SIGSTOP)),
+            vec![
+                Action::Enable {
+                    pid: pid(10),
+                    addr: BP
+                },
+                cont(10)
+            ]
+        );
+        // The child steps over its own copy rather than waiting for thread 2
+        // and the parent's step isn't taken as the child's
+        assert_eq!(
+            handle(&mut core, hit(10, BP)),
+            vec![step_over(10, BP), step(10)]
+        );
+        assert_eq!(
+            handle(&mut core, hit(1, BP)),
+            vec![
+                Action::Rewind {
+                    pid: pid(1),
+                    addr: BP
+                },
+                cont(1)
+            ]
+        );
+        core.start_batch();
+        assert_eq!(hits(&handle(&mut core, stepped(2, BP + 1))), 1);
+        assert_eq!(hits(&handle(&mut core, stepped(10, BP + 1))), 1);
+
+        handle(
+            &mut core,
+            Event::Exited {
+                pid: pid(10),
+                code: 0,
+            },
+        );
+        assert!(core.address_spaces.get(&pid(10)).is_none());
+        assert!(!core.processes.contains(&pid(10)));
+    }
+
+    #[test]
+    fn forked_child_without_counting_keeps_removed_breakpoints_removed() {
+        let mut core = tracer(false, false);
+        handle(&mut core, hit(1, BP));
+        fork(&mut core, 1, 10, false);
+        assert_eq!(
+            handle(&mut core, stopped(10, Signal::SIGSTOP)),
+            vec![cont(10)]
+        );
+    }
+
+    #[test]
+    fn child_stopped_before_fork_event_is_held() {
+        let mut core = tracer(true, false);
+        handle(&mut core, hit(1, OTHER_BP));
+        assert_eq!(handle(&mut core, stopped(10, Signal::SIGSTOP)), vec![]);
+        assert_eq!(
+            fork(&mut core, 3, 10, false),
+            vec![
+                cont(3),
+                Action::Enable {
+                    pid: pid(10),
+                    addr: OTHER_BP
+                },
+                cont(10)
+            ]
+        );
+        assert!(core.early_stops.is_empty());
+        assert!(core.new_forks.is_empty());
+
+        // The same goes for a new thread
+        assert_eq!(handle(&mut core, stopped(11, Signal::SIGSTOP)), vec![]);
+        assert_eq!(
+            handle(
+                &mut core,
+                Event::Clone {
+                    pid: pid(1),
+                    thread: Some(pid(11)),
+                },
+            ),
+            vec![cont(1), cont(11)]
+        );
+    }
+
+    #[test]
+    fn vforked_child_shares_breakpoints() {
+        let mut core = tracer(true, false);
+        handle(&mut core, hit(1, BP));
+        fork(&mut core, 1, 10, true);
+        assert_eq!(
+            handle(&mut core, stopped(10, Signal::SIGSTOP)),
+            vec![cont(10)]
+        );
+        // The breakpoint is already removed from the shared memory
+        assert_eq!(
+            handle(&mut core, hit(10, BP)),
+            vec![
+                Action::Rewind {
+                    pid: pid(10),
+                    addr: BP
+                },
+                cont(10)
+            ]
+        );
+    }
+
+    #[test]
+    fn forked_child_survives_parent_reexec() {
+        let mut core = tracer(false, false);
+        fork(&mut core, 1, 10, false);
+        handle(&mut core, stopped(10, Signal::SIGSTOP));
+        handle(&mut core, hit(10, BP));
+        handle(
+            &mut core,
+            Event::Exec {
+                pid: pid(1),
+                image: IMAGE,
+                path: None,
+            },
+        );
+        assert!(core.processes.contains(&pid(10)));
+        assert_eq!(core.pending_steps.get(&pid(10)), Some(&BP));
+        core.start_batch();
+        assert_eq!(hits(&handle(&mut core, stepped(10, BP + 1))), 1);
+    }
+}
@@ -1,72 +1,155 @@
+use crate::breakpoint_dump;
 use crate::config::Config;
 use crate::errors::RunError;
+use crate::statemachine::events::*;
 use crate::statemachine::*;
-use log::{debug, trace};
+use crate::stats;
+use log::{debug, trace, warn};
 use nix::errno::Errno;
-use nix::sys::signal::Signal;
+use nix::libc::{S_ISGID, S_ISUID};
+use nix::sys::signal::{kill, Signal};
 use nix::sys::wait::*;
 use nix::unistd::Pid;
 use nix::Error as NixErr;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::time::Instant;
 
 pub fn create_state_machine<'a>(
     test: Pid,
     traces: &'a mut TraceMap,
     config: &'a Config,
 ) -> (TestState, LinuxData<'a>) {
-    let mut data = LinuxData::new(traces, config);
-    data.parent = test;
+    let data = LinuxData::new(test, traces, config);
     (TestState::start_state(), data)
 }
 
-pub type UpdateContext = (TestState, TracerAction<ProcessInfo>);
+/// Device and inode of the executable running in the process
+fn executable_image(pid: Pid) -> Option<(u64, u64)> {
+    fs::metadata(format!("/proc/{}/exe", pid))
+        .ok()
+        .map(|m| (m.dev(), m.ino()))
+}
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-pub struct ProcessInfo {
-    pid: Pid,
-    signal: Option<Signal>,
+/// Whether the tests are built with AddressSanitizer from the flags passed to
+/// rustc
+fn address_sanitized() -> bool {
+    ["RUSTFLAGS", "CARGO_ENCODED_RUSTFLAGS"]
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .any(|flags| flags.contains("sanitizer=address"))
 }
 
-impl ProcessInfo {
-    fn new(pid: Pid, signal: Option<Signal>) -> Self {
-        Self { pid, signal }
+/// AddressSanitizer reserves and protects shadow memory across the address
+/// space, which can stop breakpoints being written into the test. Ptrace
+/// can't work around that so point at a coverage engine which doesn't need
+/// breakpoints.
+fn warn_if_sanitized(addr: u64) {
+    if address_sanitized() {
+        warn!(
+            "Couldn't insert a breakpoint at 0x{:x} in a test built with AddressSanitizer, its shadow memory interferes with breakpoints. Use LLVM source based coverage (-C instrument-coverage) for sanitized tests",
+            addr
+        );
     }
 }
 
-impl From<Pid> for ProcessInfo {
-    fn from(pid: Pid) -> Self {
-        ProcessInfo::new(pid, None)
+/// Name of the trace's function for logs, empty if the debug info doesn't
+/// give one for its address
+fn in_function(fn_name: Option<&str>) -> String {
+    match fn_name {
+        Some(name) => format!(" in {}", name),
+        None => String::new(),
     }
 }
 
-impl From<&Pid> for ProcessInfo {
-    fn from(pid: &Pid) -> Self {
-        ProcessInfo::new(*pid, None)
-    }
+/// Path of the executable running in the process and whether it has the
+/// setuid or setgid bit set
+fn executable_path(pid: Pid) -> Option<(PathBuf, bool)> {
+    let exe = format!("/proc/{}/exe", pid);
+    let path = fs::read_link(&exe).ok()?;
+    let setid = fs::metadata(&exe)
+        .map(|m| m.mode() & (S_ISUID | S_ISGID) != 0)
+        .unwrap_or(false);
+    Some((path, setid))
 }
 
-/// Handle to linux process state
+/// Handle to linux process state. The decisions are made by the
+/// `TracerCore`, this carries out its actions with ptrace
 pub struct LinuxData<'a> {
     /// Recent results from waitpid to be handled by statemachine
     wait_queue: Vec<WaitStatus>,
     /// Current Pid to process
     current: Pid,
-    /// Parent PID of test process
-    parent: Pid,
-    /// Map of addresses to breakpoints
-    breakpoints: HashMap<u64, Breakpoint>,
-    /// Instrumentation points in code with associated coverage data
-    traces: &'a mut TraceMap,
+    /// Breakpoints inserted in the test
+    breakpoints: Breakpoints,
+    /// Instrumentation points in code with associated coverage data, the
+    /// breakpoints refer to them by index
+    traces: Vec<&'a mut Trace>,
     /// Program config
     config: &'a Config,
-    /// Thread count. Hopefully getting rid of in future
-    thread_count: isize,
+    /// When tracing started, used for the hit times with --profile-hits
+    start_time: Instant,
+    /// Process state and the handling of events
+    core: TracerCore,
+}
+
+impl<'a> Drop for LinuxData<'a> {
+    /// If tracing ends early due to an error or panic the test may still be
+    /// stopped with breakpoints inserted. Restore the original instructions
+    /// and then kill and reap the test so nothing is left behind.
+    fn drop(&mut self) {
+        let parent = self.core.parent;
+        if self.core.finished || parent == Pid::from_raw(0) {
+            return;
+        }
+        debug!("Tracing ended early, cleaning up test {}", parent);
+        self.core.processes.insert(parent);
+        // Memory is shared between threads so it's restored via any stopped
+        // one, forked children have their own copy
+        let test_threads = self.core.processes.iter().filter(|p| {
+            self.core
+                .address_spaces
+                .get(p)
+                .map_or(true, |s| *s == parent)
+        });
+        for pid in test_threads {
+            if self.breakpoints.iter().all(|bp| bp.restore(*pid).is_ok()) {
+                break;
+            }
+        }
+        let _ = kill(parent, Signal::SIGKILL);
+        // Children forked by the test aren't killed with it
+        for (pid, space) in &self.core.address_spaces {
+            if pid == space {
+                let _ = kill(*pid, Signal::SIGKILL);
+            }
+        }
+        // The thread group leader can only be reaped once the other threads are
+        let mut pids = self.core.processes.iter().copied().collect::<Vec<_>>();
+        pids.sort_by_key(|pid| *pid == parent);
+        for pid in pids {
+            loop {
+                match waitpid(pid, Some(WaitPidFlag::__WALL)) {
+                    Err(NixErr::Sys(Errno::EINTR)) => {}
+                    Ok(WaitStatus::Exited(_, _)) | Ok(WaitStatus::Signaled(_, _, _)) | Err(_) => {
+                        break
+                    }
+                    Ok(_) => {
+                        let _ = continue_exec(pid, None);
+                    }
+                }
+            }
+        }
+    }
 }
 
 impl<'a> StateData for LinuxData<'a> {
     fn start(&mut self) -> Result<Option<TestState>, RunError> {
         match waitpid(self.current, Some(WaitPidFlag::WNOHANG)) {
-            Ok(WaitStatus::StillAlive) => Ok(None),
+            Ok(WaitStatus::StillAlive) | Err(NixErr::Sys(Errno::EINTR)) => Ok(None),
             Ok(sig @ WaitStatus::Stopped(_, Signal::SIGTRAP)) => {
                 if let WaitStatus::Stopped(child, _) = sig {
                     self.current = child;
@@ -86,34 +169,13 @@ impl<'a> StateData for LinuxData<'a> {
 
     fn init(&mut self) -> Result<TestState, RunError> {
         trace_children(self.current)?;
-        for trace in self.traces.all_traces() {
-            for addr in &trace.address {
-                match Breakpoint::new(self.current, *addr) {
-                    Ok(bp) => {
-                        let _ = self.breakpoints.insert(*addr, bp);
-                    }
-                    Err(e) if e == NixErr::Sys(Errno::EIO) => {
-                        return Err(RunError::TestRuntime(
-                            "ERROR: Tarpaulin cannot find code addresses \
-                             check that pie is disabled for your linker. \
-                             If linking with gcc try adding -C link-args=-no-pie \
-                             to your rust flags"
-                                .to_string(),
-                        ));
-                    }
-                    Err(NixErr::UnsupportedOperation) => {
-                        debug!("Instrumentation address clash, ignoring 0x{:x}", addr);
-                    }
-                    Err(_) => {
-                        return Err(RunError::TestRuntime(
-                            "Failed to instrument test executable".to_string(),
-                        ));
-                    }
-                }
-            }
-        }
+        self.core.image = executable_image(self.current);
+        self.attach_existing_threads();
+        self.add_breakpoints(self.current)?;
+        // Untraced threads could have spawned more while inserting
+        self.attach_existing_threads();
 
-        if continue_exec(self.parent, None).is_ok() {
+        if continue_exec(self.core.parent, None).is_ok() {
             trace!("Initialised inferior, transitioning to wait state");
             Ok(TestState::wait_state())
         } else {
@@ -135,7 +197,9 @@ impl<'a> StateData for LinuxData<'a> {
                 Ok(WaitStatus::StillAlive) => {
                     running = false;
                 }
-                Ok(WaitStatus::Exited(_, _)) => {
+                // Interrupted before anything was reaped so nothing is lost
+                Err(NixErr::Sys(Errno::EINTR)) => {}
+                Ok(WaitStatus::Exited(_, _)) | Ok(WaitStatus::Signaled(_, _, _)) => {
                     self.wait_queue.push(wait.unwrap());
                     result = Ok(Some(TestState::Stopped));
                     running = false;
@@ -149,6 +213,10 @@ impl<'a> StateData for LinuxData<'a> {
                     self.wait_queue.push(s);
                     result = Ok(Some(TestState::Stopped));
                 }
+                // Every child has been reaped, the queued statuses still need handling
+                Err(NixErr::Sys(Errno::ECHILD)) if !self.wait_queue.is_empty() => {
+                    running = false;
+                }
                 Err(e) => {
                     running = false;
                     result = Err(RunError::TestRuntime(format!(
@@ -165,95 +233,25 @@ impl<'a> StateData for LinuxData<'a> {
     }
 
     fn stop(&mut self) -> Result<TestState, RunError> {
-        let mut actions = Vec::new();
-        let mut pcs = HashSet::new();
+        let mut resumes = Vec::new();
         let mut result = Ok(TestState::wait_state());
         let pending = self.wait_queue.clone();
         self.wait_queue.clear();
+        self.core.start_batch();
         for status in &pending {
-            let state = match status {
-                WaitStatus::PtraceEvent(c, s, e) => match self.handle_ptrace_event(*c, *s, *e) {
-                    Ok(s) => Ok(s),
-                    Err(e) => Err(RunError::TestRuntime(format!(
-                        "Error occurred when handling ptrace event: {}",
-                        e
-                    ))),
-                },
-                WaitStatus::Stopped(c, Signal::SIGTRAP) => {
-                    self.current = *c;
-                    match self.collect_coverage_data(&mut pcs) {
-                        Ok(s) => Ok(s),
-                        Err(e) => Err(RunError::TestRuntime(format!(
-                            "Error when collecting coverage: {}",
-                            e
-                        ))),
-                    }
-                }
-                WaitStatus::Stopped(child, Signal::SIGSTOP) => Ok((
-                    TestState::wait_state(),
-                    TracerAction::Continue(child.into()),
-                )),
-                WaitStatus::Stopped(_, Signal::SIGSEGV) => Err(RunError::TestRuntime(
-                    "A segfault occurred while executing tests".to_string(),
-                )),
-                WaitStatus::Stopped(child, Signal::SIGILL) => {
-                    let pc = current_instruction_pointer(*child).unwrap_or_else(|_| 1) - 1;
-                    trace!("SIGILL raised. Child program counter is: 0x{:x}", pc);
-                    Err(RunError::TestRuntime(format!(
-                        "Error running test - SIGILL raised in {}",
-                        child
-                    )))
-                }
-                WaitStatus::Stopped(c, s) => {
-                    let sig = if self.config.forward_signals {
-                        Some(*s)
-                    } else {
-                        None
-                    };
-                    let info = ProcessInfo::new(*c, sig);
-                    Ok((TestState::wait_state(), TracerAction::TryContinue(info)))
-                }
-                WaitStatus::Signaled(c, s, f) => {
-                    if let Ok(s) = self.handle_signaled(c, s, *f) {
-                        Ok(s)
-                    } else {
-                        Err(RunError::TestRuntime(
-                            "Attempting to handle tarpaulin being signaled".to_string(),
-                        ))
-                    }
-                }
-                WaitStatus::Exited(child, ec) => {
-                    for ref mut value in self.breakpoints.values_mut() {
-                        value.thread_killed(*child);
-                    }
-                    trace!("Exited {:?} parent {:?}", child, self.parent);
-                    if child == &self.parent {
-                        Ok((TestState::End(*ec), TracerAction::Nothing))
-                    } else {
-                        // Process may have already been destroyed. This is just incase
-                        Ok((
-                            TestState::wait_state(),
-                            TracerAction::TryContinue(self.parent.into()),
-                        ))
-                    }
-                }
-                _ => Err(RunError::TestRuntime(
-                    "An unexpected signal has been caught by tarpaulin!".to_string(),
-                )),
-            };
+            let event = self.event(status);
+            let state = self
+                .core
+                .handle_event(event)
+                .and_then(|(state, actions)| self.apply(actions, &mut resumes).map(|_| state));
             match state {
-                Ok((TestState::Waiting { .. }, action)) => {
-                    actions.push(action);
-                }
-                Ok((state, action)) => {
-                    result = Ok(state);
-                    actions.push(action);
-                }
+                Ok(TestState::Waiting { .. }) => {}
+                Ok(state) => result = Ok(state),
                 Err(e) => result = Err(e),
             }
         }
         let mut continued = false;
-        for a in &actions {
+        for a in &resumes {
             match a {
                 TracerAction::TryContinue(t) => {
                     continued = true;
@@ -271,137 +269,246 @@ impl<'a> StateData for LinuxData<'a> {
                     continued = true;
                     detach_child(t.pid)?;
                 }
-                _ => {}
             }
         }
         if !continued {
             trace!("No action suggested to continue tracee. Attempting a continue");
-            let _ = continue_exec(self.parent, None);
+            let _ = continue_exec(self.core.parent, None);
         }
         result
     }
 }
 
 impl<'a> LinuxData<'a> {
-    pub fn new(traces: &'a mut TraceMap, config: &'a Config) -> LinuxData<'a> {
+    pub fn new(test: Pid, traces: &'a mut TraceMap, config: &'a Config) -> LinuxData<'a> {
         LinuxData {
             wait_queue: Vec::new(),
             current: Pid::from_raw(0),
-            parent: Pid::from_raw(0),
-            breakpoints: HashMap::new(),
-            traces,
+            breakpoints: Breakpoints::default(),
+            traces: traces.all_traces_mut(),
             config,
-            thread_count: 0,
+            start_time: Instant::now(),
+            core: TracerCore::new(test, config.count, config.forward_signals),
         }
     }
 
-    fn handle_ptrace_event(
-        &mut self,
-        child: Pid,
-        sig: Signal,
-        event: i32,
-    ) -> Result<(TestState, TracerAction<ProcessInfo>), RunError> {
-        use nix::libc::*;
+    /// Traces the threads of the test which weren't created under tracing,
+    /// such as ones spawned by a `#[ctor]` function or lazy static before the
+    /// initial stop. Threads created after it are traced through
+    /// `PTRACE_O_TRACECLONE`, but a thread that isn't traced is killed by the
+    /// first breakpoint it hits. The untraced threads can spawn more while
+    /// they're being attached so this repeats until no new threads appear.
+    fn attach_existing_threads(&mut self) {
+        let mut attempted = HashSet::new();
+        loop {
+            let new_threads = thread_ids(self.core.parent)
+                .into_iter()
+                .filter(|t| !self.core.processes.contains(t) && !attempted.contains(t))
+                .collect::<Vec<_>>();
+            if new_threads.is_empty() {
+                break;
+            }
+            for tid in new_threads {
+                attempted.insert(tid);
+                match seize_thread(tid) {
+                    Ok(()) => {
+                        trace!("Attached to existing thread {}", tid);
+                        self.core.thread_count += 1;
+                        self.core.processes.insert(tid);
+                    }
+                    // The thread may have exited since it was listed
+                    Err(e) => debug!("Couldn't attach to thread {}: {}", tid, e),
+                }
+            }
+        }
+    }
 
-        if sig == Signal::SIGTRAP {
-            match event {
-                PTRACE_EVENT_CLONE => match get_event_data(child) {
-                    Ok(t) => {
-                        trace!("New thread spawned {}", t);
-                        self.thread_count += 1;
-                        Ok((
-                            TestState::wait_state(),
-                            TracerAction::Continue(child.into()),
-                        ))
+    /// Inserts a breakpoint at every trace address in the process, replacing
+    /// any existing breakpoints
+    fn add_breakpoints(&mut self, pid: Pid) -> Result<(), RunError> {
+        self.breakpoints = Breakpoints::default();
+        self.core.breakpoints.clear();
+        let mut breakpoints = vec![];
+        for (i, trace) in self.traces.iter().enumerate() {
+            for addr in &trace.address {
+                match Breakpoint::new(pid, *addr, i as u32) {
+                    Ok(bp) => {
+                        breakpoints.push(bp);
+                        self.core.breakpoints.insert(*addr);
+                        if self.config.dump_breakpoints.is_some() {
+                            breakpoint_dump::inserted(*addr);
+                        }
                     }
-                    Err(e) => {
-                        trace!("Error in clone event {:?}", e);
-                        Err(RunError::TestRuntime(
-                            "Error occurred upon test executable thread creation".to_string(),
-                        ))
+                    Err(e) if e == NixErr::Sys(Errno::EIO) => {
+                        warn_if_sanitized(*addr);
+                        return Err(RunError::TestRuntime(
+                            "ERROR: Tarpaulin cannot find code addresses \
+                             check that pie is disabled for your linker. \
+                             If linking with gcc try adding -C link-args=-no-pie \
+                             to your rust flags"
+                                .to_string(),
+                        ));
                     }
-                },
-                PTRACE_EVENT_FORK | PTRACE_EVENT_VFORK => {
-                    trace!("Caught fork event");
-                    Ok((
-                        TestState::wait_state(),
-                        TracerAction::Continue(child.into()),
-                    ))
+                    Err(NixErr::UnsupportedOperation) => {
+                        debug!(
+                            "Instrumentation address clash, ignoring 0x{:x} for line {}{}",
+                            addr,
+                            trace.line,
+                            in_function(trace.fn_name.as_deref())
+                        );
+                    }
+                    Err(_) => {
+                        warn_if_sanitized(*addr);
+                        return Err(RunError::TestRuntime(
+                            "Failed to instrument test executable".to_string(),
+                        ));
+                    }
+                }
+            }
+        }
+        self.breakpoints = Breakpoints::new(breakpoints);
+        stats::add_breakpoints(self.breakpoints.len());
+        Ok(())
+    }
+
+    /// Reads what's needed to handle the wait status from the tracee
+    fn event(&mut self, status: &WaitStatus) -> Event {
+        use nix::libc::{
+            PTRACE_EVENT_CLONE, PTRACE_EVENT_EXEC, PTRACE_EVENT_EXIT, PTRACE_EVENT_FORK,
+            PTRACE_EVENT_STOP, PTRACE_EVENT_VFORK,
+        };
+
+        match *status {
+            WaitStatus::PtraceEvent(pid, Signal::SIGTRAP, event) => match event {
+                PTRACE_EVENT_CLONE => {
+                    let thread = match get_event_data(pid) {
+                        Ok(t) => Some(Pid::from_raw(t as _)),
+                        Err(e) => {
+                            trace!("Error in clone event {:?}", e);
+                            None
+                        }
+                    };
+                    Event::Clone { pid, thread }
                 }
-                PTRACE_EVENT_EXEC => {
-                    trace!("Child execed other process - detaching ptrace");
-                    Ok((TestState::wait_state(), TracerAction::Detach(child.into())))
+                PTRACE_EVENT_FORK | PTRACE_EVENT_VFORK => {
+                    let child = match get_event_data(pid) {
+                        Ok(c) => Some(Pid::from_raw(c as _)),
+                        Err(e) => {
+                            trace!("Error in fork event {:?}", e);
+                            None
+                        }
+                    };
+                    Event::Fork {
+                        pid,
+                        child,
+                        vfork: event == PTRACE_EVENT_VFORK,
+                    }
                 }
-                PTRACE_EVENT_EXIT => {
-                    trace!("Child exiting");
-                    self.thread_count -= 1;
-                    Ok((
-                        TestState::wait_state(),
-                        TracerAction::TryContinue(child.into()),
-                    ))
+                PTRACE_EVENT_EXEC => Event::Exec {
+                    pid,
+                    image: executable_image(pid),
+                    path: executable_path(pid),
+                },
+                PTRACE_EVENT_EXIT => Event::Exiting { pid },
+                event => Event::UnknownPtraceEvent {
+                    pid,
+                    signal: Signal::SIGTRAP,
+                    event,
+                },
+            },
+            // Group stops of threads attached with `seize_thread`
+            WaitStatus::PtraceEvent(pid, signal, PTRACE_EVENT_STOP) => {
+                Event::Stopped { pid, signal }
+            }
+            WaitStatus::PtraceEvent(pid, signal, event) => {
+                Event::UnknownPtraceEvent { pid, signal, event }
+            }
+            WaitStatus::Stopped(pid, Signal::SIGTRAP) => {
+                self.current = pid;
+                let pc = current_instruction_pointer(pid).ok().map(|pc| pc as u64);
+                Event::Trap { pid, pc }
+            }
+            WaitStatus::Stopped(pid, signal) => {
+                if signal == Signal::SIGILL {
+                    let pc = current_instruction_pointer(pid).unwrap_or_else(|_| 1) - 1;
+                    trace!("SIGILL raised. Child program counter is: 0x{:x}", pc);
                 }
-                _ => Err(RunError::TestRuntime(format!(
-                    "Unrecognised ptrace event {}",
-                    event
-                ))),
+                Event::Stopped { pid, signal }
             }
-        } else {
-            trace!("Unexpected signal with ptrace event {}", event);
-            trace!("Signal: {:?}", sig);
-            Err(RunError::TestRuntime("Unexpected signal".to_string()))
+            WaitStatus::Signaled(pid, signal, core_dumped) => Event::Signaled {
+                pid,
+                signal,
+                core_dumped,
+            },
+            WaitStatus::Exited(pid, code) => Event::Exited { pid, code },
+            _ => Event::Unexpected,
         }
     }
 
-    fn collect_coverage_data(
+    /// Carries out the actions other than resuming threads, which are added
+    /// to `resumes` to be done once every event has been handled
+    fn apply(
         &mut self,
-        visited_pcs: &mut HashSet<u64>,
-    ) -> Result<UpdateContext, RunError> {
-        let mut action = None;
-        if let Ok(rip) = current_instruction_pointer(self.current) {
-            let rip = (rip - 1) as u64;
-            trace!("Hit address 0x{:x}", rip);
-            if self.breakpoints.contains_key(&rip) {
-                let bp = &mut self.breakpoints.get_mut(&rip).unwrap();
-                let updated = if visited_pcs.contains(&rip) {
-                    let _ = bp.jump_to(self.current);
-                    (true, TracerAction::Continue(self.current.into()))
-                } else {
-                    let enable = self.config.count;
-                    // Don't reenable if multithreaded as can't yet sort out segfault issue
-                    if let Ok(x) = bp.process(self.current, enable) {
-                        x
-                    } else {
-                        // So failed to process a breakpoint.. Still continue to avoid
-                        // stalling
-                        (false, TracerAction::Continue(self.current.into()))
+        actions: Vec<Action>,
+        resumes: &mut Vec<TracerAction<ProcessInfo>>,
+    ) -> Result<(), RunError> {
+        for action in actions {
+            match action {
+                Action::Resume(a) => resumes.push(a),
+                Action::StepOver { pid, addr } => {
+                    let stepped = match self.breakpoints.get(addr) {
+                        Some(bp) => bp.step_over(pid).is_ok(),
+                        None => false,
+                    };
+                    if !stepped {
+                        // The rest of the actions assumed the step over worked
+                        let (_, actions) = self
+                            .core
+                            .handle_event(Event::StepOverFailed { pid, addr })?;
+                        return self.apply(actions, resumes);
                     }
-                };
-                if updated.0 {
-                    if let Some(ref mut t) = self.traces.get_trace_mut(rip) {
-                        if let CoverageStat::Line(ref mut x) = t.stats {
-                            trace!("Incrementing hit count for trace");
-                            *x += 1;
-                        }
+                }
+                Action::Rewind { pid, addr } => {
+                    if let Some(bp) = self.breakpoints.get(addr) {
+                        let _ = bp.jump_to(pid);
+                    }
+                }
+                Action::Enable { pid, addr } => {
+                    if let Some(bp) = self.breakpoints.get(addr) {
+                        let _ = bp.enable(pid);
                     }
                 }
-                action = Some(updated.1);
+                Action::Hit { addr } => self.hit(addr),
+                Action::Reinstrument { pid } => self.add_breakpoints(pid).map_err(|e| {
+                    RunError::TestRuntime(format!(
+                        "Error occurred when handling ptrace event: {}",
+                        e
+                    ))
+                })?,
+                // The core keeps the only per thread state
+                Action::Forget { .. } => {}
             }
         }
-        let action = action.unwrap_or_else(|| TracerAction::Continue(self.current.into()));
-        Ok((TestState::wait_state(), action))
+        Ok(())
     }
 
-    fn handle_signaled(
-        &mut self,
-        pid: &Pid,
-        sig: &Signal,
-        flag: bool,
-    ) -> Result<UpdateContext, RunError> {
-        match (sig, flag) {
-            (Signal::SIGTRAP, true) => {
-                Ok((TestState::wait_state(), TracerAction::Continue(pid.into())))
+    fn hit(&mut self, addr: u64) {
+        if self.config.dump_breakpoints.is_some() {
+            breakpoint_dump::hit(addr);
+        }
+        let trace = self.breakpoints.get(addr).map(|bp| bp.trace as usize);
+        let traces = &mut self.traces;
+        if let Some(t) = trace.and_then(|i| traces.get_mut(i)) {
+            // Only the name is borrowed, the label is made if it's logged
+            let function = t.fn_name.as_deref();
+            if let CoverageStat::Line(ref mut x) = t.stats {
+                trace!("Hit 0x{:x}, line {}{}", addr, t.line, in_function(function));
+                *x += 1;
+            }
+            if self.config.profile_hits {
+                let millis = self.start_time.elapsed().as_millis() as u64;
+                HitSpan::record(&mut t.hit_span, millis);
             }
-            _ => Err(RunError::StateMachine("Unexpected stop".to_string())),
         }
     }
 }
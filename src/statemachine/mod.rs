@@ -6,6 +6,8 @@ use crate::traces::*;
 use log::error;
 use std::time::Instant;
 
+#[cfg(target_os = "linux")]
+pub mod events;
 #[cfg(target_os = "linux")]
 pub mod linux;
 
@@ -22,8 +24,30 @@ pub enum TestState {
     Waiting { start_time: Instant },
     /// Test process stopped, check coverage
     Stopped,
-    /// Test exited normally. Includes the exit code of the test executable.
-    End(i32),
+    /// Test ended, either exiting or killed by a signal
+    End(TestExit),
+}
+
+/// How the test process ended. Either way the coverage collected before the
+/// end is kept, only how the result is classified differs.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TestExit {
+    /// Exited with the exit code
+    Code(i32),
+    /// Killed by the signal, for example `SIGABRT` from `abort()` or a
+    /// sanitizer
+    Signal { signal: i32, core_dumped: bool },
+}
+
+impl TestExit {
+    /// The exit code, a signal is reported as 128 plus the signal number like
+    /// a shell does
+    pub fn code(self) -> i32 {
+        match self {
+            TestExit::Code(code) => code,
+            TestExit::Signal { signal, .. } => 128 + signal,
+        }
+    }
 }
 
 /// This enum represents a generic action for the process tracing API to take
@@ -37,42 +61,6 @@ pub enum TracerAction<T> {
     Continue(T),
     Step(T),
     Detach(T),
-    Nothing,
-}
-
-impl<T> TracerAction<T> {
-    pub fn is_detach(&self) -> bool {
-        if let TracerAction::Detach(_) = self {
-            true
-        } else {
-            false
-        }
-    }
-
-    pub fn is_continue(&self) -> bool {
-        if let TracerAction::Continue(_) = self {
-            true
-        } else {
-            false
-        }
-    }
-
-    pub fn is_step(&self) -> bool {
-        if let TracerAction::Step(_) = self {
-            true
-        } else {
-            false
-        }
-    }
-
-    pub fn get_data(&self) -> Option<&T> {
-        match self {
-            TracerAction::Continue(d) => Some(d),
-            TracerAction::Step(d) => Some(d),
-            TracerAction::Detach(d) => Some(d),
-            _ => None,
-        }
-    }
 }
 
 /// Tracing a process on an OS will have platform specific code.
@@ -95,14 +83,6 @@ pub trait StateData {
 }
 
 impl TestState {
-    /// Convenience function used to check if the test has finished or errored
-    pub fn is_finished(self) -> bool {
-        match self {
-            TestState::End(_) => true,
-            _ => false,
-        }
-    }
-
     /// Convenience function for creating start states
     fn start_state() -> TestState {
         TestState::Start {
@@ -149,7 +129,7 @@ impl TestState {
                 if config.verbose {
                     error!("Tarpaulin error: unhandled test state");
                 }
-                Ok(TestState::End(-1))
+                Ok(TestState::End(TestExit::Code(-1)))
             }
         }
     }
@@ -0,0 +1,226 @@
+//! Usage statistics of a run for build engineers, `--stats-file PATH`. The
+//! time spent in each phase, how much was built and traced and how the run
+//! ended are written as a single JSON document. It's only ever written to the
+//! local file, nothing is sent anywhere. The file is written when the run
+//! ends however it ends, including failures and panics, so the statistics
+//! are best effort and only cover what happened before that point.
+use crate::errors::{write_error, RunError};
+use crate::trace_cache;
+use crate::traces::TraceMap;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Version of the file's schema, increased when fields are removed or their
+/// meaning changes
+pub const SCHEMA_VERSION: u32 = 1;
+
+lazy_static! {
+    static ref STATS: Mutex<RunStats> = Mutex::new(RunStats::default());
+}
+
+/// Contents of the stats file
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct RunStats {
+    pub schema_version: u32,
+    /// Seconds spent in each phase, summed over the configs and binaries
+    pub phases: BTreeMap<String, f64>,
+    /// Test binaries built
+    pub binaries: usize,
+    /// Breakpoints placed in the traced binaries
+    pub breakpoints: usize,
+    pub trace_map: TraceMapStats,
+    pub cache: CacheStats,
+    /// Peak resident set size of tarpaulin in KiB, not including the tests
+    pub peak_rss_kib: Option<u64>,
+    /// How the run ended, one of success, tests-failed, below-threshold,
+    /// cancelled, error or panic
+    pub exit: String,
+    /// The error the run ended with if any
+    pub error: Option<String>,
+}
+
+/// Size of the merged results
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TraceMapStats {
+    pub files: usize,
+    pub lines: usize,
+    pub addresses: usize,
+}
+
+/// Use of the cache of the traces read from the test binaries
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+fn with_stats<F: FnOnce(&mut RunStats)>(f: F) {
+    // Statistics are best effort so a poisoned lock is still used
+    let mut stats = STATS.lock().unwrap_or_else(|e| e.into_inner());
+    f(&mut stats);
+}
+
+/// Adds the time until the guard is dropped to a phase
+pub struct Phase {
+    name: &'static str,
+    start: Instant,
+}
+
+impl Phase {
+    pub fn start(name: &'static str) -> Self {
+        Phase {
+            name,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Phase {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        with_stats(|s| *s.phases.entry(self.name.to_string()).or_default() += elapsed);
+    }
+}
+
+pub fn add_binaries(count: usize) {
+    with_stats(|s| s.binaries += count);
+}
+
+pub fn add_breakpoints(count: usize) {
+    with_stats(|s| s.breakpoints += count);
+}
+
+/// Records the size of the results, called once they're merged
+pub fn set_trace_map(traces: &TraceMap) {
+    let stats = TraceMapStats {
+        files: traces.files().len(),
+        lines: traces.total_coverable(),
+        addresses: traces.all_traces().iter().map(|t| t.address.len()).sum(),
+    };
+    with_stats(|s| s.trace_map = stats);
+}
+
+/// How a run ended
+fn exit_classification(result: &Result<(), RunError>) -> &'static str {
+    match result {
+        Ok(()) => "success",
+        Err(RunError::TestFailed) => "tests-failed",
+        Err(RunError::BelowThreshold(..))
+        | Err(RunError::Ratchet(_))
+        | Err(RunError::NewFilesBelowThreshold(..))
+        | Err(RunError::OnlyIgnored(_)) => "below-threshold",
+        Err(RunError::Cancelled(_)) => "cancelled",
+        Err(_) => "error",
+    }
+}
+
+/// Peak resident set size from the `VmHWM` line of `/proc/self/status`
+fn peak_rss_kib(status: &str) -> Option<u64> {
+    status
+        .lines()
+        .find(|l| l.starts_with("VmHWM:"))
+        .and_then(|l| l.split_whitespace().nth(1))
+        .and_then(|kib| kib.parse().ok())
+}
+
+/// The statistics collected so far, without how the run ended
+pub fn snapshot() -> RunStats {
+    let mut stats = STATS.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    stats.schema_version = SCHEMA_VERSION;
+    stats.cache = CacheStats {
+        hits: trace_cache::hits(),
+        misses: trace_cache::misses(),
+    };
+    stats.peak_rss_kib = fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|s| peak_rss_kib(&s));
+    stats
+}
+
+/// Writes the stats file when dropped, the run's result should be given to
+/// `finish` first otherwise the run is recorded as having panicked
+pub struct StatsWriter {
+    path: Option<PathBuf>,
+    exit: &'static str,
+    error: Option<String>,
+}
+
+impl StatsWriter {
+    /// Starts collecting statistics, nothing is written if `path` is `None`
+    pub fn new(path: Option<PathBuf>) -> Self {
+        if path.is_some() {
+            with_stats(|s| *s = RunStats::default());
+        }
+        StatsWriter {
+            path,
+            exit: "panic",
+            error: None,
+        }
+    }
+
+    pub fn finish(&mut self, result: &Result<(), RunError>) {
+        self.exit = exit_classification(result);
+        self.error = result.as_ref().err().map(ToString::to_string);
+    }
+
+    fn write(&self, path: &Path) -> io::Result<()> {
+        let mut stats = snapshot();
+        stats.exit = self.exit.to_string();
+        stats.error = self.error.clone();
+        let mut file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut file, &stats)?;
+        file.flush()
+    }
+}
+
+impl Drop for StatsWriter {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.path {
+            match self.write(path) {
+                Ok(()) => info!("Run statistics written to {}", path.display()),
+                Err(e) => warn!("Failed to save run statistics: {}", write_error(path, &e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_peak_rss() {
+        let status = "Name:\tcargo-tarpaulin\nVmPeak:\t  200000 kB\nVmHWM:\t   51234 kB\nVmRSS:\t   40000 kB\n";
+        assert_eq!(peak_rss_kib(status), Some(51234));
+        assert_eq!(peak_rss_kib("Name:\tcargo-tarpaulin\n"), None);
+    }
+
+    #[test]
+    fn exits_classified() {
+        assert_eq!(exit_classification(&Ok(())), "success");
+        assert_eq!(
+            exit_classification(&Err(RunError::TestFailed)),
+            "tests-failed"
+        );
+        assert_eq!(
+            exit_classification(&Err(RunError::BelowThreshold(50.0, 80.0))),
+            "below-threshold"
+        );
+        assert_eq!(
+            exit_classification(&Err(RunError::NewFilesBelowThreshold(80.0, String::new()))),
+            "below-threshold"
+        );
+        assert_eq!(
+            exit_classification(&Err(RunError::Cancelled(TraceMap::new()))),
+            "cancelled"
+        );
+        assert_eq!(exit_classification(&Err(RunError::Internal)), "error");
+    }
+}
@@ -1,19 +1,26 @@
+use crate::breakpoint_dump::{AddressOrigin, MappingSource};
 use crate::config::Config;
 use crate::source_analysis::*;
+use crate::stats::Phase;
+use crate::trace_cache::TraceCache;
 use crate::traces::*;
 use gimli::read::Error;
 use gimli::*;
-use log::{debug, error, trace};
+use log::{debug, error, info, trace, warn};
 use memmap::MmapOptions;
 use object::{read::ObjectSection, File as OFile, Object};
 use rustc_demangle::demangle;
+use serde::Serialize;
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
-use std::fs::File;
+use std::fs::{self, File};
 use std::io;
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
-/// Describes a function as `low_pc`, `high_pc` and bool representing `is_test`.
-type FuncDesc = (u64, u64, FunctionType, Option<String>);
+/// Describes a function as `low_pc`, `high_pc`, bool representing `is_test`,
+/// its name and the file index and line it's declared on.
+type FuncDesc = (u64, u64, FunctionType, Option<String>, Option<(u64, u64)>);
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum FunctionType {
@@ -68,8 +75,45 @@ pub struct TracerData {
     pub fn_name: Option<String>,
 }
 
-fn generate_func_desc<R, Offset>(
-    die: &DebuggingInformationEntry<R, Offset>,
+/// Gets the file index and line a function is declared on. Concrete instances
+/// of inlined or generic functions don't carry their declaration so it's read
+/// from the entry their `DW_AT_abstract_origin` refers to.
+fn get_declaration<'abbrev, 'unit, R, Offset>(
+    debug_info: &'unit CompilationUnitHeader<R, Offset>,
+    debug_abbrev: &'abbrev Abbreviations,
+    die: &DebuggingInformationEntry<'abbrev, 'unit, R, Offset>,
+) -> Result<Option<(u64, u64)>>
+where
+    R: Reader<Offset = Offset>,
+    Offset: ReaderOffset,
+{
+    let mut entry = die.clone();
+    // An origin can itself be a concrete instance so a few levels are followed
+    for _ in 0..4 {
+        let decl = (
+            entry.attr_value(DW_AT_decl_file)?,
+            entry.attr_value(DW_AT_decl_line)?,
+        );
+        if let (Some(AttributeValue::FileIndex(f)), Some(AttributeValue::Udata(l))) = decl {
+            return Ok(Some((f, l)));
+        }
+        let origin = match entry.attr_value(DW_AT_abstract_origin)? {
+            Some(AttributeValue::UnitRef(o)) => o,
+            _ => return Ok(None),
+        };
+        let mut origin_cursor = debug_info.entries_at_offset(debug_abbrev, origin)?;
+        entry = match origin_cursor.next_dfs()? {
+            Some((_, e)) => e.clone(),
+            None => return Ok(None),
+        };
+    }
+    Ok(None)
+}
+
+fn generate_func_desc<'abbrev, 'unit, R, Offset>(
+    debug_info: &'unit CompilationUnitHeader<R, Offset>,
+    debug_abbrev: &'abbrev Abbreviations,
+    die: &DebuggingInformationEntry<'abbrev, 'unit, R, Offset>,
     debug_str: &DebugStr<R>,
 ) -> Result<FuncDesc>
 where
@@ -81,6 +125,7 @@ where
     let high = die.attr_value(DW_AT_high_pc)?;
     let linkage = die.attr_value(DW_AT_linkage_name)?;
     let fn_name = die.attr_value(DW_AT_name)?;
+    let decl = get_declaration(debug_info, debug_abbrev, die)?;
 
     let fn_name: Option<String> = match fn_name {
         Some(AttributeValue::DebugStrRef(offset)) => debug_str
@@ -117,7 +162,7 @@ where
             FunctionType::Standard
         };
     }
-    Ok((low, high, func_type, fn_name))
+    Ok((low, high, func_type, fn_name, decl))
 }
 
 /// Finds all function entry points and returns a vector
@@ -138,7 +183,7 @@ where
     while let Ok(Some((_, node))) = cursor.next_dfs() {
         // Function DIE
         if node.tag() == DW_TAG_subprogram {
-            if let Ok(fd) = generate_func_desc(node, debug_str) {
+            if let Ok(fd) = generate_func_desc(debug_info, debug_abbrev, node, debug_str) {
                 result.push(fd);
             }
         }
@@ -146,18 +191,452 @@ where
     result
 }
 
+/// Address range of a `#[track_caller]` shim and the file index and line the
+/// function it calls is declared on
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CallerShim {
+    low: u64,
+    high: u64,
+    decl: (u64, u64),
+}
+
+impl CallerShim {
+    fn contains(&self, address: u64) -> bool {
+        address >= self.low && address < self.low + self.high
+    }
+}
+
+/// Finds the shims rustc generates for `#[track_caller]` functions, such as
+/// when one is used as a function pointer. A shim passes a synthesised caller
+/// location on to the function and its line rows can mix the callee's file
+/// with the caller's lines, so the addresses are put on the callee's
+/// declaration instead. A shim is a concrete instance of a function which does
+/// nothing but call that same function.
+fn get_caller_shims<R, Offset>(
+    debug_info: &CompilationUnitHeader<R, Offset>,
+    debug_abbrev: &Abbreviations,
+    debug_str: &DebugStr<R>,
+) -> Vec<CallerShim>
+where
+    R: Reader<Offset = Offset>,
+    Offset: ReaderOffset,
+{
+    let mut result = vec![];
+    if let Ok(mut tree) = debug_info.entries_tree(debug_abbrev, None) {
+        if let Ok(root) = tree.root() {
+            if let Err(e) =
+                find_caller_shims(debug_info, debug_abbrev, debug_str, root, &mut result)
+            {
+                debug!("Issue reading track_caller shims {}", e);
+            }
+        }
+    }
+    result
+}
+
+fn find_caller_shims<R, Offset>(
+    debug_info: &CompilationUnitHeader<R, Offset>,
+    debug_abbrev: &Abbreviations,
+    debug_str: &DebugStr<R>,
+    node: EntriesTreeNode<R>,
+    result: &mut Vec<CallerShim>,
+) -> Result<()>
+where
+    R: Reader<Offset = Offset>,
+    Offset: ReaderOffset,
+{
+    let entry = node.entry().clone();
+    let mut calls = vec![];
+    let mut only_calls = true;
+    let mut children = node.children();
+    while let Some(child) = children.next()? {
+        let tag = child.entry().tag();
+        if tag == DW_TAG_call_site {
+            calls.push(child.entry().attr_value(DW_AT_call_origin)?);
+        } else if tag == DW_TAG_GNU_call_site {
+            calls.push(child.entry().attr_value(DW_AT_abstract_origin)?);
+        } else if tag != DW_TAG_formal_parameter && tag != DW_TAG_template_type_parameter {
+            only_calls = false;
+        }
+        find_caller_shims(debug_info, debug_abbrev, debug_str, child, result)?;
+    }
+    if entry.tag() != DW_TAG_subprogram {
+        return Ok(());
+    }
+    let origin = match entry.attr_value(DW_AT_abstract_origin)? {
+        Some(AttributeValue::UnitRef(o)) => o,
+        _ => return Ok(()),
+    };
+    let mut origin_cursor = debug_info.entries_at_offset(debug_abbrev, origin)?;
+    let origin_entry = match origin_cursor.next_dfs()? {
+        Some((_, e)) => e.clone(),
+        None => return Ok(()),
+    };
+    let reify_shim =
+        [&entry, &origin_entry]
+            .iter()
+            .any(|e| match e.attr_value(DW_AT_linkage_name) {
+                Ok(Some(AttributeValue::DebugStrRef(offset))) => debug_str
+                    .get_str(offset)
+                    .and_then(|r| r.to_string().map(|s| demangle(s.as_ref()).to_string()))
+                    .map_or(false, |name| name.contains("{shim:reify")),
+                _ => false,
+            });
+    let calls_itself = only_calls
+        && calls.iter().any(|c| match c {
+            Some(AttributeValue::UnitRef(o)) => *o == origin,
+            Some(AttributeValue::DebugInfoRef(o)) => *o == origin.to_debug_info_offset(debug_info),
+            _ => false,
+        });
+    if !(reify_shim || calls_itself) {
+        return Ok(());
+    }
+    let range = (
+        entry.attr_value(DW_AT_low_pc)?,
+        entry.attr_value(DW_AT_high_pc)?,
+    );
+    let decl = (
+        origin_entry.attr_value(DW_AT_decl_file)?,
+        origin_entry.attr_value(DW_AT_decl_line)?,
+    );
+    if let (
+        (Some(AttributeValue::Addr(low)), Some(AttributeValue::Udata(high))),
+        (Some(AttributeValue::FileIndex(file)), Some(AttributeValue::Udata(line))),
+    ) = (range, decl)
+    {
+        if low > 0 {
+            result.push(CallerShim {
+                low,
+                high,
+                decl: (file, line),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Normalises a source path from the DWARF line tables. Code inlined from
+/// another crate in the workspace can name the same file with a different path
+/// prefix so everything is canonicalised before being compared against the
+/// project. Returns `None` if the file isn't a project source file that should
+/// be covered.
+fn canonical_source_path(path: PathBuf, project: &Path) -> Option<PathBuf> {
+    let path = path.canonicalize().unwrap_or(path);
+    // Source in target directory shouldn't be covered as it's either
+    // autogenerated or resulting from the projects Cargo.lock
+    let is_target = if path.is_relative() {
+        path.starts_with("target")
+    } else {
+        path.starts_with(project.join("target"))
+    };
+    let is_hidden = path.iter().any(|x| x.to_string_lossy().starts_with('.'));
+    if !is_target && !is_hidden && path.starts_with(project) && path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Path of a file in a line program's file table as it was compiled
+fn entry_path<R, Offset>(
+    file: &FileEntry<R, Offset>,
+    header: &LineProgramHeader<R, Offset>,
+    debug_strs: &DebugStr<R>,
+    project: &Path,
+) -> Option<PathBuf>
+where
+    R: Reader<Offset = Offset>,
+    Offset: ReaderOffset,
+{
+    let get_string = |x: R| x.to_string().map(|y| y.to_string()).ok();
+    // Relative directories are relative to the unit's compilation directory,
+    // which isn't the project for units of the standard library
+    let mut path = project.to_path_buf();
+    if let Some(dir) = header.directory(0) {
+        if let Some(temp) = dir.string_value(debug_strs).and_then(get_string) {
+            path.push(temp);
+        }
+    }
+    if file.directory_index() != 0 {
+        if let Some(dir) = file.directory(header) {
+            if let Some(temp) = dir.string_value(debug_strs).and_then(get_string) {
+                path.push(temp);
+            }
+        }
+    }
+    path.push(
+        file.path_name()
+            .string_value(debug_strs)
+            .and_then(get_string)?,
+    );
+    Some(path)
+}
+
+/// Path of a file in a line program's file table, `None` if it isn't a
+/// project source file
+fn file_entry_path<R, Offset>(
+    file: &FileEntry<R, Offset>,
+    header: &LineProgramHeader<R, Offset>,
+    debug_strs: &DebugStr<R>,
+    project: &Path,
+) -> Option<PathBuf>
+where
+    R: Reader<Offset = Offset>,
+    Offset: ReaderOffset,
+{
+    canonical_source_path(entry_path(file, header, debug_strs, project)?, project)
+}
+
+/// Finds the project source files for the paths in the line tables. A crate
+/// can be compiled from a copy of its source, such as `target/package` while
+/// packaging or a `patch` pointing at a staging directory, so a file which
+/// isn't a project source file is matched to the analysed file with the same
+/// name and content. Its coverage is only moved if exactly one file matches.
+struct SourcePaths<'a> {
+    project: &'a Path,
+    analysis: &'a HashMap<PathBuf, LineAnalysis>,
+    /// Project file each copy was matched to, `None` if it wasn't matched
+    copies: RefCell<HashMap<PathBuf, Option<PathBuf>>>,
+    hashes: RefCell<HashMap<PathBuf, Option<blake3::Hash>>>,
+}
+
+impl<'a> SourcePaths<'a> {
+    fn new(project: &'a Path, analysis: &'a HashMap<PathBuf, LineAnalysis>) -> Self {
+        Self {
+            project,
+            analysis,
+            copies: RefCell::new(HashMap::new()),
+            hashes: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Project source file of a file table entry, and the copy it was
+    /// compiled from if it wasn't compiled from the file itself
+    fn resolve<R, Offset>(
+        &self,
+        file: &FileEntry<R, Offset>,
+        header: &LineProgramHeader<R, Offset>,
+        debug_strs: &DebugStr<R>,
+    ) -> Option<(PathBuf, Option<PathBuf>)>
+    where
+        R: Reader<Offset = Offset>,
+        Offset: ReaderOffset,
+    {
+        let path = entry_path(file, header, debug_strs, self.project)?;
+        match canonical_source_path(path.clone(), self.project) {
+            Some(p) => Some((p, None)),
+            None => self.original(&path).map(|o| (o, Some(path))),
+        }
+    }
+
+    fn hash(&self, path: &Path) -> Option<blake3::Hash> {
+        *self
+            .hashes
+            .borrow_mut()
+            .entry(path.to_path_buf())
+            .or_insert_with(|| fs::read(path).ok().map(|c| blake3::hash(&c)))
+    }
+
+    /// The analysed file a source file is a copy of, decided once per copy
+    fn original(&self, copy: &Path) -> Option<PathBuf> {
+        if let Some(original) = self.copies.borrow().get(copy) {
+            return original.clone();
+        }
+        let original = self.find_original(copy);
+        self.copies
+            .borrow_mut()
+            .insert(copy.to_path_buf(), original.clone());
+        original
+    }
+
+    fn find_original(&self, copy: &Path) -> Option<PathBuf> {
+        let name = copy.file_name()?;
+        let mut candidates = self
+            .analysis
+            .keys()
+            .filter(|p| p.file_name() == Some(name))
+            .peekable();
+        candidates.peek()?;
+        let hash = self.hash(copy)?;
+        let matches = candidates
+            .filter(|p| self.hash(p) == Some(hash))
+            .collect::<Vec<_>>();
+        match matches.as_slice() {
+            [] => None,
+            [original] => {
+                info!(
+                    "{} was compiled from a copy at {}, its coverage is attributed to it",
+                    original.display(),
+                    copy.display()
+                );
+                Some(original.to_path_buf())
+            }
+            _ => {
+                let names = matches
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>();
+                warn!(
+                    "{} is a copy of {} source files so its coverage isn't attributed to any of them: {}",
+                    copy.display(),
+                    matches.len(),
+                    names.join(", ")
+                );
+                None
+            }
+        }
+    }
+}
+
+/// Address ranges of the functions declared in a struct, enum or union
+/// definition, these are generated by derives on the type
+fn derived_ranges<R, Offset>(
+    entry_points: &[FuncDesc],
+    header: &LineProgramHeader<R, Offset>,
+    debug_strs: &DebugStr<R>,
+    sources: &SourcePaths,
+    analysis: &HashMap<PathBuf, LineAnalysis>,
+) -> Vec<(u64, u64)>
+where
+    R: Reader<Offset = Offset>,
+    Offset: ReaderOffset,
+{
+    let mut paths = HashMap::new();
+    let mut result = vec![];
+    for (low, high, _, _, decl) in entry_points {
+        let (file, line) = match decl {
+            Some(d) if *low > 0 => *d,
+            _ => continue,
+        };
+        let path = paths.entry(file).or_insert_with(|| {
+            header
+                .file(file)
+                .and_then(|f| sources.resolve(f, header, debug_strs))
+                .map(|(path, _)| path)
+        });
+        let derived = path
+            .as_ref()
+            .and_then(|p| analysis.get(p))
+            .map(|a| a.in_type_definition(line as usize))
+            .unwrap_or(false);
+        if derived {
+            result.push((*low, low + high));
+        }
+    }
+    result
+}
+
+/// Marks the functions async runtime attributes generate to wrap the body of
+/// the function they're on, such as the async block `#[tokio::main]` passes to
+/// the runtime. These are declared on the attribute's line and their names
+/// don't say where they came from so they're found by their declaration.
+fn mark_runtime_wrappers<R, Offset>(
+    entry_points: &mut [FuncDesc],
+    header: &LineProgramHeader<R, Offset>,
+    debug_strs: &DebugStr<R>,
+    sources: &SourcePaths,
+    analysis: &HashMap<PathBuf, LineAnalysis>,
+) where
+    R: Reader<Offset = Offset>,
+    Offset: ReaderOffset,
+{
+    let mut paths = HashMap::new();
+    for (_, _, func_type, _, decl) in entry_points.iter_mut() {
+        let (file, line) = match decl {
+            Some(d) => *d,
+            None => continue,
+        };
+        let path = paths.entry(file).or_insert_with(|| {
+            header
+                .file(file)
+                .and_then(|f| sources.resolve(f, header, debug_strs))
+                .map(|(path, _)| path)
+        });
+        let wrapper = path
+            .as_ref()
+            .and_then(|p| analysis.get(p))
+            .map(|a| a.is_runtime_attr(line as usize))
+            .unwrap_or(false);
+        if wrapper {
+            *func_type = FunctionType::Generated;
+        }
+    }
+}
+
+/// Whether the line is in a type definition and has code from a function
+/// derived for it
+fn is_derived(
+    loc: &SourceLocation,
+    data: &[TracerData],
+    derived: &[(u64, u64)],
+    analysis: &HashMap<PathBuf, LineAnalysis>,
+) -> bool {
+    let in_type = analysis
+        .get(&loc.path)
+        .map(|a| a.in_type_definition(loc.line as usize))
+        .unwrap_or(false);
+    in_type
+        && data
+            .iter()
+            .filter_map(|d| d.address)
+            .any(|a| derived.iter().any(|&(low, high)| a >= low && a < high))
+}
+
+/// The function whose code contains the address, the functions are sorted by
+/// their low address
+fn containing_function(functions: &[FuncDesc], address: u64) -> Option<&FuncDesc> {
+    let index = match functions.binary_search_by_key(&address, |f| f.0) {
+        Ok(i) => i,
+        Err(0) => return None,
+        Err(i) => i - 1,
+    };
+    functions
+        .get(index)
+        .filter(|(low, high, ..)| *low > 0 && address < low + high)
+}
+
+/// Where the line of an address came from for `--dump-breakpoints`. Rows in a
+/// function declared in a different file to the row's are from code inlined
+/// into it.
+fn address_origin(
+    row: &LineRow,
+    functions: &[FuncDesc],
+    shim: bool,
+    copy: Option<&PathBuf>,
+) -> AddressOrigin {
+    let address = row.address();
+    let function = containing_function(functions, address);
+    let inlined = function
+        .and_then(|(_, _, _, _, decl)| *decl)
+        .map_or(false, |(file, _)| file != row.file_index());
+    AddressOrigin {
+        function: function.and_then(|(_, _, _, name, _)| name.clone()),
+        source: if shim {
+            MappingSource::CallerShim
+        } else if inlined {
+            MappingSource::Inlined
+        } else {
+            MappingSource::LineTable
+        },
+        compiled_from: copy.cloned(),
+    }
+}
+
 fn get_addresses_from_program<R, Offset>(
     prog: IncompleteLineProgram<R>,
     debug_strs: &DebugStr<R>,
     entries: &Vec<(u64, LineType, &Option<String>)>,
-    project: &Path,
+    sources: &SourcePaths,
     result: &mut HashMap<SourceLocation, Vec<TracerData>>,
+    functions: &[FuncDesc],
+    shims: &[CallerShim],
+    function_lines: &mut HashMap<SourceLocation, Function>,
+    mut origins: Option<&mut HashMap<u64, AddressOrigin>>,
 ) -> Result<()>
 where
     R: Reader<Offset = Offset>,
     Offset: ReaderOffset,
 {
-    let get_string = |x: R| x.to_string().map(|y| y.to_string()).ok();
     let (cprog, seq) = prog.sequences()?;
     for s in seq {
         let mut sm = cprog.resume_from(&s);
@@ -166,64 +645,69 @@ where
             if !ln_row.is_stmt() || ln_row.line().is_none() {
                 continue;
             }
-            if let Some(file) = ln_row.file(header) {
-                let mut path = project.to_path_buf();
-                if let Some(dir) = file.directory(header) {
-                    if let Some(temp) = dir.string_value(debug_strs).and_then(get_string) {
-                        path.push(temp);
+            let address = ln_row.address();
+            let shim = shims.iter().find(|s| s.contains(address));
+            let (file, line) = match shim {
+                Some(shim) => (header.file(shim.decl.0), shim.decl.1),
+                None => (ln_row.file(header), ln_row.line().unwrap()),
+            };
+            if let Some(file) = file {
+                let (path, copy) = match sources.resolve(file, header, debug_strs) {
+                    Some(p) => p,
+                    None => continue,
+                };
+                let (desc, fn_name) = entries
+                    .iter()
+                    .filter(|&&(addr, _, _)| addr == address)
+                    .map(|&(_, t, fn_name)| (t, fn_name.to_owned()))
+                    .nth(0)
+                    .unwrap_or((LineType::Unknown, None));
+                // Lines of a function in the file it's declared in give its
+                // range, closures are counted as part of their function
+                let containing = containing_function(functions, address);
+                let function = containing.filter(|_| shim.is_none());
+                if let Some((_, _, _, Some(name), Some((file, decl)))) = function {
+                    if *file == ln_row.file_index() && !name.starts_with('{') {
+                        let key = SourceLocation {
+                            path: path.clone(),
+                            line: *decl,
+                        };
+                        function_lines
+                            .entry(key)
+                            .and_modify(|f| {
+                                f.start = f.start.min(line);
+                                f.end = f.end.max(line);
+                            })
+                            .or_insert_with(|| Function {
+                                name: name.clone(),
+                                start: line,
+                                end: line,
+                            });
                     }
                 }
-
-                if let Ok(p) = path.canonicalize() {
-                    path = p;
+                trace!(
+                    "{}:{} at 0x{:x} in {}",
+                    path.display(),
+                    line,
+                    address,
+                    containing
+                        .and_then(|f| f.3.as_deref())
+                        .unwrap_or("unknown function")
+                );
+                let loc = SourceLocation { path, line };
+                if let Some(ref mut origins) = origins {
+                    origins.entry(address).or_insert_with(|| {
+                        address_origin(&ln_row, functions, shim.is_some(), copy.as_ref())
+                    });
                 }
-                // Fix relative paths and determine if in target directory
-                // Source in target directory shouldn't be covered as it's either
-                // autogenerated or resulting from the projects Cargo.lock
-                let is_target = if path.is_relative() {
-                    path.starts_with("target")
-                } else {
-                    path.starts_with(project.join("target"))
-                };
-                let is_hidden = path
-                    .into_iter()
-                    .any(|x| x.to_string_lossy().starts_with("."));
-
-                // Source is part of project so we cover it.
-                if !is_target && !is_hidden && path.starts_with(project) {
-                    if let Some(file) = ln_row.file(header) {
-                        let line = ln_row.line().unwrap();
-                        let file = file.path_name();
-                        if let Some(file) = file.string_value(debug_strs).and_then(get_string) {
-                            path.push(file);
-                            if !path.is_file() {
-                                // Not really a source file!
-                                continue;
-                            }
-                            let address = ln_row.address();
-                            let (desc, fn_name) = entries
-                                .iter()
-                                .filter(|&&(addr, _, _)| addr == address)
-                                .map(|&(_, t, fn_name)| (t, fn_name.to_owned()))
-                                .nth(0)
-                                .unwrap_or((LineType::Unknown, None));
-                            let loc = SourceLocation { path, line };
-                            if desc != LineType::TestMain {
-                                let trace = TracerData {
-                                    address: Some(address),
-                                    trace_type: desc,
-                                    length: 1,
-                                    fn_name,
-                                };
-                                if result.contains_key(&loc) {
-                                    let x = result.get_mut(&loc).unwrap();
-                                    x.push(trace);
-                                } else {
-                                    result.insert(loc, vec![trace]);
-                                }
-                            }
-                        }
-                    }
+                if desc != LineType::TestMain {
+                    let trace = TracerData {
+                        address: Some(address),
+                        trace_type: desc,
+                        length: 1,
+                        fn_name,
+                    };
+                    result.entry(loc).or_default().push(trace);
                 }
             }
         }
@@ -237,12 +721,15 @@ fn get_line_addresses(
     obj: &OFile,
     analysis: &HashMap<PathBuf, LineAnalysis>,
     config: &Config,
-) -> Result<TraceMap> {
+    mut origins: Option<&mut HashMap<u64, AddressOrigin>>,
+) -> Result<(TraceMap, SourceDigests)> {
     let io_err = |e| {
         error!("Io error parsing section: {}", e);
         Error::Io
     };
     let mut result = TraceMap::new();
+    let mut digests = SourceDigests::new();
+    let mut lines: HashMap<SourceLocation, Vec<TracerData>> = HashMap::new();
     let debug_info = obj.section_by_name(".debug_info").ok_or(Error::Io)?;
     let debug_info = DebugInfo::new(debug_info.data().map_err(io_err)?, endian);
     let debug_abbrev = obj.section_by_name(".debug_abbrev").ok_or(Error::Io)?;
@@ -251,6 +738,7 @@ fn get_line_addresses(
     let debug_strings = DebugStr::new(debug_strings.data().map_err(io_err)?, endian);
     let debug_line = obj.section_by_name(".debug_line").ok_or(Error::Io)?;
     let debug_line = DebugLine::new(debug_line.data().map_err(io_err)?, endian);
+    let sources = SourcePaths::new(project, analysis);
 
     let mut iter = debug_info.units();
     while let Ok(Some(cu)) = iter.next() {
@@ -259,40 +747,80 @@ fn get_line_addresses(
             Ok(a) => a,
             _ => continue,
         };
-        let entry_points = get_entry_points(&cu, &abbr, &debug_strings);
-        let entries = entry_points
-            .iter()
-            .map(|(a, b, c, fn_name)| match c {
-                FunctionType::Test => (*a, LineType::TestEntry(*b), fn_name),
-                FunctionType::Standard => (*a, LineType::FunctionEntry(*b), fn_name),
-                FunctionType::Generated => (*a, LineType::TestMain, fn_name),
-            })
-            .collect::<Vec<_>>();
+        let mut entry_points = get_entry_points(&cu, &abbr, &debug_strings);
+        entry_points.sort_by_key(|f| f.0);
+        let shims = get_caller_shims(&cu, &abbr, &debug_strings);
 
         if let Ok(Some((_, root))) = cu.entries(&abbr).next_dfs() {
             let offset = match root.attr_value(DW_AT_stmt_list) {
                 Ok(Some(AttributeValue::DebugLineRef(o))) => o,
                 _ => continue,
             };
-            let prog = debug_line.program(offset, addr_size, None, None)?;
+            let comp_dir = root
+                .attr_value(DW_AT_comp_dir)
+                .ok()
+                .flatten()
+                .and_then(|d| d.string_value(&debug_strings));
+            let prog = debug_line.program(offset, addr_size, comp_dir, None)?;
+            add_source_digests(prog.header(), &debug_strings, project, &mut digests);
+            mark_runtime_wrappers(
+                &mut entry_points,
+                prog.header(),
+                &debug_strings,
+                &sources,
+                analysis,
+            );
+            let entries = entry_points
+                .iter()
+                .map(|(a, b, c, fn_name, _)| match c {
+                    FunctionType::Test => (*a, LineType::TestEntry(*b), fn_name),
+                    FunctionType::Standard => (*a, LineType::FunctionEntry(*b), fn_name),
+                    FunctionType::Generated => (*a, LineType::TestMain, fn_name),
+                })
+                .collect::<Vec<_>>();
+            let derived = derived_ranges(
+                &entry_points,
+                prog.header(),
+                &debug_strings,
+                &sources,
+                analysis,
+            );
             let mut temp_map: HashMap<SourceLocation, Vec<TracerData>> = HashMap::new();
+            let mut function_lines = HashMap::new();
 
-            if let Err(e) =
-                get_addresses_from_program(prog, &debug_strings, &entries, project, &mut temp_map)
-            {
+            if let Err(e) = get_addresses_from_program(
+                prog,
+                &debug_strings,
+                &entries,
+                &sources,
+                &mut temp_map,
+                &entry_points,
+                &shims,
+                &mut function_lines,
+                origins.as_deref_mut(),
+            ) {
                 debug!("Potential issue reading test addresses {}", e);
             } else {
+                for (k, function) in function_lines {
+                    if !config.excludes_file(&k.path)
+                        && !analysis.should_ignore(k.path.as_ref(), &(k.line as usize))
+                    {
+                        result.add_function(&k.path, k.line, function);
+                    }
+                }
                 // Deduplicate addresses
                 for v in temp_map.values_mut() {
                     v.dedup_by_key(|x| x.address);
                 }
                 let temp_map = temp_map
                     .into_iter()
-                    .filter(|&(ref k, _)| {
-                        !(config.ignore_tests && k.path.starts_with(project.join("tests")))
-                    })
-                    .filter(|&(ref k, _)| !(config.exclude_path(&k.path)))
-                    .filter(|&(ref k, _)| {
+                    .filter(|&(ref k, _)| !(config.excludes_file(&k.path)))
+                    .filter(|&(ref k, ref v)| {
+                        // Derived code is ignored with the rest of the type
+                        // definition, but noted so it can be reported
+                        if !config.include_derives && is_derived(k, v, &derived, analysis) {
+                            result.add_derived(&k.path, k.line);
+                        }
                         !analysis.should_ignore(k.path.as_ref(), &(k.line as usize))
                     })
                     .map(|(k, v)| {
@@ -300,52 +828,58 @@ fn get_line_addresses(
                         let k_n = SourceLocation::from(ret);
                         (k_n, v)
                     })
-                    .collect::<HashMap<SourceLocation, Vec<TracerData>>>();
-
-                let mut tracemap = TraceMap::new();
-                for (k, val) in &temp_map {
-                    let rpath = config.strip_base_dir(&k.path);
-                    let mut address = HashSet::new();
-                    let mut fn_name = None;
-                    for v in val.iter() {
-                        if let Some(a) = v.address {
-                            address.insert(a);
-                            trace!(
-                                "Adding trace at address 0x{:x} in {}:{}",
-                                a,
-                                rpath.display(),
-                                k.line
-                            );
-                        }
-                        if fn_name.is_none() && v.fn_name.is_some() {
-                            fn_name = v.fn_name.clone();
-                        }
-                    }
-                    if address.is_empty() {
-                        trace!(
-                            "Adding trace with no address at {}:{}",
-                            rpath.display(),
-                            k.line
-                        );
-                    }
-                    tracemap.add_trace(
-                        &k.path,
-                        Trace {
-                            line: k.line,
-                            address,
-                            length: 1,
-                            stats: CoverageStat::Line(0),
-                            fn_name,
-                        },
-                    );
+                    .collect::<Vec<_>>();
+                // The same line can be emitted by several compilation units
+                // when it's inlined or monomorphised into other crates, so
+                // every copy is gathered before building a single trace.
+                for (k, v) in temp_map {
+                    lines.entry(k).or_default().extend(v);
                 }
-                result.merge(&tracemap);
             }
         }
     }
 
+    for (k, val) in &lines {
+        let rpath = config.strip_base_dir(&k.path);
+        let mut address = HashSet::new();
+        let mut fn_name = None;
+        for v in val.iter() {
+            if let Some(a) = v.address {
+                address.insert(a);
+                trace!(
+                    "Adding trace at address 0x{:x} in {}:{}",
+                    a,
+                    rpath.display(),
+                    k.line
+                );
+            }
+            if fn_name.is_none() && v.fn_name.is_some() {
+                fn_name = v.fn_name.clone();
+            }
+        }
+        if address.is_empty() {
+            trace!(
+                "Adding trace with no address at {}:{}",
+                rpath.display(),
+                k.line
+            );
+        }
+        result.add_trace(
+            &k.path,
+            Trace {
+                line: k.line,
+                address,
+                length: 1,
+                stats: CoverageStat::Line(0),
+                fn_name,
+                hit_span: None,
+                sampled: false,
+            },
+        );
+    }
+
     for (file, ref line_analysis) in analysis.iter() {
-        if config.exclude_path(file) {
+        if config.excludes_file(file) {
             continue;
         }
         for line in &line_analysis.cover {
@@ -366,11 +900,74 @@ fn get_line_addresses(
                         length: 0,
                         stats: CoverageStat::Line(0),
                         fn_name: None,
+                        hit_span: None,
+                        sampled: false,
                     },
                 );
             }
         }
     }
+    Ok((result, digests))
+}
+
+/// MD5 digests of the project source files recorded in a binary's line
+/// tables. Only DWARF 5 line tables can record them so this is often empty.
+pub type SourceDigests = HashMap<PathBuf, [u8; 16]>;
+
+/// Adds the digests of the project source files in a unit's line table
+fn add_source_digests<R, Offset>(
+    header: &LineProgramHeader<R, Offset>,
+    debug_strs: &DebugStr<R>,
+    project: &Path,
+    digests: &mut SourceDigests,
+) where
+    R: Reader<Offset = Offset>,
+    Offset: ReaderOffset,
+{
+    if !header.file_has_md5() {
+        return;
+    }
+    for file in header.file_names() {
+        if let Some(path) = file_entry_path(file, header, debug_strs, project) {
+            digests.insert(path, *file.md5());
+        }
+    }
+}
+
+/// Whether a source file has changed since the binary was built. The content
+/// is compared if the line tables have its digest, otherwise a file modified
+/// after the binary is assumed to have changed.
+fn is_stale(path: &Path, digest: Option<&[u8; 16]>, built: SystemTime) -> bool {
+    match digest {
+        Some(digest) => fs::read(path)
+            .map(|content| md5::compute(&content).0 != *digest)
+            .unwrap_or(false),
+        None => fs::metadata(path)
+            .and_then(|m| m.modified())
+            .map(|modified| modified > built)
+            .unwrap_or(false),
+    }
+}
+
+/// The given source files which have changed since the test binary was built,
+/// coverage of these files is reported against source the binary wasn't built
+/// from. `digests` are the ones read with the binary's traces, files without
+/// one are compared by modification time.
+pub fn stale_sources(
+    test: &Path,
+    files: &[&PathBuf],
+    digests: &SourceDigests,
+) -> io::Result<Vec<PathBuf>> {
+    let built = fs::metadata(test)?.modified()?;
+    let mut result = files
+        .iter()
+        .filter(|path| {
+            let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+            is_stale(path, digests.get(&canonical), built)
+        })
+        .map(|path| path.to_path_buf())
+        .collect::<Vec<_>>();
+    result.sort();
     Ok(result)
 }
 
@@ -385,12 +982,54 @@ fn open_symbols_file(test: &Path) -> io::Result<File> {
     File::open(&d_sym)
 }
 
+/// Reads the traces of a test binary and the digests of its sources, from
+/// the trace cache if it has an entry for it
 pub fn generate_tracemap(
     test: &Path,
     analysis: &HashMap<PathBuf, LineAnalysis>,
     config: &Config,
-) -> io::Result<TraceMap> {
-    let manifest = config.root();
+) -> io::Result<(TraceMap, SourceDigests)> {
+    let _phase = Phase::start("debug-info");
+    let cache = TraceCache::new(test, analysis, config);
+    if let Some(result) = cache.as_ref().and_then(|c| c.load()) {
+        return Ok(result);
+    }
+    let (traces, digests) = read_tracemap(test, analysis, config, None)?;
+    if let Some(cache) = cache {
+        cache.store(&traces, &digests);
+    }
+    Ok((traces, digests))
+}
+
+/// Reads the traces along with the origin of each address for
+/// `--dump-breakpoints`. The cache doesn't keep the origins so it isn't used.
+pub fn generate_tracemap_with_origins(
+    test: &Path,
+    analysis: &HashMap<PathBuf, LineAnalysis>,
+    config: &Config,
+) -> io::Result<(TraceMap, SourceDigests, HashMap<u64, AddressOrigin>)> {
+    let _phase = Phase::start("debug-info");
+    let mut origins = HashMap::new();
+    let (traces, digests) = read_tracemap(test, analysis, config, Some(&mut origins))?;
+    Ok((traces, digests, origins))
+}
+
+/// A row of a binary's line tables for a repro bundle. The file is its index
+/// in the unit's file table so no paths are included.
+#[derive(Clone, Debug, Serialize)]
+pub struct LineTableRow {
+    /// Index of the compilation unit in `.debug_info`
+    pub unit: usize,
+    /// Hex address, as shown by disassemblers
+    pub address: String,
+    pub file: u64,
+    pub line: u64,
+    pub is_stmt: bool,
+}
+
+/// Every row with a line in the line tables of a binary, for
+/// `--repro-line-tables`
+pub fn line_table_rows(test: &Path) -> io::Result<Vec<LineTableRow>> {
     let file = open_symbols_file(test)?;
     let file = unsafe { MmapOptions::new().map(&file)? };
     if let Ok(obj) = OFile::parse(&*file) {
@@ -399,14 +1038,74 @@ pub fn generate_tracemap(
         } else {
             RunTimeEndian::Big
         };
-        if let Ok(result) = get_line_addresses(endian, &manifest, &obj, &analysis, config) {
-            Ok(result)
-        } else {
-            Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Error while parsing",
-            ))
+        read_line_table_rows(endian, &obj)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Error while parsing"))
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Unable to parse binary.",
+        ))
+    }
+}
+
+fn read_line_table_rows(endian: RunTimeEndian, obj: &OFile) -> Result<Vec<LineTableRow>> {
+    let io_err = |_| Error::Io;
+    let debug_info = obj.section_by_name(".debug_info").ok_or(Error::Io)?;
+    let debug_info = DebugInfo::new(debug_info.data().map_err(io_err)?, endian);
+    let debug_abbrev = obj.section_by_name(".debug_abbrev").ok_or(Error::Io)?;
+    let debug_abbrev = DebugAbbrev::new(debug_abbrev.data().map_err(io_err)?, endian);
+    let debug_line = obj.section_by_name(".debug_line").ok_or(Error::Io)?;
+    let debug_line = DebugLine::new(debug_line.data().map_err(io_err)?, endian);
+
+    let mut rows = vec![];
+    let mut units = debug_info.units();
+    let mut unit = 0;
+    while let Ok(Some(cu)) = units.next() {
+        unit += 1;
+        let abbr = match cu.abbreviations(&debug_abbrev) {
+            Ok(a) => a,
+            _ => continue,
+        };
+        if let Ok(Some((_, root))) = cu.entries(&abbr).next_dfs() {
+            let offset = match root.attr_value(DW_AT_stmt_list) {
+                Ok(Some(AttributeValue::DebugLineRef(o))) => o,
+                _ => continue,
+            };
+            let prog = debug_line.program(offset, cu.address_size(), None, None)?;
+            let mut program_rows = prog.rows();
+            while let Ok(Some((_, row))) = program_rows.next_row() {
+                if let Some(line) = row.line() {
+                    rows.push(LineTableRow {
+                        unit: unit - 1,
+                        address: format!("0x{:x}", row.address()),
+                        file: row.file_index(),
+                        line,
+                        is_stmt: row.is_stmt(),
+                    });
+                }
+            }
         }
+    }
+    Ok(rows)
+}
+
+fn read_tracemap(
+    test: &Path,
+    analysis: &HashMap<PathBuf, LineAnalysis>,
+    config: &Config,
+    origins: Option<&mut HashMap<u64, AddressOrigin>>,
+) -> io::Result<(TraceMap, SourceDigests)> {
+    let manifest = config.root();
+    let file = open_symbols_file(test)?;
+    let file = unsafe { MmapOptions::new().map(&file)? };
+    if let Ok(obj) = OFile::parse(&*file) {
+        let endian = if obj.is_little_endian() {
+            RunTimeEndian::Little
+        } else {
+            RunTimeEndian::Big
+        };
+        get_line_addresses(endian, &manifest, &obj, &analysis, config, origins)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Error while parsing"))
     } else {
         Err(io::Error::new(
             io::ErrorKind::InvalidData,
@@ -414,3 +1113,73 @@ pub fn generate_tracemap(
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::time::Duration;
+
+    #[test]
+    fn stale_sources_detected() {
+        let dir = env::temp_dir().join(format!("tarpaulin-stale-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("lib.rs");
+        fs::write(&source, "pub fn answer() -> u32 { 42 }\n").unwrap();
+        let digest = md5::compute(fs::read(&source).unwrap()).0;
+        let modified = fs::metadata(&source).unwrap().modified().unwrap();
+
+        assert!(!is_stale(&source, Some(&digest), modified));
+        assert!(!is_stale(&source, None, modified));
+        assert!(is_stale(&source, None, modified - Duration::from_secs(10)));
+
+        fs::write(&source, "pub fn answer() -> u32 { 43 }\n").unwrap();
+        assert!(is_stale(&source, Some(&digest), SystemTime::now()));
+        // Only the digest is used when there is one
+        let digest = md5::compute(fs::read(&source).unwrap()).0;
+        assert!(!is_stale(
+            &source,
+            Some(&digest),
+            modified - Duration::from_secs(10)
+        ));
+
+        // Files which can't be read aren't reported
+        assert!(!is_stale(&dir.join("missing.rs"), None, modified));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn copied_sources_matched() {
+        let dir = env::temp_dir().join(format!("tarpaulin-copies-{}", std::process::id()));
+        let staged = dir.join("target/package/foo-0.1.0/src");
+        fs::create_dir_all(dir.join("src/a")).unwrap();
+        fs::create_dir_all(dir.join("src/b")).unwrap();
+        fs::create_dir_all(&staged).unwrap();
+        fs::write(dir.join("src/lib.rs"), "pub mod a;\n").unwrap();
+        fs::write(dir.join("src/a/mod.rs"), "pub fn a() {}\n").unwrap();
+        fs::write(dir.join("src/b/mod.rs"), "pub fn a() {}\n").unwrap();
+        fs::write(staged.join("lib.rs"), "pub mod a;\n").unwrap();
+        fs::write(staged.join("mod.rs"), "pub fn a() {}\n").unwrap();
+        fs::write(staged.join("main.rs"), "fn main() {}\n").unwrap();
+        let mut analysis = HashMap::new();
+        for file in &["src/lib.rs", "src/a/mod.rs", "src/b/mod.rs"] {
+            analysis.insert(dir.join(file), LineAnalysis::default());
+        }
+
+        let sources = SourcePaths::new(&dir, &analysis);
+        assert_eq!(
+            sources.original(&staged.join("lib.rs")),
+            Some(dir.join("src/lib.rs"))
+        );
+        // Identical files can't be told apart so neither gets the coverage
+        assert_eq!(sources.original(&staged.join("mod.rs")), None);
+        assert_eq!(sources.original(&staged.join("main.rs")), None);
+        // The match is remembered even if the copy changes
+        fs::write(staged.join("lib.rs"), "pub mod b;\n").unwrap();
+        assert_eq!(
+            sources.original(&staged.join("lib.rs")),
+            Some(dir.join("src/lib.rs"))
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
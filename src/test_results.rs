@@ -0,0 +1,1036 @@
+//! Results of the individual tests in each test binary, parsed from the output
+//! of the libtest harness. Tarpaulin runs the binaries itself so this is how
+//! the pass/fail breakdown `cargo test` would give is kept for the JUnit
+//! report, and how a test that hangs is found so `--per-test-timeout` can kill
+//! it. Everything that reads libtest's output goes through here.
+//!
+//! The output's layout is detected rather than assumed as it depends on the
+//! `--format` the tests were given and changes between toolchains:
+//!
+//! * `pretty`, the default, prints a line per test. Since 1.51 the summary
+//!   ends with `finished in`, since 1.61 ignored tests can give a reason after
+//!   `ignored, ` and since 1.73 a panic's location comes before its message
+//!   rather than after it. `--report-time` adds the time of each test.
+//! * `terse` prints a character per test so only the failed tests are named,
+//!   the others are only counted in the summary.
+//! * `json`, unstable, prints an event per line. Before 1.53 the time of a
+//!   test was a string such as `"0.002s"` rather than a number.
+//!
+//! Output that looks like libtest's but can't be parsed, such as from a
+//! toolchain that changed it again, is reported as a single result for the
+//! whole binary and a warning is logged once.
+//!
+//! A test can close or redirect its stdout. Its output is then only read up to
+//! that point and the rest goes wherever the test pointed it, the test can't
+//! be watched by `--per-test-timeout` once its output ends.
+use crate::config::RunType;
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use regex::Regex;
+use serde_json::Value;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    /// A test's result, the time is only given with `--report-time`
+    static ref RESULT: Regex = Regex::new(
+        r"^test (.+?) \.\.\. (ok|FAILED|ignored)(?:, [^<]*)?(?: <(\d+(?:\.\d+)?)s>)?\s*$"
+    )
+    .unwrap();
+    /// Start of the output of a failed test
+    static ref OUTPUT: Regex = Regex::new(r"^---- (.+) stdout ----$").unwrap();
+    /// Printed before the tests of a libtest harness run
+    static ref RUNNING: Regex = Regex::new(r"^running \d+ tests?$").unwrap();
+    /// A test starting, only printed when the tests run on one thread
+    static ref STARTED: Regex = Regex::new(r"^test (.+?) \.\.\. $").unwrap();
+    /// Printed by libtest when a test has been running for a minute
+    static ref SLOW: Regex = Regex::new(r"^test (.+?) has been running for over \d+ seconds$").unwrap();
+    /// Printed after the tests of a libtest harness run
+    static ref SUMMARY: Regex = Regex::new(
+        r"^test result: (?:ok|FAILED)\. (\d+) passed; (\d+) failed; (\d+) ignored;"
+    )
+    .unwrap();
+    /// A line of `--format terse` progress, long runs end each line with the
+    /// number of tests finished
+    static ref TERSE: Regex = Regex::new(r"^[.Fi]+(?: +\d+/\d+)?$").unwrap();
+}
+
+/// How long to wait for the end of a test's output once it has exited, the
+/// output stays open while processes the test started are still holding it
+const OUTPUT_GRACE: Duration = Duration::from_secs(5);
+
+/// Megabytes of a test's output kept for its results without
+/// `--capture-limit`, the rest is still passed on and followed for progress
+/// but isn't kept
+const CAPTURE_LIMIT: u64 = 64;
+
+/// Longest line followed for progress, longer lines are JSON events with
+/// enormous output or aren't libtest's
+const MAX_LINE: usize = 1024 * 1024;
+
+/// Longest unfinished line checked for a test starting, those lines are short
+/// and checking longer ones on every read would be quadratic
+const MAX_PARTIAL: usize = 1024;
+
+/// The unknown format warning is only given once per run
+static UNKNOWN_FORMAT: Once = Once::new();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Failed,
+    Ignored,
+}
+
+/// Layout of the output of a test binary
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// libtest's default, a line per test
+    Pretty,
+    /// libtest's `--format terse`, only failed tests are named
+    Terse,
+    /// libtest's `--format json`, an event per line
+    Json,
+    /// Looks like libtest's output but couldn't be parsed
+    Unknown,
+    /// Not from a libtest harness, such as a `harness = false` test or a bin
+    NotLibtest,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestCase {
+    pub name: String,
+    pub outcome: Outcome,
+    /// Only known if the tests were run with `--report-time`
+    pub duration: Option<Duration>,
+    /// What a failed test printed, including the panic message
+    pub output: Option<String>,
+}
+
+/// Number of tests with each outcome from the harness's summary
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Totals {
+    pub passed: usize,
+    pub failed: usize,
+    pub ignored: usize,
+}
+
+impl Totals {
+    fn count(&self, outcome: Outcome) -> usize {
+        match outcome {
+            Outcome::Passed => self.passed,
+            Outcome::Failed => self.failed,
+            Outcome::Ignored => self.ignored,
+        }
+    }
+}
+
+/// What could be read from the output of a test binary
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParsedOutput {
+    pub format: OutputFormat,
+    /// Every test for the pretty and JSON formats, only the failed ones for
+    /// the terse format and none otherwise
+    pub cases: Vec<TestCase>,
+    /// The harness's summary, `None` if it didn't finish
+    pub totals: Option<Totals>,
+}
+
+/// The tests run by a test binary
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestSuite {
+    /// Name of the target the binary was built for
+    pub name: String,
+    pub run_type: RunType,
+    pub format: OutputFormat,
+    pub cases: Vec<TestCase>,
+    /// The harness's summary, the counts of tests which aren't in `cases`
+    /// with the terse format
+    pub totals: Option<Totals>,
+}
+
+impl TestSuite {
+    pub fn new(name: String, run_type: RunType, output: &str) -> Self {
+        let parsed = parse(output);
+        Self {
+            name,
+            run_type,
+            format: parsed.format,
+            cases: parsed.cases,
+            totals: parsed.totals,
+        }
+    }
+
+    /// Adds the results of running the binary's ignored tests, which replace
+    /// the results saying they were ignored
+    pub fn merge_ignored(&mut self, other: TestSuite) {
+        for case in other.cases {
+            match self.cases.iter_mut().find(|c| c.name == case.name) {
+                Some(c) if c.outcome == Outcome::Ignored => *c = case,
+                Some(_) => {}
+                None => self.cases.push(case),
+            }
+        }
+        if let (Some(totals), Some(other)) = (self.totals.as_mut(), other.totals) {
+            let run = other.passed + other.failed;
+            totals.ignored = totals.ignored.saturating_sub(run);
+            totals.passed += other.passed;
+            totals.failed += other.failed;
+        }
+    }
+
+    pub fn count(&self, outcome: Outcome) -> usize {
+        match (self.format, self.totals) {
+            (OutputFormat::Terse, Some(totals)) => totals.count(outcome),
+            _ => self.cases.iter().filter(|c| c.outcome == outcome).count(),
+        }
+    }
+
+    /// Number of tests run or ignored, including those the terse format
+    /// doesn't name
+    pub fn tests(&self) -> usize {
+        match (self.format, self.totals) {
+            (OutputFormat::Terse, Some(t)) => t.passed + t.failed + t.ignored,
+            _ => self.cases.len(),
+        }
+    }
+
+    /// Total time of the tests, zero if they weren't timed
+    pub fn duration(&self) -> Duration {
+        self.cases.iter().filter_map(|c| c.duration).sum()
+    }
+
+    /// Suite of the captured output of a binary which exited with `code`, if
+    /// a test hung it's added as a failure as the binary was killed before
+    /// printing its result. If the output isn't libtest's or couldn't be
+    /// parsed the binary is reported as a single test.
+    pub fn from_capture(
+        name: String,
+        run_type: RunType,
+        output: &CapturedOutput,
+        code: i32,
+    ) -> Self {
+        let mut suite = Self::new(name, run_type, &output.text);
+        match suite.format {
+            OutputFormat::Unknown | OutputFormat::NotLibtest => {
+                if suite.format == OutputFormat::Unknown {
+                    UNKNOWN_FORMAT.call_once(|| {
+                        warn!(
+                            "The test output of {} couldn't be parsed, only whether each binary passed is reported",
+                            suite.name
+                        )
+                    });
+                }
+                suite.cases = vec![TestCase {
+                    name: suite.name.clone(),
+                    outcome: if code == 0 {
+                        Outcome::Passed
+                    } else {
+                        Outcome::Failed
+                    },
+                    duration: None,
+                    output: None,
+                }];
+            }
+            _ => {}
+        }
+        if let Some(ref hung) = output.hung {
+            let case = TestCase {
+                name: hung.clone(),
+                outcome: Outcome::Failed,
+                duration: None,
+                output: Some("test hung and was killed by --per-test-timeout".to_string()),
+            };
+            match suite.cases.iter_mut().find(|c| c.name == case.name) {
+                Some(c) => *c = case,
+                None => suite.cases.push(case),
+            }
+        }
+        suite
+    }
+}
+
+/// The JSON event of a line of `--format json` output
+fn json_event(line: &str) -> Option<Value> {
+    if !line.starts_with('{') {
+        return None;
+    }
+    let event: Value = serde_json::from_str(line).ok()?;
+    match event["type"].as_str() {
+        Some("suite") | Some("test") => Some(event),
+        _ => None,
+    }
+}
+
+/// Works out the layout of a test binary's output
+pub fn detect_format(output: &str) -> OutputFormat {
+    let mut running = false;
+    let mut terse = false;
+    for line in output.lines() {
+        if json_event(line).is_some() {
+            return OutputFormat::Json;
+        } else if RESULT.is_match(line) || STARTED.is_match(line) || SLOW.is_match(line) {
+            return OutputFormat::Pretty;
+        } else if RUNNING.is_match(line) {
+            running = true;
+        } else if running && TERSE.is_match(line) {
+            terse = true;
+        }
+    }
+    if terse {
+        OutputFormat::Terse
+    } else if output.lines().any(|l| l == "running 0 tests") {
+        OutputFormat::Pretty
+    } else if running {
+        OutputFormat::Unknown
+    } else {
+        OutputFormat::NotLibtest
+    }
+}
+
+/// Parses the output of a test binary in whichever format it's in
+pub fn parse(output: &str) -> ParsedOutput {
+    let format = detect_format(output);
+    let (cases, totals) = match format {
+        OutputFormat::Pretty | OutputFormat::Terse => parse_text(output),
+        OutputFormat::Json => parse_json(output),
+        OutputFormat::Unknown | OutputFormat::NotLibtest => (vec![], None),
+    };
+    ParsedOutput {
+        format,
+        cases,
+        totals,
+    }
+}
+
+/// Parses the result of each test from the output of a libtest harness, the
+/// output of failed tests is attached to their result
+pub fn parse_test_output(output: &str) -> Vec<TestCase> {
+    parse(output).cases
+}
+
+/// Parses the pretty and terse formats. The terse format only names failed
+/// tests, in their output and the list of failures after it.
+fn parse_text(output: &str) -> (Vec<TestCase>, Option<Totals>) {
+    let mut cases: Vec<TestCase> = vec![];
+    let mut failure: Option<(String, Vec<&str>)> = None;
+    let mut failures = vec![];
+    let mut totals = None;
+    // The names in the list of failures at the end
+    let mut listed = false;
+    for line in output.lines() {
+        if let Some(c) = RESULT.captures(line) {
+            let name = c[1].trim_end_matches(" - should panic").to_string();
+            let outcome = match &c[2] {
+                "ok" => Outcome::Passed,
+                "FAILED" => Outcome::Failed,
+                _ => Outcome::Ignored,
+            };
+            let duration = c
+                .get(3)
+                .and_then(|d| d.as_str().parse::<f64>().ok())
+                .map(Duration::from_secs_f64);
+            cases.push(TestCase {
+                name,
+                outcome,
+                duration,
+                output: None,
+            });
+        } else if let Some(c) = OUTPUT.captures(line) {
+            failures.extend(failure.take());
+            failure = Some((c[1].to_string(), vec![]));
+        } else if line == "failures:" || line == "successes:" {
+            failures.extend(failure.take());
+            listed = line == "failures:";
+        } else if let Some(c) = SUMMARY.captures(line) {
+            failures.extend(failure.take());
+            listed = false;
+            let count = |i: usize| c[i].parse().unwrap_or_default();
+            totals = Some(Totals {
+                passed: count(1),
+                failed: count(2),
+                ignored: count(3),
+            });
+        } else if let Some((_, ref mut lines)) = failure {
+            lines.push(line);
+        } else if listed && line.starts_with("    ") {
+            let name = line.trim();
+            if !cases.iter().any(|c| c.name == name) {
+                cases.push(TestCase {
+                    name: name.to_string(),
+                    outcome: Outcome::Failed,
+                    duration: None,
+                    output: None,
+                });
+            }
+        }
+    }
+    failures.extend(failure);
+    for (name, lines) in failures {
+        if let Some(case) = cases
+            .iter_mut()
+            .find(|c| c.name == name && c.outcome == Outcome::Failed)
+        {
+            case.output = Some(lines.join("\n").trim_end().to_string());
+        }
+    }
+    (cases, totals)
+}
+
+/// Time of a test in a JSON event, a number of seconds or from before 1.53 a
+/// string such as `"0.002s"`
+fn exec_time(event: &Value) -> Option<Duration> {
+    let seconds = match event["exec_time"] {
+        Value::Number(ref n) => n.as_f64()?,
+        Value::String(ref s) => s.trim_end_matches('s').parse().ok()?,
+        _ => return None,
+    };
+    Some(Duration::from_secs_f64(seconds))
+}
+
+fn parse_json(output: &str) -> (Vec<TestCase>, Option<Totals>) {
+    let mut cases = vec![];
+    let mut totals = None;
+    for event in output.lines().filter_map(json_event) {
+        let kind = event["event"].as_str().unwrap_or_default();
+        if event["type"] == "suite" {
+            if kind == "ok" || kind == "failed" {
+                let count = |k: &str| event[k].as_u64().unwrap_or_default() as usize;
+                totals = Some(Totals {
+                    passed: count("passed"),
+                    failed: count("failed"),
+                    ignored: count("ignored"),
+                });
+            }
+            continue;
+        }
+        let outcome = match kind {
+            "ok" => Outcome::Passed,
+            "failed" => Outcome::Failed,
+            "ignored" => Outcome::Ignored,
+            _ => continue,
+        };
+        let output = match (outcome, event["stdout"].as_str()) {
+            (Outcome::Failed, Some(stdout)) => Some(stdout.trim_end().to_string()),
+            _ => None,
+        };
+        cases.push(TestCase {
+            name: event["name"].as_str().unwrap_or_default().to_string(),
+            outcome,
+            duration: exec_time(&event),
+            output,
+        });
+    }
+    (cases, totals)
+}
+
+/// What a test binary printed to stdout
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CapturedOutput {
+    pub text: String,
+    /// The test killed by `--per-test-timeout`
+    pub hung: Option<String>,
+    /// Whether output past `--capture-limit` was dropped
+    pub truncated: bool,
+}
+
+/// Which test a libtest harness is running, tracked from its output as it's
+/// printed
+#[derive(Debug)]
+struct Progress {
+    /// Only libtest harnesses are watched, `harness = false` tests and bins
+    /// are left to the global timeout
+    libtest: bool,
+    /// The test started last that has no result yet
+    running: Option<String>,
+    /// When the harness last started a test or printed a result
+    last_event: Instant,
+    hung: Option<String>,
+    /// Tests started without a result in the JSON format, which says when
+    /// each test starts whatever the number of threads
+    in_flight: Vec<String>,
+    /// Length of the line of terse progress being printed
+    terse: usize,
+    /// The output has ended, when the test closes or redirects its stdout
+    /// this is before it exits and which test is running can't be followed
+    closed: bool,
+}
+
+impl Progress {
+    fn new() -> Self {
+        Self {
+            libtest: false,
+            running: None,
+            last_event: Instant::now(),
+            hung: None,
+            in_flight: vec![],
+            terse: 0,
+            closed: false,
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        if RUNNING.is_match(line) {
+            self.libtest = true;
+            self.last_event = Instant::now();
+        } else if RESULT.is_match(line) {
+            self.running = None;
+            self.last_event = Instant::now();
+        } else if let Some(c) = SLOW.captures(line) {
+            // With several test threads this is the only way to know which
+            // test is still running
+            if self.running.is_none() {
+                self.running = Some(c[1].to_string());
+            }
+        } else if line.starts_with("test result: ") {
+            self.libtest = false;
+            self.running = None;
+        } else if let Some(event) = json_event(line) {
+            self.event(&event);
+        }
+        self.terse = 0;
+    }
+
+    fn event(&mut self, event: &Value) {
+        let name = event["name"].as_str().unwrap_or_default();
+        match (event["type"].as_str(), event["event"].as_str()) {
+            (Some("suite"), Some("started")) => {
+                self.libtest = true;
+                self.last_event = Instant::now();
+            }
+            (Some("suite"), _) => {
+                self.libtest = false;
+                self.in_flight.clear();
+            }
+            (_, Some("started")) => {
+                self.in_flight.push(name.to_string());
+                self.last_event = Instant::now();
+            }
+            // Printed when a test has been running for a minute
+            (_, Some("timeout")) => {}
+            _ => {
+                self.in_flight.retain(|t| t != name);
+                self.last_event = Instant::now();
+            }
+        }
+        self.running = self.in_flight.last().cloned();
+    }
+
+    /// The harness prints the name of a test and flushes it before running
+    /// it, the rest of the line is printed once it finishes
+    fn partial(&mut self, partial: &str) {
+        // The terse format prints a character as each test finishes
+        if self.libtest && TERSE.is_match(partial) && partial.len() != self.terse {
+            self.terse = partial.len();
+            self.last_event = Instant::now();
+        } else if let Some(c) = STARTED.captures(partial) {
+            let name = c[1].trim_end_matches(" - should panic");
+            if self.running.as_deref() != Some(name) {
+                self.running = Some(name.to_string());
+                self.last_event = Instant::now();
+            }
+        }
+    }
+
+    fn hung(&mut self, timeout: Duration) -> Option<String> {
+        if !self.libtest
+            || self.closed
+            || self.hung.is_some()
+            || self.last_event.elapsed() < timeout
+        {
+            return None;
+        }
+        let name = self
+            .running
+            .clone()
+            .unwrap_or_else(|| "<unknown test>".to_string());
+        self.hung = Some(name.clone());
+        Some(name)
+    }
+}
+
+/// Output of a test kept for its results, the first `limit` bytes of it
+struct Captured {
+    text: Vec<u8>,
+    limit: usize,
+    truncated: bool,
+}
+
+impl Captured {
+    fn new(limit: usize) -> Self {
+        Self {
+            text: vec![],
+            limit,
+            truncated: false,
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        let room = self.limit.saturating_sub(self.text.len());
+        if data.len() > room {
+            self.truncated = true;
+        }
+        self.text.extend_from_slice(&data[..data.len().min(room)]);
+    }
+}
+
+/// Reads the output of a test from a pipe while it runs, passing it on to
+/// tarpaulin's stdout so it's still shown
+pub(crate) struct OutputCapture {
+    output: Arc<Mutex<Captured>>,
+    progress: Arc<Mutex<Progress>>,
+    /// Sent to when the output ends
+    done: Receiver<()>,
+}
+
+impl OutputCapture {
+    /// Starts reading the read end of the pipe. It ends when every process
+    /// holding the write end has closed it, usually when the test exits but
+    /// it's earlier if the test closes or redirects its stdout.
+    /// At most `limit` megabytes of the output are kept.
+    pub fn start(fd: RawFd, limit: Option<u64>) -> Self {
+        let limit = limit.unwrap_or(CAPTURE_LIMIT).saturating_mul(1024 * 1024);
+        Self::with_limit(fd, usize::try_from(limit).unwrap_or(usize::MAX))
+    }
+
+    fn with_limit(fd: RawFd, limit: usize) -> Self {
+        let mut pipe = unsafe { File::from_raw_fd(fd) };
+        let output = Arc::new(Mutex::new(Captured::new(limit)));
+        let progress = Arc::new(Mutex::new(Progress::new()));
+        let (sender, done) = mpsc::channel();
+        {
+            let output = output.clone();
+            let progress = progress.clone();
+            thread::spawn(move || {
+                let mut buffer = [0u8; 4096];
+                // The line the harness is part way through printing
+                let mut line = vec![];
+                let mut overlong = false;
+                loop {
+                    match pipe.read(&mut buffer) {
+                        Ok(0) => break,
+                        Ok(n) => {
+                            let read = &buffer[..n];
+                            // Failing to pass the output on doesn't stop it
+                            // being read, tarpaulin's own stdout may be closed
+                            let stdout = io::stdout();
+                            let mut stdout = stdout.lock();
+                            let _ = stdout.write_all(read);
+                            let _ = stdout.flush();
+                            match output.lock() {
+                                Ok(mut output) => output.push(read),
+                                Err(_) => break,
+                            }
+                            if let Ok(mut progress) = progress.lock() {
+                                let mut rest = read;
+                                while let Some(end) = rest.iter().position(|b| *b == b'\n') {
+                                    if !overlong && line.len() + end <= MAX_LINE {
+                                        line.extend_from_slice(&rest[..end]);
+                                        progress.line(String::from_utf8_lossy(&line).trim_end());
+                                    }
+                                    line.clear();
+                                    overlong = false;
+                                    rest = &rest[(end + 1)..];
+                                }
+                                if !overlong {
+                                    line.extend_from_slice(rest);
+                                    if line.len() > MAX_LINE {
+                                        line.clear();
+                                        overlong = true;
+                                    } else if line.len() <= MAX_PARTIAL {
+                                        progress.partial(&String::from_utf8_lossy(&line));
+                                    }
+                                }
+                            }
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                        // Any other error won't go away by reading again
+                        Err(e) => {
+                            debug!("Stopped reading the test's output: {}", e);
+                            break;
+                        }
+                    }
+                }
+                if let Ok(mut progress) = progress.lock() {
+                    progress.closed = true;
+                }
+                let _ = sender.send(());
+            });
+        }
+        OutputCapture {
+            output,
+            progress,
+            done,
+        }
+    }
+
+    /// Returns the test that's been running for longer than the timeout
+    /// without a result, only the first time it's found
+    pub fn hung_test(&self, timeout: Duration) -> Option<String> {
+        self.progress.lock().ok()?.hung(timeout)
+    }
+
+    /// Waits for the test's output to end and returns it, called once the
+    /// test has exited. If a process the test started is still holding its
+    /// stdout the output so far is returned rather than waiting for it.
+    pub fn finish(self) -> CapturedOutput {
+        if let Err(RecvTimeoutError::Timeout) = self.done.recv_timeout(OUTPUT_GRACE) {
+            warn!("The test exited but a process it started still has its stdout open, only the output so far is read");
+        }
+        let (text, truncated) = self
+            .output
+            .lock()
+            .map(|o| {
+                let mut text = String::from_utf8_lossy(&o.text).into_owned();
+                if o.truncated {
+                    text.push_str(&format!(
+                        "\n[tarpaulin: output truncated after {} bytes]\n",
+                        o.limit
+                    ));
+                }
+                (text, o.truncated)
+            })
+            .unwrap_or_default();
+        let hung = self.progress.lock().ok().and_then(|p| p.hung.clone());
+        CapturedOutput {
+            text,
+            hung,
+            truncated,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OUTPUT: &str = "
+running 5 tests
+test tests::adds ... ok <0.002s>
+test tests::slow ... ignored, takes too long
+test tests::panics - should panic ... ok <0.001s>
+test tests::subtracts ... FAILED <0.010s>
+test tests::divides ... FAILED
+
+failures:
+
+---- tests::subtracts stdout ----
+thread 'tests::subtracts' panicked at 'assertion failed: `(left == right)`
+  left: `1`,
+ right: `2`', src/lib.rs:20:9
+note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace
+
+---- tests::divides stdout ----
+thread 'tests::divides' panicked at 'attempt to divide by zero', src/lib.rs:25:9
+
+
+failures:
+    tests::divides
+    tests::subtracts
+
+test result: FAILED. 2 passed; 2 failed; 1 ignored; 0 measured; 0 filtered out
+";
+
+    #[test]
+    fn libtest_results() {
+        let cases = parse_test_output(OUTPUT);
+        let names = cases.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "tests::adds",
+                "tests::slow",
+                "tests::panics",
+                "tests::subtracts",
+                "tests::divides"
+            ]
+        );
+        assert_eq!(cases[0].outcome, Outcome::Passed);
+        assert_eq!(cases[0].duration, Some(Duration::from_millis(2)));
+        assert_eq!(cases[0].output, None);
+        assert_eq!(cases[1].outcome, Outcome::Ignored);
+        assert_eq!(cases[1].duration, None);
+        assert_eq!(cases[2].outcome, Outcome::Passed);
+
+        assert_eq!(cases[3].outcome, Outcome::Failed);
+        let output = cases[3].output.as_ref().unwrap();
+        assert!(output.starts_with("thread 'tests::subtracts' panicked"));
+        assert!(output.ends_with("to display a backtrace"));
+        assert_eq!(
+            cases[4].output.as_deref(),
+            Some(
+                "thread 'tests::divides' panicked at 'attempt to divide by zero', src/lib.rs:25:9"
+            )
+        );
+    }
+
+    /// Output of the same tests captured from several toolchains
+    const SAMPLES: &[(&str, &str, OutputFormat)] = &[
+        (
+            "1.41.0 pretty",
+            include_str!("../tests/data/libtest/1.41.0-pretty.txt"),
+            OutputFormat::Pretty,
+        ),
+        (
+            "1.50.0 json",
+            include_str!("../tests/data/libtest/1.50.0-json.txt"),
+            OutputFormat::Json,
+        ),
+        (
+            "1.60.0 pretty",
+            include_str!("../tests/data/libtest/1.60.0-pretty.txt"),
+            OutputFormat::Pretty,
+        ),
+        (
+            "1.75.0 pretty",
+            include_str!("../tests/data/libtest/1.75.0-pretty.txt"),
+            OutputFormat::Pretty,
+        ),
+        (
+            "1.75.0 terse",
+            include_str!("../tests/data/libtest/1.75.0-terse.txt"),
+            OutputFormat::Terse,
+        ),
+        (
+            "1.75.0 json",
+            include_str!("../tests/data/libtest/1.75.0-json.txt"),
+            OutputFormat::Json,
+        ),
+    ];
+
+    #[test]
+    fn sample_outputs_parsed() {
+        for (sample, output, format) in SAMPLES {
+            let parsed = parse(output);
+            assert_eq!(parsed.format, *format, "{}", sample);
+            let totals = Totals {
+                passed: 2,
+                failed: 2,
+                ignored: 1,
+            };
+            assert_eq!(parsed.totals, Some(totals), "{}", sample);
+            let mut failed = parsed
+                .cases
+                .iter()
+                .filter(|c| c.outcome == Outcome::Failed)
+                .collect::<Vec<_>>();
+            failed.sort_by_key(|c| c.name.as_str());
+            let names = failed.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+            assert_eq!(
+                names,
+                vec!["tests::divides", "tests::subtracts"],
+                "{}",
+                sample
+            );
+            for case in failed {
+                let output = case.output.as_deref().unwrap_or_default();
+                let start = format!("thread '{}' panicked at ", case.name);
+                assert!(output.starts_with(&start), "{}: {}", sample, output);
+                assert!(!output.ends_with('\n'), "{}", sample);
+            }
+
+            let suite = TestSuite::new("lib".to_string(), RunType::Tests, output);
+            assert_eq!(suite.tests(), 5, "{}", sample);
+            assert_eq!(suite.count(Outcome::Passed), 2, "{}", sample);
+            assert_eq!(suite.count(Outcome::Ignored), 1, "{}", sample);
+            if *format == OutputFormat::Terse {
+                // Only the failed tests are named
+                assert_eq!(suite.cases.len(), 2);
+            } else {
+                assert_eq!(suite.cases.len(), 5, "{}", sample);
+            }
+        }
+    }
+
+    #[test]
+    fn sample_times_parsed() {
+        for (sample, output, _) in SAMPLES {
+            let parsed = parse(output);
+            let adds = parsed.cases.iter().find(|c| c.name == "tests::adds");
+            let timed = sample.contains("json") || sample.starts_with("1.60.0");
+            let millis = adds
+                .and_then(|c| c.duration)
+                .map(|d| (d.as_secs_f64() * 1000.0).round() as u64);
+            assert_eq!(millis, if timed { Some(2) } else { None }, "{}", sample);
+        }
+    }
+
+    #[test]
+    fn unparsed_output_degrades() {
+        assert_eq!(
+            detect_format("Hello from a bin\n"),
+            OutputFormat::NotLibtest
+        );
+        assert_eq!(detect_format(""), OutputFormat::NotLibtest);
+        assert_eq!(
+            detect_format("\nrunning 0 tests\n\ntest result: ok. 0 passed; 0 failed; 0 ignored;"),
+            OutputFormat::Pretty
+        );
+        // A first test that hangs is still recognised
+        assert_eq!(
+            detect_format("running 2 tests\ntest tests::hangs ... "),
+            OutputFormat::Pretty
+        );
+        let unknown = "running 2 tests\n[PASS] tests::adds\n[FAIL] tests::subtracts\n";
+        assert_eq!(detect_format(unknown), OutputFormat::Unknown);
+
+        // Only whether the binary passed is known
+        let capture = CapturedOutput {
+            text: unknown.to_string(),
+            hung: None,
+            truncated: false,
+        };
+        let suite = TestSuite::from_capture("lib".to_string(), RunType::Tests, &capture, 101);
+        assert_eq!(suite.format, OutputFormat::Unknown);
+        assert_eq!(suite.cases.len(), 1);
+        assert_eq!(suite.cases[0].name, "lib");
+        assert_eq!(suite.cases[0].outcome, Outcome::Failed);
+        let capture = CapturedOutput {
+            text: "Hello from a bin\n".to_string(),
+            hung: None,
+            truncated: false,
+        };
+        let suite = TestSuite::from_capture("bin".to_string(), RunType::Bins, &capture, 0);
+        assert_eq!(suite.count(Outcome::Passed), 1);
+    }
+
+    #[test]
+    fn ignored_results_merged() {
+        let mut suite = TestSuite::new("lib".to_string(), RunType::Tests, OUTPUT);
+        assert_eq!(suite.count(Outcome::Ignored), 1);
+        assert_eq!(suite.duration(), Duration::from_millis(13));
+        let ignored = TestSuite::new(
+            "lib".to_string(),
+            RunType::Tests,
+            "test tests::slow ... ok\ntest tests::adds ... ignored\n",
+        );
+        suite.merge_ignored(ignored);
+        assert_eq!(suite.cases.len(), 5);
+        assert_eq!(suite.count(Outcome::Ignored), 0);
+        assert_eq!(suite.count(Outcome::Passed), 3);
+        assert_eq!(suite.count(Outcome::Failed), 2);
+    }
+
+    #[test]
+    fn hung_test_found() {
+        let mut progress = Progress::new();
+        progress.partial("test tests::adds ... ");
+        assert_eq!(progress.hung(Duration::from_secs(0)), None);
+
+        progress.line("running 2 tests");
+        progress.partial("test tests::adds ... ");
+        progress.line("test tests::adds ... ok");
+        assert_eq!(progress.running, None);
+        progress.partial("test tests::hangs ... ");
+        assert_eq!(progress.hung(Duration::from_secs(60)), None);
+        assert_eq!(
+            progress.hung(Duration::from_secs(0)).as_deref(),
+            Some("tests::hangs")
+        );
+        assert_eq!(progress.hung(Duration::from_secs(0)), None);
+
+        let suite = TestSuite::from_capture(
+            "lib".to_string(),
+            RunType::Tests,
+            &CapturedOutput {
+                text: "running 2 tests\ntest tests::adds ... ok\ntest tests::hangs ... "
+                    .to_string(),
+                hung: progress.hung,
+                truncated: false,
+            },
+            1,
+        );
+        assert_eq!(suite.count(Outcome::Passed), 1);
+        assert_eq!(suite.cases[1].name, "tests::hangs");
+        assert_eq!(suite.cases[1].outcome, Outcome::Failed);
+    }
+
+    #[test]
+    fn closed_output_not_hung() {
+        let (read, write) = nix::unistd::pipe().unwrap();
+        let capture = OutputCapture::start(read, None);
+        let mut pipe = unsafe { File::from_raw_fd(write) };
+        pipe.write_all(b"running 2 tests\ntest tests::adds ... ok\ntest tests::redirects ... ")
+            .unwrap();
+        // The test redirecting its stdout closes the only write end
+        drop(pipe);
+        let start = Instant::now();
+        while !capture.progress.lock().unwrap().closed {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+        // It's still running but can't be followed
+        assert_eq!(capture.hung_test(Duration::from_secs(0)), None);
+        let output = capture.finish();
+        assert!(output.text.ends_with("test tests::redirects ... "));
+        assert_eq!(output.hung, None);
+    }
+
+    #[test]
+    fn capture_bounded() {
+        let (read, write) = nix::unistd::pipe().unwrap();
+        let capture = OutputCapture::with_limit(read, 64);
+        let mut pipe = unsafe { File::from_raw_fd(write) };
+        pipe.write_all(b"running 1 test\n").unwrap();
+        // A line longer than the limit without a newline is neither kept nor
+        // decoded again on every read
+        pipe.write_all(&vec![b'x'; MAX_LINE + 10]).unwrap();
+        pipe.write_all(b"\ntest tests::adds ... ").unwrap();
+        drop(pipe);
+        let start = Instant::now();
+        while !capture.progress.lock().unwrap().closed {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+        // Progress is still followed past the limit
+        assert_eq!(
+            capture.progress.lock().unwrap().running.as_deref(),
+            Some("tests::adds")
+        );
+        let output = capture.finish();
+        assert!(output.text.starts_with("running 1 test\nxxx"));
+        assert!(output
+            .text
+            .ends_with("x\n[tarpaulin: output truncated after 64 bytes]\n"));
+        assert!(output.truncated);
+    }
+
+    #[test]
+    fn hung_json_test_found() {
+        let mut progress = Progress::new();
+        let events = [
+            r#"{ "type": "suite", "event": "started", "test_count": 3 }"#,
+            r#"{ "type": "test", "event": "started", "name": "tests::hangs" }"#,
+            r#"{ "type": "test", "event": "started", "name": "tests::adds" }"#,
+            r#"{ "type": "test", "name": "tests::adds", "event": "ok" }"#,
+            r#"{ "type": "test", "event": "timeout", "name": "tests::hangs" }"#,
+        ];
+        for event in &events {
+            progress.line(event);
+        }
+        assert_eq!(
+            progress.hung(Duration::from_secs(0)).as_deref(),
+            Some("tests::hangs")
+        );
+    }
+
+    #[test]
+    fn terse_progress_tracked() {
+        let mut progress = Progress::new();
+        progress.line("running 3 tests");
+        progress.partial("..");
+        assert_eq!(progress.terse, 2);
+        assert_eq!(progress.hung(Duration::from_secs(60)), None);
+        // The terse format doesn't name the running test
+        assert_eq!(
+            progress.hung(Duration::from_secs(0)).as_deref(),
+            Some("<unknown test>")
+        );
+    }
+}
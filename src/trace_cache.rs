@@ -0,0 +1,236 @@
+//! Cache of the traces extracted from the debug info of each test binary.
+//! Reading the DWARF tables dominates runs where nothing was rebuilt, so the
+//! results are saved in `target/tarpaulin/trace-cache` as zstd compressed
+//! JSON. An entry is only used if its key matches, the key is a hash of the
+//! binary, the tarpaulin version, the source analysis and the options that
+//! change which lines are traced. Entries which can't be read or don't match
+//! are regenerated, `--no-cache` skips the cache entirely.
+use crate::config::Config;
+use crate::errors::write_error;
+use crate::report::compress::open_maybe_compressed;
+use crate::source_analysis::LineAnalysis;
+use crate::test_loader::SourceDigests;
+use crate::traces::TraceMap;
+use log::debug;
+use memmap::MmapOptions;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static HITS: AtomicUsize = AtomicUsize::new(0);
+static MISSES: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of times traces were loaded from the cache in this process
+pub fn hits() -> usize {
+    HITS.load(Ordering::SeqCst)
+}
+
+/// Number of times there was no usable entry in the cache in this process
+pub fn misses() -> usize {
+    MISSES.load(Ordering::SeqCst)
+}
+
+#[derive(Deserialize, Serialize)]
+struct CacheEntry {
+    key: String,
+    traces: TraceMap,
+    /// Checked to find sources changed since the binary was built
+    #[serde(default)]
+    digests: SourceDigests,
+}
+
+/// The cache entry for a test binary
+pub struct TraceCache {
+    path: PathBuf,
+    key: String,
+}
+
+impl TraceCache {
+    /// Finds the entry for the binary, `None` if caching is disabled or the
+    /// binary can't be read
+    pub fn new(
+        test: &Path,
+        analysis: &HashMap<PathBuf, LineAnalysis>,
+        config: &Config,
+    ) -> Option<Self> {
+        if config.no_cache {
+            return None;
+        }
+        let dir = config.tarpaulin_dir().join("trace-cache");
+        // One entry per binary so the cache doesn't grow with every build
+        let mut name = test.file_name()?.to_os_string();
+        name.push(".json.zst");
+        let key = cache_key(test, analysis, config).ok()?;
+        Some(TraceCache {
+            path: dir.join(name),
+            key,
+        })
+    }
+
+    /// The cached traces and source digests if there's an entry with a
+    /// matching key
+    pub fn load(&self) -> Option<(TraceMap, SourceDigests)> {
+        let result = self.read();
+        let counter = if result.is_some() { &HITS } else { &MISSES };
+        counter.fetch_add(1, Ordering::SeqCst);
+        result
+    }
+
+    fn read(&self) -> Option<(TraceMap, SourceDigests)> {
+        let reader = open_maybe_compressed(&self.path).ok()?;
+        match serde_json::from_reader::<_, CacheEntry>(reader) {
+            Ok(entry) if entry.key == self.key => {
+                debug!("Loaded traces from {}", self.path.display());
+                Some((entry.traces, entry.digests))
+            }
+            Ok(_) => {
+                debug!("Trace cache {} is out of date", self.path.display());
+                None
+            }
+            Err(e) => {
+                debug!("Couldn't read trace cache {}: {}", self.path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Saves the traces, failing to is only logged as the cache is optional
+    pub fn store(&self, traces: &TraceMap, digests: &SourceDigests) {
+        if let Err(e) = self.write(traces, digests) {
+            debug!("Couldn't save trace cache: {}", write_error(&self.path, &e));
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    fn write(&self, traces: &TraceMap, digests: &SourceDigests) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = BufWriter::new(File::create(&self.path)?);
+        let mut encoder = zstd::stream::write::Encoder::new(file, 0)?;
+        let entry = CacheEntry {
+            key: self.key.clone(),
+            traces: traces.clone(),
+            digests: digests.clone(),
+        };
+        serde_json::to_writer(&mut encoder, &entry)?;
+        encoder.finish()?.flush()
+    }
+}
+
+/// Hash of everything the traces extracted from the binary depend on
+fn cache_key(
+    test: &Path,
+    analysis: &HashMap<PathBuf, LineAnalysis>,
+    config: &Config,
+) -> io::Result<String> {
+    let file = File::open(test)?;
+    let binary = unsafe { MmapOptions::new().map(&file)? };
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&binary);
+    hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+    hasher.update(config.root().to_string_lossy().as_bytes());
+    hasher.update(&[config.ignore_tests as u8, config.include_derives as u8]);
+    let mut files = analysis.iter().collect::<Vec<_>>();
+    files.sort_by(|a, b| a.0.cmp(b.0));
+    for (path, lines) in files {
+        hasher.update(path.to_string_lossy().as_bytes());
+        hasher.update(&[config.excludes_file(path) as u8]);
+        hasher.update(analysis_fingerprint(lines).as_bytes());
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// The parts of the analysis of a file that decide which lines are traced
+fn analysis_fingerprint(analysis: &LineAnalysis) -> String {
+    let sorted = |lines: &mut dyn Iterator<Item = usize>| {
+        let mut lines = lines.collect::<Vec<_>>();
+        lines.sort_unstable();
+        lines
+    };
+    let mut logical = analysis
+        .logical_lines
+        .iter()
+        .map(|(l, o)| (*l, *o))
+        .collect::<Vec<_>>();
+    logical.sort_unstable();
+    let types = analysis
+        .type_definitions
+        .iter()
+        .map(|r| (r.start, r.end))
+        .collect::<Vec<_>>();
+    format!(
+        "{}{:?}{:?}{:?}{:?}{:?}",
+        analysis.ignores_all(),
+        sorted(&mut analysis.ignored_lines()),
+        sorted(&mut analysis.cover.iter().copied()),
+        logical,
+        types,
+        sorted(&mut analysis.runtime_attrs.iter().copied())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traces::{CoverageStat, Trace};
+    use std::collections::HashSet;
+    use std::env;
+
+    #[test]
+    fn entries_reused_until_binary_changes() {
+        let dir = env::temp_dir().join(format!("tarpaulin-trace-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let binary = dir.join("tests-0123");
+        fs::write(&binary, b"first build").unwrap();
+        let mut config = Config::default();
+        config.manifest = dir.join("Cargo.toml");
+        let analysis = HashMap::new();
+        let mut traces = TraceMap::new();
+        traces.add_trace(
+            Path::new("src/lib.rs"),
+            Trace {
+                line: 3,
+                address: [0x1000].iter().copied().collect::<HashSet<_>>(),
+                length: 1,
+                stats: CoverageStat::Line(0),
+                fn_name: None,
+                hit_span: None,
+                sampled: false,
+            },
+        );
+
+        let mut digests = SourceDigests::new();
+        digests.insert(PathBuf::from("src/lib.rs"), [7; 16]);
+
+        let cache = TraceCache::new(&binary, &analysis, &config).unwrap();
+        assert!(cache.load().is_none());
+        cache.store(&traces, &digests);
+        let hits_before = hits();
+        let cache = TraceCache::new(&binary, &analysis, &config).unwrap();
+        let (loaded, loaded_digests) = cache.load().unwrap();
+        assert!(hits() > hits_before);
+        assert_eq!(loaded.get_trace(0x1000).map(|t| t.line), Some(3));
+        // The digests are kept so the sources can be checked without reading
+        // the binary again
+        assert_eq!(loaded_digests, digests);
+
+        // A rebuilt binary doesn't match the entry
+        fs::write(&binary, b"second build").unwrap();
+        let cache = TraceCache::new(&binary, &analysis, &config).unwrap();
+        assert!(cache.load().is_none());
+
+        // Corrupted entries are ignored and replaced
+        fs::write(&cache.path, b"not a cache entry").unwrap();
+        assert!(cache.load().is_none());
+        cache.store(&traces, &digests);
+        assert!(cache.load().is_some());
+
+        config.no_cache = true;
+        assert!(TraceCache::new(&binary, &analysis, &config).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
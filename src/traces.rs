@@ -1,10 +1,11 @@
+use crate::line_map::{content_hash, line_map, SourceSnapshot};
 use serde::{Deserialize, Serialize};
 use std::cmp::{Ord, Ordering};
 use std::collections::btree_map::Iter;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt::{Display, Formatter, Result};
 use std::ops::Add;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 /// Used to track the state of logical conditions
 #[derive(Debug, Clone, Copy, Default, Hash, PartialEq, Eq, PartialOrd, Deserialize, Serialize)]
@@ -60,6 +61,48 @@ impl Display for CoverageStat {
     }
 }
 
+/// Time of the first and last hit of a trace in milliseconds since tracing of
+/// the test started
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HitSpan {
+    pub first: u64,
+    pub last: u64,
+}
+
+impl HitSpan {
+    /// Records a hit at the given time
+    pub fn record(span: &mut Option<HitSpan>, millis: u64) {
+        match span {
+            Some(s) => {
+                s.first = s.first.min(millis);
+                s.last = s.last.max(millis);
+            }
+            None => {
+                *span = Some(HitSpan {
+                    first: millis,
+                    last: millis,
+                })
+            }
+        }
+    }
+
+    /// Combines the spans of two hits of the same line
+    pub fn combine(a: Option<HitSpan>, b: Option<HitSpan>) -> Option<HitSpan> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(HitSpan {
+                first: a.first.min(b.first),
+                last: a.last.max(b.last),
+            }),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Time between the first and last hit in milliseconds
+    pub fn duration(&self) -> u64 {
+        self.last - self.first
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct Trace {
     /// Line the trace is on in the file
@@ -72,6 +115,34 @@ pub struct Trace {
     pub stats: CoverageStat,
     /// Function name
     pub fn_name: Option<String>,
+    /// First and last hit, only recorded with --profile-hits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hit_span: Option<HitSpan>,
+    /// Whether only some of the line's addresses are instrumented, hit counts
+    /// are approximate if so
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub sampled: bool,
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+impl Trace {
+    /// A line with the given number of hits and no addresses, for building
+    /// results in tests
+    #[cfg(test)]
+    pub(crate) fn test_line(line: u64, hits: u64) -> Self {
+        Trace {
+            line,
+            address: HashSet::new(),
+            length: 1,
+            stats: CoverageStat::Line(hits),
+            fn_name: None,
+            hit_span: None,
+            sampled: false,
+        }
+    }
 }
 
 impl PartialOrd for Trace {
@@ -130,16 +201,52 @@ pub fn amount_covered(traces: &[&Trace]) -> usize {
     result
 }
 
+/// A function from the debug info, spanning the lines of its file with code
+/// from it. Every instantiation of a generic function shares one.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Function {
+    /// Demangled name
+    pub name: String,
+    /// First line with code from the function
+    pub start: u64,
+    /// Last line with code from the function
+    pub end: u64,
+}
+
+/// Whether a function was entered, see `TraceMap::function_coverage`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FunctionCoverage {
+    pub name: String,
+    /// Line the function is declared on
+    pub line: u64,
+    pub entered: bool,
+}
+
 pub fn coverage_percentage(traces: &[&Trace]) -> f64 {
     (amount_covered(traces) as f64) / (amount_coverable(traces) as f64)
 }
 
 /// Stores all the program traces mapped to files and provides an interface to
 /// add, query and change traces.
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct TraceMap {
     /// Traces in the program mapped to the given file
     traces: BTreeMap<PathBuf, Vec<Trace>>,
+    /// Lines of type definitions with code generated by derives, which isn't
+    /// traced unless `--include-derives` is used
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    derived: BTreeMap<PathBuf, BTreeSet<u64>>,
+    /// Hits on each line from runs of the ignored tests, kept separately so
+    /// the lines only ignored tests cover can still be found after merging
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    ignored_hits: BTreeMap<PathBuf, BTreeMap<u64, u64>>,
+    /// Hits on the lines of test scaffolding, which were traced but aren't
+    /// coverable with `--exclude-test-scaffolding`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    scaffolding_hits: BTreeMap<PathBuf, BTreeMap<u64, u64>>,
+    /// Functions in each file by the line they're declared on
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    functions: BTreeMap<PathBuf, BTreeMap<u64, Function>>,
 }
 
 impl TraceMap {
@@ -147,6 +254,10 @@ impl TraceMap {
     pub fn new() -> TraceMap {
         TraceMap {
             traces: BTreeMap::new(),
+            derived: BTreeMap::new(),
+            ignored_hits: BTreeMap::new(),
+            scaffolding_hits: BTreeMap::new(),
+            functions: BTreeMap::new(),
         }
     }
 
@@ -160,10 +271,79 @@ impl TraceMap {
         self.traces.iter()
     }
 
+    /// Checks the traces of each file in `sources` were collected from the
+    /// source `read` returns for it now. Traces of files which have changed
+    /// since are dropped or with `with_diff` moved to the lines they're on
+    /// now, traces of lines in changed regions are still dropped. Files
+    /// without a snapshot can't be checked and are kept.
+    pub fn align_to_sources<F>(
+        &mut self,
+        sources: &BTreeMap<PathBuf, SourceSnapshot>,
+        with_diff: bool,
+        read: F,
+    ) where
+        F: Fn(&Path) -> Option<String>,
+    {
+        let mut stale = vec![];
+        for (path, traces) in self.traces.iter_mut() {
+            let snapshot = match sources.get(path) {
+                Some(s) => s,
+                None => continue,
+            };
+            let current = match read(path) {
+                Some(c) => c,
+                None => {
+                    stale.push(path.clone());
+                    continue;
+                }
+            };
+            if content_hash(&current) == snapshot.hash {
+                continue;
+            }
+            // The function ranges aren't moved with the lines
+            self.functions.remove(path);
+            if with_diff {
+                let lines = line_map(&snapshot.content, &current);
+                traces.retain(|t| lines.contains_key(&t.line));
+                for t in traces.iter_mut() {
+                    t.line = lines[&t.line];
+                }
+                traces.sort_unstable();
+            }
+            if !with_diff || traces.is_empty() {
+                stale.push(path.clone());
+            }
+        }
+        for path in &stale {
+            self.traces.remove(path);
+            self.functions.remove(path);
+        }
+    }
+
     /// Merges the results of one tracemap into the current one.
     /// This adds records which are missing and adds the statistics gathered to
     /// existing records
     pub fn merge(&mut self, other: &TraceMap) {
+        for (k, lines) in &other.derived {
+            self.derived.entry(k.clone()).or_default().extend(lines);
+        }
+        for (k, lines) in &other.ignored_hits {
+            let existing = self.ignored_hits.entry(k.clone()).or_default();
+            for (line, hits) in lines {
+                *existing.entry(*line).or_default() += hits;
+            }
+        }
+        for (k, lines) in &other.scaffolding_hits {
+            let existing = self.scaffolding_hits.entry(k.clone()).or_default();
+            for (line, hits) in lines {
+                *existing.entry(*line).or_default() += hits;
+            }
+        }
+        for (k, functions) in &other.functions {
+            for (line, function) in functions {
+                self.add_function(k, *line, function.clone());
+            }
+        }
         for (k, values) in other.iter() {
             if !self.traces.contains_key(k) {
                 self.traces.insert(k.to_path_buf(), values.to_vec());
@@ -176,6 +356,8 @@ impl TraceMap {
                         .find(|ref x| x.line == v.line && x.address == v.address)
                     {
                         t.stats = t.stats.clone() + v.stats.clone();
+                        t.hit_span = HitSpan::combine(t.hit_span, v.hit_span);
+                        t.sampled |= v.sampled;
                         added = true;
                     }
                     if !added {
@@ -187,6 +369,28 @@ impl TraceMap {
         }
     }
 
+    /// Records a function declared on a line of the file, a function already
+    /// recorded there is widened to cover both ranges
+    pub fn add_function(&mut self, file: &Path, line: u64, function: Function) {
+        self.functions
+            .entry(file.to_path_buf())
+            .or_default()
+            .entry(line)
+            .and_modify(|f| {
+                f.start = f.start.min(function.start);
+                f.end = f.end.max(function.end);
+            })
+            .or_insert(function);
+    }
+
+    /// The functions of a file ordered by the line they're declared on
+    pub fn get_functions(&self, path: &Path) -> Vec<&Function> {
+        self.functions
+            .get(path)
+            .map(|f| f.values().collect())
+            .unwrap_or_default()
+    }
+
     /// This will collapse duplicate Traces into a single trace. Warning this
     /// will lose the addresses of the duplicate traces but increment the results
     /// should be called only if you don't need those addresses from then on
@@ -194,7 +398,7 @@ impl TraceMap {
     pub fn dedup(&mut self) {
         for values in self.traces.values_mut() {
             // Map of lines and stats, merge duplicated stats here
-            let mut lines: HashMap<u64, CoverageStat> = HashMap::new();
+            let mut lines: HashMap<u64, (CoverageStat, Option<HitSpan>, bool)> = HashMap::new();
             // Duplicated traces need cleaning up. Maintain a list of them!
             let mut dirty: Vec<u64> = Vec::new();
             for v in values.iter() {
@@ -202,9 +406,11 @@ impl TraceMap {
                     .entry(v.line)
                     .and_modify(|e| {
                         dirty.push(v.line);
-                        *e = e.clone() + v.stats.clone();
+                        e.0 = e.0.clone() + v.stats.clone();
+                        e.1 = HitSpan::combine(e.1, v.hit_span);
+                        e.2 |= v.sampled;
                     })
-                    .or_insert_with(|| v.stats.clone());
+                    .or_insert_with(|| (v.stats.clone(), v.hit_span, v.sampled));
             }
             for d in &dirty {
                 let mut first = true;
@@ -221,15 +427,93 @@ impl TraceMap {
                         res
                     }
                 });
-                if let Some(new_stat) = lines.remove(&d) {
+                if let Some((new_stat, new_span, sampled)) = lines.remove(&d) {
                     if let Some(ref mut t) = values.iter_mut().find(|x| x.line == *d) {
                         t.stats = new_stat;
+                        t.hit_span = new_span;
+                        t.sampled = sampled;
                     }
                 }
             }
         }
     }
 
+    /// Returns a copy of the tracemap where only the lines covered in `other`
+    /// keep their hits. Used to find the coverage from a subset of the runs
+    /// while still counting every coverable line.
+    pub fn covered_by(&self, other: &TraceMap) -> TraceMap {
+        let mut result = self.clone();
+        for (file, traces) in result.traces.iter_mut() {
+            for t in traces.iter_mut() {
+                let hit = match other.traces.get(file) {
+                    Some(o) => {
+                        let lines = o.iter().filter(|x| x.line == t.line).collect::<Vec<_>>();
+                        amount_covered(&lines) > 0
+                    }
+                    None => false,
+                };
+                if !hit {
+                    t.stats = match t.stats {
+                        CoverageStat::Line(_) => CoverageStat::Line(0),
+                        CoverageStat::Branch(_) => CoverageStat::Branch(Default::default()),
+                        CoverageStat::Condition(ref c) => {
+                            CoverageStat::Condition(vec![Default::default(); c.len()])
+                        }
+                    };
+                }
+            }
+        }
+        result
+    }
+
+    /// Keeps only the traces the predicate returns true for, files left
+    /// without any traces are removed
+    pub fn retain(&mut self, mut f: impl FnMut(&Path, &Trace) -> bool) {
+        for (path, traces) in self.traces.iter_mut() {
+            traces.retain(|t| f(path, t));
+        }
+        self.traces.retain(|_, v| !v.is_empty());
+    }
+
+    /// Removes every file the predicate returns true for along with its
+    /// derived lines, hits and functions. Returns the number of files removed.
+    pub fn remove_files(&mut self, mut f: impl FnMut(&Path) -> bool) -> usize {
+        let before = self.traces.len();
+        self.traces.retain(|path, _| !f(path));
+        let removed = before - self.traces.len();
+        self.derived.retain(|path, _| !f(path));
+        self.ignored_hits.retain(|path, _| !f(path));
+        self.scaffolding_hits.retain(|path, _| !f(path));
+        self.functions.retain(|path, _| !f(path));
+        removed
+    }
+
+    /// Limits each trace to at most `limit` addresses, marking the traces
+    /// which lose addresses as sampled. Lines duplicated by monomorphisation
+    /// such as const generic functions can have hundreds of addresses, the
+    /// kept addresses are spread evenly over them so different instantiations
+    /// are still instrumented. Returns the number of addresses removed.
+    pub fn sample_addresses(&mut self, limit: usize) -> usize {
+        let mut removed = 0;
+        for trace in self.all_traces_mut() {
+            let len = trace.address.len();
+            if limit == 0 || len <= limit {
+                continue;
+            }
+            let mut addresses = trace.address.iter().copied().collect::<Vec<_>>();
+            addresses.sort_unstable();
+            trace.address = (0..limit).map(|i| addresses[i * len / limit]).collect();
+            trace.sampled = true;
+            removed += len - limit;
+        }
+        removed
+    }
+
+    /// Number of lines with sampled addresses
+    pub fn total_sampled(&self) -> usize {
+        self.all_traces().iter().filter(|t| t.sampled).count()
+    }
+
     /// Add a trace to the tracemap for the given file
     pub fn add_trace(&mut self, file: &Path, trace: Trace) {
         if self.traces.contains_key(file) {
@@ -242,6 +526,139 @@ impl TraceMap {
         }
     }
 
+    /// Records that derives generated code on a line which wasn't traced
+    pub fn add_derived(&mut self, file: &Path, line: u64) {
+        self.derived
+            .entry(file.to_path_buf())
+            .or_default()
+            .insert(line);
+    }
+
+    /// The functions of a file ordered by the line they're declared on and
+    /// whether any line in them was hit. Functions without coverable lines,
+    /// such as ones left out as tests, aren't included.
+    pub fn function_coverage(&self, path: &Path) -> Vec<FunctionCoverage> {
+        let traces = self.get_child_traces(path);
+        self.functions
+            .get(path)
+            .into_iter()
+            .flatten()
+            .filter_map(|(line, function)| {
+                let body = traces
+                    .iter()
+                    .filter(|t| t.line >= function.start && t.line <= function.end)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if body.is_empty() {
+                    None
+                } else {
+                    Some(FunctionCoverage {
+                        name: function.name.clone(),
+                        line: *line,
+                        entered: amount_covered(&body) > 0,
+                    })
+                }
+            })
+            .collect()
+    }
+
+    /// Number of lines with derive generated code that were excluded, lines
+    /// traced by another binary aren't counted
+    pub fn derived_lines(&self) -> usize {
+        self.derived
+            .iter()
+            .map(|(path, lines)| {
+                lines
+                    .iter()
+                    .filter(|l| !self.contains_location(path, **l))
+                    .count()
+            })
+            .sum()
+    }
+
+    /// Records all the hits in the tracemap as coming from ignored tests,
+    /// called on the results of running them before they're merged
+    pub fn mark_ignored(&mut self) {
+        for (path, traces) in &self.traces {
+            let lines = self.ignored_hits.entry(path.clone()).or_default();
+            for t in traces {
+                if let CoverageStat::Line(hits) = t.stats {
+                    if hits > 0 {
+                        *lines.entry(t.line).or_default() += hits;
+                    }
+                }
+            }
+        }
+        self.ignored_hits.retain(|_, lines| !lines.is_empty());
+    }
+
+    /// Lines in each file where every hit came from ignored tests
+    pub fn only_ignored_lines(&self) -> BTreeMap<PathBuf, BTreeSet<u64>> {
+        let mut result = BTreeMap::new();
+        for (path, ignored) in &self.ignored_hits {
+            let traces = match self.traces.get(path) {
+                Some(t) => t,
+                None => continue,
+            };
+            let lines = ignored
+                .iter()
+                .filter(|(line, ignored_hits)| {
+                    let hits = traces
+                        .iter()
+                        .filter(|t| t.line == **line)
+                        .map(|t| match t.stats {
+                            CoverageStat::Line(hits) => hits,
+                            _ => 0,
+                        })
+                        .sum::<u64>();
+                    hits == **ignored_hits
+                })
+                .map(|(line, _)| *line)
+                .collect::<BTreeSet<_>>();
+            if !lines.is_empty() {
+                result.insert(path.clone(), lines);
+            }
+        }
+        result
+    }
+
+    /// Moves the traces on the given lines of each file out of the results,
+    /// keeping their hits. Returns the number of lines moved.
+    pub fn exclude_scaffolding(&mut self, lines: &BTreeMap<PathBuf, BTreeSet<u64>>) -> usize {
+        let mut moved = 0;
+        for (path, traces) in self.traces.iter_mut() {
+            let scaffolding = match lines.get(path) {
+                Some(l) => l,
+                None => continue,
+            };
+            let hits = self.scaffolding_hits.entry(path.clone()).or_default();
+            traces.retain(|t| {
+                if !scaffolding.contains(&t.line) {
+                    return true;
+                }
+                let count = match t.stats {
+                    CoverageStat::Line(hits) => hits,
+                    _ => 0,
+                };
+                let line = hits.entry(t.line).or_insert_with(|| {
+                    moved += 1;
+                    0
+                });
+                *line += count;
+                false
+            });
+        }
+        self.traces.retain(|_, v| !v.is_empty());
+        self.scaffolding_hits.retain(|_, lines| !lines.is_empty());
+        moved
+    }
+
+    /// Hits on the lines of test scaffolding in each file, see
+    /// `exclude_scaffolding`
+    pub fn scaffolding_hits(&self) -> &BTreeMap<PathBuf, BTreeMap<u64, u64>> {
+        &self.scaffolding_hits
+    }
+
     /// Gets an immutable reference to a trace from an address. Returns None if
     /// there is no trace at that address
     pub fn get_trace(&self, address: u64) -> Option<&Trace> {
@@ -305,7 +722,7 @@ impl TraceMap {
     }
 
     /// Gets a vector of all the traces to mutate
-    fn all_traces_mut(&mut self) -> Vec<&mut Trace> {
+    pub(crate) fn all_traces_mut(&mut self) -> Vec<&mut Trace> {
         self.traces
             .values_mut()
             .flat_map(|x| x.iter_mut())
@@ -316,6 +733,21 @@ impl TraceMap {
         self.traces.keys().collect()
     }
 
+    /// The name every report uses for a file: relative to `root` with forward
+    /// slashes and no leading `./`. Files outside `root` keep their full path.
+    pub fn file_id(path: &Path, root: &Path) -> String {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        relative
+            .components()
+            .filter_map(|c| match c {
+                Component::CurDir => None,
+                Component::RootDir => Some(String::new()),
+                c => Some(c.as_os_str().to_string_lossy().into_owned()),
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
     pub fn coverable_in_path(&self, path: &Path) -> usize {
         amount_coverable(self.get_child_traces(path).as_slice())
     }
@@ -324,6 +756,27 @@ impl TraceMap {
         amount_covered(self.get_child_traces(path).as_slice())
     }
 
+    /// Runs of consecutive uncovered coverable lines in a file, as inclusive
+    /// start and end lines
+    pub fn uncovered_ranges(&self, path: &Path) -> Vec<(u64, u64)> {
+        let mut lines = self
+            .get_child_traces(path)
+            .iter()
+            .filter(|t| t.stats == CoverageStat::Line(0))
+            .map(|t| t.line)
+            .collect::<Vec<_>>();
+        lines.sort();
+        lines.dedup();
+        let mut ranges: Vec<(u64, u64)> = vec![];
+        for line in lines {
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == line => *end = line,
+                _ => ranges.push((line, line)),
+            }
+        }
+        ranges
+    }
+
     /// Give the total amount of coverable points in the code. This will vary
     /// based on the statistics available for line coverage it will be total
     /// lines whereas for condition or decision it will count the number of
@@ -341,6 +794,22 @@ impl TraceMap {
     pub fn coverage_percentage(&self) -> f64 {
         coverage_percentage(self.all_traces().as_slice())
     }
+
+    /// Returns up to `count` traces with the longest time between their first
+    /// and last hit, longest first
+    pub fn longest_hit_spans(&self, count: usize) -> Vec<(&Path, &Trace)> {
+        let mut result = self
+            .traces
+            .iter()
+            .flat_map(|(k, v)| v.iter().map(move |t| (k.as_path(), t)))
+            .filter(|(_, t)| t.hit_span.is_some())
+            .collect::<Vec<_>>();
+        result.sort_by_key(|(_, t)| {
+            std::cmp::Reverse(t.hit_span.map(|s| s.duration()).unwrap_or_default())
+        });
+        result.truncate(count);
+        result
+    }
 }
 
 #[cfg(test)]
@@ -388,6 +857,131 @@ mod tests {
         assert_eq!(&n + &n, n);
     }
 
+    #[test]
+    fn sample_many_addresses() {
+        let mut map = TraceMap::new();
+        for (line, count) in &[(1, 200u64), (2, 8), (3, 1)] {
+            map.add_trace(
+                Path::new("lib.rs"),
+                Trace {
+                    line: *line,
+                    address: (0..*count).map(|a| line * 1000 + a).collect(),
+                    length: 1,
+                    stats: CoverageStat::Line(0),
+                    fn_name: None,
+                    hit_span: None,
+                    sampled: false,
+                },
+            );
+        }
+        let removed = map.sample_addresses(8);
+        assert_eq!(removed, 192);
+        assert_eq!(map.total_sampled(), 1);
+
+        let traces = map.get_child_traces(Path::new("lib.rs"));
+        let many = traces.iter().find(|t| t.line == 1).unwrap();
+        assert!(many.sampled);
+        assert_eq!(many.address.len(), 8);
+        // Spread over the instantiations rather than the first few
+        assert!(many.address.contains(&1000));
+        assert!(many.address.iter().any(|a| *a >= 1175));
+        assert!(traces.iter().filter(|t| t.line != 1).all(|t| !t.sampled));
+
+        assert_eq!(map.sample_addresses(0), 0);
+    }
+
+    #[test]
+    fn derived_lines_traced_elsewhere_not_counted() {
+        let mut a = TraceMap::new();
+        a.add_derived(Path::new("lib.rs"), 1);
+        a.add_derived(Path::new("lib.rs"), 2);
+        let mut b = TraceMap::new();
+        b.add_derived(Path::new("lib.rs"), 2);
+        b.add_trace(Path::new("lib.rs"), Trace::test_line(1, 0));
+        assert_eq!(a.derived_lines(), 2);
+        a.merge(&b);
+        assert_eq!(a.derived_lines(), 1);
+    }
+
+    #[test]
+    fn files_removed() {
+        let mut traces = TraceMap::new();
+        for file in &["src/lib.rs", "src/generated/schema.rs"] {
+            traces.add_trace(Path::new(file), Trace::test_line(1, 0));
+            traces.add_derived(Path::new(file), 2);
+        }
+        let removed = traces.remove_files(|p| p.starts_with("src/generated"));
+        assert_eq!(removed, 1);
+        assert_eq!(traces.files(), vec![&PathBuf::from("src/lib.rs")]);
+        assert_eq!(traces.total_coverable(), 1);
+        assert_eq!(traces.derived_lines(), 1);
+    }
+
+    #[test]
+    fn functions_entered() {
+        let path = Path::new("lib.rs");
+        let function = |name: &str, start, end| Function {
+            name: name.to_string(),
+            start,
+            end,
+        };
+        let mut a = TraceMap::new();
+        for (line, hits) in &[(2, 1), (3, 0), (6, 0), (7, 0)] {
+            a.add_trace(path, Trace::test_line(*line, *hits));
+        }
+        a.add_function(path, 1, function("parse", 2, 3));
+        a.add_function(path, 5, function("helper", 6, 6));
+        // A test function with its lines ignored
+        a.add_function(path, 10, function("check", 11, 12));
+        // Another instantiation of helper reaches line 7
+        let mut b = TraceMap::new();
+        b.add_function(path, 5, function("helper", 6, 7));
+        a.merge(&b);
+        let expected = vec![
+            FunctionCoverage {
+                name: "parse".to_string(),
+                line: 1,
+                entered: true,
+            },
+            FunctionCoverage {
+                name: "helper".to_string(),
+                line: 5,
+                entered: false,
+            },
+        ];
+        assert_eq!(a.function_coverage(path), expected);
+        assert_eq!(a.functions[path][&5].end, 7);
+    }
+
+    #[test]
+    fn only_ignored_lines_kept_through_merge() {
+        let mut normal = TraceMap::new();
+        normal.add_trace(Path::new("lib.rs"), Trace::test_line(1, 1));
+        normal.add_trace(Path::new("lib.rs"), Trace::test_line(2, 0));
+        normal.add_trace(Path::new("lib.rs"), Trace::test_line(3, 0));
+        let mut ignored = TraceMap::new();
+        ignored.add_trace(Path::new("lib.rs"), Trace::test_line(1, 2));
+        ignored.add_trace(Path::new("lib.rs"), Trace::test_line(2, 3));
+        ignored.add_trace(Path::new("lib.rs"), Trace::test_line(3, 0));
+        ignored.mark_ignored();
+
+        let mut merged = TraceMap::new();
+        merged.merge(&normal);
+        merged.merge(&ignored);
+        merged.dedup();
+        let expected = vec![2].into_iter().collect::<BTreeSet<_>>();
+        assert_eq!(
+            merged.only_ignored_lines().get(Path::new("lib.rs")),
+            Some(&expected)
+        );
+
+        // Another binary's normal tests hitting the line means it's tested
+        let mut other = TraceMap::new();
+        other.add_trace(Path::new("lib.rs"), Trace::test_line(2, 1));
+        merged.merge(&other);
+        assert!(merged.only_ignored_lines().is_empty());
+    }
+
     #[test]
     fn multiple_traces_per_line() {
         let mut t1 = TraceMap::new();
@@ -400,6 +994,8 @@ mod tests {
             length: 0,
             stats: CoverageStat::Line(1),
             fn_name: Some(String::from("f")),
+            hit_span: None,
+            sampled: false,
         };
         t1.add_trace(Path::new("file.rs"), trace_1);
 
@@ -422,6 +1018,8 @@ mod tests {
             length: 0,
             stats: CoverageStat::Line(1),
             fn_name: Some(String::from("f")),
+            hit_span: None,
+            sampled: false,
         };
         t1.add_trace(Path::new("file.rs"), a_trace.clone());
         t2.add_trace(
@@ -432,6 +1030,8 @@ mod tests {
                 length: 0,
                 stats: CoverageStat::Line(2),
                 fn_name: Some(String::from("f")),
+                hit_span: None,
+                sampled: false,
             },
         );
 
@@ -457,6 +1057,8 @@ mod tests {
             length: 0,
             stats: CoverageStat::Line(1),
             fn_name: Some(String::from("f1")),
+            hit_span: None,
+            sampled: false,
         };
         t1.add_trace(Path::new("file.rs"), a_trace.clone());
         t2.add_trace(
@@ -467,6 +1069,8 @@ mod tests {
                 length: 0,
                 stats: CoverageStat::Line(2),
                 fn_name: Some(String::from("f2")),
+                hit_span: None,
+                sampled: false,
             },
         );
 
@@ -493,6 +1097,8 @@ mod tests {
                 length: 0,
                 stats: CoverageStat::Line(5),
                 fn_name: Some(String::from("f")),
+                hit_span: None,
+                sampled: false,
             },
         );
         t2.add_trace(
@@ -503,6 +1109,8 @@ mod tests {
                 length: 0,
                 stats: CoverageStat::Line(2),
                 fn_name: Some(String::from("f")),
+                hit_span: None,
+                sampled: false,
             },
         );
         t1.merge(&t2);
@@ -515,6 +1123,8 @@ mod tests {
                 length: 0,
                 stats: CoverageStat::Line(7),
                 fn_name: Some(String::from("f")),
+                hit_span: None,
+                sampled: false,
             })
         );
         // Deduplicating should have no effect.
@@ -528,7 +1138,150 @@ mod tests {
                 length: 0,
                 stats: CoverageStat::Line(7),
                 fn_name: Some(String::from("f")),
+                hit_span: None,
+                sampled: false,
             })
         );
     }
+
+    #[test]
+    fn coverage_from_subset_of_runs() {
+        let line = |line, hits, addr| {
+            let mut address = HashSet::new();
+            address.insert(addr);
+            Trace {
+                line,
+                address,
+                length: 1,
+                stats: CoverageStat::Line(hits),
+                fn_name: None,
+                hit_span: None,
+                sampled: false,
+            }
+        };
+        let file = Path::new("lib.rs");
+        let mut tests = TraceMap::new();
+        tests.add_trace(file, line(1, 1, 0));
+        tests.add_trace(file, line(2, 0, 8));
+        tests.add_trace(file, line(3, 2, 16));
+        tests.add_trace(file, line(4, 0, 24));
+        let mut examples = TraceMap::new();
+        // examples are a different binary so the addresses differ
+        examples.add_trace(file, line(1, 1, 100));
+        examples.add_trace(file, line(2, 1, 108));
+        examples.add_trace(file, line(4, 0, 124));
+        examples.add_trace(Path::new("examples/a.rs"), line(1, 1, 200));
+
+        let mut merged = TraceMap::new();
+        merged.merge(&tests);
+        merged.merge(&examples);
+        merged.dedup();
+        assert_eq!(merged.total_coverable(), 5);
+        assert_eq!(merged.total_covered(), 4);
+
+        let gated = merged.covered_by(&tests);
+        assert_eq!(gated.total_coverable(), 5);
+        // Line 1 is covered by both and line 3 just by tests
+        assert_eq!(gated.total_covered(), 2);
+        assert_eq!(gated.covered_in_path(file), 2);
+        assert_eq!(gated.covered_in_path(Path::new("examples")), 0);
+    }
+
+    #[test]
+    fn hit_spans_combined() {
+        let line = |line, addr, span: Option<(u64, u64)>| {
+            let mut address = HashSet::new();
+            address.insert(addr);
+            Trace {
+                line,
+                address,
+                length: 1,
+                stats: CoverageStat::Line(1),
+                fn_name: None,
+                hit_span: span.map(|(first, last)| HitSpan { first, last }),
+                sampled: false,
+            }
+        };
+        let file = Path::new("lib.rs");
+        let mut a = TraceMap::new();
+        a.add_trace(file, line(1, 0, Some((5, 10))));
+        a.add_trace(file, line(2, 8, None));
+        a.add_trace(file, line(3, 16, Some((20, 21))));
+        let mut b = TraceMap::new();
+        b.add_trace(file, line(1, 100, Some((2, 6))));
+        b.add_trace(file, line(2, 108, Some((7, 7))));
+        a.merge(&b);
+        a.dedup();
+        let span = |l| {
+            a.get_child_traces(file)
+                .iter()
+                .find(|t| t.line == l)
+                .unwrap()
+                .hit_span
+        };
+        assert_eq!(span(1), Some(HitSpan { first: 2, last: 10 }));
+        assert_eq!(span(2), Some(HitSpan { first: 7, last: 7 }));
+
+        let longest = a.longest_hit_spans(2);
+        assert_eq!(longest.len(), 2);
+        assert_eq!(longest[0].1.line, 1);
+        assert_eq!(longest[1].1.line, 3);
+    }
+
+    #[test]
+    fn file_ids_are_root_relative() {
+        let root = Path::new("/work/project");
+        assert_eq!(
+            TraceMap::file_id(Path::new("/work/project/src/lib.rs"), root),
+            "src/lib.rs"
+        );
+        assert_eq!(
+            TraceMap::file_id(Path::new("./src/lib.rs"), Path::new("")),
+            "src/lib.rs"
+        );
+        assert_eq!(
+            TraceMap::file_id(Path::new("/elsewhere/dep/src/lib.rs"), root),
+            "/elsewhere/dep/src/lib.rs"
+        );
+    }
+
+    #[test]
+    fn stale_sources_dropped_or_realigned() {
+        let mut saved = TraceMap::new();
+        for (path, l, hits) in &[("same.rs", 2, 1), ("moved.rs", 1, 1), ("moved.rs", 3, 2)] {
+            saved.add_trace(Path::new(path), Trace::test_line(*l, *hits));
+        }
+        let mut sources = BTreeMap::new();
+        sources.insert(
+            PathBuf::from("same.rs"),
+            SourceSnapshot::new("a\nb\n".to_string()),
+        );
+        sources.insert(
+            PathBuf::from("moved.rs"),
+            SourceSnapshot::new("a\nb\nc\n".to_string()),
+        );
+        let read = |path: &Path| match path.to_str() {
+            Some("same.rs") => Some("a\nb\n".to_string()),
+            Some("moved.rs") => Some("new\na\nchanged\nc\n".to_string()),
+            _ => None,
+        };
+
+        let mut dropped = saved.clone();
+        dropped.align_to_sources(&sources, false, read);
+        assert_eq!(dropped.files(), vec![&PathBuf::from("same.rs")]);
+
+        let mut realigned = saved.clone();
+        realigned.align_to_sources(&sources, true, read);
+        let moved = realigned.get_child_traces(Path::new("moved.rs"));
+        assert_eq!(moved.len(), 2);
+        assert_eq!(
+            (moved[0].line, &moved[0].stats),
+            (2, &CoverageStat::Line(1))
+        );
+        assert_eq!(
+            (moved[1].line, &moved[1].stats),
+            (4, &CoverageStat::Line(2))
+        );
+        assert!(realigned.contains_file(Path::new("same.rs")));
+    }
 }
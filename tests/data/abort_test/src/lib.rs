@@ -0,0 +1,7 @@
+pub fn checksum(data: &[u8]) -> u32 {
+    let mut sum = 0u32;
+    for b in data {
+        sum = sum.wrapping_mul(31).wrapping_add(u32::from(*b));
+    }
+    sum
+}
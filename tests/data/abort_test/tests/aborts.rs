@@ -0,0 +1,7 @@
+fn main() {
+    let sum = abort_test::checksum(b"tarpaulin");
+    if sum != 0 {
+        // Ends the test without unwinding, like a sanitizer finding an error
+        std::process::abort();
+    }
+}
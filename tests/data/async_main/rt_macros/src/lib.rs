@@ -0,0 +1,40 @@
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, Group, TokenStream, TokenTree};
+
+/// Turns `async fn f() { body }` into `fn f() { ::rt::block_on(async move { body }) }`,
+/// the generated tokens are spanned to the attribute like a real runtime's
+fn wrap(prefix: &str, item: TokenStream) -> TokenStream {
+    let mut tokens = item
+        .into_iter()
+        .filter(|t| match t {
+            TokenTree::Ident(i) => i.to_string() != "async",
+            _ => true,
+        })
+        .collect::<Vec<_>>();
+    let body = match tokens.pop() {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => g,
+        _ => panic!("expected an async function"),
+    };
+    let mut future: TokenStream = "async move".parse().unwrap();
+    future.extend(vec![TokenTree::Group(body)]);
+    let mut call: TokenStream = "::rt::block_on".parse().unwrap();
+    call.extend(vec![TokenTree::Group(Group::new(
+        Delimiter::Parenthesis,
+        future,
+    ))]);
+    tokens.push(TokenTree::Group(Group::new(Delimiter::Brace, call)));
+    let mut result: TokenStream = prefix.parse().unwrap();
+    result.extend(tokens);
+    result
+}
+
+#[proc_macro_attribute]
+pub fn main(_: TokenStream, item: TokenStream) -> TokenStream {
+    wrap("", item)
+}
+
+#[proc_macro_attribute]
+pub fn test(_: TokenStream, item: TokenStream) -> TokenStream {
+    wrap("#[test]", item)
+}
@@ -0,0 +1,24 @@
+async fn compute(x: u32) -> u32 {
+    x * 2
+}
+
+#[rt::main]
+async fn main() {
+    let x = compute(2).await;
+    println!("{}", x);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_main() {
+        main();
+    }
+
+    #[rt::test]
+    async fn async_test() {
+        assert_eq!(compute(1).await, 2);
+    }
+}
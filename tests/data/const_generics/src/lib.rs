@@ -0,0 +1,27 @@
+pub fn process<const N: usize>(data: [u8; N]) -> usize {
+    let mut total = 0;
+    for x in data.iter() {
+        total += *x as usize;
+    }
+    total + N
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! instantiate {
+        ($($n:literal)*) => {
+            0 $(+ process([1u8; $n]))*
+        };
+    }
+
+    #[test]
+    fn many_sizes() {
+        let total = instantiate!(
+            1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16
+            17 18 19 20 21 22 23 24 25 26 27 28 29 30 31 32
+        );
+        assert_eq!(total, 1056);
+    }
+}
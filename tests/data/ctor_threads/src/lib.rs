@@ -0,0 +1,38 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+static REQUEST: AtomicUsize = AtomicUsize::new(0);
+static RESPONSE: AtomicUsize = AtomicUsize::new(0);
+
+// What the ctor crate expands to, run before main so the worker thread exists
+// before the tests start
+#[used]
+#[link_section = ".init_array"]
+static START_WORKER: extern "C" fn() = start_worker;
+
+extern "C" fn start_worker() {
+    thread::spawn(|| loop {
+        let request = REQUEST.swap(0, Ordering::SeqCst);
+        if request > 0 {
+            RESPONSE.store(request * 2, Ordering::SeqCst);
+        }
+        thread::sleep(Duration::from_millis(1));
+    });
+}
+
+pub fn double(x: usize) -> usize {
+    REQUEST.store(x, Ordering::SeqCst);
+    loop {
+        let response = RESPONSE.swap(0, Ordering::SeqCst);
+        if response > 0 {
+            return response;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn worker_doubles() {
+    assert_eq!(double(21), 42);
+}
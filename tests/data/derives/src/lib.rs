@@ -0,0 +1,19 @@
+#![allow(dead_code)]
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Settings {
+    pub name: String,
+    pub retries: u32,
+}
+
+pub fn settings(name: &str) -> Settings {
+    Settings {
+        name: name.to_string(),
+        retries: 3,
+    }
+}
+
+#[test]
+fn make_settings() {
+    assert_eq!(settings("a").retries, 3);
+}
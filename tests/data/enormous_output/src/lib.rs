@@ -0,0 +1,21 @@
+pub fn add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn prints_megabytes() {
+        let line = vec![b'x'; 1023];
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+        for _ in 0..3 * 1024 {
+            stdout.write_all(&line).unwrap();
+            stdout.write_all(b"\n").unwrap();
+        }
+        assert_eq!(add(1, 2), 3);
+    }
+}
@@ -0,0 +1,25 @@
+use std::process::Command;
+
+pub fn run_foreign() -> bool {
+    Command::new("/bin/true")
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+pub fn after_foreign(x: u32) -> u32 {
+    x + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spawn_foreign_binary() {
+        for _ in 0..5 {
+            assert!(run_foreign());
+        }
+        assert_eq!(after_foreign(1), 2);
+    }
+}
@@ -0,0 +1,28 @@
+#[cfg(feature = "std")]
+pub fn describe(x: u32) -> String {
+    format!("value {}", x)
+}
+
+#[cfg(not(feature = "std"))]
+pub fn describe(x: u32) -> &'static str {
+    if x > 0 {
+        "positive"
+    } else {
+        "zero"
+    }
+}
+
+#[cfg(all(feature = "extra", feature = "fast"))]
+pub fn extra(x: u32) -> u32 {
+    x * 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes() {
+        assert_eq!(describe(1), "value 1");
+    }
+}
@@ -0,0 +1,23 @@
+use std::fs;
+use std::path::Path;
+
+pub fn first_attempt(state: &Path) -> bool {
+    if state.exists() {
+        fs::remove_file(state).unwrap();
+        false
+    } else {
+        fs::write(state, "failed").unwrap();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fails_first_time() {
+        let state = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/flaky-state");
+        assert!(!first_attempt(&state), "fails the first time it's run");
+    }
+}
@@ -0,0 +1,29 @@
+pub fn in_parent(x: i32) -> i32 {
+    x + 1
+}
+
+pub fn in_child(x: i32) -> i32 {
+    x * 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use nix::unistd::{fork, ForkResult};
+
+    #[test]
+    fn forks() {
+        match fork().unwrap() {
+            ForkResult::Child => {
+                let code = if in_child(2) == 4 { 0 } else { 1 };
+                // Exit without unwinding into the test harness
+                unsafe { nix::libc::_exit(code) }
+            }
+            ForkResult::Parent { child } => {
+                assert_eq!(in_parent(2), 3);
+                assert_eq!(waitpid(child, None).unwrap(), WaitStatus::Exited(child, 0));
+            }
+        }
+    }
+}
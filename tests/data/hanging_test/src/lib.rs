@@ -0,0 +1,32 @@
+pub fn add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+pub fn multiply(a: i32, b: i32) -> i32 {
+    a * b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn adds() {
+        assert_eq!(add(2, 3), 5);
+    }
+
+    #[test]
+    fn multiplies() {
+        assert_eq!(multiply(2, 3), 6);
+    }
+
+    // Runs last as the tests are run in order of their names
+    #[test]
+    fn waits_forever() {
+        loop {
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+}
@@ -0,0 +1,23 @@
+pub fn tested(x: u32) -> u32 {
+    x + 1
+}
+
+pub fn slow(x: u32) -> u32 {
+    x * 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tested_works() {
+        assert_eq!(tested(1), 2);
+    }
+
+    #[test]
+    #[ignore]
+    fn slow_works() {
+        assert_eq!(slow(2), 4);
+    }
+}
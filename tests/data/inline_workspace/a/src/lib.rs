@@ -0,0 +1,19 @@
+#[inline(always)]
+pub fn clamp_add(x: u32, y: u32) -> u32 {
+    let res = x.saturating_add(y);
+    if res > 100 {
+        100
+    } else {
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_small() {
+        assert_eq!(clamp_add(1, 2), 3);
+    }
+}
@@ -0,0 +1,15 @@
+use a::clamp_add;
+
+pub fn double_clamped(x: u32) -> u32 {
+    clamp_add(x, x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn double_large() {
+        assert_eq!(double_clamped(60), 100);
+    }
+}
@@ -0,0 +1,5 @@
+use multiple_bins::add;
+
+fn main() {
+    println!("{}", add(2, 3));
+}
@@ -0,0 +1,14 @@
+use multiple_bins::multiply;
+use std::env;
+
+fn main() {
+    let args = env::args()
+        .skip(1)
+        .filter_map(|a| a.parse().ok())
+        .collect::<Vec<u64>>();
+    if args.len() == 2 {
+        println!("{}", multiply(args[0], args[1]));
+    } else {
+        println!("{}", multiply(2, 3));
+    }
+}
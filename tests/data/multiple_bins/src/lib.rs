@@ -0,0 +1,7 @@
+pub fn add(a: u64, b: u64) -> u64 {
+    a + b
+}
+
+pub fn multiply(a: u64, b: u64) -> u64 {
+    a * b
+}
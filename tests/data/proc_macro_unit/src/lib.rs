@@ -0,0 +1,22 @@
+extern crate proc_macro;
+use proc_macro::TokenStream;
+
+#[proc_macro]
+pub fn make_answer(_item: TokenStream) -> TokenStream {
+    answer_fn(42).parse().unwrap()
+}
+
+fn answer_fn(value: u32) -> String {
+    let body = value.to_string();
+    format!("fn answer() -> u32 {{ {} }}", body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_answer() {
+        assert_eq!(answer_fn(42), "fn answer() -> u32 { 42 }");
+    }
+}
@@ -0,0 +1,7 @@
+pub fn before_exec() -> bool {
+    std::env::var_os("REEXEC_DONE").is_none()
+}
+
+pub fn after_exec(x: u32) -> u32 {
+    x * 2
+}
@@ -0,0 +1,14 @@
+use std::env;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+fn main() {
+    if reexec::before_exec() {
+        let err = Command::new(env::current_exe().unwrap())
+            .args(env::args_os().skip(1))
+            .env("REEXEC_DONE", "1")
+            .exec();
+        panic!("Failed to re-exec: {}", err);
+    }
+    assert_eq!(reexec::after_exec(2), 4);
+}
@@ -0,0 +1,40 @@
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const ITERATIONS: u64 = 2000;
+
+pub static ALARMS: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn on_alarm(_: libc::c_int) {
+    ALARMS.fetch_add(1, Ordering::SeqCst);
+}
+
+fn set_timer(usec: libc::suseconds_t) {
+    let interval = libc::timeval {
+        tv_sec: 0,
+        tv_usec: usec,
+    };
+    let timer = libc::itimerval {
+        it_interval: interval,
+        it_value: interval,
+    };
+    unsafe {
+        libc::setitimer(libc::ITIMER_REAL, &timer, ptr::null_mut());
+    }
+}
+
+/// Raises SIGALRM every millisecond so tracing is constantly interrupted
+pub fn start_timer() {
+    unsafe {
+        libc::signal(libc::SIGALRM, on_alarm as libc::sighandler_t);
+    }
+    set_timer(1000);
+}
+
+pub fn stop_timer() {
+    set_timer(0);
+}
+
+pub fn step(x: u64) -> u64 {
+    x.wrapping_mul(31).wrapping_add(7)
+}
@@ -0,0 +1,12 @@
+use signal_timer::*;
+
+#[test]
+fn hits_under_interval_timer() {
+    start_timer();
+    let mut x = 0;
+    for _ in 0..ITERATIONS {
+        x = step(x);
+    }
+    stop_timer();
+    assert_ne!(x, 0);
+}
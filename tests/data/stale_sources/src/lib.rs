@@ -0,0 +1,11 @@
+pub fn answer() -> u32 {
+    42
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn it_answers() {
+        assert_eq!(super::answer(), 42);
+    }
+}
@@ -0,0 +1,3 @@
+pub fn count_lines(input: &str) -> usize {
+    input.lines().count()
+}
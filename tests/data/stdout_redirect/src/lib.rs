@@ -0,0 +1,6 @@
+pub fn double(x: u32) -> u32 {
+    x * 2
+}
+
+/// File the test redirecting its stdout writes to
+pub const REDIRECTED: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/target/redirected.txt");
@@ -0,0 +1,22 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::io::FromRawFd;
+use std::thread;
+use std::time::Duration;
+
+// Written with `io::stdout` rather than `println!` as libtest captures the
+// output of the print macros
+#[test]
+fn a_closes_stdout() {
+    let mut stdout = io::stdout();
+    stdout.write_all(b"before closing stdout\n").unwrap();
+    stdout.flush().unwrap();
+    drop(unsafe { File::from_raw_fd(1) });
+}
+
+// Outlives the per test timeout after the output has ended
+#[test]
+fn b_runs_after_stdout_closed() {
+    thread::sleep(Duration::from_secs(3));
+    assert_eq!(stdout_redirect::double(2), 4);
+}
@@ -0,0 +1,27 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+extern "C" {
+    fn dup2(old: i32, new: i32) -> i32;
+}
+
+#[test]
+fn a_doubles() {
+    assert_eq!(stdout_redirect::double(3), 6);
+}
+
+#[test]
+fn b_redirects_stdout() {
+    let path = Path::new(stdout_redirect::REDIRECTED);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let file = File::create(path).unwrap();
+    let mut stdout = io::stdout();
+    stdout.write_all(b"before redirecting stdout\n").unwrap();
+    stdout.flush().unwrap();
+    assert_eq!(unsafe { dup2(file.as_raw_fd(), 1) }, 1);
+    stdout.write_all(b"after redirecting stdout\n").unwrap();
+    stdout.flush().unwrap();
+    assert_eq!(stdout_redirect::double(4), 8);
+}
@@ -0,0 +1,40 @@
+pub struct Platform;
+
+impl Platform {
+    #[cfg(unix)]
+    pub fn name() -> String {
+        "unix".to_string()
+    }
+
+    #[cfg(windows)]
+    pub fn name() -> String {
+        "windows".to_string()
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    pub fn name() -> String {
+        "other".to_string()
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn wasm_only(x: u32) -> u32 {
+        x + 1
+    }
+}
+
+#[cfg(windows)]
+mod win;
+
+pub fn describe(x: u32) -> String {
+    format!("{} {}", Platform::name(), x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes() {
+        assert_eq!(describe(1), "unix 1");
+    }
+}
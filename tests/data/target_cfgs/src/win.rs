@@ -0,0 +1,3 @@
+pub fn handle() -> usize {
+    42
+}
@@ -0,0 +1,6 @@
+use super::*;
+
+#[test]
+fn file_module_test() {
+    assert_eq!(shared_helper(2), 4);
+}
@@ -0,0 +1,33 @@
+pub fn production(x: u32) -> u32 {
+    shared_helper(x) + 1
+}
+
+pub fn shared_helper(x: u32) -> u32 {
+    x * 2
+}
+
+#[cfg(test)]
+fn test_only_helper() -> u32 {
+    shared_helper(4)
+}
+
+#[test]
+fn outside_test_module() {
+    assert_eq!(shared_helper(1), 2);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inline_test() {
+        assert_eq!(production(1), 3);
+        assert_eq!(test_only_helper(), 8);
+    }
+}
+
+#[cfg(test)]
+mod file_tests;
+
+pub mod parser;
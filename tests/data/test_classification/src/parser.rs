@@ -0,0 +1,25 @@
+pub fn parse(x: u32) -> u32 {
+    x + 1
+}
+
+#[cfg(test)]
+mod tests;
+
+pub mod inline {
+    pub fn helper() -> u32 {
+        super::parse(1)
+    }
+
+    #[cfg(test)]
+    mod checks {
+        mod deeper {
+            #[test]
+            fn deep() {
+                assert_eq!(super::super::helper(), 2);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod file_checks;
+}
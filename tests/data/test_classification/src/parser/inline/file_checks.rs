@@ -0,0 +1,4 @@
+#[test]
+fn file_check() {
+    assert_eq!(super::helper(), 2);
+}
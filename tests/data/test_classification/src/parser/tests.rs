@@ -0,0 +1,4 @@
+#[test]
+fn parses() {
+    assert_eq!(super::parse(1), 2);
+}
@@ -0,0 +1,3 @@
+pub fn expected(x: u32) -> u32 {
+    x * 2 + 1
+}
@@ -0,0 +1,8 @@
+mod common;
+
+use test_classification::production;
+
+#[test]
+fn integration_test() {
+    assert_eq!(production(2), common::expected(2));
+}
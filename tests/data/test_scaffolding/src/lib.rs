@@ -0,0 +1,21 @@
+pub fn deposit(balance: u64, amount: u64) -> u64 {
+    balance + amount
+}
+
+pub fn setup_account() -> u64 {
+    deposit(0, 10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup() -> u64 {
+        setup_account()
+    }
+
+    #[test]
+    fn setup_gives_balance() {
+        assert_eq!(setup(), 10);
+    }
+}
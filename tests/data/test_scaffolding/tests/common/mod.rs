@@ -0,0 +1,16 @@
+pub struct TestContext {
+    pub balance: u64,
+}
+
+pub fn setup() -> TestContext {
+    let balance = 10;
+    TestContext { balance }
+}
+
+pub fn teardown(ctx: TestContext) {
+    assert!(ctx.balance > 0);
+}
+
+pub fn account_fixture() -> u64 {
+    setup().balance * 10
+}
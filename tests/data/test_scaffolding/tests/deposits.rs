@@ -0,0 +1,15 @@
+mod common;
+
+use test_scaffolding::deposit;
+
+#[test]
+fn deposit_adds() {
+    let ctx = common::setup();
+    assert_eq!(deposit(ctx.balance, common::account_fixture()), 110);
+    common::teardown(ctx);
+}
+
+#[test]
+fn setup_is_repeatable() {
+    assert_eq!(common::setup().balance, 10);
+}
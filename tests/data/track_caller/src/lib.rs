@@ -0,0 +1,30 @@
+#[track_caller]
+pub fn assert_even(x: u32) {
+    if x % 2 != 0 {
+        panic!("{} is odd", x);
+    }
+}
+
+#[track_caller]
+pub fn assert_small(x: u32) -> u32 {
+    assert!(x < 100, "{} is too big", x);
+    x * 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn helpers() {
+        assert_even(2);
+        assert_even(4);
+        assert_eq!(assert_small(3), 6);
+        let checks: [fn(u32); 2] = [assert_even, assert_even];
+        for check in &checks {
+            check(8);
+        }
+        let small: fn(u32) -> u32 = assert_small;
+        assert_eq!(small(10), 20);
+    }
+}
@@ -1,10 +1,29 @@
 use crate::utils::get_test_path;
-use cargo_tarpaulin::config::{Config, ConfigWrapper, RunType};
-use cargo_tarpaulin::launch_tarpaulin;
+use cargo_tarpaulin::config::{
+    find_manifest, Config, ConfigWrapper, OutputFormat, PathFormat, RunType,
+};
+use cargo_tarpaulin::errors::RunError;
+use cargo_tarpaulin::harness::CoverageCheck;
+use cargo_tarpaulin::plan::Plan;
+use cargo_tarpaulin::report::{
+    cobertura, html, html_split, json, lcov, overhead, Report, RunMetadata,
+};
+use cargo_tarpaulin::stats;
+use cargo_tarpaulin::test_loader::{generate_tracemap, stale_sources};
+use cargo_tarpaulin::test_results::Outcome;
+use cargo_tarpaulin::trace_cache;
 use cargo_tarpaulin::traces::*;
+use cargo_tarpaulin::{
+    launch_tarpaulin, launch_tarpaulin_by_run_type, launch_tarpaulin_cancellable, run,
+    CancellationToken,
+};
 use clap::App;
+use std::collections::{HashMap, HashSet};
 use std::env;
-use std::time::Duration;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 
 mod compile_fail;
 mod doc_coverage;
@@ -91,6 +110,1131 @@ fn proc_macro_link() {
     assert!(launch_tarpaulin(&config).is_ok());
 }
 
+#[test]
+fn proc_macro_unit_tests_coverage() {
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(60);
+    let test_dir = get_test_path("proc_macro_unit");
+    config.manifest = test_dir.join("Cargo.toml");
+    let (res, ret) = launch_tarpaulin(&config).unwrap();
+    assert_eq!(ret, 0);
+
+    let lib_file = test_dir.join("src/lib.rs");
+    let helper_lines = res
+        .get_child_traces(&lib_file)
+        .into_iter()
+        .filter(|x| x.line == 10 || x.line == 11)
+        .collect::<Vec<_>>();
+    assert!(!helper_lines.is_empty());
+    for t in &helper_lines {
+        assert_ne!(t.stats, CoverageStat::Line(0));
+    }
+}
+
+#[test]
+fn threads_spawned_before_main_traced() {
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(60);
+    let test_dir = get_test_path("ctor_threads");
+    config.manifest = test_dir.join("Cargo.toml");
+    let (res, ret) = launch_tarpaulin(&config).unwrap();
+    assert_eq!(ret, 0);
+
+    let lib_file = test_dir.join("src/lib.rs");
+    let traces = res.get_child_traces(&lib_file);
+    // Lines run by the worker thread and by the test
+    for line in &[16, 18, 20, 37] {
+        let hits = traces
+            .iter()
+            .filter(|x| x.line == *line)
+            .collect::<Vec<_>>();
+        assert!(!hits.is_empty(), "line {} isn't traced", line);
+        assert!(
+            hits.iter().any(|t| t.stats != CoverageStat::Line(0)),
+            "line {} isn't covered",
+            line
+        );
+    }
+}
+
+#[test]
+fn inlined_workspace_function_single_trace() {
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(60);
+    let test_dir = get_test_path("inline_workspace");
+    config.manifest = test_dir.join("Cargo.toml");
+    let (res, ret) = launch_tarpaulin(&config).unwrap();
+    assert_eq!(ret, 0);
+
+    let lib_file = test_dir.join("a/src/lib.rs");
+    let traces = res
+        .get_child_traces(&lib_file)
+        .into_iter()
+        .filter(|x| x.line < 10)
+        .collect::<Vec<_>>();
+    assert!(!traces.is_empty());
+    let mut lines = traces.iter().map(|x| x.line).collect::<Vec<_>>();
+    lines.dedup();
+    assert_eq!(lines.len(), traces.len());
+    let line_3 = traces.iter().find(|x| x.line == 3).unwrap();
+    assert_ne!(line_3.stats, CoverageStat::Line(0));
+}
+
+#[test]
+fn workspace_packages_selected() {
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(60);
+    let test_dir = get_test_path("inline_workspace");
+    config.manifest = test_dir.join("Cargo.toml");
+    let a = test_dir.join("a/src/lib.rs");
+    let b = test_dir.join("b/src/lib.rs");
+
+    // a is built as a path dependency of b but isn't traced
+    let mut selected = config.clone();
+    selected.packages = vec!["b".to_string()];
+    let (results, ret) =
+        launch_tarpaulin_by_run_type(&selected, &CancellationToken::new()).unwrap();
+    assert_eq!(ret, 0);
+    let files = results
+        .traces
+        .values()
+        .flat_map(|traces| traces.files())
+        .collect::<Vec<_>>();
+    assert!(files.contains(&&b), "{:?}", files);
+    assert!(!files.contains(&&a), "{:?}", files);
+
+    let mut excluded = config;
+    excluded.exclude = vec!["b".to_string()];
+    // The test results are only read to see which binaries ran
+    excluded.generate = vec![OutputFormat::Junit];
+    let (results, ret) =
+        launch_tarpaulin_by_run_type(&excluded, &CancellationToken::new()).unwrap();
+    assert_eq!(ret, 0);
+    let files = results
+        .traces
+        .values()
+        .flat_map(|traces| traces.files())
+        .collect::<Vec<_>>();
+    assert!(files.contains(&&a), "{:?}", files);
+    assert!(!files.contains(&&b), "{:?}", files);
+    assert!(results.test_suites.iter().any(|s| s.name == "a"));
+    assert!(results.test_suites.iter().all(|s| s.name != "b"));
+}
+
+#[test]
+fn async_runtime_main_coverage() {
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(60);
+    let test_dir = get_test_path("async_main");
+    config.manifest = test_dir.join("Cargo.toml");
+    let (res, ret) = launch_tarpaulin(&config).unwrap();
+    assert_eq!(ret, 0);
+
+    let main_file = test_dir.join("src/main.rs");
+    let traces = res.get_child_traces(&main_file);
+    // The attribute line is mapped onto the line of the function signature
+    assert!(!traces.iter().any(|x| x.line == 5));
+    for line in 6..=8 {
+        let trace = traces.iter().find(|x| x.line == line).unwrap();
+        assert_ne!(trace.stats, CoverageStat::Line(0));
+    }
+}
+
+#[test]
+fn custom_harness_reading_stdin() {
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(20);
+    let test_dir = get_test_path("stdin_harness");
+    config.manifest = test_dir.join("Cargo.toml");
+    // Without a null stdin the test would block until it timed out
+    let (res, ret) = launch_tarpaulin(&config).unwrap();
+    assert_eq!(ret, 0);
+
+    let lib_file = test_dir.join("src/lib.rs");
+    let line = res
+        .get_child_traces(&lib_file)
+        .into_iter()
+        .find(|x| x.line == 2)
+        .unwrap();
+    assert_ne!(line.stats, CoverageStat::Line(0));
+}
+
+#[test]
+fn reexec_keeps_coverage() {
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(20);
+    let test_dir = get_test_path("reexec");
+    config.manifest = test_dir.join("Cargo.toml");
+    let (res, ret) = launch_tarpaulin(&config).unwrap();
+    assert_eq!(ret, 0);
+
+    // Line 8 of the test is only hit before the exec and line 13 after it
+    let test_file = test_dir.join("tests/reexec_self.rs");
+    let traces = res.get_child_traces(&test_file);
+    for line in &[8, 13] {
+        let trace = traces.iter().find(|x| x.line == *line).unwrap();
+        assert_ne!(trace.stats, CoverageStat::Line(0));
+    }
+}
+
+#[test]
+fn forked_child_covered() {
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(20);
+    let test_dir = get_test_path("fork_test");
+    config.manifest = test_dir.join("Cargo.toml");
+    let (res, ret) = launch_tarpaulin(&config).unwrap();
+    assert_eq!(ret, 0);
+
+    // Line 2 only runs in the parent and line 6 only in the forked child
+    let lib_file = test_dir.join("src/lib.rs");
+    let traces = res.get_child_traces(&lib_file);
+    for line in &[2, 6] {
+        let trace = traces.iter().find(|x| x.line == *line).unwrap();
+        assert_ne!(trace.stats, CoverageStat::Line(0), "line {}", line);
+    }
+}
+
+#[test]
+fn aborted_test_keeps_coverage() {
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(20);
+    let test_dir = get_test_path("abort_test");
+    config.manifest = test_dir.join("Cargo.toml");
+    let (res, ret) = launch_tarpaulin(&config).unwrap();
+    // SIGABRT is reported like a shell would, 128 + 6
+    assert_eq!(ret, 134);
+
+    let lib_file = test_dir.join("src/lib.rs");
+    let traces = res.get_child_traces(&lib_file);
+    // Line 5 closes the loop and has no code of its own
+    for &line in &[2, 3, 4, 6] {
+        let trace = traces.iter().find(|x| x.line == line).unwrap();
+        assert_ne!(trace.stats, CoverageStat::Line(0), "line {}", line);
+    }
+}
+
+#[test]
+fn hit_counts_bounded_under_signal_load() {
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(60);
+    config.count = true;
+    config.forward_signals = true;
+    let test_dir = get_test_path("signal_timer");
+    config.manifest = test_dir.join("Cargo.toml");
+    let (res, ret) = launch_tarpaulin(&config).unwrap();
+    assert_eq!(ret, 0);
+
+    // Line 39 is the body of `step` which runs once per iteration, a trap that
+    // was counted again after an interruption would push it over
+    let lib_file = test_dir.join("src/lib.rs");
+    let trace = res
+        .get_child_traces(&lib_file)
+        .into_iter()
+        .find(|x| x.line == 39)
+        .unwrap();
+    match trace.stats {
+        CoverageStat::Line(hits) => assert!(hits > 0 && hits <= 2000, "{} hits", hits),
+        ref s => panic!("Unexpected stat {:?}", s),
+    }
+}
+
+#[test]
+fn const_generic_addresses_sampled() {
+    let test_dir = get_test_path("const_generics");
+    let instrumented = |limit| {
+        let mut config = Config::default();
+        config.test_timeout = Duration::from_secs(60);
+        config.manifest = test_dir.join("Cargo.toml");
+        config.max_addresses_per_line = limit;
+        let (res, ret) = launch_tarpaulin(&config).unwrap();
+        assert_eq!(ret, 0);
+        let addresses: usize = res.all_traces().iter().map(|t| t.address.len()).sum();
+        (res, addresses)
+    };
+    let (full, all_addresses) = instrumented(0);
+    let (sampled, sampled_addresses) = instrumented(8);
+    assert_eq!(full.total_sampled(), 0);
+    assert!(
+        sampled_addresses * 2 < all_addresses,
+        "{} sampled addresses, {} in total",
+        sampled_addresses,
+        all_addresses
+    );
+
+    // Line 2 is in every instantiation of `process`, it's still covered
+    let lib_file = test_dir.join("src/lib.rs");
+    let trace = sampled
+        .get_child_traces(&lib_file)
+        .into_iter()
+        .find(|x| x.line == 2)
+        .unwrap();
+    assert!(trace.sampled);
+    assert_ne!(trace.stats, CoverageStat::Line(0));
+}
+
+#[test]
+fn track_caller_shims_attributed() {
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(60);
+    let test_dir = get_test_path("track_caller");
+    config.manifest = test_dir.join("Cargo.toml");
+    let (res, ret) = launch_tarpaulin(&config).unwrap();
+    assert_eq!(ret, 0);
+
+    let lib_file = test_dir.join("src/lib.rs");
+    let length = fs::read_to_string(&lib_file).unwrap().lines().count() as u64;
+    let traces = res.get_child_traces(&lib_file);
+    assert!(traces.iter().all(|x| x.line > 0 && x.line <= length));
+    // The helper bodies are covered through calls and function pointers
+    for line in &[3, 10, 11] {
+        let trace = traces.iter().find(|x| x.line == *line).unwrap();
+        assert_ne!(trace.stats, CoverageStat::Line(0));
+    }
+}
+
+#[test]
+fn foreign_exec_detached() {
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(60);
+    let test_dir = get_test_path("exec_foreign");
+    config.manifest = test_dir.join("Cargo.toml");
+    let (res, ret) = launch_tarpaulin(&config).unwrap();
+    assert_eq!(ret, 0);
+
+    // Line 11 runs after the child processes executed /bin/true and were
+    // detached, tracing of the test carries on
+    let lib_file = test_dir.join("src/lib.rs");
+    let trace = res
+        .get_child_traces(&lib_file)
+        .into_iter()
+        .find(|x| x.line == 11)
+        .unwrap();
+    assert_ne!(trace.stats, CoverageStat::Line(0));
+}
+
+#[test]
+fn non_target_code_excluded() {
+    let test_dir = get_test_path("target_cfgs");
+    let lib_file = test_dir.join("src/lib.rs");
+    let run = |count_all_targets| {
+        let mut config = Config::default();
+        config.test_timeout = Duration::from_secs(60);
+        config.manifest = test_dir.join("Cargo.toml");
+        config.count_all_targets = count_all_targets;
+        let (res, ret) = launch_tarpaulin(&config).unwrap();
+        assert_eq!(ret, 0);
+        res
+    };
+    // Only the unix code is counted so all of it is covered
+    let res = run(false);
+    assert!(!res.contains_location(&lib_file, 11));
+    assert_eq!(res.coverage_percentage(), 1.0);
+
+    // The windows method is counted but can't run
+    let res = run(true);
+    assert!(res.contains_location(&lib_file, 11));
+    assert!(res.coverage_percentage() < 1.0);
+}
+
+#[test]
+fn test_code_ignored_consistently() {
+    let test_dir = get_test_path("test_classification");
+    let lib_file = test_dir.join("src/lib.rs");
+    let test_files = [
+        test_dir.join("src/file_tests.rs"),
+        test_dir.join("src/parser/tests.rs"),
+        test_dir.join("src/parser/inline/file_checks.rs"),
+        test_dir.join("tests/integration.rs"),
+        test_dir.join("tests/common/mod.rs"),
+    ];
+    let run = |ignore_tests| {
+        let mut config = Config::default();
+        config.test_timeout = Duration::from_secs(60);
+        config.manifest = test_dir.join("Cargo.toml");
+        config.ignore_tests = ignore_tests;
+        let (res, ret) = launch_tarpaulin(&config).unwrap();
+        assert_eq!(ret, 0);
+        res
+    };
+    let res = run(false);
+    for line in &[2, 6, 11, 16, 25] {
+        assert!(res.contains_location(&lib_file, *line), "line {}", line);
+    }
+    for file in &test_files {
+        assert!(res.contains_file(file), "{}", file.display());
+    }
+
+    // The tracing leaves out the same test code as the source analysis, the
+    // helper shared with production code is still counted
+    let res = run(true);
+    assert!(res.contains_location(&lib_file, 2));
+    assert!(res.contains_location(&lib_file, 6));
+    let parser_file = test_dir.join("src/parser.rs");
+    assert!(res.contains_location(&parser_file, 2));
+    assert!(res.contains_location(&parser_file, 10));
+    assert!(!res.contains_location(&parser_file, 18));
+    for line in &[11, 16, 25] {
+        assert!(!res.contains_location(&lib_file, *line), "line {}", line);
+    }
+    for file in &test_files {
+        assert!(!res.contains_file(file), "{}", file.display());
+    }
+    assert_eq!(res.coverage_percentage(), 1.0);
+}
+
+#[test]
+fn test_scaffolding_excluded() {
+    let test_dir = get_test_path("test_scaffolding");
+    let common = test_dir.join("tests/common/mod.rs");
+    let deposits = test_dir.join("tests/deposits.rs");
+    let lib = test_dir.join("src/lib.rs");
+    let run = |exclude| {
+        let mut config = Config::default();
+        config.test_timeout = Duration::from_secs(60);
+        config.manifest = test_dir.join("Cargo.toml");
+        config.exclude_test_scaffolding = exclude;
+        let (res, ret) = launch_tarpaulin(&config).unwrap();
+        assert_eq!(ret, 0);
+        res
+    };
+    let counted = run(false);
+    assert!(counted.contains_location(&common, 6));
+    assert!(counted.contains_location(&lib, 14));
+    assert!(counted.scaffolding_hits().is_empty());
+
+    // The helpers are still traced but left out of the denominator, the
+    // tests and production code named like them are still counted
+    let excluded = run(true);
+    assert!(!excluded.contains_file(&common));
+    assert!(!excluded.contains_location(&lib, 14));
+    assert!(excluded.contains_location(&lib, 6));
+    assert!(excluded.contains_location(&deposits, 14));
+    assert!(excluded.coverable_in_path(&lib) < counted.coverable_in_path(&lib));
+    assert_eq!(
+        excluded.coverable_in_path(&deposits),
+        counted.coverable_in_path(&deposits)
+    );
+    let hit = |path: &Path, line: u64| {
+        excluded
+            .scaffolding_hits()
+            .get(path)
+            .and_then(|hits| hits.get(&line))
+            .map_or(false, |hits| *hits > 0)
+    };
+    assert!(hit(&common, 6), "no hits on common/mod.rs:6");
+    assert!(hit(&common, 15), "no hits on common/mod.rs:15");
+    assert!(hit(&lib, 14), "no hits on lib.rs:14");
+}
+
+#[test]
+fn derive_generated_code_excluded() {
+    let test_dir = get_test_path("derives");
+    let lib_file = test_dir.join("src/lib.rs");
+    let run = |include_derives| {
+        let mut config = Config::default();
+        config.test_timeout = Duration::from_secs(60);
+        config.manifest = test_dir.join("Cargo.toml");
+        config.include_derives = include_derives;
+        let (res, ret) = launch_tarpaulin(&config).unwrap();
+        assert_eq!(ret, 0);
+        res
+    };
+    // None of the derived impls are used but the struct isn't uncovered
+    let res = run(false);
+    for line in 3..=7 {
+        assert!(!res.contains_location(&lib_file, line));
+    }
+    assert!(res.derived_lines() > 0);
+    assert_eq!(res.coverage_percentage(), 1.0);
+
+    let res = run(true);
+    assert!((3..=7).any(|line| res.contains_location(&lib_file, line)));
+    assert_eq!(res.derived_lines(), 0);
+    assert!(res.coverage_percentage() < 1.0);
+}
+
+#[test]
+fn cancel_during_build() {
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(60);
+    let test_dir = get_test_path("simple_project");
+    config.manifest = test_dir.join("Cargo.toml");
+    let token = CancellationToken::new();
+    token.cancel();
+    match launch_tarpaulin_cancellable(&config, &token) {
+        Err(RunError::Cancelled(partial)) => assert!(partial.is_empty()),
+        res => panic!("Expected cancellation, got {:?}", res.map(|_| ())),
+    }
+}
+
+#[test]
+fn dry_run_plans_without_building() {
+    let test_dir = get_test_path("simple_project");
+    let target = test_dir.join("target");
+    let built = target.exists();
+    let mut config = Config::default();
+    config.manifest = test_dir.join("Cargo.toml");
+    let plan = Plan::new(&[config.clone()], Some(&config));
+    assert!(plan.is_valid(), "{:?}", plan.errors());
+    assert_eq!(plan.configs[0].packages.len(), 1);
+    assert_eq!(plan.configs[0].packages[0].name, "simple_project");
+    assert!(plan.configs[0]
+        .source_files
+        .contains(&PathBuf::from("src/lib.rs")));
+    assert_eq!(target.exists(), built);
+
+    config.packages = vec!["not_a_package".to_string()];
+    let plan = Plan::new(&[config.clone()], Some(&config));
+    assert_eq!(
+        plan.errors(),
+        vec!["Package not_a_package isn't in the workspace".to_string()]
+    );
+}
+
+#[test]
+fn dry_run_plans_selected_bins() {
+    let mut config = Config::default();
+    config.manifest = get_test_path("multiple_bins").join("Cargo.toml");
+    config.run_types = vec![RunType::Bins];
+    config.bins = vec!["multiply".to_string()];
+    let plan = Plan::new(&[config.clone()], Some(&config));
+    assert!(plan.is_valid(), "{:?}", plan.errors());
+    let targets = &plan.configs[0].packages[0].targets;
+    assert_eq!(targets.len(), 1);
+    assert_eq!(targets[0].name, "multiply");
+
+    // The plan fails the same way the run would
+    config.bins = vec!["divide".to_string()];
+    let plan = Plan::new(&[config.clone()], Some(&config));
+    assert_eq!(plan.errors().len(), 1);
+    assert!(plan.errors()[0].contains("No bin target named divide"));
+}
+
+#[test]
+fn tracing_overhead_measured() {
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(60);
+    config.measure_overhead = true;
+    let test_dir = get_test_path("simple_project");
+    config.manifest = test_dir.join("Cargo.toml");
+    let (results, ret) = launch_tarpaulin_by_run_type(&config, &CancellationToken::new()).unwrap();
+    assert_eq!(ret, 0);
+    assert!(!results.overhead.is_empty());
+    // The fixture's tests take a few milliseconds either way so the factor
+    // is noise, only check that both runs were timed
+    for binary in &results.overhead {
+        assert!(binary.untraced_ms > 0.0, "{:?}", binary);
+        assert!(binary.traced_ms > 0.0, "{:?}", binary);
+        assert!(binary.factor > 0.0, "{:?}", binary);
+    }
+    assert!(overhead::summarise(results.overhead).unwrap().factor > 0.0);
+}
+
+#[test]
+fn junit_test_results() {
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(60);
+    let test_dir = get_test_path("simple_project");
+    config.manifest = test_dir.join("Cargo.toml");
+    config.generate = vec![OutputFormat::Junit];
+    let (results, ret) = launch_tarpaulin_by_run_type(&config, &CancellationToken::new()).unwrap();
+    assert_eq!(ret, 0);
+    assert_eq!(results.test_suites.len(), 1);
+    assert_eq!(results.test_suites[0].name, "simple_project");
+    assert_eq!(results.test_suites[0].cases.len(), 1);
+    assert_eq!(results.test_suites[0].cases[0].name, "tests::bad_test");
+    assert_eq!(results.test_suites[0].cases[0].outcome, Outcome::Passed);
+
+    // Output isn't captured unless it's needed
+    config.generate = vec![];
+    let (results, _) = launch_tarpaulin_by_run_type(&config, &CancellationToken::new()).unwrap();
+    assert!(results.test_suites.is_empty());
+}
+
+#[test]
+fn enormous_output_truncated() {
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(60);
+    config.per_test_timeout = Some(Duration::from_secs(30));
+    config.capture_limit = Some(1);
+    let test_dir = get_test_path("enormous_output");
+    config.manifest = test_dir.join("Cargo.toml");
+    let (results, ret) = launch_tarpaulin_by_run_type(&config, &CancellationToken::new()).unwrap();
+    // Dropping the output past the limit doesn't affect the run
+    assert_eq!(ret, 0);
+    assert_eq!(results.test_suites.len(), 1);
+
+    let lib_file = test_dir.join("src/lib.rs");
+    let traces = results.traces[&RunType::Tests].get_child_traces(&lib_file);
+    let add = traces.iter().find(|x| x.line == 2).unwrap();
+    assert_eq!(add.stats, CoverageStat::Line(1));
+}
+
+#[test]
+fn hung_test_killed() {
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(60);
+    config.per_test_timeout = Some(Duration::from_secs(2));
+    let test_dir = get_test_path("hanging_test");
+    config.manifest = test_dir.join("Cargo.toml");
+    let (results, ret) = launch_tarpaulin_by_run_type(&config, &CancellationToken::new()).unwrap();
+    // Killed by SIGKILL, 128 + 9
+    assert_eq!(ret, 137);
+
+    let lib_file = test_dir.join("src/lib.rs");
+    let traces = results.traces[&RunType::Tests].get_child_traces(&lib_file);
+    for line in &[2, 6] {
+        let trace = traces.iter().find(|x| x.line == *line).unwrap();
+        assert_ne!(trace.stats, CoverageStat::Line(0), "line {}", line);
+    }
+
+    assert_eq!(results.test_suites.len(), 1);
+    let outcomes = results.test_suites[0]
+        .cases
+        .iter()
+        .map(|c| (c.name.as_str(), c.outcome))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        outcomes,
+        vec![
+            ("tests::adds", Outcome::Passed),
+            ("tests::multiplies", Outcome::Passed),
+            ("tests::waits_forever", Outcome::Failed),
+        ]
+    );
+}
+
+#[test]
+fn tests_closing_or_redirecting_stdout() {
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(60);
+    config.per_test_timeout = Some(Duration::from_secs(2));
+    let test_dir = get_test_path("stdout_redirect");
+    config.manifest = test_dir.join("Cargo.toml");
+    let redirected = test_dir.join("target/redirected.txt");
+    let _ = fs::remove_file(&redirected);
+    let start = Instant::now();
+    let (results, ret) = launch_tarpaulin_by_run_type(&config, &CancellationToken::new()).unwrap();
+    // A test still running once its output has ended isn't taken to be hung
+    assert_eq!(ret, 0);
+    assert!(start.elapsed() < config.test_timeout);
+
+    // The output before the redirect is read, the rest is in the file
+    let suite = results
+        .test_suites
+        .iter()
+        .find(|s| s.name == "redirects_stdout")
+        .unwrap();
+    let doubles = suite.cases.iter().find(|c| c.name == "a_doubles").unwrap();
+    assert_eq!(doubles.outcome, Outcome::Passed);
+    let after = fs::read_to_string(&redirected).unwrap();
+    assert!(after.starts_with("after redirecting stdout\n"), "{}", after);
+    assert!(after.contains("test result: ok"), "{}", after);
+
+    let suite = results
+        .test_suites
+        .iter()
+        .find(|s| s.name == "closes_stdout")
+        .unwrap();
+    assert!(suite.cases.iter().all(|c| c.outcome == Outcome::Passed));
+}
+
+#[test]
+fn bins_selected() {
+    let test_dir = get_test_path("multiple_bins");
+    let lib = test_dir.join("src/lib.rs");
+    let multiply = test_dir.join("src/bin/multiply.rs");
+    let hits = |traces: &TraceMap, path: &Path, line: u64| {
+        traces
+            .get_child_traces(path)
+            .into_iter()
+            .find(|t| t.line == line)
+            .map(|t| t.stats.clone())
+    };
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(60);
+    config.manifest = test_dir.join("Cargo.toml");
+    config.run_types = vec![RunType::Bins];
+    config.bins = vec!["multiply".to_string()];
+    config.bin_args.insert(
+        "multiply".to_string(),
+        vec!["4".to_string(), "5".to_string()],
+    );
+    let (results, ret) = launch_tarpaulin_by_run_type(&config, &CancellationToken::new()).unwrap();
+    assert_eq!(ret, 0);
+    assert_eq!(results.bin_runs.len(), 1);
+    assert_eq!(results.bin_runs[0].name, "multiply");
+    assert_eq!(
+        results.bin_runs[0].args,
+        vec!["4".to_string(), "5".to_string()]
+    );
+    let traces = &results.traces[&RunType::Bins];
+    assert_eq!(hits(traces, &lib, 2), Some(CoverageStat::Line(0)));
+    assert_eq!(hits(traces, &lib, 6), Some(CoverageStat::Line(1)));
+    // The println! on line 10 has an address for each of its statements so
+    // without --count it can be hit more than once
+    assert_ne!(hits(traces, &multiply, 10), Some(CoverageStat::Line(0)));
+    assert!(hits(traces, &multiply, 10).is_some());
+    assert_eq!(hits(traces, &multiply, 12), Some(CoverageStat::Line(0)));
+
+    // Only the default-run bin
+    config.bins = vec![];
+    config.default_bin_only = true;
+    let (results, ret) = launch_tarpaulin_by_run_type(&config, &CancellationToken::new()).unwrap();
+    assert_eq!(ret, 0);
+    assert_eq!(results.bin_runs.len(), 1);
+    assert_eq!(results.bin_runs[0].name, "add");
+    assert!(results.bin_runs[0].args.is_empty());
+    let traces = &results.traces[&RunType::Bins];
+    assert_eq!(hits(traces, &lib, 2), Some(CoverageStat::Line(1)));
+    assert_eq!(hits(traces, &lib, 6), Some(CoverageStat::Line(0)));
+
+    config.bins = vec!["divide".to_string()];
+    let err = launch_tarpaulin(&config).unwrap_err().to_string();
+    assert!(err.contains("available bins: add, multiply"), "{}", err);
+}
+
+#[test]
+fn flaky_binary_retried() {
+    let test_dir = get_test_path("flaky_retry");
+    let state = test_dir.join("target/flaky-state");
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(60);
+    config.manifest = test_dir.join("Cargo.toml");
+    config.retries = 2;
+    let (results, ret) = launch_tarpaulin_by_run_type(&config, &CancellationToken::new()).unwrap();
+    assert_eq!(ret, 0);
+    assert!(!state.exists());
+    assert_eq!(results.flaky.len(), 1);
+    assert_eq!(results.flaky[0].failures, 1);
+    assert_eq!(results.flaky[0].run_type, RunType::Tests);
+    assert_eq!(results.targets[&RunType::Tests].executions, 2);
+    // Both branches are covered as the coverage of the attempts is merged
+    let lib_file = test_dir.join("src/lib.rs");
+    let traces = &results.traces[&RunType::Tests];
+    for line in &[6, 9] {
+        let trace = traces
+            .get_child_traces(&lib_file)
+            .into_iter()
+            .find(|t| t.line == *line)
+            .cloned()
+            .unwrap();
+        assert_eq!(trace.stats, CoverageStat::Line(1), "line {}", line);
+    }
+
+    // Exempt binaries fail as they would without retries
+    config.no_retry_tests = vec!["flaky_retry".to_string()];
+    let (results, ret) = launch_tarpaulin_by_run_type(&config, &CancellationToken::new()).unwrap();
+    let _ = fs::remove_file(&state);
+    assert_ne!(ret, 0);
+    assert!(results.flaky.is_empty());
+}
+
+#[test]
+fn trace_cache_reused() {
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(60);
+    let test_dir = get_test_path("simple_project");
+    config.manifest = test_dir.join("Cargo.toml");
+    let (first, ret) = launch_tarpaulin(&config).unwrap();
+    assert_eq!(ret, 0);
+
+    let hits = trace_cache::hits();
+    let start = Instant::now();
+    let (second, ret) = launch_tarpaulin(&config).unwrap();
+    println!("Second run took {:?}", start.elapsed());
+    assert_eq!(ret, 0);
+    assert!(trace_cache::hits() > hits);
+    assert_eq!(first.total_coverable(), second.total_coverable());
+    assert_eq!(first.total_covered(), second.total_covered());
+
+    config.no_cache = true;
+    let (uncached, ret) = launch_tarpaulin(&config).unwrap();
+    assert_eq!(ret, 0);
+    assert_eq!(first.total_coverable(), uncached.total_coverable());
+}
+
+#[test]
+fn stats_file_written() {
+    let test_dir = get_test_path("simple_project");
+    let stats_file = env::temp_dir().join(format!("tarpaulin-stats-{}.json", std::process::id()));
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(60);
+    config.manifest = test_dir.join("Cargo.toml");
+    config.stats_file = Some(stats_file.clone());
+    run(&[config]).unwrap();
+
+    let stats: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&stats_file).unwrap()).unwrap();
+    let _ = fs::remove_file(&stats_file);
+    assert_eq!(stats["schema_version"], stats::SCHEMA_VERSION);
+    for phase in &["build", "analysis", "debug-info", "trace", "report"] {
+        assert!(stats["phases"][phase].is_f64(), "{} missing", phase);
+    }
+    assert!(stats["binaries"].as_u64().unwrap() > 0);
+    assert!(stats["breakpoints"].as_u64().unwrap() > 0);
+    assert!(stats["trace_map"]["lines"].as_u64().unwrap() > 0);
+    assert!(stats["cache"]["hits"].is_u64());
+    assert!(stats["cache"]["misses"].is_u64());
+    assert!(stats["peak_rss_kib"].as_u64().unwrap() > 0);
+    assert_eq!(stats["exit"], "success");
+    assert!(stats["error"].is_null());
+}
+
+#[test]
+fn lines_only_covered_by_ignored_tests() {
+    let test_dir = get_test_path("ignored_only");
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(60);
+    config.manifest = test_dir.join("Cargo.toml");
+    config.run_ignored = true;
+    let (res, ret) = launch_tarpaulin(&config).unwrap();
+    assert_eq!(ret, 0);
+
+    let lib_file = test_dir.join("src/lib.rs");
+    let only_ignored = res.only_ignored_lines();
+    let lines = only_ignored.get(&lib_file).unwrap();
+    assert!(lines.contains(&6), "{:?}", lines);
+    assert!(!lines.contains(&2), "{:?}", lines);
+
+    // Without the ignored tests the line isn't covered at all
+    config.run_ignored = false;
+    let (res, _) = launch_tarpaulin(&config).unwrap();
+    assert!(res.only_ignored_lines().is_empty());
+}
+
+#[test]
+fn invoked_from_subdirectory() {
+    let test_dir = get_test_path("inline_workspace");
+    let run_from = |dir: PathBuf, packages: &[&str], workspace: bool| {
+        let mut config = Config::default();
+        config.test_timeout = Duration::from_secs(60);
+        config.manifest = find_manifest(&dir).unwrap();
+        config.invocation_dir = dir;
+        config.packages = packages.iter().map(ToString::to_string).collect();
+        config.all = workspace;
+        let (res, ret) = launch_tarpaulin(&config).unwrap();
+        assert_eq!(ret, 0);
+        (config, res)
+    };
+    let (root_config, from_root) = run_from(test_dir.clone(), &["a"], false);
+    let (sub_config, from_sub) = run_from(test_dir.join("a/src"), &[], false);
+    assert_eq!(sub_config.manifest, test_dir.join("a/Cargo.toml"));
+    assert_eq!(sub_config.root(), root_config.root());
+    assert_eq!(sub_config.tarpaulin_dir(), root_config.tarpaulin_dir());
+
+    // Only the package containing the directory is tested by default, the
+    // same as selecting it from the workspace root
+    let a = test_dir.join("a/src/lib.rs");
+    assert_eq!(from_sub.files(), from_root.files());
+    assert_eq!(sub_config.display_path(&a), root_config.display_path(&a));
+    assert_eq!(
+        sub_config.strip_base_dir(&a),
+        root_config.strip_base_dir(&a)
+    );
+    assert_eq!(from_sub.covered_in_path(&a), from_root.covered_in_path(&a));
+    assert_eq!(
+        from_sub.coverable_in_path(&a),
+        from_root.coverable_in_path(&a)
+    );
+
+    // --workspace widens it to every package
+    let (_, whole_workspace) = run_from(test_dir.clone(), &[], false);
+    let (_, widened) = run_from(test_dir.join("a/src"), &[], true);
+    assert_eq!(widened.files(), whole_workspace.files());
+    assert_eq!(widened.total_covered(), whole_workspace.total_covered());
+}
+
+/// The test binary of the `stale_sources` fixture in the target directory
+fn stale_sources_binary(target: &Path) -> PathBuf {
+    fs::read_dir(target.join("debug/deps"))
+        .unwrap()
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .find(|p| {
+            let name = p.file_name().unwrap().to_string_lossy();
+            name.starts_with("stale_sources-") && p.extension().is_none()
+        })
+        .unwrap()
+}
+
+#[test]
+fn sources_changed_after_build() {
+    let fixture = get_test_path("stale_sources");
+    let test_dir = env::temp_dir().join(format!("tarpaulin-stale-{}", std::process::id()));
+    fs::create_dir_all(test_dir.join("src")).unwrap();
+    for file in &["Cargo.toml", "Cargo.lock", "src/lib.rs"] {
+        fs::copy(fixture.join(file), test_dir.join(file)).unwrap();
+    }
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(60);
+    config.manifest = test_dir.join("Cargo.toml");
+    // The digests read with the binary's traces, none without DWARF 5
+    let digests = |binary: &Path| {
+        let mut config = config.clone();
+        config.no_cache = true;
+        generate_tracemap(binary, &HashMap::new(), &config)
+            .unwrap()
+            .1
+    };
+    let (res, ret) = launch_tarpaulin(&config).unwrap();
+    assert_eq!(ret, 0);
+    let lib_file = test_dir.join("src/lib.rs").canonicalize().unwrap();
+    let binary = stale_sources_binary(&test_dir.join("target"));
+    let built = digests(&binary);
+    assert!(stale_sources(&binary, &res.files(), &built)
+        .unwrap()
+        .is_empty());
+
+    // Editing the source after the build makes the binary stale, the new line
+    // calls a function so it isn't folded away into the return
+    thread::sleep(Duration::from_secs(1));
+    fs::write(
+        &lib_file,
+        fs::read_to_string(&lib_file)
+            .unwrap()
+            .replace("    42\n", "    let answer = 42;\n    answer.max(0)\n"),
+    )
+    .unwrap();
+    let stale = stale_sources(&binary, &res.files(), &built).unwrap();
+    assert_eq!(stale.len(), 1);
+    assert_eq!(stale[0].canonicalize().unwrap(), lib_file);
+
+    // Reporting again rebuilds the tests so the new line is covered
+    let (res, ret) = launch_tarpaulin(&config).unwrap();
+    assert_eq!(ret, 0);
+    let binary = stale_sources_binary(&test_dir.join("target"));
+    assert!(stale_sources(&binary, &res.files(), &digests(&binary))
+        .unwrap()
+        .is_empty());
+    let covered = res
+        .get_child_traces(&lib_file)
+        .iter()
+        .filter(|t| t.stats != CoverageStat::Line(0))
+        .map(|t| t.line)
+        .collect::<Vec<_>>();
+    assert!(covered.contains(&3), "{:?}", covered);
+    fs::remove_dir_all(&test_dir).unwrap();
+}
+
+#[test]
+fn reports_name_files_consistently() {
+    let test_dir = get_test_path("simple_project");
+    let lib_file = test_dir.join("src/lib.rs");
+    let mut traces = TraceMap::new();
+    traces.add_trace(
+        &lib_file,
+        Trace {
+            line: 4,
+            address: HashSet::new(),
+            length: 1,
+            stats: CoverageStat::Line(1),
+            fn_name: None,
+            hit_span: None,
+            sampled: false,
+        },
+    );
+    let render = |path_format| {
+        let mut config = Config::default();
+        config.manifest = test_dir.join("Cargo.toml");
+        config.path_format = path_format;
+        config.output_directory = Some(env::temp_dir().join(format!(
+            "tarpaulin-path-format-{:?}-{}",
+            path_format,
+            std::process::id()
+        )));
+        fs::create_dir_all(config.output_dir()).unwrap();
+        json::export(&traces, &config, &RunMetadata::default()).unwrap();
+        html::export(&traces, &config).unwrap();
+        lcov::export(&traces, &config).unwrap();
+        cobertura::report(&traces, &config).unwrap();
+        let read = |name| fs::read_to_string(config.output_dir().join(name)).unwrap();
+        let reports = (
+            config.display_path(&lib_file),
+            read(json::FILE_NAME),
+            read(html::FILE_NAME),
+            read(lcov::FILE_NAME),
+            read(cobertura::FILE_NAME),
+        );
+        fs::remove_dir_all(config.output_dir()).unwrap();
+        reports
+    };
+
+    let (console, json, html, lcov, cobertura) = render(PathFormat::Relative);
+    assert_eq!(console, "src/lib.rs");
+    assert!(json.contains(r#""path":["src","lib.rs"]"#), "{}", json);
+    let absolute = lib_file.canonicalize().unwrap();
+    assert!(json.contains(&format!(r#""absolute_path":"{}""#, absolute.display())));
+    assert!(json
+        .contains(r#""directories":[{"path":"src","covered":1,"coverable":1,"percent":100.0}]"#));
+    assert!(json.starts_with(r#"{"schema_version":1,"covered":1,"coverable":1,"percent":100.0,"#));
+    assert!(
+        json.contains(r#""lines":[{"line":4,"hits":1}],"covered":1,"coverable":1,"percent":100.0"#)
+    );
+    assert!(html.contains(r#""path":["src","lib.rs"]"#));
+    assert!(!html.contains("absolute_path"));
+    assert!(html.contains(r#""directories":[{"path":"src""#));
+    assert!(lcov.contains("SF:src/lib.rs\n"), "{}", lcov);
+    assert!(
+        cobertura.contains(r#"filename="src/lib.rs""#),
+        "{}",
+        cobertura
+    );
+
+    let (_, json, _, lcov, _) = render(PathFormat::Legacy);
+    assert!(!json.contains(r#""path":["src","lib.rs"]"#));
+    assert!(lcov.contains(&format!("SF:{}\n", lib_file.display())));
+}
+
+#[test]
+fn reports_grouped_by_package_and_run_type() {
+    let test_dir = get_test_path("inline_workspace");
+    let a = test_dir.join("a/src/lib.rs");
+    let b = test_dir.join("b/src/lib.rs");
+    let trace = |line, hits| Trace {
+        line,
+        address: HashSet::new(),
+        length: 1,
+        stats: CoverageStat::Line(hits),
+        fn_name: None,
+        hit_span: None,
+        sampled: false,
+    };
+    let mut tests = TraceMap::new();
+    tests.add_trace(&a, trace(3, 1));
+    tests.add_trace(&b, trace(2, 0));
+    let mut doctests = TraceMap::new();
+    doctests.add_trace(&b, trace(2, 2));
+    let mut metadata = RunMetadata::default();
+    metadata.run_types.insert(RunType::Tests, tests.clone());
+    metadata
+        .run_types
+        .insert(RunType::Doctests, doctests.clone());
+    let mut merged = TraceMap::new();
+    merged.merge(&tests);
+    merged.merge(&doctests);
+    merged.dedup();
+
+    let mut config = Config::default();
+    config.manifest = test_dir.join("Cargo.toml");
+    config.output_directory =
+        Some(env::temp_dir().join(format!("tarpaulin-package-reports-{}", std::process::id())));
+    fs::create_dir_all(config.output_dir()).unwrap();
+    cobertura::CoberturaReport
+        .render(&config, &merged, &metadata)
+        .unwrap();
+    lcov::LcovReport
+        .render(&config, &merged, &metadata)
+        .unwrap();
+    let read = |name| fs::read_to_string(config.output_dir().join(name)).unwrap();
+
+    let xml = read(cobertura::FILE_NAME);
+    assert!(xml.contains(r#"<package name="a" line-rate="1" branch-rate="0" complexity="0"><classes><class name="lib" filename="a/src/lib.rs""#), "{}", xml);
+    assert!(xml.contains(r#"<package name="b" line-rate="1" branch-rate="0" complexity="0"><classes><class name="lib" filename="b/src/lib.rs""#), "{}", xml);
+    assert_eq!(xml.matches("<package ").count(), 2);
+
+    let lcov = read(lcov::FILE_NAME);
+    let records = lcov
+        .split("end_of_record\n")
+        .filter(|r| !r.is_empty())
+        .map(|r| r.lines().take(2).collect::<Vec<_>>().join(" "))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        records,
+        vec![
+            "TN:Tests SF:a/src/lib.rs",
+            "TN:Tests SF:b/src/lib.rs",
+            "TN:Doctests SF:b/src/lib.rs",
+        ]
+    );
+    fs::remove_dir_all(config.output_dir()).unwrap();
+}
+
+#[test]
+fn lcov_report_alongside_others() {
+    let test_dir = get_test_path("simple_project");
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(60);
+    config.manifest = test_dir.join("Cargo.toml");
+    config.generate = vec![OutputFormat::Lcov, OutputFormat::Json];
+    config.output_directory =
+        Some(env::temp_dir().join(format!("tarpaulin-lcov-{}", std::process::id())));
+    fs::create_dir_all(config.output_dir()).unwrap();
+    run(&[config.clone()]).unwrap();
+
+    assert!(config.output_dir().join(json::FILE_NAME).is_file());
+    let lcov = fs::read_to_string(config.output_dir().join(lcov::FILE_NAME)).unwrap();
+    fs::remove_dir_all(config.output_dir()).unwrap();
+    let record = lcov
+        .split("end_of_record\n")
+        .find(|r| r.contains("SF:src/lib.rs\n"))
+        .unwrap();
+    // Uncovered lines still get a record
+    assert!(record.contains("\nDA:6,0\n"), "{}", record);
+    assert!(record.contains("\nDA:8,1\n"), "{}", record);
+    let count = |prefix: &str| {
+        record
+            .lines()
+            .find(|l| l.starts_with(prefix))
+            .and_then(|l| l[prefix.len()..].parse::<usize>().ok())
+            .unwrap()
+    };
+    let lines = record.lines().filter(|l| l.starts_with("DA:"));
+    assert_eq!(count("LF:"), lines.clone().count());
+    assert_eq!(count("LH:"), lines.filter(|l| !l.ends_with(",0")).count());
+}
+
+#[test]
+fn split_html_report() {
+    let test_dir = get_test_path("simple_project");
+    let mut config = Config::default();
+    config.test_timeout = Duration::from_secs(60);
+    config.manifest = test_dir.join("Cargo.toml");
+    let (traces, ret) = launch_tarpaulin(&config).unwrap();
+    assert_eq!(ret, 0);
+
+    config.html_split = true;
+    config.output_directory =
+        Some(env::temp_dir().join(format!("tarpaulin-split-{}", std::process::id())));
+    fs::create_dir_all(config.output_dir()).unwrap();
+    html::HtmlReport
+        .render(&config, &traces, &RunMetadata::default())
+        .unwrap();
+    let dir = config.output_dir().join(html_split::DIR_NAME);
+    let index = fs::read_to_string(dir.join("index.html")).unwrap();
+    assert!(!config.output_dir().join(html::FILE_NAME).exists());
+    assert!(index.contains(r#"<div class="dir-tree">"#));
+    assert!(index.contains(r#"open><summary class="dir-tree__summary"#));
+    assert!(index.contains(r#"title="src">src: Covered: "#));
+
+    let links = index
+        .split("href=\"")
+        .skip(1)
+        .map(|s| s[..s.find('"').unwrap()].to_string())
+        .collect::<Vec<_>>();
+    assert!(links.contains(&"report.css".to_string()));
+    assert!(links.contains(&"files/src/lib.rs.html".to_string()));
+    for link in &links {
+        assert!(dir.join(link).is_file(), "{} doesn't resolve", link);
+    }
+
+    let page = fs::read_to_string(dir.join("files/src/lib.rs.html")).unwrap();
+    assert!(page.contains(r#"href="../../report.css""#));
+    assert!(page.contains(r#"href="../../index.html""#));
+    let class_of = |line: usize| {
+        let start = page.find(&format!(r#"id="L{}""#, line)).unwrap();
+        page[start..page[start..].find('>').unwrap() + start].to_string()
+    };
+    assert!(class_of(5).contains("code-line_covered"));
+    assert!(class_of(8).contains("code-line_covered"));
+    assert!(class_of(6).contains("code-line_uncovered"));
+    assert!(!class_of(2).contains("code-line_"));
+    // Every line of the file has an anchor and nothing past its end does
+    class_of(24);
+    assert!(!page.contains(r#"id="L25""#));
+    fs::remove_dir_all(config.output_dir()).unwrap();
+}
+
 #[test]
 fn array_coverage() {
     check_percentage("arrays", 1.0f64, true);
@@ -194,3 +1338,20 @@ fn examples_coverage() {
     config.run_types = vec![RunType::Examples];
     check_percentage_with_config(test, 1.0f64, true, config);
 }
+
+#[test]
+fn harness_checks_fixture_coverage() {
+    let manifest = get_test_path("simple_project").join("Cargo.toml");
+    let traces = CoverageCheck::new(&manifest)
+        .package("simple_project")
+        .exclude_files("src/unused.rs")
+        .min_total(50.0)
+        .run()
+        .unwrap();
+    assert!(traces.files().iter().all(|f| !f.ends_with("unused.rs")));
+
+    match CoverageCheck::new(&manifest).min_file(1.0).run() {
+        Err(RunError::FilesBelowThreshold(_, files)) => assert!(files.contains("unused.rs")),
+        res => panic!("Expected unused.rs to fail, got {:?}", res.map(|_| ())),
+    }
+}